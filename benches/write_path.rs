@@ -0,0 +1,135 @@
+//! Throughput of the write path: the framing on its own, the library's
+//! `Router`, and the router binary end to end, fed from a pipe the way a
+//! producer would. Run with `cargo bench --bench write_path`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use log_revolve_rs::framing::lines::{Frame, PairedDecoder};
+use log_revolve_rs::rotation::{FileTimestamp, Rotation};
+use log_revolve_rs::router::{Channel, RotationPolicy, Router, SinkConfig};
+use log_revolve_rs::runtime;
+
+/// Bytes of each message, newline included, about those of a typical
+/// application log line.
+const MESSAGE_BYTES: usize = 128;
+
+/// Messages sent to the binary per run, so its start is amortized.
+const BINARY_MESSAGES: usize = 256 * 1024;
+
+fn message() -> String {
+    let mut message = "x".repeat(MESSAGE_BYTES - 1);
+    message.push('\n');
+    message
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("log-revolve-bench-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
+
+/// Pairs of the `lines` framing, as a producer would send them.
+fn paired_input(messages: usize) -> String {
+    let message = message();
+    let mut input = String::with_capacity(messages * (MESSAGE_BYTES + 4));
+    for _ in 0..messages {
+        input.push_str("app\n");
+        input.push_str(&message);
+    }
+
+    input
+}
+
+fn framing(c: &mut Criterion) {
+    let input = paired_input(10_000);
+    let mut group = c.benchmark_group("framing");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.bench_function("paired", |b| {
+        let mut decoder = PairedDecoder::default();
+        b.iter(|| {
+            let mut bytes = 0;
+            for line in input.split_inclusive('\n') {
+                if let Frame::Message { message, .. } =
+                    decoder.push(line, |channel| channel == "app")
+                {
+                    bytes += message.len();
+                }
+            }
+            bytes
+        })
+    });
+    group.finish();
+}
+
+fn router(c: &mut Criterion) {
+    let dir = scratch_dir("router");
+    let message = message();
+    let lines = 10_000;
+    let mut router = Router::new(vec![Channel::new(
+        "app",
+        SinkConfig::new(&dir),
+        RotationPolicy::new(Rotation::Hourly(FileTimestamp::Seconds)),
+    )]);
+
+    let mut group = c.benchmark_group("router");
+    group.throughput(Throughput::Bytes((lines * MESSAGE_BYTES) as u64));
+    group.bench_function("library", |b| {
+        b.iter(|| {
+            runtime::block_on(async {
+                for _ in 0..lines {
+                    router.write("app", message.trim_end()).await.unwrap();
+                }
+                router.flush().await.unwrap();
+            })
+        })
+    });
+    group.finish();
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+fn binary(c: &mut Criterion) {
+    let input = paired_input(BINARY_MESSAGES);
+    let mut group = c.benchmark_group("binary");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes((BINARY_MESSAGES * MESSAGE_BYTES) as u64));
+    group.bench_function("lines", |b| {
+        b.iter_batched(
+            || scratch_dir("binary"),
+            |dir| {
+                let mut child = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+                    .arg("--log-dir")
+                    .arg(&dir)
+                    .args(["--accepted-log-channels", "app"])
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn()
+                    .unwrap();
+                child
+                    .stdin
+                    .take()
+                    .unwrap()
+                    .write_all(input.as_bytes())
+                    .unwrap();
+                assert!(child.wait().unwrap().success());
+                dir
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+
+    let _ = fs::remove_dir_all(scratch_dir("binary"));
+}
+
+criterion_group!(benches, framing, router, binary);
+criterion_main!(benches);
@@ -0,0 +1,18 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use log_revolve_rs::framing::cri::{self, CriAssembler};
+
+fuzz_target!(|data: &[u8]| {
+    let input = String::from_utf8_lossy(data);
+    let mut assembler = CriAssembler::default();
+
+    for line in input.split_inclusive('\n') {
+        if let Some(entry) = cri::parse_line(line) {
+            if let Some((stream, message)) = assembler.push(&entry) {
+                assert_eq!(stream, entry.stream);
+                assert!(message.ends_with('\n'));
+            }
+        }
+    }
+});
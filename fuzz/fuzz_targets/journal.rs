@@ -0,0 +1,18 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use log_revolve_rs::framing::journal;
+
+fuzz_target!(|data: &[u8]| {
+    let mut buffer = data;
+
+    // Every decoded entry has to consume input, or a reader would spin, and
+    // has to come back the same once written out again.
+    while let Ok(Some((entry, consumed))) = journal::decode(buffer) {
+        assert!(consumed > 0 && consumed <= buffer.len());
+        let encoded = journal::encode(&entry.fields);
+        let (decoded, _) = journal::decode(&encoded).unwrap().unwrap();
+        assert_eq!(decoded, entry);
+        buffer = &buffer[consumed..];
+    }
+});
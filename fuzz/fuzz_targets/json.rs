@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use log_revolve_rs::framing::json;
+
+fuzz_target!(|data: &[u8]| {
+    let input = String::from_utf8_lossy(data);
+
+    for line in input.lines() {
+        let _ = json::parse_line(line, "channel");
+    }
+});
@@ -0,0 +1,18 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use log_revolve_rs::framing::length_prefixed;
+
+fuzz_target!(|data: &[u8]| {
+    let mut buffer = data;
+
+    // Every decoded record has to consume input, or a reader would spin.
+    while let Ok(Some((record, consumed))) = length_prefixed::decode(buffer) {
+        assert!(consumed > 0 && consumed <= buffer.len());
+        assert_eq!(
+            length_prefixed::encode(record.channel, record.payload),
+            &buffer[..consumed]
+        );
+        buffer = &buffer[consumed..];
+    }
+});
@@ -0,0 +1,26 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use log_revolve_rs::framing::lines::{Frame, PairedDecoder};
+
+fuzz_target!(|data: &[u8]| {
+    let input = String::from_utf8_lossy(data);
+    let mut decoder = PairedDecoder::default();
+    let mut previous: Option<&str> = None;
+
+    for line in input.split_inclusive('\n') {
+        match decoder.push(line, |channel| channel.len() % 3 == 0) {
+            Frame::Channel => {}
+            Frame::Message { channel, message } | Frame::Rejected { channel, message } => {
+                assert_eq!(Some(channel), previous.map(str::trim_end));
+                assert_eq!(message, line);
+            }
+            Frame::Unframed(unframed) => assert_eq!(Some(unframed.as_str()), previous),
+        }
+        previous = Some(line);
+    }
+
+    if let Some(unframed) = decoder.finish() {
+        assert_eq!(Some(unframed.as_str()), previous);
+    }
+});
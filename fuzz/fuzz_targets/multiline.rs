@@ -0,0 +1,36 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use log_revolve_rs::framing::multiline::{Continuation, MessageAssembler, Multiline};
+
+fuzz_target!(|data: &[u8]| {
+    let input = String::from_utf8_lossy(data);
+    let (multiline, input) = match input.split_once('\n') {
+        Some(("indented", rest)) => (Multiline::Indented, rest),
+        Some((delimiter, rest)) => (Multiline::Delimited(delimiter.to_string()), rest),
+        None => return,
+    };
+    let mut assembler = MessageAssembler::new(multiline);
+    let mut written = String::new();
+
+    // Every line not starting a message continues one, or ends it.
+    for line in input.split_inclusive('\n') {
+        let message = match assembler.push(line) {
+            Continuation::Appended => continue,
+            Continuation::Ended(message) => Some(message),
+            Continuation::Other(message) => {
+                assert!(assembler.start("app", line).is_none());
+                message
+            }
+        };
+        if let Some(message) = message {
+            assert_eq!(message.channel, "app");
+            written.push_str(&message.text);
+        }
+    }
+    if let Some(message) = assembler.finish() {
+        written.push_str(&message.text);
+    }
+
+    assert!(written.len() <= input.len());
+});
@@ -0,0 +1,17 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use log_revolve_rs::framing::producers::{ProducerDecoder, MAX_PRODUCERS};
+
+fuzz_target!(|data: &[u8]| {
+    let input = String::from_utf8_lossy(data);
+    let mut decoder = ProducerDecoder::default();
+
+    for line in input.split_inclusive('\n') {
+        if let Some((channel, message)) = decoder.push(line) {
+            assert!(!channel.is_empty());
+            assert!(line.ends_with(message));
+        }
+        assert!(decoder.producers() <= MAX_PRODUCERS);
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use log_revolve_rs::framing::syslog;
+
+fuzz_target!(|data: &[u8]| {
+    let input = String::from_utf8_lossy(data);
+
+    if let Some(message) = syslog::parse(&input) {
+        assert!(message.facility < 24);
+        assert!(message.severity < 8);
+    }
+});
@@ -0,0 +1,681 @@
+use async_std::sync::Mutex;
+
+use tracing::level_filters::LevelFilter;
+
+use serde_json::{json, Map, Value};
+
+use crate::logger;
+use crate::quota::Quota;
+use crate::stats;
+use crate::{ChannelPause, FileHandle, FileWriter};
+
+/// Runs one admin request against the shared writer. Requests and responses
+/// are JSON objects: `{"command": "rotate", "channel": "web"}` is answered with
+/// `{"ok": true, ...}` or `{"ok": false, "error": "..."}`, whatever transport
+/// carried them.
+#[cfg(feature = "control-socket")]
+pub async fn execute(request: &str, writer: &Mutex<FileWriter>) -> Value {
+    match serde_json::from_str(request) {
+        Ok(request) => execute_request(&request, writer).await,
+        Err(error) => failure(format!("malformed request: {}", error)),
+    }
+}
+
+/// Names of the commands `execute` understands, and whether each one only
+/// reads state.
+#[cfg(feature = "http-admin")]
+pub const COMMANDS: &[(&str, bool)] = &[
+    ("status", true),
+    ("list-channels", true),
+    ("stats", true),
+    ("last", true),
+    ("rotate", false),
+    ("flush", false),
+    ("reload", false),
+    ("pause", false),
+    ("resume", false),
+    ("set-level", false),
+];
+
+pub async fn execute_request(request: &Value, writer: &Mutex<FileWriter>) -> Value {
+    let command = match request.get("command").and_then(Value::as_str) {
+        Some(command) => command,
+        None => return failure("missing `command`"),
+    };
+    let channel = request.get("channel").and_then(Value::as_str);
+
+    let result = match command {
+        "status" => Ok(status(&*writer.lock().await)),
+        "list-channels" => Ok(json!({
+            "channels": writer.lock().await.channels.channels().collect::<Vec<_>>(),
+        })),
+        "stats" => stats(
+            &*writer.lock().await,
+            channel,
+            request.get("hours").and_then(Value::as_str),
+        ),
+        "last" => last(
+            &*writer.lock().await,
+            channel,
+            request.get("lines").and_then(Value::as_str),
+        ),
+        "rotate" => rotate(&mut *writer.lock().await, channel).await,
+        "flush" => flush(&mut *writer.lock().await, channel).await,
+        "reload" => {
+            let channels = request.get("channels").and_then(Value::as_str);
+            reload(&mut *writer.lock().await, channels).await
+        }
+        "pause" => pause(&mut *writer.lock().await, channel),
+        "resume" => resume(&mut *writer.lock().await, channel).await,
+        "set-level" => set_level(request.get("level").and_then(Value::as_str)),
+        _ => Err(format!("unknown command `{}`", command)),
+    };
+
+    match result {
+        Ok(Value::Object(mut response)) => {
+            response.insert(String::from("ok"), Value::Bool(true));
+            Value::Object(response)
+        }
+        Ok(_) => json!({ "ok": true }),
+        Err(error) => failure(error),
+    }
+}
+
+fn failure<E: ToString>(error: E) -> Value {
+    json!({ "ok": false, "error": error.to_string() })
+}
+
+fn status(writer: &FileWriter) -> Value {
+    let channels: Vec<Value> = writer
+        .handles()
+        .map(|(name, handle)| {
+            handle_status(
+                name,
+                handle,
+                writer.paused_channels.get(name),
+                writer.quotas.get(name),
+            )
+        })
+        .collect();
+
+    let pending: Vec<Value> = writer
+        .pending
+        .iter()
+        .flat_map(|pending| {
+            pending.channels.iter().map(move |(name, channel)| {
+                let mut status = handle_status(name, &channel.handle, None, None);
+                status["first_seen"] = json!(channel.first_seen.to_rfc3339());
+                status["expires_at"] = json!((channel.last_seen + pending.ttl).to_rfc3339());
+                status
+            })
+        })
+        .collect();
+
+    let mut status = json!({
+        "pid": std::process::id(),
+        "version": env!("CARGO_PKG_VERSION"),
+        "paused": writer.paused,
+        "level": logger::level().to_string().to_lowercase(),
+        "channels": channels,
+        "inapt": handle_status(&writer.inapt_file_handle.file_name, &writer.inapt_file_handle, None, None),
+        "pending": pending,
+    });
+    if let Some(ref budget) = writer.memory_budget {
+        status["memory"] = json!({
+            "budget_bytes": budget.limit,
+            "queued_bytes": writer.queued_bytes(),
+            "dropped_lines": budget.dropped_lines,
+        });
+    }
+    if let Some(ref watch) = writer.idle_watch {
+        status["idle"] = json!({
+            "channels": watch.idle,
+            "alerts": watch.alerts,
+        });
+    }
+
+    status
+}
+
+fn handle_status(
+    name: &str,
+    handle: &FileHandle,
+    pause: Option<&ChannelPause>,
+    quota: Option<&Quota>,
+) -> Value {
+    let mut status = json!({
+        "name": name,
+        "path": handle.current_path,
+        "opened_at": handle.schedule.opened_at.to_rfc3339(),
+        "last_rotation": handle.rotated_at.map(|time| time.to_rfc3339()),
+        "last_write": handle.written_at.map(|time| time.to_rfc3339()),
+        "degraded": handle.degraded.as_ref().map(|degraded| &degraded.error),
+        "next_rotation": handle.schedule.due.as_ref().map(|time| time.to_rfc3339()),
+        "priority": handle.is_priority(),
+    });
+
+    if let Some(pause) = pause {
+        status["paused"] = json!({
+            "buffered_lines": pause.buffered.len(),
+            "buffered_bytes": pause.buffered_bytes,
+            "rejected_lines": pause.rejected,
+        });
+    }
+
+    if let Some(quota) = quota {
+        status["quota"] = json!({
+            "limit_bytes": quota.limit,
+            "used_bytes": quota.used,
+            "overflow_lines": quota.overflow_lines,
+            "overflow_bytes": quota.overflow_bytes,
+        });
+    }
+
+    status
+}
+
+/// Pauses ingestion altogether, or only the given channel while the rest keep
+/// flowing. Priority channels are never held back on their own.
+fn pause(writer: &mut FileWriter, channel: Option<&str>) -> Result<Value, String> {
+    match channel {
+        Some(channel) => {
+            match writer.handle(channel) {
+                Some(handle) if handle.is_priority() => {
+                    return Err(format!("`{}` is a priority channel", channel))
+                }
+                Some(_) => {}
+                None if writer.channels.contains(channel) => {}
+                None => return Err(format!("unknown channel `{}`", channel)),
+            }
+            writer
+                .paused_channels
+                .entry(channel.to_string())
+                .or_default();
+            Ok(json!({ "paused": true, "channel": channel }))
+        }
+        None => {
+            writer.paused = true;
+            Ok(json!({ "paused": true }))
+        }
+    }
+}
+
+/// Resumes ingestion, writing out whatever a paused channel held back.
+async fn resume(writer: &mut FileWriter, channel: Option<&str>) -> Result<Value, String> {
+    let channel = match channel {
+        Some(channel) => channel,
+        None => {
+            writer.paused = false;
+            return Ok(json!({ "paused": false }));
+        }
+    };
+
+    let pause = match writer.paused_channels.remove(channel) {
+        Some(pause) => pause,
+        None => return Err(format!("channel `{}` is not paused", channel)),
+    };
+
+    let replayed = pause.buffered.len();
+    for line in pause.buffered.iter() {
+        writer
+            .deliver(channel, line)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    writer.observe_backpressure();
+
+    Ok(json!({
+        "paused": false,
+        "channel": channel,
+        "replayed_lines": replayed,
+        "rejected_lines": pause.rejected,
+    }))
+}
+
+/// Hourly line and byte counts, newest first, of every channel or the given
+/// one, over the last `hours` hours (all that is kept when omitted).
+fn stats(writer: &FileWriter, channel: Option<&str>, hours: Option<&str>) -> Result<Value, String> {
+    let hours: usize = match hours {
+        Some(hours) => hours
+            .parse()
+            .map_err(|_| format!("invalid `hours`: {}", hours))?,
+        None => usize::MAX,
+    };
+
+    let channels: Map<String, Value> = writer
+        .stats
+        .channels
+        .iter()
+        .filter(|(name, _)| channel.is_none_or(|channel| channel == name.as_str()))
+        .map(|(name, buckets)| {
+            let buckets: Vec<Value> = buckets
+                .iter()
+                .rev()
+                .take(hours)
+                .map(|(hour, counters)| {
+                    json!({
+                        "hour": stats::hour_start(*hour).to_rfc3339(),
+                        "lines": counters.lines,
+                        "bytes": counters.bytes,
+                    })
+                })
+                .collect();
+            (name.clone(), Value::from(buckets))
+        })
+        .collect();
+
+    Ok(json!({ "channels": channels }))
+}
+
+/// The last `lines` lines (10 when omitted) written to a channel, oldest
+/// first, as kept with `--recent-lines`.
+fn last(writer: &FileWriter, channel: Option<&str>, lines: Option<&str>) -> Result<Value, String> {
+    let channel = channel.ok_or("missing `channel`")?;
+    let lines: usize = match lines {
+        Some(lines) => lines
+            .parse()
+            .map_err(|_| format!("invalid `lines`: {}", lines))?,
+        None => 10,
+    };
+    let recent_lines = writer
+        .recent_lines
+        .as_ref()
+        .ok_or("recent lines aren't kept, start with `--recent-lines`")?;
+    if !writer.channels.contains(channel) {
+        return Err(format!("unknown channel `{}`", channel));
+    }
+
+    Ok(json!({
+        "channel": channel,
+        "lines": recent_lines.last(channel, lines),
+    }))
+}
+
+async fn rotate(writer: &mut FileWriter, channel: Option<&str>) -> Result<Value, String> {
+    let rotated: Vec<String> = match channel {
+        Some(channel) => {
+            if !writer.channels.contains(channel) {
+                return Err(format!("unknown channel `{}`", channel));
+            }
+            writer
+                .channels
+                .rotate(channel)
+                .await
+                .map_err(|e| e.to_string())?;
+            writer
+                .handle(channel)
+                .map(|handle| handle.file_name.clone())
+                .into_iter()
+                .collect()
+        }
+        None => {
+            let rotated = writer
+                .handles()
+                .map(|(_, handle)| handle)
+                .chain(writer.combined_handles.values())
+                .chain(std::iter::once(&writer.inapt_file_handle))
+                .map(|handle| handle.file_name.clone())
+                .collect();
+            writer.rotate_all().await.map_err(|e| e.to_string())?;
+            rotated
+        }
+    };
+
+    Ok(json!({ "rotated": rotated }))
+}
+
+/// Writes out the batched lines of one channel, or of every file, and
+/// fsyncs them, e.g. ahead of a backup. Files held while their directory is
+/// away, or degraded, are skipped.
+async fn flush(writer: &mut FileWriter, channel: Option<&str>) -> Result<Value, String> {
+    let handles: Vec<&mut FileHandle> = match channel {
+        Some(channel) if !writer.channels.contains(channel) => {
+            return Err(format!("unknown channel `{}`", channel))
+        }
+        Some(channel) => writer.handle_mut(channel).into_iter().collect(),
+        None => writer.all_handles_mut().collect(),
+    };
+
+    let mut flushed = Vec::new();
+    for handle in handles {
+        if handle.held.is_some() || handle.degraded.is_some() {
+            continue;
+        }
+        handle.sync().await.map_err(|e| e.to_string())?;
+        flushed.push(handle.file_name.clone());
+    }
+
+    Ok(json!({ "flushed": flushed }))
+}
+
+/// Reopens every file and, given a comma-separated `channels` list, makes it
+/// the new set of accepted channels.
+async fn reload(writer: &mut FileWriter, channels: Option<&str>) -> Result<Value, String> {
+    writer.reopen_all().await.map_err(|e| e.to_string())?;
+
+    let channels: Vec<String> = match channels {
+        Some(channels) => channels
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect(),
+        None => return Ok(json!({})),
+    };
+    if channels.is_empty() {
+        return Err(String::from("`channels` must name at least one channel"));
+    }
+
+    let (added, removed) = writer
+        .set_channels(&channels)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(json!({ "added": added, "removed": removed }))
+}
+
+fn set_level(level: Option<&str>) -> Result<Value, String> {
+    let level: LevelFilter = level
+        .ok_or("missing `level`")?
+        .parse()
+        .map_err(|_| String::from("expected one of off, error, warn, info, debug, trace"))?;
+    logger::set_level(level);
+    tracing::info!("log level set to {}", level);
+
+    Ok(json!({ "level": level.to_string().to_lowercase() }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::task;
+
+    use chrono::{DateTime, Duration, Local, NaiveTime, Timelike};
+
+    use crate::testing::{self, LogDir};
+
+    async fn writer(dir: &LogDir, args: &[&str]) -> Mutex<FileWriter> {
+        let mut args = args.to_vec();
+        args.extend(["--accepted-log-channels", "app,web"]);
+        Mutex::new(testing::writer(dir, &args).await)
+    }
+
+    #[test]
+    fn requests_name_a_known_command() {
+        task::block_on(async {
+            let dir = LogDir::new("admin-commands");
+            let writer = writer(&dir, &[]).await;
+
+            let response = execute_request(&json!({ "channel": "web" }), &writer).await;
+            assert_eq!(
+                response,
+                json!({ "ok": false, "error": "missing `command`" })
+            );
+
+            let response = execute_request(&json!({ "command": "compact" }), &writer).await;
+            assert_eq!(
+                response,
+                json!({ "ok": false, "error": "unknown command `compact`" })
+            );
+        });
+    }
+
+    #[test]
+    fn status_lists_every_channel() {
+        task::block_on(async {
+            let dir = LogDir::new("admin-status");
+            let writer = writer(&dir, &[]).await;
+
+            let response = execute_request(&json!({ "command": "status" }), &writer).await;
+            assert_eq!(response["ok"], true);
+            assert_eq!(response["paused"], false);
+            let names: Vec<&str> = response["channels"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|channel| channel["name"].as_str().unwrap())
+                .collect();
+            assert_eq!(names, ["app", "web"]);
+
+            let response = execute_request(&json!({ "command": "list-channels" }), &writer).await;
+            assert_eq!(response, json!({ "ok": true, "channels": ["app", "web"] }));
+        });
+    }
+
+    #[test]
+    fn unknown_channels_are_refused() {
+        task::block_on(async {
+            let dir = LogDir::new("admin-unknown");
+            let writer = writer(&dir, &[]).await;
+
+            for command in ["rotate", "flush", "pause"] {
+                let request = json!({ "command": command, "channel": "db" });
+                assert_eq!(
+                    execute_request(&request, &writer).await,
+                    json!({ "ok": false, "error": "unknown channel `db`" })
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn flush_writes_out_batched_lines() {
+        task::block_on(async {
+            let dir = LogDir::new("admin-flush");
+            let writer = writer(&dir, &["--flush-bytes", "65536"]).await;
+            writer
+                .lock()
+                .await
+                .write_to_channel("", "web", "GET /\n")
+                .await
+                .unwrap();
+            assert_eq!(dir.read("web"), "");
+
+            let request = json!({ "command": "flush", "channel": "web" });
+            let response = execute_request(&request, &writer).await;
+            assert_eq!(response, json!({ "ok": true, "flushed": ["web"] }));
+            assert_eq!(dir.read("web"), "GET /\n");
+        });
+    }
+
+    #[test]
+    fn reload_needs_a_channel() {
+        task::block_on(async {
+            let dir = LogDir::new("admin-reload");
+            let writer = writer(&dir, &[]).await;
+
+            let request = json!({ "command": "reload", "channels": "," });
+            assert_eq!(
+                execute_request(&request, &writer).await,
+                json!({ "ok": false, "error": "`channels` must name at least one channel" })
+            );
+
+            let request = json!({ "command": "reload", "channels": "web,db" });
+            assert_eq!(
+                execute_request(&request, &writer).await,
+                json!({ "ok": true, "added": ["db"], "removed": ["app"] })
+            );
+        });
+    }
+
+    async fn send(writer: &Mutex<FileWriter>, channel: &str, line: &str) {
+        writer
+            .lock()
+            .await
+            .write_to_channel("", channel, line)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn paused_channels_hold_their_lines_until_resumed() {
+        task::block_on(async {
+            let dir = LogDir::new("admin-pause");
+            let writer = writer(&dir, &["--flush-bytes", "0"]).await;
+
+            let request = json!({ "command": "pause", "channel": "web" });
+            let response = execute_request(&request, &writer).await;
+            assert_eq!(
+                response,
+                json!({ "ok": true, "paused": true, "channel": "web" })
+            );
+            send(&writer, "web", "GET /\n").await;
+            send(&writer, "app", "started\n").await;
+            assert_eq!(dir.read("web"), "");
+            assert_eq!(dir.read("app"), "started\n");
+
+            let status = execute_request(&json!({ "command": "status" }), &writer).await;
+            assert_eq!(
+                status["channels"][1]["paused"],
+                json!({ "buffered_lines": 1, "buffered_bytes": 6, "rejected_lines": 0 })
+            );
+
+            let request = json!({ "command": "resume", "channel": "web" });
+            let response = execute_request(&request, &writer).await;
+            assert_eq!(
+                response,
+                json!({
+                    "ok": true,
+                    "paused": false,
+                    "channel": "web",
+                    "replayed_lines": 1,
+                    "rejected_lines": 0,
+                })
+            );
+            assert_eq!(dir.read("web"), "GET /\n");
+
+            let response = execute_request(&request, &writer).await;
+            assert_eq!(response["error"], "channel `web` is not paused");
+        });
+    }
+
+    #[test]
+    fn lines_past_the_pause_buffer_go_to_the_inapt_file() {
+        task::block_on(async {
+            let dir = LogDir::new("admin-pause-cap");
+            let writer = writer(&dir, &["--flush-bytes", "0", "--pause-buffer-bytes", "8"]).await;
+
+            let request = json!({ "command": "pause", "channel": "web" });
+            execute_request(&request, &writer).await;
+            send(&writer, "web", "GET /\n").await;
+            send(&writer, "web", "GET /a\n").await;
+
+            let request = json!({ "command": "resume", "channel": "web" });
+            let response = execute_request(&request, &writer).await;
+            assert_eq!(response["replayed_lines"], 1);
+            assert_eq!(response["rejected_lines"], 1);
+            assert_eq!(dir.read("web"), "GET /\n");
+            assert!(dir.read("inapt").ends_with("[paused:web] GET /a\n"));
+        });
+    }
+
+    #[test]
+    fn priority_channels_are_not_paused_on_their_own() {
+        task::block_on(async {
+            let dir = LogDir::new("admin-pause-priority");
+            let writer = writer(&dir, &["--priority-channels", "app"]).await;
+
+            let request = json!({ "command": "pause", "channel": "app" });
+            assert_eq!(
+                execute_request(&request, &writer).await,
+                json!({ "ok": false, "error": "`app` is a priority channel" })
+            );
+
+            let request = json!({ "command": "pause" });
+            execute_request(&request, &writer).await;
+            assert!(writer.lock().await.paused);
+            let request = json!({ "command": "resume" });
+            execute_request(&request, &writer).await;
+            assert!(!writer.lock().await.paused);
+        });
+    }
+
+    fn rotation_times(
+        status: &Value,
+        index: usize,
+    ) -> (Option<DateTime<Local>>, Option<DateTime<Local>>) {
+        let time = |name: &str| {
+            status["channels"][index][name].as_str().map(|time| {
+                DateTime::parse_from_rfc3339(time)
+                    .unwrap()
+                    .with_timezone(&Local)
+            })
+        };
+        (time("last_rotation"), time("next_rotation"))
+    }
+
+    #[test]
+    fn status_tells_when_channels_rotated_and_rotate_next() {
+        task::block_on(async {
+            let dir = LogDir::new("admin-rotation-times");
+            let writer = writer(&dir, &["--channel-rotation-intervals", "web=1d"]).await;
+
+            let status = execute_request(&json!({ "command": "status" }), &writer).await;
+            let (last, next) = rotation_times(&status, 0);
+            assert_eq!(last, None);
+            let next = next.unwrap();
+            assert!(next > Local::now() && next <= Local::now() + Duration::hours(1));
+            assert_eq!((next.minute(), next.second()), (0, 0));
+            let (_, next) = rotation_times(&status, 1);
+            assert_eq!(next.unwrap().time(), NaiveTime::MIN);
+
+            let request = json!({ "command": "rotate", "channel": "app" });
+            execute_request(&request, &writer).await;
+            let status = execute_request(&json!({ "command": "status" }), &writer).await;
+            let (last, _) = rotation_times(&status, 0);
+            assert!(last.unwrap() <= Local::now());
+        });
+    }
+
+    #[test]
+    fn externally_rotated_channels_have_no_next_rotation() {
+        task::block_on(async {
+            let dir = LogDir::new("admin-rotation-external");
+            let writer = writer(&dir, &["--external-rotation"]).await;
+
+            let status = execute_request(&json!({ "command": "status" }), &writer).await;
+            assert_eq!(rotation_times(&status, 0), (None, None));
+        });
+    }
+
+    #[test]
+    fn levels_are_checked() {
+        assert_eq!(set_level(None).unwrap_err(), "missing `level`");
+        assert_eq!(
+            set_level(Some("loud")).unwrap_err(),
+            "expected one of off, error, warn, info, debug, trace"
+        );
+    }
+
+    #[test]
+    fn last_shows_the_recent_lines_of_a_channel() {
+        task::block_on(async {
+            let dir = LogDir::new("admin-last");
+            let request = json!({ "command": "last", "channel": "web", "lines": "2" });
+
+            let without = writer(&dir, &[]).await;
+            let response = execute_request(&request, &without).await;
+            assert_eq!(
+                response["error"],
+                "recent lines aren't kept, start with `--recent-lines`"
+            );
+
+            let writer = writer(&dir, &["--recent-lines", "5"]).await;
+            for line in ["GET /a\n", "GET /b\n", "GET /c\n"] {
+                send(&writer, "web", line).await;
+            }
+            let response = execute_request(&request, &writer).await;
+            assert_eq!(response["lines"], json!(["GET /b", "GET /c"]));
+
+            let response = execute_request(
+                &json!({ "command": "last", "channel": "web", "lines": "many" }),
+                &writer,
+            )
+            .await;
+            assert_eq!(response["error"], "invalid `lines`: many");
+            let response =
+                execute_request(&json!({ "command": "last", "channel": "db" }), &writer).await;
+            assert_eq!(response["error"], "unknown channel `db`");
+        });
+    }
+}
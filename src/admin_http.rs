@@ -0,0 +1,179 @@
+use async_std::io;
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+use serde_json::{json, Map, Value};
+
+use crate::{admin, http, FileWriter};
+
+/// Mirrors the control socket commands as `/admin/<command>` endpoints, for
+/// hosts where a unix socket is awkward to reach. Every request has to carry
+/// `Authorization: Bearer <token>`.
+pub async fn serve(listener: TcpListener, writer: Arc<Mutex<FileWriter>>, token: Arc<String>) {
+    let mut incoming = listener.incoming();
+
+    while let Some(stream) = incoming.next().await {
+        match stream {
+            Ok(stream) => {
+                let writer = writer.clone();
+                let token = token.clone();
+                task::spawn(async move {
+                    if let Err(error) = handle_connection(stream, writer, &token).await {
+                        tracing::warn!("admin HTTP connection failed: {}", error);
+                    }
+                });
+            }
+            Err(error) => tracing::warn!("unable to accept admin HTTP connection: {}", error),
+        }
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    writer: Arc<Mutex<FileWriter>>,
+    token: &str,
+) -> Result<(), io::Error> {
+    let (status, body) = match http::read_request(&stream).await {
+        Ok(request) => route(&request, &writer, token).await,
+        Err(error) => (400, failure(error)),
+    };
+
+    respond(&stream, status, body).await
+}
+
+async fn route(request: &http::Request, writer: &Mutex<FileWriter>, token: &str) -> (u16, Value) {
+    let authorized = request
+        .header("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|presented| constant_time_eq(presented.as_bytes(), token.as_bytes()))
+        .unwrap_or(false);
+    if !authorized {
+        return (401, failure("missing or invalid token"));
+    }
+
+    let command = match request.path.strip_prefix("/admin/") {
+        Some(command) => command,
+        None => return (404, failure("not found")),
+    };
+    let read_only = match admin::COMMANDS.iter().find(|(name, _)| *name == command) {
+        Some((_, read_only)) => *read_only,
+        None => return (404, failure("unknown command")),
+    };
+    let expected_method = if read_only { "GET" } else { "POST" };
+    if request.method != expected_method {
+        return (405, failure("method not allowed"));
+    }
+
+    // Arguments come from the query string or a JSON object body.
+    let mut arguments = match serde_json::from_slice::<Value>(&request.body) {
+        Ok(Value::Object(arguments)) => arguments,
+        _ => Map::new(),
+    };
+    for (key, value) in request.query.iter() {
+        arguments.insert(key.clone(), Value::String(value.clone()));
+    }
+    arguments.insert(String::from("command"), Value::String(command.to_string()));
+
+    let response = admin::execute_request(&Value::Object(arguments), writer).await;
+    match response.get("ok") {
+        Some(Value::Bool(true)) => (200, response),
+        _ => (400, response),
+    }
+}
+
+fn failure<E: ToString>(error: E) -> Value {
+    json!({ "ok": false, "error": error.to_string() })
+}
+
+async fn respond(stream: &TcpStream, status: u16, body: Value) -> Result<(), io::Error> {
+    let mut body = body.to_string();
+    body.push('\n');
+    http::write_response(stream, status, "application/json", body.as_bytes()).await
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{self, LogDir};
+
+    const TOKEN: &str = "s3cret";
+
+    fn request(method: &str, target: &str, token: Option<&str>) -> http::Request {
+        let mut head = format!("{} {} HTTP/1.1\r\n", method, target);
+        if let Some(token) = token {
+            head.push_str(&format!("Authorization: Bearer {}\r\n", token));
+        }
+        head.push_str("\r\n");
+        task::block_on(http::read_request(head.as_bytes())).unwrap()
+    }
+
+    fn route_on(dir: &LogDir, request: http::Request) -> (u16, Value) {
+        task::block_on(async {
+            let writer =
+                Mutex::new(testing::writer(dir, &["--accepted-log-channels", "web"]).await);
+            route(&request, &writer, TOKEN).await
+        })
+    }
+
+    #[test]
+    fn requests_need_the_token() {
+        let dir = LogDir::new("admin-http-token");
+
+        for token in [None, Some("wrong"), Some("s3cre")] {
+            let (status, body) = route_on(&dir, request("GET", "/admin/status", token));
+            assert_eq!(status, 401);
+            assert_eq!(body["error"], "missing or invalid token");
+        }
+    }
+
+    #[test]
+    fn commands_are_routed_by_path_and_method() {
+        let dir = LogDir::new("admin-http-routes");
+
+        let (status, _) = route_on(&dir, request("GET", "/metrics", Some(TOKEN)));
+        assert_eq!(status, 404);
+        let (status, _) = route_on(&dir, request("GET", "/admin/compact", Some(TOKEN)));
+        assert_eq!(status, 404);
+        let (status, _) = route_on(&dir, request("GET", "/admin/rotate", Some(TOKEN)));
+        assert_eq!(status, 405);
+        let (status, _) = route_on(&dir, request("POST", "/admin/status", Some(TOKEN)));
+        assert_eq!(status, 405);
+
+        let (status, body) = route_on(&dir, request("GET", "/admin/list-channels", Some(TOKEN)));
+        assert_eq!(status, 200);
+        assert_eq!(body, json!({ "ok": true, "channels": ["web"] }));
+    }
+
+    #[test]
+    fn arguments_come_from_the_query() {
+        let dir = LogDir::new("admin-http-query");
+
+        let (status, body) = route_on(
+            &dir,
+            request("POST", "/admin/rotate?channel=db", Some(TOKEN)),
+        );
+        assert_eq!(status, 400);
+        assert_eq!(body["error"], "unknown channel `db`");
+    }
+
+    #[test]
+    fn tokens_are_compared_whole() {
+        assert!(constant_time_eq(b"s3cret", b"s3cret"));
+        assert!(!constant_time_eq(b"s3cret", b"s3cre"));
+        assert!(!constant_time_eq(b"s3cret", b"s3creT"));
+    }
+}
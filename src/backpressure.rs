@@ -0,0 +1,234 @@
+use async_std::io;
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+use chrono::Local;
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::Duration;
+
+use crate::{fd, report, FileWriter};
+
+/// How often the router checks for lines dropped under pressure, and for
+/// held back bytes having drained without a line to notice it.
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tells a cooperating producer to slow down once the router holds back more
+/// than `high_water` bytes, and that it may speed up again once that drains to
+/// `low_water`. Notices are JSON lines such as
+/// `{"event":"backpressure","state":"on","queued_bytes":1048576,"at":"..."}`.
+/// Lines dropped because the router couldn't keep up, from full input
+/// queues or over the memory budget, are told of as well:
+/// `{"event":"drops","state":"on","dropped_lines":12,"at":"..."}` once they
+/// start, and `"off"` with the lines dropped meanwhile once a check finds no
+/// more.
+pub struct Backpressure {
+    high_water: usize,
+    low_water: usize,
+    engaged: bool,
+    /// Lines dropped under pressure as of the last check.
+    dropped_lines: u64,
+    /// Lines dropped since drops started, `None` while there are none.
+    dropping: Option<u64>,
+    output: Box<dyn Write + Send>,
+}
+
+impl Backpressure {
+    /// `output` is `stdout`, `stderr`, `fd:<n>` for a descriptor inherited
+    /// from the parent process, or the path of a file or named pipe appended
+    /// to. A named pipe is waited on until the producer opens it to read.
+    pub fn new(high_water: usize, low_water: usize, output: &str) -> Result<Self, io::Error> {
+        let output: Box<dyn Write + Send> = match output {
+            "stdout" => Box::new(std::io::stdout()),
+            "stderr" => Box::new(std::io::stderr()),
+            _ if output.starts_with("fd:") => Box::new(fd::open(output)?),
+            path => Box::new(OpenOptions::new().append(true).create(true).open(path)?),
+        };
+
+        Ok(Backpressure {
+            high_water,
+            low_water,
+            engaged: false,
+            dropped_lines: 0,
+            dropping: None,
+            output,
+        })
+    }
+
+    pub fn observe(&mut self, queued_bytes: usize) {
+        if !self.engaged && queued_bytes >= self.high_water {
+            self.engaged = true;
+            self.notify("on", queued_bytes);
+        } else if self.engaged && queued_bytes <= self.low_water {
+            self.engaged = false;
+            self.notify("off", queued_bytes);
+        }
+    }
+
+    /// Takes the count of lines dropped under pressure so far, telling of
+    /// drops starting when it grew since the last check and of them
+    /// stopping when it no longer does.
+    pub fn observe_drops(&mut self, dropped_lines: u64) {
+        let new = dropped_lines.saturating_sub(self.dropped_lines);
+        self.dropped_lines = dropped_lines;
+        match (self.dropping, new) {
+            (None, 0) => {}
+            (None, new) => {
+                self.dropping = Some(new);
+                self.emit("drops", "on", "dropped_lines", new as usize);
+            }
+            (Some(dropped), 0) => {
+                self.dropping = None;
+                self.emit("drops", "off", "dropped_lines", dropped as usize);
+            }
+            (Some(dropped), new) => self.dropping = Some(dropped + new),
+        }
+    }
+
+    fn notify(&mut self, state: &str, queued_bytes: usize) {
+        self.emit("backpressure", state, "queued_bytes", queued_bytes);
+    }
+
+    fn emit(&mut self, event: &str, state: &str, count_name: &str, count: usize) {
+        let notice = format!(
+            "{{\"event\":\"{}\",\"state\":\"{}\",\"{}\":{},\"at\":\"{}\"}}\n",
+            event,
+            state,
+            count_name,
+            count,
+            Local::now().to_rfc3339()
+        );
+
+        let result = self
+            .output
+            .write_all(notice.as_bytes())
+            .and_then(|()| self.output.flush());
+        if let Err(error) = result {
+            tracing::warn!("unable to emit backpressure notice: {}", error);
+            report::record_error("backpressure", error);
+        }
+    }
+}
+
+/// Checks every `interval` for lines dropped under pressure, and for held
+/// back bytes drained by flushes rather than by lines written.
+pub async fn watch_every(writer: Arc<Mutex<FileWriter>>, interval: Duration) {
+    loop {
+        task::sleep(interval).await;
+
+        writer.lock().await.observe_pressure();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex as SyncMutex;
+
+    /// Notices written so far, shared with the test.
+    #[derive(Clone, Default)]
+    struct Notices(Arc<SyncMutex<Vec<u8>>>);
+
+    impl Write for Notices {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Notices {
+        /// The notices written since the last call, up to their time.
+        fn take(&self) -> Vec<String> {
+            let notices = std::mem::take(&mut *self.0.lock().unwrap());
+            String::from_utf8(notices)
+                .unwrap()
+                .lines()
+                .map(|line| {
+                    let (notice, at) = line.split_once(",\"at\":").unwrap();
+                    assert!(at.ends_with("\"}"));
+                    notice.to_string()
+                })
+                .collect()
+        }
+    }
+
+    fn backpressure(high_water: usize, low_water: usize) -> (Backpressure, Notices) {
+        let notices = Notices::default();
+        let backpressure = Backpressure {
+            high_water,
+            low_water,
+            engaged: false,
+            dropped_lines: 0,
+            dropping: None,
+            output: Box::new(notices.clone()),
+        };
+        (backpressure, notices)
+    }
+
+    #[test]
+    fn notices_go_out_once_per_crossing() {
+        let (mut backpressure, notices) = backpressure(100, 20);
+
+        backpressure.observe(99);
+        assert!(notices.take().is_empty());
+        backpressure.observe(100);
+        backpressure.observe(150);
+        assert_eq!(
+            notices.take(),
+            [r#"{"event":"backpressure","state":"on","queued_bytes":100"#]
+        );
+
+        backpressure.observe(50);
+        assert!(notices.take().is_empty());
+        backpressure.observe(20);
+        backpressure.observe(0);
+        assert_eq!(
+            notices.take(),
+            [r#"{"event":"backpressure","state":"off","queued_bytes":20"#]
+        );
+    }
+
+    #[test]
+    fn drops_are_told_of_when_they_start_and_stop() {
+        let (mut backpressure, notices) = backpressure(100, 20);
+
+        backpressure.observe_drops(0);
+        assert!(notices.take().is_empty());
+        backpressure.observe_drops(3);
+        assert_eq!(
+            notices.take(),
+            [r#"{"event":"drops","state":"on","dropped_lines":3"#]
+        );
+        backpressure.observe_drops(10);
+        assert!(notices.take().is_empty());
+
+        backpressure.observe_drops(10);
+        assert_eq!(
+            notices.take(),
+            [r#"{"event":"drops","state":"off","dropped_lines":10"#]
+        );
+        backpressure.observe_drops(10);
+        assert!(notices.take().is_empty());
+    }
+
+    #[test]
+    fn notices_may_be_appended_to_a_file() {
+        let dir = crate::testing::LogDir::new("backpressure-file");
+        let path = dir.path().join("notices");
+        std::fs::write(&path, "earlier\n").unwrap();
+
+        let mut backpressure = Backpressure::new(10, 5, path.to_str().unwrap()).unwrap();
+        backpressure.observe(10);
+        let notices = dir.read("notices");
+        assert!(
+            notices.starts_with("earlier\n{\"event\":\"backpressure\",\"state\":\"on\""),
+            "{}",
+            notices
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use std::str::FromStr;
+
+use crate::CliOptions;
+
+/// What the router prints on stdout once it is ready for input.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StartupBanner {
+    /// `log-revolve-rs started`
+    Plain,
+    /// The startup record, for fleet tooling checking what actually started.
+    Json,
+}
+
+impl FromStr for StartupBanner {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(StartupBanner::Plain),
+            "json" => Ok(StartupBanner::Json),
+            _ => Err(format!("unknown startup banner: {}", s)),
+        }
+    }
+}
+
+/// Describes the router as started: version, pid, a hash of the effective
+/// options, channels and inputs, e.g. `{"event":"started","version":"0.1.0",
+/// "pid":42,"config_hash":"9f3c…","channels":["app"],"inputs":["stdin"]}`.
+pub fn startup_record(options: &CliOptions) -> String {
+    let channels = crate::accepted_channels(options);
+    let syslog: Vec<String> = options
+        .listen_syslog_udp
+        .iter()
+        .map(|addr| format!("syslog+udp://{}", addr))
+        .collect();
+    let inputs: Vec<&str> = std::iter::once("stdin")
+        .chain(options.inputs.iter().map(String::as_str))
+        .chain(options.listen.iter().map(String::as_str))
+        .chain(syslog.iter().map(String::as_str))
+        .collect();
+
+    format!(
+        "{{\"event\":\"started\",\"version\":{:?},\"pid\":{},\"config_hash\":\"{:016x}\",\"channels\":{},\"inputs\":{}}}",
+        env!("CARGO_PKG_VERSION"),
+        std::process::id(),
+        config_hash(options),
+        json_list(&channels),
+        json_list(&inputs)
+    )
+}
+
+/// FNV-1a over the options once placeholders are resolved, so two routers
+/// reporting the same hash were started alike whatever their command lines
+/// looked like.
+fn config_hash(options: &CliOptions) -> u64 {
+    format!("{:?}", options)
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+fn json_list(items: &[&str]) -> String {
+    let items: Vec<String> = items.iter().map(|item| format!("{:?}", item)).collect();
+    format!("[{}]", items.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{self, LogDir};
+
+    #[test]
+    fn banners_are_plain_or_json() {
+        assert_eq!("plain".parse(), Ok(StartupBanner::Plain));
+        assert_eq!("json".parse(), Ok(StartupBanner::Json));
+        assert_eq!(
+            "yaml".parse::<StartupBanner>(),
+            Err(String::from("unknown startup banner: yaml"))
+        );
+    }
+
+    #[test]
+    fn records_describe_what_started() {
+        let dir = LogDir::new("banner");
+        let options = testing::options(
+            &dir,
+            &[
+                "--accepted-log-channels",
+                "app,web",
+                "--listen",
+                "tcp://127.0.0.1:5140",
+                "--listen-syslog-udp",
+                "0.0.0.0:514",
+            ],
+        );
+
+        let record = startup_record(&options);
+        let prefix = format!(
+            "{{\"event\":\"started\",\"version\":\"{}\",\"pid\":{},\"config_hash\":\"",
+            env!("CARGO_PKG_VERSION"),
+            std::process::id()
+        );
+        assert!(record.starts_with(&prefix), "{}", record);
+        assert!(
+            record.ends_with(
+                "\",\"channels\":[\"app\",\"web\"],\"inputs\":[\"stdin\",\"tcp://127.0.0.1:5140\",\"syslog+udp://0.0.0.0:514\"]}"
+            ),
+            "{}",
+            record
+        );
+    }
+
+    #[test]
+    fn routers_started_alike_share_their_hash() {
+        let dir = LogDir::new("banner-hash");
+        let options = |channels| testing::options(&dir, &["--accepted-log-channels", channels]);
+
+        assert_eq!(config_hash(&options("app")), config_hash(&options("app")));
+        assert_ne!(config_hash(&options("app")), config_hash(&options("web")));
+    }
+}
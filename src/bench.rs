@@ -0,0 +1,269 @@
+use async_std::io::{self, BufWriter, Write};
+use async_std::net::TcpStream;
+#[cfg(unix)]
+use async_std::os::unix::net::UnixStream;
+use async_std::prelude::*;
+use async_std::task;
+
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use structopt::StructOpt;
+
+use crate::quota;
+
+/// How often the producer catches up with its target rate.
+const TICK: Duration = Duration::from_millis(10);
+
+/// Generates synthetic traffic for a running router, to size deployments
+/// empirically: `log-revolve-rs bench-produce --channels 50 --rate 100k/s
+/// --size 200B | log-revolve-rs --accepted-log-channels bench-0,...`.
+#[derive(StructOpt)]
+#[structopt(name = "bench-produce")]
+pub struct BenchOptions {
+    /// Number of channels lines are spread over, named `<prefix><n>`
+    #[structopt(long, default_value = "1")]
+    channels: usize,
+
+    #[structopt(long, default_value = "bench-")]
+    channel_prefix: String,
+
+    /// Lines per second, e.g. `100k/s`; `k` and `m` multiply by a thousand
+    /// and a million
+    #[structopt(long, default_value = "1k/s")]
+    rate: Rate,
+
+    /// Size of every message, newline included, e.g. `200B`
+    #[structopt(long, default_value = "200B", parse(try_from_str = parse_message_size))]
+    size: usize,
+
+    /// Framing to produce: `lines` or `json`
+    #[structopt(long, default_value = "lines")]
+    framing: BenchFraming,
+
+    /// Where lines go: `stdout`, `tcp://host:port` or `unix:<path>`
+    #[structopt(long, default_value = "stdout")]
+    to: String,
+
+    /// Seconds to produce for, 0 to go on until interrupted
+    #[structopt(long, default_value = "10")]
+    duration: u64,
+}
+
+pub struct Rate(u64);
+
+impl FromStr for Rate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let count = s.strip_suffix("/s").unwrap_or(s);
+        let (digits, multiplier) = match count.chars().last() {
+            Some('k') | Some('K') => (&count[..count.len() - 1], 1_000),
+            Some('m') | Some('M') => (&count[..count.len() - 1], 1_000_000),
+            _ => (count, 1),
+        };
+
+        digits
+            .parse::<u64>()
+            .ok()
+            .and_then(|digits| digits.checked_mul(multiplier))
+            .filter(|rate| *rate > 0)
+            .map(Rate)
+            .ok_or_else(|| format!("expected a rate such as `100k/s`, got `{}`", s))
+    }
+}
+
+fn parse_message_size(s: &str) -> Result<usize, String> {
+    quota::parse_size(s)
+        .map(|size| size as usize)
+        .filter(|size| *size > 0)
+        .ok_or_else(|| format!("expected a size such as `200B`, got `{}`", s))
+}
+
+pub enum BenchFraming {
+    Lines,
+    Json,
+}
+
+impl FromStr for BenchFraming {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lines" => Ok(BenchFraming::Lines),
+            "json" => Ok(BenchFraming::Json),
+            _ => Err(format!("unknown framing: {}", s)),
+        }
+    }
+}
+
+pub async fn run(options: BenchOptions) -> Result<(), io::Error> {
+    let output: Box<dyn Write + Unpin + Send> = match options.to.as_str() {
+        "stdout" => Box::new(io::stdout()),
+        to => match to.strip_prefix("tcp://") {
+            Some(addr) => Box::new(TcpStream::connect(addr).await?),
+            #[cfg(unix)]
+            None if to.starts_with("unix:") => Box::new(UnixStream::connect(&to[5..]).await?),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("expected stdout, tcp:// or unix:, got `{}`", to),
+                ))
+            }
+        },
+    };
+    let mut output = BufWriter::new(output);
+
+    let channels: Vec<String> = (0..options.channels.max(1))
+        .map(|n| format!("{}{}", options.channel_prefix, n))
+        .collect();
+    let duration = Duration::from_secs(options.duration);
+    let started_at = Instant::now();
+    let mut sent: u64 = 0;
+    let mut bytes: u64 = 0;
+
+    loop {
+        let elapsed = started_at.elapsed();
+        if options.duration > 0 && elapsed >= duration {
+            break;
+        }
+
+        let due = (elapsed.as_secs_f64() * options.rate.0 as f64) as u64;
+        while sent < due {
+            let channel = &channels[sent as usize % channels.len()];
+            let record = frame(&options.framing, channel, &message(sent, options.size));
+            output.write_all(record.as_bytes()).await?;
+            sent += 1;
+            bytes += options.size as u64;
+        }
+        output.flush().await?;
+
+        task::sleep(TICK).await;
+    }
+
+    let seconds = started_at.elapsed().as_secs_f64();
+    eprintln!(
+        "sent {} lines ({} message bytes) in {:.1}s, {:.0} lines/s",
+        sent,
+        bytes,
+        seconds,
+        sent as f64 / seconds
+    );
+
+    Ok(())
+}
+
+/// `size` bytes: a sequence number padded out, ending in a newline.
+fn message(sequence: u64, size: usize) -> String {
+    let mut message = format!("bench {} ", sequence);
+    let padding = size.saturating_sub(message.len() + 1);
+    message.extend(std::iter::repeat_n('x', padding));
+    message.push('\n');
+    message
+}
+
+fn frame(framing: &BenchFraming, channel: &str, message: &str) -> String {
+    match framing {
+        BenchFraming::Lines => format!("{}\n{}", channel, message),
+        BenchFraming::Json => format!(
+            "{{\"channel\":\"{}\",\"message\":\"{}\"}}\n",
+            channel,
+            message.trim_end()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::net::TcpListener;
+
+    #[test]
+    fn rates_take_thousands_and_millions() {
+        assert_eq!("250".parse::<Rate>().unwrap().0, 250);
+        assert_eq!("100k/s".parse::<Rate>().unwrap().0, 100_000);
+        assert_eq!("2M/s".parse::<Rate>().unwrap().0, 2_000_000);
+        for rate in ["0/s", "fast", "k/s", "-5/s"] {
+            assert_eq!(
+                rate.parse::<Rate>().err(),
+                Some(format!("expected a rate such as `100k/s`, got `{}`", rate))
+            );
+        }
+    }
+
+    #[test]
+    fn messages_are_exactly_their_size() {
+        assert_eq!(parse_message_size("200B"), Ok(200));
+        assert!(parse_message_size("0B").is_err());
+
+        let padded = message(42, 32);
+        assert_eq!(padded.len(), 32);
+        assert!(padded.starts_with("bench 42 x"));
+        assert!(padded.ends_with("x\n"));
+        // Too small for the sequence number, which is kept whole.
+        assert_eq!(message(12345, 4), "bench 12345 \n");
+    }
+
+    #[test]
+    fn records_are_framed_for_the_router() {
+        assert_eq!(
+            frame(&BenchFraming::Lines, "bench-0", "bench 0 x\n"),
+            "bench-0\nbench 0 x\n"
+        );
+        assert_eq!(
+            frame(&BenchFraming::Json, "bench-1", "bench 1 x\n"),
+            "{\"channel\":\"bench-1\",\"message\":\"bench 1 x\"}\n"
+        );
+        assert!("cri".parse::<BenchFraming>().is_err());
+    }
+
+    #[test]
+    fn lines_are_spread_over_the_channels_at_the_rate() {
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let to = format!("tcp://{}", listener.local_addr().unwrap());
+            let options = BenchOptions::from_iter(&[
+                "bench-produce",
+                "--channels",
+                "3",
+                "--rate",
+                "200/s",
+                "--size",
+                "16B",
+                "--duration",
+                "1",
+                "--to",
+                &to,
+            ]);
+
+            let producer = task::spawn(run(options));
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).await.unwrap();
+            producer.await.unwrap();
+
+            let lines: Vec<&str> = received.lines().collect();
+            let sent = lines.len() / 2;
+            assert!((100..=200).contains(&sent), "{} lines sent", sent);
+            assert_eq!(
+                &lines[..6],
+                &[
+                    "bench-0",
+                    "bench 0 xxxxxxx",
+                    "bench-1",
+                    "bench 1 xxxxxxx",
+                    "bench-2",
+                    "bench 2 xxxxxxx"
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn destinations_are_stdout_tcp_or_unix() {
+        let options = BenchOptions::from_iter(&["bench-produce", "--to", "udp://localhost:9"]);
+        let error = task::block_on(run(options)).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,115 @@
+use chrono::{DateTime, FixedOffset};
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::stamp::TimestampFormat;
+
+/// A line written at the start or the end of each file of a channel, such as
+/// the header row of CSV files, e.g. `time,level,message` or `# {channel}
+/// opened {ts}`. `{channel}` is replaced by the channel's name and `{ts}` by
+/// the time the file was opened or closed, written as `--timestamp-format`
+/// says. A footer can sum up what the file holds: `{lines}` and `{bytes}`
+/// written to it, and `{first_ts}` and `{last_ts}`, when its first and last
+/// lines were, `-` if it has none. A line break is added if the template
+/// doesn't end in one.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BoundaryLine {
+    template: String,
+}
+
+impl BoundaryLine {
+    const PLACEHOLDERS: [&'static str; 6] =
+        ["channel", "ts", "lines", "bytes", "first_ts", "last_ts"];
+
+    /// Appends the line of `channel` at `time` to `out`, for a file `stats`
+    /// was written to.
+    pub fn render(
+        &self,
+        channel: &str,
+        format: &TimestampFormat,
+        time: &DateTime<FixedOffset>,
+        stats: &FileStats,
+        out: &mut String,
+    ) {
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            // Checked when parsed, every placeholder is closed and known.
+            let end = start + rest[start..].find('}').unwrap_or(0);
+            // Writing to a `String` can't fail.
+            let _ = match &rest[start + 1..end] {
+                "channel" => out.write_str(channel),
+                "lines" => write!(out, "{}", stats.lines),
+                "bytes" => write!(out, "{}", stats.bytes),
+                "first_ts" | "last_ts" => {
+                    let at = match &rest[start + 1..end] {
+                        "first_ts" => stats.first_at.as_ref(),
+                        _ => stats.last_at.as_ref(),
+                    };
+                    match at {
+                        Some(at) => format.write(at, out),
+                        None => out.write_str("-"),
+                    }
+                }
+                _ => format.write(time, out),
+            };
+            rest = &rest[end + 1..];
+        }
+        out.push_str(rest);
+        if !self.template.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+}
+
+impl FromStr for BoundaryLine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| format!("unclosed placeholder in `{}`", s))?;
+            let name = &rest[start + 1..end];
+            if !BoundaryLine::PLACEHOLDERS.contains(&name) {
+                return Err(format!("unknown placeholder {{{}}} in `{}`", name, s));
+            }
+            rest = &rest[end + 1..];
+        }
+
+        Ok(BoundaryLine {
+            template: s.to_string(),
+        })
+    }
+}
+
+/// The lines a channel's files start and end with, and how their times are
+/// written.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Boundaries {
+    pub header: Option<BoundaryLine>,
+    pub footer: Option<BoundaryLine>,
+    pub timestamp_format: TimestampFormat,
+}
+
+/// What was written to a file so far, for its footer to sum up.
+#[derive(Clone, Default, Debug)]
+pub struct FileStats {
+    pub lines: u64,
+    pub bytes: u64,
+    pub first_at: Option<DateTime<FixedOffset>>,
+    pub last_at: Option<DateTime<FixedOffset>>,
+}
+
+impl FileStats {
+    /// Counts a line of `bytes` written at `at`.
+    pub fn count(&mut self, bytes: usize, at: DateTime<FixedOffset>) {
+        self.lines += 1;
+        self.bytes += bytes as u64;
+        self.first_at.get_or_insert(at);
+        self.last_at = Some(at);
+    }
+}
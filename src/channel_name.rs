@@ -0,0 +1,139 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// What channel names come in by, that normalizations may be given for: the
+/// input formats, and the HTTP, gRPC and syslog listeners.
+const PROTOCOLS: &[&str] = &[
+    "lines",
+    "prefixed",
+    "producers",
+    "cri",
+    "json",
+    "length-prefixed",
+    "journald",
+    "routed",
+    "http",
+    "grpc",
+    "syslog",
+];
+
+/// A change made to a channel name sent by a producer before it is looked up.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Step {
+    /// `fold-case`: lower case, so `ERRORS` and `Errors` are `errors`.
+    FoldCase,
+    /// `trim`: without leading and trailing whitespace.
+    Trim,
+    /// `strip-prefix:<prefix>`: without `<prefix>`, e.g. `channel=`.
+    StripPrefix(String),
+}
+
+impl FromStr for Step {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fold-case" => Ok(Step::FoldCase),
+            "trim" => Ok(Step::Trim),
+            _ => match s.strip_prefix("strip-prefix:") {
+                Some(prefix) if !prefix.is_empty() => Ok(Step::StripPrefix(prefix.to_string())),
+                _ => Err(format!("unknown channel normalization: {}", s)),
+            },
+        }
+    }
+}
+
+/// Steps for the channel names of every protocol, or of one,
+/// `[<protocol>=]<step>,<step>...`, e.g. `trim,fold-case` or
+/// `json=strip-prefix:channel=`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Normalization {
+    protocol: Option<String>,
+    steps: Vec<Step>,
+}
+
+impl FromStr for Normalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // A prefix to strip may hold `=` itself, so only a known protocol is
+        // taken for one.
+        let (protocol, steps) = match s.split_once('=') {
+            Some((protocol, steps)) if PROTOCOLS.contains(&protocol) => {
+                (Some(protocol.to_string()), steps)
+            }
+            _ => (None, s),
+        };
+        let steps = steps
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<Step>, String>>()?;
+        if steps.is_empty() {
+            return Err(format!("no channel normalization in `{}`", s));
+        }
+
+        Ok(Normalization { protocol, steps })
+    }
+}
+
+/// How the channel names producers send are normalized, by protocol.
+#[derive(Default)]
+pub struct ChannelNames {
+    /// Steps for every protocol, taken first.
+    steps: Vec<Step>,
+    /// Steps for one protocol, taken after those for every one.
+    protocol_steps: BTreeMap<String, Vec<Step>>,
+}
+
+impl ChannelNames {
+    pub fn new(normalizations: &[Normalization]) -> Self {
+        let mut names = ChannelNames::default();
+        for normalization in normalizations.iter() {
+            let steps = match normalization.protocol {
+                Some(ref protocol) => names.protocol_steps.entry(protocol.clone()).or_default(),
+                None => &mut names.steps,
+            };
+            steps.extend(normalization.steps.iter().cloned());
+        }
+
+        names
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty() && self.protocol_steps.is_empty()
+    }
+
+    /// `channel` as sent by `protocol`, normalized; borrowed when nothing
+    /// changed it.
+    pub fn normalize<'a>(&self, protocol: &str, channel: &'a str) -> Cow<'a, str> {
+        let steps = self
+            .steps
+            .iter()
+            .chain(self.protocol_steps.get(protocol).into_iter().flatten());
+
+        let mut channel = Cow::Borrowed(channel);
+        for step in steps {
+            channel = match (step, channel) {
+                (Step::FoldCase, channel) if channel.chars().any(char::is_uppercase) => {
+                    Cow::Owned(channel.to_lowercase())
+                }
+                (Step::Trim, Cow::Borrowed(channel)) => Cow::Borrowed(channel.trim()),
+                (Step::Trim, Cow::Owned(channel)) => Cow::Owned(channel.trim().to_string()),
+                (Step::StripPrefix(prefix), Cow::Borrowed(channel)) => {
+                    Cow::Borrowed(channel.strip_prefix(prefix.as_str()).unwrap_or(channel))
+                }
+                (Step::StripPrefix(prefix), Cow::Owned(channel)) => {
+                    match channel.strip_prefix(prefix.as_str()) {
+                        Some(stripped) => Cow::Owned(stripped.to_string()),
+                        None => Cow::Owned(channel),
+                    }
+                }
+                (Step::FoldCase, channel) => channel,
+            };
+        }
+
+        channel
+    }
+}
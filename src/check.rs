@@ -0,0 +1,97 @@
+use async_std::io;
+
+use crate::quota::Quota;
+use crate::rate_limit::RateLimit;
+use crate::{
+    accepted_channels, check_accepted_channels, log_dir, parse_values, retention, tenants,
+    ChannelSettings, CliOptions,
+};
+
+/// Validates what the router would start with, without opening a file or
+/// reading an input: the config file, the directories, and the options
+/// parsed as startup parses them. Each channel's effective settings are
+/// printed, so a deployment's config can be checked in CI.
+pub async fn run(options: &CliOptions) -> Result<(), io::Error> {
+    log_dir::check_only();
+    log_dir::ensure(&options.log_dir)?;
+    if let Some(ref inapt_dir) = options.inapt_dir {
+        log_dir::ensure(inapt_dir)?;
+    }
+    #[cfg(feature = "state")]
+    if let Some(ref state_dir) = options.state_dir {
+        log_dir::ensure(state_dir)?;
+    }
+
+    let settings = ChannelSettings::with_options(options, &options.configured_channels).await?;
+    let accepted = accepted_channels(options);
+    check_accepted_channels(&accepted)?;
+    settings.check_aliases(&accepted)?;
+    tenants(options)?;
+    retention(options)?;
+    parse_values::<Quota>(&options.quotas)?;
+    parse_values::<RateLimit>(&options.rate_limit)?;
+
+    for channel in accepted.iter() {
+        println!("{}", describe(&settings, channel));
+    }
+    println!("configuration is valid");
+
+    Ok(())
+}
+
+/// A channel's settings on a line, e.g. `app: dir=/var/log
+/// rotation=Hourly(Seconds) compression=Zstd(3) sink=File …`.
+fn describe(settings: &ChannelSettings, channel: &str) -> String {
+    let settings_name = settings.settings_name(channel);
+    let dir = settings
+        .channel_dirs
+        .get(channel)
+        .unwrap_or(&settings.log_dir);
+    let file_name = settings
+        .channel_file_names
+        .get(channel)
+        .map_or(channel, String::as_str);
+    let max_file_size = settings
+        .channel_max_file_sizes
+        .get(channel)
+        .or(settings.max_file_size.as_ref());
+    let profile = settings
+        .buffering_profiles
+        .get(channel)
+        .or_else(|| settings.buffering_profiles.get(settings_name));
+    let sync_policy = settings
+        .channel_sync_policies
+        .get(channel)
+        .or_else(|| settings.channel_sync_policies.get(settings_name))
+        .unwrap_or(&settings.sync_policy);
+
+    let mut line = format!(
+        "{}: dir={} file_name={} rotation={:?} zone={:?} compression={:?} sink={:?} \
+         sync_policy={:?}",
+        channel,
+        dir,
+        file_name,
+        settings.rotation_of(channel),
+        settings.zone_of(channel),
+        settings.compression_of(channel),
+        settings.sink(channel),
+        sync_policy
+    );
+    if let Some(max_file_size) = max_file_size {
+        line.push_str(&format!(" max_file_size={}", max_file_size));
+    }
+    if settings.encrypts(channel) {
+        line.push_str(" encrypted=true");
+    }
+    if let Some(profile) = profile {
+        line.push_str(&format!(" buffering_profile={:?}", profile));
+    }
+    if let Some(disk_quota) = settings.channel_disk_quotas.get(channel) {
+        line.push_str(&format!(
+            " disk_quota={} disk_quota_action={:?}",
+            disk_quota.limit, disk_quota.action
+        ));
+    }
+
+    line
+}
@@ -0,0 +1,80 @@
+use chrono::{DateTime, FixedOffset, Local, Offset};
+
+use std::path::PathBuf;
+#[cfg(feature = "split")]
+use std::sync::Mutex;
+use std::sync::OnceLock;
+
+use log_revolve_rs::router;
+
+/// File the time is read from instead of the system clock, set once at
+/// startup by `--simulated-clock`.
+static SIMULATED_CLOCK: OnceLock<PathBuf> = OnceLock::new();
+
+/// Time of the lines being read, set as they are by `split`, which takes the
+/// time from the lines themselves rather than from a clock.
+#[cfg(feature = "split")]
+static LINE_TIME: OnceLock<Mutex<Option<DateTime<Local>>>> = OnceLock::new();
+
+/// Makes the router read the time from `path` from now on, an RFC 3339
+/// timestamp rewritten by whoever drives it, so tests can move it past
+/// rotations without waiting for them.
+pub fn simulate(path: PathBuf) {
+    let _ = SIMULATED_CLOCK.set(path);
+}
+
+/// Makes the router take the time from `set` from now on, the clock only
+/// read until it is first called.
+#[cfg(feature = "split")]
+pub fn follow_lines() {
+    let _ = LINE_TIME.set(Mutex::new(None));
+}
+
+/// Sets the time of the lines read from here on, if the router follows them.
+#[cfg(feature = "split")]
+pub fn set(time: DateTime<Local>) {
+    if let Some(mut line_time) = LINE_TIME.get().and_then(|time| time.lock().ok()) {
+        *line_time = Some(time);
+    }
+}
+
+/// The current time, that of the lines if the router follows them, simulated
+/// or not otherwise. A simulated clock that can't be read falls back to the
+/// system clock.
+pub fn now() -> DateTime<Local> {
+    #[cfg(feature = "split")]
+    if let Some(time) = LINE_TIME
+        .get()
+        .and_then(|time| time.lock().ok())
+        .and_then(|time| *time)
+    {
+        return time;
+    }
+
+    let path = match SIMULATED_CLOCK.get() {
+        Some(path) => path,
+        None => return Local::now(),
+    };
+
+    let simulated = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|time| DateTime::parse_from_rfc3339(time.trim()).ok());
+    match simulated {
+        Some(time) => time.with_timezone(&Local),
+        None => {
+            tracing::warn!("unable to read the simulated clock {}", path.display());
+            Local::now()
+        }
+    }
+}
+
+/// `now` as the library's `Router` reads it, so the channels it writes to
+/// are on the router's clock, simulated or following the lines alike.
+pub struct Clock;
+
+impl router::Clock for Clock {
+    fn now(&self) -> DateTime<FixedOffset> {
+        let now = now();
+        now.with_timezone(&now.offset().fix())
+    }
+}
@@ -0,0 +1,193 @@
+use async_std::task;
+
+use chrono::{Duration, NaiveDateTime};
+
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use structopt::StructOpt;
+
+use log_revolve_rs::reader::{ChannelFile, ChannelReader, Codec};
+use log_revolve_rs::rotation::FileTimestamp;
+
+use crate::compress::{self, Compression};
+use crate::{log_dir, parse_rotation_interval, retention};
+
+/// Rewrites the files channels rotated away from, e.g. to recompress a log
+/// directory kept plain while it was busy: `log-revolve-rs compact --log-dir
+/// /var/log/app --compress zstd:19 --merge 1d`. Files are decompressed and
+/// written again with `--compress`, and with `--merge` those of each period
+/// are joined into one named after the period's start. Every channel's
+/// newest file, and its period, are left alone, as the router may still be
+/// writing to them, and so are encrypted files.
+#[derive(StructOpt)]
+#[structopt(name = "compact")]
+pub struct CompactOptions {
+    /// Directory holding the files, as given to the router's `--log-dir`
+    #[structopt(long)]
+    log_dir: String,
+
+    /// Timestamp in the file names, as given to the router's
+    /// `--file-timestamp`
+    #[structopt(long, default_value = "seconds")]
+    file_timestamp: FileTimestamp,
+
+    /// `<algorithm>[:<level>]` the files are written with, as given to the
+    /// router's `--compress`; `none` decompresses them. Files already in the
+    /// algorithm are left as they are unless they are merged
+    #[structopt(long, default_value = "none")]
+    compress: Compression,
+
+    /// Merges each channel's files of a period, e.g. `1d`, into one; the
+    /// period must divide a day evenly
+    #[structopt(long, parse(try_from_str = parse_rotation_interval))]
+    merge: Option<Duration>,
+}
+
+pub async fn run(options: CompactOptions) -> Result<(), io::Error> {
+    let hours_only = matches!(
+        options.file_timestamp,
+        FileTimestamp::Hours | FileTimestamp::Rfc3339Hours
+    );
+    if hours_only
+        && options
+            .merge
+            .is_some_and(|merge| merge < Duration::hours(1))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "periods under an hour take a seconds file timestamp",
+        ));
+    }
+
+    let reader = ChannelReader::new(options.log_dir.as_str(), options.file_timestamp);
+    for channel in channels(&options)? {
+        let mut files = reader.files(&channel).await?;
+        let newest = match files.pop() {
+            Some(newest) => newest,
+            None => continue,
+        };
+        files.retain(|file| !file.encrypted);
+
+        let groups = match options.merge {
+            Some(period) => {
+                let mut groups: Vec<Vec<ChannelFile>> = Vec::new();
+                for file in files {
+                    match groups.last_mut() {
+                        Some(group)
+                            if period_start(period, &group[0]) == period_start(period, &file) =>
+                        {
+                            group.push(file)
+                        }
+                        _ => groups.push(vec![file]),
+                    }
+                }
+                groups.retain(|group| {
+                    period_start(period, &group[0]) != period_start(period, &newest)
+                });
+                groups
+            }
+            None => files.into_iter().map(|file| vec![file]).collect(),
+        };
+
+        for group in groups {
+            let target = target_path(&options, &channel, &group);
+            if group.len() == 1 && group[0].path == target {
+                continue;
+            }
+
+            let sources = group.clone();
+            let compression = options.compress;
+            let written = target.to_string_lossy().into_owned();
+            task::spawn_blocking(move || {
+                let sources = sources
+                    .iter()
+                    .map(open)
+                    .collect::<Result<Vec<_>, io::Error>>()?;
+                compress::write_archive(sources, &written, compression)
+            })
+            .await?;
+            for file in group.iter().filter(|file| file.path != target) {
+                retention::remove_file(file.path.as_path().into()).await?;
+            }
+            println!("{} from {} files", target.display(), group.len());
+        }
+    }
+
+    log_dir::sync(Path::new(&options.log_dir))
+}
+
+/// Channels with files stamped in `--file-timestamp` format in the log
+/// directory.
+fn channels(options: &CompactOptions) -> Result<BTreeSet<String>, io::Error> {
+    let default_names = BTreeSet::new();
+    let mut channels = BTreeSet::new();
+    for entry in std::fs::read_dir(&options.log_dir)? {
+        let name = entry?.file_name();
+        let stamped = name
+            .to_str()
+            .and_then(|name| retention::stamped(&options.file_timestamp, &default_names, name));
+        if let Some((channel, _, _)) = stamped {
+            channels.insert(channel.to_string());
+        }
+    }
+
+    Ok(channels)
+}
+
+/// The start of the period `file` was opened in.
+fn period_start(period: Duration, file: &ChannelFile) -> NaiveDateTime {
+    let midnight = file.opened_at.date().and_hms_opt(0, 0, 0).unwrap();
+    let elapsed = (file.opened_at - midnight).num_seconds();
+
+    midnight + Duration::seconds(elapsed - elapsed % period.num_seconds())
+}
+
+/// Path `group` is written to: named after its period's start when merged,
+/// or its one file's name with the extension of `--compress`.
+fn target_path(options: &CompactOptions, channel: &str, group: &[ChannelFile]) -> PathBuf {
+    let log_path = match options.merge {
+        Some(period) => Path::new(&options.log_dir).join(format!(
+            "{}_{}.log",
+            channel,
+            period_start(period, &group[0]).format(options.file_timestamp.format())
+        )),
+        None if group[0].codec == Codec::Plain => group[0].path.clone(),
+        None => group[0].path.with_extension(""),
+    };
+
+    match options.compress.extension() {
+        Some(extension) => {
+            let mut path = log_path.into_os_string();
+            path.push(".");
+            path.push(extension);
+            path.into()
+        }
+        None => log_path,
+    }
+}
+
+/// The lines of `file`, decompressed.
+fn open(file: &ChannelFile) -> Result<Box<dyn BufRead + Send>, io::Error> {
+    let input = File::open(&file.path)?;
+    match file.codec {
+        #[cfg(feature = "gzip")]
+        Codec::Gzip => Ok(Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(
+            input,
+        )))),
+        #[cfg(feature = "zstd")]
+        Codec::Zstd => Ok(Box::new(BufReader::new(zstd::Decoder::new(input)?))),
+        Codec::Plain => Ok(Box::new(BufReader::new(input))),
+        #[cfg(not(all(feature = "gzip", feature = "zstd")))]
+        codec => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "{:?} support isn't built in for {}",
+                codec,
+                file.path.display()
+            ),
+        )),
+    }
+}
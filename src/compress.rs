@@ -0,0 +1,600 @@
+use async_std::task;
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::post_rotation::Running;
+use crate::{log_dir, report};
+
+/// Uncompressed bytes per gzip member or zstd frame. Each is a complete
+/// stream of its own, ending on a line boundary, so an archive cut short in
+/// transfer still decodes up to its last whole member.
+const MEMBER_BYTES: usize = 1024 * 1024;
+
+/// Whether files are compressed as lines are written to them rather than
+/// once their channel has rotated away from them, set once at startup.
+static STREAMING: AtomicBool = AtomicBool::new(false);
+
+/// Whether archives are staged in anonymous `O_TMPFILE` files rather than
+/// `.partial` ones, set once at startup.
+#[cfg(all(target_os = "linux", feature = "tmpfile"))]
+static TMPFILE_STAGING: AtomicBool = AtomicBool::new(false);
+
+/// Stages archives in anonymous files linked into place once complete, so a
+/// crash while compressing leaves nothing behind in the log directory, not
+/// even a `.partial` file.
+#[cfg(all(target_os = "linux", feature = "tmpfile"))]
+pub fn stage_with_tmpfile() {
+    TMPFILE_STAGING.store(true, Ordering::Relaxed);
+}
+
+/// Compresses the files of channels that compress theirs as lines are
+/// written to them, named `<file>.gz` or `<file>.zst` from the start.
+pub fn stream_active_files() {
+    STREAMING.store(true, Ordering::Relaxed);
+}
+
+/// How a file is compressed once its channel has rotated away from it,
+/// written as `<algorithm>[:<level>]`, e.g. `zstd:3` or `gzip:9`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Compression {
+    None,
+    #[cfg(feature = "gzip")]
+    Gzip(u32),
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (algorithm, level) = match s.split_once(':') {
+            Some((algorithm, level)) => (algorithm, Some(level)),
+            None => (s, None),
+        };
+
+        match algorithm {
+            "none" if level.is_none() => Ok(Compression::None),
+            #[cfg(feature = "gzip")]
+            "gzip" => Ok(Compression::Gzip(parse_level(level, 6, 0, 9)?)),
+            #[cfg(feature = "zstd")]
+            "zstd" => Ok(Compression::Zstd(parse_level(level, 3, 1, 22)?)),
+            #[cfg(not(feature = "gzip"))]
+            "gzip" => Err(not_built_in(algorithm)),
+            #[cfg(not(feature = "zstd"))]
+            "zstd" => Err(not_built_in(algorithm)),
+            _ => Err(format!("unknown compression: {}", s)),
+        }
+    }
+}
+
+impl Compression {
+    /// Extension of the files compressed this way, `None` for plain ones.
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            #[cfg(feature = "gzip")]
+            Compression::Gzip(_) => Some("gz"),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(_) => Some("zst"),
+        }
+    }
+}
+
+#[cfg(not(all(feature = "gzip", feature = "zstd")))]
+pub fn not_built_in(algorithm: &str) -> String {
+    format!(
+        "{} support isn't built in, rebuild with `--features {}`",
+        algorithm, algorithm
+    )
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+fn parse_level<L>(level: Option<&str>, default: L, min: L, max: L) -> Result<L, String>
+where
+    L: FromStr + PartialOrd + std::fmt::Display,
+{
+    let level = match level {
+        Some(level) => level
+            .parse()
+            .map_err(|_| format!("invalid compression level: {}", level))?,
+        None => return Ok(default),
+    };
+    if level < min || level > max {
+        return Err(format!(
+            "compression level {} is outside {}..={}",
+            level, min, max
+        ));
+    }
+
+    Ok(level)
+}
+
+/// Compresses a channel's current file as its lines are written out, into
+/// the same series of gzip members or zstd frames as an archive. Each write
+/// is flushed through the encoder, so everything written out can be decoded
+/// while the file grows; the member left open is ended by `finish`, as the
+/// file is rotated away from or the router exits.
+pub struct Encoder {
+    compression: Compression,
+    member: Option<Member>,
+    /// Uncompressed bytes of the open member.
+    member_bytes: usize,
+}
+
+enum Member {
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    /// The encoder of a file compressed with `compression`, `None` unless
+    /// files are compressed as they are written.
+    pub fn streaming(compression: Compression) -> Option<Encoder> {
+        if !STREAMING.load(Ordering::Relaxed) || compression == Compression::None {
+            return None;
+        }
+
+        Some(Encoder {
+            compression,
+            member: None,
+            member_bytes: 0,
+        })
+    }
+
+    /// What the file's name ends with, e.g. `.zst`.
+    pub fn extension(&self) -> &'static str {
+        match self.compression {
+            Compression::None => "",
+            #[cfg(feature = "gzip")]
+            Compression::Gzip(_) => ".gz",
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(_) => ".zst",
+        }
+    }
+
+    /// Compresses `lines`, returning what to append to the file. A member is
+    /// ended once it holds `MEMBER_BYTES`, on the line boundary `lines` ends
+    /// at.
+    pub fn encode(&mut self, lines: &[u8]) -> Result<Vec<u8>, io::Error> {
+        let mut member = match self.member.take() {
+            Some(member) => member,
+            None => Member::new(self.compression)?,
+        };
+        member.writer().write_all(lines)?;
+        self.member_bytes += lines.len();
+        if self.member_bytes >= MEMBER_BYTES {
+            self.member_bytes = 0;
+            return member.finish();
+        }
+
+        member.writer().flush()?;
+        let encoded = member.take_output();
+        self.member = Some(member);
+        Ok(encoded)
+    }
+
+    /// Ends the open member, returning what is left of it to append.
+    pub fn finish(&mut self) -> Result<Vec<u8>, io::Error> {
+        self.member_bytes = 0;
+        match self.member.take() {
+            Some(member) => member.finish(),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+impl Member {
+    fn new(compression: Compression) -> Result<Member, io::Error> {
+        match compression {
+            Compression::None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "files without compression have no members",
+            )),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip(level) => Ok(Member::Gzip(flate2::write::GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::new(level),
+            ))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(level) => Ok(Member::Zstd(zstd::Encoder::new(Vec::new(), level)?)),
+        }
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match *self {
+            #[cfg(feature = "gzip")]
+            Member::Gzip(ref mut encoder) => encoder,
+            #[cfg(feature = "zstd")]
+            Member::Zstd(ref mut encoder) => encoder,
+        }
+    }
+
+    /// The bytes the encoder has come to so far, taken out of it.
+    fn take_output(&mut self) -> Vec<u8> {
+        match *self {
+            #[cfg(feature = "gzip")]
+            Member::Gzip(ref mut encoder) => std::mem::take(encoder.get_mut()),
+            #[cfg(feature = "zstd")]
+            Member::Zstd(ref mut encoder) => std::mem::take(encoder.get_mut()),
+        }
+    }
+
+    fn finish(self) -> Result<Vec<u8>, io::Error> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Member::Gzip(encoder) => encoder.finish(),
+            #[cfg(feature = "zstd")]
+            Member::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// Compresses the file at `path` on a blocking thread, replacing it with
+/// `<path>.gz` or `<path>.zst` once the compressed copy is complete, and
+/// syncing the directory after the swap when `sync_dir` is set. `finished`
+/// is handed the path of the file once final, compressed or not.
+pub fn spawn<F>(path: String, compression: Compression, sync_dir: bool, finished: F)
+where
+    F: FnOnce(String) + Send + 'static,
+{
+    if compression == Compression::None {
+        finished(path);
+        return;
+    }
+
+    let running = Running::start();
+    task::spawn(async move {
+        // Counted until the file is handed on, to its hook among others.
+        let _running = running;
+        let source = path.clone();
+        let compressed = task::spawn_blocking(move || {
+            let compressed = compress_file(&source, compression)?;
+            if sync_dir {
+                if let Some(dir) = Path::new(&compressed).parent() {
+                    log_dir::sync(dir)?;
+                }
+            }
+            Ok::<_, io::Error>(compressed)
+        });
+        match compressed.await {
+            Ok(compressed) => {
+                tracing::debug!("compressed {} into {}", path, compressed);
+                finished(compressed);
+            }
+            Err(error) => {
+                tracing::warn!("unable to compress {}: {}", path, error);
+                report::record_error("compress", error);
+                finished(path);
+            }
+        }
+    });
+}
+
+fn compress_file(path: &str, compression: Compression) -> Result<String, io::Error> {
+    let extension = match compression.extension() {
+        Some(extension) => extension,
+        None => return Ok(path.to_string()),
+    };
+    let target = format!("{}.{}", path, extension);
+
+    write_archive(
+        vec![BufReader::new(File::open(path)?)],
+        &target,
+        compression,
+    )?;
+    fs::remove_file(path)?;
+
+    Ok(target)
+}
+
+/// Writes the lines of `sources`, one after the other, to an archive at
+/// `target`, in members ending on line boundaries. A source that doesn't end
+/// on one is ended with a newline before the next is read, so its last line
+/// doesn't run into the next one's first.
+pub fn write_archive<R: BufRead>(
+    sources: Vec<R>,
+    target: &str,
+    compression: Compression,
+) -> Result<(), io::Error> {
+    let (mut output, staging) = Staging::create(target)?;
+    let mut member = Vec::with_capacity(MEMBER_BYTES);
+    let mut sources = sources.into_iter().peekable();
+    while let Some(mut input) = sources.next() {
+        while input.read_until(b'\n', &mut member)? != 0 {
+            if member.len() >= MEMBER_BYTES {
+                output = encode_member(output, &member, compression)?;
+                member.clear();
+            }
+        }
+        if sources.peek().is_some() && !member.is_empty() && !member.ends_with(b"\n") {
+            member.push(b'\n');
+        }
+    }
+    if !member.is_empty() {
+        output = encode_member(output, &member, compression)?;
+    }
+
+    staging.complete(finish(output)?, target)
+}
+
+/// Appends `member` to `output` as a complete gzip member or zstd frame.
+fn encode_member(
+    mut output: File,
+    member: &[u8],
+    compression: Compression,
+) -> Result<File, io::Error> {
+    match compression {
+        Compression::None => {
+            output.write_all(member)?;
+            Ok(output)
+        }
+        #[cfg(feature = "gzip")]
+        Compression::Gzip(level) => {
+            let level = flate2::Compression::new(level);
+            let mut encoder = flate2::write::GzEncoder::new(output, level);
+            encoder.write_all(member)?;
+            encoder.finish()
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd(level) => {
+            let mut encoder = zstd::Encoder::new(output, level)?;
+            encoder.write_all(member)?;
+            encoder.finish()
+        }
+    }
+}
+
+fn finish(mut output: File) -> Result<File, io::Error> {
+    output.flush()?;
+    output.sync_all()?;
+    Ok(output)
+}
+
+/// Where an archive is written until it is complete, so a reader never sees
+/// half of one under its final name.
+enum Staging {
+    /// A `<target>.partial` file, renamed into place.
+    Partial(String),
+    /// An anonymous file in the target's directory, linked into place.
+    #[cfg(all(target_os = "linux", feature = "tmpfile"))]
+    Tmpfile,
+}
+
+impl Staging {
+    fn create(target: &str) -> Result<(File, Staging), io::Error> {
+        #[cfg(all(target_os = "linux", feature = "tmpfile"))]
+        if TMPFILE_STAGING.load(Ordering::Relaxed) {
+            use std::os::unix::fs::OpenOptionsExt;
+
+            let dir = Path::new(target)
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new("."));
+            match fs::OpenOptions::new()
+                .write(true)
+                .mode(0o644)
+                .custom_flags(libc::O_TMPFILE)
+                .open(dir)
+            {
+                Ok(file) => return Ok((file, Staging::Tmpfile)),
+                // Not every filesystem supports them.
+                Err(error) => tracing::debug!("unable to stage {} in a tmpfile: {}", target, error),
+            }
+        }
+
+        let partial = format!("{}.partial", target);
+        Ok((File::create(&partial)?, Staging::Partial(partial)))
+    }
+
+    fn complete(self, output: File, target: &str) -> Result<(), io::Error> {
+        match self {
+            Staging::Partial(partial) => {
+                drop(output);
+                fs::rename(&partial, target)
+            }
+            #[cfg(all(target_os = "linux", feature = "tmpfile"))]
+            Staging::Tmpfile => link_tmpfile(&output, target),
+        }
+    }
+}
+
+/// Gives an `O_TMPFILE` file the name `target`, through its `/proc` link as
+/// linking by descriptor alone takes privileges.
+#[cfg(all(target_os = "linux", feature = "tmpfile"))]
+fn link_tmpfile(file: &File, target: &str) -> Result<(), io::Error> {
+    use std::ffi::CString;
+    use std::os::unix::io::AsRawFd;
+
+    let source = CString::new(format!("/proc/self/fd/{}", file.as_raw_fd()))?;
+    let target_c = CString::new(target)?;
+    let link = || unsafe {
+        libc::linkat(
+            libc::AT_FDCWD,
+            source.as_ptr(),
+            libc::AT_FDCWD,
+            target_c.as_ptr(),
+            libc::AT_SYMLINK_FOLLOW,
+        )
+    };
+
+    if link() == 0 {
+        return Ok(());
+    }
+    let error = io::Error::last_os_error();
+    if error.kind() != io::ErrorKind::AlreadyExists {
+        return Err(error);
+    }
+    // Left over from an earlier run; a rename would have replaced it too.
+    fs::remove_file(target)?;
+    if link() == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::LogDir;
+
+    #[test]
+    fn no_compression_takes_no_level() {
+        assert_eq!("none".parse(), Ok(Compression::None));
+        assert_eq!(Compression::None.extension(), None);
+        assert!("none:3".parse::<Compression>().is_err());
+        assert_eq!(
+            "brotli:5".parse::<Compression>(),
+            Err(String::from("unknown compression: brotli:5"))
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_levels_go_from_0_to_9() {
+        assert_eq!("gzip".parse(), Ok(Compression::Gzip(6)));
+        assert_eq!("gzip:0".parse(), Ok(Compression::Gzip(0)));
+        assert_eq!("gzip:9".parse(), Ok(Compression::Gzip(9)));
+        assert_eq!(Compression::Gzip(9).extension(), Some("gz"));
+        assert_eq!(
+            "gzip:10".parse::<Compression>(),
+            Err(String::from("compression level 10 is outside 0..=9"))
+        );
+        assert_eq!(
+            "gzip:best".parse::<Compression>(),
+            Err(String::from("invalid compression level: best"))
+        );
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_levels_go_from_1_to_22() {
+        assert_eq!("zstd".parse(), Ok(Compression::Zstd(3)));
+        assert_eq!("zstd:22".parse(), Ok(Compression::Zstd(22)));
+        assert_eq!(Compression::Zstd(3).extension(), Some("zst"));
+        assert_eq!(
+            "zstd:0".parse::<Compression>(),
+            Err(String::from("compression level 0 is outside 1..=22"))
+        );
+        assert_eq!(
+            "zstd:-1".parse::<Compression>(),
+            Err(String::from("compression level -1 is outside 1..=22"))
+        );
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn algorithms_left_out_of_the_build_say_so() {
+        assert_eq!(
+            "zstd:3".parse::<Compression>(),
+            Err(String::from(
+                "zstd support isn't built in, rebuild with `--features zstd`"
+            ))
+        );
+    }
+
+    #[test]
+    fn archives_run_sources_together_on_line_boundaries() {
+        let dir = LogDir::new("compress-archive");
+        let target = dir.path().join("web.log").to_str().unwrap().to_string();
+
+        let sources = vec![
+            &b"first\nunfinished"[..],
+            &b"second\n"[..],
+            &b""[..],
+            &b"last"[..],
+        ];
+        write_archive(sources, &target, Compression::None).unwrap();
+
+        assert_eq!(dir.file_names(), vec!["web.log"]);
+        assert_eq!(dir.read("web.log"), "first\nunfinished\nsecond\nlast");
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_archives_are_members_each_ending_on_a_line() {
+        use flate2::read::{GzDecoder, MultiGzDecoder};
+        use std::io::Read;
+
+        let dir = LogDir::new("compress-gzip");
+        let target = dir.path().join("web.log.gz").to_str().unwrap().to_string();
+        let line = "x".repeat(99) + "\n";
+        let lines = line.repeat(MEMBER_BYTES / line.len() + 10);
+
+        write_archive(vec![lines.as_bytes()], &target, Compression::Gzip(1)).unwrap();
+        assert_eq!(dir.file_names(), vec!["web.log.gz"]);
+
+        let mut whole = String::new();
+        MultiGzDecoder::new(File::open(&target).unwrap())
+            .read_to_string(&mut whole)
+            .unwrap();
+        assert_eq!(whole, lines);
+
+        let mut first = String::new();
+        GzDecoder::new(File::open(&target).unwrap())
+            .read_to_string(&mut first)
+            .unwrap();
+        assert!(first.len() >= MEMBER_BYTES && first.len() < lines.len());
+        assert!(first.ends_with('\n'));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_archives_decode_frame_after_frame() {
+        let dir = LogDir::new("compress-zstd");
+        let target = dir.path().join("web.log.zst").to_str().unwrap().to_string();
+        let line = "y".repeat(49) + "\n";
+        let lines = line.repeat(MEMBER_BYTES / line.len() * 2 + 1);
+
+        write_archive(
+            vec![lines.as_bytes(), &b"tail"[..]],
+            &target,
+            Compression::Zstd(1),
+        )
+        .unwrap();
+
+        let decoded = zstd::decode_all(File::open(&target).unwrap()).unwrap();
+        assert_eq!(decoded, (lines + "tail").into_bytes());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn compressed_files_replace_their_source() {
+        let dir = LogDir::new("compress-file");
+        let source = dir.path().join("web.log").to_str().unwrap().to_string();
+        fs::write(&source, "GET /a\n").unwrap();
+
+        let target = compress_file(&source, Compression::Gzip(6)).unwrap();
+        assert_eq!(target, format!("{}.gz", source));
+        assert_eq!(dir.file_names(), vec!["web.log.gz"]);
+
+        assert_eq!(compress_file(&target, Compression::None).unwrap(), target);
+    }
+
+    #[cfg(all(target_os = "linux", feature = "tmpfile"))]
+    #[test]
+    fn tmpfiles_stay_nameless_until_complete() {
+        let dir = LogDir::new("compress-tmpfile");
+        let target = dir.path().join("web.log").to_str().unwrap().to_string();
+        std::fs::write(&target, "left over\n").unwrap();
+        stage_with_tmpfile();
+
+        let (mut output, staging) = Staging::create(&target).unwrap();
+        assert!(matches!(staging, Staging::Tmpfile));
+        output.write_all(b"archived\n").unwrap();
+        assert_eq!(dir.file_names(), vec!["web.log"]);
+        assert_eq!(dir.read("web.log"), "left over\n");
+
+        staging.complete(finish(output).unwrap(), &target).unwrap();
+        assert_eq!(dir.file_names(), vec!["web.log"]);
+        assert_eq!(dir.read("web.log"), "archived\n");
+    }
+}
@@ -0,0 +1,239 @@
+#[cfg(feature = "config")]
+use async_std::fs;
+#[cfg(feature = "config")]
+use async_std::io;
+
+#[cfg(feature = "config")]
+use serde::Deserialize;
+
+#[cfg(feature = "config")]
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// Channels declared in a TOML file given with `--config`, each with settings
+/// of its own; command line flags apply to whatever a channel leaves out.
+///
+/// ```toml
+/// [channels.app]
+/// rotation_interval = "15m"
+/// max_file_size = "100MB"
+/// compress = "zstd:3"
+///
+/// [channels.audit]
+/// directory = "/var/log/audit"
+/// file_name = "audit-{hostname}"
+///
+/// [channels.web]
+/// aliases = ["web-1", "web-2", "web-3"]
+///
+/// [channels.metrics]
+/// header = "time,name,value"
+///
+/// [tenants.acme]
+/// channels = ["app", "audit"]
+/// quota = "20GB/day"
+/// ```
+#[cfg(feature = "config")]
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub channels: BTreeMap<String, ChannelConfig>,
+    /// Tenants sharing the router on top of those of `--tenants`.
+    #[serde(default)]
+    pub tenants: BTreeMap<String, TenantConfig>,
+}
+
+#[derive(Default, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "config", derive(Deserialize), serde(deny_unknown_fields))]
+pub struct ChannelConfig {
+    /// As `--rotation-interval`, e.g. `1d`.
+    pub rotation_interval: Option<String>,
+    /// As `--max-file-size`, e.g. `100MB`.
+    pub max_file_size: Option<String>,
+    /// Directory the channel's files go to instead of the log directory.
+    pub directory: Option<String>,
+    /// Name the channel's files take instead of the channel's, the timestamp
+    /// still appended; placeholders are replaced as in `--log-dir`.
+    pub file_name: Option<String>,
+    /// As in `--buffering-profiles`, e.g. `throughput`.
+    pub buffering_profile: Option<String>,
+    /// As `--sync-policy`, e.g. `every-line`.
+    pub sync_policy: Option<String>,
+    /// As in `--sample-rates`, e.g. `1/100` or `0.05`.
+    pub sample_rate: Option<String>,
+    /// `high` to write the channel as one of `--priority-channels`, or
+    /// `normal`, the default.
+    pub priority: Option<String>,
+    /// As `--compress`, e.g. `zstd:9` for a high-volume channel.
+    pub compress: Option<String>,
+    /// Whether the channel's files are encrypted, as in `--encrypt-channels`.
+    #[cfg(feature = "encryption")]
+    pub encrypt: Option<bool>,
+    /// Where the channel's lines go, `file` when left out.
+    pub sink: Option<String>,
+    /// Most the channel's files may take in its directory together, e.g.
+    /// `2GB`, whatever other channels write.
+    pub disk_quota: Option<String>,
+    /// What happens past `disk_quota`: `delete-oldest`, the default, removes
+    /// the channel's oldest rotated files, `stop` drops its lines.
+    pub disk_quota_action: Option<String>,
+    /// As `--forward`, e.g. `syslog+tcp://collector:514`.
+    #[cfg(feature = "forward")]
+    pub forward: Option<String>,
+    /// Other names producers send the channel's lines under, e.g. `["web-1",
+    /// "web-2"]` for replicas sharing its files.
+    pub aliases: Option<Vec<String>>,
+    /// Line each new file of the channel starts with, e.g. the header row of
+    /// CSV files; `{channel}` and `{ts}`, the time it was opened, are
+    /// replaced.
+    pub header: Option<String>,
+    /// Line each file of the channel ends with once rotated away from or
+    /// closed at shutdown, `{ts}` being the time it was closed; `{lines}`,
+    /// `{bytes}`, `{first_ts}` and `{last_ts}` sum up what was written to it.
+    pub footer: Option<String>,
+    /// As `--redact`, applied after the redactions for every channel, e.g.
+    /// `["s/\\d{16}/****/"]`.
+    #[cfg(feature = "redact")]
+    pub redact: Option<Vec<String>>,
+}
+
+#[derive(Default, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "config", derive(Deserialize), serde(deny_unknown_fields))]
+pub struct TenantConfig {
+    /// Channels the tenant's lines may go to, every accepted channel when
+    /// left out.
+    pub channels: Option<Vec<String>>,
+    /// As in `--tenant-quotas`, e.g. `20GB/day`.
+    pub quota: Option<String>,
+    /// As in `--tenant-retention`, e.g. `7d`.
+    pub retention: Option<String>,
+}
+
+#[cfg(feature = "config")]
+impl Config {
+    pub async fn load(path: &str) -> Result<Self, io::Error> {
+        let contents = fs::read_to_string(path).await?;
+
+        toml::from_str(&contents).map_err(|error| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid config file {}: {}", path, error),
+            )
+        })
+    }
+}
+
+/// Where an accepted channel's lines go.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChannelSink {
+    /// Rotated files in the log directory, or the channel's own.
+    File,
+    /// Nowhere: lines are counted, then discarded, and no file is opened, for
+    /// channels such as `debug` accepted without filling a disk.
+    Null,
+    /// The channel's forwarding destination alone, no file opened either.
+    #[cfg(feature = "forward")]
+    Forward,
+}
+
+impl FromStr for ChannelSink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "file" => Ok(ChannelSink::File),
+            "null" => Ok(ChannelSink::Null),
+            #[cfg(feature = "forward")]
+            "forward" => Ok(ChannelSink::Forward),
+            _ => Err(format!("unknown sink: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "config")]
+    use async_std::task;
+
+    #[cfg(feature = "config")]
+    use crate::testing::LogDir;
+
+    #[test]
+    fn sinks_are_files_or_nothing() {
+        assert_eq!("file".parse(), Ok(ChannelSink::File));
+        assert_eq!("null".parse(), Ok(ChannelSink::Null));
+        assert_eq!(
+            "kafka".parse::<ChannelSink>(),
+            Err(String::from("unknown sink: kafka"))
+        );
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn config_files_declare_channels_and_tenants() {
+        task::block_on(async {
+            let dir = LogDir::new("config");
+            let path = dir.path().join("router.toml");
+            std::fs::write(
+                &path,
+                "[channels.app]\n\
+                 rotation_interval = \"15m\"\n\
+                 aliases = [\"app-1\"]\n\
+                 \n\
+                 [channels.debug]\n\
+                 sink = \"null\"\n\
+                 \n\
+                 [tenants.acme]\n\
+                 quota = \"20GB/day\"\n",
+            )
+            .unwrap();
+
+            let config = Config::load(path.to_str().unwrap()).await.unwrap();
+            assert_eq!(
+                config.channels["app"],
+                ChannelConfig {
+                    rotation_interval: Some(String::from("15m")),
+                    aliases: Some(vec![String::from("app-1")]),
+                    ..ChannelConfig::default()
+                }
+            );
+            assert_eq!(config.channels["debug"].sink.as_deref(), Some("null"));
+            assert_eq!(config.tenants["acme"].quota.as_deref(), Some("20GB/day"));
+        });
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn unknown_settings_are_refused() {
+        task::block_on(async {
+            let dir = LogDir::new("config-unknown");
+            let path = dir.path().join("router.toml");
+            std::fs::write(&path, "[channels.app]\nrotation = \"15m\"\n").unwrap();
+
+            let path = path.to_str().unwrap();
+            let error = Config::load(path).await.err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert!(
+                error
+                    .to_string()
+                    .starts_with(&format!("invalid config file {}: ", path)),
+                "{}",
+                error
+            );
+            assert!(
+                error.to_string().contains("unknown field `rotation`"),
+                "{}",
+                error
+            );
+
+            let error = Config::load(&format!("{}.missing", path))
+                .await
+                .err()
+                .unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        });
+    }
+}
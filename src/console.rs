@@ -0,0 +1,61 @@
+use async_std::channel::Sender;
+use async_std::io;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use windows_sys::core::BOOL;
+use windows_sys::Win32::System::Console::{
+    SetConsoleCtrlHandler, CTRL_BREAK_EVENT, CTRL_CLOSE_EVENT, CTRL_C_EVENT,
+};
+
+use crate::Stop;
+
+static STOP: OnceLock<Sender<Stop>> = OnceLock::new();
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// How long the console waits on a handler of its window being closed
+/// before ending the process anyway.
+const CLOSE_GRACE: Duration = Duration::from_secs(5);
+
+/// Starts the shutdown on Ctrl-C, Ctrl-Break or the console window being
+/// closed, as SIGINT and SIGTERM do elsewhere. A second event exits right
+/// away.
+pub fn stop_on_ctrl_c(stop: Sender<Stop>) -> Result<(), io::Error> {
+    let _ = STOP.set(stop);
+
+    // The handler lives as long as the process.
+    if unsafe { SetConsoleCtrlHandler(Some(on_console_event), 1) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Runs on a thread the console starts for each event.
+unsafe extern "system" fn on_console_event(event: u32) -> BOOL {
+    let name = match event {
+        CTRL_C_EVENT => "Ctrl-C",
+        CTRL_BREAK_EVENT => "Ctrl-Break",
+        CTRL_CLOSE_EVENT => "console close",
+        _ => return 0,
+    };
+
+    if STOPPING.swap(true, Ordering::SeqCst) {
+        tracing::error!("exiting on second {} without flushing", name);
+        std::process::exit(1);
+    }
+    tracing::info!("shutting down on {}", name);
+    if let Some(stop) = STOP.get() {
+        let _ = stop.try_send(Stop::Terminated);
+    }
+    // The process ends as soon as a close handler returns, so it holds on
+    // while the files are flushed.
+    if event == CTRL_CLOSE_EVENT {
+        thread::sleep(CLOSE_GRACE);
+    }
+
+    1
+}
@@ -0,0 +1,107 @@
+use async_std::io::{self, BufReader};
+use async_std::os::unix::net::UnixStream;
+use async_std::prelude::*;
+
+use serde_json::{json, Value};
+
+use structopt::StructOpt;
+
+/// Prints the status of a router serving `--control-socket`, its channels
+/// and their files, as JSON: `log-revolve-rs status --control-socket
+/// /run/log-revolve.sock`.
+#[derive(StructOpt)]
+#[structopt(name = "status")]
+pub struct StatusOptions {
+    /// Socket the router serves, as given to its `--control-socket`
+    #[structopt(long)]
+    control_socket: String,
+}
+
+/// Has a router serving `--control-socket` start fresh files right away,
+/// for one channel or every one, whatever their rotation: `log-revolve-rs
+/// rotate --control-socket /run/log-revolve.sock web`.
+#[derive(StructOpt)]
+#[structopt(name = "rotate")]
+pub struct RotateOptions {
+    /// Socket the router serves, as given to its `--control-socket`
+    #[structopt(long)]
+    control_socket: String,
+
+    /// Channel to rotate, every file of the router if left out
+    channel: Option<String>,
+}
+
+/// Prints the hourly line and byte counts a router serving
+/// `--control-socket` keeps, newest first, as JSON: `log-revolve-rs stats
+/// --control-socket /run/log-revolve.sock --hours 24 web`. With
+/// `--stats-file` they reach back past the router's restarts.
+#[derive(StructOpt)]
+#[structopt(name = "stats")]
+pub struct StatsOptions {
+    /// Socket the router serves, as given to its `--control-socket`
+    #[structopt(long)]
+    control_socket: String,
+
+    /// Hours to print per channel, all the router keeps if left out
+    #[structopt(long)]
+    hours: Option<usize>,
+
+    /// Channel to print, every channel if left out
+    channel: Option<String>,
+}
+
+pub async fn status(options: StatusOptions) -> Result<(), io::Error> {
+    let response = request(&options.control_socket, json!({ "command": "status" })).await?;
+    println!("{:#}", response);
+
+    Ok(())
+}
+
+pub async fn rotate(options: RotateOptions) -> Result<(), io::Error> {
+    let mut request = json!({ "command": "rotate" });
+    if let Some(channel) = options.channel {
+        request["channel"] = Value::String(channel);
+    }
+    let response = self::request(&options.control_socket, request).await?;
+    println!("{:#}", response);
+
+    Ok(())
+}
+
+pub async fn stats(options: StatsOptions) -> Result<(), io::Error> {
+    let mut request = json!({ "command": "stats" });
+    if let Some(channel) = options.channel {
+        request["channel"] = Value::String(channel);
+    }
+    if let Some(hours) = options.hours {
+        request["hours"] = Value::String(hours.to_string());
+    }
+    let response = self::request(&options.control_socket, request).await?;
+    println!("{:#}", response);
+
+    Ok(())
+}
+
+/// Sends `request` to the router serving `socket` and returns its answer,
+/// failing if the router turned the request down.
+async fn request(socket: &str, request: Value) -> Result<Value, io::Error> {
+    let stream = UnixStream::connect(socket).await?;
+    let mut line = request.to_string();
+    line.push('\n');
+    (&stream).write_all(line.as_bytes()).await?;
+
+    let mut response = String::new();
+    BufReader::new(&stream).read_line(&mut response).await?;
+    let response: Value = serde_json::from_str(&response).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("malformed response: {}", e),
+        )
+    })?;
+    if response["ok"] != Value::Bool(true) {
+        let error = response["error"].as_str().unwrap_or("request failed");
+        return Err(io::Error::other(error.to_string()));
+    }
+
+    Ok(response)
+}
@@ -0,0 +1,132 @@
+use async_std::io::{self, BufReader};
+use async_std::os::unix::net::{UnixListener, UnixStream};
+use async_std::prelude::*;
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+use serde_json::{json, Map, Value};
+
+use crate::{admin, FileWriter};
+
+/// Arguments each command takes when written as words, in order.
+const ARGUMENTS: &[(&str, &[&str])] = &[
+    ("stats", &["channel", "hours"]),
+    ("last", &["channel", "lines"]),
+    ("reload", &["channels"]),
+    ("set-level", &["level"]),
+];
+
+pub async fn serve(listener: UnixListener, writer: Arc<Mutex<FileWriter>>) {
+    let mut incoming = listener.incoming();
+
+    while let Some(stream) = incoming.next().await {
+        match stream {
+            Ok(stream) => {
+                let writer = writer.clone();
+                task::spawn(async move {
+                    if let Err(error) = handle_connection(stream, writer).await {
+                        tracing::warn!("control connection failed: {}", error);
+                    }
+                });
+            }
+            Err(error) => tracing::warn!("unable to accept control connection: {}", error),
+        }
+    }
+}
+
+async fn handle_connection(
+    stream: UnixStream,
+    writer: Arc<Mutex<FileWriter>>,
+) -> Result<(), io::Error> {
+    let mut lines = BufReader::new(&stream).lines();
+    let mut output = &stream;
+
+    while let Some(line) = lines.next().await {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match line.trim_start().starts_with('{') {
+            true => admin::execute(&line, &writer).await,
+            false => match from_words(&line) {
+                Ok(request) => admin::execute_request(&request, &writer).await,
+                Err(error) => json!({ "ok": false, "error": error }),
+            },
+        };
+        let mut response = response.to_string();
+        response.push('\n');
+        output.write_all(response.as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+/// A request written as words, e.g. `rotate web` or `reload app,web`, as
+/// the JSON object `admin` takes. Commands taking a channel take it first.
+fn from_words(line: &str) -> Result<Value, String> {
+    let mut words = line.split_whitespace();
+    let command = words.next().unwrap_or_default();
+    let names = ARGUMENTS
+        .iter()
+        .find(|(name, _)| *name == command)
+        .map_or(&["channel"][..], |(_, names)| *names);
+
+    let mut request = Map::new();
+    request.insert(String::from("command"), Value::from(command));
+    for word in words {
+        let name = names
+            .get(request.len() - 1)
+            .ok_or_else(|| format!("`{}` takes at most {} arguments", command, names.len()))?;
+        request.insert(name.to_string(), Value::from(word));
+    }
+
+    Ok(Value::Object(request))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn commands_take_a_channel_first() {
+        assert_eq!(
+            from_words("rotate web").unwrap(),
+            json!({ "command": "rotate", "channel": "web" })
+        );
+        assert_eq!(
+            from_words("status").unwrap(),
+            json!({ "command": "status" })
+        );
+    }
+
+    #[test]
+    fn commands_name_their_own_arguments() {
+        assert_eq!(
+            from_words("stats web 3").unwrap(),
+            json!({ "command": "stats", "channel": "web", "hours": "3" })
+        );
+        assert_eq!(
+            from_words("reload app,web").unwrap(),
+            json!({ "command": "reload", "channels": "app,web" })
+        );
+        assert_eq!(
+            from_words("set-level debug").unwrap(),
+            json!({ "command": "set-level", "level": "debug" })
+        );
+    }
+
+    #[test]
+    fn extra_words_are_refused() {
+        assert_eq!(
+            from_words("rotate web app").unwrap_err(),
+            "`rotate` takes at most 1 arguments"
+        );
+        assert_eq!(
+            from_words("last web 5 6").unwrap_err(),
+            "`last` takes at most 2 arguments"
+        );
+    }
+}
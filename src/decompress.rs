@@ -0,0 +1,110 @@
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+use async_std::io::BufReader;
+use async_std::io::{self, BufRead};
+use std::future;
+use std::pin::Pin;
+use std::str::FromStr;
+
+#[cfg(feature = "gzip")]
+use async_compression::futures::bufread::GzipDecoder;
+#[cfg(feature = "zstd")]
+use async_compression::futures::bufread::ZstdDecoder;
+
+#[cfg(not(all(feature = "gzip", feature = "zstd")))]
+use crate::compress::not_built_in;
+
+#[cfg(feature = "gzip")]
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+#[cfg(feature = "zstd")]
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// How input streams are compressed by their producers: `none`, `gzip`,
+/// `zstd`, or `auto` to tell from the first bytes of each input. Streams may
+/// hold several gzip members or zstd frames back to back, one per batch.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InputCompression {
+    None,
+    Auto,
+    #[cfg(feature = "gzip")]
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl FromStr for InputCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(InputCompression::None),
+            "auto" => Ok(InputCompression::Auto),
+            #[cfg(feature = "gzip")]
+            "gzip" => Ok(InputCompression::Gzip),
+            #[cfg(feature = "zstd")]
+            "zstd" => Ok(InputCompression::Zstd),
+            #[cfg(not(feature = "gzip"))]
+            "gzip" => Err(not_built_in(s)),
+            #[cfg(not(feature = "zstd"))]
+            "zstd" => Err(not_built_in(s)),
+            _ => Err(format!("unknown input compression: {}", s)),
+        }
+    }
+}
+
+pub type Input = Box<dyn BufRead + Unpin + Send>;
+
+/// The stream of lines `reader` carries once decompressed.
+pub async fn decompressed<R>(
+    name: &str,
+    mut reader: R,
+    compression: InputCompression,
+) -> Result<Input, io::Error>
+where
+    R: BufRead + Unpin + Send + 'static,
+{
+    let compression = match compression {
+        InputCompression::Auto => {
+            future::poll_fn(|cx| Pin::new(&mut reader).poll_fill_buf(cx).map_ok(detect)).await?
+        }
+        compression => compression,
+    };
+    if compression != InputCompression::None {
+        tracing::info!(
+            "input {} is compressed, decoding it as {:?}",
+            name,
+            compression
+        );
+    }
+
+    match compression {
+        InputCompression::None | InputCompression::Auto => Ok(Box::new(reader)),
+        #[cfg(feature = "gzip")]
+        InputCompression::Gzip => {
+            let mut decoder = GzipDecoder::new(reader);
+            decoder.multiple_members(true);
+            Ok(Box::new(BufReader::new(decoder)))
+        }
+        #[cfg(feature = "zstd")]
+        InputCompression::Zstd => {
+            let mut decoder = ZstdDecoder::new(reader);
+            decoder.multiple_members(true);
+            Ok(Box::new(BufReader::new(decoder)))
+        }
+    }
+}
+
+/// Compression of a stream starting with `head`; plain text never starts
+/// with either magic number.
+fn detect(head: &[u8]) -> InputCompression {
+    let magic_numbers: &[(&[u8], InputCompression)] = &[
+        #[cfg(feature = "gzip")]
+        (GZIP_MAGIC, InputCompression::Gzip),
+        #[cfg(feature = "zstd")]
+        (ZSTD_MAGIC, InputCompression::Zstd),
+    ];
+
+    magic_numbers
+        .iter()
+        .find(|(magic, _)| head.starts_with(magic))
+        .map_or(InputCompression::None, |(_, compression)| *compression)
+}
@@ -0,0 +1,60 @@
+use async_std::fs;
+use async_std::io::{self, WriteExt};
+
+use structopt::StructOpt;
+
+use log_revolve_rs::encryption::{FileHeader, Opener, Record, FILE_HEADER_BYTES};
+
+use crate::encrypt;
+
+/// Writes what encrypted files hold to stdout, one file after the other,
+/// e.g. `log-revolve-rs decrypt --key-file audit.key
+/// audit_2026-10-15-00-00-00.log.enc`. Files compressed as they were written come out compressed, e.g. to be
+/// piped to `gunzip`. Every record is authenticated before it is written, and
+/// a file cut short, or tampered with, fails once its last whole record is
+/// out, as does one still being written.
+#[derive(StructOpt)]
+#[structopt(name = "decrypt")]
+pub struct DecryptOptions {
+    /// File holding the key the files were encrypted with, as given to
+    /// `--encryption-key-file`
+    #[structopt(long)]
+    key_file: String,
+
+    /// Encrypted files, e.g. `audit_2026-10-15-00-00-00.log.enc`
+    #[structopt(required = true)]
+    files: Vec<String>,
+}
+
+pub async fn run(options: DecryptOptions) -> Result<(), io::Error> {
+    let key = encrypt::read_key(&options.key_file)?;
+    let mut stdout = io::stdout();
+
+    for path in options.files.iter() {
+        let file = fs::read(path).await?;
+        let invalid = |at: usize, e: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} at byte {}: {}", path, at, e),
+            )
+        };
+        let header = FileHeader::parse(&file)
+            .map_err(|e| invalid(0, e))?
+            .ok_or_else(|| invalid(0, String::from("header cut short")))?;
+        let mut opener = Opener::new(&header);
+        let mut rest = &file[FILE_HEADER_BYTES..];
+        while !rest.is_empty() {
+            let at = file.len() - rest.len();
+            let (record, used) = match Record::parse(rest).map_err(|e| invalid(at, e))? {
+                Some(parsed) => parsed,
+                None => return Err(invalid(at, String::from("record cut short"))),
+            };
+            let plaintext = opener.open(&key, &record).map_err(|e| invalid(at, e))?;
+            stdout.write_all(&plaintext).await?;
+            rest = &rest[used..];
+        }
+        opener.finish().map_err(|e| invalid(file.len(), e))?;
+    }
+
+    stdout.flush().await
+}
@@ -0,0 +1,114 @@
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::{report, FileWriter};
+
+/// Collapses a channel's runs of identical lines, such as those of a retry
+/// storm, into the first of them annotated with `... repeated N times`. A
+/// line is held until a different one comes in or its window passes, so a
+/// run is only ever as long as the channel's window, e.g. `app=10s`.
+pub struct Deduplicator {
+    windows: BTreeMap<String, Duration>,
+    held: BTreeMap<String, Run>,
+    /// Lines collapsed into the one before them, per channel.
+    pub repeated_lines: BTreeMap<String, u64>,
+}
+
+struct Run {
+    started_at: Instant,
+    message: String,
+    count: u64,
+}
+
+impl Run {
+    /// The line written for the run, annotated when it was repeated.
+    fn into_line(self) -> String {
+        if self.count == 1 {
+            return self.message;
+        }
+
+        let text = self.message.trim_end_matches(['\r', '\n']);
+        let terminator = &self.message[text.len()..];
+        format!("{} ... repeated {} times{}", text, self.count, terminator)
+    }
+}
+
+impl Deduplicator {
+    pub fn new(windows: BTreeMap<String, Duration>) -> Self {
+        Deduplicator {
+            windows,
+            held: BTreeMap::new(),
+            repeated_lines: BTreeMap::new(),
+        }
+    }
+
+    pub fn covers(&self, channel: &str) -> bool {
+        self.windows.contains_key(channel)
+    }
+
+    /// How often held lines are checked, often enough to let them go within
+    /// a quarter of the shortest window.
+    pub fn check_interval(&self) -> Duration {
+        let shortest = self.windows.values().min().copied().unwrap_or_default();
+        (shortest / 4).clamp(Duration::from_millis(250), Duration::from_secs(60))
+    }
+
+    /// Holds `message` of `channel`, returning the line held before it when
+    /// the message doesn't repeat it within the window.
+    pub fn offer(&mut self, channel: &str, message: &str) -> Option<String> {
+        let window = self.windows[channel];
+        if let Some(run) = self.held.get_mut(channel) {
+            if run.message == message && run.started_at.elapsed() < window {
+                run.count += 1;
+                *self.repeated_lines.entry(channel.to_string()).or_default() += 1;
+                return None;
+            }
+        }
+
+        let run = Run {
+            started_at: Instant::now(),
+            message: message.to_string(),
+            count: 1,
+        };
+        self.held
+            .insert(channel.to_string(), run)
+            .map(Run::into_line)
+    }
+
+    /// Lines held for longer than their window, or all of them when
+    /// `everything` is asked for.
+    pub fn release(&mut self, everything: bool) -> Vec<(String, String)> {
+        let windows = &self.windows;
+        let expired: Vec<String> = self
+            .held
+            .iter()
+            .filter(|(channel, run)| everything || run.started_at.elapsed() >= windows[*channel])
+            .map(|(channel, _)| channel.clone())
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|channel| {
+                let run = self.held.remove(&channel)?;
+                Some((channel, run.into_line()))
+            })
+            .collect()
+    }
+}
+
+/// Writes out held lines as their window passes, while no new line of their
+/// channel comes in to do so.
+pub async fn release_every(writer: Arc<Mutex<FileWriter>>, interval: Duration) {
+    loop {
+        task::sleep(interval).await;
+
+        let mut writer = writer.lock().await;
+        if let Err(error) = writer.release_deduplicated(false).await {
+            tracing::warn!("unable to write deduplicated lines: {}", error);
+            report::record_error("dedup", error);
+        }
+    }
+}
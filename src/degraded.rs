@@ -0,0 +1,107 @@
+use async_std::io;
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::FileWriter;
+
+/// How often degraded channels are looked at for a retry.
+pub const RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Wait before the first retry of a degraded channel, doubled by every retry
+/// that fails up to `MAX_BACKOFF`.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Where the lines of a channel whose file can't be written to go until a
+/// retry succeeds: `hold` keeps them in memory, up to `--spill-buffer-size`,
+/// and `inapt` sends them to the inapt file as `[degraded:<channel>] <line>`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WriteFailure {
+    Hold,
+    Inapt,
+}
+
+impl FromStr for WriteFailure {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hold" => Ok(WriteFailure::Hold),
+            "inapt" => Ok(WriteFailure::Inapt),
+            _ => Err(format!("unknown write failure policy: {}", s)),
+        }
+    }
+}
+
+/// A channel taken out of the way of the others after a failed write, and
+/// when its file is tried again.
+pub struct Degraded {
+    pub error: String,
+    backoff: Duration,
+    retry_at: Instant,
+}
+
+impl Degraded {
+    pub fn new(error: &io::Error) -> Self {
+        Degraded {
+            error: error.to_string(),
+            backoff: FIRST_BACKOFF,
+            retry_at: Instant::now() + FIRST_BACKOFF,
+        }
+    }
+
+    pub fn is_due(&self) -> bool {
+        Instant::now() >= self.retry_at
+    }
+
+    /// Puts the next retry off after another failure.
+    pub fn back_off(&mut self, error: &io::Error) {
+        self.error = error.to_string();
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+        self.retry_at = Instant::now() + self.backoff;
+    }
+}
+
+/// A write that failed, and the file it was for, which is taken out of the
+/// way of the others until a retry finds it writable again.
+#[derive(Debug, thiserror::Error)]
+pub enum WriteError {
+    #[error("unable to write channel {0}: {1}")]
+    Channel(String, #[source] io::Error),
+    /// The combined file of a paired service or rollup file of a group.
+    #[error("unable to write combined file {0}: {1}")]
+    Combined(String, #[source] io::Error),
+    /// The `<channel>.overflow` file of a channel.
+    #[error("unable to write overflow file of {0}: {1}")]
+    Overflow(String, #[source] io::Error),
+    #[error("unable to write inapt file: {0}")]
+    Inapt(#[source] io::Error),
+}
+
+impl WriteError {
+    pub fn io_error(&self) -> &io::Error {
+        match self {
+            WriteError::Channel(_, error)
+            | WriteError::Combined(_, error)
+            | WriteError::Overflow(_, error)
+            | WriteError::Inapt(error) => error,
+        }
+    }
+}
+
+impl From<WriteError> for io::Error {
+    fn from(error: WriteError) -> Self {
+        io::Error::new(error.io_error().kind(), error.to_string())
+    }
+}
+
+pub async fn retry_every(writer: Arc<Mutex<FileWriter>>, interval: Duration) {
+    loop {
+        task::sleep(interval).await;
+
+        writer.lock().await.retry_degraded().await;
+    }
+}
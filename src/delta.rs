@@ -0,0 +1,189 @@
+use async_std::fs;
+use async_std::io;
+use async_std::prelude::*;
+
+use std::collections::BTreeSet;
+use std::time::{Duration, SystemTime};
+
+use log_revolve_rs::rotation::{self, Rotation};
+
+/// Files written to within this long make up the channel set the log
+/// directory was last fed with: the current period and the one before.
+const RECENT: Duration = Duration::from_secs(2 * 60 * 60);
+
+/// How the accepted channels differ from those the log directory was
+/// recently written with, so a deploy that silently stops accepting a
+/// service's lines shows up as soon as it starts.
+#[derive(Default)]
+pub struct ChannelDelta {
+    /// Channels with recent files that are no longer accepted.
+    pub disappeared: Vec<String>,
+    /// Accepted channels without any recent file.
+    pub appeared: Vec<String>,
+}
+
+impl ChannelDelta {
+    /// Compares `accepted` against the recent files of `log_dir`. Files of
+    /// names in `expected` (the inapt file, combined files) are not channels
+    /// to be missed.
+    pub async fn scan(
+        log_dir: &str,
+        rotation: Rotation,
+        accepted: &BTreeSet<String>,
+        expected: &BTreeSet<String>,
+    ) -> Result<Self, io::Error> {
+        let recent = recent_channels(log_dir, rotation).await?;
+        // A fresh directory has nothing to compare against.
+        if recent.is_empty() {
+            return Ok(ChannelDelta::default());
+        }
+
+        Ok(ChannelDelta {
+            disappeared: recent
+                .iter()
+                .filter(|channel| !accepted.contains(*channel) && !expected.contains(*channel))
+                .cloned()
+                .collect(),
+            appeared: accepted
+                .iter()
+                .filter(|channel| !recent.contains(*channel))
+                .cloned()
+                .collect(),
+        })
+    }
+
+    /// Logs an event per changed channel, e.g.
+    /// `{"event":"channel_delta","change":"disappeared","channel":"api"}`.
+    pub fn report(&self) {
+        for channel in self.disappeared.iter() {
+            tracing::warn!(
+                "{{\"event\":\"channel_delta\",\"change\":\"disappeared\",\"channel\":{:?}}}",
+                channel
+            );
+        }
+        for channel in self.appeared.iter() {
+            tracing::info!(
+                "{{\"event\":\"channel_delta\",\"change\":\"appeared\",\"channel\":{:?}}}",
+                channel
+            );
+        }
+    }
+}
+
+async fn recent_channels(log_dir: &str, rotation: Rotation) -> Result<BTreeSet<String>, io::Error> {
+    let mut channels = BTreeSet::new();
+    let now = SystemTime::now();
+
+    let mut entries = fs::read_dir(log_dir).await?;
+    while let Some(entry) = entries.next().await {
+        let entry = entry?;
+        let name = entry.file_name();
+        let channel = match name.to_str().and_then(|name| channel_of(rotation, name)) {
+            Some(channel) => channel,
+            None => continue,
+        };
+
+        let modified = entry.metadata().await?.modified()?;
+        let age = now.duration_since(modified).unwrap_or_default();
+        if age <= RECENT {
+            channels.insert(channel.to_string());
+        }
+    }
+
+    Ok(channels)
+}
+
+/// The channel a file in the log directory belongs to, compressed, encrypted
+/// or not. Overflow files belong to no channel of their own.
+fn channel_of(rotation: Rotation, file_name: &str) -> Option<&str> {
+    let file_name = file_name.strip_suffix(".enc").unwrap_or(file_name);
+    let file_name = file_name
+        .strip_suffix(".gz")
+        .or_else(|| file_name.strip_suffix(".zst"))
+        .unwrap_or(file_name);
+
+    let channel = match rotation {
+        Rotation::Hourly(timestamp) | Rotation::Periodic(timestamp, _) => {
+            let (channel, _) = file_name.rsplit_once('_')?;
+            rotation::parse_file_name(channel, timestamp, file_name)?;
+            channel
+        }
+        Rotation::External => file_name.strip_suffix(".log")?,
+    };
+
+    Some(channel).filter(|channel| !channel.ends_with(".overflow"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::task;
+
+    use log_revolve_rs::rotation::FileTimestamp;
+
+    use crate::testing::LogDir;
+
+    const HOURLY: Rotation = Rotation::Hourly(FileTimestamp::Seconds);
+
+    fn set(channels: &[&str]) -> BTreeSet<String> {
+        channels.iter().map(|channel| channel.to_string()).collect()
+    }
+
+    #[test]
+    fn files_name_their_channel_however_they_are_stored() {
+        let name = |file_name| channel_of(HOURLY, file_name);
+        assert_eq!(name("web_2024-06-01-13-00-00.log"), Some("web"));
+        assert_eq!(name("my_app_2024-06-01-13-00-00.log.gz"), Some("my_app"));
+        assert_eq!(name("web_2024-06-01-13-00-00.001.log.zst.enc"), Some("web"));
+        assert_eq!(name("web.overflow_2024-06-01-13-00-00.log"), None);
+        assert_eq!(name("web_yesterday.log"), None);
+        assert_eq!(name("stats.json"), None);
+
+        assert_eq!(channel_of(Rotation::External, "web.log.gz"), Some("web"));
+        assert_eq!(channel_of(Rotation::External, "web.log.1"), None);
+    }
+
+    #[test]
+    fn recent_channels_are_compared_with_the_accepted_ones() {
+        task::block_on(async {
+            let dir = LogDir::new("delta");
+            for file_name in [
+                "web_2024-06-01-13-00-00.log",
+                "db_2024-06-01-13-00-00.log.gz",
+                "inapt_2024-06-01-13-00-00.log",
+                "old_2024-05-01-13-00-00.log",
+            ] {
+                std::fs::write(dir.path().join(file_name), "line\n").unwrap();
+            }
+            let long_ago = SystemTime::now() - 2 * RECENT;
+            std::fs::File::options()
+                .write(true)
+                .open(dir.path().join("old_2024-05-01-13-00-00.log"))
+                .unwrap()
+                .set_modified(long_ago)
+                .unwrap();
+
+            let log_dir = dir.path().to_str().unwrap();
+            let delta =
+                ChannelDelta::scan(log_dir, HOURLY, &set(&["web", "api"]), &set(&["inapt"]))
+                    .await
+                    .unwrap();
+            assert_eq!(delta.disappeared, vec!["db"]);
+            assert_eq!(delta.appeared, vec!["api"]);
+        });
+    }
+
+    #[test]
+    fn fresh_directories_have_nothing_to_compare_against() {
+        task::block_on(async {
+            let dir = LogDir::new("delta-fresh");
+            let log_dir = dir.path().to_str().unwrap();
+            let delta = ChannelDelta::scan(log_dir, HOURLY, &set(&["web"]), &set(&[]))
+                .await
+                .unwrap();
+            assert!(delta.disappeared.is_empty());
+            assert!(delta.appeared.is_empty());
+        });
+    }
+}
@@ -0,0 +1,140 @@
+use async_std::fs;
+use async_std::io;
+use async_std::path::Path;
+use async_std::prelude::*;
+
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use log_revolve_rs::rotation::FileTimestamp;
+
+use crate::retention;
+
+/// What happens once a channel's files take more than its disk quota.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DiskQuotaAction {
+    /// The channel's oldest rotated files are removed.
+    DeleteOldest,
+    /// Lines are dropped, a marker left in the current file, until the
+    /// channel is back under its quota.
+    Stop,
+}
+
+impl FromStr for DiskQuotaAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "delete-oldest" => Ok(DiskQuotaAction::DeleteOldest),
+            "stop" => Ok(DiskQuotaAction::Stop),
+            _ => Err(format!("unknown disk quota action: {}", s)),
+        }
+    }
+}
+
+/// Most a channel's files may take in its directory together, the current
+/// one included, given by `disk_quota` in the config file, so a chatty
+/// channel can't push another's history out of a shared budget.
+#[derive(Clone, Copy, Debug)]
+pub struct DiskQuota {
+    pub limit: u64,
+    pub action: DiskQuotaAction,
+    /// Bytes of the channel's rotated files, as of the last rotation.
+    rotated_bytes: u64,
+    /// Whether the marker of dropped lines is in the current file.
+    pub stopped: bool,
+}
+
+impl DiskQuota {
+    pub fn new(limit: u64, action: DiskQuotaAction) -> Self {
+        DiskQuota {
+            limit,
+            action,
+            rotated_bytes: 0,
+            stopped: false,
+        }
+    }
+
+    /// Takes stock of the rotated files of `file_name` in `dir`, next to the
+    /// `current` one holding `current_bytes`, removing the oldest while the
+    /// channel is over its quota if that is its action. The `spared` file and
+    /// files being compressed count, but are left alone. Returns how many
+    /// files went.
+    pub async fn enforce(
+        &mut self,
+        dir: &str,
+        file_name: &str,
+        timestamp: FileTimestamp,
+        current: &str,
+        current_bytes: u64,
+        spared: Option<&str>,
+    ) -> Result<usize, io::Error> {
+        let mut names = Vec::new();
+        let mut entries = fs::read_dir(dir).await?;
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            if entry.file_type().await?.is_file() {
+                names.extend(entry.file_name().into_string());
+            }
+        }
+
+        let file_names = BTreeSet::from([file_name.to_string()]);
+        let mut files = Vec::new();
+        for name in names.iter() {
+            let (channel, opened_at, seq) = match retention::stamped(&timestamp, &file_names, name)
+            {
+                Some(stamped) => stamped,
+                None => continue,
+            };
+            let path = Path::new(dir).join(name);
+            if channel != file_name || path.to_str() == Some(current) {
+                continue;
+            }
+            let size = fs::metadata(&path).await?.len();
+            files.push((opened_at, seq, name, size));
+        }
+        // Oldest first.
+        files.sort_unstable();
+
+        self.rotated_bytes = files.iter().map(|(_, _, _, size)| size).sum();
+        let mut removed = 0;
+        if self.action == DiskQuotaAction::DeleteOldest {
+            for (_, _, name, size) in files {
+                if self.rotated_bytes + current_bytes <= self.limit {
+                    break;
+                }
+                let path = Path::new(dir).join(name);
+                let compressing = [".gz.partial", ".zst.partial"]
+                    .iter()
+                    .any(|extension| names.contains(&format!("{}{}", name, extension)));
+                if compressing || path.to_str().is_some_and(|path| spared == Some(path)) {
+                    continue;
+                }
+
+                retention::remove_file(&path).await?;
+                self.rotated_bytes -= size;
+                removed += 1;
+            }
+        }
+        if self.rotated_bytes + current_bytes <= self.limit {
+            self.stopped = false;
+        }
+
+        Ok(removed)
+    }
+
+    /// Whether a line of `bytes` is dropped, the channel's current file
+    /// holding `current_bytes`.
+    pub fn drops(&self, current_bytes: u64, bytes: usize) -> bool {
+        self.action == DiskQuotaAction::Stop
+            && self.rotated_bytes + current_bytes + bytes as u64 > self.limit
+    }
+
+    /// Line left in the current file as lines start being dropped.
+    pub fn marker(&self) -> String {
+        format!(
+            "# over the disk quota of {} bytes, lines are dropped until files are removed\n",
+            self.limit
+        )
+    }
+}
@@ -0,0 +1,130 @@
+use std::str::FromStr;
+
+/// First of the 256 private use characters each standing for a byte of a
+/// line decoded by `Passthrough` until it is written. Characters of the line
+/// already among them are escaped as their bytes too, so every one is put
+/// back as received.
+const ESCAPE_BASE: u32 = 0x10_FF00;
+
+/// Leading byte of every escaping character, and of few others.
+const ESCAPE_LEAD: u8 = 0xF4;
+
+/// What happens to a line that isn't valid UTF-8.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InvalidUtf8 {
+    /// Written with each invalid sequence replaced by U+FFFD.
+    Lossy,
+    /// Written with the invalid bytes as received.
+    Passthrough,
+    /// Rejected to the inapt file, replaced as by `lossy`.
+    Inapt,
+}
+
+impl FromStr for InvalidUtf8 {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lossy" => Ok(InvalidUtf8::Lossy),
+            "passthrough" => Ok(InvalidUtf8::Passthrough),
+            "inapt" => Ok(InvalidUtf8::Inapt),
+            _ => Err(format!("unknown invalid UTF-8 handling: {}", s)),
+        }
+    }
+}
+
+impl InvalidUtf8 {
+    /// Appends `bytes` to `line` as text, returning whether they were valid
+    /// UTF-8. `Passthrough` escapes the bytes, for `unescape` to put back.
+    pub fn decode(self, bytes: &[u8], line: &mut String) -> bool {
+        let passthrough = self == InvalidUtf8::Passthrough;
+        if let Ok(text) = std::str::from_utf8(bytes) {
+            match passthrough {
+                true => push_escaped(line, text),
+                false => line.push_str(text),
+            }
+            return true;
+        }
+
+        for chunk in bytes.utf8_chunks() {
+            match passthrough {
+                true => push_escaped(line, chunk.valid()),
+                false => line.push_str(chunk.valid()),
+            }
+            if chunk.invalid().is_empty() {
+                continue;
+            }
+            match self {
+                InvalidUtf8::Passthrough => {
+                    line.extend(chunk.invalid().iter().map(|&byte| escape(byte)))
+                }
+                InvalidUtf8::Lossy | InvalidUtf8::Inapt => line.push(char::REPLACEMENT_CHARACTER),
+            }
+        }
+
+        false
+    }
+}
+
+/// Whether `text`, decoded by `Passthrough`, holds escaped bytes.
+pub fn is_escaped(text: &str) -> bool {
+    text.as_bytes().contains(&ESCAPE_LEAD) && text.chars().any(is_escape)
+}
+
+/// The bytes `text` was decoded by `Passthrough` from, as received.
+pub fn unescape(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        match (c as u32).checked_sub(ESCAPE_BASE) {
+            Some(byte) => bytes.push(byte as u8),
+            None => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+
+    bytes
+}
+
+/// Appends `text` to `line`, escaping the characters escapes are made of.
+fn push_escaped(line: &mut String, text: &str) {
+    if !text.as_bytes().contains(&ESCAPE_LEAD) {
+        return line.push_str(text);
+    }
+
+    for c in text.chars() {
+        match is_escape(c) {
+            true => line.extend(c.encode_utf8(&mut [0; 4]).bytes().map(escape)),
+            false => line.push(c),
+        }
+    }
+}
+
+fn is_escape(c: char) -> bool {
+    c as u32 >= ESCAPE_BASE
+}
+
+fn escape(byte: u8) -> char {
+    // Every value from the base up is a private use character.
+    char::from_u32(ESCAPE_BASE + u32::from(byte)).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+/// How many of `bytes` come before a character their end cuts in two, all
+/// of them if none is.
+pub fn whole_len(bytes: &[u8]) -> usize {
+    let last = bytes.len().saturating_sub(4)..bytes.len();
+    let start = match last.rev().find(|&i| bytes[i] & 0xC0 != 0x80) {
+        Some(start) => start,
+        None => return bytes.len(),
+    };
+    let width = match bytes[start] {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => 1,
+    };
+
+    if start + width > bytes.len() {
+        start
+    } else {
+        bytes.len()
+    }
+}
@@ -0,0 +1,201 @@
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::OnceLock;
+
+use log_revolve_rs::encryption::{
+    record_aad, FileHeader, Key, Record, FILE_HEADER_BYTES, FILE_ID_BYTES, NONCE_BYTES,
+    RECORD_HEADER_BYTES,
+};
+
+/// Key the files of encrypted channels are sealed with, set once at startup.
+static KEY: OnceLock<Key> = OnceLock::new();
+
+/// Reads a key file, 64 hexadecimal digits such as `openssl rand -hex 32`
+/// prints, whitespace around them ignored.
+pub fn read_key(path: &str) -> Result<Key, io::Error> {
+    let text = std::fs::read_to_string(path)?;
+    Key::from_hex(&text)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", path, e)))
+}
+
+/// Seals the files of encrypted channels with the key in `path`.
+pub fn install(path: &str) -> Result<(), io::Error> {
+    let _ = KEY.set(read_key(path)?);
+    Ok(())
+}
+
+/// Seals what a file's handle writes out into records, under nonces made of
+/// a prefix drawn for the handle and a count of the records sealed with it.
+/// The sealer is attached to a file before its first record goes to it, and
+/// detached once the file's last record is sealed, as the file is closed.
+pub struct Sealer {
+    key: &'static Key,
+    prefix: [u8; NONCE_BYTES - 4],
+    count: u32,
+    file: Option<SealedFile>,
+}
+
+/// The file a sealer is attached to.
+struct SealedFile {
+    header: FileHeader,
+    /// Index of the next record sealed.
+    index: u64,
+    /// Whether the header is still to be written, ahead of the next record.
+    new: bool,
+}
+
+impl Sealer {
+    /// The sealer of an encrypted channel's handle, `None` unless a key was
+    /// installed.
+    pub fn new() -> Result<Option<Sealer>, io::Error> {
+        let key = match KEY.get() {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+
+        Ok(Some(Sealer {
+            key,
+            prefix: random_prefix()?,
+            count: 0,
+            file: None,
+        }))
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Attaches the sealer to the file at `path`, to append records to it.
+    /// A file already there, written before a restart or before it was
+    /// closed while idle, is carried on with: its last record, and whatever
+    /// a crash cut short after its last whole one, are cut off, and the
+    /// bytes cut are returned.
+    pub fn attach(&mut self, path: &str) -> Result<u64, io::Error> {
+        let invalid =
+            |e: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, e));
+        let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                self.file = Some(SealedFile::new()?);
+                return Ok(0);
+            }
+            Err(error) => return Err(error),
+        };
+        let length = file.metadata()?.len();
+
+        let mut header = [0; FILE_HEADER_BYTES];
+        if length < FILE_HEADER_BYTES as u64 {
+            file.set_len(0)?;
+            self.file = Some(SealedFile::new()?);
+            return Ok(length);
+        }
+        file.read_exact(&mut header)?;
+        let header = FileHeader::parse(&header)
+            .map_err(invalid)?
+            .ok_or_else(|| invalid(String::from("header cut short")))?;
+
+        let mut index = 0;
+        let mut end = FILE_HEADER_BYTES as u64;
+        let mut last_at = None;
+        let mut record = [0; RECORD_HEADER_BYTES];
+        while end + RECORD_HEADER_BYTES as u64 <= length {
+            file.seek(SeekFrom::Start(end))?;
+            file.read_exact(&mut record)?;
+            let (sealed, last) = Record::parse_header(&record).map_err(invalid)?;
+            let record_end = end + (RECORD_HEADER_BYTES + sealed) as u64;
+            if record_end > length {
+                break;
+            }
+            last_at = if last { Some(end) } else { None };
+            index += 1;
+            end = record_end;
+        }
+        if let Some(last_at) = last_at {
+            index -= 1;
+            end = last_at;
+        }
+        if end < length {
+            file.set_len(end)?;
+        }
+
+        self.file = Some(SealedFile {
+            header,
+            index,
+            new: false,
+        });
+        Ok(length - end)
+    }
+
+    /// `bytes` sealed into the attached file's next record, ready to be
+    /// appended to it along with the file's header if it is its first.
+    pub fn seal(&mut self, bytes: &[u8]) -> Result<Vec<u8>, io::Error> {
+        self.seal_record(bytes, false)
+    }
+
+    /// The attached file's last record, empty, ready to be appended to it
+    /// before it is closed, and the sealer detached from it.
+    pub fn finish(&mut self) -> Result<Vec<u8>, io::Error> {
+        let record = self.seal_record(&[], true)?;
+        self.detach();
+        Ok(record)
+    }
+
+    /// Detaches the sealer from a file that went away.
+    pub fn detach(&mut self) {
+        self.file = None;
+    }
+
+    fn seal_record(&mut self, bytes: &[u8], last: bool) -> Result<Vec<u8>, io::Error> {
+        if self.count == u32::MAX {
+            self.prefix = random_prefix()?;
+            self.count = 0;
+        }
+        let mut nonce = [0; NONCE_BYTES];
+        nonce[..self.prefix.len()].copy_from_slice(&self.prefix);
+        nonce[self.prefix.len()..].copy_from_slice(&self.count.to_be_bytes());
+        self.count += 1;
+
+        let file = self
+            .file
+            .as_mut()
+            .expect("records are sealed into an attached file");
+        let aad = record_aad(&file.header.file_id, file.index, last);
+        let sealed = self.key.seal(&nonce, &aad, bytes);
+        let mut record = Vec::with_capacity(FILE_HEADER_BYTES + RECORD_HEADER_BYTES + sealed.len());
+        if file.new {
+            file.header.encode(&mut record);
+            file.new = false;
+        }
+        Record {
+            last,
+            nonce,
+            sealed: &sealed,
+        }
+        .encode(&mut record);
+        file.index += 1;
+        Ok(record)
+    }
+}
+
+impl SealedFile {
+    /// A file of its own, named with an id drawn for it.
+    fn new() -> Result<Self, io::Error> {
+        let mut file_id = [0; FILE_ID_BYTES];
+        getrandom::getrandom(&mut file_id).map_err(io::Error::from)?;
+
+        Ok(SealedFile {
+            header: FileHeader { file_id },
+            index: 0,
+            new: true,
+        })
+    }
+}
+
+/// Bytes drawn from the system's random number generator, so no other
+/// handle, of this process or another, draws them too.
+fn random_prefix() -> Result<[u8; NONCE_BYTES - 4], io::Error> {
+    let mut prefix = [0; NONCE_BYTES - 4];
+    getrandom::getrandom(&mut prefix).map_err(io::Error::from)?;
+
+    Ok(prefix)
+}
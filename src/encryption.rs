@@ -0,0 +1,255 @@
+//! AES-256-GCM, as channels' files are encrypted at rest, and the records
+//! those files are made of. Free of I/O: keys and nonces are handed in.
+//!
+//! An encrypted file is a header naming it with a random id, then a series of
+//! records, one per batch of lines written out, each sealed on its own so a
+//! file appended to after a restart, or cut short by a crash, still decrypts
+//! up to its last whole record:
+//!
+//! ```text
+//! "LRF2" | file id: 16 bytes
+//! "LRE2" | length: u32, big endian | last: u8 | nonce: 12 bytes | ciphertext and tag: length bytes
+//! ```
+//!
+//! Each record is authenticated along with the file id, its index in the
+//! file and whether it is the file's last, so records dropped, reordered,
+//! repeated or moved from another file fail to open. A file that is done
+//! with ends with a last record, and one without it was cut short, or is
+//! still being written.
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::Aes256Gcm;
+use zeroize::Zeroizing;
+
+/// Bytes of an AES-256 key.
+pub const KEY_BYTES: usize = 32;
+
+/// Bytes of a GCM nonce, which must never be used twice with a key.
+pub const NONCE_BYTES: usize = 12;
+
+/// Bytes of the tag authenticating a sealed message.
+pub const TAG_BYTES: usize = 16;
+
+/// What every encrypted file starts with.
+pub const FILE_MAGIC: &[u8; 4] = b"LRF2";
+
+/// Bytes of the random id naming an encrypted file.
+pub const FILE_ID_BYTES: usize = 16;
+
+/// Bytes of a file's header ahead of its first record.
+pub const FILE_HEADER_BYTES: usize = FILE_MAGIC.len() + FILE_ID_BYTES;
+
+/// What every record starts with.
+pub const RECORD_MAGIC: &[u8; 4] = b"LRE2";
+
+/// Bytes of a record ahead of its sealed message.
+pub const RECORD_HEADER_BYTES: usize = RECORD_MAGIC.len() + 4 + 1 + NONCE_BYTES;
+
+/// Bytes of the data a record is authenticated along with.
+const AAD_BYTES: usize = FILE_ID_BYTES + 8 + 1;
+
+/// An AES-256-GCM key, expanded once for all the messages it seals. The
+/// cipher is RustCrypto's, constant-time where the hardware allows.
+pub struct Key {
+    cipher: Aes256Gcm,
+}
+
+impl Key {
+    pub fn new(key: &[u8; KEY_BYTES]) -> Self {
+        Key {
+            cipher: Aes256Gcm::new(key.into()),
+        }
+    }
+
+    /// A key written as 64 hexadecimal digits, such as `openssl rand -hex
+    /// 32` prints.
+    pub fn from_hex(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        if text.len() != 2 * KEY_BYTES || !text.is_ascii() {
+            return Err(format!(
+                "key must be {} hexadecimal digits, got {} characters",
+                2 * KEY_BYTES,
+                text.len()
+            ));
+        }
+
+        let mut key = Zeroizing::new([0; KEY_BYTES]);
+        for (byte, digits) in key.iter_mut().zip(text.as_bytes().chunks_exact(2)) {
+            let digits = std::str::from_utf8(digits).unwrap_or("");
+            *byte = u8::from_str_radix(digits, 16)
+                .map_err(|_| String::from("key holds a non-hexadecimal digit"))?;
+        }
+        Ok(Key::new(&key))
+    }
+
+    /// `plaintext` encrypted, followed by its tag authenticating it along
+    /// with `aad`.
+    pub fn seal(&self, nonce: &[u8; NONCE_BYTES], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let payload = Payload {
+            msg: plaintext,
+            aad,
+        };
+        self.cipher
+            .encrypt(nonce.into(), payload)
+            // Only messages past 64 GiB can't be sealed, and batches never
+            // come near.
+            .expect("message too large to seal")
+    }
+
+    /// The plaintext of a message sealed with `nonce` and `aad`, unless it
+    /// was tampered with, sealed with another key or along with other data.
+    pub fn open(
+        &self,
+        nonce: &[u8; NONCE_BYTES],
+        aad: &[u8],
+        sealed: &[u8],
+    ) -> Result<Vec<u8>, String> {
+        if sealed.len() < TAG_BYTES {
+            return Err(String::from("sealed message shorter than its tag"));
+        }
+
+        let payload = Payload { msg: sealed, aad };
+        self.cipher.decrypt(nonce.into(), payload).map_err(|_| {
+            String::from(
+                "message fails authentication: wrong key, tampered with, \
+                 out of place or from another file",
+            )
+        })
+    }
+}
+
+/// What the record at `index` of the file `file_id` is authenticated along
+/// with.
+pub fn record_aad(file_id: &[u8; FILE_ID_BYTES], index: u64, last: bool) -> [u8; AAD_BYTES] {
+    let mut aad = [0; AAD_BYTES];
+    aad[..FILE_ID_BYTES].copy_from_slice(file_id);
+    aad[FILE_ID_BYTES..FILE_ID_BYTES + 8].copy_from_slice(&index.to_be_bytes());
+    aad[FILE_ID_BYTES + 8] = u8::from(last);
+    aad
+}
+
+/// The header an encrypted file starts with.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FileHeader {
+    pub file_id: [u8; FILE_ID_BYTES],
+}
+
+impl FileHeader {
+    /// The header `input` starts with, `None` if `input` ends before it
+    /// does.
+    pub fn parse(input: &[u8]) -> Result<Option<Self>, String> {
+        if input.len() < FILE_HEADER_BYTES {
+            return Ok(None);
+        }
+        if &input[..FILE_MAGIC.len()] != FILE_MAGIC {
+            return Err(String::from("not an encrypted file"));
+        }
+
+        let mut file_id = [0; FILE_ID_BYTES];
+        file_id.copy_from_slice(&input[FILE_MAGIC.len()..FILE_HEADER_BYTES]);
+        Ok(Some(FileHeader { file_id }))
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(FILE_MAGIC);
+        out.extend_from_slice(&self.file_id);
+    }
+}
+
+/// A record of an encrypted file.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Record<'a> {
+    /// Whether the record is the file's last.
+    pub last: bool,
+    pub nonce: [u8; NONCE_BYTES],
+    /// The ciphertext, followed by its tag.
+    pub sealed: &'a [u8],
+}
+
+impl<'a> Record<'a> {
+    /// The record `input` starts with and the bytes it takes, `None` if
+    /// `input` ends before it does.
+    pub fn parse(input: &'a [u8]) -> Result<Option<(Self, usize)>, String> {
+        if input.len() < RECORD_HEADER_BYTES {
+            return Ok(None);
+        }
+        let (length, last) = Record::parse_header(input)?;
+        let end = RECORD_HEADER_BYTES + length;
+        if input.len() < end {
+            return Ok(None);
+        }
+
+        let mut nonce = [0; NONCE_BYTES];
+        nonce.copy_from_slice(&input[RECORD_HEADER_BYTES - NONCE_BYTES..RECORD_HEADER_BYTES]);
+        let record = Record {
+            last,
+            nonce,
+            sealed: &input[RECORD_HEADER_BYTES..end],
+        };
+        Ok(Some((record, end)))
+    }
+
+    /// Bytes of the sealed message of the record whose header `header`,
+    /// `RECORD_HEADER_BYTES` long, is, and whether it is the file's last.
+    pub fn parse_header(header: &[u8]) -> Result<(usize, bool), String> {
+        if &header[..RECORD_MAGIC.len()] != RECORD_MAGIC {
+            return Err(String::from("not a record of an encrypted file"));
+        }
+        let length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let last = match header[8] {
+            0 => false,
+            1 => true,
+            flag => return Err(format!("unknown record flag {}", flag)),
+        };
+        Ok((length, last))
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(RECORD_MAGIC);
+        out.extend_from_slice(&(self.sealed.len() as u32).to_be_bytes());
+        out.push(u8::from(self.last));
+        out.extend_from_slice(&self.nonce);
+        out.extend_from_slice(self.sealed);
+    }
+}
+
+/// Opens the records of a file one after the other, in the order they were
+/// sealed, refusing any out of place and anything after the last.
+pub struct Opener {
+    file_id: [u8; FILE_ID_BYTES],
+    index: u64,
+    ended: bool,
+}
+
+impl Opener {
+    pub fn new(header: &FileHeader) -> Self {
+        Opener {
+            file_id: header.file_id,
+            index: 0,
+            ended: false,
+        }
+    }
+
+    /// The plaintext of the file's next record.
+    pub fn open(&mut self, key: &Key, record: &Record) -> Result<Vec<u8>, String> {
+        if self.ended {
+            return Err(String::from("record after the file's last"));
+        }
+
+        let aad = record_aad(&self.file_id, self.index, record.last);
+        let plaintext = key.open(&record.nonce, &aad, record.sealed)?;
+        self.index += 1;
+        self.ended = record.last;
+        Ok(plaintext)
+    }
+
+    /// Whether the file's last record was opened, once the file ends.
+    pub fn finish(&self) -> Result<(), String> {
+        match self.ended {
+            true => Ok(()),
+            false => Err(String::from(
+                "file ends before its last record: cut short, or still being written",
+            )),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use chrono::{DateTime, FixedOffset};
+
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+use log_revolve_rs::platform;
+
+use crate::stamp::TimestampFormat;
+
+/// How lines are written to files: `plain`, as received, or `jsonl`, each
+/// wrapped in a `{"ts": ..., "channel": ..., "msg": ...}` record for
+/// ingestion pipelines that want structured records.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OutputFormat {
+    Plain,
+    Jsonl,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(OutputFormat::Plain),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            _ => Err(format!("unknown output format: {}", s)),
+        }
+    }
+}
+
+/// Appends `line` of `channel` to `wrapped` as a JSON Lines record, received
+/// at `time`, written as `format` says: a number for `epoch-millis`, a
+/// string otherwise.
+pub fn wrap(
+    channel: &str,
+    format: &TimestampFormat,
+    time: &DateTime<FixedOffset>,
+    line: &str,
+    wrapped: &mut String,
+) {
+    // Writing to a `String` can't fail.
+    wrapped.push_str("{\"ts\":");
+    if *format == TimestampFormat::EpochMillis {
+        let _ = format.write(time, wrapped);
+    } else {
+        wrapped.push('"');
+        let _ = format.write(time, &mut Escaped(wrapped));
+        wrapped.push('"');
+    }
+    wrapped.push_str(",\"channel\":\"");
+    let _ = Escaped(wrapped).write_str(channel);
+    wrapped.push_str("\",\"msg\":\"");
+    let _ = Escaped(wrapped).write_str(platform::strip_line_ending(line));
+    wrapped.push_str("\"}\n");
+}
+
+/// Writes what it is given into a JSON string, escaped.
+pub struct Escaped<'a>(pub &'a mut String);
+
+impl Write for Escaped<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Whatever is escaped is ASCII, so the runs between are whole text.
+        let mut run = 0;
+        for (i, &byte) in s.as_bytes().iter().enumerate() {
+            let escaped = match byte {
+                b'"' => "\\\"",
+                b'\\' => "\\\\",
+                b'\n' => "\\n",
+                b'\r' => "\\r",
+                b'\t' => "\\t",
+                0..=0x1f => "",
+                _ => continue,
+            };
+            self.0.push_str(&s[run..i]);
+            match escaped {
+                "" => write!(self.0, "\\u{:04x}", byte)?,
+                escaped => self.0.push_str(escaped),
+            }
+            run = i + 1;
+        }
+        self.0.push_str(&s[run..]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use async_std::io;
+use async_std::task;
+
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::report;
+
+/// Exit status of a router that couldn't start, from `sysexits.h`'s
+/// `EX_CONFIG`: invalid options or config file, or directories failing the
+/// preflight. Restarting won't help until the deployment is fixed.
+pub const EXIT_CONFIG: i32 = 78;
+
+/// Exit status of a router that failed once running, `EX_IOERR`: an input
+/// or a file failed. Restarting may well help.
+pub const EXIT_IO: i32 = 74;
+
+/// Whether reads that fail are tried again, set once at startup by
+/// `--on-error retry`.
+static RETRYING: AtomicBool = AtomicBool::new(false);
+
+/// Whether the router got as far as reading its inputs, which tells a
+/// failure to start from one while running.
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Wait before an input that failed is read again, doubled by every retry
+/// that fails in a row up to `MAX_BACKOFF`.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// What a failing input does to the router: `abort` stops it at once,
+/// `continue` lets the other inputs carry on, the files flushed once they
+/// are closed too, and `retry` reads the input again after a pause, for
+/// streams that fail only for a while. An input that can't be read again,
+/// such as one whose records can't be resumed, is left as by `continue`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OnError {
+    Abort,
+    Continue,
+    Retry,
+}
+
+impl FromStr for OnError {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(OnError::Abort),
+            "continue" => Ok(OnError::Continue),
+            "retry" => Ok(OnError::Retry),
+            _ => Err(format!("unknown error policy: {}", s)),
+        }
+    }
+}
+
+/// Reads failing inputs again from now on.
+pub fn retry_reads() {
+    RETRYING.store(true, Ordering::Relaxed);
+}
+
+/// Marks the router as running, its failures runtime ones from now on.
+pub fn started() {
+    STARTED.store(true, Ordering::Relaxed);
+}
+
+/// The exit status of a router that ended with `result`.
+pub fn exit_status(result: &Result<(), io::Error>) -> i32 {
+    match result {
+        Ok(()) => 0,
+        Err(_) if STARTED.load(Ordering::Relaxed) => EXIT_IO,
+        Err(_) => EXIT_CONFIG,
+    }
+}
+
+/// How long an input waits before it is read again, growing while reads
+/// keep failing.
+pub struct Backoff(Duration);
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff(FIRST_BACKOFF)
+    }
+}
+
+impl Backoff {
+    /// Starts over after a read that succeeded.
+    pub fn reset(&mut self) {
+        self.0 = FIRST_BACKOFF;
+    }
+}
+
+/// Waits before `input`, whose read failed with `error`, is read again, or
+/// gives the error back unless reads are retried.
+pub async fn retry_read(
+    input: &str,
+    error: io::Error,
+    backoff: &mut Backoff,
+) -> Result<(), io::Error> {
+    if !RETRYING.load(Ordering::Relaxed) {
+        return Err(error);
+    }
+
+    tracing::warn!(
+        "reading input {} failed, trying again in {}s: {}",
+        input,
+        backoff.0.as_secs(),
+        error
+    );
+    report::record_error(&format!("input {}", input), &error);
+    task::sleep(backoff.0).await;
+    backoff.0 = (backoff.0 * 2).min(MAX_BACKOFF);
+
+    Ok(())
+}
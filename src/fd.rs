@@ -0,0 +1,65 @@
+use async_std::io;
+
+use std::fs::File;
+
+/// Takes over a descriptor inherited from the parent process, given as
+/// `fd:<n>`.
+#[cfg(unix)]
+pub fn open(spec: &str) -> Result<File, io::Error> {
+    use std::os::unix::io::FromRawFd;
+
+    let fd: i32 = spec
+        .strip_prefix("fd:")
+        .and_then(|fd| fd.parse().ok())
+        .filter(|fd| *fd > 2)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("expected `fd:<n>` with n > 2, got `{}`", spec),
+            )
+        })?;
+
+    // The descriptor is handed over by the parent process and owned by us
+    // from here on.
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+#[cfg(not(unix))]
+pub fn open(spec: &str) -> Result<File, io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("inherited descriptors are not supported here: `{}`", spec),
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::os::unix::io::IntoRawFd;
+
+    #[test]
+    fn standard_streams_and_malformed_specs_are_refused() {
+        for spec in ["fd:0", "fd:2", "fd:", "fd:three", "3"] {
+            let error = open(spec).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{}", spec);
+        }
+    }
+
+    #[test]
+    fn descriptors_are_taken_over() {
+        let path = std::env::temp_dir().join(format!("log-revolve-fd-{}", std::process::id()));
+        let fd = File::create(&path).unwrap().into_raw_fd();
+
+        let mut file = open(&format!("fd:{}", fd)).unwrap();
+        file.write_all(b"inherited\n").unwrap();
+        drop(file);
+
+        let mut written = String::new();
+        let mut file = File::open(&path).unwrap();
+        file.read_to_string(&mut written).unwrap();
+        assert_eq!(written, "inherited\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+use regex::Regex;
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// A pattern applying to a single channel, written `<channel>=<pattern>`.
+#[derive(Clone, Debug)]
+pub struct ChannelPattern {
+    channel: String,
+    pattern: Regex,
+}
+
+impl FromStr for ChannelPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (channel, pattern) = s
+            .split_once('=')
+            .filter(|(channel, _)| !channel.is_empty())
+            .ok_or_else(|| format!("expected `<channel>=<pattern>`, got `{}`", s))?;
+        let pattern = Regex::new(pattern).map_err(|e| e.to_string())?;
+
+        Ok(ChannelPattern {
+            channel: channel.to_string(),
+            pattern,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Patterns {
+    drop: Vec<Regex>,
+    keep: Vec<Regex>,
+}
+
+impl Patterns {
+    fn admits(&self, line: &str) -> bool {
+        (self.keep.is_empty() || self.keep.iter().any(|keep| keep.is_match(line)))
+            && !self.drop.iter().any(|drop| drop.is_match(line))
+    }
+}
+
+/// Lines discarded before they reach their files, such as health checks: a
+/// line matching a drop pattern is dropped, and so is one matching none of
+/// the keep patterns, when there are any. Patterns given for every channel
+/// and those of the line's own channel both apply.
+pub struct Filters {
+    global: Patterns,
+    channels: BTreeMap<String, Patterns>,
+    /// Lines filtered out per channel.
+    pub filtered_lines: BTreeMap<String, u64>,
+}
+
+impl Filters {
+    pub fn new(
+        drop: &[Regex],
+        keep: &[Regex],
+        channel_drop: &[ChannelPattern],
+        channel_keep: &[ChannelPattern],
+    ) -> Option<Self> {
+        if drop.is_empty() && keep.is_empty() && channel_drop.is_empty() && channel_keep.is_empty()
+        {
+            return None;
+        }
+
+        let mut channels: BTreeMap<String, Patterns> = BTreeMap::new();
+        for drop in channel_drop {
+            let patterns = channels.entry(drop.channel.clone()).or_default();
+            patterns.drop.push(drop.pattern.clone());
+        }
+        for keep in channel_keep {
+            let patterns = channels.entry(keep.channel.clone()).or_default();
+            patterns.keep.push(keep.pattern.clone());
+        }
+
+        Some(Filters {
+            global: Patterns {
+                drop: drop.to_vec(),
+                keep: keep.to_vec(),
+            },
+            channels,
+            filtered_lines: BTreeMap::new(),
+        })
+    }
+
+    /// Whether `line` of `channel`, whose settings are those of
+    /// `settings_name`, is written, counting it otherwise.
+    pub fn admits(&mut self, channel: &str, settings_name: &str, line: &str) -> bool {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let own = self
+            .channels
+            .get(channel)
+            .or_else(|| self.channels.get(settings_name));
+        if self.global.admits(line) && own.is_none_or(|own| own.admits(line)) {
+            return true;
+        }
+
+        *self.filtered_lines.entry(channel.to_string()).or_default() += 1;
+        false
+    }
+}
@@ -0,0 +1,258 @@
+use async_std::channel::{self, Receiver, Sender, TrySendError};
+use async_std::future;
+use async_std::prelude::*;
+use async_std::task::{self, JoinHandle};
+
+use chrono::{Local, SecondsFormat};
+
+use std::fmt;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::level::syslog_level;
+use crate::report;
+use crate::tls::{self, Stream};
+
+/// Wait before reconnecting to a destination that failed, doubled by every
+/// failure that follows up to `MAX_BACKOFF`.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How long lines still queued at shutdown get to reach their destination.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Facility of forwarded syslog messages, `user`.
+const SYSLOG_FACILITY: u8 = 1;
+
+/// How a channel's lines are put on the wire.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Framing {
+    /// Each line as it is written to the channel's files, newline-delimited.
+    Lines,
+    /// An RFC 5424 message per line, octet-counted as RFC 6587 has it.
+    Syslog,
+}
+
+/// Remote host a channel's lines are relayed to: `tcp://host:port` for
+/// newline-delimited lines or `syslog+tcp://host:port` for syslog, and
+/// `tls://` or `syslog+tls://` for either over TLS.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Destination {
+    framing: Framing,
+    tls: bool,
+    addr: String,
+}
+
+impl FromStr for Destination {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, addr) = s
+            .split_once("://")
+            .filter(|(_, addr)| !addr.is_empty())
+            .ok_or_else(|| format!("expected `tcp://host:port`, got `{}`", s))?;
+        let (framing, tls) = match scheme {
+            "tcp" => (Framing::Lines, false),
+            "syslog+tcp" => (Framing::Syslog, false),
+            "tls" => (Framing::Lines, true),
+            "syslog+tls" => (Framing::Syslog, true),
+            _ => return Err(format!("unknown forwarding destination: {}", s)),
+        };
+
+        Ok(Destination {
+            framing,
+            tls,
+            addr: addr.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let transport = if self.tls { "tls" } else { "tcp" };
+        match self.framing {
+            Framing::Lines => write!(f, "{}://{}", transport, self.addr),
+            Framing::Syslog => write!(f, "syslog+{}://{}", transport, self.addr),
+        }
+    }
+}
+
+/// Relays a channel's lines to a remote host from a task of its own, so a
+/// slow or unreachable destination never holds up the files. Lines wait in a
+/// bounded queue while the destination is away, newer ones dropped once it
+/// is full; a failed connection is retried with a growing backoff, the line
+/// it failed on sent again once it is back.
+pub struct Forwarder {
+    pub destination: Destination,
+    queue: Sender<String>,
+    sender: Option<JoinHandle<()>>,
+    #[cfg(feature = "metrics")]
+    forwarded: Arc<AtomicU64>,
+    pub dropped_lines: u64,
+}
+
+impl Forwarder {
+    pub fn start(
+        channel: &str,
+        destination: Destination,
+        hostname: &str,
+        buffer_lines: usize,
+    ) -> Self {
+        let (queue, queued) = channel::bounded(buffer_lines.max(1));
+        let forwarded = Arc::new(AtomicU64::new(0));
+        let sender = task::spawn(forward(
+            Message {
+                destination: destination.clone(),
+                hostname: syslog_field(hostname, 255),
+                app_name: syslog_field(channel, 48),
+            },
+            queued,
+            forwarded.clone(),
+        ));
+
+        Forwarder {
+            destination,
+            queue,
+            sender: Some(sender),
+            #[cfg(feature = "metrics")]
+            forwarded,
+            dropped_lines: 0,
+        }
+    }
+
+    pub fn send(&mut self, line: &str) {
+        match self.queue.try_send(line.to_string()) {
+            Ok(()) | Err(TrySendError::Closed(_)) => {}
+            Err(TrySendError::Full(_)) => {
+                if self.dropped_lines == 0 {
+                    tracing::warn!(
+                        "forwarding buffer of {} is full, dropping lines",
+                        self.destination.addr
+                    );
+                }
+                self.dropped_lines += 1;
+            }
+        }
+    }
+
+    /// Lines the destination took.
+    #[cfg(feature = "metrics")]
+    pub fn forwarded_lines(&self) -> u64 {
+        self.forwarded.load(Ordering::Relaxed)
+    }
+
+    /// Stops accepting lines and gives the sender a moment to relay what is
+    /// still queued.
+    pub async fn close(&mut self) {
+        self.queue.close();
+
+        if let Some(sender) = self.sender.take() {
+            if future::timeout(SHUTDOWN_GRACE, sender).await.is_err() {
+                tracing::warn!(
+                    "lines queued for {} at shutdown were lost",
+                    self.destination.addr
+                );
+            }
+        }
+    }
+}
+
+/// What every message to a destination is framed with.
+struct Message {
+    destination: Destination,
+    hostname: String,
+    app_name: String,
+}
+
+impl Message {
+    fn frame(&self, line: &str) -> Vec<u8> {
+        let text = line.trim_end_matches(['\r', '\n']);
+        match self.destination.framing {
+            Framing::Lines => format!("{}\n", text).into_bytes(),
+            Framing::Syslog => {
+                let message = format!(
+                    "<{}>1 {} {} {} {} - - {}",
+                    SYSLOG_FACILITY * 8 + syslog_level(text),
+                    Local::now().to_rfc3339_opts(SecondsFormat::Micros, false),
+                    self.hostname,
+                    self.app_name,
+                    std::process::id(),
+                    text
+                );
+                format!("{} {}", message.len(), message).into_bytes()
+            }
+        }
+    }
+}
+
+async fn forward(message: Message, queued: Receiver<String>, forwarded: Arc<AtomicU64>) {
+    let addr = message.destination.addr.as_str();
+    let mut stream: Option<Box<dyn Stream>> = None;
+    let mut backoff = FIRST_BACKOFF;
+
+    while let Ok(line) = queued.recv().await {
+        let frame = message.frame(&line);
+        loop {
+            let connection = match stream {
+                Some(ref mut connection) => Ok(connection),
+                None => tls::connect(addr, message.destination.tls)
+                    .await
+                    .map(|connection| stream.insert(connection)),
+            };
+            let result = match connection {
+                Ok(connection) => match connection.write_all(&frame).await {
+                    Ok(()) => connection.flush().await,
+                    Err(error) => Err(error),
+                },
+                Err(error) => Err(error),
+            };
+
+            match result {
+                Ok(()) => {
+                    forwarded.fetch_add(1, Ordering::Relaxed);
+                    backoff = FIRST_BACKOFF;
+                    break;
+                }
+                Err(error) => {
+                    stream = None;
+                    report::record_error("forward", &error);
+                    if queued.is_closed() {
+                        tracing::warn!("giving up on forwarding to {}: {}", addr, error);
+                        return;
+                    }
+                    tracing::warn!(
+                        "unable to forward to {}, retrying in {}s: {}",
+                        addr,
+                        backoff.as_secs(),
+                        error
+                    );
+                    task::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    // Over TLS, the destination is told the last line was the last.
+    if let Some(mut connection) = stream {
+        let _ = future::poll_fn(|cx| Pin::new(&mut connection).poll_close(cx)).await;
+    }
+}
+
+/// `value` as a syslog header field: printable ASCII, at most `max` long,
+/// `-` when nothing is left.
+fn syslog_field(value: &str, max: usize) -> String {
+    let field: String = value
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max)
+        .collect();
+    if field.is_empty() {
+        return String::from("-");
+    }
+
+    field
+}
@@ -0,0 +1,181 @@
+use chrono::DateTime;
+
+/// Stream a CRI log entry was captured from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Single line of the CRI logging format used by containerd and the kubelet:
+/// `<timestamp> <stream> <P|F> <message>`.
+#[derive(Debug)]
+pub struct CriEntry<'a> {
+    pub timestamp: &'a str,
+    pub stream: Stream,
+    pub partial: bool,
+    pub message: &'a str,
+}
+
+pub fn parse_line(line: &str) -> Option<CriEntry<'_>> {
+    let line = line.trim_end_matches(['\n', '\r']);
+    let mut parts = line.splitn(4, ' ');
+
+    let timestamp = parts.next()?;
+    DateTime::parse_from_rfc3339(timestamp).ok()?;
+
+    let stream = match parts.next()? {
+        "stdout" => Stream::Stdout,
+        "stderr" => Stream::Stderr,
+        _ => return None,
+    };
+
+    let partial = match parts.next()? {
+        "P" => true,
+        "F" => false,
+        _ => return None,
+    };
+
+    Some(CriEntry {
+        timestamp,
+        stream,
+        partial,
+        message: parts.next().unwrap_or(""),
+    })
+}
+
+/// Glues `P` (partial) entries back together until the closing `F` entry
+/// arrives. Each stream is reassembled independently since the runtime may
+/// interleave them.
+#[derive(Default)]
+pub struct CriAssembler {
+    stdout: Option<String>,
+    stderr: Option<String>,
+}
+
+impl CriAssembler {
+    /// Returns the complete message, prefixed with the timestamp of its first
+    /// fragment, and the stream its fragments came from once the final
+    /// fragment has been seen.
+    pub fn push(&mut self, entry: &CriEntry) -> Option<(Stream, String)> {
+        let pending = match entry.stream {
+            Stream::Stdout => &mut self.stdout,
+            Stream::Stderr => &mut self.stderr,
+        };
+
+        let message = pending.get_or_insert_with(|| {
+            let mut message = String::from(entry.timestamp);
+            message.push(' ');
+            message
+        });
+        message.push_str(entry.message);
+
+        if entry.partial {
+            return None;
+        }
+
+        pending.take().map(|mut message| {
+            message.push('\n');
+            (entry.stream, message)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIME: &str = "2024-01-02T03:04:05.678901234Z";
+
+    fn push(assembler: &mut CriAssembler, line: &str) -> Option<(Stream, String)> {
+        assembler.push(&parse_line(line).unwrap())
+    }
+
+    #[test]
+    fn parses_full_entries() {
+        let line = format!("{} stderr F oops: a b\n", TIME);
+        let entry = parse_line(&line).unwrap();
+
+        assert_eq!(entry.timestamp, TIME);
+        assert_eq!(entry.stream, Stream::Stderr);
+        assert!(!entry.partial);
+        assert_eq!(entry.message, "oops: a b");
+    }
+
+    #[test]
+    fn refuses_malformed_timestamps() {
+        for timestamp in [
+            "",
+            "yesterday",
+            "2024-01-02",
+            "2024-01-02 03:04:05Z",
+            "2024-13-02T03:04:05Z",
+        ] {
+            assert!(
+                parse_line(&format!("{} stdout F hello", timestamp)).is_none(),
+                "{}",
+                timestamp
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_entries_without_a_tag() {
+        assert!(parse_line(&format!("{} stdout", TIME)).is_none());
+        assert!(parse_line(&format!("{} stdout hello", TIME)).is_none());
+        assert!(parse_line(&format!("{} stdout  hello", TIME)).is_none());
+    }
+
+    #[test]
+    fn refuses_unknown_streams() {
+        assert!(parse_line(&format!("{} stdin F hello", TIME)).is_none());
+    }
+
+    #[test]
+    fn reassembles_partial_entries() {
+        let mut assembler = CriAssembler::default();
+
+        assert_eq!(
+            push(&mut assembler, &format!("{} stdout P hel", TIME)),
+            None
+        );
+        assert_eq!(
+            push(&mut assembler, "2024-01-02T03:04:06Z stdout P lo "),
+            None
+        );
+        assert_eq!(
+            push(&mut assembler, "2024-01-02T03:04:07Z stdout F world"),
+            Some((Stream::Stdout, format!("{} hello world\n", TIME)))
+        );
+        assert_eq!(
+            push(&mut assembler, &format!("{} stdout F next", TIME)),
+            Some((Stream::Stdout, format!("{} next\n", TIME)))
+        );
+    }
+
+    #[test]
+    fn reassembles_interleaved_streams_apart() {
+        let mut assembler = CriAssembler::default();
+
+        assert_eq!(
+            push(&mut assembler, &format!("{} stdout P out ", TIME)),
+            None
+        );
+        assert_eq!(
+            push(&mut assembler, &format!("{} stderr P err ", TIME)),
+            None
+        );
+        assert_eq!(
+            push(&mut assembler, &format!("{} stderr F one", TIME)),
+            Some((Stream::Stderr, format!("{} err one\n", TIME)))
+        );
+        assert_eq!(
+            push(&mut assembler, &format!("{} stdout P two ", TIME)),
+            None
+        );
+        assert_eq!(
+            push(&mut assembler, &format!("{} stdout F three", TIME)),
+            Some((Stream::Stdout, format!("{} out two three\n", TIME)))
+        );
+    }
+}
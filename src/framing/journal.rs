@@ -0,0 +1,128 @@
+use std::borrow::Cow;
+use std::convert::TryInto;
+use std::io;
+
+/// Entries above this size are rejected rather than buffered, so a corrupt or
+/// hostile field length can't make the reader allocate without bound.
+pub const MAX_ENTRY_BYTES: usize = 16 * 1024 * 1024;
+
+/// A journal entry in the export format `journalctl -o export` writes:
+/// fields of `NAME=value\n`, or `NAME\n` followed by a 64-bit little-endian
+/// length and that many bytes for values holding newlines or binary data,
+/// the entry ended by an empty line.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Entry<'a> {
+    pub fields: Vec<(&'a str, &'a [u8])>,
+}
+
+impl<'a> Entry<'a> {
+    pub fn field(&self, name: &str) -> Option<&'a [u8]> {
+        self.fields
+            .iter()
+            .find(|(field, _)| *field == name)
+            .map(|(_, value)| *value)
+    }
+
+    /// The entry's `MESSAGE`, with anything that isn't UTF-8 replaced.
+    pub fn message(&self) -> Option<Cow<'a, str>> {
+        self.field("MESSAGE").map(String::from_utf8_lossy)
+    }
+
+    /// The value of the first of `fields` the entry has, such as
+    /// `SYSLOG_IDENTIFIER` then `_SYSTEMD_UNIT`, the unit's `.service`
+    /// suffix left out.
+    pub fn channel(&self, fields: &[&str]) -> Option<&'a str> {
+        fields.iter().find_map(|name| {
+            let value = std::str::from_utf8(self.field(name)?).ok()?;
+            let value = if name.ends_with("_UNIT") {
+                value.strip_suffix(".service").unwrap_or(value)
+            } else {
+                value
+            };
+            Some(value).filter(|value| !value.is_empty())
+        })
+    }
+}
+
+/// Decodes the first entry of `buffer`, returning it along with the number
+/// of bytes it took up, or `None` while the entry is still incomplete.
+pub fn decode(buffer: &[u8]) -> Result<Option<(Entry<'_>, usize)>, io::Error> {
+    let mut fields = Vec::new();
+    let mut position = 0;
+
+    loop {
+        if position > MAX_ENTRY_BYTES {
+            return Err(invalid("entry is too large"));
+        }
+        let rest = &buffer[position..];
+        let end = match rest.iter().position(|&byte| byte == b'\n') {
+            Some(end) => end,
+            None if buffer.len() > MAX_ENTRY_BYTES => return Err(invalid("entry is too large")),
+            None => return Ok(None),
+        };
+        if end == 0 {
+            return Ok(Some((Entry { fields }, position + 1)));
+        }
+
+        let line = &rest[..end];
+        if let Some(equals) = line.iter().position(|&byte| byte == b'=') {
+            fields.push((field_name(&line[..equals])?, &line[equals + 1..]));
+            position += end + 1;
+            continue;
+        }
+
+        // A binary field: its name, then the length of its value.
+        let name = field_name(line)?;
+        let value_start = end + 1 + 8;
+        let length = match rest.get(end + 1..value_start) {
+            Some(length) => u64::from_le_bytes(length.try_into().unwrap()),
+            None => return Ok(None),
+        };
+        if length > MAX_ENTRY_BYTES as u64 {
+            return Err(invalid("entry is too large"));
+        }
+        let value_end = value_start + length as usize;
+        match rest.get(value_end) {
+            Some(b'\n') => {}
+            Some(_) => return Err(invalid("binary field isn't followed by a newline")),
+            None => return Ok(None),
+        }
+        fields.push((name, &rest[value_start..value_end]));
+        position += value_end + 1;
+    }
+}
+
+/// Writes an entry in the export format, its values binary where they need
+/// to be.
+pub fn encode(fields: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut entry = Vec::new();
+    for (name, value) in fields {
+        entry.extend_from_slice(name.as_bytes());
+        if value.contains(&b'\n') {
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value);
+        entry.push(b'\n');
+    }
+    entry.push(b'\n');
+    entry
+}
+
+fn field_name(name: &[u8]) -> Result<&str, io::Error> {
+    let valid = !name.is_empty()
+        && name
+            .iter()
+            .all(|&byte| byte.is_ascii_uppercase() || byte.is_ascii_digit() || byte == b'_');
+    if !valid {
+        return Err(invalid("invalid field name"));
+    }
+
+    Ok(std::str::from_utf8(name).unwrap())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,30 @@
+use serde_json::Value;
+
+/// One JSON object per line, naming its channel in a field of its own:
+/// `{"channel": "app", "message": "started"}`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct JsonRecord {
+    pub channel: String,
+    /// The `message` field, as a string whatever its type.
+    pub message: Option<String>,
+}
+
+/// Reads the channel of a record out of `channel_field`, which may name a
+/// nested field as a dotted path, e.g. `kubernetes.labels.app`.
+pub fn parse_line(line: &str, channel_field: &str) -> Option<JsonRecord> {
+    let value: Value = serde_json::from_str(line).ok()?;
+
+    let channel = channel_field
+        .split('.')
+        .try_fold(&value, |value, key| value.get(key))?
+        .as_str()?;
+    let message = value.get("message").map(|message| match message {
+        Value::String(message) => message.clone(),
+        other => other.to_string(),
+    });
+
+    Some(JsonRecord {
+        channel: channel.to_string(),
+        message,
+    })
+}
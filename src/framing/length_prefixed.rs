@@ -0,0 +1,56 @@
+use std::io;
+
+/// Records above this size are rejected rather than buffered, so a corrupt or
+/// hostile length can't make the reader allocate without bound.
+pub const MAX_RECORD_BYTES: usize = 16 * 1024 * 1024;
+
+/// A 4-byte big-endian length followed by that many bytes of
+/// `<channel>\0<payload>`, for payloads that may hold newlines or binary data.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Record<'a> {
+    pub channel: &'a str,
+    pub payload: &'a [u8],
+}
+
+/// Decodes the first record of `buffer`, returning it along with the number
+/// of bytes it took up, or `None` while the record is still incomplete.
+pub fn decode(buffer: &[u8]) -> Result<Option<(Record<'_>, usize)>, io::Error> {
+    let length = match buffer.get(..4) {
+        Some(prefix) => u32::from_be_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize,
+        None => return Ok(None),
+    };
+    if length > MAX_RECORD_BYTES {
+        return Err(invalid("record is too large"));
+    }
+
+    let body = match buffer.get(4..4 + length) {
+        Some(body) => body,
+        None => return Ok(None),
+    };
+    let separator = body
+        .iter()
+        .position(|&byte| byte == 0)
+        .ok_or_else(|| invalid("record has no channel"))?;
+    let channel =
+        std::str::from_utf8(&body[..separator]).map_err(|_| invalid("channel is not UTF-8"))?;
+
+    let record = Record {
+        channel,
+        payload: &body[separator + 1..],
+    };
+    Ok(Some((record, 4 + length)))
+}
+
+pub fn encode(channel: &str, payload: &[u8]) -> Vec<u8> {
+    let length = channel.len() + 1 + payload.len();
+    let mut frame = Vec::with_capacity(4 + length);
+    frame.extend_from_slice(&(length as u32).to_be_bytes());
+    frame.extend_from_slice(channel.as_bytes());
+    frame.push(0);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,77 @@
+/// What a line of the two-line protocol turned out to be.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Frame<'a> {
+    /// A channel line; its message is on the next line.
+    Channel,
+    /// The message following a known channel line.
+    Message { channel: &'a str, message: &'a str },
+    /// The message following a channel line naming no known channel.
+    Rejected { channel: &'a str, message: &'a str },
+    /// An earlier line that named no known channel and was followed by a
+    /// known channel rather than a message, so was never a channel line.
+    Unframed(String),
+}
+
+#[derive(Clone, Copy)]
+enum Pending {
+    Accepted,
+    Rejected,
+}
+
+/// A channel name on one line, followed by the message on the next.
+#[derive(Default)]
+pub struct PairedDecoder {
+    pending: Option<Pending>,
+    /// The channel line awaiting its message, its buffer reused by the next
+    /// so a pair costs no allocation.
+    channel: String,
+}
+
+impl PairedDecoder {
+    pub fn push<'a, F>(&'a mut self, line: &'a str, is_channel: F) -> Frame<'a>
+    where
+        F: Fn(&str) -> bool,
+    {
+        match self.pending.take() {
+            None => {
+                let channel = line.trim_end();
+                self.channel.clear();
+                self.pending = Some(if is_channel(channel) {
+                    self.channel.push_str(channel);
+                    Pending::Accepted
+                } else {
+                    self.channel.push_str(line);
+                    Pending::Rejected
+                });
+                Frame::Channel
+            }
+            Some(Pending::Accepted) => Frame::Message {
+                channel: &self.channel,
+                message: line,
+            },
+            // A known channel always starts a new pair, so a stray line only
+            // ever costs itself.
+            Some(Pending::Rejected) => {
+                let channel = line.trim_end();
+                if is_channel(channel) {
+                    self.pending = Some(Pending::Accepted);
+                    Frame::Unframed(std::mem::replace(&mut self.channel, channel.to_string()))
+                } else {
+                    Frame::Rejected {
+                        channel: self.channel.trim_end(),
+                        message: line,
+                    }
+                }
+            }
+        }
+    }
+
+    /// A line naming no known channel, left waiting for a message when the
+    /// input ended.
+    pub fn finish(&mut self) -> Option<String> {
+        match self.pending.take() {
+            Some(Pending::Rejected) => Some(std::mem::take(&mut self.channel)),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,16 @@
+//! Every parser here takes whatever bytes a producer sent and never panics on
+//! them: malformed input is reported as such and left to the caller to route
+//! to the inapt file.
+
+#[cfg(feature = "cri")]
+pub mod cri;
+#[cfg(feature = "journald")]
+pub mod journal;
+#[cfg(feature = "serde_json")]
+pub mod json;
+pub mod length_prefixed;
+pub mod lines;
+pub mod multiline;
+pub mod prefixed;
+pub mod producers;
+pub mod syslog;
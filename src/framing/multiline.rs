@@ -0,0 +1,100 @@
+/// Messages above this size are cut short rather than buffered, so a
+/// producer that never sends the end of a message can't make the router
+/// hold it without bound.
+pub const MAX_MESSAGE_BYTES: usize = 1024 * 1024;
+
+/// How the lines of a message spanning several, such as a stack trace, are
+/// told apart from the next message.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Multiline {
+    /// Lines starting with a space or a tab continue the message before
+    /// them, the way the frames of a stack trace do.
+    Indented,
+    /// A message runs until a line holding nothing but the delimiter, which
+    /// is dropped; an empty delimiter ends messages with a blank line.
+    Delimited(String),
+}
+
+/// A message with all of its lines, line ends included.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Message {
+    pub channel: String,
+    pub text: String,
+}
+
+/// What a line turned out to be while a message may be held.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Continuation {
+    /// The line was added to the held message.
+    Appended,
+    /// The held message is complete and the line taken care of: it was the
+    /// delimiter, or it went on past `MAX_MESSAGE_BYTES` and the line opens
+    /// a message of its own on the same channel.
+    Ended(Message),
+    /// The line starts something else, and the held message, if any, is
+    /// complete.
+    Other(Option<Message>),
+}
+
+/// Gathers the lines of each message before it is written, so a message
+/// spanning several lines lands in its file in one piece.
+pub struct MessageAssembler {
+    multiline: Multiline,
+    held: Option<Message>,
+}
+
+impl MessageAssembler {
+    pub fn new(multiline: Multiline) -> Self {
+        MessageAssembler {
+            multiline,
+            held: None,
+        }
+    }
+
+    /// Holds the first line of a message of `channel` until the lines
+    /// following it tell where it ends.
+    pub fn start(&mut self, channel: &str, line: &str) -> Option<Message> {
+        let previous = self.held.take();
+        self.held = Some(Message {
+            channel: channel.to_string(),
+            text: line.to_string(),
+        });
+
+        previous
+    }
+
+    /// Offers `line` to the held message before it is decoded on its own.
+    pub fn push(&mut self, line: &str) -> Continuation {
+        let held = match self.held {
+            Some(ref mut held) => held,
+            None => return Continuation::Other(None),
+        };
+
+        let continues = match self.multiline {
+            Multiline::Indented => line.starts_with(' ') || line.starts_with('\t'),
+            Multiline::Delimited(ref delimiter) => {
+                if line.trim_end_matches(['\r', '\n']) == delimiter {
+                    return Continuation::Ended(self.held.take().unwrap());
+                }
+                true
+            }
+        };
+        if !continues {
+            return Continuation::Other(self.held.take());
+        }
+
+        if held.text.len() + line.len() > MAX_MESSAGE_BYTES {
+            let channel = held.channel.clone();
+            let message = self.start(&channel, line);
+            return Continuation::Ended(message.unwrap());
+        }
+        held.text.push_str(line);
+
+        Continuation::Appended
+    }
+
+    /// The message held when the input ended, which nothing else will end.
+    pub fn finish(&mut self) -> Option<Message> {
+        self.held.take()
+    }
+}
@@ -0,0 +1,20 @@
+/// A line naming its channel ahead of a delimiter, `<channel>|<message>`, so
+/// a channel and its message can't be torn apart the way a channel line and
+/// a message line can.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PrefixedLine<'a> {
+    pub channel: &'a str,
+    /// The rest of the line, line end included.
+    pub message: &'a str,
+}
+
+/// Splits `line` at the first `delimiter`, or `None` when there is no channel
+/// before it.
+pub fn parse_line<'a>(line: &'a str, delimiter: &str) -> Option<PrefixedLine<'a>> {
+    let (channel, message) = line.split_once(delimiter)?;
+    if channel.is_empty() {
+        return None;
+    }
+
+    Some(PrefixedLine { channel, message })
+}
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+/// Producers remembered at most, so ids that are never seen again, such as
+/// the pids of processes long gone, can't grow the state without bound.
+pub const MAX_PRODUCERS: usize = 4096;
+
+/// A line naming the producer that sent it ahead of its channel,
+/// `<producer>:<channel>:<message>`, so producers sharing a pipe can't take
+/// each other's channels. An empty channel, `<producer>::<message>`, is the
+/// one that producer named last.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ProducerLine<'a> {
+    pub producer: &'a str,
+    /// `None` for the producer's last channel.
+    pub channel: Option<&'a str>,
+    /// The rest of the line, line end included.
+    pub message: &'a str,
+}
+
+/// Splits `line` at its first two colons, or `None` when it names no
+/// producer or has no channel field.
+pub fn parse_line(line: &str) -> Option<ProducerLine<'_>> {
+    let (producer, rest) = line.split_once(':')?;
+    let (channel, message) = rest.split_once(':')?;
+    if producer.is_empty() {
+        return None;
+    }
+
+    Some(ProducerLine {
+        producer,
+        channel: Some(channel).filter(|channel| !channel.is_empty()),
+        message,
+    })
+}
+
+/// The channel each producer named last, kept apart so one producer's lines
+/// never land in another's channel however their writes interleave.
+#[derive(Default)]
+pub struct ProducerDecoder {
+    channels: HashMap<String, String>,
+}
+
+impl ProducerDecoder {
+    /// The channel and message of `line`, or `None` when it is malformed or
+    /// continues a producer that never named a channel.
+    pub fn push<'a>(&'a mut self, line: &'a str) -> Option<(&'a str, &'a str)> {
+        let parsed = parse_line(line)?;
+        match parsed.channel {
+            Some(channel) => {
+                match self.channels.get_mut(parsed.producer) {
+                    // The buffer reused, so a known producer costs no
+                    // allocation.
+                    Some(last) => {
+                        last.clear();
+                        last.push_str(channel);
+                    }
+                    None => {
+                        if self.channels.len() >= MAX_PRODUCERS {
+                            self.channels.clear();
+                        }
+                        self.channels
+                            .insert(parsed.producer.to_string(), channel.to_string());
+                    }
+                }
+                Some((channel, parsed.message))
+            }
+            None => self
+                .channels
+                .get(parsed.producer)
+                .map(|channel| (channel.as_str(), parsed.message)),
+        }
+    }
+
+    /// How many producers have a channel remembered.
+    pub fn producers(&self) -> usize {
+        self.channels.len()
+    }
+}
@@ -0,0 +1,161 @@
+/// A syslog message in either the RFC 5424 or the older BSD (RFC 3164)
+/// format. Header fields the sender left out, or sent as the `-` nil value,
+/// are `None`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SyslogMessage<'a> {
+    pub facility: u8,
+    pub severity: u8,
+    pub timestamp: Option<&'a str>,
+    pub hostname: Option<&'a str>,
+    pub app_name: Option<&'a str>,
+    pub message: &'a str,
+}
+
+/// Length of a BSD timestamp such as `Jun  1 13:00:00`.
+const BSD_TIMESTAMP_LENGTH: usize = 15;
+/// Longest tag accepted in a BSD message before it is taken as message text.
+const BSD_MAX_TAG_LENGTH: usize = 48;
+
+/// Parses a datagram or line; `None` if it doesn't even start with a valid
+/// `<PRI>`. Anything after that which doesn't fit either header format is
+/// kept whole as the message.
+pub fn parse(input: &str) -> Option<SyslogMessage<'_>> {
+    let input = input.trim_end_matches(['\n', '\r', '\0']);
+    let (priority, rest) = parse_priority(input)?;
+
+    let mut message = parse_rfc5424(rest).unwrap_or_else(|| parse_bsd(rest));
+    message.facility = priority / 8;
+    message.severity = priority % 8;
+    Some(message)
+}
+
+fn parse_priority(input: &str) -> Option<(u8, &str)> {
+    let (digits, rest) = input.strip_prefix('<')?.split_once('>')?;
+    if digits.is_empty() || digits.len() > 3 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let priority: u8 = digits.parse().ok()?;
+    if priority > 191 {
+        return None;
+    }
+
+    Some((priority, rest))
+}
+
+/// `1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA [MSG]`
+fn parse_rfc5424(rest: &str) -> Option<SyslogMessage<'_>> {
+    let mut fields = rest.strip_prefix("1 ")?.splitn(6, ' ');
+
+    let timestamp = nil(fields.next()?);
+    let hostname = nil(fields.next()?);
+    let app_name = nil(fields.next()?);
+    let _process_id = fields.next()?;
+    let _message_id = fields.next()?;
+    let message = skip_structured_data(fields.next().unwrap_or(""))?;
+
+    Some(SyslogMessage {
+        facility: 0,
+        severity: 0,
+        timestamp,
+        hostname,
+        app_name,
+        message: message.trim_start_matches('\u{feff}'),
+    })
+}
+
+fn nil(field: &str) -> Option<&str> {
+    match field {
+        "-" | "" => None,
+        field => Some(field),
+    }
+}
+
+/// Skips `-` or a run of `[id key="value" ...]` elements, returning what
+/// follows. Quoted values may contain escaped quotes and brackets.
+fn skip_structured_data(input: &str) -> Option<&str> {
+    if let Some(rest) = input.strip_prefix('-') {
+        return Some(rest.strip_prefix(' ').unwrap_or(rest));
+    }
+
+    let mut in_element = false;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (index, c) in input.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+
+        match c {
+            '\\' if in_quotes => escaped = true,
+            '"' if in_element => in_quotes = !in_quotes,
+            '[' if !in_element => in_element = true,
+            ']' if in_element && !in_quotes => in_element = false,
+            ' ' if !in_element && index > 0 => return Some(&input[index + 1..]),
+            _ if !in_element => return None,
+            _ => {}
+        }
+    }
+
+    if in_element {
+        None
+    } else {
+        Some("")
+    }
+}
+
+/// `Mmm dd hh:mm:ss HOSTNAME TAG: MSG`, as far as the sender followed it.
+fn parse_bsd(rest: &str) -> SyslogMessage<'_> {
+    let mut message = SyslogMessage {
+        facility: 0,
+        severity: 0,
+        timestamp: None,
+        hostname: None,
+        app_name: None,
+        message: rest,
+    };
+
+    let timestamp = match rest.get(..BSD_TIMESTAMP_LENGTH) {
+        Some(timestamp) if is_bsd_timestamp(timestamp) => timestamp,
+        _ => return message,
+    };
+    let after_timestamp = match rest.get(BSD_TIMESTAMP_LENGTH..) {
+        Some(after) => after.strip_prefix(' ').unwrap_or(after),
+        None => return message,
+    };
+    message.timestamp = Some(timestamp);
+
+    let (hostname, content) = match after_timestamp.split_once(' ') {
+        Some(split) => split,
+        None => {
+            message.message = after_timestamp;
+            return message;
+        }
+    };
+    message.hostname = Some(hostname);
+    message.message = content;
+
+    if let Some((tag, text)) = content.split_once(": ") {
+        if !tag.is_empty() && tag.len() <= BSD_MAX_TAG_LENGTH && !tag.contains(' ') {
+            let app_name = tag.split('[').next().unwrap_or(tag);
+            message.app_name = nil(app_name);
+            message.message = text;
+        }
+    }
+
+    message
+}
+
+fn is_bsd_timestamp(timestamp: &str) -> bool {
+    let bytes = timestamp.as_bytes();
+    bytes.len() == BSD_TIMESTAMP_LENGTH
+        && bytes[..3].iter().all(|b| b.is_ascii_alphabetic())
+        && bytes[3] == b' '
+        && bytes[6] == b' '
+        && bytes[9] == b':'
+        && bytes[12] == b':'
+        && [4, 5, 7, 8, 10, 11, 13, 14]
+            .iter()
+            .all(|&i| bytes[i].is_ascii_digit() || (i == 4 && bytes[i] == b' '))
+}
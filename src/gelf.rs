@@ -0,0 +1,512 @@
+use async_std::channel::{self, Receiver, Sender, TryRecvError, TrySendError};
+use async_std::future;
+use async_std::io;
+use async_std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use async_std::prelude::*;
+use async_std::sync::{Arc, Mutex};
+use async_std::task::{self, JoinHandle};
+
+use chrono::Local;
+
+use serde_json::json;
+
+use crate::level::syslog_level;
+use crate::report;
+use crate::spill::Spill;
+
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+const UDP_CHUNK_MAGIC: [u8; 2] = [0x1e, 0x0f];
+const UDP_MAX_DATAGRAM: usize = 8192;
+const UDP_CHUNK_HEADER: usize = 12;
+const UDP_MAX_CHUNKS: usize = 128;
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+enum Transport {
+    Udp(UdpSocket),
+    /// Connected on the first message and again after a failed one, but
+    /// not before `RETRY_INTERVAL` has passed since a connection failed.
+    Tcp {
+        addr: String,
+        stream: Option<TcpStream>,
+        retry_at: Option<Instant>,
+    },
+}
+
+/// Ships channel lines to Graylog as GELF 1.1 messages, over chunked UDP or
+/// null-delimited TCP. Messages are sent from a task of their own through a
+/// queue of `queue_capacity` messages, so the local file output is never held
+/// up by Graylog. Without a spill, messages that don't fit in the queue or
+/// fail to send are dropped; with one they are written to disk and shipped
+/// once Graylog takes them again.
+pub struct GelfSink {
+    host: String,
+    channels: BTreeSet<String>,
+    queue: Sender<Vec<u8>>,
+    spill: Option<Arc<Mutex<Spill>>>,
+    forwarder: Option<JoinHandle<()>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl GelfSink {
+    /// `url` is either `udp://host:port` or `tcp://host:port`. An empty
+    /// channel set ships every accepted channel. A TCP server that isn't up
+    /// yet doesn't keep the router from starting: messages are dropped or
+    /// spilled until it is.
+    pub async fn connect(
+        url: &str,
+        host: String,
+        channels: BTreeSet<String>,
+        queue_capacity: usize,
+        spill: Option<Spill>,
+    ) -> Result<Self, io::Error> {
+        let transport = if let Some(addr) = url.strip_prefix("udp://") {
+            let remote = addr
+                .to_socket_addrs()
+                .await?
+                .next()
+                .ok_or_else(|| io::Error::other("unable to resolve GELF address"))?;
+            let local = if remote.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            };
+            let socket = UdpSocket::bind(local).await?;
+            socket.connect(remote).await?;
+            Transport::Udp(socket)
+        } else if let Some(addr) = url.strip_prefix("tcp://") {
+            Transport::Tcp {
+                addr: addr.to_string(),
+                stream: None,
+                retry_at: None,
+            }
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("GELF address must start with udp:// or tcp://: {}", url),
+            ));
+        };
+
+        let (queue, queued) = channel::bounded(queue_capacity.max(1));
+        let spill = spill.map(|spill| Arc::new(Mutex::new(spill)));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let forwarder = task::spawn(forward(transport, queued, spill.clone(), dropped.clone()));
+
+        Ok(GelfSink {
+            host,
+            channels,
+            queue,
+            spill,
+            forwarder: Some(forwarder),
+            dropped,
+        })
+    }
+
+    pub fn accepts(&self, channel: &str) -> bool {
+        self.channels.is_empty() || self.channels.contains(channel)
+    }
+
+    pub async fn send(&mut self, channel: &str, message: &str) {
+        let payload = self.encode(channel, message);
+
+        let spill = match self.spill {
+            Some(ref spill) => spill,
+            None => {
+                if let Err(TrySendError::Full(_)) = self.queue.try_send(payload) {
+                    tracing::warn!("GELF queue is full, dropping message");
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                return;
+            }
+        };
+
+        // Once anything is spilled, newer messages queue up behind it on disk.
+        let mut spill = spill.lock().await;
+        let payload = if spill.is_empty() {
+            match self.queue.try_send(payload) {
+                Ok(()) => return,
+                Err(error) => error.into_inner(),
+            }
+        } else {
+            payload
+        };
+        if let Err(error) = spill.push(&payload).await {
+            tracing::warn!("unable to spill GELF message: {}", error);
+            report::record_error("gelf", &error);
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Messages lost to a full queue or a failed send, not counting what the
+    /// spill discarded.
+    #[cfg(any(feature = "metrics", feature = "report"))]
+    pub fn dropped_lines(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "report")]
+    pub async fn spill_dropped_bytes(&self) -> u64 {
+        match self.spill {
+            Some(ref spill) => spill.lock().await.dropped_bytes(),
+            None => 0,
+        }
+    }
+
+    /// Stops accepting messages and gives the forwarder a moment to ship, or
+    /// spill, what is still queued.
+    pub async fn close(&mut self) {
+        self.queue.close();
+
+        if let Some(forwarder) = self.forwarder.take() {
+            if future::timeout(SHUTDOWN_GRACE, forwarder).await.is_err() {
+                tracing::warn!("GELF messages still queued at shutdown were lost");
+            }
+        }
+    }
+
+    fn encode(&self, channel: &str, message: &str) -> Vec<u8> {
+        let message = message.trim_end();
+        let now = Local::now();
+
+        json!({
+            "version": "1.1",
+            "host": self.host,
+            "short_message": message,
+            "timestamp": now.timestamp_millis() as f64 / 1000.0,
+            "level": syslog_level(message),
+            "_channel": channel,
+        })
+        .to_string()
+        .into_bytes()
+    }
+}
+
+/// Ships queued messages, then whatever was spilled once the queue runs dry.
+/// The queue always holds messages older than the spill, since nothing is
+/// queued while the spill has records.
+async fn forward(
+    mut transport: Transport,
+    queued: Receiver<Vec<u8>>,
+    spill: Option<Arc<Mutex<Spill>>>,
+    dropped: Arc<AtomicU64>,
+) {
+    loop {
+        let payload = match queued.try_recv() {
+            Ok(payload) => payload,
+            Err(TryRecvError::Closed) => return,
+            Err(TryRecvError::Empty) => {
+                if let Some(ref spill) = spill {
+                    let spilled = spill.lock().await.peek().await;
+                    match spilled {
+                        Ok(Some(payload)) => {
+                            if transport.send(&payload).await.is_ok() {
+                                spill.lock().await.advance(&payload);
+                            } else if !queued.is_closed() {
+                                task::sleep(RETRY_INTERVAL).await;
+                            } else {
+                                return;
+                            }
+                            continue;
+                        }
+                        Ok(None) => {}
+                        Err(error) => {
+                            tracing::warn!("unable to read GELF spill: {}", error);
+                            task::sleep(RETRY_INTERVAL).await;
+                            continue;
+                        }
+                    }
+                }
+
+                match queued.recv().await {
+                    Ok(payload) => payload,
+                    Err(_) => return,
+                }
+            }
+        };
+
+        if let Err(error) = transport.send(&payload).await {
+            report::record_error("gelf", &error);
+            match spill {
+                Some(ref spill) => {
+                    tracing::warn!("unable to ship GELF message, spilling: {}", error);
+                    if let Err(error) = spill.lock().await.push(&payload).await {
+                        tracing::warn!("unable to spill GELF message: {}", error);
+                        dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                None => {
+                    tracing::warn!("unable to ship GELF message: {}", error);
+                    dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+impl Transport {
+    async fn send(&mut self, payload: &[u8]) -> Result<(), io::Error> {
+        match self {
+            Transport::Udp(socket) => {
+                for datagram in udp_datagrams(payload, next_message_id())? {
+                    socket.send(&datagram).await?;
+                }
+
+                Ok(())
+            }
+            Transport::Tcp {
+                addr,
+                stream,
+                retry_at,
+            } => {
+                let connection = match stream {
+                    Some(connection) => connection,
+                    None => {
+                        if let Some(at) = *retry_at {
+                            if Instant::now() < at {
+                                return Err(io::Error::new(
+                                    io::ErrorKind::NotConnected,
+                                    "GELF server is unreachable, waiting to reconnect",
+                                ));
+                            }
+                        }
+                        match TcpStream::connect(addr.as_str()).await {
+                            Ok(connection) => {
+                                *retry_at = None;
+                                stream.insert(connection)
+                            }
+                            Err(error) => {
+                                *retry_at = Some(Instant::now() + RETRY_INTERVAL);
+                                return Err(error);
+                            }
+                        }
+                    }
+                };
+
+                let result = connection.write_all(&tcp_frame(payload)).await;
+
+                // Reconnect on the next message rather than retrying this one.
+                if result.is_err() {
+                    *stream = None;
+                }
+                result
+            }
+        }
+    }
+}
+
+/// `payload` as one datagram, or as chunks of at most `UDP_MAX_DATAGRAM`
+/// bytes headed by the chunk magic, `message_id`, sequence number and count.
+fn udp_datagrams(payload: &[u8], message_id: [u8; 8]) -> Result<Vec<Vec<u8>>, io::Error> {
+    if payload.len() <= UDP_MAX_DATAGRAM {
+        return Ok(vec![payload.to_vec()]);
+    }
+
+    let chunks: Vec<&[u8]> = payload
+        .chunks(UDP_MAX_DATAGRAM - UDP_CHUNK_HEADER)
+        .collect();
+    if chunks.len() > UDP_MAX_CHUNKS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "message is too large for chunked GELF",
+        ));
+    }
+
+    let datagrams = chunks
+        .iter()
+        .enumerate()
+        .map(|(sequence, chunk)| {
+            let mut datagram = Vec::with_capacity(UDP_CHUNK_HEADER + chunk.len());
+            datagram.extend_from_slice(&UDP_CHUNK_MAGIC);
+            datagram.extend_from_slice(&message_id);
+            datagram.push(sequence as u8);
+            datagram.push(chunks.len() as u8);
+            datagram.extend_from_slice(chunk);
+            datagram
+        })
+        .collect();
+
+    Ok(datagrams)
+}
+
+/// `payload` null-terminated, as GELF over TCP frames messages.
+fn tcp_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 1);
+    frame.extend_from_slice(payload);
+    frame.push(0);
+    frame
+}
+
+fn next_message_id() -> [u8; 8] {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let nanos = Local::now().timestamp_nanos_opt().unwrap_or_default() as u64;
+    let id = nanos ^ COUNTER.fetch_add(1, Ordering::Relaxed).rotate_left(32);
+    id.to_be_bytes()
+}
+
+#[cfg(unix)]
+pub fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    let result =
+        unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+    if result != 0 {
+        return String::from("localhost");
+    }
+
+    let length = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..length]).into_owned()
+}
+
+/// Windows keeps the computer's name in the environment.
+#[cfg(not(unix))]
+pub fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| String::from("localhost"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::net::TcpListener;
+
+    const MESSAGE_ID: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    #[test]
+    fn small_messages_go_in_one_datagram() {
+        let datagrams = udp_datagrams(b"{}", MESSAGE_ID).unwrap();
+
+        assert_eq!(datagrams, vec![b"{}".to_vec()]);
+    }
+
+    #[test]
+    fn large_messages_are_chunked_with_a_header() {
+        let payload = vec![b'x'; UDP_MAX_DATAGRAM * 2];
+        let datagrams = udp_datagrams(&payload, MESSAGE_ID).unwrap();
+
+        assert_eq!(datagrams.len(), 3);
+        for (sequence, datagram) in datagrams.iter().enumerate() {
+            assert!(datagram.len() <= UDP_MAX_DATAGRAM);
+            assert_eq!(datagram[..2], UDP_CHUNK_MAGIC);
+            assert_eq!(datagram[2..10], MESSAGE_ID);
+            assert_eq!(datagram[10], sequence as u8);
+            assert_eq!(datagram[11], 3);
+        }
+        let body: Vec<u8> = datagrams
+            .iter()
+            .flat_map(|datagram| datagram[UDP_CHUNK_HEADER..].iter().copied())
+            .collect();
+        assert_eq!(body, payload);
+    }
+
+    #[test]
+    fn messages_fill_at_most_128_chunks() {
+        let largest = (UDP_MAX_DATAGRAM - UDP_CHUNK_HEADER) * UDP_MAX_CHUNKS;
+
+        let datagrams = udp_datagrams(&vec![b'x'; largest], MESSAGE_ID).unwrap();
+        assert_eq!(datagrams.len(), UDP_MAX_CHUNKS);
+        assert_eq!(datagrams[UDP_MAX_CHUNKS - 1][10], 127);
+        assert_eq!(datagrams[UDP_MAX_CHUNKS - 1][11], 128);
+
+        let error = udp_datagrams(&vec![b'x'; largest + 1], MESSAGE_ID).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn oversized_messages_are_dropped_and_the_rest_shipped() {
+        task::block_on(async {
+            let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("udp://{}", server.local_addr().unwrap());
+            let mut sink = GelfSink::connect(&url, String::from("host"), BTreeSet::new(), 4, None)
+                .await
+                .unwrap();
+
+            let oversized = "x".repeat(UDP_MAX_DATAGRAM * (UDP_MAX_CHUNKS + 1));
+            sink.send("web", &oversized).await;
+            sink.send("web", "hello").await;
+            sink.close().await;
+
+            let mut datagram = [0; UDP_MAX_DATAGRAM];
+            let length = server.recv(&mut datagram).await.unwrap();
+            let message: serde_json::Value = serde_json::from_slice(&datagram[..length]).unwrap();
+            assert_eq!(message["short_message"], "hello");
+            assert_eq!(message["_channel"], "web");
+            assert_eq!(sink.dropped.load(Ordering::Relaxed), 1);
+        });
+    }
+
+    #[test]
+    fn tcp_messages_are_null_delimited() {
+        task::block_on(async {
+            let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let mut transport = Transport::Tcp {
+                addr: server.local_addr().unwrap().to_string(),
+                stream: None,
+                retry_at: None,
+            };
+
+            transport.send(b"{\"a\":1}").await.unwrap();
+            transport.send(b"{\"b\":2}").await.unwrap();
+            drop(transport);
+
+            let (mut connection, _) = server.accept().await.unwrap();
+            let mut received = Vec::new();
+            connection.read_to_end(&mut received).await.unwrap();
+            assert_eq!(received, b"{\"a\":1}\0{\"b\":2}\0");
+        });
+    }
+
+    #[test]
+    fn tcp_connects_once_the_server_is_up() {
+        task::block_on(async {
+            let addr = TcpListener::bind("127.0.0.1:0")
+                .await
+                .unwrap()
+                .local_addr()
+                .unwrap();
+            let url = format!("tcp://{}", addr);
+            let mut sink = GelfSink::connect(&url, String::from("host"), BTreeSet::new(), 4, None)
+                .await
+                .unwrap();
+
+            let server = TcpListener::bind(addr).await.unwrap();
+            sink.send("web", "hello").await;
+            sink.close().await;
+
+            let (mut connection, _) = server.accept().await.unwrap();
+            let mut received = Vec::new();
+            connection.read_to_end(&mut received).await.unwrap();
+            assert_eq!(received.pop(), Some(0));
+            let message: serde_json::Value = serde_json::from_slice(&received).unwrap();
+            assert_eq!(message["short_message"], "hello");
+        });
+    }
+
+    #[test]
+    fn tcp_waits_before_reconnecting_to_an_unreachable_server() {
+        task::block_on(async {
+            let addr = TcpListener::bind("127.0.0.1:0")
+                .await
+                .unwrap()
+                .local_addr()
+                .unwrap();
+            let mut transport = Transport::Tcp {
+                addr: addr.to_string(),
+                stream: None,
+                retry_at: None,
+            };
+
+            assert!(transport.send(b"{}").await.is_err());
+            let _server = TcpListener::bind(addr).await.unwrap();
+            let error = transport.send(b"{}").await.unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::NotConnected);
+
+            if let Transport::Tcp { retry_at, .. } = &mut transport {
+                *retry_at = Some(Instant::now());
+            }
+            transport.send(b"{}").await.unwrap();
+        });
+    }
+}
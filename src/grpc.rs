@@ -0,0 +1,109 @@
+//! The wire of the gRPC ingestion service: the protobuf messages of the
+//! `Ingest` call and the length-prefixed framing gRPC carries them in over
+//! HTTP/2, which is left to `h2`. Free of I/O: bytes go in and come out.
+//!
+//! ```proto
+//! syntax = "proto3";
+//! package logrevolve;
+//!
+//! service Ingest {
+//!   // Lines streamed by the client, acknowledged as they are written. The
+//!   // acks are a stream too, one a batch, which a client-streaming call
+//!   // answered once at its end couldn't carry.
+//!   rpc Ingest(stream Line) returns (stream Ack);
+//! }
+//!
+//! message Line {
+//!   string channel = 1;
+//!   bytes payload = 2;
+//! }
+//!
+//! message Ack {
+//!   // Lines of the call written so far.
+//!   uint64 written = 1;
+//! }
+//! ```
+
+/// Path of the `Ingest` call.
+pub const INGEST_PATH: &str = "/logrevolve.Ingest/Ingest";
+
+/// Largest message taken, gRPC's own default.
+pub const MAX_MESSAGE_BYTES: usize = 4 * 1024 * 1024;
+
+/// gRPC status codes the service answers with.
+pub const STATUS_OK: u32 = 0;
+pub const STATUS_INVALID_ARGUMENT: u32 = 3;
+pub const STATUS_UNIMPLEMENTED: u32 = 12;
+pub const STATUS_UNAVAILABLE: u32 = 14;
+
+/// Gathers the messages of a call from the bytes of its `DATA` frames, each
+/// behind a compression flag and a length.
+#[derive(Default)]
+pub struct Messages {
+    buffer: Vec<u8>,
+}
+
+impl Messages {
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// The next whole message, if there is one yet.
+    pub fn pop(&mut self) -> Result<Option<Vec<u8>>, String> {
+        if self.buffer.len() < 5 {
+            return Ok(None);
+        }
+        if self.buffer[0] != 0 {
+            return Err(String::from("compressed messages are not supported"));
+        }
+        let length = u32::from_be_bytes([
+            self.buffer[1],
+            self.buffer[2],
+            self.buffer[3],
+            self.buffer[4],
+        ]) as usize;
+        if length > MAX_MESSAGE_BYTES {
+            return Err(format!(
+                "message of {} bytes is larger than {}",
+                length, MAX_MESSAGE_BYTES
+            ));
+        }
+        if self.buffer.len() < 5 + length {
+            return Ok(None);
+        }
+
+        let message = self.buffer[5..5 + length].to_vec();
+        self.buffer.drain(..5 + length);
+        Ok(Some(message))
+    }
+
+    /// Whether a message was left half sent.
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+}
+
+/// `message` framed for a `DATA` frame, uncompressed.
+pub fn message(message: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(5 + message.len());
+    framed.push(0);
+    framed.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    framed.extend_from_slice(message);
+    framed
+}
+
+/// A line sent to a channel.
+#[derive(Clone, PartialEq, Eq, prost::Message)]
+pub struct Line {
+    #[prost(string, tag = "1")]
+    pub channel: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub payload: Vec<u8>,
+}
+
+/// How many lines of a call are written.
+#[derive(Clone, Copy, PartialEq, Eq, prost::Message)]
+pub struct Ack {
+    #[prost(uint64, tag = "1")]
+    pub written: u64,
+}
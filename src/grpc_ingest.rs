@@ -0,0 +1,226 @@
+use async_std::io;
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use h2::server::SendResponse;
+use h2::{RecvStream, SendStream};
+use http::{HeaderMap, HeaderValue, Request, Response};
+use prost::Message;
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+use log_revolve_rs::grpc::{self, Ack, Line, Messages};
+
+use crate::{input, report, FileWriter};
+
+/// Pause after a failed accept, as for `--listen`.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Calls a connection may have going at once, as each may buffer up to
+/// `grpc::MAX_MESSAGE_BYTES` of a message. More are refused by `h2` with
+/// `REFUSED_STREAM`.
+const MAX_CALLS: u32 = 16;
+
+/// Largest header list taken, so a client can't have the connection buffer
+/// headers without end. `h2` refuses a call over it, and ends a connection
+/// whose header block runs on past it with `ENHANCE_YOUR_CALM`.
+const MAX_HEADER_LIST_BYTES: u32 = 16 * 1024;
+
+/// Serves the `Ingest` call of `log_revolve_rs::grpc` over cleartext HTTP/2:
+/// clients stream lines, each naming its channel, and are sent how many of
+/// them are written after every batch, a batch being what arrived in one
+/// `DATA` frame. Lines are written as the writer takes them, and their window
+/// given back only then, so a paused or busy router holds clients back
+/// through HTTP/2 flow control. A connection has up to `MAX_CALLS` calls going
+/// at once.
+pub async fn serve(listener: TcpListener, writer: Arc<Mutex<FileWriter>>) {
+    let mut incoming = listener.incoming();
+
+    while let Some(stream) = incoming.next().await {
+        match stream {
+            Ok(stream) => {
+                let writer = writer.clone();
+                task::spawn(async move {
+                    let name = match stream.peer_addr() {
+                        Ok(addr) => format!("grpc://{}", addr),
+                        Err(_) => String::from("grpc"),
+                    };
+                    match connection(&name, stream, writer).await {
+                        Ok(()) => tracing::debug!("connection {} closed", name),
+                        Err(error) => tracing::warn!("connection {} failed: {}", name, error),
+                    }
+                });
+            }
+            Err(error) => {
+                tracing::warn!("unable to accept gRPC connection: {}", error);
+                task::sleep(ACCEPT_BACKOFF).await;
+            }
+        }
+    }
+}
+
+/// Takes the calls of a connection, each served by a task of its own, until
+/// the client goes away.
+async fn connection(
+    name: &str,
+    stream: TcpStream,
+    writer: Arc<Mutex<FileWriter>>,
+) -> Result<(), h2::Error> {
+    let mut connection = h2::server::Builder::new()
+        .max_concurrent_streams(MAX_CALLS)
+        .max_header_list_size(MAX_HEADER_LIST_BYTES)
+        .handshake(stream.compat())
+        .await?;
+
+    while let Some(call) = connection.accept().await {
+        let (request, respond) = call?;
+        let name = name.to_string();
+        let writer = writer.clone();
+        task::spawn(async move {
+            if let Err(error) = serve_call(&name, request, respond, &writer).await {
+                tracing::debug!("call of {} failed: {}", name, error);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+async fn serve_call(
+    name: &str,
+    request: Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    writer: &Arc<Mutex<FileWriter>>,
+) -> Result<(), h2::Error> {
+    let path = request.uri().path();
+    if request.method() != http::Method::POST || path != grpc::INGEST_PATH {
+        tracing::warn!("{} called unknown method {}", name, path);
+        let message = format!("unknown method {}", path);
+        let mut response = response();
+        response
+            .headers_mut()
+            .extend(status(grpc::STATUS_UNIMPLEMENTED, Some(&message)));
+        // Dropping the request afterwards resets it, unless the client is
+        // done already.
+        respond.send_response(response, true)?;
+        return Ok(());
+    }
+
+    tracing::debug!("{} started an Ingest call", name);
+    let mut body = request.into_body();
+    let mut send = respond.send_response(response(), false)?;
+    let mut messages = Messages::default();
+    let mut written = 0;
+
+    while let Some(data) = body.data().await {
+        let data = data?;
+        messages.push(&data);
+        let mut lines = Vec::new();
+        let failed = loop {
+            match messages.pop() {
+                Ok(Some(message)) => match Line::decode(message.as_slice()) {
+                    Ok(line) => lines.push(line),
+                    Err(error) => break Some(error.to_string()),
+                },
+                Ok(None) => break None,
+                Err(error) => break Some(error),
+            }
+        };
+
+        if let Err(error) = write(writer, &lines, &mut written).await {
+            tracing::warn!("unable to write lines of {}: {}", name, error);
+            report::record_error(&format!("input {}", name), &error);
+            return fail(&mut send, grpc::STATUS_UNAVAILABLE, &error.to_string());
+        }
+        if let Some(error) = failed {
+            return fail(&mut send, grpc::STATUS_INVALID_ARGUMENT, &error);
+        }
+
+        if !lines.is_empty() {
+            acknowledge(&mut send, written)?;
+        }
+        body.flow_control().release_capacity(data.len())?;
+    }
+
+    if !messages.is_empty() {
+        return fail(
+            &mut send,
+            grpc::STATUS_INVALID_ARGUMENT,
+            "call ended inside a message",
+        );
+    }
+    tracing::debug!("{} ended a call of {} lines", name, written);
+    send.send_trailers(status(grpc::STATUS_OK, None))
+}
+
+/// Writes a batch of lines of a call under a single hold of the writer,
+/// counting those written.
+async fn write(
+    writer: &Arc<Mutex<FileWriter>>,
+    lines: &[Line],
+    written: &mut u64,
+) -> Result<(), io::Error> {
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    let mut writer = input::lock_unpaused(writer).await;
+    let mut text = String::new();
+    for line in lines.iter() {
+        text.clear();
+        text.push_str(&String::from_utf8_lossy(&line.payload));
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        writer
+            .write_to_channel("grpc", &line.channel, &text)
+            .await?;
+        *written += 1;
+    }
+    input::unlock_throttled(writer).await;
+
+    Ok(())
+}
+
+/// Tells the client how many lines of the call are written. Acks count from
+/// the start of the call, so one left unsent for want of window is made up
+/// for by the next, rather than buffered for a client not reading them.
+fn acknowledge(send: &mut SendStream<Bytes>, written: u64) -> Result<(), h2::Error> {
+    let ack = grpc::message(&Ack { written }.encode_to_vec());
+    send.reserve_capacity(ack.len());
+    if send.capacity() < ack.len() {
+        return Ok(());
+    }
+
+    send.send_data(Bytes::from(ack), false)
+}
+
+/// Ends a call with `status`. Dropping its request afterwards resets it,
+/// unless the client is done already.
+fn fail(send: &mut SendStream<Bytes>, status: u32, message: &str) -> Result<(), h2::Error> {
+    send.send_trailers(self::status(status, Some(message)))
+}
+
+fn response() -> Response<()> {
+    let mut response = Response::new(());
+    response.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        HeaderValue::from_static("application/grpc"),
+    );
+    response
+}
+
+/// The headers telling of a call's `status`, with `message` unless it can't
+/// be given as a header.
+fn status(status: u32, message: Option<&str>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("grpc-status", HeaderValue::from(status));
+    if let Some(message) = message.and_then(|message| HeaderValue::from_str(message).ok()) {
+        headers.insert("grpc-message", message);
+    }
+    headers
+}
@@ -0,0 +1,87 @@
+use async_std::channel::{self, Receiver, Sender};
+use async_std::io;
+use async_std::task;
+
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use crate::post_rotation::Running;
+use crate::report;
+#[cfg(feature = "state")]
+use crate::state;
+
+/// Command run for every finished file, set once at startup by `install`.
+static ROTATE_HOOK: OnceLock<RotateHook> = OnceLock::new();
+
+/// A command run for each file a channel is done with, once compressed if it
+/// is, given the file's path and the channel as arguments and as
+/// `LOG_REVOLVE_PATH` and `LOG_REVOLVE_CHANNEL`. Runs past the concurrency
+/// limit wait for a slot; failures are logged, never fatal.
+struct RotateHook {
+    program: String,
+    args: Vec<String>,
+    /// One message per run in progress, so a full channel holds the next
+    /// run back.
+    slots: (Sender<()>, Receiver<()>),
+}
+
+/// Runs `command`, split on whitespace, for every file finished from now on,
+/// at most `concurrency` at a time.
+pub fn install(command: &str, concurrency: usize) -> Result<(), io::Error> {
+    let mut words = command.split_whitespace().map(str::to_string);
+    let program = words
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "--on-rotate-cmd is empty"))?;
+
+    let _ = ROTATE_HOOK.set(RotateHook {
+        program,
+        args: words.collect(),
+        slots: channel::bounded(concurrency.max(1)),
+    });
+
+    Ok(())
+}
+
+/// Runs the hook, if one is installed, for the file at `path` of `channel`.
+pub fn run(channel: String, path: String) {
+    let hook = match ROTATE_HOOK.get() {
+        Some(hook) => hook,
+        None => {
+            #[cfg(feature = "state")]
+            state::finished(&path);
+            return;
+        }
+    };
+
+    let running = Running::start();
+    task::spawn(async move {
+        let _running = running;
+        let (ref acquire, ref release) = hook.slots;
+        let _ = acquire.send(()).await;
+
+        let mut command = Command::new(&hook.program);
+        command
+            .args(&hook.args)
+            .arg(&path)
+            .arg(&channel)
+            .env("LOG_REVOLVE_PATH", &path)
+            .env("LOG_REVOLVE_CHANNEL", &channel)
+            .stdin(Stdio::null());
+        let status = task::spawn_blocking(move || command.status()).await;
+        let _ = release.recv().await;
+
+        match status {
+            Ok(status) if status.success() => tracing::debug!("rotate hook ran for {}", path),
+            Ok(status) => {
+                tracing::warn!("rotate hook for {} exited with {}", path, status);
+                report::record_error("on-rotate", format!("{} exited with {}", path, status));
+            }
+            Err(error) => {
+                tracing::warn!("unable to run rotate hook for {}: {}", path, error);
+                report::record_error("on-rotate", error);
+            }
+        }
+        #[cfg(feature = "state")]
+        state::finished(&path);
+    });
+}
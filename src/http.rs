@@ -0,0 +1,269 @@
+use async_std::io::{self, BufReader, Read, Write};
+use async_std::prelude::*;
+
+const MAX_HEAD_BYTES: usize = 16 * 1024;
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Just enough HTTP/1.1 for the router's own endpoints: one request per
+/// connection, bodies sized by `Content-Length`.
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    #[cfg_attr(not(feature = "http-admin"), allow(dead_code))]
+    pub query: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub async fn read_request<R: Read + Unpin>(stream: R) -> Result<Request, io::Error> {
+    let mut reader = BufReader::new(stream);
+    let mut head_bytes = 0;
+    let mut line = String::new();
+
+    head_bytes += reader.read_line(&mut line).await?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Err(invalid("malformed request line")),
+    };
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        head_bytes += reader.read_line(&mut line).await?;
+        if head_bytes > MAX_HEAD_BYTES {
+            return Err(invalid("request head is too large"));
+        }
+
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), parse_query(query)),
+        None => (target, Vec::new()),
+    };
+
+    let mut request = Request {
+        method,
+        path: percent_decode(&path),
+        query,
+        headers,
+        body: Vec::new(),
+    };
+
+    let length: usize = match request.header("Content-Length") {
+        Some(length) => length
+            .parse()
+            .map_err(|_| invalid("malformed Content-Length"))?,
+        None => 0,
+    };
+    if length > MAX_BODY_BYTES {
+        return Err(invalid("request body is too large"));
+    }
+    request.body.resize(length, 0);
+    reader.read_exact(&mut request.body).await?;
+
+    Ok(request)
+}
+
+pub async fn write_response<W: Write + Unpin>(
+    mut stream: W,
+    status: u16,
+    content_type: &str,
+    body: &[u8],
+) -> Result<(), io::Error> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason(status),
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.flush().await
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => (percent_decode(key), percent_decode(value)),
+            None => (percent_decode(pair), String::new()),
+        })
+        .collect()
+}
+
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' if index + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[index + 1..index + 3]).unwrap_or("");
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => {
+                        decoded.push(byte);
+                        index += 3;
+                        continue;
+                    }
+                    Err(_) => decoded.push(b'%'),
+                }
+            }
+            b'+' => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::task;
+
+    fn read(request: &str) -> Result<Request, io::Error> {
+        task::block_on(read_request(request.as_bytes()))
+    }
+
+    fn refusal(request: &str) -> String {
+        match read(request) {
+            Ok(_) => panic!("request was read"),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn requests_are_read_up_to_their_length() {
+        let request = read(
+            "POST /ingest/web?format=json HTTP/1.1\r\n\
+             Content-Type: text/plain\r\n\
+             content-length: 5\r\n\
+             \r\n\
+             hello, and more",
+        )
+        .unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/ingest/web");
+        assert_eq!(
+            request.query,
+            [(String::from("format"), String::from("json"))]
+        );
+        assert_eq!(request.header("Content-Length"), Some("5"));
+        assert_eq!(request.header("CONTENT-TYPE"), Some("text/plain"));
+        assert_eq!(request.body, b"hello");
+    }
+
+    #[test]
+    fn paths_and_queries_are_percent_decoded() {
+        let request =
+            read("GET /admin/stats%2Fweb?channel=a%20b&hours=1+2&flag HTTP/1.1\r\n\r\n").unwrap();
+
+        assert_eq!(request.path, "/admin/stats/web");
+        assert_eq!(
+            request.query,
+            [
+                (String::from("channel"), String::from("a b")),
+                (String::from("hours"), String::from("1 2")),
+                (String::from("flag"), String::new()),
+            ]
+        );
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn broken_percent_escapes_are_kept() {
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+        assert_eq!(percent_decode("%41%4"), "A%4");
+    }
+
+    #[test]
+    fn malformed_requests_are_refused() {
+        assert_eq!(refusal("GET\r\n\r\n"), "malformed request line");
+
+        assert_eq!(
+            refusal("POST / HTTP/1.1\r\nContent-Length: five\r\n\r\n"),
+            "malformed Content-Length"
+        );
+    }
+
+    #[test]
+    fn oversized_requests_are_refused() {
+        let request = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_BYTES + 1
+        );
+        assert_eq!(refusal(&request), "request body is too large");
+
+        let request = format!(
+            "GET / HTTP/1.1\r\nX-Padding: {}\r\n\r\n",
+            "x".repeat(MAX_HEAD_BYTES)
+        );
+        assert_eq!(refusal(&request), "request head is too large");
+    }
+
+    #[test]
+    fn responses_say_how_long_they_are() {
+        let mut response = Vec::new();
+        task::block_on(write_response(
+            &mut response,
+            404,
+            "application/json",
+            b"{}",
+        ))
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(response).unwrap(),
+            "HTTP/1.1 404 Not Found\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: 2\r\n\
+             Connection: close\r\n\
+             \r\n\
+             {}"
+        );
+    }
+}
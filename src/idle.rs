@@ -0,0 +1,239 @@
+use async_std::io;
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+use crate::{report, FileWriter};
+
+/// Channels expected to see a line at least every so often, e.g. `app=5m`.
+/// A channel going quiet for longer is often the first sign of an outage
+/// upstream, so it's alerted on once, and again once it's back.
+pub struct IdleWatch {
+    expected: BTreeMap<String, Duration>,
+    last_seen: BTreeMap<String, Instant>,
+    pub idle: BTreeSet<String>,
+    pub alerts: u64,
+}
+
+impl IdleWatch {
+    pub fn new(expected: BTreeMap<String, Duration>) -> Self {
+        // Silence counts from startup for channels that never speak.
+        let now = Instant::now();
+        let last_seen = expected
+            .keys()
+            .map(|channel| (channel.clone(), now))
+            .collect();
+
+        IdleWatch {
+            expected,
+            last_seen,
+            idle: BTreeSet::new(),
+            alerts: 0,
+        }
+    }
+
+    /// How often channels are checked, often enough to alert within a
+    /// quarter of the shortest expectation.
+    pub fn check_interval(&self) -> Duration {
+        let shortest = self.expected.values().min().copied().unwrap_or_default();
+        (shortest / 4).clamp(Duration::from_secs(1), Duration::from_secs(60))
+    }
+
+    /// Records a line of `channel`, returning the event of its recovery when
+    /// it was idle.
+    pub fn seen(&mut self, channel: &str) -> Option<String> {
+        let last_seen = self.last_seen.get_mut(channel)?;
+        let silent = last_seen.elapsed();
+        *last_seen = Instant::now();
+
+        if self.idle.remove(channel) {
+            return Some(format!(
+                "{{\"event\":\"channel_recovered\",\"channel\":{:?},\"silent_seconds\":{}}}",
+                channel,
+                silent.as_secs()
+            ));
+        }
+        None
+    }
+
+    /// Events of the channels that have gone quiet for longer than expected
+    /// since the last check.
+    pub fn check(&mut self) -> Vec<String> {
+        let mut events = Vec::new();
+        for (channel, expected) in self.expected.iter() {
+            let silent = self.last_seen[channel].elapsed();
+            if silent <= *expected || self.idle.contains(channel) {
+                continue;
+            }
+
+            self.idle.insert(channel.clone());
+            self.alerts += 1;
+            events.push(format!(
+                "{{\"event\":\"channel_idle\",\"channel\":{:?},\"silent_seconds\":{},\"expected_seconds\":{}}}",
+                channel,
+                silent.as_secs(),
+                expected.as_secs()
+            ));
+        }
+
+        events
+    }
+}
+
+/// Parses an interval such as `90s`, `5m`, `1h` or `1d`.
+pub fn parse_interval(s: &str) -> Result<Duration, io::Error> {
+    let (digits, unit) = s.split_at(s.len() - s.chars().last().map_or(0, char::len_utf8));
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => 0,
+    };
+
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|count| count.checked_mul(seconds))
+        .filter(|seconds| *seconds > 0)
+        .map(Duration::from_secs)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("expected an interval such as `5m`, got `{}`", s),
+            )
+        })
+}
+
+pub async fn watch(writer: Arc<Mutex<FileWriter>>, interval: Duration) {
+    loop {
+        task::sleep(interval).await;
+
+        let mut writer = writer.lock().await;
+        if let Err(error) = writer.check_idle().await {
+            tracing::warn!("unable to report idle channels: {}", error);
+            report::record_error("idle", error);
+        }
+    }
+}
+
+/// Closes the files of idle channels, checking a quarter as often as they
+/// may be silent.
+pub async fn close_every(writer: Arc<Mutex<FileWriter>>, idle: Duration) {
+    let interval = (idle / 4).clamp(Duration::from_secs(1), Duration::from_secs(60));
+    loop {
+        task::sleep(interval).await;
+
+        let mut writer = writer.lock().await;
+        if let Err(error) = writer.close_idle().await {
+            tracing::warn!("unable to close files of idle channels: {}", error);
+            report::record_error("idle_close", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{self, LogDir};
+
+    fn watch(expected: &[(&str, u64)]) -> IdleWatch {
+        IdleWatch::new(
+            expected
+                .iter()
+                .map(|(channel, secs)| (channel.to_string(), Duration::from_secs(*secs)))
+                .collect(),
+        )
+    }
+
+    /// Makes `channel` look silent for `secs` seconds.
+    fn silence(watch: &mut IdleWatch, channel: &str, secs: u64) {
+        watch.last_seen.insert(
+            channel.to_string(),
+            Instant::now() - Duration::from_secs(secs),
+        );
+    }
+
+    #[test]
+    fn intervals_take_a_unit() {
+        assert_eq!(parse_interval("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_interval("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_interval("1h").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse_interval("1d").unwrap(), Duration::from_secs(86400));
+        for interval in ["5", "0m", "5w", "", "m", "5é"] {
+            let error = parse_interval(interval).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{}", interval);
+        }
+    }
+
+    #[test]
+    fn checks_come_a_quarter_of_the_shortest_expectation_apart() {
+        assert_eq!(
+            watch(&[("app", 300), ("web", 120)]).check_interval(),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            watch(&[("app", 2)]).check_interval(),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            watch(&[("app", 86400)]).check_interval(),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn silent_channels_are_alerted_on_once_and_again_when_back() {
+        let mut watch = watch(&[("app", 300), ("web", 60)]);
+        assert!(watch.check().is_empty());
+
+        silence(&mut watch, "app", 301);
+        assert_eq!(
+            watch.check(),
+            vec![
+                "{\"event\":\"channel_idle\",\"channel\":\"app\",\"silent_seconds\":301,\"expected_seconds\":300}"
+            ]
+        );
+        assert!(watch.check().is_empty());
+        assert_eq!(watch.alerts, 1);
+
+        assert_eq!(watch.seen("web"), None);
+        assert_eq!(watch.seen("unwatched"), None);
+        assert_eq!(
+            watch.seen("app").unwrap(),
+            "{\"event\":\"channel_recovered\",\"channel\":\"app\",\"silent_seconds\":301}"
+        );
+        assert!(watch.idle.is_empty());
+    }
+
+    #[test]
+    fn alerts_go_to_the_meta_channel() {
+        task::block_on(async {
+            let dir = LogDir::new("idle");
+            let args = [
+                "--accepted-log-channels",
+                "app,meta",
+                "--expected-traffic",
+                "app=5m",
+                "--meta-channel",
+                "meta",
+                "--flush-bytes",
+                "0",
+            ];
+            let mut writer = testing::writer(&dir, &args).await;
+            silence(writer.idle_watch.as_mut().unwrap(), "app", 600);
+
+            writer.check_idle().await.unwrap();
+            writer.sync_all().await.unwrap();
+            let meta = dir.read("meta_");
+            assert!(
+                meta.starts_with("{\"event\":\"channel_idle\",\"channel\":\"app\""),
+                "{}",
+                meta
+            );
+        });
+    }
+}
@@ -0,0 +1,59 @@
+use chrono::{DateTime, FixedOffset};
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::stamp::TimestampFormat;
+
+/// How lines are written to the inapt file, given by `--inapt-format`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InaptFormat {
+    /// `[<reason>:<channel>] <line>`, or `[<reason>] <line>` without a
+    /// channel.
+    Marked,
+    /// `<time> reason=<reason> channel=<channel> <line>`, the channel left
+    /// out when there was none, for tools sorting rejected lines by why they
+    /// were.
+    Structured,
+}
+
+impl InaptFormat {
+    /// `line`, rejected for `reason` at `time`, as written to the inapt file.
+    pub fn mark(
+        self,
+        time: &DateTime<FixedOffset>,
+        timestamp: &TimestampFormat,
+        reason: &str,
+        channel: Option<&str>,
+        line: &str,
+    ) -> String {
+        match (self, channel) {
+            (InaptFormat::Marked, Some(channel)) => format!("[{}:{}] {}", reason, channel, line),
+            (InaptFormat::Marked, None) => format!("[{}] {}", reason, line),
+            (InaptFormat::Structured, channel) => {
+                let mut marked = String::new();
+                // Writing to a `String` can't fail.
+                let _ = timestamp.write(time, &mut marked);
+                let _ = write!(marked, " reason={}", reason);
+                if let Some(channel) = channel {
+                    let _ = write!(marked, " channel={}", channel);
+                }
+                marked.push(' ');
+                marked.push_str(line);
+                marked
+            }
+        }
+    }
+}
+
+impl FromStr for InaptFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "marked" => Ok(InaptFormat::Marked),
+            "structured" => Ok(InaptFormat::Structured),
+            _ => Err(format!("unknown inapt format: {}", s)),
+        }
+    }
+}
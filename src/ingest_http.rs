@@ -0,0 +1,145 @@
+use async_std::io::{self, Cursor};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+use serde_json::{json, Value};
+
+use std::time::Duration;
+
+use crate::{http, input, CliOptions, FileWriter};
+
+/// Largest gzip body taken once decompressed, so a small gzip bomb can't have
+/// the router buffer gigabytes.
+#[cfg(feature = "gzip")]
+const MAX_DECODED_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Pause after a failed accept, as for `--listen`.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Takes lines over HTTP, `POST /ingest/<channel>` with a newline-delimited
+/// body, plain or `Content-Encoding: gzip`. NDJSON bodies are lines like any
+/// other. Lines go through the same decoding as an input's, and requests
+/// are answered with `202` and how many lines and bytes they carried once
+/// written.
+pub async fn serve(
+    listener: TcpListener,
+    writer: Arc<Mutex<FileWriter>>,
+    options: Arc<CliOptions>,
+) {
+    let mut incoming = listener.incoming();
+
+    while let Some(stream) = incoming.next().await {
+        match stream {
+            Ok(stream) => {
+                let writer = writer.clone();
+                let options = options.clone();
+                task::spawn(async move {
+                    if let Err(error) = handle_connection(stream, writer, options).await {
+                        tracing::warn!("ingest HTTP connection failed: {}", error);
+                    }
+                });
+            }
+            Err(error) => {
+                tracing::warn!("unable to accept ingest HTTP connection: {}", error);
+                task::sleep(ACCEPT_BACKOFF).await;
+            }
+        }
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    writer: Arc<Mutex<FileWriter>>,
+    options: Arc<CliOptions>,
+) -> Result<(), io::Error> {
+    let name = match stream.peer_addr() {
+        Ok(addr) => format!("http://{}", addr),
+        Err(_) => String::from("http"),
+    };
+    let (status, body) = match http::read_request(&stream).await {
+        Ok(request) => ingest(&name, request, writer, options).await,
+        Err(error) => (400, failure(error)),
+    };
+
+    let mut body = body.to_string();
+    body.push('\n');
+    http::write_response(&stream, status, "application/json", body.as_bytes()).await
+}
+
+async fn ingest(
+    name: &str,
+    request: http::Request,
+    writer: Arc<Mutex<FileWriter>>,
+    options: Arc<CliOptions>,
+) -> (u16, Value) {
+    let channel = match request.path.strip_prefix("/ingest/") {
+        Some(channel) if !channel.is_empty() && !channel.contains('/') => channel,
+        _ => return (404, failure("not found")),
+    };
+    if request.method != "POST" {
+        return (405, failure("method not allowed"));
+    }
+    if !writer.lock().await.knows("http", channel) {
+        return (404, failure(format!("unknown channel {}", channel)));
+    }
+
+    let mut body = match request.header("Content-Encoding") {
+        None | Some("identity") => request.body,
+        #[cfg(feature = "gzip")]
+        Some("gzip") => match gunzip(request.body).await {
+            Ok(body) if body.len() as u64 > MAX_DECODED_BYTES => {
+                return (413, failure("request body is too large once decompressed"))
+            }
+            Ok(body) => body,
+            Err(error) => return (400, failure(error)),
+        },
+        Some(encoding) => {
+            return (
+                415,
+                failure(format!("unsupported Content-Encoding {}", encoding)),
+            )
+        }
+    };
+
+    // Every request ends its last line, lest the next one's first be glued
+    // to it.
+    if body.last().is_some_and(|&byte| byte != b'\n') {
+        body.push(b'\n');
+    }
+    let bytes = body.len();
+    let lines = body.iter().filter(|&&byte| byte == b'\n').count();
+    let reader = Box::new(Cursor::new(body));
+    if let Err(error) = input::read_channel(name, reader, channel, writer, options).await {
+        tracing::warn!("unable to write lines of {}: {}", name, error);
+        return (500, failure(error));
+    }
+
+    (
+        202,
+        json!({ "ok": true, "channel": channel, "lines": lines, "bytes": bytes }),
+    )
+}
+
+/// A gzip body, any number of members, decompressed up to a byte past
+/// `MAX_DECODED_BYTES`.
+#[cfg(feature = "gzip")]
+async fn gunzip(body: Vec<u8>) -> Result<Vec<u8>, io::Error> {
+    use async_compression::futures::bufread::GzipDecoder;
+    use async_std::io::ReadExt;
+
+    let mut decoder = GzipDecoder::new(Cursor::new(body));
+    decoder.multiple_members(true);
+    let mut decoded = Vec::new();
+    decoder
+        .take(MAX_DECODED_BYTES + 1)
+        .read_to_end(&mut decoded)
+        .await?;
+
+    Ok(decoded)
+}
+
+fn failure<E: ToString>(error: E) -> Value {
+    json!({ "ok": false, "error": error.to_string() })
+}
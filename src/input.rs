@@ -0,0 +1,1027 @@
+use async_std::channel::Sender;
+use async_std::io::{self, BufRead, Read, ReadExt};
+use async_std::sync::{Arc, Mutex, MutexGuard};
+use async_std::task;
+
+use std::borrow::Cow;
+use std::future::{self, Future};
+use std::pin::{pin, Pin};
+use std::str::FromStr;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use log_revolve_rs::framing::length_prefixed::{self, MAX_RECORD_BYTES};
+use log_revolve_rs::framing::lines::{Frame, PairedDecoder};
+use log_revolve_rs::framing::multiline::{Continuation, MessageAssembler, Multiline};
+use log_revolve_rs::framing::prefixed;
+use log_revolve_rs::framing::producers::ProducerDecoder;
+
+#[cfg(feature = "cri")]
+use log_revolve_rs::framing::cri::{self, CriAssembler, Stream};
+#[cfg(feature = "journald")]
+use log_revolve_rs::framing::journal;
+#[cfg(feature = "json")]
+use log_revolve_rs::framing::json;
+
+use crate::decompress::Input;
+use crate::encoding::{self, InvalidUtf8};
+use crate::failure::{self, Backoff};
+use crate::line_limit::{LineReader, Piece};
+use crate::queue::Lines;
+#[cfg(feature = "routing")]
+use crate::route;
+#[cfg(feature = "split")]
+use crate::split;
+use crate::tenant;
+use crate::{decompress, report, CliOptions, FileWriter, Stop};
+
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often a drained stdin is read again under `--stay-alive`.
+const REATTACH_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Lines decoded under one hold of the writer while an input has them
+/// ready, so a busy input neither locks it for every line nor keeps it from
+/// everyone else.
+const LINES_PER_LOCK: usize = 256;
+
+/// First line of a network connection sending nothing but a single channel's
+/// messages, one per line: `@channel <name>`.
+const HANDSHAKE_PREFIX: &str = "@channel ";
+
+/// First line of a network connection sending a single tenant's lines,
+/// `@tenant <name>`, possibly followed by a channel handshake.
+const TENANT_HANDSHAKE_PREFIX: &str = "@tenant ";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InputFormat {
+    Lines,
+    Prefixed,
+    /// `<producer>:<channel>:<message>` lines, for producers sharing a pipe.
+    Producers,
+    #[cfg(feature = "cri")]
+    Cri,
+    #[cfg(feature = "json")]
+    Json,
+    /// `lines` or `json`, whichever the first line of each input turns out to
+    /// be, so producers can move from one to the other one at a time.
+    #[cfg(feature = "json")]
+    Auto,
+    /// Binary records rather than lines, for messages holding newlines.
+    LengthPrefixed,
+    /// Entries of the systemd journal, as `journalctl -o export` writes
+    /// them.
+    #[cfg(feature = "journald")]
+    Journald,
+    /// Bare messages, routed to a channel by their content.
+    #[cfg(feature = "routing")]
+    Routed,
+}
+
+impl InputFormat {
+    /// The format's name, which `--normalize-channels` gives steps for.
+    pub fn name(self) -> &'static str {
+        match self {
+            InputFormat::Lines => "lines",
+            InputFormat::Prefixed => "prefixed",
+            InputFormat::Producers => "producers",
+            #[cfg(feature = "cri")]
+            InputFormat::Cri => "cri",
+            #[cfg(feature = "json")]
+            InputFormat::Json => "json",
+            #[cfg(feature = "json")]
+            InputFormat::Auto => "auto",
+            InputFormat::LengthPrefixed => "length-prefixed",
+            #[cfg(feature = "journald")]
+            InputFormat::Journald => "journald",
+            #[cfg(feature = "routing")]
+            InputFormat::Routed => "routed",
+        }
+    }
+
+    /// Whether a message may take two lines, its channel on the first, so
+    /// dropping a single line splits it.
+    pub fn is_paired(self) -> bool {
+        match self {
+            InputFormat::Lines => true,
+            #[cfg(feature = "json")]
+            InputFormat::Auto => true,
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lines" | "paired" => Ok(InputFormat::Lines),
+            "prefixed" => Ok(InputFormat::Prefixed),
+            "producers" => Ok(InputFormat::Producers),
+            #[cfg(feature = "cri")]
+            "cri" => Ok(InputFormat::Cri),
+            #[cfg(feature = "json")]
+            "json" => Ok(InputFormat::Json),
+            #[cfg(feature = "json")]
+            "auto" => Ok(InputFormat::Auto),
+            "length-prefixed" => Ok(InputFormat::LengthPrefixed),
+            #[cfg(feature = "journald")]
+            "journald" => Ok(InputFormat::Journald),
+            #[cfg(feature = "routing")]
+            "routed" => Ok(InputFormat::Routed),
+            _ => Err(format!("unknown input format: {}", s)),
+        }
+    }
+}
+
+/// The formats `--protocol` chooses between: `prefixed`, or `paired` for
+/// the channel and message lines of old.
+pub fn parse_protocol(s: &str) -> Result<InputFormat, String> {
+    match s {
+        "prefixed" => Ok(InputFormat::Prefixed),
+        "paired" => Ok(InputFormat::Lines),
+        _ => Err(format!("expected `prefixed` or `paired`, got `{}`", s)),
+    }
+}
+
+/// How messages spanning several lines end, given `--message-delimiter`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MultilineMode {
+    Indented,
+    Delimited,
+}
+
+impl FromStr for MultilineMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "indented" => Ok(MultilineMode::Indented),
+            "delimited" => Ok(MultilineMode::Delimited),
+            _ => Err(format!("unknown multiline mode: {}", s)),
+        }
+    }
+}
+
+/// Framing state of one input stream. Every stream keeps its own, so a
+/// producer sending a channel line can't have its message line stolen by
+/// another producer writing to a different input.
+struct InputDecoder {
+    options: Arc<CliOptions>,
+    /// The configured format, until `auto` is settled by the first line.
+    format: InputFormat,
+    /// What channel names are normalized as sent by: the format, or `http`
+    /// for the body of a request.
+    protocol: &'static str,
+    /// Channel every line goes to, once a connection has declared it.
+    scope: Option<String>,
+    /// Tenant every channel belongs to, once a connection has declared it.
+    tenant: Option<String>,
+    paired: PairedDecoder,
+    /// The channel each producer named last, under the `producers` framing.
+    producers: ProducerDecoder,
+    /// Channel the line being decoded went to, for the rest of it should
+    /// `--max-line-bytes` split it; empty if it went to none. Kept from one
+    /// line to the next, so recording it doesn't allocate.
+    split_channel: String,
+    /// Why the message of the line being decoded is rejected to the inapt
+    /// file, if it is: it was cut at `--max-line-bytes`, or isn't UTF-8.
+    rejected: Option<&'static str>,
+    /// Lines of the message being gathered, under `--multiline`.
+    messages: Option<MessageAssembler>,
+    /// Whether lines hold bytes `--invalid-utf8 passthrough` escaped.
+    passthrough: bool,
+    #[cfg(feature = "cri")]
+    cri_assembler: CriAssembler,
+}
+
+impl InputDecoder {
+    fn new(options: Arc<CliOptions>) -> Self {
+        let messages = options.multiline.map(|mode| {
+            MessageAssembler::new(match mode {
+                MultilineMode::Indented => Multiline::Indented,
+                MultilineMode::Delimited => Multiline::Delimited(options.message_delimiter.clone()),
+            })
+        });
+        let passthrough = options.invalid_utf8 == InvalidUtf8::Passthrough;
+
+        InputDecoder {
+            format: options.input_format,
+            protocol: options.input_format.name(),
+            scope: None,
+            tenant: None,
+            options,
+            paired: PairedDecoder::default(),
+            producers: ProducerDecoder::default(),
+            split_channel: String::new(),
+            rejected: None,
+            messages,
+            passthrough,
+            #[cfg(feature = "cri")]
+            cri_assembler: CriAssembler::default(),
+        }
+    }
+
+    async fn decode(&mut self, line: &str, writer: &mut FileWriter) -> Result<(), io::Error> {
+        self.split_channel.clear();
+        if let Some(ref mut messages) = self.messages {
+            match messages.push(line) {
+                Continuation::Appended => return Ok(()),
+                Continuation::Ended(message) => {
+                    return self.write(writer, &message.channel, &message.text).await
+                }
+                Continuation::Other(Some(message)) => {
+                    self.write(writer, &message.channel, &message.text).await?
+                }
+                Continuation::Other(None) => {}
+            }
+        }
+
+        if let Some(channel) = self.scope.clone() {
+            return self.deliver(writer, &channel, line).await;
+        }
+
+        match self.format {
+            InputFormat::Lines => self.decode_paired(line, writer).await,
+            InputFormat::Prefixed => self.decode_prefixed(line, writer).await,
+            InputFormat::Producers => self.decode_producers(line, writer).await,
+            #[cfg(feature = "cri")]
+            InputFormat::Cri => self.decode_cri(line, writer).await,
+            #[cfg(feature = "json")]
+            InputFormat::Json => self.decode_json(line, writer).await,
+            // Settled before the first line is decoded.
+            #[cfg(feature = "json")]
+            InputFormat::Auto => self.decode_paired(line, writer).await,
+            #[cfg(feature = "routing")]
+            InputFormat::Routed => self.decode_routed(line, writer).await,
+            // Read by `read_records`, never line by line.
+            InputFormat::LengthPrefixed => self.write_inapt(writer, "unframed", None, line).await,
+            // Read by `read_journal`, never line by line.
+            #[cfg(feature = "journald")]
+            InputFormat::Journald => self.write_inapt(writer, "unframed", None, line).await,
+        }
+    }
+
+    /// A channel name on one line, followed by the message on the next.
+    async fn decode_paired(
+        &mut self,
+        line: &str,
+        writer: &mut FileWriter,
+    ) -> Result<(), io::Error> {
+        // Taken while its channel is delivered to, as the frame borrows it.
+        let mut paired = std::mem::take(&mut self.paired);
+        let (tenant, protocol) = (&self.tenant, self.protocol);
+        let result = match paired.push(line, |channel| {
+            writer.knows(protocol, tenant_channel(tenant, channel).as_ref())
+        }) {
+            Frame::Channel => Ok(()),
+            Frame::Message { channel, message } => self.deliver(writer, channel, message).await,
+            Frame::Rejected { channel, message } => self.deliver(writer, channel, message).await,
+            Frame::Unframed(line) => self.write_inapt(writer, "unframed", None, &line).await,
+        };
+        self.paired = paired;
+        result
+    }
+
+    /// A channel name ahead of the delimiter, the message after it.
+    async fn decode_prefixed(
+        &mut self,
+        line: &str,
+        writer: &mut FileWriter,
+    ) -> Result<(), io::Error> {
+        match prefixed::parse_line(line, &self.options.prefix_delimiter) {
+            Some(prefixed) => {
+                self.deliver(writer, prefixed.channel, prefixed.message)
+                    .await
+            }
+            None => self.write_inapt(writer, "unframed", None, line).await,
+        }
+    }
+
+    /// A producer and a channel ahead of the message, the channel left out
+    /// for the one the producer named last.
+    async fn decode_producers(
+        &mut self,
+        line: &str,
+        writer: &mut FileWriter,
+    ) -> Result<(), io::Error> {
+        // Taken while its channel is delivered to, as the message borrows it.
+        let mut producers = std::mem::take(&mut self.producers);
+        let result = match producers.push(line) {
+            Some((channel, message)) => self.deliver(writer, channel, message).await,
+            None => self.write_inapt(writer, "unframed", None, line).await,
+        };
+        self.producers = producers;
+        result
+    }
+
+    /// A message without a channel, sent to the channel of the first route
+    /// it matches.
+    #[cfg(feature = "routing")]
+    async fn decode_routed(
+        &mut self,
+        line: &str,
+        writer: &mut FileWriter,
+    ) -> Result<(), io::Error> {
+        let options = self.options.clone();
+        match route::channel_of(&options.routes, line) {
+            Some(channel) => self.deliver(writer, channel, line).await,
+            None => self.write_inapt(writer, "unrouted", None, line).await,
+        }
+    }
+
+    /// Writes a message of the `lines`, `prefixed`, `producers` or `routed`
+    /// framing, or holds it for the lines that may follow under `--multiline`.
+    async fn deliver(
+        &mut self,
+        writer: &mut FileWriter,
+        channel: &str,
+        message: &str,
+    ) -> Result<(), io::Error> {
+        self.split_channel.clear();
+        self.split_channel.push_str(channel);
+        self.deliver_message(writer, channel, message).await
+    }
+
+    /// As `deliver`, the channel recorded for a split line already.
+    async fn deliver_message(
+        &mut self,
+        writer: &mut FileWriter,
+        channel: &str,
+        message: &str,
+    ) -> Result<(), io::Error> {
+        let messages = match self.messages {
+            Some(ref mut messages) if self.rejected.is_none() => messages,
+            _ => return self.write(writer, channel, message).await,
+        };
+
+        match messages.start(channel, message) {
+            Some(previous) => self.write(writer, &previous.channel, &previous.text).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn write(
+        &self,
+        writer: &mut FileWriter,
+        channel: &str,
+        message: &str,
+    ) -> Result<(), io::Error> {
+        // A connection declared for a tenant can't reach another's channels.
+        let reason = match self.tenant {
+            Some(_) if tenant::split(channel).is_some() => Some("cross-tenant"),
+            _ => self.rejected,
+        };
+        let channel = tenant_channel(&self.tenant, channel);
+        if let Some(reason) = reason {
+            return self
+                .write_inapt(writer, reason, Some(&channel), message)
+                .await;
+        }
+
+        if self.passthrough && encoding::is_escaped(message) {
+            let bytes = encoding::unescape(message);
+            return writer
+                .write_bytes_to_channel(self.protocol, &channel, &bytes)
+                .await;
+        }
+        writer
+            .write_to_channel(self.protocol, &channel, message)
+            .await
+    }
+
+    /// Writes `text` to the inapt file, the bytes `--invalid-utf8
+    /// passthrough` escaped replaced as by `lossy`.
+    async fn write_inapt(
+        &self,
+        writer: &mut FileWriter,
+        reason: &str,
+        channel: Option<&str>,
+        text: &str,
+    ) -> Result<(), io::Error> {
+        if self.passthrough && encoding::is_escaped(text) {
+            let text = String::from_utf8_lossy(&encoding::unescape(text)).into_owned();
+            return writer.write_inapt(reason, channel, &text).await;
+        }
+        writer.write_inapt(reason, channel, text).await
+    }
+
+    /// Writes more of a line split at `--max-line-bytes` as a message of the
+    /// channel the line went to, or to the inapt file if it went to none.
+    async fn decode_continuation(
+        &mut self,
+        piece: &str,
+        writer: &mut FileWriter,
+    ) -> Result<(), io::Error> {
+        if self.split_channel.is_empty() {
+            return self.write_inapt(writer, "split", None, piece).await;
+        }
+        let channel = std::mem::take(&mut self.split_channel);
+        let result = self.deliver_message(writer, &channel, piece).await;
+        self.split_channel = channel;
+        result
+    }
+
+    /// Writes out whatever the framing still held when the input ended.
+    async fn finish(&mut self, writer: &mut FileWriter) -> Result<(), io::Error> {
+        if let Some(message) = self.messages.as_mut().and_then(MessageAssembler::finish) {
+            self.write(writer, &message.channel, &message.text).await?;
+        }
+
+        match self.paired.finish() {
+            Some(line) => self.write_inapt(writer, "unframed", None, &line).await,
+            None => Ok(()),
+        }
+    }
+
+    /// One JSON object per line, naming its own channel. Records without a
+    /// message are malformed unless they are written whole.
+    #[cfg(feature = "json")]
+    async fn decode_json(&mut self, line: &str, writer: &mut FileWriter) -> Result<(), io::Error> {
+        let record = match json::parse_line(line, &self.options.channel_field) {
+            Some(record) => record,
+            None => return self.write_inapt(writer, "malformed", None, line).await,
+        };
+
+        if self.options.json_whole_records {
+            return self.write(writer, &record.channel, line).await;
+        }
+        match record.message {
+            Some(mut message) => {
+                message.push('\n');
+                self.write(writer, &record.channel, &message).await
+            }
+            None => self.write_inapt(writer, "malformed", None, line).await,
+        }
+    }
+
+    #[cfg(feature = "cri")]
+    async fn decode_cri(&mut self, line: &str, writer: &mut FileWriter) -> Result<(), io::Error> {
+        let entry = match cri::parse_line(line) {
+            Some(entry) => entry,
+            None => return self.write_inapt(writer, "malformed", None, line).await,
+        };
+
+        if let Some((stream, message)) = self.cri_assembler.push(&entry) {
+            let channel = match stream {
+                Stream::Stdout => &self.options.cri_stdout_channel,
+                Stream::Stderr => &self.options.cri_stderr_channel,
+            };
+            self.write(writer, channel, &message).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Name of `channel` within `tenant`, if the input declared one.
+fn tenant_channel<'a>(tenant: &Option<String>, channel: &'a str) -> Cow<'a, str> {
+    match tenant {
+        Some(tenant) => Cow::Owned(format!("{}/{}", tenant, channel)),
+        None => Cow::Borrowed(channel),
+    }
+}
+
+/// Settles the framing of an `auto` input from its first line: a legacy
+/// producer opens with a channel name, which never parses as a JSON record.
+#[cfg(feature = "json")]
+fn detect(line: &str, channel_field: &str) -> InputFormat {
+    match json::parse_line(line, channel_field) {
+        Some(_) => InputFormat::Json,
+        None => InputFormat::Lines,
+    }
+}
+
+/// Reads `R`, waiting for more rather than ending when it runs dry, for a
+/// stdin a producer may attach to again once the last one closed it.
+pub struct StayAlive<R> {
+    inner: R,
+    pause: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+    /// Whether the end was reached since the last bytes were read.
+    drained: bool,
+}
+
+impl<R> StayAlive<R> {
+    pub fn new(inner: R) -> Self {
+        StayAlive {
+            inner,
+            pause: None,
+            drained: false,
+        }
+    }
+}
+
+impl<R: Read + Unpin> Read for StayAlive<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            if let Some(ref mut pause) = self.pause {
+                ready!(pause.as_mut().poll(cx));
+                self.pause = None;
+            }
+            match Pin::new(&mut self.inner).poll_read(cx, buf) {
+                Poll::Ready(Ok(0)) if !buf.is_empty() => {
+                    if !self.drained {
+                        tracing::info!(
+                            "stdin closed, waiting for a producer to attach to it again"
+                        );
+                        self.drained = true;
+                    }
+                    self.pause = Some(Box::pin(task::sleep(REATTACH_POLL_INTERVAL)));
+                }
+                Poll::Ready(Ok(read)) => {
+                    self.drained = false;
+                    return Poll::Ready(Ok(read));
+                }
+                other => return other,
+            }
+        }
+    }
+}
+
+/// Reads an input stream on its own task until it is closed, reporting how it
+/// ended through `finished`.
+pub fn spawn<R>(
+    name: String,
+    reader: R,
+    writer: Arc<Mutex<FileWriter>>,
+    options: Arc<CliOptions>,
+    finished: Sender<Stop>,
+) where
+    R: BufRead + Unpin + Send + 'static,
+{
+    task::spawn(async move {
+        let result = read_input(&name, reader, writer, options, false).await;
+        match result {
+            Ok(()) => tracing::info!("input {} closed", name),
+            Err(ref error) => {
+                tracing::error!("input {} failed: {}", name, error);
+                report::record_error(&format!("input {}", name), error);
+            }
+        }
+
+        let _ = finished.send(Stop::InputClosed(result)).await;
+    });
+}
+
+/// Reads an input stream until it is closed, on the caller's task.
+#[cfg(feature = "split")]
+pub async fn read<R>(
+    name: &str,
+    reader: R,
+    writer: Arc<Mutex<FileWriter>>,
+    options: Arc<CliOptions>,
+) -> Result<(), io::Error>
+where
+    R: BufRead + Unpin + Send + 'static,
+{
+    read_input(name, reader, writer, options, false).await
+}
+
+/// Reads a network connection on its own task until the peer hangs up. The
+/// connection may open with a `@channel <name>` handshake.
+pub fn spawn_connection<R>(
+    name: String,
+    reader: R,
+    writer: Arc<Mutex<FileWriter>>,
+    options: Arc<CliOptions>,
+) where
+    R: BufRead + Unpin + Send + 'static,
+{
+    task::spawn(async move {
+        match read_input(&name, reader, writer, options, true).await {
+            Ok(()) => tracing::debug!("connection {} closed", name),
+            Err(error) => tracing::warn!("connection {} failed: {}", name, error),
+        }
+    });
+}
+
+async fn read_input<R: BufRead + Unpin + Send + 'static>(
+    name: &str,
+    reader: R,
+    writer: Arc<Mutex<FileWriter>>,
+    options: Arc<CliOptions>,
+    handshake: bool,
+) -> Result<(), io::Error> {
+    let reader = decompress::decompressed(name, reader, options.input_compression).await?;
+    if options.input_format == InputFormat::LengthPrefixed {
+        return read_records(reader, &writer).await;
+    }
+    #[cfg(feature = "journald")]
+    if options.input_format == InputFormat::Journald {
+        return read_journal(reader, &writer, &options.journal_channel_fields).await;
+    }
+    read_lines(name, reader, writer, options, handshake, None).await
+}
+
+/// Reads the lines of an HTTP request's body, decompressed already, as
+/// messages of `channel` whatever `--input-format` says.
+#[cfg(feature = "http-input")]
+pub async fn read_channel(
+    name: &str,
+    reader: Input,
+    channel: &str,
+    writer: Arc<Mutex<FileWriter>>,
+    options: Arc<CliOptions>,
+) -> Result<(), io::Error> {
+    read_lines(
+        name,
+        reader,
+        writer,
+        options,
+        false,
+        Some(channel.to_string()),
+    )
+    .await
+}
+
+/// Decodes lines until the input is closed; `scope`, if any, is the channel
+/// of every line.
+async fn read_lines(
+    name: &str,
+    reader: Input,
+    writer: Arc<Mutex<FileWriter>>,
+    options: Arc<CliOptions>,
+    mut handshake: bool,
+    scope: Option<String>,
+) -> Result<(), io::Error> {
+    let mut reader = Lines::new(
+        name,
+        reader,
+        LineReader::new(
+            options.max_line_bytes,
+            options.max_line_action,
+            options.invalid_utf8,
+            options.normalize_line_endings,
+        ),
+        options.queue_depth,
+        options.queue_full,
+    );
+    let mut decoder = InputDecoder::new(options);
+    // Only the body of an HTTP request is scoped from the start.
+    if scope.is_some() {
+        decoder.protocol = "http";
+    }
+    decoder.scope = scope;
+    let mut line = String::new();
+    // The writer, held on to while lines are ready, and for how many.
+    let mut held: Option<MutexGuard<FileWriter>> = None;
+    let mut held_lines = 0;
+    let mut backoff = Backoff::default();
+    tracing::debug!("reading input {}", name);
+
+    loop {
+        let throttle = match held {
+            Some(ref mut writer) => writer.throttle.take(),
+            None => None,
+        };
+        if let Some(throttle) = throttle {
+            held = None;
+            task::sleep(throttle).await;
+        }
+        line.clear();
+        let piece = {
+            let mut read = pin!(reader.read_line(&mut line));
+            match future::poll_fn(|cx| Poll::Ready(read.as_mut().poll(cx))).await {
+                Poll::Ready(piece) => piece,
+                // Others get the writer while the input waits for more.
+                Poll::Pending => {
+                    held = None;
+                    read.await
+                }
+            }
+        };
+        let piece = match piece {
+            Ok(piece) => piece,
+            Err(error) => {
+                held = None;
+                failure::retry_read(name, error, &mut backoff).await?;
+                continue;
+            }
+        };
+        backoff.reset();
+        let piece = match piece {
+            Some(piece) => piece,
+            None => {
+                let mut writer = match held {
+                    Some(writer) => writer,
+                    None => writer.lock().await,
+                };
+                return decoder.finish(&mut writer).await;
+            }
+        };
+        if held_lines == LINES_PER_LOCK {
+            held = None;
+        }
+        let writer = match held {
+            Some(ref mut writer) => {
+                held_lines += 1;
+                writer
+            }
+            None => {
+                held_lines = 1;
+                held.insert(lock_unpaused(&writer).await)
+            }
+        };
+
+        match piece {
+            Piece::Line => {}
+            Piece::Continuation => {
+                decoder.decode_continuation(&line, writer).await?;
+                continue;
+            }
+            Piece::Oversized => decoder.rejected = Some("oversized"),
+            Piece::InvalidUtf8 => decoder.rejected = Some("invalid-utf8"),
+        }
+
+        if handshake {
+            if let Some(tenant) = line.strip_prefix(TENANT_HANDSHAKE_PREFIX) {
+                let tenant = tenant.trim_end();
+                tracing::info!("input {} sends lines of tenant {}", name, tenant);
+                decoder.tenant = Some(tenant.to_string());
+                continue;
+            }
+            handshake = false;
+            if let Some(channel) = line.strip_prefix(HANDSHAKE_PREFIX) {
+                let channel = channel.trim_end();
+                tracing::info!("input {} sends channel {} only", name, channel);
+                decoder.scope = Some(channel.to_string());
+                continue;
+            }
+        }
+
+        #[cfg(feature = "json")]
+        if decoder.format == InputFormat::Auto {
+            decoder.format = detect(&line, &decoder.options.channel_field);
+            decoder.protocol = decoder.format.name();
+            tracing::info!("input {} framed as {:?}", name, decoder.format);
+        }
+
+        #[cfg(feature = "split")]
+        split::see(&line);
+        decoder.decode(&line, writer).await?;
+        decoder.rejected = None;
+    }
+}
+
+/// Reads `length-prefixed` records until the input is closed, writing each
+/// payload byte for byte as it came, UTF-8 or not, nothing added. A record
+/// that doesn't parse still says where the next one starts, but a length
+/// past `MAX_RECORD_BYTES` or a record cut short leaves nothing to resume
+/// from, and fails the input. Records are read as the writer takes them,
+/// never queued ahead.
+async fn read_records(mut reader: Input, writer: &Mutex<FileWriter>) -> Result<(), io::Error> {
+    let mut record = Vec::new();
+
+    loop {
+        let closed = future::poll_fn(|cx| {
+            Pin::new(&mut reader)
+                .poll_fill_buf(cx)
+                .map_ok(<[u8]>::is_empty)
+        })
+        .await?;
+        if closed {
+            return Ok(());
+        }
+        let mut prefix = [0; 4];
+        reader.read_exact(&mut prefix).await?;
+        let length = u32::from_be_bytes(prefix) as usize;
+        if length > MAX_RECORD_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("record of {} bytes is too large", length),
+            ));
+        }
+        record.clear();
+        record.extend_from_slice(&prefix);
+        record.resize(4 + length, 0);
+        reader.read_exact(&mut record[4..]).await?;
+
+        let mut writer = lock_unpaused(writer).await;
+        match length_prefixed::decode(&record) {
+            Ok(Some((record, _))) => {
+                writer
+                    .write_bytes_to_channel("length-prefixed", record.channel, record.payload)
+                    .await?
+            }
+            _ => {
+                let body = String::from_utf8_lossy(&record[4..]);
+                let escaped = format!("{}\n", body.escape_debug());
+                writer.write_inapt("malformed", None, &escaped).await?
+            }
+        }
+        unlock_throttled(writer).await;
+    }
+}
+
+/// Reads journal entries in the export format until the input is closed,
+/// writing each entry's `MESSAGE` to the channel the first of
+/// `channel_fields` it has names. Entries naming no channel go to the inapt
+/// file, those without a message are skipped. An entry that doesn't parse
+/// leaves nothing to resume from, and fails the input.
+#[cfg(feature = "journald")]
+async fn read_journal(
+    mut reader: Input,
+    writer: &Mutex<FileWriter>,
+    channel_fields: &str,
+) -> Result<(), io::Error> {
+    let channel_fields: Vec<&str> = channel_fields
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .collect();
+    let mut buffer = Vec::new();
+    let mut chunk = vec![0; 64 * 1024];
+    let mut closed = false;
+
+    loop {
+        let mut consumed = 0;
+        while let Some((entry, length)) = journal::decode(&buffer[consumed..])? {
+            consumed += length;
+            let message = match entry.message() {
+                Some(message) => format!("{}\n", message),
+                None => continue,
+            };
+
+            let mut writer = lock_unpaused(writer).await;
+            match entry.channel(&channel_fields) {
+                Some(channel) => {
+                    writer
+                        .write_to_channel("journald", channel, &message)
+                        .await?
+                }
+                None => writer.write_inapt("unrouted", None, &message).await?,
+            }
+            unlock_throttled(writer).await;
+        }
+        buffer.drain(..consumed);
+
+        if closed {
+            if !buffer.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "journal entry cut short",
+                ));
+            }
+            return Ok(());
+        }
+        let read = reader.read(&mut chunk).await?;
+        buffer.extend_from_slice(&chunk[..read]);
+        if read == 0 {
+            closed = true;
+            // The last entry may come without the empty line ending it.
+            if !buffer.is_empty() {
+                buffer.push(b'\n');
+            }
+        }
+    }
+}
+
+/// Lets go of the writer, then waits out what the rate limits of the
+/// channels just written ask of the input under `--rate-limit-action block`,
+/// so only its producer is held back.
+pub async fn unlock_throttled(mut writer: MutexGuard<'_, FileWriter>) {
+    let throttle = writer.throttle.take();
+    drop(writer);
+    if let Some(throttle) = throttle {
+        task::sleep(throttle).await;
+    }
+}
+
+/// Waits for ingestion to be resumed before handing out the writer, so a
+/// paused router stops reading and the producer is held back by the pipe.
+pub async fn lock_unpaused(writer: &Mutex<FileWriter>) -> MutexGuard<'_, FileWriter> {
+    loop {
+        let mut guard = writer.lock().await;
+        if !guard.paused {
+            // Whatever wrote last without an input to hold back owes nothing.
+            guard.throttle = None;
+            return guard;
+        }
+
+        drop(guard);
+        task::sleep(PAUSE_POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use async_std::channel;
+    use async_std::fs::File;
+    use async_std::io::BufReader;
+
+    use std::io::Write;
+    use std::os::unix::io::IntoRawFd;
+    use std::os::unix::net::UnixStream;
+
+    use crate::fd;
+    use crate::testing::{self, LogDir};
+
+    /// The reading end of a socket pair, as `fd:<n>`, once `lines` were
+    /// written to the other end and it was closed.
+    fn inherited(lines: &str) -> String {
+        let (mut producer, input) = UnixStream::pair().unwrap();
+        producer.write_all(lines.as_bytes()).unwrap();
+        format!("fd:{}", input.into_raw_fd())
+    }
+
+    #[test]
+    fn descriptors_are_read_as_streams_of_their_own() {
+        task::block_on(async {
+            let dir = LogDir::new("input-fds");
+            let args = ["--accepted-log-channels", "app,web", "--flush-bytes", "0"];
+            let options = Arc::new(testing::options(&dir, &args));
+            let writer = Arc::new(Mutex::new(testing::writer(&dir, &args).await));
+            let (finished, finished_inputs) = channel::unbounded();
+
+            // A channel line left hanging by one descriptor pairs with
+            // nothing of the other's.
+            for lines in ["web\nfrom three\napp\n", "app\nfrom four\n"] {
+                let spec = inherited(lines);
+                let file = File::from(fd::open(&spec).unwrap());
+                spawn(
+                    spec,
+                    BufReader::new(file),
+                    writer.clone(),
+                    options.clone(),
+                    finished.clone(),
+                );
+            }
+            for _ in 0..2 {
+                match finished_inputs.recv().await.unwrap() {
+                    Stop::InputClosed(result) => result.unwrap(),
+                    Stop::Terminated => panic!("terminated"),
+                }
+            }
+
+            assert_eq!(dir.read("web"), "from three\n");
+            assert_eq!(dir.read("app"), "from four\n");
+        });
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn auto_inputs_are_told_apart_by_their_first_line() {
+        assert_eq!(
+            detect(r#"{"channel": "web", "message": "GET /"}"#, "channel"),
+            InputFormat::Json
+        );
+        assert_eq!(
+            detect(r#"{"k8s": {"app": "web"}}"#, "k8s.app"),
+            InputFormat::Json
+        );
+        assert_eq!(detect("web", "channel"), InputFormat::Lines);
+        // A record without its channel is no record.
+        assert_eq!(
+            detect(r#"{"message": "GET /"}"#, "channel"),
+            InputFormat::Lines
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn legacy_and_json_producers_share_the_router() {
+        task::block_on(async {
+            let dir = LogDir::new("input-auto");
+            let args = [
+                "--accepted-log-channels",
+                "app,web",
+                "--input-format",
+                "auto",
+                "--flush-bytes",
+                "0",
+            ];
+            let options = Arc::new(testing::options(&dir, &args));
+            let writer = Arc::new(Mutex::new(testing::writer(&dir, &args).await));
+            let (finished, finished_inputs) = channel::unbounded();
+
+            let inputs = [
+                "web\nGET /\n",
+                "{\"channel\": \"app\", \"message\": \"started\"}\n",
+            ];
+            for (index, lines) in inputs.iter().enumerate() {
+                spawn(
+                    format!("input-{}", index),
+                    BufReader::new(io::Cursor::new(lines.as_bytes())),
+                    writer.clone(),
+                    options.clone(),
+                    finished.clone(),
+                );
+            }
+            for _ in 0..2 {
+                match finished_inputs.recv().await.unwrap() {
+                    Stop::InputClosed(result) => result.unwrap(),
+                    Stop::Terminated => panic!("terminated"),
+                }
+            }
+
+            assert_eq!(dir.read("web_"), "GET /\n");
+            assert_eq!(dir.read("app_"), "started\n");
+        });
+    }
+}
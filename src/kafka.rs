@@ -0,0 +1,382 @@
+use async_std::channel::{self, Receiver, Sender, TrySendError};
+use async_std::fs::OpenOptions;
+use async_std::future;
+use async_std::io;
+use async_std::prelude::*;
+use async_std::task::{self, JoinHandle};
+
+use chrono::Utc;
+use rskafka::client::partition::{Compression, PartitionClient, UnknownTopicHandling};
+use rskafka::client::ClientBuilder;
+use rskafka::record::Record;
+use rskafka::BackoffConfig;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::report;
+
+const CLIENT_ID: &str = "log-revolve-rs";
+
+/// Longest a batch may take to reach the brokers, setting up the client
+/// included.
+const IO_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long the client retries a request the brokers failed before giving
+/// up on its batch, which goes to the fallback file.
+const RETRY_DEADLINE: Duration = Duration::from_secs(1);
+
+/// Wait before trying the brokers again after they failed, doubled by every
+/// failure that follows up to `MAX_BACKOFF`; batches meanwhile go to the
+/// fallback file.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How long lines still queued at shutdown get to reach the brokers.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Produces channel lines to a Kafka topic, keyed by channel so each
+/// channel keeps to a partition. Lines are batched from a task of their own
+/// through a queue of `queue_capacity` lines, so the files are never held up
+/// by the brokers; lines a full queue can't take are dropped. Batches the
+/// brokers don't acknowledge go to a fallback file, in the router's own
+/// `lines` input format so they can be fed back once the brokers are.
+///
+/// Batches are produced by `rskafka`, which runs on tokio: the producer has a
+/// runtime of its own on a blocking thread, whatever the router runs on.
+pub struct KafkaSink {
+    channels: BTreeSet<String>,
+    queue: Sender<(String, String)>,
+    producer: Option<JoinHandle<()>>,
+    counters: Arc<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    produced: AtomicU64,
+    fallback: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Where and how batches are produced.
+pub struct KafkaConfig {
+    /// `host:port` of the brokers asked for the topic's partitions.
+    pub brokers: Vec<String>,
+    pub topic: String,
+    pub batch_lines: usize,
+    /// Longest a line waits for its batch to fill.
+    pub linger: Duration,
+    pub fallback_path: String,
+}
+
+impl KafkaSink {
+    /// An empty channel set produces every accepted channel.
+    pub fn start(config: KafkaConfig, channels: BTreeSet<String>, queue_capacity: usize) -> Self {
+        let (queue, queued) = channel::bounded(queue_capacity.max(1));
+        let counters = Arc::new(Counters::default());
+        let producer = {
+            let counters = counters.clone();
+            task::spawn_blocking(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build();
+                match runtime {
+                    Ok(runtime) => runtime.block_on(produce_all(config, queued, counters)),
+                    Err(error) => tracing::warn!("unable to start the Kafka producer: {}", error),
+                }
+            })
+        };
+
+        KafkaSink {
+            channels,
+            queue,
+            producer: Some(producer),
+            counters,
+        }
+    }
+
+    pub fn accepts(&self, channel: &str) -> bool {
+        self.channels.is_empty() || self.channels.contains(channel)
+    }
+
+    pub fn send(&mut self, channel: &str, message: &str) {
+        let line = message.trim_end_matches(['\r', '\n']).to_string();
+        if let Err(TrySendError::Full(_)) = self.queue.try_send((channel.to_string(), line)) {
+            if self.counters.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+                tracing::warn!("Kafka queue is full, dropping lines");
+            }
+        }
+    }
+
+    /// Lines the brokers acknowledged.
+    #[cfg(any(feature = "metrics", feature = "report"))]
+    pub fn produced_lines(&self) -> u64 {
+        self.counters.produced.load(Ordering::Relaxed)
+    }
+
+    /// Lines written to the fallback file instead.
+    #[cfg(any(feature = "metrics", feature = "report"))]
+    pub fn fallback_lines(&self) -> u64 {
+        self.counters.fallback.load(Ordering::Relaxed)
+    }
+
+    /// Lines lost to a full queue or a failed fallback write.
+    #[cfg(any(feature = "metrics", feature = "report"))]
+    pub fn dropped_lines(&self) -> u64 {
+        self.counters.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Stops accepting lines and gives the producer a moment to produce, or
+    /// fall back with, what is still queued.
+    pub async fn close(&mut self) {
+        self.queue.close();
+
+        if let Some(producer) = self.producer.take() {
+            if future::timeout(SHUTDOWN_GRACE, producer).await.is_err() {
+                tracing::warn!("Kafka lines still queued at shutdown were lost");
+            }
+        }
+    }
+}
+
+async fn produce_all(
+    config: KafkaConfig,
+    queued: Receiver<(String, String)>,
+    counters: Arc<Counters>,
+) {
+    let mut producer = Producer {
+        config,
+        partitions: Vec::new(),
+        retry_at: None,
+        backoff: FIRST_BACKOFF,
+    };
+
+    while let Ok(first) = queued.recv().await {
+        let mut batch = vec![first];
+        let deadline = Instant::now() + producer.config.linger;
+        while batch.len() < producer.config.batch_lines {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match future::timeout(remaining, queued.recv()).await {
+                Ok(Ok(line)) => batch.push(line),
+                _ => break,
+            }
+        }
+
+        let lines = batch.len() as u64;
+        match producer.produce(&batch).await {
+            Ok(()) => {
+                counters.produced.fetch_add(lines, Ordering::Relaxed);
+            }
+            Err(error) => {
+                match fall_back(&producer.config.fallback_path, &batch).await {
+                    Ok(()) => counters.fallback.fetch_add(lines, Ordering::Relaxed),
+                    Err(fallback_error) => {
+                        tracing::warn!(
+                            "unable to write Kafka fallback {}: {}",
+                            producer.config.fallback_path,
+                            fallback_error
+                        );
+                        report::record_error("kafka", &fallback_error);
+                        counters.dropped.fetch_add(lines, Ordering::Relaxed)
+                    }
+                };
+                if let Some(error) = error {
+                    tracing::warn!(
+                        "unable to produce to Kafka, falling back to {}: {}",
+                        producer.config.fallback_path,
+                        error
+                    );
+                    report::record_error("kafka", &error);
+                }
+            }
+        }
+    }
+}
+
+/// Appends lines the brokers didn't take to the fallback file, each under
+/// its channel.
+async fn fall_back(path: &str, batch: &[(String, String)]) -> Result<(), io::Error> {
+    let mut contents = String::new();
+    for (channel, line) in batch {
+        contents.push_str(channel);
+        contents.push('\n');
+        contents.push_str(line);
+        contents.push('\n');
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(contents.as_bytes()).await?;
+    file.flush().await
+}
+
+struct Producer {
+    config: KafkaConfig,
+    /// A client of each partition of the topic, by partition.
+    partitions: Vec<PartitionClient>,
+    /// Set while the brokers are failing, until they are tried again.
+    retry_at: Option<Instant>,
+    backoff: Duration,
+}
+
+impl Producer {
+    /// Produces a batch, each line to the partition its channel hashes to.
+    /// Fails without an error when the brokers are known to be failing and
+    /// aren't due to be tried again yet.
+    async fn produce(&mut self, batch: &[(String, String)]) -> Result<(), Option<io::Error>> {
+        if self
+            .retry_at
+            .is_some_and(|retry_at| Instant::now() < retry_at)
+        {
+            return Err(None);
+        }
+
+        let result = future::timeout(IO_TIMEOUT, self.try_produce(batch))
+            .await
+            .unwrap_or_else(|_| {
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Kafka brokers timed out",
+                ))
+            });
+        match result {
+            Ok(()) => {
+                self.retry_at = None;
+                self.backoff = FIRST_BACKOFF;
+                Ok(())
+            }
+            Err(error) => {
+                // The client is set up afresh next time.
+                self.partitions.clear();
+                self.retry_at = Some(Instant::now() + self.backoff);
+                self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+                Err(Some(error))
+            }
+        }
+    }
+
+    async fn try_produce(&mut self, batch: &[(String, String)]) -> Result<(), io::Error> {
+        if self.partitions.is_empty() {
+            self.partitions = self.connect().await?;
+        }
+        let partitions = self.partitions.len() as u32;
+
+        let now = Utc::now();
+        let mut by_partition: BTreeMap<usize, Vec<Record>> = BTreeMap::new();
+        for (channel, line) in batch {
+            let partition = (murmur2(channel.as_bytes()) & 0x7fff_ffff) % partitions;
+            by_partition
+                .entry(partition as usize)
+                .or_default()
+                .push(Record {
+                    key: Some(channel.as_bytes().to_vec()),
+                    value: Some(line.as_bytes().to_vec()),
+                    headers: BTreeMap::new(),
+                    timestamp: now,
+                });
+        }
+
+        for (partition, records) in by_partition {
+            self.partitions[partition]
+                .produce(records, Compression::NoCompression)
+                .await
+                .map_err(io::Error::other)?;
+        }
+
+        Ok(())
+    }
+
+    /// A client of each partition of the topic, asking the brokers in turn
+    /// for them.
+    async fn connect(&self) -> Result<Vec<PartitionClient>, io::Error> {
+        if self.config.brokers.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no Kafka broker given",
+            ));
+        }
+        let topic = &self.config.topic;
+
+        let client = ClientBuilder::new(self.config.brokers.clone())
+            .client_id(CLIENT_ID)
+            .backoff_config(BackoffConfig {
+                deadline: Some(RETRY_DEADLINE),
+                ..BackoffConfig::default()
+            })
+            .build()
+            .await
+            .map_err(io::Error::other)?;
+        let partitions = client
+            .list_topics()
+            .await
+            .map_err(io::Error::other)?
+            .into_iter()
+            .find(|found| &found.name == topic)
+            .ok_or_else(|| invalid(format!("Kafka topic {} not found", topic)))?
+            .partitions;
+        // Lines are only produced once every partition is known, so a channel
+        // never moves to another partition.
+        if partitions.is_empty() || partitions.iter().copied().ne(0..partitions.len() as i32) {
+            return Err(invalid(format!(
+                "Kafka topic {} has partitions missing",
+                topic
+            )));
+        }
+
+        let mut clients = Vec::with_capacity(partitions.len());
+        for partition in partitions {
+            let partition_client = client
+                .partition_client(topic.as_str(), partition, UnknownTopicHandling::Error)
+                .await
+                .map_err(io::Error::other)?;
+            clients.push(partition_client);
+        }
+        Ok(clients)
+    }
+}
+
+/// Murmur2 as Kafka's default partitioner hashes keys with, so a channel
+/// lands on the partition other Kafka clients would put it on.
+fn murmur2(data: &[u8]) -> u32 {
+    const SEED: u32 = 0x9747_b28c;
+    const M: u32 = 0x5bd1_e995;
+    const R: u32 = 24;
+
+    let mut h = SEED ^ data.len() as u32;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M);
+        h ^= k;
+    }
+
+    let rest = chunks.remainder();
+    if rest.len() >= 3 {
+        h ^= (rest[2] as u32) << 16;
+    }
+    if rest.len() >= 2 {
+        h ^= (rest[1] as u32) << 8;
+    }
+    if !rest.is_empty() {
+        h ^= rest[0] as u32;
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^= h >> 15;
+    h
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,21 @@
+/// Guesses a syslog severity from the first word of a message, falling back
+/// to informational.
+pub fn syslog_level(message: &str) -> u8 {
+    let first_word = message
+        .split_whitespace()
+        .next()
+        .unwrap_or("")
+        .trim_matches(|c: char| !c.is_ascii_alphabetic())
+        .to_ascii_uppercase();
+
+    match first_word.as_str() {
+        "EMERG" | "EMERGENCY" => 0,
+        "ALERT" => 1,
+        "CRIT" | "CRITICAL" | "FATAL" => 2,
+        "ERR" | "ERROR" => 3,
+        "WARN" | "WARNING" => 4,
+        "NOTICE" => 5,
+        "DEBUG" | "TRACE" => 7,
+        _ => 6,
+    }
+}
@@ -0,0 +1,22 @@
+//! The parts of log-revolve that can be used on their own: the framings it
+//! reads, the wire of its gRPC service, the encryption of its files at rest,
+//! the signing of its uploads, the rotation schedule of its files and the
+//! rules of the platforms it runs on, free of any I/O so they can be fuzzed
+//! and tested, a reader over the files it leaves behind, and a router
+//! writing channels to rotated files, or sinks of their own, for daemons
+//! that embed it, on async-std (`runtime-async-std`) or, with
+//! `runtime-tokio`, on tokio.
+
+#[cfg(feature = "encryption")]
+pub mod encryption;
+pub mod framing;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod platform;
+pub mod reader;
+pub mod rotation;
+pub mod router;
+pub mod runtime;
+#[cfg(feature = "s3")]
+pub mod sigv4;
+pub mod sink;
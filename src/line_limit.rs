@@ -0,0 +1,219 @@
+use async_std::io::{self, BufRead, BufReadExt};
+
+use std::future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{ready, Poll};
+
+use crate::decompress::Input;
+use crate::encoding::{self, InvalidUtf8};
+
+/// How much of a line being skipped is looked at a time.
+const SKIP_BYTES: usize = 64 * 1024;
+
+/// What happens to a line past `--max-line-bytes`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MaxLineAction {
+    /// Cut at the limit, the rest of the line skipped.
+    Truncate,
+    /// Cut into lines of the limit at most, all for the same channel.
+    Split,
+    /// Rejected to the inapt file, cut at the limit.
+    DropToInapt,
+}
+
+impl FromStr for MaxLineAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "truncate" => Ok(MaxLineAction::Truncate),
+            "split" => Ok(MaxLineAction::Split),
+            "drop-to-inapt" => Ok(MaxLineAction::DropToInapt),
+            _ => Err(format!("unknown max line action: {}", s)),
+        }
+    }
+}
+
+/// What a line read under the limit turned out to be.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Piece {
+    /// A line, or its first bytes if it was cut.
+    Line,
+    /// More of a line split at the limit.
+    Continuation,
+    /// The first bytes of a line past the limit, for the inapt file.
+    Oversized,
+    /// A line that isn't valid UTF-8, for the inapt file.
+    InvalidUtf8,
+}
+
+/// Reads lines of an input, never holding more than `max_bytes` of one in
+/// memory, however long the producer makes it, and decodes them as text.
+pub struct LineReader {
+    limit: Option<(usize, MaxLineAction)>,
+    invalid_utf8: InvalidUtf8,
+    /// Whether lines ending in `\r\n` are made to end in `\n`.
+    normalize_line_endings: bool,
+    /// Bytes of the piece being read, kept from one to the next so reading
+    /// doesn't allocate. Between pieces, the bytes of a character cut in two
+    /// by the limit, for the next one.
+    bytes: Vec<u8>,
+    /// Whether the line being split goes on.
+    splitting: bool,
+}
+
+impl LineReader {
+    pub fn new(
+        max_bytes: Option<usize>,
+        action: MaxLineAction,
+        invalid_utf8: InvalidUtf8,
+        normalize_line_endings: bool,
+    ) -> Self {
+        LineReader {
+            // Room for the longest character, so every piece holds one.
+            limit: max_bytes.map(|max_bytes| (max_bytes.max(4), action)),
+            invalid_utf8,
+            normalize_line_endings,
+            bytes: Vec::new(),
+            splitting: false,
+        }
+    }
+
+    /// Appends the next line, or piece of one, to `line`, `None` once the
+    /// input is closed. A line cut at the limit is given a newline, cut at a
+    /// character boundary.
+    pub async fn read_line(
+        &mut self,
+        input: &mut Input,
+        line: &mut String,
+    ) -> Result<Option<Piece>, io::Error> {
+        let (max_bytes, action) = match self.limit {
+            Some(limit) => limit,
+            None => {
+                self.bytes.clear();
+                return match input.read_until(b'\n', &mut self.bytes).await? {
+                    0 => Ok(None),
+                    _ => {
+                        let valid = self.decode_line(line);
+                        Ok(Some(self.checked(Piece::Line, valid)))
+                    }
+                };
+            }
+        };
+
+        let cut = loop {
+            match take(input, &mut self.bytes, max_bytes).await? {
+                Taken::More => continue,
+                Taken::Ended => break false,
+                Taken::Full => break true,
+            }
+        };
+        if self.bytes.is_empty() {
+            self.splitting = false;
+            return Ok(None);
+        }
+
+        let piece = match self.splitting {
+            true => Piece::Continuation,
+            false => Piece::Line,
+        };
+        if !cut {
+            self.splitting = false;
+            let valid = self.decode_line(line);
+            self.bytes.clear();
+            return Ok(Some(self.checked(piece, valid)));
+        }
+
+        let whole = encoding::whole_len(&self.bytes);
+        let valid = self.invalid_utf8.decode(&self.bytes[..whole], line);
+        line.push('\n');
+        // What is left of a cut character is carried to the next piece.
+        self.bytes.drain(..whole);
+        let piece = self.checked(piece, valid);
+
+        match action {
+            MaxLineAction::Split => {
+                self.splitting = true;
+                Ok(Some(piece))
+            }
+            MaxLineAction::Truncate => {
+                skip_line(input, &mut self.bytes).await?;
+                Ok(Some(piece))
+            }
+            MaxLineAction::DropToInapt => {
+                skip_line(input, &mut self.bytes).await?;
+                Ok(Some(Piece::Oversized))
+            }
+        }
+    }
+
+    /// Appends the line read whole into `bytes` to `line`, returning whether
+    /// it was valid UTF-8.
+    fn decode_line(&self, line: &mut String) -> bool {
+        match self.bytes.strip_suffix(b"\r\n") {
+            Some(bytes) if self.normalize_line_endings => {
+                let valid = self.invalid_utf8.decode(bytes, line);
+                line.push('\n');
+                valid
+            }
+            _ => self.invalid_utf8.decode(&self.bytes, line),
+        }
+    }
+
+    /// `piece`, or `InvalidUtf8` for a line to reject for its encoding.
+    fn checked(&self, piece: Piece, valid: bool) -> Piece {
+        match piece {
+            Piece::Line if !valid && self.invalid_utf8 == InvalidUtf8::Inapt => Piece::InvalidUtf8,
+            piece => piece,
+        }
+    }
+}
+
+/// How far `take` got into a line.
+enum Taken {
+    /// Up to its newline, or the end of the input.
+    Ended,
+    /// Up to the limit, the line going on.
+    Full,
+    /// Everything buffered, the line going on.
+    More,
+}
+
+/// Moves what `input` has buffered of the line being read into `bytes`, up
+/// to its newline or `limit` bytes in all. A newline right at the limit
+/// still ends the line whole.
+async fn take(input: &mut Input, bytes: &mut Vec<u8>, limit: usize) -> Result<Taken, io::Error> {
+    future::poll_fn(|cx| {
+        let mut input = Pin::new(&mut *input);
+        let buffer = ready!(input.as_mut().poll_fill_buf(cx))?;
+        if buffer.is_empty() {
+            return Poll::Ready(Ok(Taken::Ended));
+        }
+
+        let room = limit - bytes.len();
+        let window = &buffer[..buffer.len().min(room + 1)];
+        let (length, taken) = match window.iter().position(|&byte| byte == b'\n') {
+            Some(end) => (end + 1, Taken::Ended),
+            None if buffer.len() > room => (room, Taken::Full),
+            None => (buffer.len(), Taken::More),
+        };
+        bytes.extend_from_slice(&buffer[..length]);
+        input.consume(length);
+
+        Poll::Ready(Ok(taken))
+    })
+    .await
+}
+
+/// Throws away the rest of the line being read, newline included, looking
+/// at it through `skipped`, which is left empty.
+async fn skip_line(input: &mut Input, skipped: &mut Vec<u8>) -> Result<(), io::Error> {
+    loop {
+        skipped.clear();
+        if let Taken::Ended = take(input, skipped, SKIP_BYTES).await? {
+            skipped.clear();
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use async_std::io::{self, BufReader};
+use async_std::net::TcpListener;
+#[cfg(unix)]
+use async_std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use async_std::path::Path;
+use async_std::prelude::*;
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+use std::time::Duration;
+
+use crate::{input, CliOptions, FileWriter};
+
+/// Pause after a failed accept, typically out of descriptors, so the loop
+/// doesn't spin on the same error while connections are being closed.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Where producers connect to send lines over the network, written as
+/// `tcp://host:port` or `unix:<path>`.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+pub async fn bind(spec: &str) -> Result<Listener, io::Error> {
+    if let Some(addr) = spec.strip_prefix("tcp://") {
+        return Ok(Listener::Tcp(TcpListener::bind(addr).await?));
+    }
+    #[cfg(unix)]
+    if let Some(path) = spec.strip_prefix("unix:") {
+        return Ok(Listener::Unix(bind_unix(path).await?));
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "listen address must start with tcp:// or unix:, got `{}`",
+            spec
+        ),
+    ))
+}
+
+/// Binds a unix socket, replacing a stale socket file left behind by a
+/// previous instance.
+#[cfg(unix)]
+pub async fn bind_unix(path: &str) -> Result<UnixListener, io::Error> {
+    let path = Path::new(path);
+    if path.exists().await && UnixStream::connect(path).await.is_err() {
+        async_std::fs::remove_file(path).await?;
+    }
+
+    UnixListener::bind(path).await
+}
+
+/// Reads every accepted connection as an input of its own, which may open
+/// with a channel handshake.
+pub async fn serve(listener: Listener, writer: Arc<Mutex<FileWriter>>, options: Arc<CliOptions>) {
+    match listener {
+        Listener::Tcp(listener) => {
+            let mut incoming = listener.incoming();
+            while let Some(stream) = incoming.next().await {
+                match stream {
+                    Ok(stream) => {
+                        let name = match stream.peer_addr() {
+                            Ok(addr) => format!("tcp://{}", addr),
+                            Err(_) => String::from("tcp"),
+                        };
+                        let reader = BufReader::new(stream);
+                        input::spawn_connection(name, reader, writer.clone(), options.clone());
+                    }
+                    Err(error) => {
+                        tracing::warn!("unable to accept input connection: {}", error);
+                        task::sleep(ACCEPT_BACKOFF).await;
+                    }
+                }
+            }
+        }
+        #[cfg(unix)]
+        Listener::Unix(listener) => {
+            let mut incoming = listener.incoming();
+            let mut accepted: u64 = 0;
+            while let Some(stream) = incoming.next().await {
+                match stream {
+                    Ok(stream) => {
+                        // Unix peers are unnamed, so connections are numbered.
+                        accepted += 1;
+                        let name = format!("unix#{}", accepted);
+                        let reader = BufReader::new(stream);
+                        input::spawn_connection(name, reader, writer.clone(), options.clone());
+                    }
+                    Err(error) => {
+                        tracing::warn!("unable to accept input connection: {}", error);
+                        task::sleep(ACCEPT_BACKOFF).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::net::TcpStream;
+
+    use std::time::Instant;
+
+    use crate::testing::{self, LogDir};
+
+    #[test]
+    fn addresses_name_their_transport() {
+        task::block_on(async {
+            let error = bind("127.0.0.1:0").await.err().unwrap();
+            assert_eq!(
+                error.to_string(),
+                "listen address must start with tcp:// or unix:, got `127.0.0.1:0`"
+            );
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stale_sockets_are_replaced_and_live_ones_kept() {
+        task::block_on(async {
+            let dir = LogDir::new("listen-unix");
+            let path = dir.path().join("input.sock");
+            let path = path.to_str().unwrap();
+
+            let live = bind_unix(path).await.unwrap();
+            assert!(bind_unix(path).await.is_err());
+
+            drop(live);
+            bind_unix(path).await.unwrap();
+        });
+    }
+
+    #[test]
+    fn connections_are_inputs_of_their_own() {
+        task::block_on(async {
+            let dir = LogDir::new("listen-tcp");
+            let args = ["--accepted-log-channels", "app,web", "--flush-bytes", "0"];
+            let options = Arc::new(testing::options(&dir, &args));
+            let writer = Arc::new(Mutex::new(testing::writer(&dir, &args).await));
+            let listener = match bind("tcp://127.0.0.1:0").await.unwrap() {
+                Listener::Tcp(listener) => listener,
+                #[cfg(unix)]
+                Listener::Unix(_) => unreachable!(),
+            };
+            let addr = listener.local_addr().unwrap();
+            task::spawn(serve(Listener::Tcp(listener), writer, options));
+
+            let mut web = TcpStream::connect(addr).await.unwrap();
+            let mut app = TcpStream::connect(addr).await.unwrap();
+            web.write_all(b"@channel web\nGET /\n").await.unwrap();
+            app.write_all(b"app\nstarted\n").await.unwrap();
+            drop((web, app));
+
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while dir.read("web_") != "GET /\n" || dir.read("app_") != "started\n" {
+                assert!(Instant::now() < deadline, "timed out");
+                task::sleep(Duration::from_millis(10)).await;
+            }
+        });
+    }
+}
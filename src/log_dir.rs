@@ -0,0 +1,424 @@
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex as SyncMutex;
+use std::time::Duration;
+
+use log_revolve_rs::platform::{CurrentLink, Platform};
+
+use crate::{report, FileWriter};
+
+/// Whether missing directories are an error rather than created, set once at
+/// startup by `--no-create-dirs`.
+static NO_CREATE_DIRS: AtomicBool = AtomicBool::new(false);
+
+/// Whether directories are only checked rather than created, set once by
+/// `--check-config`.
+static CHECK_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Directories this instance took the lock on, along with their lock files,
+/// held open until exit.
+static DIR_LOCKS: SyncMutex<Vec<(PathBuf, File)>> = SyncMutex::new(Vec::new());
+
+/// Hidden, and no channel's file name, so scans of the directory pass it by.
+const LOCK_FILE_NAME: &str = ".log-revolve.lock";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Identity {
+    device: u64,
+    inode: u64,
+    mount_point: bool,
+}
+
+pub enum DirState {
+    Available,
+    /// Missing, or an empty mount point left on the parent filesystem.
+    Unavailable(&'static str),
+    /// Present again, but as a different directory or mount; open files point
+    /// at the old one.
+    Replaced,
+}
+
+/// Notices `log_dir` going away (NFS blips, container volume restarts) so the
+/// router doesn't keep writing into unlinked files or a shadow directory on the
+/// root filesystem.
+pub struct LogDirWatch {
+    path: PathBuf,
+    identity: Option<Identity>,
+}
+
+impl LogDirWatch {
+    pub fn new(path: &str) -> Self {
+        let path = PathBuf::from(path);
+        let identity = identify(&path);
+
+        LogDirWatch { path, identity }
+    }
+
+    pub fn probe(&mut self) -> DirState {
+        let current = match identify(&self.path) {
+            Some(current) => current,
+            None => return DirState::Unavailable("log directory is missing"),
+        };
+
+        match self.identity {
+            Some(known) if known == current => DirState::Available,
+            Some(known) if known.mount_point && !current.mount_point => {
+                DirState::Unavailable("log directory is no longer mounted")
+            }
+            _ => {
+                self.identity = Some(current);
+                DirState::Replaced
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn identify(path: &Path) -> Option<Identity> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::metadata(path).ok()?;
+    if !metadata.is_dir() {
+        return None;
+    }
+
+    let parent_device = std::fs::metadata(path.join(".."))
+        .map(|parent| parent.dev())
+        .unwrap_or_else(|_| metadata.dev());
+
+    Some(Identity {
+        device: metadata.dev(),
+        inode: metadata.ino(),
+        mount_point: parent_device != metadata.dev(),
+    })
+}
+
+/// What became of a file open for writing since it was opened at its path.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FileState {
+    InPlace,
+    Removed,
+    /// Renamed away, with another file or none put in its place.
+    Moved,
+}
+
+/// Whether the file `open` was read from, by its open handle, is still the
+/// one at `path`, so lines don't go on into a file no path leads to.
+#[cfg(unix)]
+pub fn file_state(open: &std::fs::Metadata, path: &Path) -> FileState {
+    use std::os::unix::fs::MetadataExt;
+
+    match std::fs::metadata(path) {
+        Ok(at_path) if (at_path.dev(), at_path.ino()) == (open.dev(), open.ino()) => {
+            FileState::InPlace
+        }
+        Ok(_) => FileState::Moved,
+        Err(_) if open.nlink() == 0 => FileState::Removed,
+        Err(_) => FileState::Moved,
+    }
+}
+
+/// Open files can't be deleted or renamed here as a rule, so only a file
+/// gone from its path is noticed.
+#[cfg(not(unix))]
+pub fn file_state(_open: &std::fs::Metadata, path: &Path) -> FileState {
+    match path.exists() {
+        true => FileState::InPlace,
+        false => FileState::Removed,
+    }
+}
+
+/// Makes a missing directory an error from now on, for setups where the
+/// directories are provisioned, e.g. mounted, by someone else.
+pub fn forbid_creating() {
+    NO_CREATE_DIRS.store(true, Ordering::Relaxed);
+}
+
+/// Makes `ensure` check that directories exist or could be created, and
+/// that they aren't read-only, without creating any.
+pub fn check_only() {
+    CHECK_ONLY.store(true, Ordering::Relaxed);
+}
+
+/// Makes sure `dir` exists, creating it and its parents unless that is
+/// forbidden.
+pub fn ensure(dir: &str) -> Result<(), io::Error> {
+    if CHECK_ONLY.load(Ordering::Relaxed) {
+        return check(dir);
+    }
+    if Path::new(dir).is_dir() {
+        return Ok(());
+    }
+    if NO_CREATE_DIRS.load(Ordering::Relaxed) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "directory {} doesn't exist and --no-create-dirs is set",
+                dir
+            ),
+        ));
+    }
+
+    tracing::info!("creating directory {}", dir);
+    std::fs::create_dir_all(dir)
+}
+
+/// What `ensure` would make of `dir`, judged by the nearest directory of it
+/// that exists, the one anything missing would be created in.
+fn check(dir: &str) -> Result<(), io::Error> {
+    let existing = Path::new(dir)
+        .ancestors()
+        .find(|ancestor| ancestor.is_dir())
+        .unwrap_or_else(|| Path::new("."));
+    if existing != Path::new(dir) && NO_CREATE_DIRS.load(Ordering::Relaxed) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "directory {} doesn't exist and --no-create-dirs is set",
+                dir
+            ),
+        ));
+    }
+    if std::fs::metadata(existing)?.permissions().readonly() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("directory {} is read-only", existing.display()),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Takes the advisory lock on `dir` for as long as the router runs, so a
+/// second instance pointed at the same directory refuses to start instead of
+/// interleaving its writes with ours. The lock file holds the pid of the
+/// instance holding it.
+pub fn lock(dir: &str) -> Result<(), io::Error> {
+    let canonical = std::fs::canonicalize(dir)?;
+    let mut locks = DIR_LOCKS.lock().unwrap();
+    if locks.iter().any(|(locked, _)| *locked == canonical) {
+        return Ok(());
+    }
+
+    let path = canonical.join(LOCK_FILE_NAME);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            let mut holder = String::new();
+            let _ = file.read_to_string(&mut holder);
+            let holder = match holder.trim() {
+                "" => String::new(),
+                pid => format!(" (pid {})", pid),
+            };
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!(
+                    "{} is locked by another instance{}; give each instance a directory of its \
+                     own, or --allow-shared-dir to share it anyway",
+                    dir, holder
+                ),
+            ));
+        }
+        Err(TryLockError::Error(error)) => {
+            return Err(io::Error::new(
+                error.kind(),
+                format!("unable to lock {}: {}", path.display(), error),
+            ))
+        }
+    }
+
+    file.set_len(0)?;
+    writeln!(file, "{}", std::process::id())?;
+    locks.push((canonical, file));
+    Ok(())
+}
+
+/// Makes the entries of `dir` durable: files created or renamed in it are
+/// only sure to survive a power loss once the directory itself is synced.
+#[cfg(unix)]
+pub fn sync(dir: &Path) -> Result<(), io::Error> {
+    std::fs::File::open(dir)?.sync_all()
+}
+
+/// Directories can't be opened for syncing here; their entries are left to
+/// the filesystem.
+#[cfg(not(unix))]
+pub fn sync(_dir: &Path) -> Result<(), io::Error> {
+    Ok(())
+}
+
+/// Points `link`, a path under `dir`, at `target`, a file beside it, by
+/// renaming a fresh link over the old one, so a reader following it never
+/// finds it missing.
+pub fn link_current(dir: &Path, link: &str, target: &str) -> Result<(), io::Error> {
+    let link = dir.join(Platform::CURRENT.native_path(link).as_ref());
+    let link_name = link.file_name().unwrap_or_default().to_string_lossy();
+    let staged = link.with_file_name(format!(".{}.tmp", link_name));
+    let _ = std::fs::remove_file(&staged);
+    match Platform::CURRENT.current_link() {
+        CurrentLink::Symlink => symlink(target, &staged)?,
+        CurrentLink::HardLink => std::fs::hard_link(link.with_file_name(target), &staged)?,
+    }
+
+    std::fs::rename(&staged, &link)
+}
+
+#[cfg(unix)]
+fn symlink(target: &str, link: &Path) -> Result<(), io::Error> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &str, link: &Path) -> Result<(), io::Error> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_target: &str, _link: &Path) -> Result<(), io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symlinks are not supported here",
+    ))
+}
+
+#[cfg(not(unix))]
+fn identify(path: &Path) -> Option<Identity> {
+    let metadata = std::fs::metadata(path).ok()?;
+    if !metadata.is_dir() {
+        return None;
+    }
+
+    Some(Identity {
+        device: 0,
+        inode: 0,
+        mount_point: false,
+    })
+}
+
+pub async fn watch(writer: Arc<Mutex<FileWriter>>, interval: Duration) {
+    loop {
+        task::sleep(interval).await;
+
+        let mut writer = writer.lock().await;
+        if let Err(error) = writer.check_log_dir().await {
+            tracing::warn!("unable to restore log directory: {}", error);
+            report::record_error("log_dir", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{self, LogDir};
+
+    #[test]
+    fn probes_notice_the_directory_going_and_coming_back() {
+        let dir = LogDir::new("log-dir-probe");
+        let mut watch = LogDirWatch::new(dir.path().to_str().unwrap());
+        assert!(matches!(watch.probe(), DirState::Available));
+
+        // Moved away rather than removed, so the new one can't take its
+        // inode.
+        let away = dir.path().with_extension("away");
+        std::fs::rename(dir.path(), &away).unwrap();
+        assert!(matches!(
+            watch.probe(),
+            DirState::Unavailable("log directory is missing")
+        ));
+
+        std::fs::create_dir(dir.path()).unwrap();
+        assert!(matches!(watch.probe(), DirState::Replaced));
+        assert!(matches!(watch.probe(), DirState::Available));
+        std::fs::remove_dir(away).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn open_files_know_when_they_were_moved_or_removed() {
+        let dir = LogDir::new("log-dir-file-state");
+        let path = dir.path().join("web");
+        let file = File::create(&path).unwrap();
+        let open = || file.metadata().unwrap();
+        assert_eq!(file_state(&open(), &path), FileState::InPlace);
+
+        std::fs::rename(&path, dir.path().join("web.1")).unwrap();
+        assert_eq!(file_state(&open(), &path), FileState::Moved);
+        File::create(&path).unwrap();
+        assert_eq!(file_state(&open(), &path), FileState::Moved);
+
+        std::fs::remove_file(dir.path().join("web.1")).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(file_state(&open(), &path), FileState::Removed);
+    }
+
+    #[test]
+    fn lines_are_held_while_the_directory_is_away() {
+        task::block_on(async {
+            let dir = LogDir::new("log-dir-hold");
+            let mut writer = testing::writer(
+                &dir,
+                &["--accepted-log-channels", "web", "--flush-bytes", "0"],
+            )
+            .await;
+            writer
+                .write_to_channel("", "web", "before\n")
+                .await
+                .unwrap();
+
+            std::fs::remove_dir_all(dir.path()).unwrap();
+            writer.check_log_dir().await.unwrap();
+            writer
+                .write_to_channel("", "web", "while away\n")
+                .await
+                .unwrap();
+            assert!(!dir.path().exists());
+
+            std::fs::create_dir(dir.path()).unwrap();
+            writer.check_log_dir().await.unwrap();
+            assert_eq!(dir.read("web"), "while away\n");
+        });
+    }
+
+    #[test]
+    fn directories_are_synced_only_when_there() {
+        let dir = LogDir::new("log-dir-sync");
+        sync(dir.path()).unwrap();
+        #[cfg(unix)]
+        assert!(sync(&dir.path().join("missing")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn current_links_are_swapped_in_place() {
+        let dir = LogDir::new("log-dir-link");
+        for file_name in ["web_1.log", "web_2.log"] {
+            std::fs::write(dir.path().join(file_name), file_name).unwrap();
+        }
+
+        link_current(dir.path(), "web.current", "web_1.log").unwrap();
+        assert_eq!(dir.read("web.current"), "web_1.log");
+        link_current(dir.path(), "web.current", "web_2.log").unwrap();
+        assert_eq!(dir.read("web.current"), "web_2.log");
+
+        let link = std::fs::read_link(dir.path().join("web.current")).unwrap();
+        assert_eq!(link, Path::new("web_2.log"));
+        assert_eq!(
+            dir.file_names(),
+            vec!["web.current", "web_1.log", "web_2.log"]
+        );
+    }
+}
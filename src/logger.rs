@@ -0,0 +1,162 @@
+use async_std::channel::{self, Receiver, Sender};
+use async_std::sync::{Arc, Mutex};
+
+use chrono::Local;
+
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::reload::{self, Handle};
+use tracing_subscriber::util::{SubscriberInitExt, TryInitError};
+use tracing_subscriber::Registry;
+
+use std::collections::VecDeque;
+use std::fmt::{self, Write};
+use std::sync::{Mutex as SyncMutex, OnceLock};
+
+use crate::{report, FileWriter};
+
+/// Channel the router's own records are written to with `--internal-log`.
+pub const INTERNAL_CHANNEL: &str = "_internal";
+
+/// Records queued for the `_internal` channel at most; past that, they are
+/// only on stderr until the writer catches up.
+const INTERNAL_BACKLOG: usize = 1024;
+
+/// Records on their way to the `_internal` channel, once it is set up.
+static INTERNAL: OnceLock<Internal> = OnceLock::new();
+
+/// Level records are kept at, changed while running by the admin `level`
+/// command.
+static LEVEL: OnceLock<Handle<LevelFilter, Registry>> = OnceLock::new();
+
+/// Records are left queued until the writer is held to write them, so none
+/// is in flight where `flush_internal` can't find it.
+struct Internal {
+    records: SyncMutex<VecDeque<String>>,
+    /// Wakes the writing task up once records are queued.
+    queued: (Sender<()>, Receiver<()>),
+}
+
+/// Writes the router's own events to stderr, a line each: the time, the
+/// level and the message, followed by any other fields as `name=value`.
+/// Events from dependencies are left out.
+struct StderrLayer;
+
+impl<S: Subscriber> Layer<S> for StderrLayer {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        let metadata = event.metadata();
+        if !metadata.target().starts_with(env!("CARGO_CRATE_NAME")) {
+            return;
+        }
+
+        let mut line = format!(
+            "{} {:<5} ",
+            Local::now().format("%Y-%m-%dT%H:%M:%S%.3f%:z"),
+            metadata.level().as_str()
+        );
+        event.record(&mut LineVisitor(&mut line));
+        eprintln!("{}", line);
+        if let Some(internal) = INTERNAL.get() {
+            let mut records = internal.records.lock().unwrap();
+            if records.len() < INTERNAL_BACKLOG {
+                records.push_back(line + "\n");
+            }
+            let _ = internal.queued.0.try_send(());
+        }
+    }
+}
+
+/// Appends an event's fields to its line, the message first.
+struct LineVisitor<'a>(&'a mut String);
+
+impl Visit for LineVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let _ = match field.name() {
+            "message" => write!(self.0, "{:?}", value),
+            name => write!(self.0, " {}={:?}", name, value),
+        };
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let _ = match field.name() {
+            "message" => write!(self.0, "{}", value),
+            name => write!(self.0, " {}={}", name, value),
+        };
+    }
+}
+
+pub fn init(level: LevelFilter) -> Result<(), TryInitError> {
+    let (filter, handle) = reload::Layer::new(level);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(StderrLayer)
+        .try_init()?;
+    let _ = LEVEL.set(handle);
+
+    Ok(())
+}
+
+/// The level records are kept at.
+#[cfg(any(feature = "control-socket", feature = "http-admin"))]
+pub fn level() -> LevelFilter {
+    LEVEL
+        .get()
+        .and_then(Handle::clone_current)
+        .unwrap_or(LevelFilter::OFF)
+}
+
+/// Keeps records at `level` from now on.
+#[cfg(any(feature = "control-socket", feature = "http-admin"))]
+pub fn set_level(level: LevelFilter) {
+    if let Some(handle) = LEVEL.get() {
+        let _ = handle.reload(level);
+    }
+}
+
+/// Starts queueing records for the `_internal` channel, from now on.
+pub fn queue_internal() {
+    let _ = INTERNAL.set(Internal {
+        records: SyncMutex::new(VecDeque::new()),
+        queued: channel::bounded(1),
+    });
+}
+
+/// Writes the queued records to the `_internal` channel as they come.
+pub async fn write_internal(writer: Arc<Mutex<FileWriter>>) {
+    let queued = match INTERNAL.get() {
+        Some(internal) => internal.queued.1.clone(),
+        None => return,
+    };
+
+    while queued.recv().await.is_ok() {
+        flush_internal(&mut *writer.lock().await).await;
+    }
+}
+
+/// Writes out the records still queued for the `_internal` channel, e.g.
+/// before the files are synced at shutdown.
+pub async fn flush_internal(writer: &mut FileWriter) {
+    if let Some(internal) = INTERNAL.get() {
+        loop {
+            let record = internal.records.lock().unwrap().pop_front();
+            match record {
+                Some(record) => write_record(writer, &record).await,
+                None => break,
+            }
+        }
+    }
+}
+
+async fn write_record(writer: &mut FileWriter, record: &str) {
+    let handle = match writer.combined_handles.get_mut(INTERNAL_CHANNEL) {
+        Some(handle) => handle,
+        None => return,
+    };
+    // Not logged, as that would queue another record to fail the same way.
+    if let Err(error) = handle.write_line(record).await {
+        eprintln!("unable to write to {}: {}", INTERNAL_CHANNEL, error);
+        report::record_error("logger", error);
+    }
+}
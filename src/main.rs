@@ -1,208 +1,6110 @@
+use async_std::channel;
 use async_std::fs::File;
 use async_std::fs::OpenOptions;
-use async_std::io;
+use async_std::io::{self, BufReader};
 use async_std::path::{Path, PathBuf};
 use async_std::prelude::*;
-use async_std::task::block_on;
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
 
-use chrono::{prelude::*, DateTime, Duration, Local};
+use chrono::{DateTime, FixedOffset, Local};
 
-use std::collections::BTreeMap;
+use std::borrow::Cow;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::time;
+
+use tracing::level_filters::LevelFilter;
 
 use structopt::StructOpt;
 
-#[derive(StructOpt)]
+#[cfg(any(feature = "control-socket", feature = "http-admin"))]
+mod admin;
+#[cfg(feature = "http-admin")]
+mod admin_http;
+mod backpressure;
+mod banner;
+mod bench;
+mod boundary;
+mod channel_name;
+mod check;
+mod clock;
+mod compact;
+mod compress;
+mod config;
+#[cfg(windows)]
+mod console;
+#[cfg(all(unix, feature = "control-socket"))]
+mod control_client;
+#[cfg(all(unix, feature = "control-socket"))]
+mod control_socket;
+mod decompress;
+#[cfg(feature = "encryption")]
+mod decrypt;
+mod dedup;
+mod degraded;
+mod delta;
+mod disk_quota;
+mod encoding;
+#[cfg(feature = "encryption")]
+mod encrypt;
+mod envelope;
+mod failure;
+mod fd;
+#[cfg(feature = "filter")]
+mod filter;
+#[cfg(feature = "forward")]
+mod forward;
+#[cfg(feature = "gelf")]
+mod gelf;
+#[cfg(feature = "grpc")]
+mod grpc_ingest;
+mod hook;
+#[cfg(any(feature = "http-admin", feature = "http-input", feature = "metrics"))]
+mod http;
+mod idle;
+mod inapt;
+#[cfg(feature = "http-input")]
+mod ingest_http;
+mod input;
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(any(feature = "forward", feature = "gelf", feature = "siem"))]
+mod level;
+mod line_limit;
+mod listen;
+mod log_dir;
+mod logger;
+#[cfg(feature = "manifest")]
+mod manifest;
+mod memory;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod pending;
+mod pipe_out;
+mod placeholders;
+mod post_rotation;
+mod preflight;
+mod profile;
+mod query;
+mod queue;
+mod quota;
+mod rate_limit;
+#[cfg(any(feature = "control-socket", feature = "http-admin"))]
+mod recent;
+#[cfg(feature = "redact")]
+mod redact;
+mod reorder;
+mod report;
+mod retention;
+mod rotation_events;
+#[cfg(feature = "routing")]
+mod route;
+mod sample;
+mod sequence;
+#[cfg(feature = "siem")]
+mod siem;
+#[cfg(unix)]
+mod signals;
+#[cfg(feature = "gelf")]
+mod spill;
+#[cfg(feature = "split")]
+mod split;
+mod stamp;
+#[cfg(feature = "state")]
+mod state;
+mod stats;
+mod syslog;
+mod tee;
+mod tenant;
+mod terminator;
+#[cfg(test)]
+mod testing;
+#[cfg(any(feature = "forward", feature = "s3"))]
+mod tls;
+#[cfg(feature = "trace")]
+mod trace;
+#[cfg(feature = "s3")]
+mod upload;
+mod user_signal;
+mod writer_pool;
+mod zone;
+
+use backpressure::Backpressure;
+use banner::StartupBanner;
+use boundary::{Boundaries, FileStats};
+use channel_name::{ChannelNames, Normalization};
+use compress::{Compression, Encoder};
+#[cfg(feature = "config")]
+use config::Config;
+use config::{ChannelConfig, ChannelSink, TenantConfig};
+use decompress::InputCompression;
+use dedup::Deduplicator;
+use degraded::{Degraded, WriteError, WriteFailure};
+use delta::ChannelDelta;
+use disk_quota::{DiskQuota, DiskQuotaAction};
+use encoding::InvalidUtf8;
+#[cfg(feature = "encryption")]
+use encrypt::Sealer;
+use envelope::OutputFormat;
+use failure::OnError;
+#[cfg(feature = "filter")]
+use filter::{ChannelPattern, Filters};
+#[cfg(feature = "forward")]
+use forward::Forwarder;
+#[cfg(feature = "gelf")]
+use gelf::GelfSink;
+use idle::IdleWatch;
+use inapt::InaptFormat;
+use input::{InputFormat, MultilineMode, StayAlive};
+#[cfg(feature = "kafka")]
+use kafka::{KafkaConfig, KafkaSink};
+use line_limit::MaxLineAction;
+use log_dir::{DirState, FileState, LogDirWatch};
+use log_revolve_rs::platform::Platform;
+use log_revolve_rs::rotation::{
+    self, DirLayout, FileNameLayout, FileNameParser, FileTimestamp, Rotation, Schedule,
+};
+use log_revolve_rs::router::Router;
+use log_revolve_rs::runtime;
+use log_revolve_rs::sink::{NullSink, Sink, SinkFuture};
+use memory::MemoryBudget;
+use pending::{PendingChannels, UnknownChannels};
+use pipe_out::PipeOut;
+use placeholders::{InstanceMetadata, Placeholders};
+use profile::{BufferingProfile, SyncPolicy};
+use queue::QueueFull;
+use quota::{Quota, QuotaAction};
+use rate_limit::{RateLimit, RateLimitAction};
+#[cfg(any(feature = "control-socket", feature = "http-admin"))]
+use recent::RecentLines;
+#[cfg(feature = "redact")]
+use redact::{ChannelRedaction, Redaction, Transforms};
+use reorder::Reorderer;
+use retention::Retention;
+use rotation_events::Reason;
+#[cfg(feature = "routing")]
+use route::Route;
+use sample::{SampleRate, Sampler};
+#[cfg(feature = "siem")]
+use siem::SiemFormatter;
+#[cfg(feature = "gelf")]
+use spill::Spill;
+use stamp::TimestampFormat;
+use stats::HourlyStats;
+use syslog::SyslogChannel;
+use tee::{TeeChannel, TeeStream};
+use tenant::Tenants;
+use terminator::LineTerminator;
+#[cfg(feature = "trace")]
+use trace::{TracePredicate, Tracer};
+#[cfg(feature = "s3")]
+use upload::{AfterUpload, Bucket};
+use user_signal::UserSignal;
+use writer_pool::Writer;
+use zone::Zone;
+
+#[derive(StructOpt, Debug)]
 #[structopt(rename_all = "kebab_case")]
 struct CliOptions {
+    /// Directory the channel files are written to; `{hostname}`,
+    /// `{instance_id}` and `{pod}` are replaced here, in `--inapt-dir`,
+    /// `--inapt-file-name`, `--gelf-host` and `--siem-fields`
     #[structopt(long)]
     log_dir: String,
 
-    #[structopt(long)]
-    accepted_log_channels: String,
+    /// Comma-separated channels accepted, on top of those of `--config`
+    #[structopt(long, default_value = "")]
+    accepted_log_channels: String,
+
+    /// TOML file declaring channels with settings of their own: rotation
+    /// interval, max file size, directory and file name; the flags apply to
+    /// whatever a channel leaves out. Read again on SIGHUP, opening the
+    /// channels it adds and retiring those it drops
+    #[cfg(feature = "config")]
+    #[structopt(long)]
+    config: Option<String>,
+
+    /// Validate the options, the config file and the directories, print
+    /// each channel's effective settings and exit, without reading any
+    /// input or opening any file
+    #[structopt(long)]
+    check_config: bool,
+
+    /// Channels declared in the config file, once loaded.
+    #[structopt(skip)]
+    configured_channels: BTreeMap<String, ChannelConfig>,
+
+    /// Tenants declared in the config file, once loaded.
+    #[structopt(skip)]
+    configured_tenants: BTreeMap<String, TenantConfig>,
+
+    #[structopt(long, default_value = "inapt")]
+    inapt_file_name: String,
+
+    /// Value of `{hostname}`, from `HOSTNAME` or the system when omitted
+    #[structopt(long)]
+    hostname: Option<String>,
+
+    /// Value of `{instance_id}`, from `INSTANCE_ID` or
+    /// `--instance-metadata` when omitted
+    #[structopt(long)]
+    instance_id: Option<String>,
+
+    /// Metadata service asked for `{instance_id}` at startup: `aws` or `gcp`
+    #[structopt(long)]
+    instance_metadata: Option<InstanceMetadata>,
+
+    /// Value of `{pod}`, from `POD_NAME` when omitted
+    #[structopt(long)]
+    pod: Option<String>,
+
+    /// Compress files once their channel has rotated away from them:
+    /// `<algorithm>[:<level>]` with `gzip` (0-9) or `zstd` (1-22), each behind
+    /// the cargo feature of the same name, or `none`
+    #[structopt(long, default_value = "none")]
+    compress: Compression,
+
+    /// Algorithm files are compressed with once their channel has rotated
+    /// away from them, `gzip`, `zstd` or `none`, as `--compress` takes it
+    #[structopt(long, conflicts_with = "compress")]
+    compression: Option<String>,
+
+    /// Level of `--compression`, 0-9 for `gzip` and 1-22 for `zstd`, its
+    /// default when omitted
+    #[structopt(long, requires = "compression")]
+    compression_level: Option<String>,
+
+    /// Comma-separated `channel=compression` pairs overriding `--compress`,
+    /// e.g. `bulk=zstd:3,archive=gzip:9,debug=none`; channels of the config
+    /// file can set their own `compress`
+    #[structopt(long, default_value = "")]
+    compress_channels: String,
+
+    /// Zone files are cut and stamped in: `local`, `UTC`, a fixed offset
+    /// such as `+09:00`, or an IANA zone such as `Europe/Berlin`. Daily and
+    /// longer periods follow the zone's daylight saving time, starting at its
+    /// midnight all year round
+    #[structopt(long, default_value = "local")]
+    timezone: Zone,
+
+    /// Comma-separated `channel=zone` pairs cutting and stamping a channel's
+    /// files in a zone of its own rather than `--timezone`'s, taking the same
+    /// values, e.g. `billing=Asia/Tokyo,edge=UTC`
+    #[structopt(long, default_value = "")]
+    channel_timezones: String,
+
+    /// Comma-separated `channel=terminator` pairs ending a channel's lines
+    /// with something else than `\n`: `crlf`, `nul` or `hex:<bytes>`, e.g.
+    /// `legacy=crlf,records=hex:1e`
+    #[structopt(long, default_value = "")]
+    line_terminators: String,
+
+    /// Comma-separated `channel=profile` pairs bundling how a channel's lines
+    /// are batched, flushed and fsynced: `latency`, `balanced` or
+    /// `throughput`, e.g. `audit=balanced,bulk=throughput`
+    #[structopt(long, default_value = "")]
+    buffering_profiles: String,
+
+    /// Bytes of a channel's lines batched before they are written out, 64 KiB
+    /// when omitted; buffering profiles set their own
+    #[structopt(long)]
+    flush_bytes: Option<usize>,
+
+    /// Milliseconds a batched line may wait before it is written out; without
+    /// it batches are written as they fill, rotate or shut down. Buffering
+    /// profiles set their own
+    #[structopt(long)]
+    flush_interval: Option<u64>,
+
+    /// When files are fsynced: `none`, leaving it to the OS, `interval`, every
+    /// `--sync-interval` and on rotation, or `every-line`. Channels of the
+    /// config file can set their own `sync_policy`
+    #[structopt(long, default_value = "none")]
+    sync_policy: SyncPolicy,
+
+    /// Milliseconds between the fsyncs of channels synced on an interval
+    #[structopt(long, default_value = "1000")]
+    sync_interval: u64,
+
+    /// Closes the file of a channel silent for this long, e.g. `30m`, so
+    /// quiet channels don't each hold a descriptor; the channel's next line
+    /// reopens it and is appended
+    #[structopt(long, parse(try_from_str = idle::parse_interval))]
+    idle_close_after: Option<time::Duration>,
+
+    /// Days rotated files are kept in the log directory, judged by the time
+    /// in their name
+    #[structopt(long)]
+    max_age: Option<i64>,
+
+    /// Files kept per channel in the log directory, the current one included
+    #[structopt(long)]
+    max_files: Option<usize>,
+
+    /// Most the files of every channel in the log directory may take
+    /// together, e.g. `20GB`; the oldest rotated files go first, whatever
+    /// their channel, and current files are never removed
+    #[structopt(long, parse(try_from_str = parse_file_size))]
+    max_total_disk: Option<u64>,
+
+    /// Compress files as lines are written to them, as `<file>.log.gz` or
+    /// `<file>.log.zst`, rather than once their channel has rotated away from
+    /// them; the compressed member left open is ended at rotation and exit
+    #[structopt(long)]
+    stream_compress: bool,
+
+    /// Stage compressed files in anonymous `O_TMPFILE` files linked into place
+    /// once complete, so a crash while compressing leaves no `.partial` file
+    /// behind; filesystems without them fall back to `.partial` files
+    #[cfg(all(target_os = "linux", feature = "tmpfile"))]
+    #[structopt(long)]
+    tmpfile_staging: bool,
+
+    /// File holding the key encrypted channels' files are sealed with, 64
+    /// hexadecimal digits such as `openssl rand -hex 32` prints
+    #[cfg(feature = "encryption")]
+    #[structopt(long)]
+    encryption_key_file: Option<String>,
+
+    /// Comma-separated channels whose files are encrypted with AES-256-GCM as
+    /// lines are written, as `<file>.log.enc`, e.g. `audit`; channels of the
+    /// config file can set their own `encrypt`. Read them back with
+    /// `log-revolve-rs decrypt`
+    #[cfg(feature = "encryption")]
+    #[structopt(long, default_value = "")]
+    encrypt_channels: String,
+
+    /// Fail on a missing log, inapt or channel directory instead of creating
+    /// it, at startup and when files are opened
+    #[structopt(long)]
+    no_create_dirs: bool,
+
+    /// Start without first checking the directories, the channels' current
+    /// files, free space and the open file limit
+    #[structopt(long)]
+    no_preflight: bool,
+
+    /// Least free space the preflight accepts in each directory written to
+    #[cfg(feature = "preflight")]
+    #[structopt(long, default_value = "64MB", parse(try_from_str = parse_file_size))]
+    min_free_space: u64,
+
+    /// Let other instances write to the log and inapt directories alongside
+    /// this one, rather than taking a lock on them that makes a second
+    /// instance refuse to start
+    #[structopt(long)]
+    allow_shared_dir: bool,
+
+    /// Directory the inapt file is written to, the log directory when omitted
+    #[structopt(long)]
+    inapt_dir: Option<String>,
+
+    /// How lines are written to the inapt file: `marked`, `[<reason>:<channel>]
+    /// <line>`, or `structured`, `<time> reason=<reason> channel=<channel>
+    /// <line>` with the time in `--timestamp-format`
+    #[structopt(long, default_value = "marked")]
+    inapt_format: InaptFormat,
+
+    /// Also rotate the inapt file once it would grow past this size, e.g.
+    /// `10MB`, whatever `--max-file-size` the channels take
+    #[structopt(long, parse(try_from_str = parse_file_size))]
+    inapt_max_file_size: Option<u64>,
+
+    /// Bytes the inapt files may take together, e.g. `1GB`, the oldest
+    /// removed as the current one rotates past it
+    #[structopt(long, parse(try_from_str = parse_file_size))]
+    inapt_disk_quota: Option<u64>,
+
+    /// What to do with lines for channels that aren't accepted: `reject` them
+    /// to the inapt file, capture them as `pending` in files of their own
+    /// under `<log-dir>/pending/` until they are accepted or expire, or
+    /// `create` a channel for them on the fly
+    #[structopt(long, default_value = "reject")]
+    unknown_channels: UnknownChannels,
+
+    /// Create a channel on the fly for lines of one that isn't accepted, as
+    /// `--unknown-channels create` does
+    #[structopt(long, conflicts_with = "unknown-channels")]
+    auto_create_channels: bool,
+
+    /// Channels created on the fly at most; lines for any further unknown
+    /// channel go to the inapt file. Names that wouldn't make a safe file
+    /// name always do
+    #[structopt(long, default_value = "256")]
+    max_dynamic_channels: usize,
+
+    /// Seconds a pending channel may go without lines before it is closed and
+    /// its files are removed
+    #[structopt(long, default_value = "86400")]
+    pending_ttl: u64,
+
+    /// Pending channels captured at once; lines for any further unknown
+    /// channel go to the inapt file
+    #[structopt(long, default_value = "64")]
+    pending_max_channels: usize,
+
+    /// Verbosity of the router's own diagnostics on stderr, and in the
+    /// `_internal` channel with `--internal-log`
+    #[structopt(long, default_value = "info")]
+    log_level: LevelFilter,
+
+    /// Also write the router's own diagnostics, startup record, rotations,
+    /// reloads, errors and shutdown among them, to an `_internal` channel in
+    /// the log directory, rotated like any other
+    #[structopt(long)]
+    internal_log: bool,
+
+    /// Serve the admin commands (status, rotate, flush, reload, pause,
+    /// resume, set-level, list-channels) on this unix socket, a request a
+    /// line, either JSON or words such as `rotate web`
+    #[cfg(all(unix, feature = "control-socket"))]
+    #[structopt(long)]
+    control_socket: Option<String>,
+
+    /// Serve the admin commands as `/admin/<command>` HTTP endpoints on this
+    /// address, e.g. `127.0.0.1:8081`
+    #[cfg(feature = "http-admin")]
+    #[structopt(long)]
+    admin_http: Option<String>,
+
+    /// File holding the bearer token required by the HTTP admin API
+    #[cfg(feature = "http-admin")]
+    #[structopt(long)]
+    admin_token_file: Option<String>,
+
+    /// Serve Prometheus metrics at `/metrics` on this address, e.g.
+    /// `127.0.0.1:9090`, and the channels' files and progress as JSON at
+    /// `/status`
+    #[cfg(feature = "metrics")]
+    #[structopt(long)]
+    metrics_addr: Option<String>,
+
+    /// Bytes held back per paused channel; lines past the cap are diverted to
+    /// the inapt file prefixed with `[paused:<channel>]`
+    #[structopt(long, default_value = "1048576")]
+    pause_buffer_bytes: usize,
+
+    /// Emit backpressure notices once the router holds back this many bytes
+    #[structopt(long)]
+    backpressure_high_water: Option<usize>,
+
+    /// Held back bytes at which the matching resume notice is emitted, half the
+    /// high water mark when omitted
+    #[structopt(long)]
+    backpressure_low_water: Option<usize>,
+
+    /// Bytes held in memory across all channels, e.g. `256MB`: batches are
+    /// written out early from three quarters of it, backpressure engages there
+    /// unless `--backpressure-high-water` says otherwise, and lines that would
+    /// have to be held beyond it are diverted or dropped
+    #[structopt(long)]
+    memory_budget: Option<MemoryBudget>,
+
+    /// Where backpressure notices go: `stdout`, `stderr`, an inherited
+    /// `fd:<n>`, or the path of a file or named pipe they are appended to
+    #[structopt(long, default_value = "stdout")]
+    backpressure_output: String,
+
+    /// Seconds between checks that the log directory is still the one files
+    /// were opened in (not unmounted or replaced) and that the files written
+    /// to are still at their paths (not deleted or renamed), 0 to disable
+    #[structopt(long, default_value = "5")]
+    log_dir_check_interval: u64,
+
+    /// Bytes held in memory per channel while the log directory is unavailable
+    #[structopt(long, default_value = "4194304")]
+    log_dir_hold_bytes: usize,
+
+    /// Where the lines of a channel whose file can't be written to go while
+    /// it is retried with backoff: `hold` them in memory, up to
+    /// `--spill-buffer-size`, or the `inapt` file. The other channels carry on
+    #[structopt(long, default_value = "hold")]
+    on_write_error: WriteFailure,
+
+    /// What an input failing does to the router: `abort` stops it at once,
+    /// `continue` keeps the other inputs going and flushes the files once
+    /// they are closed too, `retry` reads the failing input again with
+    /// backoff. The exit status is 0 once the inputs are closed, 74 after a
+    /// failure while running and 78 when the router couldn't start
+    #[structopt(long, default_value = "abort")]
+    on_error: OnError,
+
+    /// Bytes held in memory per channel whose file can't be written to, such
+    /// as on a full disk; lines past it are dropped and counted
+    #[structopt(long, default_value = "4194304")]
+    spill_buffer_size: usize,
+
+    /// Write a JSON summary of the run to this file on exit: per-channel
+    /// totals, dropped lines, unflushed bytes and the last error of every
+    /// subsystem
+    #[cfg(feature = "report")]
+    #[structopt(long)]
+    shutdown_report: Option<String>,
+
+    /// Seconds flushing may take once the inputs are closed or SIGTERM
+    /// arrives; past it the router gives up on what isn't written yet, logs
+    /// how much that is per channel, as `unflushed_at_timeout` of the
+    /// `--shutdown-report`, and exits with an error
+    #[structopt(long)]
+    shutdown_timeout: Option<u64>,
+
+    /// Line ending every channel's current file on shutdown, telling readers
+    /// the router stopped on purpose rather than died, e.g. `# shutdown`
+    #[structopt(long)]
+    shutdown_marker: Option<String>,
+
+    /// File keeping hourly line and byte counts of every channel across
+    /// restarts, as reported by the `stats` admin command
+    #[structopt(long)]
+    stats_file: Option<String>,
+
+    /// Directory keeping `state.json`: each channel's current file and the
+    /// bytes written to it, and the rotated files still to be compressed or
+    /// handed to `--on-rotate-cmd`, so a restart after a crash finishes
+    /// them, and the files of periods that ended while it was down
+    #[cfg(feature = "state")]
+    #[structopt(long)]
+    state_dir: Option<String>,
+
+    /// Hours of counts kept for the `stats` admin command
+    #[structopt(long, default_value = "168")]
+    stats_retention_hours: i64,
+
+    /// Timestamp in file names: `seconds` (2024-06-01-13-00-00), `hours`
+    /// (2024-06-01-13), `rfc3339-seconds` (2024-06-01T13:00:00) or
+    /// `rfc3339-hours` (2024-06-01T13). With an hours format, a forced
+    /// rotation within the hour carries on in the same file
+    #[structopt(long, default_value = "seconds")]
+    file_timestamp: FileTimestamp,
+
+    /// Lay out file names by a template instead, for downstream shippers
+    /// expecting names of their own, e.g. `{channel}.{year}{month}{day}.{ext}`:
+    /// `{channel}`, `{year}`, `{month}`, `{day}`, `{hour}`, `{minute}`,
+    /// `{second}`, `{seq}`, counting the files cut within a period, and
+    /// `{ext}`. Sequence numbers only carry on across restarts in default
+    /// names
+    #[structopt(long)]
+    file_name_template: Option<FileNameLayout>,
+
+    /// Shard files into subdirectories of the log directory, created as they
+    /// are needed, e.g. `{channel}/{year}/{month}/{day}`: `{channel}`,
+    /// `{year}`, `{month}`, `{day}` and `{hour}`. Retention looks for files
+    /// along the same layout and removes the subdirectories it empties
+    #[structopt(long)]
+    dir_template: Option<DirLayout>,
+
+    /// Leave rotation to an external tool such as logrotate: each channel
+    /// writes to a plain `<channel>.log`, which is reopened on SIGHUP
+    #[structopt(long)]
+    external_rotation: bool,
+
+    /// Reopen every file at its current path on this signal, `SIGUSR1` or
+    /// `SIGUSR2`, for tools such as logrotate renaming files from under the
+    /// router while it still rotates them itself
+    #[structopt(long)]
+    reopen_on_signal: Option<UserSignal>,
+
+    /// Start fresh files for every channel on this signal, `SIGUSR1` or
+    /// `SIGUSR2`, whatever their rotation, e.g. ahead of a backup window
+    #[structopt(long)]
+    rotate_on_signal: Option<UserSignal>,
+
+    /// Keep a `<channel>.log` symlink to each channel's current file, for
+    /// tools such as `tail -F` wanting a path that doesn't change; a hard link
+    /// where there are no symlinks
+    #[structopt(long)]
+    current_symlink: bool,
+
+    /// How long each file covers, e.g. `5m`, `15m`, `1h` or `1d`, aligned to
+    /// boundaries counted from midnight; it must divide a day evenly, and
+    /// periods under an hour take a seconds file timestamp
+    #[structopt(long, default_value = "1h", parse(try_from_str = parse_rotation_interval))]
+    rotation_interval: chrono::Duration,
+
+    /// Comma-separated `channel=interval` pairs rotating a channel on an
+    /// interval of its own, e.g. `audit=1d,edge=5m`
+    #[structopt(long, default_value = "")]
+    channel_rotation_intervals: String,
+
+    /// Comma-separated `channel=dir` pairs writing a channel's files to a
+    /// directory of its own instead of the log directory, created if
+    /// missing, e.g. `debug=/mnt/scratch/logs`. A `directory` given in the
+    /// config file wins
+    #[structopt(long, default_value = "")]
+    channel_dirs: String,
+
+    /// Also rotate a channel's file once it would grow past this size, e.g.
+    /// `100MB`, whichever comes first with the hour. Files are told apart by
+    /// their timestamp, and those cut within the same second, or hour under
+    /// an `hours` file timestamp, by a sequence number after it, e.g.
+    /// `app_2024-06-01-10-00-00.001.log`
+    #[structopt(long, parse(try_from_str = parse_file_size))]
+    max_file_size: Option<u64>,
+
+    /// Prefix every channel line with a per-channel sequence number, carried
+    /// on across rotation and restarts, so gaps and duplicates can be spotted
+    #[structopt(long)]
+    sequence_numbers: bool,
+
+    /// Prefix every channel line with the time it was received, in the
+    /// channel's time zone
+    #[structopt(long)]
+    prepend_timestamp: bool,
+
+    /// How `--prepend-timestamp`, `jsonl` records and the `{ts}` of file
+    /// headers and footers write the time, and `split` reads it: `rfc3339`,
+    /// `epoch-millis` or a strftime format, e.g. `%d/%b/%Y:%H:%M:%S`
+    #[structopt(long, default_value = "rfc3339")]
+    timestamp_format: TimestampFormat,
+
+    /// How lines are written to files: `plain`, as received, or `jsonl`,
+    /// each wrapped in a `{"ts":...,"channel":...,"msg":...}` record
+    #[structopt(long, default_value = "plain")]
+    output_format: OutputFormat,
+
+    /// Comma-separated channels written through immediately, with no batching
+    /// and never dropped by quotas or full buffers, e.g. `audit`; with writer
+    /// tasks, each has one of its own
+    #[structopt(long, default_value = "")]
+    priority_channels: String,
+
+    /// Also fsync priority channels after every line, and the log directory
+    /// as their files are created and rotated
+    #[structopt(long)]
+    priority_fsync: bool,
+
+    /// Comma-separated services handing over two streams, e.g. `api,worker`:
+    /// each accepts `<service>.out` and `<service>.err`, both taking the
+    /// priority, compression and quota settings given for `<service>`, and
+    /// writes both into a combined `<service>` file as well, lines marked
+    /// `[out]` or `[err]`
+    #[structopt(long, default_value = "")]
+    paired_channels: String,
+
+    /// Comma-separated groups of channels sharing their settings, e.g.
+    /// `frontend=web+cdn+edge`: settings given for `frontend` apply to every
+    /// member without settings of its own, a quota being shared by them all
+    #[structopt(long, default_value = "")]
+    channel_groups: String,
+
+    /// Comma-separated groups also written to a rollup `<group>` file, each
+    /// line marked with its channel, e.g. `[web]`
+    #[structopt(long, default_value = "")]
+    rollup_groups: String,
+
+    /// Comma-separated `channel=quota` pairs capping how much a channel may
+    /// write per period, e.g. `app=2GB/hour`
+    #[structopt(long, default_value = "")]
+    quotas: String,
+
+    /// Comma-separated tenants sharing the router, e.g. `acme,globex`: lines
+    /// of channel `<tenant>/<channel>`, or of a connection opening with
+    /// `@tenant <name>`, go to `<log_dir>/<tenant>/` for every accepted
+    /// channel
+    #[structopt(long, default_value = "")]
+    tenants: String,
+
+    /// Comma-separated `tenant=quota` pairs capping how much all of a
+    /// tenant's channels may write per period together, e.g. `acme=20GB/day`
+    #[structopt(long, default_value = "")]
+    tenant_quotas: String,
+
+    /// Comma-separated `tenant=age` pairs removing a tenant's files last
+    /// written to longer ago, e.g. `acme=7d,globex=30d`
+    #[structopt(long, default_value = "")]
+    tenant_retention: String,
+
+    /// What to do with lines over quota: `overflow` to write them to a
+    /// `<channel>.overflow` file, or `drop`
+    #[structopt(long, default_value = "overflow")]
+    quota_action: QuotaAction,
+
+    /// Drops lines matching this pattern before they are written, e.g.
+    /// `GET /healthz`; may be repeated
+    #[cfg(feature = "filter")]
+    #[structopt(long = "drop-pattern")]
+    drop_patterns: Vec<regex::Regex>,
+
+    /// Drops lines matching none of the keep patterns before they are
+    /// written; may be repeated
+    #[cfg(feature = "filter")]
+    #[structopt(long = "keep-pattern")]
+    keep_patterns: Vec<regex::Regex>,
+
+    /// As `--drop-pattern` for a single channel, `<channel>=<pattern>`; may be
+    /// repeated
+    #[cfg(feature = "filter")]
+    #[structopt(long = "channel-drop-pattern")]
+    channel_drop_patterns: Vec<ChannelPattern>,
+
+    /// As `--keep-pattern` for a single channel, `<channel>=<pattern>`; may be
+    /// repeated
+    #[cfg(feature = "filter")]
+    #[structopt(long = "channel-keep-pattern")]
+    channel_keep_patterns: Vec<ChannelPattern>,
+
+    /// Rewrites every line before it is written, sed-style, e.g.
+    /// `s/\d{16}/****/` to mask card numbers; may be repeated, the
+    /// redactions applying in turn
+    #[cfg(feature = "redact")]
+    #[structopt(long = "redact")]
+    redactions: Vec<Redaction>,
+
+    /// As `--redact` for a single channel, `<channel>=s/<pattern>/<replacement>/`,
+    /// applied after those for every channel; may be repeated. Channels of a
+    /// config file can set their own `redact` list
+    #[cfg(feature = "redact")]
+    #[structopt(long = "channel-redact")]
+    channel_redactions: Vec<ChannelRedaction>,
+
+    /// Comma-separated `channel=rate` pairs capping how fast a channel may
+    /// write, in lines or bytes per second or minute, e.g.
+    /// `app=1000/s,bulk=5MB/s`. Short bursts of up to a second's worth pass
+    #[structopt(long, default_value = "")]
+    rate_limit: String,
+
+    /// What to do with lines over a rate limit: `drop` them, `overflow` to
+    /// write them to a `<channel>.overflow` file, or `block` to write them
+    /// and have the input that sent them wait until the channel may write
+    /// again, holding back its producer meanwhile
+    #[structopt(long, default_value = "drop")]
+    rate_limit_action: RateLimitAction,
+
+    /// Comma-separated `channel=rate` pairs keeping only a fraction of a
+    /// chatty channel's lines: `1/100` for every hundredth, or a probability
+    /// such as `0.05`. The config file can set a channel's `sample_rate`
+    #[structopt(long, default_value = "")]
+    sample_rates: String,
+
+    /// How inputs are framed: `lines` or `paired` (channel line followed by
+    /// message line), `prefixed` (`<channel>|<message>` lines), `producers`
+    /// (`<producer>:<channel>:<message>` lines from producers sharing a
+    /// pipe, an empty channel standing for the one the producer named last),
+    /// `cri` (containerd / kubelet container log files), `json` (one
+    /// `{"channel": ..., "message": ...}` object per line), `auto` (`lines`
+    /// or `json`, detected from the first line of each input),
+    /// `length-prefixed` (records of a 4-byte big-endian length followed by
+    /// `<channel>\0<payload>`, payloads written as they come, newlines and
+    /// all), `routed` (bare messages, their channel chosen by `--route`) or
+    /// `journald` (systemd journal entries, as `journalctl -o export -f`
+    /// writes them)
+    #[structopt(long, default_value = "lines")]
+    input_format: InputFormat,
+
+    /// `prefixed` or `paired`, as `--input-format` takes them, for the
+    /// producers of one line per message and those of the channel line
+    /// followed by the message line
+    #[structopt(long, parse(try_from_str = input::parse_protocol))]
+    protocol: Option<InputFormat>,
+
+    /// Journal fields naming the channel of a `journald` entry, the first
+    /// one an entry has winning; a unit's `.service` suffix is left out
+    #[cfg(feature = "journald")]
+    #[structopt(long, default_value = "SYSLOG_IDENTIFIER,_SYSTEMD_UNIT")]
+    journal_channel_fields: String,
+
+    /// Makes the channel names producers send match however they are cased
+    /// or padded, before they are looked up: comma-separated steps taken in
+    /// turn, `fold-case`, `trim` and `strip-prefix:<prefix>` (e.g.
+    /// `strip-prefix:channel=`), for every protocol or, as
+    /// `<protocol>=<steps>`, for one: an input format, `http`, `grpc` or
+    /// `syslog`, its steps taken after those for every protocol; may be
+    /// repeated
+    #[structopt(long = "normalize-channels")]
+    channel_normalizations: Vec<Normalization>,
+
+    /// What separates the channel from the message in `prefixed` lines
+    #[structopt(long, default_value = "|")]
+    prefix_delimiter: String,
+
+    /// Rule choosing the channel of `routed` lines by their content,
+    /// `<pattern>=><channel>`, e.g. `ERROR.*=>errors`; may be repeated, the
+    /// first matching rule winning. Lines no rule matches go to the inapt
+    /// file
+    #[cfg(feature = "routing")]
+    #[structopt(long = "route")]
+    routes: Vec<Route>,
+
+    /// Gathers messages spanning several lines, such as stack traces, in the
+    /// `lines`, `prefixed`, `producers` and `routed` framings and on
+    /// single-channel connections:
+    /// `indented`, lines starting with a space or a tab continuing the
+    /// message before them, or `delimited`, messages running until a
+    /// `--message-delimiter` line. A message is written once the line ending
+    /// it arrives
+    #[structopt(long)]
+    multiline: Option<MultilineMode>,
+
+    /// Line ending each message under `--multiline delimited`, dropped from
+    /// the message; a blank line when omitted
+    #[structopt(long, default_value = "")]
+    message_delimiter: String,
+
+    /// Field of `json` records naming their channel, a dotted path for a
+    /// nested one, e.g. `service` or `kubernetes.labels.app`
+    #[cfg(feature = "json")]
+    #[structopt(long, default_value = "channel")]
+    channel_field: String,
+
+    /// Write `json` records whole, as received, rather than their `message`
+    #[cfg(feature = "json")]
+    #[structopt(long)]
+    json_whole_records: bool,
+
+    /// Additional input read alongside stdin, as a descriptor inherited from
+    /// the parent process (`fd:3`), or a file read instead of stdin by
+    /// `split`; may be repeated. `journald` reads stdin as systemd journal
+    /// entries instead, as `--input-format journald` does
+    #[structopt(long = "input")]
+    inputs: Vec<String>,
+
+    /// Keep reading stdin once its producer closes it, for a stdin another
+    /// producer may attach to later, such as a named pipe; the router then
+    /// runs until it is told to stop
+    #[structopt(long)]
+    stay_alive: bool,
+
+    /// How producers compress what they send through stdin, `--input` and
+    /// `--listen`: `none`, `gzip` or `zstd`, each behind the cargo feature of
+    /// the same name, or `auto` to tell from the first bytes of each input
+    #[structopt(long, default_value = "none")]
+    input_compression: InputCompression,
+
+    /// Lines of each input read ahead of the writer, so a slow disk doesn't
+    /// stall reading; by default each line is written before the next is read
+    #[structopt(long)]
+    queue_depth: Option<usize>,
+
+    /// What the reader of an input does once its queue is full: `block` until
+    /// there is room, holding back the producer, `drop-oldest` or
+    /// `drop-newest`. Lines are dropped one at a time, so dropping takes an
+    /// `--input-format` framing a message on a single line, not `lines` or
+    /// `auto`
+    #[structopt(long, default_value = "block")]
+    queue_full: QueueFull,
+
+    /// Writer tasks batches are written out by, so channels on different
+    /// ones reach the disk concurrently. A channel always goes to the same
+    /// one, keeping its lines in order, and batches of a channel queued for
+    /// it together go out in one vectored write of up to 1 MiB; by default
+    /// the router writes every batch out itself
+    #[structopt(long, default_value = "0")]
+    writer_threads: usize,
+
+    /// Longest line read from an input, e.g. `1MB`; past it, lines are dealt
+    /// with by `--max-line-action` rather than held in memory whole
+    #[structopt(long, parse(try_from_str = parse_line_size))]
+    max_line_bytes: Option<usize>,
+
+    /// What to do with a line past `--max-line-bytes`: `truncate` it, `split`
+    /// it into lines of the channel it went to, or `drop-to-inapt`, its first
+    /// bytes written to the inapt file
+    #[structopt(long, default_value = "truncate")]
+    max_line_action: MaxLineAction,
+
+    /// What to do with a line that isn't valid UTF-8: `lossy` replaces each
+    /// invalid sequence with U+FFFD, `passthrough` writes the bytes as
+    /// received, `inapt` rejects the line to the inapt file
+    #[structopt(long, default_value = "lossy")]
+    invalid_utf8: InvalidUtf8,
+
+    /// Make lines received ending in `\r\n` end in `\n`
+    #[structopt(long)]
+    normalize_line_endings: bool,
+
+    /// Accept producers connecting to `tcp://host:port` or `unix:<path>`, each
+    /// connection read as an input of its own; may be repeated. A connection
+    /// opening with `@channel <name>` sends that channel's messages only, one
+    /// per line
+    #[structopt(long = "listen")]
+    listen: Vec<String>,
+
+    /// Accept producers connecting to this TCP address, e.g.
+    /// `0.0.0.0:5140`, as `--listen tcp://<addr>` does; may be repeated
+    #[structopt(long)]
+    listen_tcp: Vec<String>,
+
+    /// Accept producers connecting to the unix socket at this path, as
+    /// `--listen unix:<path>` does; may be repeated
+    #[cfg(unix)]
+    #[structopt(long)]
+    listen_unix: Vec<String>,
+
+    /// Take lines over HTTP on this address, e.g. `0.0.0.0:8080`: `POST
+    /// /ingest/<channel>` with a newline-delimited body, gzip-encoded or
+    /// not, writes its lines to the channel and answers `202` with how many
+    /// were written
+    #[cfg(feature = "http-input")]
+    #[structopt(long)]
+    listen_http: Option<String>,
+
+    /// Serve the gRPC `Ingest` call, streaming lines with their channels, on
+    /// this address over cleartext HTTP/2, e.g. `0.0.0.0:50051`
+    #[cfg(feature = "grpc")]
+    #[structopt(long)]
+    listen_grpc: Option<String>,
+
+    /// Receive syslog datagrams, RFC 5424 or BSD, on this UDP address, e.g.
+    /// `0.0.0.0:514`; may be repeated
+    #[structopt(long)]
+    listen_syslog_udp: Vec<String>,
+
+    /// What names the channel of a syslog message: `app-name`, falling back
+    /// to the facility's name, or `facility`, e.g. `local3`
+    #[structopt(long, default_value = "app-name")]
+    syslog_channel: SyslogChannel,
+
+    /// Also copy a channel's lines live to a descriptor inherited from the
+    /// parent process, e.g. `alerts=fd:3`, for a co-process following it; may
+    /// be repeated
+    #[structopt(long = "pipe-out")]
+    pipe_outs: Vec<String>,
+
+    /// Copies a channel's lines to the router's stdout or stderr as well as
+    /// its files, `<channel>=stdout|stderr`, e.g. `errors=stderr`; may be
+    /// repeated
+    #[structopt(long = "tee-channel")]
+    tee_channels: Vec<TeeChannel>,
+
+    /// Also relays a channel's lines to a remote host, `<channel>=tcp://host:port`
+    /// for newline-delimited lines or `<channel>=syslog+tcp://host:port` for
+    /// RFC 5424 syslog, `tls://` and `syslog+tls://` for the same over TLS;
+    /// may be repeated. Channels of a config file can set their own
+    /// `forward`, and `sink = "forward"` to write no files
+    #[cfg(feature = "forward")]
+    #[structopt(long = "forward")]
+    forwards: Vec<String>,
+
+    /// Lines of a channel waiting for its forwarding destination, while it is
+    /// slow or unreachable, before newer ones are dropped
+    #[cfg(feature = "forward")]
+    #[structopt(long, default_value = "10000")]
+    forward_buffer_lines: usize,
+
+    /// PEM file of the certificate authorities `tls://` forwarding
+    /// destinations and an `https://` S3 endpoint are checked against, in
+    /// place of the Mozilla roots built in
+    #[cfg(any(feature = "forward", feature = "s3"))]
+    #[structopt(long)]
+    tls_ca_file: Option<String>,
+
+    /// Lines of each channel kept in memory for the `last` admin command, 0
+    /// to keep none
+    #[cfg(any(feature = "control-socket", feature = "http-admin"))]
+    #[structopt(long, default_value = "0")]
+    recent_lines: usize,
+
+    /// Milliseconds lines are held for, to be written in the order of the
+    /// RFC 3339 timestamp they start with rather than as they arrive from
+    /// several inputs; 0 writes them as they arrive
+    #[structopt(long, default_value = "0")]
+    reorder_window: u64,
+
+    /// Comma-separated channels reordered, all channels when omitted
+    #[structopt(long, default_value = "")]
+    reorder_channels: String,
+
+    /// Comma-separated `channel=interval` pairs of channels whose runs of
+    /// identical lines are collapsed into one annotated with `... repeated N
+    /// times`, e.g. `app=10s`. Lines of these channels are held until a
+    /// different one comes in or the interval passes
+    #[structopt(long, default_value = "")]
+    dedup_window: String,
+
+    /// Comma-separated `channel=interval` pairs of how often a channel is
+    /// expected to see a line, e.g. `app=5m,billing=1h`; a channel silent for
+    /// longer is alerted on in the router's log, the admin status and the
+    /// meta channel
+    #[structopt(long, default_value = "")]
+    expected_traffic: String,
+
+    /// Accepted channel the router writes its own events to, such as idle
+    /// channel alerts, one JSON object per line
+    #[structopt(long)]
+    meta_channel: Option<String>,
+
+    /// Channel receiving the stdout stream in `cri` input format
+    #[cfg(feature = "cri")]
+    #[structopt(long, default_value = "stdout")]
+    cri_stdout_channel: String,
+
+    /// Channel receiving the stderr stream in `cri` input format
+    #[cfg(feature = "cri")]
+    #[structopt(long, default_value = "stderr")]
+    cri_stderr_channel: String,
+
+    /// Also ship channels to Graylog, e.g. `udp://graylog:12201` or
+    /// `tcp://graylog:12201`
+    #[cfg(feature = "gelf")]
+    #[structopt(long)]
+    gelf_addr: Option<String>,
+
+    /// Comma-separated channels shipped over GELF, all accepted channels when
+    /// omitted
+    #[cfg(feature = "gelf")]
+    #[structopt(long, default_value = "")]
+    gelf_channels: String,
+
+    /// Host reported in GELF messages, the machine hostname when omitted
+    #[cfg(feature = "gelf")]
+    #[structopt(long)]
+    gelf_host: Option<String>,
+
+    /// GELF messages waiting to be sent before they are spilled, or dropped
+    /// without a spill directory
+    #[cfg(feature = "gelf")]
+    #[structopt(long, default_value = "1024")]
+    gelf_queue_capacity: usize,
+
+    /// Directory keeping GELF messages on disk while Graylog is unreachable
+    /// or falling behind, shipped once it recovers
+    #[cfg(feature = "gelf")]
+    #[structopt(long)]
+    gelf_spill_dir: Option<String>,
+
+    /// Size cap of the GELF spill; the oldest messages go first
+    #[cfg(feature = "gelf")]
+    #[structopt(long, default_value = "1073741824")]
+    gelf_spill_max_bytes: u64,
+
+    /// Also produce channels to Kafka through these comma-separated brokers,
+    /// e.g. `kafka-1:9092,kafka-2:9092`, each line keyed by its channel
+    #[cfg(feature = "kafka")]
+    #[structopt(long)]
+    kafka_brokers: Option<String>,
+
+    /// Topic lines are produced to
+    #[cfg(feature = "kafka")]
+    #[structopt(long, default_value = "logs")]
+    kafka_topic: String,
+
+    /// Comma-separated channels produced to Kafka, all accepted channels when
+    /// omitted
+    #[cfg(feature = "kafka")]
+    #[structopt(long, default_value = "")]
+    kafka_channels: String,
+
+    /// Most lines produced in one batch
+    #[cfg(feature = "kafka")]
+    #[structopt(long, default_value = "500")]
+    kafka_batch_lines: usize,
+
+    /// Milliseconds a line waits for its batch to fill
+    #[cfg(feature = "kafka")]
+    #[structopt(long, default_value = "100")]
+    kafka_linger: u64,
+
+    /// Lines waiting to be produced before newer ones are dropped
+    #[cfg(feature = "kafka")]
+    #[structopt(long, default_value = "10000")]
+    kafka_queue_capacity: usize,
+
+    /// File lines the brokers don't take go to, in the `lines` input format
+    /// so they can be fed back; `kafka.fallback` in the log directory when
+    /// omitted
+    #[cfg(feature = "kafka")]
+    #[structopt(long)]
+    kafka_fallback_file: Option<String>,
+
+    /// Comma-separated `channel=format` pairs rewriting channels as SIEM
+    /// events, `format` being `cef` or `leef`
+    #[cfg(feature = "siem")]
+    #[structopt(long, default_value = "")]
+    siem_channels: String,
+
+    /// Comma-separated `key=field` pairs copying fields of JSON messages into
+    /// the SIEM event extension
+    #[cfg(feature = "siem")]
+    #[structopt(long, default_value = "")]
+    siem_fields: String,
+
+    #[cfg(feature = "siem")]
+    #[structopt(long, default_value = "log-revolve")]
+    siem_vendor: String,
+
+    #[cfg(feature = "siem")]
+    #[structopt(long, default_value = "log-revolve-rs")]
+    siem_product: String,
+
+    /// Product version reported in SIEM events, the crate version when omitted
+    #[cfg(feature = "siem")]
+    #[structopt(long)]
+    siem_product_version: Option<String>,
+
+    /// Command run for every file a channel is done with, once compressed if
+    /// it is, split on whitespace and given the file's path and the channel
+    /// as arguments, and as `LOG_REVOLVE_PATH` and `LOG_REVOLVE_CHANNEL`
+    #[structopt(long)]
+    on_rotate_cmd: Option<String>,
+
+    /// Runs of `--on-rotate-cmd` at a time; later ones wait their turn
+    #[structopt(long, default_value = "4")]
+    on_rotate_concurrency: usize,
+
+    /// Append a JSON line to this file for every rotation: the channel, the
+    /// paths rotated from and to, why (time, size or manual), and the bytes,
+    /// lines and seconds the old file was open for
+    #[structopt(long)]
+    rotation_events: Option<String>,
+
+    /// Append `filename,size,sha256,closed_at` for every file a channel is
+    /// done with, once compressed if it is, to `manifest.csv` in its
+    /// directory, before the file is uploaded or handed to `--on-rotate-cmd`
+    #[cfg(feature = "manifest")]
+    #[structopt(long)]
+    manifest: bool,
+
+    /// Also write a `<file>.sha256` next to each file recorded in the
+    /// manifest, as `sha256sum` writes them
+    #[cfg(feature = "manifest")]
+    #[structopt(long, requires = "manifest")]
+    checksum_sidecars: bool,
+
+    /// Bucket of S3-compatible object storage rotated files are uploaded to,
+    /// once compressed if they are; credentials come from
+    /// `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN`
+    #[cfg(feature = "s3")]
+    #[structopt(long)]
+    s3_bucket: Option<String>,
+
+    /// Prefix of the keys files are uploaded under, e.g. `logs/web-1/`,
+    /// followed by the file name
+    #[cfg(feature = "s3")]
+    #[structopt(long, default_value = "")]
+    s3_prefix: String,
+
+    /// Endpoint of the object storage, `http://host[:port]` or
+    /// `https://host[:port]`
+    #[cfg(feature = "s3")]
+    #[structopt(long)]
+    s3_endpoint: Option<String>,
+
+    #[cfg(feature = "s3")]
+    #[structopt(long, default_value = "us-east-1")]
+    s3_region: String,
+
+    /// What becomes of a file once uploaded: `keep` or `delete`
+    #[cfg(feature = "s3")]
+    #[structopt(long, default_value = "keep")]
+    after_upload: AfterUpload,
+
+    /// File tracking uploads still to be done, resumed on startup,
+    /// `<log-dir>/.s3-uploads` when omitted
+    #[cfg(feature = "s3")]
+    #[structopt(long)]
+    s3_manifest: Option<String>,
+
+    /// What is printed on stdout once the router is ready: `plain`
+    /// (`log-revolve-rs started`) or `json`, the startup record also written
+    /// to the router's log, with the version, pid, a hash of the effective
+    /// options, channels and inputs
+    #[structopt(long, default_value = "plain")]
+    startup_banner: StartupBanner,
+
+    /// File holding the RFC 3339 time the router takes for the current one,
+    /// for tests driving rotations
+    #[structopt(long, hidden = true)]
+    simulated_clock: Option<String>,
+
+    /// Trace records through routing, formatting, queueing and writing:
+    /// `channel:<name>` or `regex:<pattern>` matched against the received line
+    #[cfg(feature = "trace")]
+    #[structopt(long)]
+    trace_records: Option<TracePredicate>,
+
+    /// Where trace events go: `stdout`, `stderr` or an inherited `fd:<n>`
+    #[cfg(feature = "trace")]
+    #[structopt(long, default_value = "stderr")]
+    trace_output: String,
+}
+
+fn parse_rotation_interval(s: &str) -> Result<chrono::Duration, String> {
+    let interval = idle::parse_interval(s).map_err(|e| e.to_string())?;
+    let seconds = interval.as_secs() as i64;
+    if 86_400 % seconds != 0 {
+        return Err(format!("`{}` doesn't divide a day evenly", s));
+    }
+
+    Ok(chrono::Duration::seconds(seconds))
+}
+
+/// Hourly rotation, or rotation on another interval, in `file_timestamp`.
+fn rotation_every(options: &CliOptions, interval: chrono::Duration) -> Result<Rotation, io::Error> {
+    let timestamp = options.file_timestamp;
+    let hours_only = matches!(
+        timestamp,
+        FileTimestamp::Hours | FileTimestamp::Rfc3339Hours
+    );
+    if hours_only && interval < chrono::Duration::hours(1) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "rotation intervals under an hour take a seconds file timestamp",
+        ));
+    }
+
+    if interval == chrono::Duration::hours(1) {
+        Ok(Rotation::Hourly(timestamp))
+    } else {
+        Ok(Rotation::Periodic(timestamp, interval))
+    }
+}
+
+fn parse_file_size(s: &str) -> Result<u64, String> {
+    quota::parse_size(s)
+        .filter(|size| *size > 0)
+        .ok_or_else(|| format!("expected a size such as `100MB`, got `{}`", s))
+}
+
+fn parse_line_size(s: &str) -> Result<usize, String> {
+    usize::try_from(parse_file_size(s)?)
+        .map_err(|_| format!("`{}` is more than can be held in memory", s))
+}
+
+/// What `--max-age`, `--max-files` and `--max-total-disk` keep of the log
+/// directory, if they are given.
+fn retention(options: &CliOptions) -> Result<Option<Retention>, io::Error> {
+    if options.max_age.is_none() && options.max_files.is_none() && options.max_total_disk.is_none()
+    {
+        return Ok(None);
+    }
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message);
+    if options.external_rotation {
+        return Err(invalid(
+            "--max-age, --max-files and --max-total-disk need timestamped files, not \
+             --external-rotation",
+        ));
+    }
+    if options.max_age.is_some_and(|days| days < 1)
+        || options.max_files == Some(0)
+        || options.max_total_disk == Some(0)
+    {
+        return Err(invalid(
+            "--max-age, --max-files and --max-total-disk must be at least 1",
+        ));
+    }
+
+    let parser: Arc<dyn FileNameParser> = match options.file_name_template {
+        Some(ref template) => Arc::new(template.clone()),
+        None => Arc::new(options.file_timestamp),
+    };
+    Ok(Some(Retention {
+        zone: options.timezone.clone(),
+        parser,
+        dirs: options.dir_template.clone(),
+        max_age: options.max_age.map(chrono::Duration::days),
+        max_files: options.max_files,
+        max_total_bytes: options.max_total_disk,
+    }))
+}
+
+/// Channels listed with `--accepted-log-channels`, then those of the config
+/// file.
+fn accepted_channels(options: &CliOptions) -> Vec<&str> {
+    options
+        .accepted_log_channels
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .chain(options.configured_channels.keys().map(String::as_str))
+        .collect()
+}
+
+/// Checks that some channel is accepted, and that each can name the files it
+/// is written to.
+fn check_accepted_channels(accepted: &[&str]) -> Result<(), io::Error> {
+    if accepted.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no channel is accepted, give --accepted-log-channels or --config",
+        ));
+    }
+
+    check_channel_names(accepted)
+}
+
+/// Checks that each channel can name the files it is written to.
+fn check_channel_names(channels: &[&str]) -> Result<(), io::Error> {
+    for channel in channels {
+        check_channel_name(channel).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid channel name: {}", e),
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Checks that a channel name, configured or sent by a producer, is fit for
+/// a file name: at most `MAX_CHANNEL_NAME_LENGTH` ASCII letters, digits, `.`,
+/// `_` and `-`, not starting with a dot, nor taken by the platform, as `CON`
+/// is on Windows.
+fn check_channel_name(channel: &str) -> Result<(), String> {
+    if channel.len() > MAX_CHANNEL_NAME_LENGTH {
+        return Err(format!(
+            "`{}` is longer than {} bytes",
+            channel, MAX_CHANNEL_NAME_LENGTH
+        ));
+    }
+    if channel.starts_with('.') {
+        return Err(format!("`{}` starts with a dot", channel));
+    }
+    Platform::CURRENT.check_file_name(channel)?;
+    if let Some(c) = channel
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && !"._-".contains(*c))
+    {
+        return Err(format!(
+            "`{}` holds {:?}, only ASCII letters, digits, `.`, `_` and `-` are allowed",
+            channel, c
+        ));
+    }
+
+    Ok(())
+}
+
+fn parse_pairs(list: &str) -> Result<Vec<(String, String)>, io::Error> {
+    list.split(',')
+        .filter(|s| !s.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => Ok((key.to_string(), value.to_string())),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("expected `key=value`, got `{}`", pair),
+            )),
+        })
+        .collect()
+}
+
+/// `parse_pairs`, each value parsed as a `T`.
+fn parse_values<T>(list: &str) -> Result<BTreeMap<String, T>, io::Error>
+where
+    T: FromStr<Err = String>,
+{
+    parse_pairs(list)?
+        .into_iter()
+        .map(|(key, value)| {
+            let value = value
+                .parse()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            Ok((key, value))
+        })
+        .collect()
+}
+
+/// Routes log lines to rotated files per channel. Given options rather than
+/// a subcommand, as before there were any, it runs as `run` does
+#[derive(StructOpt)]
+#[structopt(name = "log-revolve-rs", rename_all = "kebab_case")]
+enum Command {
+    /// Routes the channels of stdin and the listeners to rotated files
+    Run(CliOptions),
+    /// Validates the options, the config file and the directories, printing
+    /// each channel's effective settings, without reading any input or
+    /// opening any file
+    CheckConfig(CliOptions),
+    /// Splits existing files into channels as the router would have
+    #[cfg(feature = "split")]
+    Split(split::SplitOptions),
+    /// Writes what encrypted files hold to stdout
+    #[cfg(feature = "encryption")]
+    Decrypt(decrypt::DecryptOptions),
+    /// Recompresses the files channels rotated away from, merging those of
+    /// a period into one with `--merge`
+    Compact(compact::CompactOptions),
+    /// Prints the lines of a channel's files covering a time range
+    Cat(query::CatOptions),
+    /// Prints the lines holding a pattern of a channel's files covering a
+    /// time range
+    Grep(query::GrepOptions),
+    /// Prints the status of a router serving `--control-socket`
+    #[cfg(all(unix, feature = "control-socket"))]
+    Status(control_client::StatusOptions),
+    /// Has a router serving `--control-socket` start fresh files right away
+    #[cfg(all(unix, feature = "control-socket"))]
+    Rotate(control_client::RotateOptions),
+    /// Prints the hourly line and byte counts of a router serving
+    /// `--control-socket`
+    #[cfg(all(unix, feature = "control-socket"))]
+    Stats(control_client::StatsOptions),
+    /// Generates synthetic traffic for a running router
+    BenchProduce(bench::BenchOptions),
+}
+
+fn main() {
+    let command = match std::env::args().nth(1) {
+        Some(first) if !first.starts_with('-') => Command::from_args(),
+        _ => Command::Run(CliOptions::from_args()),
+    };
+
+    let (name, result) = match command {
+        Command::Run(options) => ("", task::block_on(start(options))),
+        Command::CheckConfig(mut options) => {
+            options.check_config = true;
+            ("", task::block_on(start(options)))
+        }
+        #[cfg(feature = "split")]
+        Command::Split(options) => (" split", task::block_on(split::run(options))),
+        #[cfg(feature = "encryption")]
+        Command::Decrypt(options) => (" decrypt", task::block_on(decrypt::run(options))),
+        // The reader compacting goes through runs on the library's runtime.
+        Command::Compact(options) => (" compact", runtime::block_on(compact::run(options))),
+        // So do the channel readers of `cat` and `grep`.
+        Command::Cat(options) => (" cat", runtime::block_on(query::cat(options))),
+        Command::Grep(options) => (" grep", runtime::block_on(query::grep(options))),
+        #[cfg(all(unix, feature = "control-socket"))]
+        Command::Status(options) => (" status", task::block_on(control_client::status(options))),
+        #[cfg(all(unix, feature = "control-socket"))]
+        Command::Rotate(options) => (" rotate", task::block_on(control_client::rotate(options))),
+        #[cfg(all(unix, feature = "control-socket"))]
+        Command::Stats(options) => (" stats", task::block_on(control_client::stats(options))),
+        // A producer of synthetic traffic rather than the router; its lines
+        // may go to stdout, so nothing else is printed unless it fails.
+        Command::BenchProduce(options) => (" bench-produce", task::block_on(bench::run(options))),
+    };
+    if let Err(ref error) = result {
+        eprintln!("log-revolve-rs{} failed: {}", name, error);
+        std::process::exit(failure::exit_status(&result));
+    }
+
+    if name.is_empty() {
+        println!("log-revolve-rs finished");
+    }
+}
+
+async fn start(mut cli_options: CliOptions) -> Result<(), io::Error> {
+    configure(&mut cli_options).await?;
+    if cli_options.check_config {
+        return check::run(&cli_options).await;
+    }
+    if !cli_options.no_preflight {
+        preflight::run(&cli_options).await?;
+    }
+    #[cfg(any(feature = "forward", feature = "s3"))]
+    if let Some(ref ca_file) = cli_options.tls_ca_file {
+        tls::trust(ca_file)?;
+    }
+    #[cfg(feature = "s3")]
+    start_uploads(&cli_options)?;
+    let cli_options = Arc::new(cli_options);
+
+    #[cfg(feature = "report")]
+    let started_at = Local::now();
+    if cli_options.internal_log {
+        logger::queue_internal();
+    }
+    let shared_writer = Arc::new(Mutex::new(FileWriter::with_options(&cli_options).await?));
+    task::spawn(logger::write_internal(shared_writer.clone()));
+    #[cfg(feature = "state")]
+    if let Some(ref dir) = cli_options.state_dir {
+        state::resume(dir, &*shared_writer.lock().await)?;
+    }
+
+    let result = serve(cli_options.clone(), shared_writer.clone()).await;
+
+    #[cfg(feature = "report")]
+    if let Some(ref path) = cli_options.shutdown_report {
+        if let Err(ref error) = result {
+            report::record_error("router", error);
+        }
+        // Past `--shutdown-timeout`, an input may still hold the writer,
+        // stuck on the same disk.
+        let writer = match result {
+            Err(ref error) if error.kind() == io::ErrorKind::TimedOut => shared_writer.try_lock(),
+            _ => Some(shared_writer.lock().await),
+        };
+        if writer.is_none() {
+            tracing::warn!("writer is busy, the shutdown report leaves out the channels");
+        }
+        if let Err(error) = report::write(path, writer.as_deref(), started_at, &result).await {
+            tracing::error!("unable to write shutdown report: {}", error);
+        }
+    }
+
+    result
+}
+
+/// Sets up what every run of the router shares, from the options: logging,
+/// the clock, the config file, placeholders, and how files are written.
+async fn configure(cli_options: &mut CliOptions) -> Result<(), io::Error> {
+    logger::init(cli_options.log_level).map_err(io::Error::other)?;
+    expand_shorthands(cli_options)?;
+    if let Some(ref path) = cli_options.simulated_clock {
+        clock::simulate(std::path::PathBuf::from(path));
+    }
+    #[cfg(feature = "config")]
+    if let Some(ref path) = cli_options.config {
+        let config = Config::load(path).await?;
+        cli_options.configured_channels = config.channels;
+        cli_options.configured_tenants = config.tenants;
+    }
+    Placeholders::resolve(cli_options)
+        .await?
+        .apply(cli_options)?;
+    if cli_options.stream_compress {
+        compress::stream_active_files();
+    }
+    if cli_options.on_error == OnError::Retry {
+        failure::retry_reads();
+    }
+    writer_pool::start(cli_options.writer_threads);
+    #[cfg(all(target_os = "linux", feature = "tmpfile"))]
+    if cli_options.tmpfile_staging {
+        compress::stage_with_tmpfile();
+    }
+    #[cfg(feature = "encryption")]
+    if let Some(ref path) = cli_options.encryption_key_file {
+        encrypt::install(path)?;
+    }
+    if cli_options.no_create_dirs {
+        log_dir::forbid_creating();
+    }
+    #[cfg(feature = "manifest")]
+    if cli_options.manifest {
+        manifest::install(cli_options.checksum_sidecars);
+    }
+    if let Some(ref command) = cli_options.on_rotate_cmd {
+        hook::install(command, cli_options.on_rotate_concurrency)?;
+    }
+    if let Some(ref path) = cli_options.rotation_events {
+        rotation_events::install(path)?;
+    }
+
+    Ok(())
+}
+
+/// Folds the options standing for others into the ones they stand for, so
+/// only those need be looked at from here on.
+fn expand_shorthands(cli_options: &mut CliOptions) -> Result<(), io::Error> {
+    if let Some(algorithm) = cli_options.compression.take() {
+        let compression = match cli_options.compression_level.take() {
+            Some(level) => format!("{}:{}", algorithm, level),
+            None => algorithm,
+        };
+        cli_options.compress = compression
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    }
+    if cli_options.auto_create_channels {
+        cli_options.unknown_channels = UnknownChannels::Create;
+    }
+    #[cfg(feature = "journald")]
+    if cli_options.inputs.iter().any(|input| input == "journald") {
+        cli_options.inputs.retain(|input| input != "journald");
+        cli_options.input_format = InputFormat::Journald;
+    }
+    if let Some(protocol) = cli_options.protocol.take() {
+        cli_options.input_format = protocol;
+    }
+    for addr in std::mem::take(&mut cli_options.listen_tcp) {
+        cli_options.listen.push(format!("tcp://{}", addr));
+    }
+    #[cfg(unix)]
+    for path in std::mem::take(&mut cli_options.listen_unix) {
+        cli_options.listen.push(format!("unix:{}", path));
+    }
+
+    Ok(())
+}
+
+async fn serve(
+    cli_options: Arc<CliOptions>,
+    shared_writer: Arc<Mutex<FileWriter>>,
+) -> Result<(), io::Error> {
+    #[cfg(all(unix, feature = "control-socket"))]
+    if let Some(ref path) = cli_options.control_socket {
+        let listener = listen::bind_unix(path).await?;
+        task::spawn(control_socket::serve(listener, shared_writer.clone()));
+    }
+
+    #[cfg(feature = "http-admin")]
+    if let Some(ref addr) = cli_options.admin_http {
+        let token = match cli_options.admin_token_file {
+            Some(ref path) => async_std::fs::read_to_string(path)
+                .await?
+                .trim()
+                .to_string(),
+            None => String::new(),
+        };
+        if token.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--admin-http requires a non-empty --admin-token-file",
+            ));
+        }
+
+        let listener = async_std::net::TcpListener::bind(addr).await?;
+        if !listener.local_addr()?.ip().is_loopback() {
+            tracing::warn!("admin HTTP API is reachable beyond localhost on {}", addr);
+        }
+        task::spawn(admin_http::serve(
+            listener,
+            shared_writer.clone(),
+            Arc::new(token),
+        ));
+    }
+
+    #[cfg(feature = "metrics")]
+    if let Some(ref addr) = cli_options.metrics_addr {
+        let listener = async_std::net::TcpListener::bind(addr).await?;
+        task::spawn(metrics::serve(listener, shared_writer.clone()));
+    }
+
+    #[cfg(all(unix, feature = "config"))]
+    let reloads_config = cli_options.config.is_some();
+    #[cfg(all(unix, not(feature = "config")))]
+    let reloads_config = false;
+    #[cfg(unix)]
+    if cli_options.external_rotation || reloads_config {
+        signals::on_hangup(shared_writer.clone(), cli_options.clone())?;
+    }
+    #[cfg(unix)]
+    if let Some(signal) = cli_options.reopen_on_signal {
+        signals::reopen_on(signal, shared_writer.clone())?;
+    }
+    if cli_options.rotate_on_signal.is_some()
+        && cli_options.rotate_on_signal == cli_options.reopen_on_signal
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--reopen-on-signal and --rotate-on-signal need signals of their own",
+        ));
+    }
+    #[cfg(unix)]
+    if let Some(signal) = cli_options.rotate_on_signal {
+        signals::rotate_on(signal, shared_writer.clone())?;
+    }
+    #[cfg(windows)]
+    if cli_options.reopen_on_signal.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--reopen-on-signal needs Unix signals, reopen files with the `reload` command \
+             of --admin-http instead",
+        ));
+    }
+    #[cfg(windows)]
+    if cli_options.rotate_on_signal.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--rotate-on-signal needs Unix signals, rotate files with the `rotate` command \
+             of --admin-http instead",
+        ));
+    }
+
+    if cli_options.unknown_channels == UnknownChannels::Pending {
+        let interval = (cli_options.pending_ttl / 4).clamp(1, 60);
+        task::spawn(pending::sweep(
+            shared_writer.clone(),
+            time::Duration::from_secs(interval),
+        ));
+    }
+
+    if cli_options.stats_file.is_some() {
+        task::spawn(stats::save_every(
+            shared_writer.clone(),
+            time::Duration::from_secs(STATS_SAVE_INTERVAL_SECS),
+        ));
+    }
+
+    #[cfg(feature = "state")]
+    if cli_options.state_dir.is_some() {
+        task::spawn(state::checkpoint_every(
+            shared_writer.clone(),
+            time::Duration::from_secs(STATE_SAVE_INTERVAL_SECS),
+        ));
+    }
+
+    if cli_options.backpressure_high_water.is_some() || cli_options.memory_budget.is_some() {
+        task::spawn(backpressure::watch_every(
+            shared_writer.clone(),
+            backpressure::WATCH_INTERVAL,
+        ));
+    }
+
+    task::spawn(degraded::retry_every(
+        shared_writer.clone(),
+        degraded::RETRY_INTERVAL,
+    ));
+
+    if cli_options.log_dir_check_interval > 0 {
+        task::spawn(log_dir::watch(
+            shared_writer.clone(),
+            time::Duration::from_secs(cli_options.log_dir_check_interval),
+        ));
+    }
+
+    if cli_options.reorder_window > 0 {
+        task::spawn(reorder::release_every(
+            shared_writer.clone(),
+            time::Duration::from_millis(cli_options.reorder_window.div_ceil(2)),
+        ));
+    }
+
+    if let Some(ref deduplicator) = shared_writer.lock().await.deduplicator {
+        task::spawn(dedup::release_every(
+            shared_writer.clone(),
+            deduplicator.check_interval(),
+        ));
+    }
+
+    let shortest_flush_interval = {
+        let writer = shared_writer.lock().await;
+        let settings = &writer.channel_settings;
+        settings
+            .buffering_profiles
+            .values()
+            .filter_map(|profile| profile.flush_interval())
+            .chain(settings.flush_interval)
+            .min()
+    };
+    if let Some(interval) = shortest_flush_interval {
+        task::spawn(profile::flush_every(shared_writer.clone(), interval / 2));
+    }
+
+    if cli_options.sync_interval > 0 {
+        task::spawn(profile::sync_every(
+            shared_writer.clone(),
+            time::Duration::from_millis(cli_options.sync_interval),
+        ));
+    }
+
+    if let Some(retention) = retention(&cli_options)? {
+        task::spawn(retention::prune_every(
+            shared_writer.clone(),
+            cli_options.log_dir.clone(),
+            retention,
+        ));
+    }
+
+    if let Some(ref watch) = shared_writer.lock().await.idle_watch {
+        task::spawn(idle::watch(shared_writer.clone(), watch.check_interval()));
+    }
+
+    if let Some(idle) = cli_options.idle_close_after {
+        task::spawn(idle::close_every(shared_writer.clone(), idle));
+    }
+
+    if let Some(ref tenants) = shared_writer.lock().await.tenants {
+        if !tenants.retention.is_empty() {
+            task::spawn(tenant::prune_every(
+                shared_writer.clone(),
+                cli_options.log_dir.clone(),
+                tenants.retention.clone(),
+            ));
+        }
+    }
+
+    for addr in cli_options.listen_syslog_udp.iter() {
+        let socket = async_std::net::UdpSocket::bind(addr).await?;
+        task::spawn(syslog::serve(
+            socket,
+            shared_writer.clone(),
+            cli_options.syslog_channel,
+        ));
+    }
+
+    // Connections come and go; the router keeps running on its other inputs.
+    for spec in cli_options.listen.iter() {
+        let listener = listen::bind(spec).await?;
+        task::spawn(listen::serve(
+            listener,
+            shared_writer.clone(),
+            cli_options.clone(),
+        ));
+    }
+
+    #[cfg(feature = "http-input")]
+    if let Some(ref addr) = cli_options.listen_http {
+        let listener = async_std::net::TcpListener::bind(addr).await?;
+        task::spawn(ingest_http::serve(
+            listener,
+            shared_writer.clone(),
+            cli_options.clone(),
+        ));
+    }
+
+    #[cfg(feature = "grpc")]
+    if let Some(ref addr) = cli_options.listen_grpc {
+        let listener = async_std::net::TcpListener::bind(addr).await?;
+        task::spawn(grpc_ingest::serve(listener, shared_writer.clone()));
+    }
+
+    let (finished, finished_inputs) = channel::unbounded();
+    #[cfg(unix)]
+    signals::stop_on_terminate(finished.clone())?;
+    #[cfg(windows)]
+    console::stop_on_ctrl_c(finished.clone())?;
+
+    let mut input_count = 1;
+    if cli_options.stay_alive {
+        input::spawn(
+            String::from("stdin"),
+            BufReader::with_capacity(INPUT_BUFFER_BYTES, StayAlive::new(io::stdin())),
+            shared_writer.clone(),
+            cli_options.clone(),
+            finished.clone(),
+        );
+    } else {
+        input::spawn(
+            String::from("stdin"),
+            BufReader::with_capacity(INPUT_BUFFER_BYTES, io::stdin()),
+            shared_writer.clone(),
+            cli_options.clone(),
+            finished.clone(),
+        );
+    }
+
+    for spec in cli_options.inputs.iter() {
+        let file = File::from(fd::open(spec)?);
+        input::spawn(
+            spec.clone(),
+            BufReader::with_capacity(INPUT_BUFFER_BYTES, file),
+            shared_writer.clone(),
+            cli_options.clone(),
+            finished.clone(),
+        );
+        input_count += 1;
+    }
+
+    failure::started();
+    let record = banner::startup_record(&cli_options);
+    tracing::info!("{}", record);
+    match cli_options.startup_banner {
+        StartupBanner::Plain => println!("log-revolve-rs started"),
+        StartupBanner::Json => println!("{}", record),
+    }
+
+    // The first input to fail, reported once the others are done with.
+    let mut failed = None;
+    while input_count > 0 {
+        match finished_inputs.recv().await.map_err(io::Error::other)? {
+            Stop::InputClosed(Ok(())) => {}
+            Stop::InputClosed(Err(error)) if cli_options.on_error == OnError::Abort => {
+                return Err(error)
+            }
+            Stop::InputClosed(Err(error)) => {
+                failed.get_or_insert(error);
+            }
+            Stop::Terminated => break,
+        }
+        input_count -= 1;
+    }
+
+    tracing::info!("flushing files before exiting");
+    let shutdown = async {
+        finish(&cli_options, &shared_writer).await?;
+        failed.map_or(Ok(()), Err)
+    };
+
+    let timeout = match cli_options.shutdown_timeout {
+        Some(timeout) => timeout,
+        None => return shutdown.await,
+    };
+    match async_std::future::timeout(time::Duration::from_secs(timeout), shutdown).await {
+        Ok(result) => result,
+        Err(_) => {
+            tracing::error!("shutdown took longer than {}s, giving up", timeout);
+            // An input may still hold the writer, stuck on the same disk.
+            match shared_writer.try_lock() {
+                Some(writer) => writer.log_unflushed(),
+                None => {
+                    tracing::error!("writer is busy, unflushed lines can't be counted");
+                    report::record_error(
+                        "shutdown",
+                        "writer is busy, unflushed lines can't be counted",
+                    );
+                }
+            }
+
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("shutdown took longer than {}s", timeout),
+            ))
+        }
+    }
+}
+
+/// Writes out what is held and batched for every channel and syncs its
+/// files, once the inputs are done.
+async fn finish(
+    cli_options: &CliOptions,
+    shared_writer: &Mutex<FileWriter>,
+) -> Result<(), io::Error> {
+    let mut writer = shared_writer.lock().await;
+    writer.release_reordered(true).await?;
+    writer.release_deduplicated(true).await?;
+    if let Some(ref marker) = cli_options.shutdown_marker {
+        writer.write_shutdown_marker(marker).await?;
+    }
+    logger::flush_internal(&mut writer).await;
+    writer.write_footers();
+    writer.sync_all().await?;
+    writer.stats.save().await?;
+    #[cfg(feature = "state")]
+    state::checkpoint(&writer);
+    for pipe_out in writer.pipe_outs.values_mut() {
+        pipe_out.close().await;
+    }
+    #[cfg(feature = "forward")]
+    for forwarder in writer.forwarders.values_mut() {
+        forwarder.close().await;
+    }
+    #[cfg(feature = "gelf")]
+    if let Some(ref mut gelf_sink) = writer.gelf_sink {
+        gelf_sink.close().await;
+    }
+    #[cfg(feature = "kafka")]
+    if let Some(ref mut kafka_sink) = writer.kafka_sink {
+        kafka_sink.close().await;
+    }
+    drop(writer);
+    post_rotation::drain().await;
+
+    Ok(())
+}
+
+/// Why the router stops waiting on its inputs.
+enum Stop {
+    /// An input was closed, or failed.
+    InputClosed(Result<(), io::Error>),
+    /// The supervisor asked for a shutdown.
+    Terminated,
+}
+
+/// How often hourly counts are saved to the stats file, bounding what a
+/// crash can lose of them.
+const STATS_SAVE_INTERVAL_SECS: u64 = 60;
+
+/// How often how far each channel got is saved to the state file, between
+/// the rotations that save it anyway.
+#[cfg(feature = "state")]
+const STATE_SAVE_INTERVAL_SECS: u64 = 10;
+
+/// Longest channel name; names, some of them sent by producers, end up in
+/// file names.
+const MAX_CHANNEL_NAME_LENGTH: usize = 64;
+
+/// Lines are batched whole and each batch reaches the file in a single
+/// `write`. Files are opened for appending, so nothing else writing to the
+/// same file, nor a rotation, can land in the middle of a line.
+const BATCH_BYTES: usize = 64 * 1024;
+
+/// Bytes read from stdin or an `--input` at a time. Each read takes a
+/// blocking thread, so reading more at once saves most of the handoffs.
+const INPUT_BUFFER_BYTES: usize = 256 * 1024;
+
+/// How eagerly a handle's lines reach the disk.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Durability {
+    /// Left to the file buffer, flushed on rotation and shutdown.
+    Buffered,
+    /// Flushed after every line.
+    Flushed,
+    /// Flushed and fsynced after every line, the log directory fsynced as
+    /// files are created and renamed.
+    Synced,
+}
+
+/// Lines kept in memory while the log directory is unavailable.
+struct HeldLines {
+    lines: Vec<Vec<u8>>,
+    bytes: usize,
+    capacity: usize,
+    dropped: u64,
+}
+
+struct FileHandle {
+    /// Channel written to, whose name the files take unless given one of
+    /// their own.
+    channel: String,
+    file_name: String,
+    log_dir: String,
+    schedule: Schedule<Zone>,
+    layout: Layout,
+    zone: Zone,
+    /// When the file was last replaced, `None` until the first rotation.
+    rotated_at: Option<DateTime<Local>>,
+    /// Files rotated away from.
+    rotations: u64,
+    /// When a line was last written, `None` until the first one.
+    written_at: Option<DateTime<Local>>,
+    /// When the handle was opened, from which a channel that never wrote a
+    /// line is idle.
+    opened_at: DateTime<Local>,
+    current_path: String,
+    /// Shared with the channel's writer task, when there are writer tasks.
+    /// `None` while closed for the channel being idle, until its next line.
+    current_file: Option<Arc<File>>,
+    /// Writer task batches are handed to, when there are writer tasks.
+    writer: Option<Writer>,
+    batch: Vec<u8>,
+    /// Bytes ready for the file, encoded and sealed if the channel is, that
+    /// a write failed to get there; written out ahead of the next batch.
+    unwritten: Vec<u8>,
+    lines_written: u64,
+    bytes_written: u64,
+    /// Writes batches went out in, those of the writer task left out.
+    writes: u64,
+    /// Size of the current file, batched lines included.
+    file_bytes: u64,
+    /// What was written to the current file, and when it was opened, for
+    /// its footer and its rotation to be recorded.
+    file_stats: FileStats,
+    file_opened_at: DateTime<Local>,
+    /// Size the current file is cut at, on top of the rotation schedule.
+    max_file_size: Option<u64>,
+    /// Lines the channel's files start and end with, when it has any.
+    boundaries: Option<Boundaries>,
+    /// Size of the header the current file was started with, which alone
+    /// doesn't get the file cut.
+    header_bytes: u64,
+    disk_quota: Option<DiskQuota>,
+    dropped_lines: u64,
+    durability: Durability,
+    /// Applied to each file the handle rotates away from.
+    compression: Compression,
+    /// Compresses the current file as lines are written out, when files are
+    /// compressed as they are written.
+    encoder: Option<Encoder>,
+    /// Whether the channel's files are encrypted as lines are written out,
+    /// after any compression, and named `<file>.enc`.
+    encrypted: bool,
+    #[cfg(feature = "encryption")]
+    sealer: Option<Sealer>,
+    /// Number given to the next line, when lines are numbered.
+    sequence: Option<u64>,
+    /// Replaces the end of each line, when the channel has one of its own.
+    terminator: Option<LineTerminator>,
+    /// Prefixes each line with its receipt time, when lines are stamped.
+    timestamp: Option<TimestampFormat>,
+    /// Wraps each line in a JSON record, its receipt time written this way,
+    /// when lines are written as JSON Lines.
+    envelope: Option<TimestampFormat>,
+    /// Buffers lines are stamped or wrapped, then numbered and terminated in,
+    /// kept from one line to the next so neither allocates.
+    stamped: String,
+    formatted: Vec<u8>,
+    /// Whether `<file_name>.log` is kept pointing at the current file.
+    current_link: bool,
+    /// Batching, flushing and fsyncing of the channel, when it has a profile.
+    profile: Option<BufferingProfile>,
+    sync_policy: SyncPolicy,
+    /// Whether lines were written out since the file was last fsynced.
+    unsynced: bool,
+    /// Bytes of lines batched before they are written out.
+    batch_bytes: usize,
+    /// Longest a batched line waits before it is written out.
+    flush_interval: Option<time::Duration>,
+    /// When the batch was last written out.
+    flushed_at: time::Instant,
+    held: Option<HeldLines>,
+    /// Set while writes to the file fail, until a retry succeeds.
+    degraded: Option<Degraded>,
+}
+
+/// How the paths of a channel's files are laid out in its directory.
+#[derive(Clone, Default, Debug)]
+struct Layout {
+    /// Subdirectories files are sharded into, by `--dir-template`.
+    dirs: Option<DirLayout>,
+    /// Names of the files instead of the schedule's, by
+    /// `--file-name-template`.
+    file_names: Option<FileNameLayout>,
+}
+
+impl Layout {
+    fn of(options: &CliOptions) -> Self {
+        Layout {
+            dirs: options.dir_template.clone(),
+            file_names: options.file_name_template.clone(),
+        }
+    }
+}
+
+impl FileHandle {
+    async fn open_file(path_str: &str) -> Result<File, io::Error> {
+        let path_string = String::from(path_str);
+        let path = Path::new(&path_string);
+
+        OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .append(true)
+            .read(false)
+            .open(path)
+            .await
+    }
+
+    /// Opens the file at `path` in `log_dir`, creating the directory, and
+    /// the subdirectory of `--dir-template` the file is sharded into, when
+    /// either is missing.
+    async fn open_in(log_dir: &str, path: &str) -> Result<File, io::Error> {
+        match FileHandle::open_file(path).await {
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                log_dir::ensure(log_dir)?;
+                if let Some(dir) = Path::new(path).parent() {
+                    if dir != Path::new(log_dir) {
+                        async_std::fs::create_dir_all(dir).await?;
+                    }
+                }
+                FileHandle::open_file(path).await
+            }
+            result => result,
+        }
+    }
+
+    async fn create(
+        log_dir: &str,
+        channel_name: &str,
+        rotation: Rotation,
+        zone: Zone,
+        layout: Layout,
+        compression: Compression,
+        encrypted: bool,
+    ) -> Result<Self, io::Error> {
+        #[cfg(feature = "encryption")]
+        let sealer = match encrypted {
+            true => Some(Sealer::new()?.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is encrypted but no key was given", channel_name),
+                )
+            })?),
+            false => None,
+        };
+        let schedule = Schedule::new(rotation, &zone.now());
+        let file_name = FileHandle::period_file_name(&schedule, &layout, channel_name);
+        let encoder = Encoder::streaming(compression);
+        let path = FileHandle::generate_file_path(log_dir, &file_name)?;
+        let path = FileHandle::encoded_path(path, encoder.as_ref(), encrypted);
+        let file = Arc::new(FileHandle::open_in(log_dir, path.as_str()).await?);
+        let file_bytes = file.metadata().await?.len();
+
+        Ok(FileHandle {
+            channel: channel_name.to_string(),
+            file_name: channel_name.to_string(),
+            schedule,
+            layout,
+            zone,
+            rotated_at: None,
+            rotations: 0,
+            written_at: None,
+            opened_at: clock::now(),
+            log_dir: log_dir.to_string(),
+            current_path: path,
+            current_file: Some(file),
+            writer: writer_pool::writer(channel_name),
+            batch: Vec::new(),
+            unwritten: Vec::new(),
+            lines_written: 0,
+            bytes_written: 0,
+            writes: 0,
+            file_stats: FileStats::default(),
+            file_opened_at: clock::now(),
+            file_bytes,
+            max_file_size: None,
+            boundaries: None,
+            header_bytes: 0,
+            disk_quota: None,
+            dropped_lines: 0,
+            durability: Durability::Buffered,
+            compression,
+            encoder,
+            encrypted,
+            #[cfg(feature = "encryption")]
+            sealer,
+            sequence: None,
+            terminator: None,
+            timestamp: None,
+            envelope: None,
+            stamped: String::new(),
+            formatted: Vec::new(),
+            current_link: false,
+            profile: None,
+            sync_policy: SyncPolicy::None,
+            unsynced: false,
+            batch_bytes: BATCH_BYTES,
+            flush_interval: None,
+            flushed_at: time::Instant::now(),
+            held: None,
+            degraded: None,
+        })
+    }
+
+    /// Path of the current file in `schedule` relative to the log
+    /// directory, `/`-separated.
+    fn period_file_name(schedule: &Schedule<Zone>, layout: &Layout, channel_name: &str) -> String {
+        let file_name = match layout.file_names {
+            Some(ref file_names) => file_names.file_name(schedule, channel_name),
+            None => schedule.file_name(channel_name),
+        };
+
+        match layout.dirs {
+            Some(ref dirs) => format!("{}/{}", dirs.dir(schedule, channel_name), file_name),
+            None => file_name,
+        }
+    }
+
+    fn generate_file_path(log_dir: &str, file_name: &str) -> Result<String, io::Error> {
+        let mut path_buf = PathBuf::new();
+        path_buf.push(log_dir);
+        path_buf.push(Platform::CURRENT.native_path(file_name).as_ref());
+
+        let path_str_opt = path_buf.to_str();
+        match path_str_opt {
+            Some(path_str) => Ok(path_str.to_string()),
+            None => Err(io::Error::other("unable to build file path")),
+        }
+    }
+
+    /// `path` with the extension of the encoder compressing the file, then
+    /// `.enc` if it is encrypted.
+    fn encoded_path(path: String, encoder: Option<&Encoder>, encrypted: bool) -> String {
+        let path = match encoder {
+            Some(encoder) => path + encoder.extension(),
+            None => path,
+        };
+        match encrypted {
+            true => path + ".enc",
+            false => path,
+        }
+    }
+
+    /// Path of `file_name` in `log_dir` or, when a file of that name is there
+    /// already, compressed or not, of the first sequenced name that isn't,
+    /// so files cut within the same second aren't appended to each other.
+    fn unused_file_path(log_dir: &str, file_name: &str) -> Result<String, io::Error> {
+        let is_taken = |path: &str| {
+            ["", ".gz", ".zst", ".enc", ".gz.enc", ".zst.enc"]
+                .iter()
+                .any(|extension| std::path::Path::new(&format!("{}{}", path, extension)).exists())
+        };
+
+        let mut path = FileHandle::generate_file_path(log_dir, file_name)?;
+        let mut seq = 0;
+        while is_taken(&path) {
+            seq += 1;
+            let sequenced = rotation::sequenced_file_name(file_name, seq);
+            path = FileHandle::generate_file_path(log_dir, &sequenced)?;
+        }
+
+        Ok(path)
+    }
+
+    async fn write_line(&mut self, line: &str) -> Result<(), io::Error> {
+        // The clock is read once a line, costly as it is on some hosts.
+        self.write_line_at(line, clock::now()).await
+    }
+
+    /// Writes a line received at `now`, which it is stamped with and which
+    /// decides the file it lands in.
+    async fn write_line_at(&mut self, line: &str, now: DateTime<Local>) -> Result<(), io::Error> {
+        if self.timestamp.is_none() && self.envelope.is_none() {
+            return self.append(line.as_bytes(), now).await;
+        }
+
+        let mut stamped = std::mem::take(&mut self.stamped);
+        stamped.clear();
+        let at = zone::fixed(&self.zone.at(now));
+        match (&self.envelope, &self.timestamp) {
+            (Some(format), _) => envelope::wrap(&self.channel, format, &at, line, &mut stamped),
+            (None, Some(format)) => format.stamp(&at, line, &mut stamped),
+            (None, None) => {}
+        }
+        let result = self.append(stamped.as_bytes(), now).await;
+        self.stamped = stamped;
+        result
+    }
+
+    /// Writes a message that isn't UTF-8 with its bytes as received, stamped
+    /// if lines are. An envelope takes text, so wraps the message with its
+    /// invalid sequences replaced.
+    async fn write_bytes_at(&mut self, line: &[u8], now: DateTime<Local>) -> Result<(), io::Error> {
+        if self.envelope.is_some() {
+            return self
+                .write_line_at(&String::from_utf8_lossy(line), now)
+                .await;
+        }
+
+        let mut stamped = Vec::with_capacity(line.len());
+        if let Some(ref format) = self.timestamp {
+            let mut stamp = String::new();
+            format.stamp(&zone::fixed(&self.zone.at(now)), "", &mut stamp);
+            stamped.extend_from_slice(stamp.as_bytes());
+        }
+        stamped.extend_from_slice(line);
+        self.append(&stamped, now).await
+    }
+
+    /// Writes a line as received, stamped already if lines are, so lines
+    /// held in memory keep the time they arrived at.
+    async fn append(&mut self, line: &[u8], now: DateTime<Local>) -> Result<(), io::Error> {
+        let priority = self.is_priority();
+        if let Some(ref mut held) = self.held {
+            if held.bytes + line.len() > held.capacity && !priority {
+                if held.dropped == 0 {
+                    tracing::warn!("dropping lines for {}, hold buffer is full", self.file_name);
+                }
+                held.dropped += 1;
+                self.dropped_lines += 1;
+            } else {
+                held.bytes += line.len();
+                held.lines.push(line.to_vec());
+            }
+
+            return Ok(());
+        }
+
+        if self.sequence.is_none() && self.terminator.is_none() {
+            return self.write_out(line, now).await;
+        }
+        let mut formatted = std::mem::take(&mut self.formatted);
+        formatted.clear();
+        if let Some(ref mut next) = self.sequence {
+            let _ = std::io::Write::write_fmt(&mut formatted, format_args!("{} ", next));
+            *next += 1;
+        }
+        match self.terminator {
+            Some(ref terminator) => terminator.apply(line, &mut formatted),
+            None => formatted.extend_from_slice(line),
+        }
+        let result = self.write_out(&formatted, now).await;
+        self.formatted = formatted;
+        result
+    }
+
+    /// Adds a line, numbered and terminated already, to the batch.
+    async fn write_out(&mut self, line: &[u8], now: DateTime<Local>) -> Result<(), io::Error> {
+        let priority = self.is_priority();
+        let at = self.zone.at(now);
+        self.update_current_file(at.clone()).await?;
+        if let Some(max_file_size) = self.max_file_size {
+            if self.file_bytes > self.header_bytes
+                && self.file_bytes + line.len() as u64 > max_file_size
+            {
+                self.schedule.force(&at);
+                self.open_period(Reason::Size).await?;
+            }
+        }
+        if let Some(ref mut disk_quota) = self.disk_quota {
+            if disk_quota.drops(self.file_bytes, line.len()) && !priority {
+                self.dropped_lines += 1;
+                if !disk_quota.stopped {
+                    disk_quota.stopped = true;
+                    tracing::warn!(
+                        "dropping lines for {}, its files are over their disk quota",
+                        self.file_name
+                    );
+                    let marker = disk_quota.marker();
+                    self.batch.extend_from_slice(marker.as_bytes());
+                    self.file_bytes += marker.len() as u64;
+                    self.flush().await?;
+                }
+
+                return Ok(());
+            }
+        }
+        let batch_bytes = self.batch_bytes;
+        if self.batch.len() + line.len() > batch_bytes {
+            self.flush().await?;
+        }
+        self.batch.extend_from_slice(line);
+        self.lines_written += 1;
+        self.file_stats.count(line.len(), zone::fixed(&at));
+        self.bytes_written += line.len() as u64;
+        self.written_at = Some(now);
+        self.file_bytes += line.len() as u64;
+
+        match self.durability {
+            _ if self.sync_policy == SyncPolicy::EveryLine => self.sync().await,
+            Durability::Buffered if self.batch.len() > batch_bytes => self.flush().await,
+            Durability::Buffered => Ok(()),
+            Durability::Flushed => self.flush().await,
+            Durability::Synced => self.sync().await,
+        }
+    }
+
+    /// Writes out the batched lines. The file's own cache is always empty
+    /// beforehand, so the whole batch goes out in a single `write`. Bytes a
+    /// write fails to get to the file are kept to be written out next time.
+    async fn flush(&mut self) -> Result<(), io::Error> {
+        if !self.batch.is_empty() || !self.unwritten.is_empty() {
+            let file = self.file().await?;
+            #[cfg(feature = "encryption")]
+            self.attach_sealer()?;
+            let encoded = match self.encoder {
+                Some(ref mut encoder) if !self.batch.is_empty() => {
+                    let encoded = encoder.encode(&self.batch)?;
+                    // Counted as they reach the file once written out.
+                    self.file_bytes =
+                        self.file_bytes - self.batch.len() as u64 + encoded.len() as u64;
+                    self.batch.clear();
+                    Some(encoded)
+                }
+                _ => None,
+            };
+            #[cfg(feature = "encryption")]
+            let encoded = match self.sealer {
+                Some(ref mut sealer) if encoded.is_some() || !self.batch.is_empty() => {
+                    let plain = encoded.as_deref().unwrap_or(&self.batch);
+                    let sealed = sealer.seal(plain)?;
+                    self.file_bytes = self.file_bytes - plain.len() as u64 + sealed.len() as u64;
+                    self.batch.clear();
+                    Some(sealed)
+                }
+                _ => encoded,
+            };
+            match self.writer {
+                Some(ref writer) => {
+                    let bytes = match encoded {
+                        Some(encoded) => encoded,
+                        None => {
+                            let capacity = self.batch.capacity();
+                            std::mem::replace(&mut self.batch, Vec::with_capacity(capacity))
+                        }
+                    };
+                    let bytes = match std::mem::take(&mut self.unwritten) {
+                        unwritten if unwritten.is_empty() => bytes,
+                        mut unwritten => {
+                            unwritten.extend_from_slice(&bytes);
+                            unwritten
+                        }
+                    };
+                    if let Err((error, unwritten)) = writer.write(&file, bytes).await {
+                        self.unwritten = unwritten;
+                        return Err(error);
+                    }
+                }
+                None => {
+                    match encoded {
+                        Some(encoded) if self.unwritten.is_empty() => self.unwritten = encoded,
+                        Some(encoded) => self.unwritten.extend_from_slice(&encoded),
+                        // Swapped, so both buffers keep their capacity.
+                        None if self.unwritten.is_empty() => {
+                            std::mem::swap(&mut self.unwritten, &mut self.batch)
+                        }
+                        None => {
+                            self.unwritten.extend_from_slice(&self.batch);
+                            self.batch.clear();
+                        }
+                    }
+                    (&*file).write_all(&self.unwritten).await?;
+                    (&*file).flush().await?;
+                    self.unwritten.clear();
+                    self.writes += 1;
+                }
+            }
+            self.unsynced = true;
+        }
+        self.flushed_at = time::Instant::now();
+
+        match self.writer {
+            // Lines of a priority channel are in the file once written.
+            Some(_) if self.is_priority() => self.drain().await,
+            Some(_) => Ok(()),
+            None => match self.current_file {
+                Some(ref file) => (&**file).flush().await,
+                None => Ok(()),
+            },
+        }
+    }
+
+    /// Attaches the sealer of an encrypted file to the current file unless
+    /// it is already, carrying on with what the file holds.
+    #[cfg(feature = "encryption")]
+    fn attach_sealer(&mut self) -> Result<(), io::Error> {
+        if let Some(ref mut sealer) = self.sealer {
+            if !sealer.is_attached() {
+                let cut = sealer.attach(&self.current_path)?;
+                self.file_bytes = self.file_bytes.saturating_sub(cut);
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits until the writer task, if there is one, has written out every
+    /// batch handed to it, taking back the bytes of those it couldn't.
+    async fn drain(&mut self) -> Result<(), io::Error> {
+        match self.writer {
+            Some(ref writer) => writer.drain().await.map_err(|(error, mut unwritten)| {
+                unwritten.append(&mut self.unwritten);
+                self.unwritten = unwritten;
+                error
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// The current file, reopened to be appended to if it was closed while
+    /// the channel was idle.
+    async fn file(&mut self) -> Result<Arc<File>, io::Error> {
+        if let Some(ref file) = self.current_file {
+            return Ok(file.clone());
+        }
+
+        // The directory may have gone away while the file was closed.
+        let file = Arc::new(FileHandle::open_in(&self.log_dir, &self.current_path).await?);
+        tracing::debug!("{} reopened {}", self.file_name, self.current_path);
+        self.current_file = Some(file.clone());
+        Ok(file)
+    }
+
+    /// Writes out the batched lines and ends the compressed member left open,
+    /// and an encrypted file with its last record, so the file decodes whole,
+    /// before it is let go of.
+    async fn close(&mut self) -> Result<(), io::Error> {
+        self.flush().await?;
+        self.drain().await?;
+        #[cfg(feature = "encryption")]
+        if self.current_file.is_some() {
+            self.attach_sealer()?;
+        }
+        // The member of a file closed while idle was ended already.
+        if let (Some(ref mut encoder), Some(ref file)) = (&mut self.encoder, &self.current_file) {
+            let encoded = encoder.finish()?;
+            #[cfg(feature = "encryption")]
+            let encoded = match self.sealer {
+                Some(ref mut sealer) if !encoded.is_empty() => sealer.seal(&encoded)?,
+                _ => encoded,
+            };
+            (&**file).write_all(&encoded).await?;
+            self.file_bytes += encoded.len() as u64;
+            (&**file).flush().await?;
+        }
+        #[cfg(feature = "encryption")]
+        if let (Some(ref mut sealer), Some(ref file)) = (&mut self.sealer, &self.current_file) {
+            let last = sealer.finish()?;
+            (&**file).write_all(&last).await?;
+            self.file_bytes += last.len() as u64;
+            (&**file).flush().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts the current file with the channel's header, if it has one and
+    /// the file is new rather than appended to.
+    fn write_header(&mut self) {
+        self.header_bytes = 0;
+        if self.file_bytes > 0 {
+            return;
+        }
+        if let Some(header) = self.boundary_line(|boundaries| boundaries.header.as_ref()) {
+            self.header_bytes = header.len() as u64;
+            self.batch.extend_from_slice(header.as_bytes());
+            self.file_bytes += header.len() as u64;
+        }
+    }
+
+    /// Ends the current file with the channel's footer, if it has one, ahead
+    /// of letting go of it.
+    fn write_footer(&mut self) {
+        if self.held.is_some() {
+            return;
+        }
+        if let Some(footer) = self.boundary_line(|boundaries| boundaries.footer.as_ref()) {
+            self.batch.extend_from_slice(footer.as_bytes());
+            self.file_bytes += footer.len() as u64;
+        }
+    }
+
+    fn boundary_line(
+        &self,
+        line: impl FnOnce(&Boundaries) -> Option<&boundary::BoundaryLine>,
+    ) -> Option<String> {
+        let boundaries = self.boundaries.as_ref()?;
+        let mut rendered = String::new();
+        line(boundaries)?.render(
+            &self.channel,
+            &boundaries.timestamp_format,
+            &zone::fixed(&self.zone.now()),
+            &self.file_stats,
+            &mut rendered,
+        );
+        Some(rendered)
+    }
+
+    /// Writes out the batched lines and fsyncs the file.
+    async fn sync(&mut self) -> Result<(), io::Error> {
+        self.flush().await?;
+        self.drain().await?;
+        if let Some(ref file) = self.current_file {
+            file.sync_data().await?;
+        }
+        self.unsynced = false;
+
+        Ok(())
+    }
+
+    /// Writes out the batch once it has waited past the flush interval.
+    async fn flush_if_stale(&mut self) -> Result<(), io::Error> {
+        let interval = match self.flush_interval {
+            Some(interval) => interval,
+            None => return Ok(()),
+        };
+        if self.batch.is_empty() || self.flushed_at.elapsed() < interval {
+            return Ok(());
+        }
+
+        self.flush().await
+    }
+
+    /// Flushes and closes the current file once the channel has been silent
+    /// for `idle`, leaving the next line to reopen it. Returns whether it
+    /// was closed.
+    async fn close_if_idle(
+        &mut self,
+        idle: chrono::Duration,
+        now: DateTime<Local>,
+    ) -> Result<bool, io::Error> {
+        if self.current_file.is_none() || self.held.is_some() || self.degraded.is_some() {
+            return Ok(false);
+        }
+        if now - self.written_at.unwrap_or(self.opened_at) < idle {
+            return Ok(false);
+        }
+
+        self.close().await?;
+        if self.unsynced && self.sync_policy == SyncPolicy::Interval {
+            self.sync().await?;
+        }
+        self.current_file = None;
+
+        Ok(true)
+    }
+
+    /// Where the last line went, for tracing.
+    fn outcome(&self) -> String {
+        match self.held {
+            Some(ref held) => format!("held in memory, {} bytes held", held.bytes),
+            None if self.batch.is_empty() => format!("written to {}", self.current_path),
+            None => format!(
+                "batched for {}, {} bytes batched",
+                self.current_path,
+                self.batch.len()
+            ),
+        }
+    }
+
+    /// Bytes of this handle's lines kept in memory.
+    fn memory_bytes(&self) -> usize {
+        self.batch.len() + self.unwritten.len() + self.held.as_ref().map_or(0, |held| held.bytes)
+    }
+
+    /// Writes the channel's lines went out in, gathered into fewer than one a
+    /// line by batching and, past a writer task, vectored writes.
+    #[cfg(feature = "metrics")]
+    fn writes(&self) -> u64 {
+        self.writes + self.writer.as_ref().map_or(0, Writer::writes)
+    }
+
+    /// Priority handles bypass batching and are exempt from every limit that
+    /// would otherwise drop or divert their lines.
+    fn is_priority(&self) -> bool {
+        self.durability != Durability::Buffered
+    }
+
+    async fn update_current_file(&mut self, now: DateTime<Zone>) -> Result<(), io::Error> {
+        if self.schedule.is_due(&now) {
+            self.schedule.advance(&now);
+            self.open_period(Reason::Time).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Switches to the file of the schedule's current period, moving on for
+    /// `reason`.
+    async fn open_period(&mut self, reason: Reason) -> Result<(), io::Error> {
+        self.write_footer();
+        self.close().await?;
+        let bytes = self.file_bytes;
+        let stats = std::mem::take(&mut self.file_stats);
+        if self
+            .profile
+            .is_some_and(BufferingProfile::syncs_on_rotation)
+            || self.sync_policy == SyncPolicy::Interval
+        {
+            self.sync().await?;
+        }
+
+        self.rotated_at = Some(clock::now());
+        let file_name = FileHandle::period_file_name(&self.schedule, &self.layout, &self.file_name);
+        // A file cut ahead of the schedule may be stamped with the same time
+        // as one already there.
+        let path_str = if self.schedule.seq > 0 {
+            FileHandle::unused_file_path(&self.log_dir, &file_name)?
+        } else {
+            FileHandle::generate_file_path(&self.log_dir, &file_name)?
+        };
+        let path_str = FileHandle::encoded_path(path_str, self.encoder.as_ref(), self.encrypted);
+        // The directory may have gone away since the last file was opened,
+        // and a new period may be sharded into a new one.
+        let file = FileHandle::open_in(&self.log_dir, &path_str).await?;
+        self.file_bytes = file.metadata().await?.len();
+        self.current_file = Some(Arc::new(file));
+        self.write_header();
+        let previous_path = std::mem::replace(&mut self.current_path, path_str);
+        if previous_path != self.current_path {
+            tracing::info!("{} rotated to {}", self.file_name, self.current_path);
+            self.rotations += 1;
+            let now = clock::now();
+            rotation_events::record(&rotation_events::Rotated {
+                channel: &self.channel,
+                from: &previous_path,
+                to: &self.current_path,
+                reason,
+                bytes,
+                lines: stats.lines,
+                opened_at: self.file_opened_at,
+                at: now,
+            });
+            self.file_opened_at = now;
+            self.link_current();
+            self.sync_dir()?;
+            let channel = self.channel.clone();
+            #[cfg(feature = "state")]
+            state::rotated(&channel, &previous_path, &self.current_path);
+            // A file compressed or encrypted as it was written is final
+            // already.
+            let compression = match self.encoder {
+                Some(_) => Compression::None,
+                None if self.encrypted => Compression::None,
+                None => self.compression,
+            };
+            self.enforce_disk_quota(Some(&previous_path)).await;
+            compress::spawn(
+                previous_path,
+                compression,
+                self.durability == Durability::Synced,
+                move |path| file_finished(channel, path),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Holds the channel's files to its disk quota, if it has one, sparing
+    /// `rotated_from`, the file just rotated away from and about to be
+    /// compressed. Failures are reported, the lines still written.
+    async fn enforce_disk_quota(&mut self, rotated_from: Option<&str>) {
+        let timestamp = match self.schedule.rotation {
+            Rotation::Hourly(timestamp) | Rotation::Periodic(timestamp, _) => timestamp,
+            Rotation::External => return,
+        };
+        let disk_quota = match self.disk_quota {
+            Some(ref mut disk_quota) => disk_quota,
+            None => return,
+        };
+
+        let enforced = disk_quota
+            .enforce(
+                &self.log_dir,
+                &self.file_name,
+                timestamp,
+                &self.current_path,
+                self.file_bytes,
+                rotated_from,
+            )
+            .await;
+        match enforced {
+            Ok(0) => {}
+            Ok(removed) => tracing::info!(
+                "removed {} files of {} over its disk quota",
+                removed,
+                self.file_name
+            ),
+            Err(error) => {
+                tracing::warn!(
+                    "unable to hold {} to its disk quota: {}",
+                    self.file_name,
+                    error
+                );
+                report::record_error("disk_quota", error);
+            }
+        }
+    }
+
+    /// Points the channel's link at the current file, if it keeps one. A link
+    /// that can't be made is reported, the lines still written.
+    fn link_current(&self) {
+        if !self.current_link {
+            return;
+        }
+
+        let link = format!("{}.log", self.file_name);
+        // Under external rotation, the current file is the link's path. Under
+        // --dir-template, it is in a subdirectory of the link's.
+        let link_path = Path::new(&self.log_dir).join(&link);
+        if Path::new(&self.current_path) == link_path {
+            return;
+        }
+        let target = link_path
+            .parent()
+            .and_then(|dir| Path::new(&self.current_path).strip_prefix(dir).ok())
+            .and_then(|target| target.to_str());
+        let target = match target {
+            Some(target) => target,
+            None => return,
+        };
+        let dir = std::path::Path::new(&self.log_dir);
+        if let Err(error) = log_dir::link_current(dir, &link, target) {
+            tracing::warn!(
+                "unable to link {} to {}: {}",
+                link,
+                self.current_path,
+                error
+            );
+            report::record_error("link", error);
+        }
+    }
+
+    /// Syncs the log directory when lines are synced, so a file just created
+    /// or renamed can't vanish in a power loss along with what it holds.
+    fn sync_dir(&self) -> Result<(), io::Error> {
+        if self.durability != Durability::Synced {
+            return Ok(());
+        }
+
+        // Subdirectories of --dir-template the file is in, then the log
+        // directory itself.
+        let log_dir = std::path::Path::new(&self.log_dir);
+        let mut dir = std::path::Path::new(&self.current_path).parent();
+        while let Some(subdir) = dir.filter(|dir| dir.starts_with(log_dir) && *dir != log_dir) {
+            log_dir::sync(subdir)?;
+            dir = subdir.parent();
+        }
+        log_dir::sync(log_dir)
+    }
+
+    /// Forces a new file regardless of the rotation schedule.
+    async fn rotate(&mut self) -> Result<(), io::Error> {
+        self.rotate_at(clock::now()).await
+    }
+
+    async fn rotate_at(&mut self, now: DateTime<Local>) -> Result<(), io::Error> {
+        self.schedule.force(&self.zone.at(now));
+        self.open_period(Reason::Manual).await
+    }
+
+    /// Closes and reopens the current file under its current name, picking up
+    /// a file that was moved away or a directory that was recreated.
+    async fn reopen(&mut self) -> Result<(), io::Error> {
+        self.close().await?;
+        let file = FileHandle::open_file(&self.current_path).await?;
+        self.file_bytes = file.metadata().await?.len();
+        self.current_file = Some(Arc::new(file));
+        self.write_header();
+
+        Ok(())
+    }
+
+    /// Reopens the current file at its path if it was deleted or renamed
+    /// while open, as lines written on would vanish with it. Returns whether
+    /// it was reopened.
+    async fn reopen_if_moved(&mut self) -> Result<bool, io::Error> {
+        let file = match self.current_file {
+            Some(ref file) if self.held.is_none() && self.degraded.is_none() => file.clone(),
+            _ => return Ok(false),
+        };
+        let path = std::path::Path::new(&self.current_path);
+        let what = match log_dir::file_state(&file.metadata().await?, path) {
+            FileState::InPlace => return Ok(false),
+            FileState::Removed => "deleted",
+            FileState::Moved => "renamed",
+        };
+        tracing::warn!(
+            "{} was {} while open, recreating it",
+            self.current_path,
+            what
+        );
+
+        self.drain().await?;
+        // The compressed member left open went with the old file; the new one
+        // starts a member of its own.
+        if let Some(ref mut encoder) = self.encoder {
+            encoder.finish()?;
+        }
+        // And so did the records sealed into it.
+        #[cfg(feature = "encryption")]
+        if let Some(ref mut sealer) = self.sealer {
+            sealer.detach();
+        }
+        // Recreated along with its directory if that went too, the lines
+        // still batched going to it after any header.
+        self.current_file = None;
+        let file = self.file().await?;
+        self.file_bytes = file.metadata().await?.len();
+        let batched = std::mem::take(&mut self.batch);
+        self.write_header();
+        self.batch.extend_from_slice(&batched);
+        self.file_bytes += batched.len() as u64;
+
+        Ok(true)
+    }
+
+    /// Stops touching the file system, keeping up to `capacity` bytes of lines
+    /// in memory until `restore` is called.
+    fn hold(&mut self, capacity: usize) {
+        if self.held.is_none() {
+            self.held = Some(HeldLines {
+                lines: Vec::new(),
+                bytes: 0,
+                capacity,
+                dropped: 0,
+            });
+        }
+    }
+
+    /// Reopens the current file once the log directory is usable again, or a
+    /// degraded handle's file may be, and writes out whatever was held in the
+    /// meantime along with the bytes failed writes left behind.
+    async fn restore(&mut self) -> Result<(), io::Error> {
+        if let Err(error) = self.close().await {
+            tracing::warn!(
+                "unable to write buffered lines to {}, writing them to the file reopened: {}",
+                self.current_path,
+                error
+            );
+        }
+        let file = FileHandle::open_file(&self.current_path).await?;
+        self.file_bytes = file.metadata().await?.len();
+        self.current_file = Some(Arc::new(file));
+        self.write_header();
+
+        if let Some(held) = self.held.take() {
+            if held.dropped > 0 {
+                tracing::warn!(
+                    "{} lines of {} were dropped while the log directory was unavailable",
+                    held.dropped,
+                    self.file_name
+                );
+            }
+            let now = clock::now();
+            for line in held.lines.iter() {
+                self.append(line, now).await?;
+            }
+        }
+        if !self.unwritten.is_empty() {
+            self.flush().await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A channel's files, as the writer's `Router` writes to them. The time
+/// handed in is the router's, `clock::Clock`.
+impl Sink for FileHandle {
+    fn write<'a>(&'a mut self, line: &'a str, now: &'a DateTime<FixedOffset>) -> SinkFuture<'a> {
+        Box::pin(self.write_line_at(line, now.with_timezone(&Local)))
+    }
+
+    fn write_bytes<'a>(
+        &'a mut self,
+        line: &'a [u8],
+        now: &'a DateTime<FixedOffset>,
+    ) -> SinkFuture<'a> {
+        Box::pin(self.write_bytes_at(line, now.with_timezone(&Local)))
+    }
+
+    fn rotate<'a>(&'a mut self, now: &'a DateTime<FixedOffset>) -> SinkFuture<'a> {
+        Box::pin(self.rotate_at(now.with_timezone(&Local)))
+    }
+
+    fn flush(&mut self) -> SinkFuture<'_> {
+        Box::pin(FileHandle::flush(self))
+    }
+
+    fn close(&mut self) -> SinkFuture<'_> {
+        Box::pin(FileHandle::close(self))
+    }
+
+    fn current_path(&self) -> Option<&std::path::Path> {
+        Some(std::path::Path::new(&self.current_path))
+    }
+}
+
+/// What the writer routes a channel's lines to: a sink, and the files behind
+/// it for channels kept in files, which the writer manages beyond what a
+/// sink is told.
+trait ChannelOutput: Sink {
+    fn handle(&self) -> Option<&FileHandle> {
+        None
+    }
+
+    fn handle_mut(&mut self) -> Option<&mut FileHandle> {
+        None
+    }
+
+    /// Whether lines are thrown away rather than kept, to be counted as
+    /// discarded.
+    fn discards(&self) -> bool {
+        false
+    }
+}
+
+/// Channels mapped to `sink = "null"`: accepted, counted, never written.
+impl ChannelOutput for NullSink {
+    fn discards(&self) -> bool {
+        true
+    }
+}
+
+impl ChannelOutput for FileHandle {
+    fn handle(&self) -> Option<&FileHandle> {
+        Some(self)
+    }
+
+    fn handle_mut(&mut self) -> Option<&mut FileHandle> {
+        Some(self)
+    }
+}
+
+/// Lines held back while a single channel is paused, typically during
+/// maintenance of whatever consumes that channel's files.
+#[derive(Default)]
+struct ChannelPause {
+    buffered: Vec<String>,
+    buffered_bytes: usize,
+    rejected: u64,
+}
+
+/// Lines of a channel going to the null sink.
+#[derive(Default)]
+struct DiscardedLines {
+    lines: u64,
+    bytes: u64,
+}
+
+/// What a channel's handle is opened with, kept so channels accepted on
+/// reload, and the overflow files opened along the way, get the same
+/// treatment as those accepted at startup.
+struct ChannelSettings {
+    log_dir: String,
+    rotation: Rotation,
+    channel_rotations: BTreeMap<String, Rotation>,
+    /// Directory of each channel writing somewhere else than the log
+    /// directory.
+    channel_dirs: BTreeMap<String, String>,
+    /// Name the files of a channel take instead of the channel's.
+    channel_file_names: BTreeMap<String, String>,
+    channel_max_file_sizes: BTreeMap<String, u64>,
+    channel_boundaries: BTreeMap<String, Boundaries>,
+    /// Where the lines of each channel not written to files go.
+    channel_sinks: BTreeMap<String, ChannelSink>,
+    channel_disk_quotas: BTreeMap<String, DiskQuota>,
+    priority_channels: Vec<String>,
+    priority_durability: Durability,
+    sequence_numbers: bool,
+    current_symlink: bool,
+    layout: Layout,
+    timestamp_format: Option<TimestampFormat>,
+    /// How the time of `jsonl` records is written, when lines are written
+    /// as JSON Lines.
+    envelope: Option<TimestampFormat>,
+    max_file_size: Option<u64>,
+    compression: Compression,
+    channel_compression: BTreeMap<String, Compression>,
+    /// Channels whose files are encrypted as lines are written out.
+    encrypted_channels: Vec<String>,
+    /// Zone of every channel without one of its own.
+    zone: Zone,
+    channel_zones: BTreeMap<String, Zone>,
+    line_terminators: BTreeMap<String, LineTerminator>,
+    buffering_profiles: BTreeMap<String, BufferingProfile>,
+    sync_policy: SyncPolicy,
+    channel_sync_policies: BTreeMap<String, SyncPolicy>,
+    #[cfg(feature = "redact")]
+    transforms: Transforms,
+    flush_bytes: usize,
+    flush_interval: Option<time::Duration>,
+    paired_services: Vec<String>,
+    /// Group of each grouped channel.
+    channel_groups: BTreeMap<String, String>,
+    rollup_groups: Vec<String>,
+    /// How the channel names producers send are normalized before they are
+    /// looked up.
+    channel_names: ChannelNames,
+    /// Channel each alias of the config file's channels stands for.
+    channel_aliases: BTreeMap<String, String>,
+}
+
+impl ChannelSettings {
+    /// Settings from the command line, the channels of the config file
+    /// taking theirs over the flags.
+    async fn with_options(
+        options: &CliOptions,
+        configured_channels: &BTreeMap<String, ChannelConfig>,
+    ) -> Result<Self, io::Error> {
+        let rotation = if options.external_rotation {
+            Rotation::External
+        } else {
+            rotation_every(options, options.rotation_interval)?
+        };
+        if options.dir_template.is_some()
+            && (options.external_rotation
+                || options.sequence_numbers
+                || options.inapt_disk_quota.is_some())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--dir-template shards timestamped files and can't be combined with \
+                 --external-rotation, --sequence-numbers or --inapt-disk-quota",
+            ));
+        }
+        if options.output_format == OutputFormat::Jsonl
+            && (options.sequence_numbers || options.prepend_timestamp)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--sequence-numbers and --prepend-timestamp would break the records of \
+                 --output-format jsonl",
+            ));
+        }
+        if options.queue_depth.is_some()
+            && options.queue_full != QueueFull::Block
+            && options.input_format.is_paired()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--queue-full drop-oldest and drop-newest drop single lines, which would \
+                 split the channel and message lines of --input-format lines; block, or \
+                 give another --input-format",
+            ));
+        }
+        let mut rotation_intervals = parse_pairs(&options.channel_rotation_intervals)?;
+        let mut channel_dirs: BTreeMap<String, String> =
+            parse_pairs(&options.channel_dirs)?.into_iter().collect();
+        let mut channel_file_names = BTreeMap::new();
+        let mut channel_max_file_sizes = BTreeMap::new();
+        let mut buffering_profiles = parse_pairs(&options.buffering_profiles)?;
+        let mut channel_sync_policies = BTreeMap::new();
+        let mut channel_sinks = BTreeMap::new();
+        let mut channel_disk_quotas = BTreeMap::new();
+        let mut channel_compression = parse_pairs(&options.compress_channels)?;
+        #[cfg(feature = "encryption")]
+        let mut encrypted_channels: Vec<String> = options
+            .encrypt_channels
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect();
+        #[cfg(not(feature = "encryption"))]
+        let encrypted_channels = Vec::new();
+        #[cfg(feature = "redact")]
+        let mut transforms = Transforms::new(&options.redactions, &options.channel_redactions);
+        let mut channel_aliases = BTreeMap::new();
+        let mut channel_boundaries = BTreeMap::new();
+        let mut priority_channels: Vec<String> = options
+            .priority_channels
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect();
+        for (channel, config) in configured_channels.iter() {
+            if let Some(ref interval) = config.rotation_interval {
+                rotation_intervals.push((channel.clone(), interval.clone()));
+            }
+            if let Some(ref size) = config.max_file_size {
+                let size = parse_file_size(size)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                channel_max_file_sizes.insert(channel.clone(), size);
+            }
+            if let Some(ref directory) = config.directory {
+                channel_dirs.insert(channel.clone(), directory.clone());
+            }
+            if let Some(ref file_name) = config.file_name {
+                channel_file_names.insert(channel.clone(), file_name.clone());
+            }
+            if let Some(ref profile) = config.buffering_profile {
+                buffering_profiles.push((channel.clone(), profile.clone()));
+            }
+            match config.priority.as_deref() {
+                Some("high") => priority_channels.push(channel.clone()),
+                Some("normal") | None => {}
+                Some(priority) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("unknown priority {} of {}", priority, channel),
+                    ))
+                }
+            }
+            if let Some(ref policy) = config.sync_policy {
+                let policy = policy
+                    .parse()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                channel_sync_policies.insert(channel.clone(), policy);
+            }
+            if let Some(ref compression) = config.compress {
+                channel_compression.push((channel.clone(), compression.clone()));
+            }
+            #[cfg(feature = "encryption")]
+            match config.encrypt {
+                Some(true) => encrypted_channels.push(channel.clone()),
+                Some(false) => encrypted_channels.retain(|name| name != channel),
+                None => {}
+            }
+            if let Some(ref sink) = config.sink {
+                let sink: ChannelSink = sink
+                    .parse()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                #[cfg(feature = "forward")]
+                if sink == ChannelSink::Forward
+                    && config.forward.is_none()
+                    && !parse_pairs(&options.forwards.join(","))?
+                        .iter()
+                        .any(|(name, _)| name == channel)
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "channel {} forwards its lines but has no destination",
+                            channel
+                        ),
+                    ));
+                }
+                if sink != ChannelSink::File {
+                    channel_sinks.insert(channel.clone(), sink);
+                }
+            }
+            if let Some(ref size) = config.disk_quota {
+                if options.external_rotation
+                    || options.file_name_template.is_some()
+                    || options.dir_template.is_some()
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "disk quotas need default file names in the log directory, not \
+                         --external-rotation, --file-name-template or --dir-template",
+                    ));
+                }
+                let limit = parse_file_size(size)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                let action = match config.disk_quota_action {
+                    Some(ref action) => action
+                        .parse()
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+                    None => DiskQuotaAction::DeleteOldest,
+                };
+                channel_disk_quotas.insert(channel.clone(), DiskQuota::new(limit, action));
+            }
+            if config.header.is_some() || config.footer.is_some() {
+                let parse = |line: &Option<String>| {
+                    line.as_deref()
+                        .map(str::parse)
+                        .transpose()
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+                };
+                let boundaries = Boundaries {
+                    header: parse(&config.header)?,
+                    footer: parse(&config.footer)?,
+                    timestamp_format: options.timestamp_format.clone(),
+                };
+                channel_boundaries.insert(channel.clone(), boundaries);
+            }
+            for alias in config.aliases.iter().flatten() {
+                if let Some(taken) = channel_aliases.insert(alias.clone(), channel.clone()) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "alias `{}` stands for both {} and {}",
+                            alias, taken, channel
+                        ),
+                    ));
+                }
+            }
+            #[cfg(feature = "redact")]
+            for redaction in config.redact.iter().flatten() {
+                let redaction: Redaction = redaction
+                    .parse()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                transforms.add(channel, redaction);
+            }
+        }
+
+        #[cfg(feature = "encryption")]
+        if !encrypted_channels.is_empty() && options.encryption_key_file.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "encrypted channels need an --encryption-key-file",
+            ));
+        }
+
+        for directory in channel_dirs.values() {
+            log_dir::ensure(directory)?;
+        }
+
+        let mut channel_rotations = BTreeMap::new();
+        for (channel, interval) in rotation_intervals {
+            let interval = parse_rotation_interval(&interval)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            if !options.external_rotation {
+                channel_rotations.insert(channel, rotation_every(options, interval)?);
+            }
+        }
+        Ok(ChannelSettings {
+            log_dir: options.log_dir.clone(),
+            rotation,
+            channel_rotations,
+            channel_dirs,
+            channel_file_names,
+            channel_max_file_sizes,
+            channel_boundaries,
+            channel_sinks,
+            channel_disk_quotas,
+            priority_channels,
+            priority_durability: if options.priority_fsync {
+                Durability::Synced
+            } else {
+                Durability::Flushed
+            },
+            sequence_numbers: options.sequence_numbers,
+            current_symlink: options.current_symlink,
+            layout: Layout::of(options),
+            timestamp_format: Some(options.timestamp_format.clone())
+                .filter(|_| options.prepend_timestamp),
+            envelope: Some(options.timestamp_format.clone())
+                .filter(|_| options.output_format == OutputFormat::Jsonl),
+            max_file_size: options.max_file_size,
+            compression: options.compress,
+            channel_compression: channel_compression
+                .into_iter()
+                .map(|(channel, compression)| {
+                    let compression = compression
+                        .parse()
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                    Ok((channel, compression))
+                })
+                .collect::<Result<_, io::Error>>()?,
+            encrypted_channels,
+            zone: options.timezone.clone(),
+            channel_zones: parse_pairs(&options.channel_timezones)?
+                .into_iter()
+                .map(|(channel, zone)| {
+                    let zone = zone
+                        .parse()
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                    Ok((channel, zone))
+                })
+                .collect::<Result<_, io::Error>>()?,
+            line_terminators: parse_pairs(&options.line_terminators)?
+                .into_iter()
+                .map(|(channel, terminator)| {
+                    let terminator = terminator
+                        .parse()
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                    Ok((channel, terminator))
+                })
+                .collect::<Result<_, io::Error>>()?,
+            buffering_profiles: buffering_profiles
+                .into_iter()
+                .map(|(channel, profile)| {
+                    let profile = profile
+                        .parse()
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                    Ok((channel, profile))
+                })
+                .collect::<Result<_, io::Error>>()?,
+            sync_policy: options.sync_policy,
+            channel_sync_policies,
+            #[cfg(feature = "redact")]
+            transforms,
+            flush_bytes: options.flush_bytes.unwrap_or(BATCH_BYTES),
+            flush_interval: options
+                .flush_interval
+                .filter(|millis| *millis > 0)
+                .map(time::Duration::from_millis),
+            paired_services: options
+                .paired_channels
+                .split(',')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect(),
+            channel_groups: parse_pairs(&options.channel_groups)?
+                .into_iter()
+                .flat_map(|(group, members)| {
+                    members
+                        .split('+')
+                        .filter(|s| !s.is_empty())
+                        .map(|member| (member.to_string(), group.clone()))
+                        .collect::<Vec<_>>()
+                })
+                .collect(),
+            rollup_groups: options
+                .rollup_groups
+                .split(',')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect(),
+            channel_names: ChannelNames::new(&options.channel_normalizations),
+            channel_aliases,
+        })
+    }
+
+    /// Path of the file a handle of the channel would open now.
+    fn current_path(&self, channel_name: &str) -> Result<String, io::Error> {
+        let log_dir = self.channel_dirs.get(channel_name).unwrap_or(&self.log_dir);
+        let file_name = self
+            .channel_file_names
+            .get(channel_name)
+            .map_or(channel_name, String::as_str);
+        let schedule = Schedule::new(
+            self.rotation_of(channel_name),
+            &self.zone_of(channel_name).now(),
+        );
+        let file_name = FileHandle::period_file_name(&schedule, &self.layout, file_name);
+        let path = FileHandle::generate_file_path(log_dir, &file_name)?;
+        let encoder = Encoder::streaming(self.compression_of(channel_name));
+
+        Ok(FileHandle::encoded_path(
+            path,
+            encoder.as_ref(),
+            self.encrypts(channel_name),
+        ))
+    }
+
+    async fn open(&self, channel_name: &str) -> Result<FileHandle, io::Error> {
+        let zone = self.zone_of(channel_name);
+        let log_dir = self.channel_dirs.get(channel_name).unwrap_or(&self.log_dir);
+        let file_name = self
+            .channel_file_names
+            .get(channel_name)
+            .map_or(channel_name, String::as_str);
+        let mut handle = FileHandle::create(
+            log_dir,
+            file_name,
+            self.rotation_of(channel_name),
+            zone,
+            self.layout.clone(),
+            self.compression_of(channel_name),
+            self.encrypts(channel_name),
+        )
+        .await?;
+        handle.channel = channel_name.to_string();
+        let settings_name = self.settings_name(channel_name);
+        if self
+            .priority_channels
+            .iter()
+            .any(|name| name == channel_name || name == settings_name)
+        {
+            handle.durability = self.priority_durability;
+            handle.sync_dir()?;
+            // Batches of busier channels sharing a writer task would be
+            // written ahead of the channel's lines.
+            if let Some(writer) = writer_pool::dedicated(channel_name) {
+                handle.writer = Some(writer);
+            }
+        }
+        if self.sequence_numbers {
+            handle.sequence = Some(sequence::resume(log_dir, file_name).await?);
+        }
+        handle.timestamp = self.timestamp_format.clone();
+        handle.envelope = self.envelope.clone();
+        if self.current_symlink {
+            handle.current_link = true;
+            handle.link_current();
+        }
+        handle.max_file_size = self
+            .channel_max_file_sizes
+            .get(channel_name)
+            .copied()
+            .or(self.max_file_size);
+        handle.boundaries = self
+            .channel_boundaries
+            .get(channel_name)
+            .or_else(|| self.channel_boundaries.get(settings_name))
+            .cloned();
+        handle.write_header();
+        handle.terminator = self
+            .line_terminators
+            .get(channel_name)
+            .or_else(|| self.line_terminators.get(settings_name))
+            .cloned();
+        handle.profile = self
+            .buffering_profiles
+            .get(channel_name)
+            .or_else(|| self.buffering_profiles.get(settings_name))
+            .copied();
+        handle.sync_policy = self
+            .channel_sync_policies
+            .get(channel_name)
+            .or_else(|| self.channel_sync_policies.get(settings_name))
+            .copied()
+            .unwrap_or(self.sync_policy);
+        match handle.profile {
+            Some(profile) => {
+                handle.batch_bytes = profile.batch_bytes();
+                handle.flush_interval = profile.flush_interval();
+            }
+            None => {
+                handle.batch_bytes = self.flush_bytes;
+                handle.flush_interval = self.flush_interval;
+            }
+        }
+        handle.disk_quota = self.channel_disk_quotas.get(channel_name).copied();
+        handle.enforce_disk_quota(None).await;
+
+        Ok(handle)
+    }
+
+    fn compression_of(&self, channel_name: &str) -> Compression {
+        self.channel_compression
+            .get(channel_name)
+            .or_else(|| {
+                self.channel_compression
+                    .get(self.settings_name(channel_name))
+            })
+            .copied()
+            .unwrap_or(self.compression)
+    }
+
+    /// Whether the channel's files are encrypted.
+    fn encrypts(&self, channel_name: &str) -> bool {
+        let settings_name = self.settings_name(channel_name);
+        self.encrypted_channels
+            .iter()
+            .any(|name| name == channel_name || name == settings_name)
+    }
+
+    fn rotation_of(&self, channel_name: &str) -> Rotation {
+        self.channel_rotations
+            .get(channel_name)
+            .or_else(|| self.channel_rotations.get(self.settings_name(channel_name)))
+            .copied()
+            .unwrap_or(self.rotation)
+    }
+
+    fn zone_of(&self, channel_name: &str) -> Zone {
+        self.channel_zones
+            .get(channel_name)
+            .or_else(|| self.channel_zones.get(self.settings_name(channel_name)))
+            .cloned()
+            .unwrap_or_else(|| self.zone.clone())
+    }
+
+    /// The service and stream of a `<service>.out` or `<service>.err`
+    /// channel, when the service is paired.
+    fn paired_stream<'a>(&self, channel_name: &'a str) -> Option<(&'a str, &'a str)> {
+        let (service, stream) = channel_name.rsplit_once('.')?;
+        let paired = self.paired_services.iter().any(|name| name == service);
+
+        Some((service, stream)).filter(|_| paired && (stream == "out" || stream == "err"))
+    }
+
+    /// Where a channel's lines go.
+    /// Whether lines of `channel_name` are routed to a sink of the writer's,
+    /// rather than only forwarded.
+    #[cfg(any(feature = "control-socket", feature = "http-admin", feature = "config"))]
+    fn routes(&self, channel_name: &str) -> bool {
+        match self.sink(channel_name) {
+            ChannelSink::File | ChannelSink::Null => true,
+            #[cfg(feature = "forward")]
+            ChannelSink::Forward => false,
+        }
+    }
+
+    /// What the writer routes `channel_name` to: its files, or the null
+    /// sink. Channels only forwarded have none.
+    async fn open_sink(
+        &self,
+        channel_name: &str,
+    ) -> Result<Option<Box<dyn ChannelOutput>>, io::Error> {
+        match self.sink(channel_name) {
+            ChannelSink::File => Ok(Some(Box::new(self.open(channel_name).await?))),
+            ChannelSink::Null => Ok(Some(Box::new(NullSink))),
+            #[cfg(feature = "forward")]
+            ChannelSink::Forward => Ok(None),
+        }
+    }
+
+    fn sink(&self, channel_name: &str) -> ChannelSink {
+        self.channel_sinks
+            .get(self.settings_name(channel_name))
+            .copied()
+            .unwrap_or(ChannelSink::File)
+    }
+
+    /// Both stream channels of every paired service.
+    fn paired_channels(&self) -> Vec<String> {
+        self.paired_services
+            .iter()
+            .flat_map(|service| {
+                ["out", "err"]
+                    .iter()
+                    .map(move |stream| format!("{}.{}", service, stream))
+            })
+            .collect()
+    }
+
+    /// Reports how `channels`, along with the paired streams, differ from the
+    /// channels recently written to the log directory. Run before any file
+    /// is opened for them.
+    async fn report_delta(&self, channels: &[&str], inapt_name: &str) -> Result<(), io::Error> {
+        let mut accepted: BTreeSet<String> = channels.iter().map(|s| s.to_string()).collect();
+        accepted.extend(self.paired_channels());
+        let mut expected: BTreeSet<String> = self
+            .paired_services
+            .iter()
+            .chain(self.rollup_groups.iter())
+            .cloned()
+            .collect();
+        expected.insert(inapt_name.to_string());
+
+        ChannelDelta::scan(&self.log_dir, self.rotation, &accepted, &expected)
+            .await?
+            .report();
+
+        Ok(())
+    }
+
+    /// Name a channel's settings are looked up by: its service's, for either
+    /// stream of a paired service, its group's for a grouped channel, and the
+    /// channel's own for a tenant's.
+    fn settings_name<'a>(&'a self, channel_name: &'a str) -> &'a str {
+        let channel_name = tenant::split(channel_name).map_or(channel_name, |(_, name)| name);
+        match self.paired_stream(channel_name) {
+            Some((service, _)) => service,
+            None => self
+                .channel_groups
+                .get(channel_name)
+                .map_or(channel_name, String::as_str),
+        }
+    }
+
+    /// Checks that no alias is the name of an accepted channel, which would
+    /// leave the alias unused.
+    fn check_aliases(&self, accepted: &[&str]) -> Result<(), io::Error> {
+        match self
+            .channel_aliases
+            .keys()
+            .find(|alias| accepted.contains(&alias.as_str()))
+        {
+            Some(alias) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("alias `{}` is also a channel", alias),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// A channel name sent by `protocol`, normalized; a tenant's keeps the
+    /// tenant as it is.
+    fn normalized<'a>(&self, protocol: &str, channel_name: &'a str) -> Cow<'a, str> {
+        if self.channel_names.is_empty() {
+            return Cow::Borrowed(channel_name);
+        }
+
+        match tenant::split(channel_name) {
+            Some((tenant, name)) => Cow::Owned(format!(
+                "{}/{}",
+                tenant,
+                self.channel_names.normalize(protocol, name)
+            )),
+            None => self.channel_names.normalize(protocol, channel_name),
+        }
+    }
+
+    /// The channel a name sent by a producer stands for, if it is an alias;
+    /// a tenant's keeps the tenant.
+    fn aliased(&self, channel_name: &str) -> Option<String> {
+        if self.channel_aliases.is_empty() {
+            return None;
+        }
+
+        match tenant::split(channel_name) {
+            Some((tenant, name)) => self
+                .channel_aliases
+                .get(name)
+                .map(|channel| format!("{}/{}", tenant, channel)),
+            None => self.channel_aliases.get(channel_name).cloned(),
+        }
+    }
+
+    fn is_alias(&self, channel_name: &str) -> bool {
+        let name = tenant::split(channel_name).map_or(channel_name, |(_, name)| name);
+        self.channel_aliases.contains_key(name)
+    }
+
+    /// The group a channel is rolled up into, if any.
+    fn rollup_group(&self, channel_name: &str) -> Option<&str> {
+        self.channel_groups
+            .get(channel_name)
+            .map(String::as_str)
+            .filter(|group| self.rollup_groups.iter().any(|name| name == group))
+    }
+}
+
+struct FileWriter {
+    tenants: Option<Tenants>,
+    paused: bool,
+    paused_channels: BTreeMap<String, ChannelPause>,
+    discarded_lines: BTreeMap<String, DiscardedLines>,
+    pause_buffer_bytes: usize,
+    backpressure: Option<Backpressure>,
+    memory_budget: Option<MemoryBudget>,
+    log_dir_watch: LogDirWatch,
+    log_dir_unavailable: bool,
+    log_dir_hold_bytes: usize,
+    write_failure: WriteFailure,
+    spill_buffer_size: usize,
+    quotas: BTreeMap<String, Quota>,
+    quota_action: QuotaAction,
+    rate_limits: BTreeMap<String, RateLimit>,
+    rate_limit_action: RateLimitAction,
+    /// How long the input whose lines are being written is to wait, once
+    /// it has let go of the writer, for `--rate-limit-action block`.
+    throttle: Option<time::Duration>,
+    /// Samplers of the channels keeping only a fraction of their lines.
+    samplers: BTreeMap<String, Sampler>,
+    #[cfg(feature = "filter")]
+    filters: Option<Filters>,
+    inapt_file_handle: FileHandle,
+    inapt_format: InaptFormat,
+    /// How the time of `structured` inapt lines is written.
+    inapt_timestamp: TimestampFormat,
+    /// The accepted channels, each written to its sink.
+    channels: Router<dyn ChannelOutput>,
+    overflow_handles: BTreeMap<String, FileHandle>,
+    /// Combined file of each paired service and rollup file of each rolled
+    /// up group, keyed by service or group, and the router's own records
+    /// under `_internal`.
+    combined_handles: BTreeMap<String, FileHandle>,
+    pipe_outs: BTreeMap<String, PipeOut>,
+    tees: BTreeMap<String, TeeStream>,
+    #[cfg(feature = "forward")]
+    forwarders: BTreeMap<String, Forwarder>,
+    #[cfg(any(feature = "control-socket", feature = "http-admin"))]
+    recent_lines: Option<RecentLines>,
+    reorderer: Option<Reorderer>,
+    deduplicator: Option<Deduplicator>,
+    idle_watch: Option<IdleWatch>,
+    /// A message being written that isn't UTF-8: the text standing for it
+    /// and its bytes, written instead where the text reaches its file as is.
+    binary: Option<(String, Vec<u8>)>,
+    /// How long a channel is silent before its file is closed.
+    idle_close_after: Option<chrono::Duration>,
+    meta_channel: Option<String>,
+    pending: Option<PendingChannels>,
+    /// Channels created on the fly, and how many may be, when unknown
+    /// channels are created.
+    dynamic_channels: BTreeSet<String>,
+    max_dynamic_channels: Option<usize>,
+    /// Channels of the config file as last read.
+    #[cfg(feature = "config")]
+    configured_channels: BTreeMap<String, ChannelConfig>,
+    stats: HourlyStats,
+    channel_settings: ChannelSettings,
+    #[cfg(feature = "gelf")]
+    gelf_sink: Option<GelfSink>,
+    #[cfg(feature = "kafka")]
+    kafka_sink: Option<KafkaSink>,
+    #[cfg(feature = "siem")]
+    siem_formatter: SiemFormatter,
+    #[cfg(feature = "trace")]
+    tracer: Option<Tracer>,
+}
+
+impl FileWriter {
+    async fn with_options(options: &CliOptions) -> Result<Self, io::Error> {
+        log_dir::ensure(&options.log_dir)?;
+        if let Some(ref inapt_dir) = options.inapt_dir {
+            log_dir::ensure(inapt_dir)?;
+        }
+        if !options.allow_shared_dir {
+            log_dir::lock(&options.log_dir)?;
+            if let Some(ref inapt_dir) = options.inapt_dir {
+                log_dir::lock(inapt_dir)?;
+            }
+        }
+        let channel_settings =
+            ChannelSettings::with_options(options, &options.configured_channels).await?;
+        let rotation = channel_settings.rotation;
+
+        let accepted = accepted_channels(options);
+        check_accepted_channels(&accepted)?;
+        channel_settings.check_aliases(&accepted)?;
+        channel_settings
+            .report_delta(&accepted, &options.inapt_file_name)
+            .await?;
+
+        let tenants = tenants(options)?;
+        let mut channel_names: Vec<String> = accepted.iter().map(|s| s.to_string()).collect();
+        if let Some(ref tenants) = tenants {
+            tenants.create_dirs(&options.log_dir).await?;
+            channel_names.extend(tenants.channels(&accepted));
+        }
+
+        let mut channels: Router<dyn ChannelOutput> = Router::with_sinks(Vec::new());
+        channels.set_clock(clock::Clock);
+        for channel_name in channel_names {
+            if let Some(sink) = channel_settings.open_sink(&channel_name).await? {
+                channels.insert(channel_name, sink);
+            }
+        }
+
+        let mut combined_handles = BTreeMap::new();
+        for channel_name in channel_settings.paired_channels() {
+            let handle = channel_settings.open(&channel_name).await?;
+            channels.insert(channel_name, Box::new(handle));
+        }
+        for service in channel_settings.paired_services.iter() {
+            let handle = channel_settings.open(service).await?;
+            combined_handles.insert(service.clone(), handle);
+        }
+        for group in channel_settings.rollup_groups.iter() {
+            if accepted.contains(&group.as_str()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("rollup group `{}` is also a channel", group),
+                ));
+            }
+            let handle = channel_settings.open(group).await?;
+            combined_handles.insert(group.clone(), handle);
+        }
+        if options.internal_log {
+            let handle = channel_settings.open(logger::INTERNAL_CHANNEL).await?;
+            combined_handles.insert(logger::INTERNAL_CHANNEL.to_string(), handle);
+        }
+
+        let inapt_dir = options.inapt_dir.as_ref().unwrap_or(&options.log_dir);
+        let inapt_zone = channel_settings.zone_of(&options.inapt_file_name);
+        let mut inapt_file_handle = FileHandle::create(
+            inapt_dir,
+            &options.inapt_file_name,
+            rotation,
+            inapt_zone,
+            channel_settings.layout.clone(),
+            channel_settings.compression_of(&options.inapt_file_name),
+            channel_settings.encrypts(&options.inapt_file_name),
+        )
+        .await?;
+        inapt_file_handle.batch_bytes = channel_settings.flush_bytes;
+        inapt_file_handle.flush_interval = channel_settings.flush_interval;
+        inapt_file_handle.max_file_size = options.inapt_max_file_size;
+        inapt_file_handle.disk_quota = options
+            .inapt_disk_quota
+            .map(|limit| DiskQuota::new(limit, DiskQuotaAction::DeleteOldest));
+        inapt_file_handle.enforce_disk_quota(None).await;
+
+        let pending = match options.unknown_channels {
+            UnknownChannels::Reject | UnknownChannels::Create => None,
+            UnknownChannels::Pending => Some(
+                PendingChannels::open(
+                    &options.log_dir,
+                    rotation,
+                    options.timezone.clone(),
+                    chrono::Duration::seconds(options.pending_ttl as i64),
+                    options.pending_max_channels,
+                )
+                .await?,
+            ),
+        };
+
+        let quotas = parse_values(&options.quotas)?;
+        let rate_limits = parse_values(&options.rate_limit)?;
+        let mut sample_rates: BTreeMap<String, SampleRate> = parse_values(&options.sample_rates)?;
+        for (channel, config) in options.configured_channels.iter() {
+            if let Some(ref rate) = config.sample_rate {
+                let rate = rate
+                    .parse()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                sample_rates.insert(channel.clone(), rate);
+            }
+        }
+
+        let mut dedup_windows = BTreeMap::new();
+        for (channel, interval) in parse_pairs(&options.dedup_window)? {
+            dedup_windows.insert(channel, idle::parse_interval(&interval)?);
+        }
+
+        let mut expected_traffic = BTreeMap::new();
+        for (channel, interval) in parse_pairs(&options.expected_traffic)? {
+            expected_traffic.insert(channel, idle::parse_interval(&interval)?);
+        }
+
+        let mut pipe_outs = BTreeMap::new();
+        for (channel, spec) in parse_pairs(&options.pipe_outs.join(","))? {
+            let pipe_out = PipeOut::open(&channel, &spec)?;
+            pipe_outs.insert(channel, pipe_out);
+        }
+
+        Ok(FileWriter {
+            tenants,
+            paused: false,
+            paused_channels: BTreeMap::new(),
+            discarded_lines: BTreeMap::new(),
+            pause_buffer_bytes: options.pause_buffer_bytes,
+            backpressure: match (options.backpressure_high_water, &options.memory_budget) {
+                (Some(high_water), _) => Some(Backpressure::new(
+                    high_water,
+                    options.backpressure_low_water.unwrap_or(high_water / 2),
+                    &options.backpressure_output,
+                )?),
+                (None, Some(budget)) => Some(Backpressure::new(
+                    budget.high_water(),
+                    budget.low_water(),
+                    &options.backpressure_output,
+                )?),
+                (None, None) => None,
+            },
+            memory_budget: options.memory_budget.clone(),
+            log_dir_watch: LogDirWatch::new(&options.log_dir),
+            log_dir_unavailable: false,
+            log_dir_hold_bytes: options.log_dir_hold_bytes,
+            write_failure: options.on_write_error,
+            spill_buffer_size: options.spill_buffer_size,
+            quotas,
+            quota_action: options.quota_action,
+            rate_limits,
+            samplers: sample_rates
+                .into_iter()
+                .map(|(channel, rate)| (channel, Sampler::new(rate)))
+                .collect(),
+            rate_limit_action: options.rate_limit_action,
+            throttle: None,
+            #[cfg(feature = "filter")]
+            filters: Filters::new(
+                &options.drop_patterns,
+                &options.keep_patterns,
+                &options.channel_drop_patterns,
+                &options.channel_keep_patterns,
+            ),
+            inapt_file_handle,
+            inapt_format: options.inapt_format,
+            inapt_timestamp: options.timestamp_format.clone(),
+            channels,
+            overflow_handles: BTreeMap::new(),
+            combined_handles,
+            pipe_outs,
+            tees: options
+                .tee_channels
+                .iter()
+                .map(|tee| (tee.channel.clone(), tee.stream))
+                .collect(),
+            #[cfg(feature = "forward")]
+            forwarders: forwarders(options, &options.configured_channels).await?,
+            #[cfg(any(feature = "control-socket", feature = "http-admin"))]
+            recent_lines: match options.recent_lines {
+                0 => None,
+                capacity => Some(RecentLines::new(capacity)),
+            },
+            binary: None,
+            reorderer: match options.reorder_window {
+                0 => None,
+                window => Some(Reorderer::new(
+                    time::Duration::from_millis(window),
+                    options
+                        .reorder_channels
+                        .split(',')
+                        .filter(|s| !s.is_empty())
+                        .map(String::from)
+                        .collect(),
+                )),
+            },
+            deduplicator: if dedup_windows.is_empty() {
+                None
+            } else {
+                Some(Deduplicator::new(dedup_windows))
+            },
+            idle_watch: if expected_traffic.is_empty() {
+                None
+            } else {
+                Some(IdleWatch::new(expected_traffic))
+            },
+            idle_close_after: options
+                .idle_close_after
+                .and_then(|idle| chrono::Duration::from_std(idle).ok()),
+            meta_channel: options.meta_channel.clone(),
+            pending,
+            dynamic_channels: BTreeSet::new(),
+            max_dynamic_channels: Some(options.max_dynamic_channels)
+                .filter(|_| options.unknown_channels == UnknownChannels::Create),
+            #[cfg(feature = "config")]
+            configured_channels: options.configured_channels.clone(),
+            stats: HourlyStats::load(options.stats_file.as_deref(), options.stats_retention_hours)
+                .await?,
+            channel_settings,
+            #[cfg(feature = "gelf")]
+            gelf_sink: gelf_sink(options).await?,
+            #[cfg(feature = "kafka")]
+            kafka_sink: kafka_sink(options),
+            #[cfg(feature = "siem")]
+            siem_formatter: siem_formatter(options)?,
+            #[cfg(feature = "trace")]
+            tracer: match options.trace_records {
+                Some(ref predicate) => Some(Tracer::new(predicate.clone(), &options.trace_output)?),
+                None => None,
+            },
+        })
+    }
+
+    /// Writes a message sent to `channel` by `protocol`, normalized, or to
+    /// the channel it is an alias of.
+    async fn write_to_channel(
+        &mut self,
+        protocol: &str,
+        channel: &str,
+        message: &str,
+    ) -> Result<(), io::Error> {
+        let channel = self.channel_settings.normalized(protocol, channel);
+        match self.channel_settings.aliased(&channel) {
+            Some(channel) => self.write_received(&channel, message).await,
+            None => self.write_received(&channel, message).await,
+        }
+    }
+
+    /// Writes a message sent to `channel` by `protocol` as its bytes, which
+    /// need not be UTF-8. Filters, transforms, the inapt file and other sinks
+    /// see it with its invalid sequences replaced.
+    async fn write_bytes_to_channel(
+        &mut self,
+        protocol: &str,
+        channel: &str,
+        bytes: &[u8],
+    ) -> Result<(), io::Error> {
+        let text = match String::from_utf8_lossy(bytes) {
+            Cow::Borrowed(text) => return self.write_to_channel(protocol, channel, text).await,
+            Cow::Owned(text) => text,
+        };
+        self.binary = Some((text.clone(), bytes.to_vec()));
+        let result = self.write_to_channel(protocol, channel, &text).await;
+        self.binary = None;
+        result
+    }
+
+    /// Whether a producer may send lines to `channel` by `protocol`: it is
+    /// accepted, or an alias of an accepted channel, once normalized.
+    fn knows(&self, protocol: &str, channel: &str) -> bool {
+        let channel = self.channel_settings.normalized(protocol, channel);
+        self.channels.contains(channel.as_ref()) || self.channel_settings.is_alias(&channel)
+    }
+
+    async fn write_received(&mut self, channel: &str, message: &str) -> Result<(), io::Error> {
+        #[cfg(feature = "redact")]
+        let redacted = self.channel_settings.transforms.apply(
+            channel,
+            self.channel_settings.settings_name(channel),
+            message,
+        );
+        #[cfg(feature = "redact")]
+        let message = redacted.as_ref();
+
+        if let Some(event) = self
+            .idle_watch
+            .as_mut()
+            .and_then(|watch| watch.seen(channel))
+        {
+            tracing::info!("{}", event);
+            self.write_meta(&event).await?;
+        }
+
+        if let Some(ref mut reorderer) = self.reorderer {
+            if reorderer.covers(channel) {
+                reorderer.hold(channel, message);
+                return self.release_reordered(false).await;
+            }
+        }
+
+        self.write_in_order(channel, message).await
+    }
+
+    /// Alerts on the channels that have gone quiet for longer than expected.
+    async fn check_idle(&mut self) -> Result<(), io::Error> {
+        let events = match self.idle_watch {
+            Some(ref mut watch) => watch.check(),
+            None => return Ok(()),
+        };
+        for event in events {
+            tracing::warn!("{}", event);
+            self.write_meta(&event).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes one of the router's own events to the meta channel, if any.
+    async fn write_meta(&mut self, event: &str) -> Result<(), io::Error> {
+        let channel = match self.meta_channel {
+            Some(ref channel) => channel.clone(),
+            None => return Ok(()),
+        };
+
+        self.write_in_order(&channel, &format!("{}\n", event)).await
+    }
+
+    /// Writes out the lines held for reordering whose turn has come, or all
+    /// of them.
+    async fn release_reordered(&mut self, everything: bool) -> Result<(), io::Error> {
+        let released = match self.reorderer {
+            Some(ref mut reorderer) => reorderer.release(everything),
+            None => return Ok(()),
+        };
+        for (channel, message) in released {
+            self.write_in_order(&channel, &message).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn write_in_order(&mut self, channel: &str, message: &str) -> Result<(), io::Error> {
+        #[cfg(feature = "trace")]
+        if let Some(ref mut tracer) = self.tracer {
+            tracer.begin(channel, message);
+        }
+
+        let result = self.route(channel, message).await;
+        if let Err(ref error) = result {
+            self.trace("failed", format_args!("{}", error));
+        }
+
+        #[cfg(feature = "trace")]
+        if let Some(ref mut tracer) = self.tracer {
+            tracer.end();
+        }
+
+        result
+    }
+
+    async fn route(&mut self, channel: &str, message: &str) -> Result<(), io::Error> {
+        if let Some(ref mut deduplicator) = self.deduplicator {
+            if deduplicator.covers(channel) {
+                return match deduplicator.offer(channel, message) {
+                    Some(previous) => self.admit(channel, &previous).await,
+                    None => {
+                        self.trace("held", format_args!("repeating the line before it"));
+                        Ok(())
+                    }
+                };
+            }
+        }
+
+        self.admit(channel, message).await
+    }
+
+    /// Writes out the lines held for deduplication whose window has passed,
+    /// or all of them.
+    async fn release_deduplicated(&mut self, everything: bool) -> Result<(), io::Error> {
+        let released = match self.deduplicator {
+            Some(ref mut deduplicator) => deduplicator.release(everything),
+            None => return Ok(()),
+        };
+        for (channel, message) in released {
+            self.admit(&channel, &message).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn admit(&mut self, channel: &str, message: &str) -> Result<(), io::Error> {
+        #[cfg(feature = "forward")]
+        if self.channel_settings.sink(channel) == ChannelSink::Forward {
+            if let Some(forwarder) = self.forwarders.get_mut(channel) {
+                forwarder.send(message);
+            }
+            self.trace("forwarded", format_args!("forward sink"));
+            return Ok(());
+        }
+        if !self.channels.contains(channel) && !self.create_channel(channel).await? {
+            return self.write_unknown(channel, message).await;
+        }
+
+        // Priority channels are never held back, so their lines are never
+        // turned away once the buffer is full either: they are written
+        // through while paused.
+        let paused = self.paused_channels.contains_key(channel)
+            && !self.handle(channel).is_some_and(FileHandle::is_priority);
+        let within_budget = !paused || self.within_budget(message.len());
+        if let Some(pause) = self.paused_channels.get_mut(channel).filter(|_| paused) {
+            let within_cap = pause.buffered_bytes + message.len() <= self.pause_buffer_bytes;
+            if within_cap && within_budget {
+                pause.buffered_bytes += message.len();
+                pause.buffered.push(message.to_string());
+                let buffered_bytes = pause.buffered_bytes;
+                self.trace(
+                    "paused",
+                    format_args!("held back, {} bytes buffered", buffered_bytes),
+                );
+                self.observe_backpressure();
+                return Ok(());
+            }
+
+            pause.rejected += 1;
+            return self.mark_inapt("paused", Some(channel), message).await;
+        }
+
+        self.deliver(channel, message).await
+    }
+
+    /// Captures a line for a channel that isn't accepted as pending, if
+    /// configured to, or rejects it to the inapt file.
+    async fn write_unknown(&mut self, channel: &str, message: &str) -> Result<(), io::Error> {
+        if let Some(ref mut pending) = self.pending {
+            if pending.write(channel, message).await? {
+                self.trace("pending", format_args!("captured for unknown channel"));
+                return Ok(());
+            }
+        }
+
+        self.mark_inapt("unknown", Some(channel), message).await
+    }
+
+    /// Accepts an unknown channel on the fly, when configured to and there is
+    /// room for another, returning whether it was.
+    async fn create_channel(&mut self, channel: &str) -> Result<bool, io::Error> {
+        let max_dynamic_channels = match self.max_dynamic_channels {
+            Some(max_dynamic_channels) => max_dynamic_channels,
+            None => return Ok(false),
+        };
+        if check_channel_name(channel).is_err()
+            || self.dynamic_channels.len() >= max_dynamic_channels
+        {
+            return Ok(false);
+        }
+
+        tracing::info!("channel {} created on the fly", channel);
+        let handle = self.channel_settings.open(channel).await?;
+        self.channels.insert(channel.to_string(), Box::new(handle));
+        self.dynamic_channels.insert(channel.to_string());
+        self.trace("created", format_args!("channel created on the fly"));
+
+        Ok(true)
+    }
+
+    /// Rejects a line an input couldn't hand to any channel to the inapt
+    /// file, redacted as every channel's lines are.
+    async fn write_inapt(
+        &mut self,
+        reason: &str,
+        channel: Option<&str>,
+        line: &str,
+    ) -> Result<(), io::Error> {
+        #[cfg(feature = "redact")]
+        let redacted = self.channel_settings.transforms.apply_global(line);
+        #[cfg(feature = "redact")]
+        let line = redacted.as_ref();
+
+        self.mark_inapt(reason, channel, line).await
+    }
+
+    /// Writes a line to the inapt file, marked with why it ended up there
+    /// and, when there was one, the channel it was sent to, as
+    /// `--inapt-format` says.
+    async fn mark_inapt(
+        &mut self,
+        reason: &str,
+        channel: Option<&str>,
+        line: &str,
+    ) -> Result<(), io::Error> {
+        let now = zone::fixed(&self.inapt_file_handle.zone.now());
+        let marked = self
+            .inapt_format
+            .mark(&now, &self.inapt_timestamp, reason, channel, line);
+
+        let lines_written = self.inapt_file_handle.lines_written;
+        if let Err(error) = self.inapt_file_handle.write_line(&marked).await {
+            let taken = self.inapt_file_handle.lines_written > lines_written;
+            let error = WriteError::Inapt(error);
+            self.hold_degraded(error, Some(marked.as_str()).filter(|_| !taken))
+                .await?;
+        }
+        self.stats
+            .record(&self.inapt_file_handle.file_name, marked.len());
+        self.trace("inapt", format_args!("{}", reason));
+
+        Ok(())
+    }
+
+    /// Files of `channel`, unless it isn't written to files.
+    fn handle(&self, channel: &str) -> Option<&FileHandle> {
+        self.channels.get(channel)?.handle()
+    }
+
+    fn handle_mut(&mut self, channel: &str) -> Option<&mut FileHandle> {
+        self.channels.get_mut(channel)?.handle_mut()
+    }
+
+    /// Channels written to files, with their files.
+    fn handles(&self) -> impl Iterator<Item = (&str, &FileHandle)> {
+        self.channels
+            .sinks()
+            .filter_map(|(channel, sink)| Some((channel, sink.handle()?)))
+    }
+
+    fn all_handles(&self) -> impl Iterator<Item = &FileHandle> {
+        self.handles()
+            .map(|(_, handle)| handle)
+            .chain(self.overflow_handles.values())
+            .chain(self.combined_handles.values())
+            .chain(
+                self.pending
+                    .iter()
+                    .flat_map(|pending| pending.channels.values().map(|channel| &channel.handle)),
+            )
+            .chain(std::iter::once(&self.inapt_file_handle))
+    }
+
+    fn all_handles_mut(&mut self) -> impl Iterator<Item = &mut FileHandle> {
+        self.channels
+            .sinks_mut()
+            .filter_map(|(_, sink)| sink.handle_mut())
+            .chain(self.overflow_handles.values_mut())
+            .chain(self.combined_handles.values_mut())
+            .chain(self.pending.iter_mut().flat_map(|pending| {
+                pending
+                    .channels
+                    .values_mut()
+                    .map(|channel| &mut channel.handle)
+            }))
+            .chain(std::iter::once(&mut self.inapt_file_handle))
+    }
+
+    /// Paths of the files currently written to.
+    fn current_paths(&mut self) -> BTreeSet<String> {
+        self.all_handles_mut()
+            .map(|handle| handle.current_path.clone())
+            .collect()
+    }
+
+    /// Names every file is written under, the channel's unless it has a
+    /// file name of its own.
+    fn file_names(&mut self) -> BTreeSet<String> {
+        self.all_handles_mut()
+            .map(|handle| handle.file_name.clone())
+            .collect()
+    }
+
+    /// Reopens every file at its current path, for files renamed by an
+    /// external rotation.
+    async fn reopen_all(&mut self) -> Result<(), io::Error> {
+        for handle in self.all_handles_mut() {
+            handle.reopen().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts a fresh file for every channel whatever its rotation, returning
+    /// how many were started. Channels still pending are left to be created.
+    async fn rotate_all(&mut self) -> Result<usize, io::Error> {
+        self.channels.rotate_all().await?;
+        let mut rotated = self.handles().count();
+        let handles = self
+            .combined_handles
+            .values_mut()
+            .chain(std::iter::once(&mut self.inapt_file_handle));
+        for handle in handles {
+            handle.rotate().await?;
+            rotated += 1;
+        }
+
+        Ok(rotated)
+    }
+
+    /// Closes the files of channels silent for longer than
+    /// `--idle-close-after`.
+    async fn close_idle(&mut self) -> Result<(), io::Error> {
+        let idle = match self.idle_close_after {
+            Some(idle) => idle,
+            None => return Ok(()),
+        };
+
+        let now = clock::now();
+        let mut closed = 0;
+        for handle in self.all_handles_mut() {
+            if handle.close_if_idle(idle, now).await? {
+                closed += 1;
+            }
+        }
+        if closed > 0 {
+            tracing::info!("closed {} files of idle channels", closed);
+        }
+
+        Ok(())
+    }
+
+    /// Ends every file with its channel's footer, ahead of the shutdown.
+    fn write_footers(&mut self) {
+        for handle in self.all_handles_mut() {
+            handle.write_footer();
+        }
+    }
+
+    /// Flushes every file and fsyncs it, so lines written before the router
+    /// exits survive a power loss right after.
+    async fn sync_all(&mut self) -> Result<(), io::Error> {
+        for handle in self.all_handles_mut() {
+            handle.close().await?;
+            if let (None, Some(file)) = (&handle.held, &handle.current_file) {
+                file.sync_data().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fsyncs the files of channels synced on an interval that were written
+    /// to since their last fsync.
+    async fn sync_unsynced(&mut self) -> Result<(), io::Error> {
+        for handle in self.all_handles_mut() {
+            if handle.sync_policy == SyncPolicy::Interval
+                && handle.unsynced
+                && handle.held.is_none()
+                && handle.degraded.is_none()
+            {
+                handle.sync().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn write_shutdown_marker(&mut self, marker: &str) -> Result<(), io::Error> {
+        let line = format!("{}\n", marker);
+        let now = self.channels.now();
+        for (_, sink) in self.channels.sinks_mut() {
+            sink.write(&line, &now).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the channels whose batch has waited past their flush
+    /// interval.
+    async fn flush_stale(&mut self) -> Result<(), io::Error> {
+        let mut failed = Vec::new();
+        let handles = self
+            .channels
+            .sinks_mut()
+            .filter_map(|(channel, sink)| Some((channel, sink.handle_mut()?)));
+        for (channel, handle) in handles {
+            if let Err(error) = handle.flush_if_stale().await {
+                failed.push(WriteError::Channel(channel.to_string(), error));
+            }
+        }
+        for (key, handle) in self.combined_handles.iter_mut() {
+            if let Err(error) = handle.flush_if_stale().await {
+                failed.push(WriteError::Combined(key.clone(), error));
+            }
+        }
+        for (channel, handle) in self.overflow_handles.iter_mut() {
+            if let Err(error) = handle.flush_if_stale().await {
+                failed.push(WriteError::Overflow(channel.clone(), error));
+            }
+        }
+        if let Err(error) = self.inapt_file_handle.flush_if_stale().await {
+            failed.push(WriteError::Inapt(error));
+        }
+        if let Some(ref mut pending) = self.pending {
+            for channel in pending.channels.values_mut() {
+                channel.handle.flush_if_stale().await?;
+            }
+        }
+
+        // What the failed writes left behind is written out once their files
+        // recover.
+        for error in failed {
+            self.degrade(error, None).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the accepted channels, returning the channels added and the
+    /// channels retired.
+    #[cfg(any(feature = "control-socket", feature = "http-admin", feature = "config"))]
+    async fn set_channels(
+        &mut self,
+        channels: &[String],
+    ) -> Result<(Vec<String>, Vec<String>), io::Error> {
+        let names: Vec<&str> = channels.iter().map(String::as_str).collect();
+        check_channel_names(&names)?;
+        let mut channels = channels.to_vec();
+        if let Some(ref tenants) = self.tenants {
+            channels.extend(tenants.channels(&names));
+        }
+
+        let retired: Vec<String> = self
+            .channels
+            .channels()
+            .filter(|name| {
+                !channels.iter().any(|channel| channel == name)
+                    || !self.channel_settings.routes(name)
+            })
+            .filter(|name| self.channel_settings.paired_stream(name).is_none())
+            .map(String::from)
+            .collect();
+        for channel in retired.iter() {
+            self.retire_channel(channel).await?;
+        }
+
+        self.channel_settings
+            .report_delta(&names, &self.inapt_file_handle.file_name)
+            .await?;
+
+        let mut added = Vec::new();
+        for channel in channels.iter() {
+            if self.channels.contains(channel) {
+                continue;
+            }
+            if let Some(sink) = self.channel_settings.open_sink(channel).await? {
+                if let Some(ref mut pending) = self.pending {
+                    pending.accept(channel).await?;
+                }
+                self.channels.insert(channel.clone(), sink);
+                added.push(channel.clone());
+            }
+        }
+
+        Ok((added, retired))
+    }
+
+    /// Takes the config file as it now reads: channels it adds are opened,
+    /// channels it drops are retired, and the files of channels whose
+    /// settings changed are flushed and reopened under the new settings.
+    /// Returns the channels added and the channels retired.
+    #[cfg(feature = "config")]
+    async fn reload_config(
+        &mut self,
+        options: &CliOptions,
+        path: &str,
+    ) -> Result<(Vec<String>, Vec<String>), io::Error> {
+        let mut configured = Config::load(path).await?.channels;
+        let placeholders = Placeholders::resolve(options).await?;
+        for config in configured.values_mut() {
+            placeholders.apply_to_channel(config)?;
+        }
+        let channel_settings = ChannelSettings::with_options(options, &configured).await?;
+        let accepted: Vec<&str> = options
+            .accepted_log_channels
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .chain(configured.keys().map(String::as_str))
+            .collect();
+        channel_settings.check_aliases(&accepted)?;
+        self.channel_settings = channel_settings;
+
+        for (channel, config) in configured.iter() {
+            if self.configured_channels.get(channel) == Some(config) {
+                continue;
+            }
+            if let Some(sink) = self.channels.get_mut(channel) {
+                sink.close().await?;
+                match self.channel_settings.open_sink(channel).await? {
+                    Some(sink) => self.channels.insert(channel.clone(), sink),
+                    None => self.channels.remove(channel),
+                };
+                tracing::info!("channel {} reopened under its new settings", channel);
+            }
+            #[cfg(feature = "forward")]
+            if let Some(ref forward) = config.forward {
+                let destination = forward
+                    .parse()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                let hostname = placeholders::hostname(options).await;
+                let forwarder = Forwarder::start(
+                    channel,
+                    destination,
+                    &hostname,
+                    options.forward_buffer_lines,
+                );
+                if let Some(mut previous) = self.forwarders.insert(channel.clone(), forwarder) {
+                    previous.close().await;
+                }
+            }
+        }
+
+        let channels: Vec<String> = options
+            .accepted_log_channels
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .chain(configured.keys().cloned())
+            .chain(self.dynamic_channels.iter().cloned())
+            .collect();
+        self.configured_channels = configured;
+
+        self.set_channels(&channels).await
+    }
+
+    /// Closes out a channel no longer accepted. Lines a pause held back are
+    /// written first, then its files are flushed and dropped; anything still
+    /// sent to it afterwards lands in the inapt file like any unknown channel.
+    #[cfg(any(feature = "control-socket", feature = "http-admin", feature = "config"))]
+    async fn retire_channel(&mut self, channel: &str) -> Result<(), io::Error> {
+        if let Some(pause) = self.paused_channels.remove(channel) {
+            for line in pause.buffered.iter() {
+                self.deliver(channel, line).await?;
+            }
+            self.observe_backpressure();
+        }
+
+        let mut sink = self.channels.remove(channel);
+        let mut overflow = self.overflow_handles.remove(channel);
+        let handles = sink
+            .as_mut()
+            .and_then(|sink| sink.handle_mut())
+            .into_iter()
+            .chain(overflow.as_mut());
+        for handle in handles {
+            if let Some(ref held) = handle.held {
+                tracing::warn!(
+                    "{} lines of {} held while the log directory was unavailable are lost",
+                    held.lines.len(),
+                    handle.file_name
+                );
+            }
+            handle.write_footer();
+            handle.close().await?;
+        }
+        self.quotas.remove(channel);
+        self.dynamic_channels.remove(channel);
+        #[cfg(any(feature = "control-socket", feature = "http-admin"))]
+        if let Some(ref mut recent_lines) = self.recent_lines {
+            recent_lines.forget(channel);
+        }
+        tracing::info!("channel {} retired", channel);
+
+        Ok(())
+    }
+
+    /// Takes a file whose write just failed out of the way of the others,
+    /// rather than failing its input: the line and those after it are held in
+    /// memory, or for a channel under `--on-write-error inapt` sent to the
+    /// inapt file, until a retry finds the file writable. `line` is `None`
+    /// when the handle took it already, to be written out with the bytes the
+    /// failed write left behind.
+    async fn degrade(&mut self, error: WriteError, line: Option<&str>) -> Result<(), io::Error> {
+        match error {
+            WriteError::Channel(ref channel, _) if self.write_failure == WriteFailure::Inapt => {
+                if !self.mark_degraded(&error) {
+                    return Ok(());
+                }
+                match line {
+                    Some(line) => self.mark_inapt("degraded", Some(channel), line).await,
+                    None => Ok(()),
+                }
+            }
+            error => self.hold_degraded(error, line).await,
+        }
+    }
+
+    /// Holds the lines of the file a write failed for in memory until a retry
+    /// finds it writable. Combined, overflow and inapt files always hold
+    /// theirs, as there is nowhere else for them to go.
+    async fn hold_degraded(
+        &mut self,
+        error: WriteError,
+        line: Option<&str>,
+    ) -> Result<(), io::Error> {
+        if !self.mark_degraded(&error) {
+            return Ok(());
+        }
+        let capacity = self.spill_buffer_size;
+        let handle = match self.degraded_handle(&error) {
+            Some(handle) => handle,
+            None => return Ok(()),
+        };
+        handle.hold(capacity);
+        match line {
+            Some(line) => handle.write_line(line).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Marks the file a write failed for as degraded, returning whether there
+    /// still is such a file.
+    fn mark_degraded(&mut self, error: &WriteError) -> bool {
+        tracing::warn!("{}, degraded until a retry succeeds", error);
+        self.trace("degraded", format_args!("{}", error));
+        let handle = match self.degraded_handle(error) {
+            Some(handle) => handle,
+            None => return false,
+        };
+        handle.degraded = Some(Degraded::new(error.io_error()));
+        report::record_error("write", error);
+
+        true
+    }
+
+    /// The handle of the file a write failed for.
+    fn degraded_handle(&mut self, error: &WriteError) -> Option<&mut FileHandle> {
+        match error {
+            WriteError::Channel(channel, _) => self.handle_mut(channel),
+            WriteError::Combined(key, _) => self.combined_handles.get_mut(key),
+            WriteError::Overflow(channel, _) => self.overflow_handles.get_mut(channel),
+            WriteError::Inapt(_) => Some(&mut self.inapt_file_handle),
+        }
+    }
+
+    /// Tries the files of degraded channels, and the combined, overflow and
+    /// inapt files, again once their backoff is up.
+    async fn retry_degraded(&mut self) {
+        if self.log_dir_unavailable {
+            return;
+        }
+
+        let capacity = self.spill_buffer_size;
+        for handle in self.all_handles_mut() {
+            if !handle.degraded.as_ref().is_some_and(Degraded::is_due) {
+                continue;
+            }
+
+            let held = handle.held.is_some();
+            match handle.restore().await {
+                Ok(()) => {
+                    tracing::info!("{} recovered", handle.file_name);
+                    handle.degraded = None;
+                }
+                Err(error) => {
+                    tracing::warn!("{} still unable to write: {}", handle.file_name, error);
+                    if held {
+                        handle.hold(capacity);
+                    }
+                    if let Some(ref mut degraded) = handle.degraded {
+                        degraded.back_off(&error);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn check_log_dir(&mut self) -> Result<(), io::Error> {
+        match self.log_dir_watch.probe() {
+            DirState::Available if !self.log_dir_unavailable => return self.reopen_moved().await,
+            DirState::Unavailable(reason) => {
+                if !self.log_dir_unavailable {
+                    tracing::warn!("{}, holding lines in memory", reason);
+                    self.log_dir_unavailable = true;
+                    let capacity = self.log_dir_hold_bytes;
+                    for handle in self.all_handles_mut() {
+                        handle.hold(capacity);
+                    }
+                }
+                return Ok(());
+            }
+            DirState::Available | DirState::Replaced => {}
+        }
 
-    #[structopt(long, default_value = "inapt")]
-    inapt_file_name: String,
-}
+        tracing::info!("log directory is usable again, re-creating files");
+        self.log_dir_unavailable = false;
+        // Degraded handles are left to their own retries.
+        for handle in self.all_handles_mut() {
+            if handle.degraded.is_none() {
+                handle.restore().await?;
+            }
+        }
 
-fn main() {
-    println!("log-revolve-rs started");
+        Ok(())
+    }
 
-    block_on(start()).expect("Something went terribly wrong");
+    /// Recreates the files deleted or renamed from under the router since the
+    /// last check.
+    async fn reopen_moved(&mut self) -> Result<(), io::Error> {
+        for handle in self.all_handles_mut() {
+            if handle.reopen_if_moved().await? {
+                report::record_error(
+                    "file",
+                    format!("{} was gone from its path", handle.current_path),
+                );
+            }
+        }
 
-    println!("log-revolve-rs finished");
-}
+        Ok(())
+    }
 
-async fn start() -> Result<(), io::Error> {
-    let cli_options = CliOptions::from_args();
+    /// Bytes accepted from producers but not written out yet: batched, held
+    /// while the log directory is unavailable, held back by a pause or for
+    /// reordering.
+    fn queued_bytes(&self) -> usize {
+        let handles: usize = self.all_handles().map(FileHandle::memory_bytes).sum();
+        let paused: usize = self
+            .paused_channels
+            .values()
+            .map(|pause| pause.buffered_bytes)
+            .sum();
+        let reordered = self
+            .reorderer
+            .as_ref()
+            .map_or(0, |reorderer| reorderer.bytes);
+
+        handles + paused + reordered
+    }
 
-    let stdin = io::stdin();
-    let mut line = String::new();
-    let mut writer = FileWriter::with_options(&cli_options).await?;
+    /// Whether `bytes` more may be held without going over the memory budget.
+    fn within_budget(&mut self, bytes: usize) -> bool {
+        let queued_bytes = self.queued_bytes();
+        match self.memory_budget {
+            Some(ref mut budget) => budget.admit(queued_bytes, bytes),
+            None => true,
+        }
+    }
 
-    loop {
-        stdin.read_line(&mut line).await?;
+    /// Writes out every batch once the memory budget's high water mark is
+    /// reached, leaving only what can't be written yet in memory.
+    async fn relieve_memory(&mut self) -> Result<(), io::Error> {
+        let high_water = match self.memory_budget {
+            Some(ref budget) => budget.high_water(),
+            None => return Ok(()),
+        };
+        if self.log_dir_unavailable || self.queued_bytes() < high_water {
+            return Ok(());
+        }
 
-        writer.write(&line).await?;
+        for handle in self.all_handles_mut() {
+            handle.flush().await?;
+        }
 
-        line.clear();
+        Ok(())
     }
-}
 
-struct FileHandle {
-    file_name: String,
-    log_dir: String,
-    last_reopened: DateTime<Local>,
-    current_file: File,
-}
+    /// Logs what is still in memory, per channel, when the router gives up on
+    /// writing it, and records it for the shutdown report.
+    fn log_unflushed(&self) {
+        for handle in self.all_handles() {
+            let bytes = handle.memory_bytes();
+            if bytes > 0 {
+                tracing::error!("{} bytes of {} are unflushed", bytes, handle.file_name);
+                report::record_unflushed(&handle.file_name, bytes);
+            }
+        }
+        for (channel, pause) in self.paused_channels.iter() {
+            if pause.buffered_bytes > 0 {
+                tracing::error!(
+                    "{} lines of paused {} are unflushed",
+                    pause.buffered.len(),
+                    channel
+                );
+                report::record_unflushed(channel, pause.buffered_bytes);
+            }
+        }
+    }
 
-impl FileHandle {
-    async fn open_file(path_str: &str) -> Result<File, io::Error> {
-        let path_string = String::from(path_str);
-        let path = Path::new(&path_string);
+    fn observe_backpressure(&mut self) {
+        let queued_bytes = self.queued_bytes();
+        if let Some(ref mut backpressure) = self.backpressure {
+            backpressure.observe(queued_bytes);
+        }
+    }
 
-        OpenOptions::new()
-            .create(true)
-            .truncate(false)
-            .append(true)
-            .read(false)
-            .open(path)
-            .await
+    /// Has backpressure notices catch up with held back bytes and with
+    /// lines dropped under pressure since the last check.
+    fn observe_pressure(&mut self) {
+        self.observe_backpressure();
+        let dropped_lines = queue::dropped_lines()
+            + self
+                .memory_budget
+                .as_ref()
+                .map_or(0, |budget| budget.dropped_lines);
+        if let Some(ref mut backpressure) = self.backpressure {
+            backpressure.observe_drops(dropped_lines);
+        }
     }
 
-    async fn create(log_dir: &str, channel_name: &str) -> Result<Self, io::Error> {
-        let now = FileHandle::get_hourly_aligned_date();
-        let path = FileHandle::generate_file_path(log_dir, channel_name, now)?;
-        let file = FileHandle::open_file(path.as_str()).await?;
+    async fn deliver(&mut self, channel: &str, message: &str) -> Result<(), io::Error> {
+        if !self.channels.contains(channel) {
+            return self.write_unknown(channel, message).await;
+        }
 
-        Ok(FileHandle {
-            file_name: channel_name.to_string(),
-            last_reopened: Local::now(),
-            log_dir: path.to_string(),
-            current_file: file,
-        })
+        #[cfg(feature = "filter")]
+        if let Some(ref mut filters) = self.filters {
+            let settings_name = self.channel_settings.settings_name(channel);
+            if !filters.admits(channel, settings_name, message) {
+                self.trace("dropped", format_args!("filtered out"));
+                return Ok(());
+            }
+        }
+
+        #[cfg(feature = "siem")]
+        let siem_record = self.siem_formatter.format(channel, message);
+        #[cfg(feature = "siem")]
+        let message = siem_record.as_deref().unwrap_or(message);
+        #[cfg(feature = "siem")]
+        if siem_record.is_some() {
+            self.trace("transformed", format_args!("{}", message.trim_end()));
+        }
+
+        let priority = self.handle(channel).is_some_and(FileHandle::is_priority);
+        if !priority && !self.sampled_in(channel) {
+            self.trace("dropped", format_args!("sampled out"));
+            return Ok(());
+        }
+        if !priority && !self.within_rate_limit(channel, message.len()) {
+            return match self.rate_limit_action {
+                RateLimitAction::Overflow => {
+                    self.write_overflow(channel, message, "over its rate limit")
+                        .await
+                }
+                _ => {
+                    if let Some(handle) = self.handle_mut(channel) {
+                        handle.dropped_lines += 1;
+                    }
+                    self.trace("dropped", format_args!("over its rate limit"));
+                    Ok(())
+                }
+            };
+        }
+
+        // Both streams of a paired service draw on the service's quota.
+        let quota_name = self.channel_settings.settings_name(channel);
+        let quota = match self.quotas.get_mut(channel) {
+            Some(quota) => Some(quota),
+            None => self.quotas.get_mut(quota_name),
+        };
+        let mut admitted = quota
+            .filter(|_| !priority)
+            .is_none_or(|quota| quota.admit(channel, message.len()));
+        // A tenant's channels draw on the tenant's quota as well.
+        if let Some((tenant, _)) = tenant::split(channel).filter(|_| admitted && !priority) {
+            if let Some(quota) = self
+                .tenants
+                .as_mut()
+                .and_then(|tenants| tenants.quotas.get_mut(tenant))
+            {
+                admitted = quota.admit(tenant, message.len());
+            }
+        }
+        if !admitted {
+            return match self.quota_action {
+                QuotaAction::Overflow => self.write_overflow(channel, message, "over quota").await,
+                QuotaAction::Drop => {
+                    if let Some(handle) = self.handle_mut(channel) {
+                        handle.dropped_lines += 1;
+                    }
+                    self.trace("dropped", format_args!("over quota"));
+                    Ok(())
+                }
+            };
+        }
+
+        // Lines held for an unavailable log directory count against the
+        // budget; the rest are written out and only ever batched.
+        if self.log_dir_unavailable && !priority && !self.within_budget(message.len()) {
+            if let Some(ref mut budget) = self.memory_budget {
+                budget.dropped_lines += 1;
+            }
+            if let Some(handle) = self.handle_mut(channel) {
+                handle.dropped_lines += 1;
+            }
+            self.trace("dropped", format_args!("over the memory budget"));
+            return Ok(());
+        }
+
+        let degraded = self
+            .handle(channel)
+            .is_some_and(|handle| handle.degraded.is_some());
+        if degraded && self.write_failure == WriteFailure::Inapt {
+            return self.mark_inapt("degraded", Some(channel), message).await;
+        }
+        let now = self.channels.now();
+        if let Some(sink) = self.channels.get_mut(channel) {
+            let discards = sink.discards();
+            let lines_written = sink.handle().map(|handle| handle.lines_written);
+            let written = match self.binary.take_if(|(text, _)| text == message) {
+                Some((_, bytes)) => sink.write_bytes(&bytes, &now).await,
+                None => sink.write(message, &now).await,
+            };
+            if let Err(error) = written {
+                let taken = sink.handle().map(|handle| handle.lines_written) != lines_written;
+                let error = WriteError::Channel(channel.to_string(), error);
+                self.degrade(error, Some(message).filter(|_| !taken))
+                    .await?;
+            }
+            if discards {
+                let discarded = match self.discarded_lines.get_mut(channel) {
+                    Some(discarded) => discarded,
+                    None => self.discarded_lines.entry(channel.to_string()).or_default(),
+                };
+                discarded.lines += 1;
+                discarded.bytes += message.len() as u64;
+                self.trace("discarded", format_args!("null sink"));
+                return Ok(());
+            }
+            self.stats.record(channel, message.len());
+        }
+        if self.tracing() {
+            if let Some(outcome) = self.handle(channel).map(FileHandle::outcome) {
+                self.trace("written", format_args!("{}", outcome));
+            }
+        }
+        if let Some(pipe_out) = self.pipe_outs.get_mut(channel) {
+            pipe_out.send(message);
+        }
+        if let Some(stream) = self.tees.get(channel) {
+            tee::write(*stream, message);
+        }
+        #[cfg(feature = "forward")]
+        if let Some(forwarder) = self.forwarders.get_mut(channel) {
+            forwarder.send(message);
+        }
+        #[cfg(any(feature = "control-socket", feature = "http-admin"))]
+        if let Some(ref mut recent_lines) = self.recent_lines {
+            recent_lines.record(channel, message);
+        }
+        if let Some((service, stream)) = self.channel_settings.paired_stream(channel) {
+            let service = service.to_string();
+            let line = format!("[{}] {}", stream, message);
+            self.write_combined(&service, &line).await?;
+        }
+        if let Some(group) = self.channel_settings.rollup_group(channel) {
+            let group = group.to_string();
+            let line = format!("[{}] {}", channel, message);
+            self.write_combined(&group, &line).await?;
+        }
+        if self.memory_budget.is_some() {
+            self.relieve_memory().await?;
+            self.observe_backpressure();
+        }
+
+        #[cfg(feature = "gelf")]
+        if let Some(ref mut gelf_sink) = self.gelf_sink {
+            if gelf_sink.accepts(channel) {
+                gelf_sink.send(channel, message).await;
+                self.trace("gelf", format_args!("queued for Graylog"));
+            }
+        }
+
+        #[cfg(feature = "kafka")]
+        if let Some(ref mut kafka_sink) = self.kafka_sink {
+            if kafka_sink.accepts(channel) {
+                kafka_sink.send(channel, message);
+                self.trace("kafka", format_args!("queued for Kafka"));
+            }
+        }
+
+        Ok(())
     }
 
-    fn get_hourly_aligned_date() -> DateTime<Local> {
-        let now = Local::now();
-        Local
-            .ymd(now.year(), now.month(), now.day())
-            .and_hms(now.hour(), 0u32, 0u32)
+    /// Writes a line to the combined file of a paired service or rollup file
+    /// of a group, if it has one, degrading it if the write fails.
+    async fn write_combined(&mut self, key: &str, line: &str) -> Result<(), io::Error> {
+        let handle = match self.combined_handles.get_mut(key) {
+            Some(handle) => handle,
+            None => return Ok(()),
+        };
+        let lines_written = handle.lines_written;
+        if let Err(error) = handle.write_line(line).await {
+            let taken = handle.lines_written > lines_written;
+            let error = WriteError::Combined(key.to_string(), error);
+            self.degrade(error, Some(line).filter(|_| !taken)).await?;
+        }
+
+        Ok(())
     }
 
-    fn generate_file_path(
-        log_dir: &str,
-        channel_name: &str,
-        now: DateTime<Local>,
-    ) -> Result<String, io::Error> {
-        let mut file_name = String::new();
-        file_name.push_str(channel_name);
-        file_name.push_str("_");
-        file_name.push_str(&now.format("%Y-%m-%d-%H-%M-%S").to_string());
-        file_name.push_str(".log");
+    #[cfg(feature = "trace")]
+    fn tracing(&self) -> bool {
+        self.tracer.as_ref().is_some_and(Tracer::is_tracing)
+    }
 
-        let mut path_buf = PathBuf::new();
-        path_buf.push(log_dir);
-        path_buf.push(file_name);
+    #[cfg(not(feature = "trace"))]
+    fn tracing(&self) -> bool {
+        false
+    }
 
-        let path_str_opt = path_buf.to_str();
-        match path_str_opt {
-            Some(path_str) => Ok(path_str.to_string()),
-            None => Err(io::Error::new(
-                io::ErrorKind::Other,
-                "unable to build file path",
-            )),
+    /// Notes a step taken by the record being traced, if any.
+    #[cfg(feature = "trace")]
+    fn trace(&mut self, stage: &str, detail: fmt::Arguments) {
+        if let Some(ref mut tracer) = self.tracer {
+            tracer.event(stage, detail);
         }
     }
 
-    async fn write_line(&mut self, line: &str) -> Result<(), io::Error> {
-        self.update_current_file().await?;
-        self.current_file.write_all(line.as_bytes()).await
+    #[cfg(not(feature = "trace"))]
+    fn trace(&mut self, _stage: &str, _detail: fmt::Arguments) {}
+
+    /// Whether the next line of `channel` is kept by its sampler, if it has
+    /// one.
+    fn sampled_in(&mut self, channel: &str) -> bool {
+        let settings_name = self.channel_settings.settings_name(channel);
+        let sampler = match self.samplers.get_mut(channel) {
+            Some(sampler) => Some(sampler),
+            None => self.samplers.get_mut(settings_name),
+        };
+        sampler.is_none_or(Sampler::keeps)
+    }
+
+    /// Whether a line of `bytes` fits the rate limit of `channel`, if it has
+    /// one. Under `--rate-limit-action block` it always does, and the input
+    /// is to wait for the bucket to make up for it once it has let go of the
+    /// writer.
+    fn within_rate_limit(&mut self, channel: &str, bytes: usize) -> bool {
+        let settings_name = self.channel_settings.settings_name(channel).to_string();
+        let rate_limit = match self.rate_limits.get_mut(channel) {
+            Some(rate_limit) => rate_limit,
+            None => match self.rate_limits.get_mut(&settings_name) {
+                Some(rate_limit) => rate_limit,
+                None => return true,
+            },
+        };
+
+        if self.rate_limit_action == RateLimitAction::Block {
+            if let Some(wait) = rate_limit.owe(channel, bytes) {
+                self.throttle = Some(self.throttle.map_or(wait, |throttle| throttle.max(wait)));
+            }
+            return true;
+        }
+        match rate_limit.take(channel, bytes) {
+            Ok(()) => true,
+            Err(_) => {
+                rate_limit.limited(bytes);
+                false
+            }
+        }
     }
 
-    async fn update_current_file(&mut self) -> Result<(), io::Error> {
-        if self.new_file_needed() {
-            self.last_reopened = FileHandle::get_hourly_aligned_date();
-            let path_str =
-                FileHandle::generate_file_path(&self.log_dir, &self.file_name, self.last_reopened)?;
-            self.current_file = FileHandle::open_file(path_str.as_str()).await?
+    async fn write_overflow(
+        &mut self,
+        channel: &str,
+        message: &str,
+        reason: &str,
+    ) -> Result<(), io::Error> {
+        let tracing = self.tracing();
+        let handle = match self.overflow_handles.entry(channel.to_string()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let name = format!("{}.overflow", channel);
+                let log_dir = &self.channel_settings.log_dir;
+                let rotation = self.channel_settings.rotation_of(channel);
+                let zone = self.channel_settings.zone_of(channel);
+                let layout = self.channel_settings.layout.clone();
+                let compression = self.channel_settings.compression_of(channel);
+                let encrypted = self.channel_settings.encrypts(channel);
+                let created = FileHandle::create(
+                    log_dir,
+                    &name,
+                    rotation,
+                    zone,
+                    layout,
+                    compression,
+                    encrypted,
+                )
+                .await;
+                match created {
+                    Ok(handle) => entry.insert(handle),
+                    // Without a file to hold them for, the lines go where a
+                    // degraded channel's would.
+                    Err(error) => {
+                        tracing::warn!("unable to create overflow file of {}: {}", channel, error);
+                        report::record_error("write", error);
+                        return self.mark_inapt("degraded", Some(channel), message).await;
+                    }
+                }
+            }
+        };
+
+        let lines_written = handle.lines_written;
+        if let Err(error) = handle.write_line(message).await {
+            let taken = handle.lines_written > lines_written;
+            let error = WriteError::Overflow(channel.to_string(), error);
+            return self
+                .hold_degraded(error, Some(message).filter(|_| !taken))
+                .await;
+        }
+        if tracing {
+            let outcome = handle.outcome();
+            self.trace("overflow", format_args!("{}, {}", reason, outcome));
         }
 
         Ok(())
     }
+}
 
-    fn new_file_needed(&self) -> bool {
-        let now = Local::now();
+/// Tenants of `--tenants` and the config file, the config file's settings
+/// taking precedence.
+fn tenants(options: &CliOptions) -> Result<Option<Tenants>, io::Error> {
+    let mut names: Vec<String> = options
+        .tenants
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect();
+    for tenant in options.configured_tenants.keys() {
+        if !names.contains(tenant) {
+            names.push(tenant.clone());
+        }
+    }
+    if names.is_empty() {
+        return Ok(None);
+    }
 
-        let date_is_after = now.date() > self.last_reopened.date();
-        let hour_is_after = now.hour() > self.last_reopened.hour();
-        if date_is_after && hour_is_after {
-            return true;
+    let mut quotas = parse_pairs(&options.tenant_quotas)?;
+    let mut retention = parse_pairs(&options.tenant_retention)?;
+    let mut channels = BTreeMap::new();
+    for (tenant, config) in options.configured_tenants.iter() {
+        quotas.extend(config.quota.clone().map(|quota| (tenant.clone(), quota)));
+        retention.extend(config.retention.clone().map(|age| (tenant.clone(), age)));
+        if let Some(ref names) = config.channels {
+            for name in names.iter() {
+                check_channel_name(name).map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("channel of tenant {}: {}", tenant, e),
+                    )
+                })?;
+            }
+            channels.insert(tenant.clone(), names.clone());
         }
+    }
 
-        let more_than_hour_passed = now - self.last_reopened > Duration::hours(1);
-        if more_than_hour_passed {
-            return true;
+    let mut tenant_quotas = BTreeMap::new();
+    for (tenant, quota) in quotas {
+        let quota = quota
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        tenant_quotas.insert(tenant, quota);
+    }
+    let mut tenant_retention = BTreeMap::new();
+    for (tenant, age) in retention {
+        tenant_retention.insert(tenant, idle::parse_interval(&age)?);
+    }
+
+    Tenants::new(names, tenant_quotas, tenant_retention, channels).map(Some)
+}
+
+/// Forwarders of the channels given a destination on the command line or in
+/// the config file, the config file's taking precedence.
+#[cfg(feature = "forward")]
+async fn forwarders(
+    options: &CliOptions,
+    configured_channels: &BTreeMap<String, ChannelConfig>,
+) -> Result<BTreeMap<String, Forwarder>, io::Error> {
+    let mut destinations: BTreeMap<String, String> = parse_pairs(&options.forwards.join(","))?
+        .into_iter()
+        .collect();
+    for (channel, config) in configured_channels.iter() {
+        if let Some(ref forward) = config.forward {
+            destinations.insert(channel.clone(), forward.clone());
         }
+    }
 
-        false
+    let hostname = placeholders::hostname(options).await;
+    let mut forwarders = BTreeMap::new();
+    for (channel, destination) in destinations {
+        let destination = destination
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let forwarder = Forwarder::start(
+            &channel,
+            destination,
+            &hostname,
+            options.forward_buffer_lines,
+        );
+        forwarders.insert(channel, forwarder);
     }
+
+    Ok(forwarders)
 }
 
-struct FileWriter {
-    current_channel_name: Option<String>,
-    inapt_file_handle: FileHandle,
-    file_handles: BTreeMap<String, FileHandle>,
+#[cfg(feature = "kafka")]
+fn kafka_sink(options: &CliOptions) -> Option<KafkaSink> {
+    let brokers = options.kafka_brokers.as_ref()?;
+    let config = KafkaConfig {
+        brokers: brokers
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect(),
+        topic: options.kafka_topic.clone(),
+        batch_lines: options.kafka_batch_lines.max(1),
+        linger: time::Duration::from_millis(options.kafka_linger),
+        fallback_path: match options.kafka_fallback_file {
+            Some(ref path) => path.clone(),
+            None => Path::new(&options.log_dir)
+                .join("kafka.fallback")
+                .to_string_lossy()
+                .into_owned(),
+        },
+    };
+    let channels = options
+        .kafka_channels
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect();
+
+    Some(KafkaSink::start(
+        config,
+        channels,
+        options.kafka_queue_capacity,
+    ))
 }
 
-impl FileWriter {
-    async fn with_options(options: &CliOptions) -> Result<Self, io::Error> {
-        let mut file_handles = BTreeMap::new();
+#[cfg(feature = "gelf")]
+async fn gelf_sink(options: &CliOptions) -> Result<Option<GelfSink>, io::Error> {
+    match options.gelf_addr {
+        Some(ref addr) => {
+            let host = options.gelf_host.clone().unwrap_or_else(gelf::hostname);
+            let channels = options
+                .gelf_channels
+                .split(',')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect();
+            let spill = match options.gelf_spill_dir {
+                Some(ref dir) => Some(Spill::open(dir, options.gelf_spill_max_bytes).await?),
+                None => None,
+            };
+            let sink =
+                GelfSink::connect(addr, host, channels, options.gelf_queue_capacity, spill).await?;
+            Ok(Some(sink))
+        }
+        None => Ok(None),
+    }
+}
 
-        let accepted_channels: Vec<String> = options
-            .accepted_log_channels
-            .split(",")
-            .map(|s| s.to_string())
-            .collect();
+/// Hands a file its channel is done with, compressed if it is, to whatever
+/// ships or processes finished files.
+fn file_finished(channel: String, path: String) {
+    #[cfg(feature = "state")]
+    state::compressed(&path);
+    #[cfg(feature = "manifest")]
+    manifest::record(path, clock::now(), move |path| file_recorded(channel, path));
+    #[cfg(not(feature = "manifest"))]
+    file_recorded(channel, path);
+}
+
+/// Hands a finished file, in its directory's manifest if there is one, on to
+/// its upload and rotate hook.
+fn file_recorded(channel: String, path: String) {
+    #[cfg(feature = "s3")]
+    upload::enqueue(path.clone());
+    hook::run(channel, path);
+}
+
+#[cfg(feature = "s3")]
+fn start_uploads(options: &CliOptions) -> Result<(), io::Error> {
+    let bucket = match options.s3_bucket {
+        Some(ref bucket) => bucket,
+        None => return Ok(()),
+    };
+    let endpoint = options.s3_endpoint.as_ref().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--s3-bucket needs --s3-endpoint",
+        )
+    })?;
+    let bucket = Bucket::new(endpoint, bucket, &options.s3_prefix, &options.s3_region)?;
+    let manifest = match options.s3_manifest {
+        Some(ref manifest) => manifest.clone(),
+        None => FileHandle::generate_file_path(&options.log_dir, ".s3-uploads")?,
+    };
+
+    upload::start(bucket, options.after_upload, &manifest)
+}
+
+#[cfg(feature = "siem")]
+fn siem_formatter(options: &CliOptions) -> Result<SiemFormatter, io::Error> {
+    let mut siem_channels = BTreeMap::new();
+    for (channel, format) in parse_pairs(&options.siem_channels)? {
+        let format = format
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        siem_channels.insert(channel, format);
+    }
+    let fields = parse_pairs(&options.siem_fields)?;
+    for (key, _) in fields.iter() {
+        siem::check_field_key(key).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    }
+
+    Ok(SiemFormatter {
+        vendor: options.siem_vendor.clone(),
+        product: options.siem_product.clone(),
+        version: options
+            .siem_product_version
+            .clone()
+            .unwrap_or_else(|| env!("CARGO_PKG_VERSION").to_string()),
+        fields,
+        channels: siem_channels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use testing::LogDir;
+
+    async fn write(writer: &mut FileWriter, channel: &str, lines: &[&str]) {
+        for line in lines {
+            writer.write_to_channel("", channel, line).await.unwrap();
+        }
+    }
+
+    /// Keeps the lines it is handed, in place of a channel's files.
+    #[derive(Clone, Default)]
+    struct MemorySink {
+        lines: Arc<std::sync::Mutex<Vec<String>>>,
+    }
 
-        let mut iterator = accepted_channels.iter();
-        while let Some(channel_name) = iterator.next() {
-            let handle = FileHandle::create(&options.log_dir, channel_name).await?;
-            file_handles.insert(channel_name.clone(), handle);
+    impl Sink for MemorySink {
+        fn write<'a>(&'a mut self, line: &'a str, _: &'a DateTime<FixedOffset>) -> SinkFuture<'a> {
+            self.lines.lock().unwrap().push(line.to_string());
+            Box::pin(async { Ok(()) })
         }
 
-        let inapt_file_handle =
-            FileHandle::create(&options.log_dir, &options.inapt_file_name).await?;
+        fn rotate<'a>(&'a mut self, _: &'a DateTime<FixedOffset>) -> SinkFuture<'a> {
+            Box::pin(async { Ok(()) })
+        }
 
-        Ok(FileWriter {
-            current_channel_name: Option::None,
-            inapt_file_handle,
-            file_handles,
-        })
+        fn flush(&mut self) -> SinkFuture<'_> {
+            Box::pin(async { Ok(()) })
+        }
+
+        fn close(&mut self) -> SinkFuture<'_> {
+            Box::pin(async { Ok(()) })
+        }
     }
 
-    async fn write(&mut self, message: &str) -> Result<(), io::Error> {
-        match self.current_channel_name {
-            None => {
-                let channel = message.trim_end();
-                if self.file_handles.contains_key(channel) {
-                    self.current_channel_name = Some(channel.clone().to_string());
+    impl ChannelOutput for MemorySink {}
 
-                    Ok(())
-                } else {
-                    self.inapt_file_handle.write_line(message).await
-                }
-            }
-            Some(ref channel) => {
-                let handle = self
-                    .file_handles
-                    .get_mut(channel)
-                    .unwrap_or(&mut self.inapt_file_handle);
+    #[test]
+    fn batches_only_ever_hold_whole_lines() {
+        task::block_on(async {
+            let dir = LogDir::new("main-whole-lines");
+            let args = ["--accepted-log-channels", "web", "--flush-bytes", "16"];
+            let mut writer = testing::writer(&dir, &args).await;
+
+            write(&mut writer, "web", &["0123456789\n"]).await;
+            assert_eq!(dir.read("web_"), "");
+            // Past the batch, the line waits for the next one rather than
+            // being cut to fit.
+            write(&mut writer, "web", &["abcdefghij\n"]).await;
+            assert_eq!(dir.read("web_"), "0123456789\n");
+            // Longer than a batch, it goes out whole by itself.
+            let long = format!("{}\n", "x".repeat(40));
+            write(&mut writer, "web", &[&long]).await;
+            assert_eq!(
+                dir.read("web_"),
+                format!("0123456789\nabcdefghij\n{}", long)
+            );
+
+            write(&mut writer, "web", &["tail\n"]).await;
+            writer.sync_all().await.unwrap();
+            assert!(dir.read("web_").ends_with(&format!("{}tail\n", long)));
+        });
+    }
+
+    #[test]
+    fn priority_lines_are_batches_of_their_own() {
+        task::block_on(async {
+            let dir = LogDir::new("main-priority-lines");
+            let args = [
+                "--accepted-log-channels",
+                "audit",
+                "--priority-channels",
+                "audit",
+            ];
+            let mut writer = testing::writer(&dir, &args).await;
+
+            write(&mut writer, "audit", &["login\n"]).await;
+            assert_eq!(dir.read("audit_"), "login\n");
+            write(&mut writer, "audit", &["logout\n"]).await;
+            assert_eq!(dir.read("audit_"), "login\nlogout\n");
+        });
+    }
+
+    #[test]
+    fn file_names_are_stamped_as_precisely_as_asked() {
+        task::block_on(async {
+            let dir = LogDir::new("main-file-timestamp");
+            let args = [
+                "--accepted-log-channels",
+                "web",
+                "--file-timestamp",
+                "hours",
+            ];
+            let writer = testing::writer(&dir, &args).await;
+
+            let path = &writer.handle("web").unwrap().current_path;
+            let file_name = std::path::Path::new(path)
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap();
+            assert!(rotation::parse_file_name("web", FileTimestamp::Hours, file_name).is_some());
+            assert_eq!(file_name.len(), "web_2024-06-01-13.log".len());
+        });
+    }
+
+    #[test]
+    fn hour_stamps_need_hours_at_least() {
+        let dir = LogDir::new("main-file-timestamp-interval");
+        let options = testing::options(&dir, &["--file-timestamp", "rfc3339-hours"]);
 
-                handle.write_line(message).await?;
+        let error = rotation_every(&options, chrono::Duration::minutes(15)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "rotation intervals under an hour take a seconds file timestamp"
+        );
+        assert_eq!(
+            rotation_every(&options, chrono::Duration::days(1)).unwrap(),
+            Rotation::Periodic(FileTimestamp::Rfc3339Hours, chrono::Duration::days(1))
+        );
+    }
+
+    #[cfg(any(feature = "control-socket", feature = "http-admin", feature = "config"))]
+    #[test]
+    fn retired_channels_finish_their_files_and_send_late_lines_to_inapt() {
+        task::block_on(async {
+            let dir = LogDir::new("main-retire");
+            let args = ["--accepted-log-channels", "app,web"];
+            let mut writer = testing::writer(&dir, &args).await;
+            write(&mut writer, "web", &["GET /\n"]).await;
+            writer
+                .paused_channels
+                .insert(String::from("web"), ChannelPause::default());
+            write(&mut writer, "web", &["GET /paused\n"]).await;
+            assert_eq!(dir.read("web_"), "");
+
+            let (added, removed) = writer.set_channels(&[String::from("app")]).await.unwrap();
+            assert!(added.is_empty());
+            assert_eq!(removed, ["web"]);
+            assert_eq!(dir.read("web_"), "GET /\nGET /paused\n");
+            assert!(!writer.paused_channels.contains_key("web"));
+
+            write(&mut writer, "web", &["GET /late\n"]).await;
+            writer.sync_all().await.unwrap();
+            assert_eq!(dir.read("web_"), "GET /\nGET /paused\n");
+            assert!(dir.read("inapt").contains("GET /late\n"));
+        });
+    }
+
+    #[test]
+    fn the_inapt_file_rotates_in_a_directory_of_its_own() {
+        task::block_on(async {
+            let dir = LogDir::new("main-inapt");
+            let inapt_dir = LogDir::new("main-inapt-dir");
+            let args = [
+                "--accepted-log-channels",
+                "web",
+                "--inapt-dir",
+                inapt_dir.path().to_str().unwrap(),
+                "--inapt-max-file-size",
+                "20",
+                "--flush-bytes",
+                "0",
+            ];
+            let mut writer = testing::writer(&dir, &args).await;
+
+            write(&mut writer, "db", &["SELECT 1\n", "SELECT 2\n"]).await;
+            let inapt_files = || {
+                inapt_dir
+                    .file_names()
+                    .into_iter()
+                    .filter(|name| name.starts_with("inapt_"))
+                    .count()
+            };
+            assert_eq!(inapt_files(), 2);
+            assert_eq!(
+                inapt_dir.read("inapt_"),
+                "[unknown:db] SELECT 1\n[unknown:db] SELECT 2\n"
+            );
+            assert!(dir
+                .file_names()
+                .iter()
+                .all(|name| !name.starts_with("inapt")));
+
+            writer.rotate_all().await.unwrap();
+            assert_eq!(inapt_files(), 3);
+        });
+    }
+
+    #[test]
+    fn channels_are_written_through_their_sinks() {
+        task::block_on(async {
+            let dir = LogDir::new("main-channel-sinks");
+            let args = ["--accepted-log-channels", "app,web", "--flush-bytes", "0"];
+            let mut writer = testing::writer(&dir, &args).await;
+            let memory = MemorySink::default();
+            writer
+                .channels
+                .insert(String::from("web"), Box::new(memory.clone()));
+
+            write(&mut writer, "web", &["one\n", "two\n"]).await;
+            write(&mut writer, "app", &["three\n"]).await;
+            assert_eq!(*memory.lines.lock().unwrap(), ["one\n", "two\n"]);
+            assert_eq!(dir.read("app_"), "three\n");
+            assert!(writer.handle("web").is_none());
+        });
+    }
+
+    #[cfg(all(feature = "gzip", feature = "zstd"))]
+    #[test]
+    fn channels_may_compress_their_own_way() {
+        task::block_on(async {
+            let dir = LogDir::new("main-channel-compression");
+            let args = [
+                "--accepted-log-channels",
+                "app,audit,web",
+                "--compress",
+                "zstd:3",
+                "--compress-channels",
+                "audit=gzip:9,web=none",
+            ];
+            let writer = testing::writer(&dir, &args).await;
+
+            let settings = &writer.channel_settings;
+            assert_eq!(settings.compression_of("app"), Compression::Zstd(3));
+            assert_eq!(settings.compression_of("audit"), Compression::Gzip(9));
+            assert_eq!(settings.compression_of("web"), Compression::None);
+        });
+    }
+
+    #[test]
+    fn paired_streams_share_their_service_and_a_combined_file() {
+        task::block_on(async {
+            let dir = LogDir::new("main-paired");
+            let args = [
+                "--accepted-log-channels",
+                "web",
+                "--paired-channels",
+                "api",
+                "--flush-bytes",
+                "0",
+            ];
+            let mut writer = testing::writer(&dir, &args).await;
 
-                self.current_channel_name = None;
-                Ok(())
+            let settings = &writer.channel_settings;
+            assert_eq!(settings.paired_stream("api.out"), Some(("api", "out")));
+            assert_eq!(settings.paired_stream("api.err"), Some(("api", "err")));
+            assert_eq!(settings.paired_stream("api.log"), None);
+            assert_eq!(settings.paired_stream("web.out"), None);
+            assert_eq!(settings.settings_name("api.err"), "api");
+
+            write(&mut writer, "api.out", &["listening\n"]).await;
+            write(&mut writer, "api.err", &["refused\n"]).await;
+            writer.sync_all().await.unwrap();
+            assert_eq!(dir.read("api.out_"), "listening\n");
+            assert_eq!(dir.read("api.err_"), "refused\n");
+            assert_eq!(dir.read("api_"), "[out] listening\n[err] refused\n");
+        });
+    }
+    #[test]
+    fn finishing_writes_out_held_lines_before_the_shutdown_marker() {
+        task::block_on(async {
+            let dir = LogDir::new("main-finish");
+            let args = [
+                "--accepted-log-channels",
+                "web",
+                "--shutdown-marker",
+                "# shutdown",
+            ];
+            let options = testing::options(&dir, &args);
+            let mut writer = testing::writer(&dir, &args).await;
+            write(&mut writer, "web", &["GET /a\n", "GET /b\n"]).await;
+            assert_eq!(dir.read("web_"), "");
+
+            finish(&options, &Mutex::new(writer)).await.unwrap();
+            assert_eq!(dir.read("web_"), "GET /a\nGET /b\n# shutdown\n");
+        });
+    }
+
+    #[cfg(feature = "report")]
+    #[test]
+    fn lines_given_up_on_at_shutdown_are_reported() {
+        task::block_on(async {
+            let dir = LogDir::new("main-unflushed");
+            let mut writer = testing::writer(&dir, &["--accepted-log-channels", "web"]).await;
+            write(&mut writer, "web", &["GET /a\n"]).await;
+            let file_name = writer.handle("web").unwrap().file_name.clone();
+
+            writer.log_unflushed();
+            let path = dir.path().join("report.json");
+            let timed_out = Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "shutdown took long",
+            ));
+            report::write(path.to_str().unwrap(), None, Local::now(), &timed_out)
+                .await
+                .unwrap();
+
+            let report: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            assert_eq!(report["outcome"], "shutdown took long");
+            assert_eq!(report["unflushed_at_timeout"][&file_name], 7);
+            assert!(report.get("channels").is_none());
+        });
+    }
+
+    #[test]
+    fn channels_may_stamp_their_lines_in_a_zone_of_their_own() {
+        task::block_on(async {
+            let dir = LogDir::new("main-zones");
+            let args = [
+                "--accepted-log-channels",
+                "billing,edge",
+                "--timezone",
+                "UTC",
+                "--channel-timezones",
+                "billing=+09:00",
+                "--prepend-timestamp",
+                "--flush-bytes",
+                "0",
+            ];
+            let mut writer = testing::writer(&dir, &args).await;
+            write(&mut writer, "billing", &["paid\n"]).await;
+            write(&mut writer, "edge", &["hit\n"]).await;
+            writer.sync_all().await.unwrap();
+
+            let billing = dir.read("billing_");
+            let edge = dir.read("edge_");
+            assert!(
+                billing.contains("+09:00") && billing.ends_with(" paid\n"),
+                "{}",
+                billing
+            );
+            assert!(
+                edge.contains("+00:00") && edge.ends_with(" hit\n"),
+                "{}",
+                edge
+            );
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pipe_outs_follow_their_channel_live() {
+        use std::io::Read;
+        use std::os::unix::io::IntoRawFd;
+        use std::os::unix::net::UnixStream;
+
+        task::block_on(async {
+            let dir = LogDir::new("main-pipe-out");
+            let (mut reader, inherited) = UnixStream::pair().unwrap();
+            let pipe_out = format!("web=fd:{}", inherited.into_raw_fd());
+            let args = ["--accepted-log-channels", "web,db", "--pipe-out", &pipe_out];
+            let mut writer = testing::writer(&dir, &args).await;
+            write(&mut writer, "web", &["GET /a\n"]).await;
+            write(&mut writer, "db", &["SELECT 1\n"]).await;
+            for pipe_out in writer.pipe_outs.values_mut() {
+                pipe_out.close().await;
             }
-        }
+
+            let mut copied = String::new();
+            reader.read_to_string(&mut copied).unwrap();
+            assert_eq!(copied, "GET /a\n");
+        });
+    }
+
+    #[test]
+    fn channels_may_end_their_lines_their_own_way() {
+        task::block_on(async {
+            let dir = LogDir::new("main-terminators");
+            let args = [
+                "--accepted-log-channels",
+                "legacy,web",
+                "--line-terminators",
+                "legacy=crlf",
+                "--flush-bytes",
+                "0",
+            ];
+            let mut writer = testing::writer(&dir, &args).await;
+            write(&mut writer, "legacy", &["a\n", "b\r\n"]).await;
+            write(&mut writer, "web", &["c\n"]).await;
+            writer.sync_all().await.unwrap();
+
+            assert_eq!(dir.read("legacy_"), "a\r\nb\r\n");
+            assert_eq!(dir.read("web_"), "c\n");
+        });
+    }
+
+    #[test]
+    fn synced_channels_rotate_into_a_synced_directory() {
+        task::block_on(async {
+            let dir = LogDir::new("main-synced");
+            let args = [
+                "--accepted-log-channels",
+                "audit,web",
+                "--priority-channels",
+                "audit",
+                "--priority-fsync",
+            ];
+            let mut writer = testing::writer(&dir, &args).await;
+            write(&mut writer, "audit", &["login\n"]).await;
+            write(&mut writer, "web", &["GET /a\n"]).await;
+            assert!(writer.handle("audit").unwrap().durability == Durability::Synced);
+            assert!(writer.handle("web").unwrap().durability != Durability::Synced);
+
+            writer.rotate_all().await.unwrap();
+            write(&mut writer, "audit", &["logout\n"]).await;
+            let audit_files = dir
+                .file_names()
+                .into_iter()
+                .filter(|name| name.starts_with("audit_"))
+                .count();
+            assert_eq!(audit_files, 2);
+            assert_eq!(dir.read("audit_"), "login\nlogout\n");
+        });
+    }
+
+    #[test]
+    fn files_are_cut_before_outgrowing_their_size() {
+        task::block_on(async {
+            let dir = LogDir::new("main-max-size");
+            let args = [
+                "--accepted-log-channels",
+                "web",
+                "--max-file-size",
+                "20B",
+                "--flush-bytes",
+                "0",
+            ];
+            let mut writer = testing::writer(&dir, &args).await;
+            write(
+                &mut writer,
+                "web",
+                &["GET /0001\n", "GET /0002\n", "GET /0003\n"],
+            )
+            .await;
+            writer.sync_all().await.unwrap();
+
+            let sizes: Vec<u64> = dir
+                .file_names()
+                .iter()
+                .filter(|name| name.starts_with("web_"))
+                .map(|name| dir.path().join(name).metadata().unwrap().len())
+                .collect();
+            assert_eq!(sizes, vec![20, 10]);
+            assert_eq!(dir.read("web_"), "GET /0001\nGET /0002\nGET /0003\n");
+
+            assert_eq!(parse_file_size("1KB"), Ok(1024));
+            assert!(parse_file_size("0B").is_err());
+        });
+    }
+
+    #[test]
+    fn grouped_channels_share_settings_and_a_rollup_file() {
+        task::block_on(async {
+            let dir = LogDir::new("main-groups");
+            let args = [
+                "--accepted-log-channels",
+                "web,cdn,db",
+                "--channel-groups",
+                "frontend=web+cdn",
+                "--rollup-groups",
+                "frontend",
+                "--line-terminators",
+                "frontend=crlf",
+                "--flush-bytes",
+                "0",
+            ];
+            let mut writer = testing::writer(&dir, &args).await;
+            let settings = &writer.channel_settings;
+            assert_eq!(settings.settings_name("cdn"), "frontend");
+            assert_eq!(settings.rollup_group("web"), Some("frontend"));
+            assert_eq!(settings.rollup_group("db"), None);
+
+            write(&mut writer, "web", &["GET /a\n"]).await;
+            write(&mut writer, "cdn", &["HIT /b\n"]).await;
+            write(&mut writer, "db", &["SELECT 1\n"]).await;
+            writer.sync_all().await.unwrap();
+
+            assert_eq!(dir.read("web_"), "GET /a\r\n");
+            assert_eq!(dir.read("cdn_"), "HIT /b\r\n");
+            assert_eq!(dir.read("db_"), "SELECT 1\n");
+            assert_eq!(dir.read("frontend_"), "[web] GET /a\r\n[cdn] HIT /b\r\n");
+        });
+    }
+
+    #[test]
+    fn rollup_groups_cant_be_channels() {
+        task::block_on(async {
+            let dir = LogDir::new("main-rollup-channel");
+            let options = testing::options(
+                &dir,
+                &[
+                    "--accepted-log-channels",
+                    "web,frontend",
+                    "--rollup-groups",
+                    "frontend",
+                ],
+            );
+            let error = FileWriter::with_options(&options).await.err().unwrap();
+            assert_eq!(
+                error.to_string(),
+                "rollup group `frontend` is also a channel"
+            );
+        });
+    }
+
+    #[test]
+    fn rotation_intervals_divide_a_day() {
+        assert_eq!(
+            parse_rotation_interval("15m"),
+            Ok(chrono::Duration::minutes(15))
+        );
+        assert_eq!(parse_rotation_interval("1d"), Ok(chrono::Duration::days(1)));
+        assert_eq!(
+            parse_rotation_interval("7m"),
+            Err(String::from("`7m` doesn't divide a day evenly"))
+        );
+        assert_eq!(
+            parse_rotation_interval("2d"),
+            Err(String::from("`2d` doesn't divide a day evenly"))
+        );
+        assert!(parse_rotation_interval("soon").is_err());
+    }
+
+    #[test]
+    fn channels_may_rotate_on_an_interval_of_their_own() {
+        task::block_on(async {
+            let dir = LogDir::new("main-rotation-intervals");
+            let args = [
+                "--accepted-log-channels",
+                "web,audit,edge",
+                "--rotation-interval",
+                "1d",
+                "--channel-groups",
+                "frontend=edge",
+                "--channel-rotation-intervals",
+                "audit=1h,frontend=5m",
+            ];
+            let writer = testing::writer(&dir, &args).await;
+            let settings = &writer.channel_settings;
+            let periodic = |minutes| {
+                Rotation::Periodic(FileTimestamp::Seconds, chrono::Duration::minutes(minutes))
+            };
+
+            assert_eq!(settings.rotation_of("web"), periodic(24 * 60));
+            assert_eq!(
+                settings.rotation_of("audit"),
+                Rotation::Hourly(FileTimestamp::Seconds)
+            );
+            assert_eq!(settings.rotation_of("edge"), periodic(5));
+
+            let dir = LogDir::new("main-rotation-intervals-external");
+            let writer =
+                testing::writer(&dir, &[&args[..], &["--external-rotation"]].concat()).await;
+            assert_eq!(
+                writer.channel_settings.rotation_of("audit"),
+                Rotation::External
+            );
+        });
+    }
+
+    #[test]
+    fn configured_channels_take_settings_of_their_own() {
+        task::block_on(async {
+            let dir = LogDir::new("main-configured");
+            let mut options = testing::options(&dir, &["--flush-bytes", "0"]);
+            options.configured_channels.insert(
+                String::from("web"),
+                ChannelConfig {
+                    file_name: Some(String::from("frontend")),
+                    aliases: Some(vec![String::from("web-1")]),
+                    ..ChannelConfig::default()
+                },
+            );
+            options.configured_channels.insert(
+                String::from("debug"),
+                ChannelConfig {
+                    sink: Some(String::from("null")),
+                    ..ChannelConfig::default()
+                },
+            );
+            let mut writer = FileWriter::with_options(&options).await.unwrap();
+            write(&mut writer, "web-1", &["GET /a\n"]).await;
+            write(&mut writer, "debug", &["x=1\n"]).await;
+            writer.sync_all().await.unwrap();
+
+            assert_eq!(dir.read("frontend_"), "GET /a\n");
+            assert!(dir
+                .file_names()
+                .iter()
+                .all(|name| !name.starts_with("web") && !name.starts_with("debug")));
+            assert_eq!(dir.read("inapt"), "");
+            assert!(writer
+                .channels
+                .get("debug")
+                .is_some_and(|sink| sink.discards()));
+            assert_eq!(writer.discarded_lines["debug"].lines, 1);
+        });
+    }
+
+    #[test]
+    fn buffering_profiles_pick_how_lines_wait() {
+        task::block_on(async {
+            let dir = LogDir::new("main-profiles");
+            let args = [
+                "--accepted-log-channels",
+                "audit,bulk,web",
+                "--buffering-profiles",
+                "audit=latency,bulk=balanced",
+                "--sync-policy",
+                "interval",
+            ];
+            let mut writer = testing::writer(&dir, &args).await;
+            write(&mut writer, "audit", &["login\n"]).await;
+            write(&mut writer, "bulk", &["row\n"]).await;
+            write(&mut writer, "web", &["GET /a\n"]).await;
+            assert_eq!(dir.read("audit_"), "login\n");
+            assert_eq!(dir.read("bulk_"), "");
+            assert!(writer.handle("web").unwrap().sync_policy == SyncPolicy::Interval);
+
+            // Past the balanced profile's flush interval; web has none.
+            let bulk = writer.handle_mut("bulk").unwrap();
+            bulk.flushed_at -= time::Duration::from_secs(2);
+            writer.flush_stale().await.unwrap();
+            assert_eq!(dir.read("bulk_"), "row\n");
+            assert_eq!(dir.read("web_"), "");
+        });
+    }
+
+    #[test]
+    fn compression_may_be_given_with_its_level_apart() {
+        let dir = LogDir::new("main-compression");
+        let expand = |args: &[&str]| {
+            let mut options = CliOptions::from_iter(
+                ["log-revolve-rs", "--log-dir", dir.path().to_str().unwrap()]
+                    .iter()
+                    .chain(args),
+            );
+            expand_shorthands(&mut options).map(|()| options.compress)
+        };
+
+        assert_eq!(
+            expand(&["--compression", "none"]).unwrap(),
+            Compression::None
+        );
+        let error = expand(&["--compression", "gzip", "--compression-level", "12"]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        #[cfg(feature = "gzip")]
+        assert_eq!(
+            expand(&["--compression", "gzip", "--compression-level", "1"]).unwrap(),
+            Compression::Gzip(1)
+        );
+        #[cfg(feature = "zstd")]
+        assert_eq!(
+            expand(&["--compression", "zstd"]).unwrap(),
+            Compression::Zstd(3)
+        );
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn configured_channels_choose_their_compression() {
+        task::block_on(async {
+            let dir = LogDir::new("main-configured-compression");
+            let mut options = testing::options(
+                &dir,
+                &["--accepted-log-channels", "web", "--compress", "none"],
+            );
+            options.configured_channels.insert(
+                String::from("bulk"),
+                ChannelConfig {
+                    compress: Some(String::from("zstd:9")),
+                    ..ChannelConfig::default()
+                },
+            );
+            let writer = FileWriter::with_options(&options).await.unwrap();
+            let settings = &writer.channel_settings;
+            assert_eq!(settings.compression_of("bulk"), Compression::Zstd(9));
+            assert_eq!(settings.compression_of("web"), Compression::None);
+        });
     }
 }
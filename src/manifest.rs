@@ -0,0 +1,135 @@
+use async_std::task;
+
+use chrono::{DateTime, Local, SecondsFormat};
+
+use sha2::{Digest, Sha256};
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use crate::post_rotation::Running;
+use crate::report;
+
+/// Name of the manifest of each directory files are finished in.
+const MANIFEST_NAME: &str = "manifest.csv";
+
+/// What the manifest's first line names.
+const HEADER: &str = "filename,size,sha256,closed_at\n";
+
+/// Bytes read from a file at a time while it is hashed.
+const READ_BYTES: usize = 64 * 1024;
+
+/// How finished files are recorded, set once at startup by `install`.
+static MANIFEST: OnceLock<Manifest> = OnceLock::new();
+
+struct Manifest {
+    /// Whether a `<file>.sha256` is written next to each file too.
+    sidecars: bool,
+    /// Held while a manifest is appended to, so lines of files finished
+    /// together aren't interleaved.
+    appending: Mutex<()>,
+}
+
+/// Records every file finished from now on in the manifest of its directory,
+/// and in a sidecar of its own when `sidecars` is set.
+pub fn install(sidecars: bool) {
+    let _ = MANIFEST.set(Manifest {
+        sidecars,
+        appending: Mutex::new(()),
+    });
+}
+
+/// Hashes the file at `path`, done with at `closed_at`, and records it
+/// before handing it to `recorded`, so what comes after, uploads and rotate
+/// hooks, finds it in the manifest. Failures are logged and reported, and the
+/// file handed on all the same.
+pub fn record<F>(path: String, closed_at: DateTime<Local>, recorded: F)
+where
+    F: FnOnce(String) + Send + 'static,
+{
+    let manifest = match MANIFEST.get() {
+        Some(manifest) => manifest,
+        None => return recorded(path),
+    };
+
+    let running = Running::start();
+    task::spawn(async move {
+        let _running = running;
+        let source = path.clone();
+        let result = task::spawn_blocking(move || manifest.record(&source, &closed_at)).await;
+        match result {
+            Ok(()) => tracing::debug!("recorded {} in its manifest", path),
+            Err(error) => {
+                tracing::warn!("unable to record {} in its manifest: {}", path, error);
+                report::record_error("manifest", error);
+            }
+        }
+        recorded(path);
+    });
+}
+
+impl Manifest {
+    fn record(&self, path: &str, closed_at: &DateTime<Local>) -> Result<(), io::Error> {
+        let path = Path::new(path);
+        let (size, digest) = hash_file(path)?;
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| io::Error::other("file name isn't UTF-8"))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+        if self.sidecars {
+            let sidecar = dir.join(format!("{}.sha256", name));
+            // As `sha256sum` writes it, so `sha256sum -c` checks the file.
+            std::fs::write(sidecar, format!("{}  {}\n", digest, name))?;
+        }
+
+        let line = format!(
+            "{},{},{},{}\n",
+            csv_field(name),
+            size,
+            digest,
+            closed_at.to_rfc3339_opts(SecondsFormat::Millis, false)
+        );
+        let _appending = self.appending.lock().unwrap();
+        let mut manifest = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(MANIFEST_NAME))?;
+        if manifest.metadata()?.len() == 0 {
+            manifest.write_all(HEADER.as_bytes())?;
+        }
+        manifest.write_all(line.as_bytes())?;
+        manifest.sync_data()
+    }
+}
+
+/// Size and hexadecimal SHA-256 of the file at `path`.
+fn hash_file(path: &Path) -> Result<(u64, String), io::Error> {
+    let mut file = File::open(path)?;
+    let mut hash = Sha256::new();
+    let mut buffer = vec![0; READ_BYTES];
+    let mut size = 0;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hash.update(&buffer[..read]);
+        size += read as u64;
+    }
+
+    Ok((size, format!("{:x}", hash.finalize())))
+}
+
+/// `field` quoted if it holds a comma or a quote, as file name templates
+/// may have it.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
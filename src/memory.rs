@@ -0,0 +1,116 @@
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use crate::quota;
+
+/// Ceiling on the bytes the router holds in memory across all channels,
+/// written as `256MB`: lines batched for their next write, held while the log
+/// directory is unavailable, or held back by a paused channel. Batches are
+/// written out early as it is approached, and lines that would have to be
+/// held beyond it are diverted or dropped instead.
+#[derive(Clone, Debug)]
+pub struct MemoryBudget {
+    pub limit: usize,
+    pub dropped_lines: u64,
+    exhausted: bool,
+}
+
+impl MemoryBudget {
+    /// Held bytes at which batches are written out early and backpressure
+    /// engages.
+    pub fn high_water(&self) -> usize {
+        self.limit / 4 * 3
+    }
+
+    /// Held bytes at which backpressure is released again.
+    pub fn low_water(&self) -> usize {
+        self.limit / 2
+    }
+
+    /// Whether `bytes` more may be held on top of the `held` bytes already.
+    pub fn admit(&mut self, held: usize, bytes: usize) -> bool {
+        let admitted = held + bytes <= self.limit;
+        if !admitted && !self.exhausted {
+            tracing::warn!(
+                "memory budget of {} bytes is spent, no longer holding lines",
+                self.limit
+            );
+        }
+        self.exhausted = !admitted;
+
+        admitted
+    }
+}
+
+impl FromStr for MemoryBudget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let limit = quota::parse_size(s)
+            .and_then(|limit| usize::try_from(limit).ok())
+            .ok_or_else(|| format!("expected a size such as `256MB`, got `{}`", s))?;
+
+        Ok(MemoryBudget {
+            limit,
+            dropped_lines: 0,
+            exhausted: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::task;
+
+    use crate::testing::{self, LogDir};
+
+    #[test]
+    fn budgets_are_sizes() {
+        let budget: MemoryBudget = "256MB".parse().unwrap();
+        assert_eq!(budget.limit, 256 << 20);
+        assert_eq!(budget.high_water(), 192 << 20);
+        assert_eq!(budget.low_water(), 128 << 20);
+        assert_eq!(
+            "lots".parse::<MemoryBudget>().unwrap_err(),
+            "expected a size such as `256MB`, got `lots`"
+        );
+    }
+
+    #[test]
+    fn lines_are_admitted_up_to_the_limit() {
+        let mut budget: MemoryBudget = "100".parse().unwrap();
+
+        assert!(budget.admit(0, 100));
+        assert!(!budget.admit(60, 41));
+        assert!(budget.exhausted);
+        assert!(budget.admit(60, 40));
+        assert!(!budget.exhausted);
+    }
+
+    #[test]
+    fn batches_are_written_out_early_near_the_budget() {
+        task::block_on(async {
+            let dir = LogDir::new("memory-early");
+            let args = ["--accepted-log-channels", "web", "--memory-budget", "40"];
+            let mut writer = testing::writer(&dir, &args).await;
+
+            writer
+                .write_to_channel("", "web", "0123456789\n")
+                .await
+                .unwrap();
+            writer
+                .write_to_channel("", "web", "0123456789\n")
+                .await
+                .unwrap();
+            assert_eq!(dir.read("web_"), "");
+            // Past three quarters of the budget.
+            writer
+                .write_to_channel("", "web", "0123456789\n")
+                .await
+                .unwrap();
+            assert_eq!(dir.read("web_"), "0123456789\n".repeat(3));
+        });
+    }
+}
@@ -0,0 +1,375 @@
+use async_std::io;
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+use serde_json::{json, Map, Value};
+
+use std::fmt::Write;
+
+use crate::{http, queue, report, retention, FileHandle, FileWriter};
+
+/// Serves `/metrics` in the Prometheus text format: lines, bytes, dropped
+/// lines and rotations per channel, lines over rate limits, filtered out,
+/// discarded by the null sink or not copied by `--pipe-out`, lines sent to
+/// the inapt file, errors per subsystem, what is queued ahead of or held back
+/// from the files, and the channels degraded by failing writes with what they
+/// hold in memory. And
+/// `/status`, a JSON snapshot of every channel's current file, its size, and
+/// when it was last written to and rotated, and of the lines `--pipe-out`
+/// dropped, for orchestration checking the router is alive and making
+/// progress.
+pub async fn serve(listener: TcpListener, writer: Arc<Mutex<FileWriter>>) {
+    let mut incoming = listener.incoming();
+
+    while let Some(stream) = incoming.next().await {
+        match stream {
+            Ok(stream) => {
+                let writer = writer.clone();
+                task::spawn(async move {
+                    if let Err(error) = handle_connection(stream, &writer).await {
+                        tracing::warn!("metrics connection failed: {}", error);
+                    }
+                });
+            }
+            Err(error) => tracing::warn!("unable to accept metrics connection: {}", error),
+        }
+    }
+}
+
+async fn handle_connection(stream: TcpStream, writer: &Mutex<FileWriter>) -> Result<(), io::Error> {
+    let request = http::read_request(&stream).await?;
+    if request.path != "/metrics" && request.path != "/status" {
+        return http::write_response(&stream, 404, "text/plain", b"not found\n").await;
+    }
+    if request.method != "GET" {
+        return http::write_response(&stream, 405, "text/plain", b"method not allowed\n").await;
+    }
+
+    let writer = writer.lock().await;
+    if request.path == "/status" {
+        let mut body = status(&writer).to_string();
+        body.push('\n');
+        return http::write_response(&stream, 200, "application/json", body.as_bytes()).await;
+    }
+
+    let body = render(&writer);
+    http::write_response(&stream, 200, "text/plain; version=0.0.4", body.as_bytes()).await
+}
+
+fn status(writer: &FileWriter) -> Value {
+    let channels: Vec<Value> = writer
+        .handles()
+        .map(|(name, handle)| file_status(name, handle))
+        .collect();
+
+    let mut status = json!({
+        "pid": std::process::id(),
+        "version": env!("CARGO_PKG_VERSION"),
+        "channels": channels,
+        "inapt": file_status(&writer.inapt_file_handle.file_name, &writer.inapt_file_handle),
+    });
+    if !writer.pipe_outs.is_empty() {
+        let pipe_outs: Map<String, Value> = writer
+            .pipe_outs
+            .iter()
+            .map(|(channel, pipe_out)| {
+                let status = json!({ "dropped_lines": pipe_out.dropped_lines });
+                (channel.clone(), status)
+            })
+            .collect();
+        status["pipe_outs"] = Value::Object(pipe_outs);
+    }
+
+    status
+}
+
+fn file_status(name: &str, handle: &FileHandle) -> Value {
+    json!({
+        "name": name,
+        "path": handle.current_path,
+        "size_bytes": handle.file_bytes,
+        "last_write": handle.written_at.map(|time| time.to_rfc3339()),
+        "last_rotation": handle.rotated_at.map(|time| time.to_rfc3339()),
+        "degraded": handle.degraded.as_ref().map(|degraded| &degraded.error),
+    })
+}
+
+type Counter = fn(&FileHandle) -> u64;
+
+/// Counters kept by each channel's handle: name, help and value.
+const CHANNEL_COUNTERS: [(&str, &str, Counter); 5] = [
+    (
+        "lines_written_total",
+        "Lines written per channel.",
+        |handle| handle.lines_written,
+    ),
+    (
+        "bytes_written_total",
+        "Bytes written per channel.",
+        |handle| handle.bytes_written,
+    ),
+    (
+        "writes_total",
+        "Writes the lines of each channel went out in; lines over writes is the batch size.",
+        |handle| handle.writes(),
+    ),
+    (
+        "dropped_lines_total",
+        "Lines dropped per channel.",
+        |handle| handle.dropped_lines,
+    ),
+    (
+        "rotations_total",
+        "Files rotated away from per channel.",
+        |handle| handle.rotations,
+    ),
+];
+
+fn render(writer: &FileWriter) -> String {
+    let mut metrics = Metrics::default();
+
+    for (name, help, counter) in CHANNEL_COUNTERS.iter() {
+        metrics.family(name, "counter", help);
+        for (channel, handle) in writer.handles() {
+            metrics.labelled(name, "channel", channel, counter(handle));
+        }
+    }
+
+    let inapt_lines = writer.inapt_file_handle.lines_written;
+    metrics.family(
+        "inapt_lines_total",
+        "counter",
+        "Lines written to the inapt file.",
+    );
+    metrics.value("inapt_lines_total", inapt_lines);
+
+    metrics.family(
+        "rate_limited_lines_total",
+        "counter",
+        "Lines over the rate limit of their channel, dropped or overflowed.",
+    );
+    for (channel, rate_limit) in writer.rate_limits.iter() {
+        metrics.labelled(
+            "rate_limited_lines_total",
+            "channel",
+            channel,
+            rate_limit.limited_lines,
+        );
+    }
+
+    metrics.family(
+        "sampled_out_lines_total",
+        "counter",
+        "Lines left out by the sample rate of their channel.",
+    );
+    for (channel, sampler) in writer.samplers.iter() {
+        metrics.labelled(
+            "sampled_out_lines_total",
+            "channel",
+            channel,
+            sampler.sampled_out_lines,
+        );
+    }
+
+    metrics.family(
+        "discarded_lines_total",
+        "counter",
+        "Lines of channels going to the null sink.",
+    );
+    for (channel, discarded) in writer.discarded_lines.iter() {
+        metrics.labelled("discarded_lines_total", "channel", channel, discarded.lines);
+    }
+    metrics.family(
+        "discarded_bytes_total",
+        "counter",
+        "Bytes of channels going to the null sink.",
+    );
+    for (channel, discarded) in writer.discarded_lines.iter() {
+        metrics.labelled("discarded_bytes_total", "channel", channel, discarded.bytes);
+    }
+
+    if let Some(removed) = retention::removed_over_budget() {
+        metrics.family(
+            "disk_budget_removed_files_total",
+            "counter",
+            "Rotated files removed to keep the log directory within --max-total-disk.",
+        );
+        metrics.value("disk_budget_removed_files_total", removed.files);
+        metrics.family(
+            "disk_budget_removed_bytes_total",
+            "counter",
+            "Bytes of the rotated files removed to keep within --max-total-disk.",
+        );
+        metrics.value("disk_budget_removed_bytes_total", removed.bytes);
+    }
+
+    #[cfg(feature = "forward")]
+    if !writer.forwarders.is_empty() {
+        metrics.family(
+            "forwarded_lines_total",
+            "counter",
+            "Lines relayed to the forwarding destination of their channel.",
+        );
+        for (channel, forwarder) in writer.forwarders.iter() {
+            metrics.labelled(
+                "forwarded_lines_total",
+                "channel",
+                channel,
+                forwarder.forwarded_lines(),
+            );
+        }
+        metrics.family(
+            "forward_dropped_lines_total",
+            "counter",
+            "Lines dropped while the forwarding destination of their channel was away.",
+        );
+        for (channel, forwarder) in writer.forwarders.iter() {
+            metrics.labelled(
+                "forward_dropped_lines_total",
+                "channel",
+                channel,
+                forwarder.dropped_lines,
+            );
+        }
+    }
+
+    if !writer.pipe_outs.is_empty() {
+        metrics.family(
+            "pipe_out_dropped_lines_total",
+            "counter",
+            "Lines not copied to the co-process of their channel, which fell behind.",
+        );
+        for (channel, pipe_out) in writer.pipe_outs.iter() {
+            metrics.labelled(
+                "pipe_out_dropped_lines_total",
+                "channel",
+                channel,
+                pipe_out.dropped_lines,
+            );
+        }
+    }
+
+    #[cfg(feature = "filter")]
+    if let Some(ref filters) = writer.filters {
+        metrics.family(
+            "filtered_lines_total",
+            "counter",
+            "Lines dropped by drop and keep patterns per channel.",
+        );
+        for (channel, count) in filters.filtered_lines.iter() {
+            metrics.labelled("filtered_lines_total", "channel", channel, *count);
+        }
+    }
+
+    if let Some(ref deduplicator) = writer.deduplicator {
+        metrics.family(
+            "repeated_lines_total",
+            "counter",
+            "Lines collapsed into an identical one before them per channel.",
+        );
+        for (channel, count) in deduplicator.repeated_lines.iter() {
+            metrics.labelled("repeated_lines_total", "channel", channel, *count);
+        }
+    }
+
+    #[cfg(feature = "gelf")]
+    if let Some(ref gelf_sink) = writer.gelf_sink {
+        metrics.family(
+            "gelf_dropped_lines_total",
+            "counter",
+            "Messages lost to a full GELF queue or a failed send.",
+        );
+        metrics.value("gelf_dropped_lines_total", gelf_sink.dropped_lines());
+    }
+
+    #[cfg(feature = "kafka")]
+    if let Some(ref kafka_sink) = writer.kafka_sink {
+        metrics.family(
+            "kafka_produced_lines_total",
+            "counter",
+            "Lines acknowledged by the Kafka brokers.",
+        );
+        metrics.value("kafka_produced_lines_total", kafka_sink.produced_lines());
+        metrics.family(
+            "kafka_fallback_lines_total",
+            "counter",
+            "Lines written to the Kafka fallback file instead.",
+        );
+        metrics.value("kafka_fallback_lines_total", kafka_sink.fallback_lines());
+        metrics.family(
+            "kafka_dropped_lines_total",
+            "counter",
+            "Lines lost to a full Kafka queue or a failed fallback write.",
+        );
+        metrics.value("kafka_dropped_lines_total", kafka_sink.dropped_lines());
+    }
+
+    metrics.family("errors_total", "counter", "Errors per subsystem.");
+    for (subsystem, count) in report::error_counts() {
+        metrics.labelled("errors_total", "subsystem", &subsystem, count);
+    }
+
+    let queued_lines = queue::queued_lines() as u64;
+    metrics.family(
+        "input_queue_lines",
+        "gauge",
+        "Lines read ahead of the writer.",
+    );
+    metrics.value("input_queue_lines", queued_lines);
+
+    let held_bytes = writer.queued_bytes() as u64;
+    metrics.family("held_bytes", "gauge", "Bytes batched or held in memory.");
+    metrics.value("held_bytes", held_bytes);
+
+    let degraded = writer
+        .handles()
+        .map(|(_, handle)| handle)
+        .filter(|handle| handle.degraded.is_some());
+    let (degraded_channels, spilled_bytes) = degraded.fold((0, 0), |(count, bytes), handle| {
+        (count + 1, bytes + handle.memory_bytes() as u64)
+    });
+    metrics.family(
+        "degraded_channels",
+        "gauge",
+        "Channels whose files can't be written to.",
+    );
+    metrics.value("degraded_channels", degraded_channels);
+    metrics.family(
+        "spilled_bytes",
+        "gauge",
+        "Bytes held in memory for degraded channels.",
+    );
+    metrics.value("spilled_bytes", spilled_bytes);
+
+    metrics.text
+}
+
+#[derive(Default)]
+struct Metrics {
+    text: String,
+}
+
+impl Metrics {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP log_revolve_{} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE log_revolve_{} {}", name, kind);
+    }
+
+    fn value(&mut self, name: &str, value: u64) {
+        let _ = writeln!(self.text, "log_revolve_{} {}", name, value);
+    }
+
+    fn labelled(&mut self, name: &str, label: &str, label_value: &str, value: u64) {
+        let escaped = label_value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+        let _ = writeln!(
+            self.text,
+            "log_revolve_{}{{{}=\"{}\"}} {}",
+            name, label, escaped, value
+        );
+    }
+}
@@ -0,0 +1,313 @@
+use async_std::fs;
+use async_std::io;
+use async_std::path::PathBuf;
+use async_std::prelude::*;
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+use chrono::{DateTime, Duration, Local};
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time;
+
+use log_revolve_rs::rotation::{self, Rotation};
+
+use crate::compress::Compression;
+use crate::zone::Zone;
+use crate::{check_channel_name, report, FileHandle, FileWriter, Layout};
+
+/// What happens to lines sent to a channel that isn't accepted.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UnknownChannels {
+    /// Written to the inapt file.
+    Reject,
+    /// Captured in files of their own under `pending/` until accepted or
+    /// expired.
+    Pending,
+    /// Accepted on the fly, as channels of their own.
+    Create,
+}
+
+impl FromStr for UnknownChannels {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(UnknownChannels::Reject),
+            "pending" => Ok(UnknownChannels::Pending),
+            "create" => Ok(UnknownChannels::Create),
+            _ => Err(format!("unknown channel policy: {}", s)),
+        }
+    }
+}
+
+pub struct PendingChannel {
+    pub handle: FileHandle,
+    pub first_seen: DateTime<Local>,
+    pub last_seen: DateTime<Local>,
+}
+
+/// Channels nobody has accepted yet, each written to its own files under
+/// `<log_dir>/pending/` so a new service's lines are kept while operators
+/// decide what to do with it. A channel left without lines for `ttl` is
+/// closed and its files removed.
+pub struct PendingChannels {
+    dir: String,
+    rotation: Rotation,
+    zone: Zone,
+    pub ttl: Duration,
+    max_channels: usize,
+    pub channels: BTreeMap<String, PendingChannel>,
+}
+
+impl PendingChannels {
+    pub async fn open(
+        log_dir: &str,
+        rotation: Rotation,
+        zone: Zone,
+        ttl: Duration,
+        max_channels: usize,
+    ) -> Result<Self, io::Error> {
+        let mut dir = PathBuf::from(log_dir);
+        dir.push("pending");
+        fs::create_dir_all(&dir).await?;
+
+        Ok(PendingChannels {
+            dir: dir
+                .to_str()
+                .ok_or_else(|| io::Error::other("unable to build pending directory path"))?
+                .to_string(),
+            rotation,
+            zone,
+            ttl,
+            max_channels,
+            channels: BTreeMap::new(),
+        })
+    }
+
+    /// Writes `line` to the pending files of `channel`, or returns `false` when
+    /// the name isn't fit for a file name or too many channels are pending
+    /// already, leaving the line to the inapt file.
+    pub async fn write(&mut self, channel: &str, line: &str) -> Result<bool, io::Error> {
+        let now = Local::now();
+        if !self.channels.contains_key(channel) {
+            if check_channel_name(channel).is_err() || self.channels.len() >= self.max_channels {
+                return Ok(false);
+            }
+
+            tracing::warn!(
+                "capturing unknown channel {} in {} until it is accepted",
+                channel,
+                self.dir
+            );
+            let handle = FileHandle::create(
+                &self.dir,
+                channel,
+                self.rotation,
+                self.zone.clone(),
+                Layout::default(),
+                Compression::None,
+                false,
+            )
+            .await?;
+            let pending = PendingChannel {
+                handle,
+                first_seen: now,
+                last_seen: now,
+            };
+            self.channels.insert(channel.to_string(), pending);
+        }
+
+        if let Some(pending) = self.channels.get_mut(channel) {
+            pending.last_seen = now;
+            pending.handle.write_line(line).await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Stops capturing a channel that has just been accepted. Its files are
+    /// left where they are.
+    #[cfg(any(feature = "control-socket", feature = "http-admin", feature = "config"))]
+    pub async fn accept(&mut self, channel: &str) -> Result<(), io::Error> {
+        if let Some(mut pending) = self.channels.remove(channel) {
+            pending.handle.flush().await?;
+            tracing::info!(
+                "channel {} accepted, its pending files are left in {}",
+                channel,
+                self.dir
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Closes the channels that have gone quiet for longer than the TTL and
+    /// removes their files.
+    pub async fn expire(&mut self) -> Result<(), io::Error> {
+        let deadline = Local::now() - self.ttl;
+        let expired: Vec<String> = self
+            .channels
+            .iter()
+            .filter(|(_, pending)| pending.last_seen <= deadline)
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        for channel in expired.iter() {
+            if let Some(pending) = self.channels.remove(channel) {
+                let removed = self.remove_files(channel).await?;
+                tracing::info!(
+                    "pending channel {} seen since {} expired, {} files removed",
+                    channel,
+                    pending.first_seen.to_rfc3339(),
+                    removed
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn remove_files(&self, channel: &str) -> Result<usize, io::Error> {
+        let unrotated = format!("{}.log", channel);
+        let mut removed = 0;
+
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = match name.to_str() {
+                Some(name) => name,
+                None => continue,
+            };
+            let is_channel_file = match self.rotation {
+                Rotation::Hourly(timestamp) | Rotation::Periodic(timestamp, _) => {
+                    rotation::parse_file_name(channel, timestamp, name).is_some()
+                }
+                Rotation::External => name == unrotated,
+            };
+            if is_channel_file {
+                fs::remove_file(entry.path()).await?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+/// Expires pending channels as their TTL runs out.
+pub async fn sweep(writer: Arc<Mutex<FileWriter>>, interval: time::Duration) {
+    loop {
+        task::sleep(interval).await;
+
+        let mut writer = writer.lock().await;
+        if let Some(ref mut pending) = writer.pending {
+            if let Err(error) = pending.expire().await {
+                tracing::warn!("unable to expire pending channels: {}", error);
+                report::record_error("pending", error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use log_revolve_rs::rotation::FileTimestamp;
+
+    use crate::testing::LogDir;
+
+    async fn pending(dir: &LogDir, ttl: Duration, max_channels: usize) -> PendingChannels {
+        PendingChannels::open(
+            dir.path().to_str().unwrap(),
+            Rotation::Hourly(FileTimestamp::Seconds),
+            Zone::Local,
+            ttl,
+            max_channels,
+        )
+        .await
+        .unwrap()
+    }
+
+    fn pending_files(dir: &LogDir) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir.path().join("pending"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn policies_are_named_on_the_command_line() {
+        assert_eq!("reject".parse(), Ok(UnknownChannels::Reject));
+        assert_eq!("pending".parse(), Ok(UnknownChannels::Pending));
+        assert_eq!("create".parse(), Ok(UnknownChannels::Create));
+        assert!("accept".parse::<UnknownChannels>().is_err());
+    }
+
+    #[test]
+    fn unknown_channels_are_captured_under_pending() {
+        task::block_on(async {
+            let dir = LogDir::new("pending-capture");
+            let mut pending = pending(&dir, Duration::hours(1), 2).await;
+
+            assert!(pending.write("web", "GET /\n").await.unwrap());
+            assert!(pending.write("web", "GET /a\n").await.unwrap());
+            assert!(!pending.write("../etc", "root\n").await.unwrap());
+            assert!(pending.write("db", "SELECT 1\n").await.unwrap());
+            // Past the limit, new channels are left to the inapt file.
+            assert!(!pending.write("cache", "HIT\n").await.unwrap());
+            assert_eq!(pending.channels.keys().collect::<Vec<_>>(), ["db", "web"]);
+
+            pending
+                .channels
+                .get_mut("web")
+                .unwrap()
+                .handle
+                .flush()
+                .await
+                .unwrap();
+            let files = pending_files(&dir);
+            let web = files.iter().find(|name| name.starts_with("web_")).unwrap();
+            let lines = std::fs::read_to_string(dir.path().join("pending").join(web)).unwrap();
+            assert_eq!(lines, "GET /\nGET /a\n");
+        });
+    }
+
+    #[test]
+    fn quiet_channels_expire_with_their_files() {
+        task::block_on(async {
+            let dir = LogDir::new("pending-expire");
+            let mut pending = pending(&dir, Duration::zero(), 10).await;
+            pending.write("web", "GET /\n").await.unwrap();
+            pending.write("webapp", "GET /\n").await.unwrap();
+            pending.channels.get_mut("webapp").unwrap().last_seen += Duration::hours(1);
+            assert_eq!(pending_files(&dir).len(), 2);
+
+            pending.expire().await.unwrap();
+            assert_eq!(pending.channels.keys().collect::<Vec<_>>(), ["webapp"]);
+            let files = pending_files(&dir);
+            assert_eq!(files.len(), 1);
+            assert!(files[0].starts_with("webapp_"));
+        });
+    }
+
+    #[cfg(any(feature = "control-socket", feature = "http-admin", feature = "config"))]
+    #[test]
+    fn accepted_channels_leave_their_files_behind() {
+        task::block_on(async {
+            let dir = LogDir::new("pending-accept");
+            let mut pending = pending(&dir, Duration::zero(), 10).await;
+            pending.write("web", "GET /\n").await.unwrap();
+
+            pending.accept("web").await.unwrap();
+            pending.expire().await.unwrap();
+            assert!(pending.channels.is_empty());
+            assert_eq!(pending_files(&dir).len(), 1);
+        });
+    }
+}
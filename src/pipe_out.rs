@@ -0,0 +1,142 @@
+use async_std::channel::{self, Receiver, Sender, TrySendError};
+use async_std::fs::File;
+use async_std::future;
+use async_std::io;
+use async_std::prelude::*;
+use async_std::task::{self, JoinHandle};
+
+use std::time::Duration;
+
+use crate::{fd, report};
+
+/// Lines waiting for a slow co-process before newer ones are dropped.
+const QUEUE_LINES: usize = 4096;
+
+/// How long lines still queued at shutdown get to reach the co-process.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+/// Copies a channel's lines, as written to its files, to a descriptor
+/// inherited from the parent process (`--pipe-out app=fd:3`), so a co-process
+/// can follow the channel live. Lines go through a bounded queue: a reader
+/// falling behind loses lines rather than holding up the files, and one that
+/// goes away stops the copy.
+pub struct PipeOut {
+    queue: Sender<String>,
+    writer: Option<JoinHandle<()>>,
+    pub dropped_lines: u64,
+}
+
+impl PipeOut {
+    pub fn open(channel: &str, spec: &str) -> Result<Self, io::Error> {
+        let file = File::from(fd::open(spec)?);
+        let (queue, queued) = channel::bounded(QUEUE_LINES);
+        let writer = task::spawn(write(channel.to_string(), file, queued));
+
+        Ok(PipeOut {
+            queue,
+            writer: Some(writer),
+            dropped_lines: 0,
+        })
+    }
+
+    pub fn send(&mut self, line: &str) {
+        match self.queue.try_send(line.to_string()) {
+            Ok(()) | Err(TrySendError::Closed(_)) => {}
+            Err(TrySendError::Full(_)) => {
+                if self.dropped_lines == 0 {
+                    tracing::warn!("pipe-out reader is falling behind, dropping lines");
+                }
+                self.dropped_lines += 1;
+            }
+        }
+    }
+
+    pub async fn close(&mut self) {
+        self.queue.close();
+
+        if let Some(writer) = self.writer.take() {
+            if future::timeout(SHUTDOWN_GRACE, writer).await.is_err() {
+                tracing::warn!("pipe-out lines still queued at shutdown were lost");
+            }
+        }
+    }
+}
+
+async fn write(channel: String, mut file: File, queued: Receiver<String>) {
+    while let Ok(line) = queued.recv().await {
+        let result = match file.write_all(line.as_bytes()).await {
+            Ok(()) => file.flush().await,
+            Err(error) => Err(error),
+        };
+        if let Err(error) = result {
+            tracing::warn!("stopped copying {} to its pipe: {}", channel, error);
+            report::record_error("pipe-out", error);
+            queued.close();
+            return;
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+    use std::os::unix::io::IntoRawFd;
+    use std::os::unix::net::UnixStream;
+
+    /// A copy to one end of a socket pair, and the other end to read it.
+    fn pipe_out() -> (PipeOut, UnixStream) {
+        let (reader, inherited) = UnixStream::pair().unwrap();
+        let spec = format!("fd:{}", inherited.into_raw_fd());
+        (PipeOut::open("app", &spec).unwrap(), reader)
+    }
+
+    #[test]
+    fn lines_are_copied_in_order_until_closed() {
+        task::block_on(async {
+            let (mut pipe_out, mut reader) = pipe_out();
+            pipe_out.send("first\n");
+            pipe_out.send("second\n");
+            pipe_out.close().await;
+            pipe_out.send("after close\n");
+
+            let mut copied = String::new();
+            reader.read_to_string(&mut copied).unwrap();
+            assert_eq!(copied, "first\nsecond\n");
+            assert_eq!(pipe_out.dropped_lines, 0);
+        });
+    }
+
+    #[test]
+    fn readers_falling_behind_lose_lines() {
+        task::block_on(async {
+            let (mut pipe_out, reader) = pipe_out();
+            let line = "x".repeat(4095) + "\n";
+            for _ in 0..QUEUE_LINES + 1000 {
+                pipe_out.send(&line);
+            }
+            assert!(pipe_out.dropped_lines >= 500, "{}", pipe_out.dropped_lines);
+
+            // Lets the stuck copy fail rather than wait out the grace period.
+            drop(reader);
+            pipe_out.close().await;
+        });
+    }
+
+    #[test]
+    fn readers_going_away_stop_the_copy() {
+        task::block_on(async {
+            let (mut pipe_out, reader) = pipe_out();
+            drop(reader);
+            pipe_out.send("lost\n");
+            while !pipe_out.queue.is_closed() {
+                task::sleep(Duration::from_millis(5)).await;
+            }
+
+            pipe_out.send("not even queued\n");
+            assert_eq!(pipe_out.dropped_lines, 0);
+            pipe_out.close().await;
+        });
+    }
+}
@@ -0,0 +1,313 @@
+use async_std::fs;
+use async_std::io::{self, ReadExt, WriteExt};
+use async_std::net::TcpStream;
+
+use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::config::ChannelConfig;
+use crate::CliOptions;
+
+/// How long a metadata service gets to answer before startup fails.
+const METADATA_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Cloud metadata service asked for the instance id when none is configured.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InstanceMetadata {
+    /// EC2 instance metadata, through an IMDSv2 session token.
+    Aws,
+    /// Compute Engine metadata server.
+    Gcp,
+}
+
+impl FromStr for InstanceMetadata {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "aws" => Ok(InstanceMetadata::Aws),
+            "gcp" => Ok(InstanceMetadata::Gcp),
+            _ => Err(format!("unknown metadata service: {}", s)),
+        }
+    }
+}
+
+/// Values of the `{hostname}`, `{instance_id}` and `{pod}` placeholders, each
+/// taken from its option, then the environment (`HOSTNAME`, `INSTANCE_ID`,
+/// `POD_NAME`), then the system or a metadata service where there is one.
+pub struct Placeholders {
+    hostname: String,
+    instance_id: Option<String>,
+    pod: Option<String>,
+}
+
+impl Placeholders {
+    pub async fn resolve(options: &CliOptions) -> Result<Self, io::Error> {
+        let hostname = hostname(options).await;
+        let instance_id = match option_or_env(&options.instance_id, "INSTANCE_ID") {
+            Some(instance_id) => Some(instance_id),
+            None => match options.instance_metadata {
+                Some(service) => Some(instance_id(service).await?),
+                None => None,
+            },
+        };
+
+        Ok(Placeholders {
+            hostname,
+            instance_id,
+            pod: option_or_env(&options.pod, "POD_NAME"),
+        })
+    }
+
+    /// Replaces the placeholders in whatever option takes them: the log and
+    /// inapt directories, the inapt file name, the GELF host and the SIEM
+    /// field values.
+    pub fn apply(&self, options: &mut CliOptions) -> Result<(), io::Error> {
+        options.log_dir = self.expand(&options.log_dir)?;
+        if let Some(ref inapt_dir) = options.inapt_dir {
+            options.inapt_dir = Some(self.expand(inapt_dir)?);
+        }
+        options.inapt_file_name = self.expand(&options.inapt_file_name)?;
+        for config in options.configured_channels.values_mut() {
+            self.apply_to_channel(config)?;
+        }
+        #[cfg(feature = "gelf")]
+        if let Some(ref gelf_host) = options.gelf_host {
+            options.gelf_host = Some(self.expand(gelf_host)?);
+        }
+        #[cfg(feature = "siem")]
+        {
+            options.siem_fields = self.expand(&options.siem_fields)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the placeholders in the directory and file name of a channel
+    /// of the config file.
+    pub fn apply_to_channel(&self, config: &mut ChannelConfig) -> Result<(), io::Error> {
+        if let Some(ref directory) = config.directory {
+            config.directory = Some(self.expand(directory)?);
+        }
+        if let Some(ref file_name) = config.file_name {
+            config.file_name = Some(self.expand(file_name)?);
+        }
+
+        Ok(())
+    }
+
+    pub fn expand(&self, template: &str) -> Result<String, io::Error> {
+        let mut expanded = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            expanded.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| invalid(format!("unclosed placeholder in `{}`", template)))?;
+
+            let value = match &rest[start + 1..end] {
+                "hostname" => Some(&self.hostname),
+                "instance_id" => self.instance_id.as_ref(),
+                "pod" => self.pod.as_ref(),
+                name => return Err(invalid(format!("unknown placeholder {{{}}}", name))),
+            };
+            let value = value.ok_or_else(|| {
+                invalid(format!(
+                    "{} is used in `{}` but has no value",
+                    &rest[start..=end],
+                    template
+                ))
+            })?;
+            expanded.push_str(value);
+            rest = &rest[end + 1..];
+        }
+        expanded.push_str(rest);
+
+        Ok(expanded)
+    }
+}
+
+/// Name of the host the router runs on, as `{hostname}` takes it.
+pub async fn hostname(options: &CliOptions) -> String {
+    match option_or_env(&options.hostname, "HOSTNAME") {
+        Some(hostname) => hostname,
+        None => system_hostname().await,
+    }
+}
+
+fn option_or_env(option: &Option<String>, variable: &str) -> Option<String> {
+    option
+        .clone()
+        .or_else(|| env::var(variable).ok())
+        .filter(|value| !value.is_empty())
+}
+
+async fn system_hostname() -> String {
+    match fs::read_to_string("/proc/sys/kernel/hostname").await {
+        Ok(hostname) if !hostname.trim().is_empty() => hostname.trim().to_string(),
+        // Windows has no /proc, but keeps the computer's name in the
+        // environment.
+        _ => env::var("COMPUTERNAME")
+            .ok()
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| String::from("localhost")),
+    }
+}
+
+async fn instance_id(service: InstanceMetadata) -> Result<String, io::Error> {
+    let instance_id = match service {
+        InstanceMetadata::Aws => {
+            let token = request(
+                "169.254.169.254",
+                "PUT",
+                "/latest/api/token",
+                "X-aws-ec2-metadata-token-ttl-seconds: 60",
+            )
+            .await?;
+            let header = format!("X-aws-ec2-metadata-token: {}", token.trim());
+            request(
+                "169.254.169.254",
+                "GET",
+                "/latest/meta-data/instance-id",
+                &header,
+            )
+            .await?
+        }
+        InstanceMetadata::Gcp => {
+            request(
+                "metadata.google.internal",
+                "GET",
+                "/computeMetadata/v1/instance/id",
+                "Metadata-Flavor: Google",
+            )
+            .await?
+        }
+    };
+
+    Ok(instance_id.trim().to_string())
+}
+
+/// A single plain HTTP request to a metadata service, returning the body of
+/// a `200` response.
+async fn request(host: &str, method: &str, path: &str, header: &str) -> Result<String, io::Error> {
+    let exchange = async {
+        let mut stream = TcpStream::connect((host, 80)).await?;
+        let request = format!(
+            "{} {} HTTP/1.0\r\nHost: {}\r\n{}\r\nContent-Length: 0\r\n\r\n",
+            method, path, host, header
+        );
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        Ok::<_, io::Error>(response)
+    };
+    let response = io::timeout(METADATA_TIMEOUT, exchange).await?;
+
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| invalid(format!("malformed response from {}", host)))?;
+    let status = head.lines().next().unwrap_or("");
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(io::Error::other(format!(
+            "{} answered {} {} with `{}`",
+            host, method, path, status
+        )));
+    }
+
+    Ok(body.to_string())
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::task;
+
+    use crate::testing::{self, LogDir};
+
+    fn placeholders(instance_id: Option<&str>) -> Placeholders {
+        Placeholders {
+            hostname: String::from("web1"),
+            instance_id: instance_id.map(String::from),
+            pod: None,
+        }
+    }
+
+    #[test]
+    fn placeholders_are_replaced_wherever_they_appear() {
+        let placeholders = placeholders(Some("i-0abc"));
+        assert_eq!(
+            placeholders
+                .expand("/var/log/{hostname}/{instance_id}")
+                .unwrap(),
+            "/var/log/web1/i-0abc"
+        );
+        assert_eq!(
+            placeholders.expand("{hostname}-{hostname}").unwrap(),
+            "web1-web1"
+        );
+        assert_eq!(placeholders.expand("plain").unwrap(), "plain");
+    }
+
+    #[test]
+    fn placeholders_without_a_value_are_refused() {
+        let placeholders = placeholders(None);
+        let error = placeholders.expand("logs/{pod}").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "{pod} is used in `logs/{pod}` but has no value"
+        );
+        let error = placeholders.expand("{zone}").unwrap_err();
+        assert_eq!(error.to_string(), "unknown placeholder {zone}");
+        let error = placeholders.expand("logs/{hostname").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "unclosed placeholder in `logs/{hostname`"
+        );
+    }
+
+    #[test]
+    fn options_come_before_the_environment_and_the_system() {
+        task::block_on(async {
+            let dir = LogDir::new("placeholders");
+            let mut options = testing::options(
+                &dir,
+                &[
+                    "--hostname",
+                    "web1",
+                    "--instance-id",
+                    "i-0abc",
+                    "--pod",
+                    "api-7d9",
+                    "--inapt-file-name",
+                    "inapt-{pod}",
+                ],
+            );
+            let log_dir = options.log_dir.clone();
+            options.log_dir = format!("{}/{{hostname}}", log_dir);
+
+            let placeholders = Placeholders::resolve(&options).await.unwrap();
+            placeholders.apply(&mut options).unwrap();
+            assert_eq!(options.log_dir, format!("{}/web1", log_dir));
+            assert_eq!(options.inapt_file_name, "inapt-api-7d9");
+            assert_eq!(hostname(&options).await, "web1");
+        });
+    }
+
+    #[test]
+    fn metadata_services_are_aws_or_gcp() {
+        assert_eq!("aws".parse(), Ok(InstanceMetadata::Aws));
+        assert_eq!("gcp".parse(), Ok(InstanceMetadata::Gcp));
+        assert_eq!(
+            "azure".parse::<InstanceMetadata>(),
+            Err(String::from("unknown metadata service: azure"))
+        );
+    }
+}
@@ -0,0 +1,107 @@
+//! What differs between the platforms the router runs on: which characters
+//! separate paths, which names can't be files, how a channel's current file
+//! is linked to and how lines end. Each platform is a value rather than a
+//! `cfg`, so the rules of all of them can be checked from any of them.
+
+use std::borrow::Cow;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Platform {
+    Unix,
+    Windows,
+}
+
+/// How the `<channel>.log` link to a channel's current file is made.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CurrentLink {
+    Symlink,
+    /// Symlinks on Windows take a privilege services seldom have, so a hard
+    /// link stands in, replaced at every rotation.
+    HardLink,
+}
+
+/// Names Windows keeps for devices, whatever their case or extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+impl Platform {
+    /// The platform the router was built for.
+    pub const CURRENT: Platform = if cfg!(windows) {
+        Platform::Windows
+    } else {
+        Platform::Unix
+    };
+
+    pub fn is_separator(self, c: char) -> bool {
+        c == '/' || (self == Platform::Windows && c == '\\')
+    }
+
+    /// Checks that `name`, such as a channel or tenant, can name a single
+    /// file or directory, rather than a path or a device.
+    pub fn check_file_name(self, name: &str) -> Result<(), String> {
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(format!("`{}` can't name a file", name));
+        }
+        if name.contains(|c| self.is_separator(c) || c == '\0') {
+            return Err(format!("`{}` must name a file, not a path", name));
+        }
+        if self == Platform::Unix {
+            return Ok(());
+        }
+
+        if let Some(c) = name
+            .chars()
+            .find(|c| c.is_control() || "<>:\"|?*".contains(*c))
+        {
+            return Err(format!("`{}` holds {:?}, which Windows forbids", name, c));
+        }
+        if name.ends_with(['.', ' ']) {
+            return Err(format!(
+                "`{}` can't end in a dot or a space on Windows",
+                name
+            ));
+        }
+        let stem = name.split('.').next().unwrap_or(name).trim_end();
+        if RESERVED_NAMES
+            .iter()
+            .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+        {
+            return Err(format!("`{}` is a device name on Windows", name));
+        }
+
+        Ok(())
+    }
+
+    /// `relative`, a `/`-separated path such as a tenant's
+    /// `<tenant>/<channel>`, with the platform's own separators.
+    pub fn native_path(self, relative: &str) -> Cow<'_, str> {
+        match self {
+            Platform::Windows if relative.contains('/') => Cow::Owned(relative.replace('/', "\\")),
+            _ => Cow::Borrowed(relative),
+        }
+    }
+
+    pub fn current_link(self) -> CurrentLink {
+        match self {
+            Platform::Unix => CurrentLink::Symlink,
+            Platform::Windows => CurrentLink::HardLink,
+        }
+    }
+}
+
+/// `line` without its ending, `\n` or the `\r\n` lines piped from Windows
+/// programs end in.
+pub fn strip_line_ending(line: &str) -> &str {
+    line.strip_suffix("\r\n")
+        .or_else(|| line.strip_suffix('\n'))
+        .unwrap_or(line)
+}
+
+/// `strip_line_ending` of a line that may not be UTF-8.
+pub fn strip_line_ending_bytes(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\r\n")
+        .or_else(|| line.strip_suffix(b"\n"))
+        .unwrap_or(line)
+}
@@ -0,0 +1,41 @@
+use async_std::task;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Compressions and rotate hooks still running for files rotated away from.
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// How often the shutdown looks whether the work it waits for is done.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Work on a file rotated away from, counted as running until dropped.
+pub struct Running(());
+
+impl Running {
+    pub fn start() -> Self {
+        RUNNING.fetch_add(1, Ordering::SeqCst);
+        Running(())
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Waits for the compressions and rotate hooks of files rotated away from to
+/// finish, so the router doesn't exit leaving one half compressed. Uploads
+/// are left to the manifest, resumed by the next start.
+pub async fn drain() {
+    let running = RUNNING.load(Ordering::SeqCst);
+    if running == 0 {
+        return;
+    }
+
+    tracing::info!("waiting for {} compressions and rotate hooks", running);
+    while RUNNING.load(Ordering::SeqCst) > 0 {
+        task::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+}
@@ -0,0 +1,212 @@
+use async_std::io;
+
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::path::Path;
+
+use crate::config::ChannelSink;
+use crate::{accepted_channels, log_dir, parse_pairs, ChannelSettings, CliOptions};
+
+/// Descriptors kept for what isn't a channel's file: stdio, the lock, inputs
+/// and listeners, compression and the odd socket.
+#[cfg(all(unix, feature = "preflight"))]
+const RESERVED_FDS: u64 = 32;
+
+/// Checks what the router needs of the machine before any file is opened:
+/// that every directory exists or can be created and takes new files, that
+/// the channels' current files, when there already, can be appended to, and,
+/// with the `preflight` feature, that the directories have room and the
+/// process may open a file for every channel. Every problem found is
+/// reported at once, naming the channels it affects.
+pub async fn run(options: &CliOptions) -> Result<(), io::Error> {
+    let mut problems = Vec::new();
+    let accepted = accepted_channels(options);
+
+    let mut dirs: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    dirs.entry(&options.log_dir).or_default();
+    if let Some(ref inapt_dir) = options.inapt_dir {
+        dirs.entry(inapt_dir).or_default();
+    }
+    // The config file's directories take over the flag's, as in the
+    // channel settings.
+    let flagged_dirs = parse_pairs(&options.channel_dirs)?;
+    let configured_dirs = options
+        .configured_channels
+        .iter()
+        .filter_map(|(channel, config)| Some((channel, config.directory.as_ref()?)));
+    let channel_dirs: BTreeMap<&str, &str> = flagged_dirs
+        .iter()
+        .map(|(channel, dir)| (channel, dir))
+        .chain(configured_dirs)
+        .map(|(channel, dir)| (channel.as_str(), dir.as_str()))
+        .collect();
+    for channel in accepted.iter() {
+        let dir = channel_dirs
+            .get(channel)
+            .copied()
+            .unwrap_or(&options.log_dir);
+        dirs.entry(dir).or_default().push(channel);
+    }
+
+    for (dir, channels) in dirs.iter() {
+        if let Err(error) = check_dir(dir) {
+            problems.push(format!("{}: {}", describe(dir, channels), error));
+        }
+    }
+
+    // Directories are all there, or reported, by now.
+    if problems.is_empty() {
+        let settings = ChannelSettings::with_options(options, &options.configured_channels).await?;
+        for channel in accepted.iter() {
+            if settings.sink(channel) != ChannelSink::File {
+                continue;
+            }
+            let path = settings.current_path(channel)?;
+            if let Err(error) = check_file(&path) {
+                problems.push(format!("channel {}: {}", channel, error));
+            }
+        }
+    }
+
+    #[cfg(all(unix, feature = "preflight"))]
+    {
+        for (dir, channels) in dirs.iter().filter(|(dir, _)| Path::new(dir).is_dir()) {
+            match free_space(dir) {
+                Ok(free) if free < options.min_free_space => problems.push(format!(
+                    "{}: {} bytes free, less than --min-free-space {}",
+                    describe(dir, channels),
+                    free,
+                    options.min_free_space
+                )),
+                Ok(_) => {}
+                Err(error) => tracing::warn!("unable to tell the free space of {}: {}", dir, error),
+            }
+        }
+
+        let needed = (accepted.len() + options.inputs.len()) as u64 + RESERVED_FDS;
+        match fd_limit() {
+            // Files of idle channels are closed as they go quiet.
+            Ok(limit) if limit < needed && options.idle_close_after.is_none() => {
+                problems.push(format!(
+                    "open file limit is {}, less than the {} needed for {} channels; raise it \
+                     (`ulimit -n`, `LimitNOFILE=`) or set --idle-close-after",
+                    limit,
+                    needed,
+                    accepted.len()
+                ))
+            }
+            Ok(_) => {}
+            Err(error) => tracing::warn!("unable to tell the open file limit: {}", error),
+        }
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+    for problem in problems.iter() {
+        tracing::error!("preflight: {}", problem);
+    }
+    Err(io::Error::other(format!(
+        "preflight found {} problems:\n  {}",
+        problems.len(),
+        problems.join("\n  ")
+    )))
+}
+
+/// `dir` and the channels writing to it, e.g. `directory /var/log/app
+/// (channels app, web)`.
+fn describe(dir: &str, channels: &[&str]) -> String {
+    if channels.is_empty() {
+        format!("directory {}", dir)
+    } else {
+        format!("directory {} (channels {})", dir, channels.join(", "))
+    }
+}
+
+/// Makes sure `dir` exists, creating it if allowed, and takes a new file.
+fn check_dir(dir: &str) -> Result<(), io::Error> {
+    log_dir::ensure(dir)?;
+
+    let probe = Path::new(dir).join(format!(".log-revolve-preflight-{}", std::process::id()));
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .map_err(|error| {
+            io::Error::new(
+                error.kind(),
+                format!("unable to create files in it: {}", error),
+            )
+        })?;
+    std::fs::remove_file(&probe)
+}
+
+/// Makes sure the file at `path`, if there already, can be appended to.
+fn check_file(path: &str) -> Result<(), io::Error> {
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error),
+    };
+
+    match OpenOptions::new().append(true).open(path) {
+        Ok(_) => Ok(()),
+        Err(error) => Err(io::Error::new(
+            error.kind(),
+            format!(
+                "unable to append to {}{}: {}",
+                path,
+                owner(&metadata),
+                error
+            ),
+        )),
+    }
+}
+
+#[cfg(unix)]
+fn owner(metadata: &std::fs::Metadata) -> String {
+    use std::os::unix::fs::MetadataExt;
+
+    format!(
+        ", owned by uid {} with mode {:o}",
+        metadata.uid(),
+        metadata.mode() & 0o7777
+    )
+}
+
+#[cfg(not(unix))]
+fn owner(_metadata: &std::fs::Metadata) -> String {
+    String::new()
+}
+
+/// Bytes the process may still write to the filesystem of `dir`.
+#[cfg(all(unix, feature = "preflight"))]
+fn free_space(dir: &str) -> Result<u64, io::Error> {
+    use std::ffi::CString;
+
+    let path = CString::new(dir).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // `path` is a valid C string and `stat` is ours to fill.
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Descriptors the process may have open at once.
+#[cfg(all(unix, feature = "preflight"))]
+fn fd_limit() -> Result<u64, io::Error> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // `limit` is ours to fill.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    Ok(limit.rlim_cur as u64)
+}
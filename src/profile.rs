@@ -0,0 +1,152 @@
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::{report, FileWriter};
+
+/// Batch size, flush interval and fsync policy of a channel, bundled so they
+/// are picked together rather than tuned one by one:
+///
+/// | profile      | batch   | flushed         | fsynced               |
+/// |--------------|---------|-----------------|-----------------------|
+/// | `latency`    | none    | after each line | never                 |
+/// | `balanced`   | 64 KiB  | every second    | as the file rotates   |
+/// | `throughput` | 1 MiB   | every 10 s      | never                 |
+///
+/// Priority channels keep flushing, and fsyncing, after every line.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BufferingProfile {
+    Latency,
+    Balanced,
+    Throughput,
+}
+
+impl BufferingProfile {
+    /// Bytes of lines batched before they are written out.
+    pub fn batch_bytes(self) -> usize {
+        match self {
+            BufferingProfile::Latency => 0,
+            BufferingProfile::Balanced => 64 * 1024,
+            BufferingProfile::Throughput => 1024 * 1024,
+        }
+    }
+
+    /// Longest a batched line waits before being written out.
+    pub fn flush_interval(self) -> Option<Duration> {
+        match self {
+            BufferingProfile::Latency => None,
+            BufferingProfile::Balanced => Some(Duration::from_secs(1)),
+            BufferingProfile::Throughput => Some(Duration::from_secs(10)),
+        }
+    }
+
+    /// Whether a file is fsynced as the channel rotates away from it.
+    pub fn syncs_on_rotation(self) -> bool {
+        self == BufferingProfile::Balanced
+    }
+}
+
+impl FromStr for BufferingProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "latency" => Ok(BufferingProfile::Latency),
+            "balanced" => Ok(BufferingProfile::Balanced),
+            "throughput" => Ok(BufferingProfile::Throughput),
+            _ => Err(format!("unknown buffering profile: {}", s)),
+        }
+    }
+}
+
+/// When a channel's files are fsynced, on top of what its durability and
+/// buffering profile do: `none` leaves it to the OS, `interval` fsyncs what
+/// was written since the last time every `--sync-interval` and as the file
+/// rotates, and `every-line` after each line, written out at once.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SyncPolicy {
+    None,
+    Interval,
+    EveryLine,
+}
+
+impl FromStr for SyncPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(SyncPolicy::None),
+            "interval" => Ok(SyncPolicy::Interval),
+            "every-line" => Ok(SyncPolicy::EveryLine),
+            _ => Err(format!("unknown sync policy: {}", s)),
+        }
+    }
+}
+
+/// Writes out the batches that waited past their channel's flush interval,
+/// from its profile or `--flush-interval`, checking every `interval`.
+pub async fn flush_every(writer: Arc<Mutex<FileWriter>>, interval: Duration) {
+    loop {
+        task::sleep(interval).await;
+
+        let mut writer = writer.lock().await;
+        if let Err(error) = writer.flush_stale().await {
+            tracing::warn!("unable to flush batched lines: {}", error);
+            report::record_error("flush", error);
+        }
+    }
+}
+
+/// Fsyncs the files of channels synced on an interval, every `interval`.
+pub async fn sync_every(writer: Arc<Mutex<FileWriter>>, interval: Duration) {
+    loop {
+        task::sleep(interval).await;
+
+        let mut writer = writer.lock().await;
+        if let Err(error) = writer.sync_unsynced().await {
+            tracing::warn!("unable to fsync written lines: {}", error);
+            report::record_error("fsync", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_bundle_batching_flushing_and_fsyncing() {
+        let latency: BufferingProfile = "latency".parse().unwrap();
+        assert_eq!(latency.batch_bytes(), 0);
+        assert_eq!(latency.flush_interval(), None);
+        assert!(!latency.syncs_on_rotation());
+
+        let balanced: BufferingProfile = "balanced".parse().unwrap();
+        assert_eq!(balanced.batch_bytes(), 64 * 1024);
+        assert_eq!(balanced.flush_interval(), Some(Duration::from_secs(1)));
+        assert!(balanced.syncs_on_rotation());
+
+        let throughput: BufferingProfile = "throughput".parse().unwrap();
+        assert_eq!(throughput.batch_bytes(), 1024 * 1024);
+        assert_eq!(throughput.flush_interval(), Some(Duration::from_secs(10)));
+        assert!(!throughput.syncs_on_rotation());
+
+        assert_eq!(
+            "fast".parse::<BufferingProfile>(),
+            Err(String::from("unknown buffering profile: fast"))
+        );
+    }
+
+    #[test]
+    fn sync_policies_are_none_interval_or_every_line() {
+        assert_eq!("none".parse(), Ok(SyncPolicy::None));
+        assert_eq!("interval".parse(), Ok(SyncPolicy::Interval));
+        assert_eq!("every-line".parse(), Ok(SyncPolicy::EveryLine));
+        assert_eq!(
+            "always".parse::<SyncPolicy>(),
+            Err(String::from("unknown sync policy: always"))
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use async_std::prelude::*;
+
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveDateTime, TimeZone};
+
+use std::io::{self, Write};
+
+use structopt::StructOpt;
+
+use log_revolve_rs::reader::ChannelReader;
+use log_revolve_rs::rotation::{DirLayout, FileNameParser, FileTimestamp};
+
+#[cfg(feature = "encryption")]
+use crate::encrypt;
+use crate::zone::Zone;
+use crate::{parse_pairs, parse_rotation_interval};
+
+/// Where a channel's files are and which of their lines are read, shared by
+/// `cat` and `grep`.
+#[derive(StructOpt)]
+pub struct RangeOptions {
+    /// Channel whose files are read
+    channel: String,
+
+    /// Directory holding the files, as given to the router's `--log-dir`
+    #[structopt(long)]
+    log_dir: String,
+
+    /// Comma-separated `channel=dir` pairs, as given to the router's
+    /// `--channel-dirs`; the channel's own directory is read instead of the
+    /// log directory
+    #[structopt(long, default_value = "")]
+    channel_dirs: String,
+
+    /// Subdirectories the files are sharded into, as given to the router's
+    /// `--dir-template`
+    #[structopt(long)]
+    dir_template: Option<DirLayout>,
+
+    /// Zone the file names are stamped in, as given to the router's
+    /// `--timezone`, or its `--channel-timezones` for the channel
+    #[structopt(long, default_value = "local")]
+    timezone: Zone,
+
+    /// File holding the key encrypted files are read with, as given to the
+    /// router's `--encryption-key-file`
+    #[cfg(feature = "encryption")]
+    #[structopt(long)]
+    key_file: Option<String>,
+
+    /// Timestamp in the file names, as given to the router's
+    /// `--file-timestamp`
+    #[structopt(long, default_value = "seconds")]
+    file_timestamp: FileTimestamp,
+
+    /// How long a file covers at most, as given to the router's
+    /// `--rotation-interval`
+    #[structopt(long, default_value = "1h", parse(try_from_str = parse_rotation_interval))]
+    rotation_interval: Duration,
+
+    /// Start of the range, e.g. `2024-06-01 09:30` on the clocks of
+    /// `--timezone` or an RFC 3339 time; the channel's first file when
+    /// omitted
+    #[structopt(long, parse(try_from_str = parse_time))]
+    from: Option<Time>,
+
+    /// End of the range, as `--from`; the channel's last file when omitted
+    #[structopt(long, parse(try_from_str = parse_time))]
+    to: Option<Time>,
+}
+
+/// A time given on the command line.
+#[derive(Clone, Copy, Debug)]
+enum Time {
+    /// An RFC 3339 time, at its offset.
+    Exact(DateTime<FixedOffset>),
+    /// A time on the clocks of `--timezone`.
+    Wall(NaiveDateTime),
+}
+
+impl Time {
+    fn in_zone(self, zone: &Zone) -> Result<DateTime<Zone>, io::Error> {
+        match self {
+            Time::Exact(time) => Ok(time.with_timezone(zone)),
+            Time::Wall(time) => zone.from_local_datetime(&time).earliest().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} never comes on the clocks of --timezone", time),
+                )
+            }),
+        }
+    }
+}
+
+/// Prints the lines of a channel's files covering a time range, e.g.
+/// `log-revolve-rs cat app --log-dir /var/log/app --from "2024-06-01 09:30"
+/// --to "2024-06-01 11:00"`. Compressed files are decompressed as they are
+/// read, and encrypted ones decrypted given `--key-file`. Lines carry no time
+/// of their own, so whole files are printed.
+#[derive(StructOpt)]
+#[structopt(name = "cat")]
+pub struct CatOptions {
+    #[structopt(flatten)]
+    range: RangeOptions,
+}
+
+/// Prints the lines holding `pattern` of a channel's files covering a time
+/// range, e.g. `log-revolve-rs grep timeout app --log-dir /var/log/app
+/// --from 2024-06-01`.
+#[derive(StructOpt)]
+#[structopt(name = "grep")]
+pub struct GrepOptions {
+    /// Text the lines printed hold
+    pattern: String,
+
+    /// Matches `pattern` whatever its case
+    #[structopt(short, long)]
+    ignore_case: bool,
+
+    /// Prints the lines not holding `pattern` instead
+    #[structopt(short = "v", long)]
+    invert_match: bool,
+
+    #[structopt(flatten)]
+    range: RangeOptions,
+}
+
+pub async fn cat(options: CatOptions) -> Result<(), io::Error> {
+    print_lines(&options.range, |_| true).await
+}
+
+pub async fn grep(options: GrepOptions) -> Result<(), io::Error> {
+    let pattern = if options.ignore_case {
+        options.pattern.to_lowercase()
+    } else {
+        options.pattern.clone()
+    };
+
+    print_lines(&options.range, |line| {
+        let found = if options.ignore_case {
+            line.to_lowercase().contains(&pattern)
+        } else {
+            line.contains(&pattern)
+        };
+        found != options.invert_match
+    })
+    .await
+}
+
+/// Prints the lines of the range `selected` keeps, stopping quietly should
+/// whatever reads them, such as `head`, stop first.
+async fn print_lines<F>(range: &RangeOptions, selected: F) -> Result<(), io::Error>
+where
+    F: Fn(&str) -> bool,
+{
+    let parser = Stamped {
+        timestamp: range.file_timestamp,
+        period: range.rotation_interval,
+    };
+    let log_dir = parse_pairs(&range.channel_dirs)?
+        .into_iter()
+        .find(|(channel, _)| *channel == range.channel)
+        .map_or_else(|| range.log_dir.clone(), |(_, dir)| dir);
+    let mut reader = ChannelReader::with_parser(log_dir, parser);
+    if let Some(ref dirs) = range.dir_template {
+        reader = reader.with_dirs(dirs.clone());
+    }
+    #[cfg(feature = "encryption")]
+    if let Some(ref key_file) = range.key_file {
+        reader = reader.with_key(encrypt::read_key(key_file)?);
+    }
+
+    let zone = &range.timezone;
+    let from = match range.from {
+        Some(from) => from.in_zone(zone)?,
+        None => zone.timestamp_opt(0, 0).unwrap(),
+    };
+    let to = match range.to {
+        Some(to) => to.in_zone(zone)?,
+        None => zone.now() + Duration::days(365 * 100),
+    };
+    let mut lines = reader.range(&range.channel, from, to).await?;
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    while let Some(line) = lines.next().await {
+        let line = line?;
+        if !selected(&line) {
+            continue;
+        }
+        match writeln!(stdout, "{}", line) {
+            Err(error) if error.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            result => result?,
+        }
+    }
+
+    match stdout.flush() {
+        Err(error) if error.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+/// File names stamped in `--file-timestamp` format, each covering up to
+/// `--rotation-interval`.
+struct Stamped {
+    timestamp: FileTimestamp,
+    period: Duration,
+}
+
+impl FileNameParser for Stamped {
+    fn parse(&self, channel: &str, file_name: &str) -> Option<NaiveDateTime> {
+        self.timestamp.parse(channel, file_name)
+    }
+
+    fn period_end(&self, opened_at: NaiveDateTime) -> NaiveDateTime {
+        if self.period == Duration::hours(1) {
+            self.timestamp.period_end(opened_at)
+        } else {
+            opened_at + self.period
+        }
+    }
+}
+
+/// An RFC 3339 time, or one on the clocks of `--timezone`: `2024-06-01
+/// 09:30[:00]`, `T` in place of the space, or a date alone for its midnight.
+fn parse_time(s: &str) -> Result<Time, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(Time::Exact(time));
+    }
+
+    [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+    .or_else(|| {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+    })
+    .map(Time::Wall)
+    .ok_or_else(|| format!("invalid time: {}", s))
+}
@@ -0,0 +1,259 @@
+use async_std::channel::{self, Receiver, Sender, TrySendError};
+use async_std::io;
+use async_std::task;
+
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::decompress::Input;
+use crate::failure::{self, Backoff};
+use crate::line_limit::{LineReader, Piece};
+
+type Queue = Receiver<Result<(String, Piece), io::Error>>;
+
+/// Queues of the inputs still open, for metrics.
+static QUEUES: Mutex<Vec<Queue>> = Mutex::new(Vec::new());
+
+/// Lines every input dropped while its queue was full.
+static DROPPED_LINES: AtomicU64 = AtomicU64::new(0);
+
+/// What the reader of an input does with a line once its queue is full.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum QueueFull {
+    /// Waits for room, holding back the producer.
+    Block,
+    /// Drops the line queued the longest to make room.
+    DropOldest,
+    /// Drops the line just read.
+    DropNewest,
+}
+
+impl FromStr for QueueFull {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(QueueFull::Block),
+            "drop-oldest" => Ok(QueueFull::DropOldest),
+            "drop-newest" => Ok(QueueFull::DropNewest),
+            _ => Err(format!("unknown queue policy: {}", s)),
+        }
+    }
+}
+
+/// Lines of an input, read as the writer asks for them or, given a queue
+/// depth, by a task of their own up to that many lines ahead of the writer,
+/// so a slow disk doesn't stall reading.
+pub enum Lines {
+    Direct(Input, LineReader),
+    Queued(Queue),
+}
+
+impl Lines {
+    pub fn new(
+        name: &str,
+        input: Input,
+        reader: LineReader,
+        depth: Option<usize>,
+        policy: QueueFull,
+    ) -> Self {
+        let depth = match depth {
+            Some(depth) => depth.max(1),
+            None => return Lines::Direct(input, reader),
+        };
+
+        let (sender, receiver) = channel::bounded(depth);
+        task::spawn(fill(
+            name.to_string(),
+            input,
+            reader,
+            sender,
+            receiver.clone(),
+            policy,
+        ));
+        if let Ok(mut queues) = QUEUES.lock() {
+            queues.push(receiver.clone());
+        }
+
+        Lines::Queued(receiver)
+    }
+
+    /// Appends the next line, or piece of one, to `line`, `None` once the
+    /// input is closed.
+    pub async fn read_line(&mut self, line: &mut String) -> Result<Option<Piece>, io::Error> {
+        match self {
+            Lines::Direct(input, reader) => reader.read_line(input, line).await,
+            Lines::Queued(receiver) => match receiver.recv().await {
+                Ok(Ok((next, piece))) => {
+                    line.push_str(&next);
+                    Ok(Some(piece))
+                }
+                Ok(Err(error)) => Err(error),
+                Err(_) => Ok(None),
+            },
+        }
+    }
+}
+
+impl Drop for Lines {
+    /// Stops the reading task once the writer gives up on the input.
+    fn drop(&mut self) {
+        if let Lines::Queued(receiver) = self {
+            receiver.close();
+            if let Ok(mut queues) = QUEUES.lock() {
+                queues.retain(|queue| !queue.is_closed());
+            }
+        }
+    }
+}
+
+/// Lines waiting in the queues of every input.
+#[cfg(feature = "metrics")]
+pub fn queued_lines() -> usize {
+    match QUEUES.lock() {
+        Ok(queues) => queues.iter().map(Receiver::len).sum(),
+        Err(_) => 0,
+    }
+}
+
+/// Lines every input dropped while its queue was full, for backpressure
+/// notices.
+pub fn dropped_lines() -> u64 {
+    DROPPED_LINES.load(Ordering::Relaxed)
+}
+
+/// Reads `input` into the queue until it is closed or fails, or the writer
+/// is gone. `receiver` is only used to drop the oldest line.
+async fn fill(
+    name: String,
+    mut input: Input,
+    mut reader: LineReader,
+    sender: Sender<Result<(String, Piece), io::Error>>,
+    receiver: Queue,
+    policy: QueueFull,
+) {
+    let mut dropped = 0;
+    let mut backoff = Backoff::default();
+    loop {
+        let mut line = String::new();
+        let next = match reader.read_line(&mut input, &mut line).await {
+            Ok(None) => break,
+            Ok(Some(piece)) => (line, piece),
+            Err(error) => match failure::retry_read(&name, error, &mut backoff).await {
+                Ok(()) => continue,
+                Err(error) => {
+                    let _ = sender.send(Err(error)).await;
+                    return;
+                }
+            },
+        };
+        backoff.reset();
+
+        let next = match sender.try_send(Ok(next)) {
+            Ok(()) => {
+                if dropped > 0 {
+                    tracing::warn!(
+                        "input {} dropped {} lines while its queue was full",
+                        name,
+                        dropped
+                    );
+                    dropped = 0;
+                }
+                continue;
+            }
+            Err(TrySendError::Closed(_)) => return,
+            Err(TrySendError::Full(next)) => next,
+        };
+
+        match policy {
+            QueueFull::Block => {
+                if sender.send(next).await.is_err() {
+                    return;
+                }
+            }
+            QueueFull::DropOldest => {
+                let _ = receiver.try_recv();
+                let _ = sender.try_send(next);
+                dropped += 1;
+            }
+            QueueFull::DropNewest => dropped += 1,
+        }
+        if policy != QueueFull::Block {
+            DROPPED_LINES.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    if dropped > 0 {
+        tracing::warn!("input {} dropped {} lines before closing", name, dropped);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::io::Cursor;
+    use std::time::Duration;
+
+    use crate::encoding::InvalidUtf8;
+    use crate::line_limit::MaxLineAction;
+
+    fn lines(depth: Option<usize>, policy: QueueFull) -> Lines {
+        let input: Input = Box::new(Cursor::new(b"1\n2\n3\n4\n5\n6\n".to_vec()));
+        let reader = LineReader::new(None, MaxLineAction::Truncate, InvalidUtf8::Lossy, false);
+        Lines::new("test", input, reader, depth, policy)
+    }
+
+    async fn read_all(lines: &mut Lines) -> Vec<String> {
+        let mut read = Vec::new();
+        let mut line = String::new();
+        while lines.read_line(&mut line).await.unwrap().is_some() {
+            read.push(std::mem::take(&mut line));
+        }
+        read
+    }
+
+    /// Waits for the reading task to have dropped `count` more lines than
+    /// `before`.
+    async fn dropped_since(before: u64, count: u64) {
+        while dropped_lines() < before + count {
+            task::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[test]
+    fn policies_are_block_or_drop() {
+        assert_eq!("block".parse(), Ok(QueueFull::Block));
+        assert_eq!("drop-oldest".parse(), Ok(QueueFull::DropOldest));
+        assert_eq!("drop-newest".parse(), Ok(QueueFull::DropNewest));
+        assert_eq!(
+            "drop-all".parse::<QueueFull>(),
+            Err(String::from("unknown queue policy: drop-all"))
+        );
+    }
+
+    #[test]
+    fn lines_come_through_whole_with_or_without_a_queue() {
+        task::block_on(async {
+            let all: Vec<String> = (1..=6).map(|n| format!("{}\n", n)).collect();
+            assert_eq!(read_all(&mut lines(None, QueueFull::Block)).await, all);
+            assert_eq!(read_all(&mut lines(Some(2), QueueFull::Block)).await, all);
+        });
+    }
+
+    #[test]
+    fn full_queues_drop_the_oldest_or_the_newest_line() {
+        task::block_on(async {
+            let before = dropped_lines();
+            let mut newest_dropped = lines(Some(2), QueueFull::DropNewest);
+            dropped_since(before, 4).await;
+            assert_eq!(read_all(&mut newest_dropped).await, ["1\n", "2\n"]);
+
+            let before = dropped_lines();
+            let mut oldest_dropped = lines(Some(2), QueueFull::DropOldest);
+            dropped_since(before, 4).await;
+            assert_eq!(read_all(&mut oldest_dropped).await, ["5\n", "6\n"]);
+        });
+    }
+}
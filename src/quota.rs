@@ -0,0 +1,196 @@
+use chrono::Local;
+
+use std::str::FromStr;
+
+/// What happens to the lines of a channel once its quota is spent.
+#[derive(Clone, Copy, Debug)]
+pub enum QuotaAction {
+    /// Written to a `<channel>.overflow` file next to the channel's own.
+    Overflow,
+    Drop,
+}
+
+impl FromStr for QuotaAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "overflow" => Ok(QuotaAction::Overflow),
+            "drop" => Ok(QuotaAction::Drop),
+            _ => Err(format!("unknown quota action: {}", s)),
+        }
+    }
+}
+
+/// Byte budget of a channel over a period, written as `2GB/hour`. Periods are
+/// aligned to local time, so an hourly quota starts over on the hour, together
+/// with the channel's file.
+pub struct Quota {
+    pub limit: u64,
+    period_secs: i64,
+    window: i64,
+    pub used: u64,
+    exhausted: bool,
+    pub overflow_lines: u64,
+    pub overflow_bytes: u64,
+}
+
+impl Quota {
+    /// Counts `bytes` against the current period. Once a line doesn't fit,
+    /// nothing else is admitted until the next period.
+    pub fn admit(&mut self, channel: &str, bytes: usize) -> bool {
+        let window = Local::now()
+            .naive_local()
+            .and_utc()
+            .timestamp()
+            .div_euclid(self.period_secs);
+        if window != self.window {
+            self.window = window;
+            self.used = 0;
+            self.exhausted = false;
+        }
+
+        if !self.exhausted && self.used + bytes as u64 > self.limit {
+            tracing::warn!(
+                "channel {} is over its quota of {} bytes",
+                channel,
+                self.limit
+            );
+            self.exhausted = true;
+        }
+        if self.exhausted {
+            self.overflow_lines += 1;
+            self.overflow_bytes += bytes as u64;
+            return false;
+        }
+
+        self.used += bytes as u64;
+        true
+    }
+}
+
+impl FromStr for Quota {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected a quota such as `2GB/hour`, got `{}`", s);
+
+        let (size, period) = s.split_once('/').ok_or_else(invalid)?;
+        let limit = parse_size(size).ok_or_else(invalid)?;
+        let period_secs = match period {
+            "second" => 1,
+            "minute" => 60,
+            "hour" => 60 * 60,
+            "day" => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+
+        Ok(Quota {
+            limit,
+            period_secs,
+            window: 0,
+            used: 0,
+            exhausted: false,
+            overflow_lines: 0,
+            overflow_bytes: 0,
+        })
+    }
+}
+
+/// A byte count such as `512`, `64KB` or `2GB`.
+pub fn parse_size(size: &str) -> Option<u64> {
+    let digits = size.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier: u64 = match size[digits.len()..].to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "KB" => 1 << 10,
+        "MB" => 1 << 20,
+        "GB" => 1 << 30,
+        "TB" => 1 << 40,
+        _ => return None,
+    };
+    let count: u64 = digits.trim().parse().ok()?;
+
+    count.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::task;
+
+    use crate::testing::{self, LogDir};
+
+    #[test]
+    fn sizes_take_binary_units() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("512B"), Some(512));
+        assert_eq!(parse_size("64KB"), Some(64 << 10));
+        assert_eq!(parse_size("2gb"), Some(2 << 30));
+        assert_eq!(parse_size("1TB"), Some(1 << 40));
+        assert_eq!(parse_size("2PB"), None);
+        assert_eq!(parse_size("GB"), None);
+        assert_eq!(parse_size("-1KB"), None);
+        assert_eq!(parse_size("20000000TB"), None);
+    }
+
+    #[test]
+    fn quotas_name_a_size_and_a_period() {
+        let quota: Quota = "2GB/hour".parse().unwrap();
+        assert_eq!(quota.limit, 2 << 30);
+        assert_eq!(quota.period_secs, 3600);
+        assert_eq!("1KB/day".parse::<Quota>().unwrap().period_secs, 86400);
+
+        for quota in ["2GB", "2GB/week", "lots/hour"] {
+            assert_eq!(
+                quota.parse::<Quota>().err().unwrap(),
+                format!("expected a quota such as `2GB/hour`, got `{}`", quota)
+            );
+        }
+    }
+
+    #[test]
+    fn nothing_is_admitted_once_a_line_does_not_fit() {
+        let mut quota: Quota = "10/day".parse().unwrap();
+
+        assert!(quota.admit("web", 6));
+        assert!(!quota.admit("web", 6));
+        // Even a line that would fit waits for the next period.
+        assert!(!quota.admit("web", 1));
+        assert_eq!(quota.used, 6);
+        assert_eq!((quota.overflow_lines, quota.overflow_bytes), (2, 7));
+
+        quota.window -= 1;
+        assert!(quota.admit("web", 10));
+        assert_eq!(quota.used, 10);
+    }
+
+    #[test]
+    fn lines_over_quota_go_to_the_overflow_file_or_nowhere() {
+        task::block_on(async {
+            for (action, overflow) in [("overflow", "GET /b\nGET /c\n"), ("drop", "")] {
+                let dir = LogDir::new(&format!("quota-{}", action));
+                let mut writer = testing::writer(
+                    &dir,
+                    &[
+                        "--accepted-log-channels",
+                        "web",
+                        "--quotas",
+                        "web=10/day",
+                        "--quota-action",
+                        action,
+                    ],
+                )
+                .await;
+                for line in ["GET /a\n", "GET /b\n", "GET /c\n"] {
+                    writer.write_to_channel("", "web", line).await.unwrap();
+                }
+                writer.sync_all().await.unwrap();
+
+                assert_eq!(dir.read("web_"), "GET /a\n", "{}", action);
+                assert_eq!(dir.read("web.overflow_"), overflow, "{}", action);
+                assert_eq!(writer.quotas["web"].overflow_lines, 2);
+            }
+        });
+    }
+}
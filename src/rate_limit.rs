@@ -0,0 +1,128 @@
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::quota;
+
+/// What happens to the lines of a channel going faster than its rate limit.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RateLimitAction {
+    Drop,
+    /// Written to a `<channel>.overflow` file next to the channel's own.
+    Overflow,
+    /// Written, then the input that sent the line waits for the channel's
+    /// bucket to refill before reading on, holding back its producer.
+    Block,
+}
+
+impl FromStr for RateLimitAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(RateLimitAction::Drop),
+            "overflow" => Ok(RateLimitAction::Overflow),
+            "block" => Ok(RateLimitAction::Block),
+            _ => Err(format!("unknown rate limit action: {}", s)),
+        }
+    }
+}
+
+/// Lines or bytes a channel may write per second, written as `1000/s` or
+/// `5MB/s`: a token bucket holding a second's worth, so short bursts pass
+/// while the rate holds on average.
+pub struct RateLimit {
+    /// Whether the bucket counts bytes rather than lines.
+    bytes: bool,
+    per_second: f64,
+    tokens: f64,
+    refilled_at: Instant,
+    limiting: bool,
+    pub limited_lines: u64,
+    pub limited_bytes: u64,
+}
+
+impl RateLimit {
+    /// Takes a line of `bytes` from the bucket, or says how long until it
+    /// holds enough for it. A line larger than the bucket waits for a full
+    /// one.
+    pub fn take(&mut self, channel: &str, bytes: usize) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.per_second);
+        self.refilled_at = now;
+
+        let cost = if self.bytes { bytes as f64 } else { 1.0 };
+        let needed = cost.min(self.per_second);
+        if self.tokens >= needed {
+            self.tokens -= cost;
+            self.limiting = false;
+            return Ok(());
+        }
+
+        if !self.limiting {
+            tracing::warn!("channel {} is over its rate limit", channel);
+            self.limiting = true;
+        }
+        Err(Duration::from_secs_f64(
+            (needed - self.tokens) / self.per_second,
+        ))
+    }
+
+    /// Takes a line of `bytes` from the bucket whether it holds enough or
+    /// not, and says how long until the bucket has made up for it if it
+    /// didn't.
+    pub fn owe(&mut self, channel: &str, bytes: usize) -> Option<Duration> {
+        self.take(channel, bytes).err()?;
+        self.tokens -= if self.bytes { bytes as f64 } else { 1.0 };
+
+        Some(Duration::from_secs_f64(
+            (-self.tokens / self.per_second).max(0.0),
+        ))
+    }
+
+    /// Counts a line that didn't get through.
+    pub fn limited(&mut self, bytes: usize) {
+        self.limited_lines += 1;
+        self.limited_bytes += bytes as u64;
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "expected a rate limit such as `1000/s` or `5MB/s`, got `{}`",
+                s
+            )
+        };
+
+        let (amount, period) = s.split_once('/').ok_or_else(invalid)?;
+        let seconds = match period {
+            "s" | "second" => 1.0,
+            "min" | "minute" => 60.0,
+            _ => return Err(invalid()),
+        };
+        let bytes = amount.ends_with(|c: char| c.is_ascii_alphabetic());
+        let amount = if bytes {
+            quota::parse_size(amount).ok_or_else(invalid)?
+        } else {
+            amount.trim().parse().map_err(|_| invalid())?
+        };
+        if amount == 0 {
+            return Err(invalid());
+        }
+        let per_second = amount as f64 / seconds;
+
+        Ok(RateLimit {
+            bytes,
+            per_second,
+            tokens: per_second,
+            refilled_at: Instant::now(),
+            limiting: false,
+            limited_lines: 0,
+            limited_bytes: 0,
+        })
+    }
+}
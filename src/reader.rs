@@ -0,0 +1,504 @@
+use async_std::stream::Stream;
+
+use chrono::{DateTime, NaiveDateTime, TimeZone};
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+#[cfg(feature = "encryption")]
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+#[cfg(feature = "encryption")]
+use crate::encryption::{FileHeader, Key, Opener, Record, FILE_HEADER_BYTES, RECORD_HEADER_BYTES};
+use crate::rotation::{DirLayout, FileNameParser, FileTimestamp};
+use crate::runtime;
+
+/// Lines of a decompressed or decrypted file read at a time, away from the
+/// runtime's threads.
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "encryption"))]
+const BATCH_LINES: usize = 1024;
+
+type LineStream = Pin<Box<dyn Stream<Item = Result<String, io::Error>> + Send>>;
+type OpenFuture = Pin<Box<dyn Future<Output = Result<LineStream, io::Error>> + Send>>;
+
+/// How a rotated file was compressed, going by its extension.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Codec {
+    Plain,
+    Gzip,
+    Zstd,
+}
+
+/// One file of a channel, as found in the log directory.
+#[derive(Clone, Debug)]
+pub struct ChannelFile {
+    pub path: PathBuf,
+    /// Time the file name is stamped with.
+    pub opened_at: NaiveDateTime,
+    /// Files cut within the same second as the one before them are
+    /// numbered in their names, from 1.
+    pub seq: u32,
+    pub codec: Codec,
+    /// Whether the file is encrypted, `.enc`, compressed before it was.
+    pub encrypted: bool,
+}
+
+/// Reads a channel's history back from the files the router left in a log
+/// directory, whether still plain, compressed after rotation or encrypted,
+/// and whether in the log directory itself or sharded into subdirectories.
+pub struct ChannelReader {
+    log_dir: PathBuf,
+    parser: Box<dyn FileNameParser>,
+    dirs: Option<DirLayout>,
+    #[cfg(feature = "encryption")]
+    key: Option<Arc<Key>>,
+}
+
+impl ChannelReader {
+    /// Reader over `log_dir`, whose files are stamped in `timestamp` format.
+    pub fn new<P: Into<PathBuf>>(log_dir: P, timestamp: FileTimestamp) -> Self {
+        ChannelReader::with_parser(log_dir, timestamp)
+    }
+
+    /// Reader over a `log_dir` laid out by some other tool, its file names
+    /// read by `parser`.
+    pub fn with_parser<P, F>(log_dir: P, parser: F) -> Self
+    where
+        P: Into<PathBuf>,
+        F: FileNameParser + 'static,
+    {
+        ChannelReader {
+            log_dir: log_dir.into(),
+            parser: Box::new(parser),
+            dirs: None,
+            #[cfg(feature = "encryption")]
+            key: None,
+        }
+    }
+
+    /// Also looks for files in the subdirectories `dirs` shards them into,
+    /// as the router's `--dir-template` does.
+    pub fn with_dirs(mut self, dirs: DirLayout) -> Self {
+        self.dirs = Some(dirs);
+        self
+    }
+
+    /// Decrypts `.enc` files with `key`, which they can't be read without.
+    #[cfg(feature = "encryption")]
+    pub fn with_key(mut self, key: Key) -> Self {
+        self.key = Some(Arc::new(key));
+        self
+    }
+
+    /// Every file of `channel`, oldest first.
+    pub async fn files(&self, channel: &str) -> Result<Vec<ChannelFile>, io::Error> {
+        let mut files = Vec::new();
+
+        let mut dirs = vec![(self.log_dir.clone(), 0)];
+        while let Some((dir, depth)) = dirs.pop() {
+            for (name, path) in runtime::read_dir(&dir).await? {
+                let name = match name.to_str() {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let sharded = self
+                    .dirs
+                    .as_ref()
+                    .is_some_and(|dirs| dirs.matches(depth, name));
+                if sharded && runtime::is_dir(&path).await {
+                    dirs.push((path, depth + 1));
+                    continue;
+                }
+                // Files are written to the layout's last level, or were
+                // to the log directory itself before there was a layout.
+                let leaf = self.dirs.as_ref().map_or(0, DirLayout::depth);
+                if depth != 0 && depth != leaf {
+                    continue;
+                }
+
+                let (name, encrypted) = match name.strip_suffix(".enc") {
+                    Some(name) => (name, true),
+                    None => (name, false),
+                };
+                let (name, codec) = match (name.strip_suffix(".gz"), name.strip_suffix(".zst")) {
+                    (Some(name), _) => (name, Codec::Gzip),
+                    (_, Some(name)) => (name, Codec::Zstd),
+                    _ => (name, Codec::Plain),
+                };
+                if let Some(opened_at) = self.parser.parse(channel, name) {
+                    files.push(ChannelFile {
+                        path,
+                        opened_at,
+                        seq: self.parser.sequence(channel, name),
+                        codec,
+                        encrypted,
+                    });
+                }
+            }
+        }
+        // A file caught mid-compression is briefly there in both forms.
+        files.sort_by_key(|file| (file.opened_at, file.seq));
+        files.dedup_by_key(|file| (file.opened_at, file.seq));
+
+        Ok(files)
+    }
+
+    /// Lines of `channel` written between `from` and `to`, in order, given
+    /// in the zone the file names were stamped in. Lines carry no time of
+    /// their own, so whole files are read: every file whose period overlaps
+    /// the range, a period lasting until the next file was opened or its
+    /// period was up.
+    pub async fn range<Tz: TimeZone>(
+        &self,
+        channel: &str,
+        from: DateTime<Tz>,
+        to: DateTime<Tz>,
+    ) -> Result<Lines, io::Error> {
+        let (from, to) = (from.naive_local(), to.naive_local());
+        let files = self.files(channel).await?;
+
+        let mut selected = VecDeque::new();
+        for (index, file) in files.iter().enumerate() {
+            let period_end = self.parser.period_end(file.opened_at);
+            // Files cut within the same second share the time of the first.
+            let next = files[index + 1..]
+                .iter()
+                .find(|next| next.opened_at > file.opened_at);
+            let end = match next {
+                Some(next) => next.opened_at.min(period_end),
+                None => period_end,
+            };
+
+            if file.opened_at < to && end > from {
+                selected.push_back(file.clone());
+            }
+        }
+
+        Ok(Lines {
+            #[cfg(feature = "encryption")]
+            newest: files.last().map(|file| file.path.clone()),
+            files: selected,
+            #[cfg(feature = "encryption")]
+            key: self.key.clone(),
+            opening: None,
+            current: None,
+        })
+    }
+}
+
+/// Lines of a run of files, read one file after the other.
+pub struct Lines {
+    files: VecDeque<ChannelFile>,
+    /// The channel's newest file, which the router may still be writing.
+    #[cfg(feature = "encryption")]
+    newest: Option<PathBuf>,
+    #[cfg(feature = "encryption")]
+    key: Option<Arc<Key>>,
+    opening: Option<OpenFuture>,
+    current: Option<LineStream>,
+}
+
+impl Stream for Lines {
+    type Item = Result<String, io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(ref mut current) = self.current {
+                match current.as_mut().poll_next(cx) {
+                    Poll::Ready(None) => self.current = None,
+                    other => return other,
+                }
+            }
+
+            if let Some(ref mut opening) = self.opening {
+                let opened = match opening.as_mut().poll(cx) {
+                    Poll::Ready(opened) => opened,
+                    Poll::Pending => return Poll::Pending,
+                };
+                self.opening = None;
+                match opened {
+                    Ok(lines) => self.current = Some(lines),
+                    Err(error) => return Poll::Ready(Some(Err(error))),
+                }
+                continue;
+            }
+
+            match self.files.pop_front() {
+                #[cfg(feature = "encryption")]
+                Some(file) => {
+                    let open_ended = self.newest.as_ref() == Some(&file.path);
+                    self.opening = Some(Box::pin(open(file, self.key.clone(), open_ended)))
+                }
+                #[cfg(not(feature = "encryption"))]
+                Some(file) => self.opening = Some(Box::pin(open(file))),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "encryption")]
+async fn open(
+    file: ChannelFile,
+    key: Option<Arc<Key>>,
+    open_ended: bool,
+) -> Result<LineStream, io::Error> {
+    if file.codec == Codec::Plain && !file.encrypted {
+        return Ok(Box::pin(runtime::lines(&file.path).await?));
+    }
+
+    let key = key.ok_or_else(|| {
+        let message = format!("{} is encrypted, and no key was given", file.path.display());
+        io::Error::new(io::ErrorKind::InvalidInput, message)
+    });
+    let reader = runtime::unblock(move || {
+        let input = std::fs::File::open(&file.path)?;
+        match file.encrypted {
+            true => decoder(&file, Decrypted::new(input, key?, &file, open_ended)),
+            false => decoder(&file, input),
+        }
+    })
+    .await?;
+
+    Ok(Box::pin(Decoded::new(reader)))
+}
+
+#[cfg(not(feature = "encryption"))]
+async fn open(file: ChannelFile) -> Result<LineStream, io::Error> {
+    if file.encrypted {
+        return Err(unsupported(&file, "encryption"));
+    }
+    match file.codec {
+        Codec::Plain => Ok(Box::pin(runtime::lines(&file.path).await?)),
+        #[cfg(any(feature = "gzip", feature = "zstd"))]
+        _ => {
+            let reader = runtime::unblock(move || {
+                let input = std::fs::File::open(&file.path)?;
+                decoder(&file, input)
+            })
+            .await?;
+            Ok(Box::pin(Decoded::new(reader)))
+        }
+        #[cfg(not(any(feature = "gzip", feature = "zstd")))]
+        _ => Err(unsupported(&file, &format!("{:?}", file.codec))),
+    }
+}
+
+/// Lines of `input`, what `file` holds, decompressed as they are read.
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "encryption"))]
+fn decoder<R>(file: &ChannelFile, input: R) -> Result<Box<dyn io::BufRead + Send>, io::Error>
+where
+    R: io::Read + Send + 'static,
+{
+    use std::io::BufReader;
+
+    match file.codec {
+        Codec::Plain => Ok(Box::new(BufReader::new(input))),
+        #[cfg(feature = "gzip")]
+        Codec::Gzip => Ok(Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(
+            input,
+        )))),
+        #[cfg(feature = "zstd")]
+        Codec::Zstd => Ok(Box::new(BufReader::new(zstd::Decoder::new(input)?))),
+        #[cfg(not(all(feature = "gzip", feature = "zstd")))]
+        codec => Err(unsupported(file, &format!("{:?}", codec))),
+    }
+}
+
+/// Lines of a file read through a decoder, which blocks: a batch at a time
+/// away from the runtime's threads, so no more than a batch is held.
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "encryption"))]
+struct Decoded {
+    lines: VecDeque<String>,
+    reader: Option<Box<dyn io::BufRead + Send>>,
+    reading: Option<BatchFuture>,
+}
+
+/// A batch of lines, and the reader to go on with unless it is done.
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "encryption"))]
+type Batch = (VecDeque<String>, Option<Box<dyn io::BufRead + Send>>);
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "encryption"))]
+type BatchFuture = Pin<Box<dyn Future<Output = Result<Batch, io::Error>> + Send>>;
+
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "encryption"))]
+impl Decoded {
+    fn new(reader: Box<dyn io::BufRead + Send>) -> Self {
+        Decoded {
+            lines: VecDeque::new(),
+            reader: Some(reader),
+            reading: None,
+        }
+    }
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "encryption"))]
+impl Stream for Decoded {
+    type Item = Result<String, io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(line) = self.lines.pop_front() {
+                return Poll::Ready(Some(Ok(line)));
+            }
+
+            if let Some(ref mut reading) = self.reading {
+                let read = match reading.as_mut().poll(cx) {
+                    Poll::Ready(read) => read,
+                    Poll::Pending => return Poll::Pending,
+                };
+                self.reading = None;
+                match read {
+                    Ok((lines, reader)) => {
+                        self.lines = lines;
+                        self.reader = reader;
+                    }
+                    Err(error) => return Poll::Ready(Some(Err(error))),
+                }
+                continue;
+            }
+
+            match self.reader.take() {
+                Some(reader) => {
+                    self.reading = Some(Box::pin(runtime::unblock(|| read_batch(reader))))
+                }
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "encryption"))]
+fn read_batch(mut reader: Box<dyn io::BufRead + Send>) -> Result<Batch, io::Error> {
+    let mut lines = VecDeque::with_capacity(BATCH_LINES);
+    while lines.len() < BATCH_LINES {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok((lines, None));
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        lines.push_back(line);
+    }
+
+    Ok((lines, Some(reader)))
+}
+
+/// Plaintext of an encrypted file, each record authenticated as it is
+/// reached; a file cut short, or tampered with, fails once its last whole
+/// record is read. An `open_ended` file, which may still be written, is
+/// read up to its last whole record even without the file's last.
+#[cfg(feature = "encryption")]
+struct Decrypted<R> {
+    input: R,
+    key: Arc<Key>,
+    path: PathBuf,
+    open_ended: bool,
+    opener: Option<Opener>,
+    plaintext: Vec<u8>,
+    read: usize,
+}
+
+#[cfg(feature = "encryption")]
+impl<R: io::Read> Decrypted<R> {
+    fn new(input: R, key: Arc<Key>, file: &ChannelFile, open_ended: bool) -> Self {
+        Decrypted {
+            input,
+            key,
+            path: file.path.clone(),
+            open_ended,
+            opener: None,
+            plaintext: Vec::new(),
+            read: 0,
+        }
+    }
+
+    fn invalid(&self, e: String) -> io::Error {
+        let message = format!("{}: {}", self.path.display(), e);
+        io::Error::new(io::ErrorKind::InvalidData, message)
+    }
+
+    /// The next record's plaintext, `false` once the file ends between
+    /// records.
+    fn next_record(&mut self) -> Result<bool, io::Error> {
+        use std::io::Read;
+
+        if self.opener.is_none() {
+            let mut header = Vec::with_capacity(FILE_HEADER_BYTES);
+            (&mut self.input)
+                .take(FILE_HEADER_BYTES as u64)
+                .read_to_end(&mut header)?;
+            // A file just opened, which nothing was sealed into yet.
+            if header.is_empty() && self.open_ended {
+                return Ok(false);
+            }
+            let header = FileHeader::parse(&header)
+                .map_err(|e| self.invalid(e))?
+                .ok_or_else(|| self.invalid(String::from("header cut short")))?;
+            self.opener = Some(Opener::new(&header));
+        }
+
+        let mut record = Vec::with_capacity(RECORD_HEADER_BYTES);
+        (&mut self.input)
+            .take(RECORD_HEADER_BYTES as u64)
+            .read_to_end(&mut record)?;
+        if record.is_empty() {
+            if !self.open_ended {
+                if let Some(ref opener) = self.opener {
+                    opener.finish().map_err(|e| self.invalid(e))?;
+                }
+            }
+            return Ok(false);
+        }
+        if record.len() == RECORD_HEADER_BYTES {
+            let length = u32::from_be_bytes([record[4], record[5], record[6], record[7]]);
+            (&mut self.input)
+                .take(u64::from(length))
+                .read_to_end(&mut record)?;
+        }
+
+        let (record, _) = Record::parse(&record)
+            .map_err(|e| self.invalid(e))?
+            .ok_or_else(|| self.invalid(String::from("record cut short")))?;
+        let opener = self.opener.as_mut().expect("header read before records");
+        let plaintext = opener.open(&self.key, &record);
+        self.plaintext = plaintext.map_err(|e| self.invalid(e))?;
+        self.read = 0;
+        Ok(true)
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl<R: io::Read> io::Read for Decrypted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read == self.plaintext.len() {
+            if !self.next_record()? {
+                return Ok(0);
+            }
+        }
+
+        let unread = &self.plaintext[self.read..];
+        let read = unread.len().min(buf.len());
+        buf[..read].copy_from_slice(&unread[..read]);
+        self.read += read;
+        Ok(read)
+    }
+}
+
+#[cfg(not(all(feature = "gzip", feature = "zstd", feature = "encryption")))]
+fn unsupported(file: &ChannelFile, support: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "{} support isn't built in for {}",
+            support,
+            file.path.display()
+        ),
+    )
+}
@@ -0,0 +1,76 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use log_revolve_rs::platform;
+
+/// The last lines written to each channel, for a look at very recent traffic
+/// through the admin API without finding and opening the current file.
+pub struct RecentLines {
+    capacity: usize,
+    channels: BTreeMap<String, VecDeque<String>>,
+}
+
+impl RecentLines {
+    pub fn new(capacity: usize) -> Self {
+        RecentLines {
+            capacity,
+            channels: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, channel: &str, line: &str) {
+        let capacity = self.capacity;
+        let lines = match self.channels.get_mut(channel) {
+            Some(lines) => lines,
+            None => self
+                .channels
+                .entry(channel.to_string())
+                .or_insert_with(|| VecDeque::with_capacity(capacity)),
+        };
+        if lines.len() == capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line.to_string());
+    }
+
+    /// Up to `count` of the channel's last lines, oldest first and without
+    /// their line ends.
+    pub fn last(&self, channel: &str, count: usize) -> Vec<&str> {
+        let lines = match self.channels.get(channel) {
+            Some(lines) => lines,
+            None => return Vec::new(),
+        };
+
+        lines
+            .iter()
+            .skip(lines.len().saturating_sub(count))
+            .map(|line| platform::strip_line_ending(line))
+            .collect()
+    }
+
+    pub fn forget(&mut self, channel: &str) {
+        self.channels.remove(channel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channels_keep_their_last_lines() {
+        let mut recent = RecentLines::new(3);
+        for line in ["a\n", "b\r\n", "c\n", "d\n"] {
+            recent.record("web", line);
+        }
+        recent.record("db", "SELECT 1\n");
+
+        assert_eq!(recent.last("web", 10), vec!["b", "c", "d"]);
+        assert_eq!(recent.last("web", 2), vec!["c", "d"]);
+        assert_eq!(recent.last("db", 0), Vec::<&str>::new());
+        assert_eq!(recent.last("app", 10), Vec::<&str>::new());
+
+        recent.forget("web");
+        assert_eq!(recent.last("web", 10), Vec::<&str>::new());
+        assert_eq!(recent.last("db", 10), vec!["SELECT 1"]);
+    }
+}
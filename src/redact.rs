@@ -0,0 +1,151 @@
+use regex::Regex;
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// A change made to each line before it is written.
+pub trait Transform: Send + Sync {
+    fn apply<'a>(&self, line: &'a str) -> Cow<'a, str>;
+}
+
+/// Replaces every match of a pattern, written sed-style as
+/// `s/<pattern>/<replacement>/`, e.g. `s/\d{16}/****/`. Any character can
+/// stand in for `/`, escaped with a backslash where the pattern or the
+/// replacement holds it; the replacement can refer to groups as `$1`.
+#[derive(Clone, Debug)]
+pub struct Redaction {
+    pattern: Regex,
+    replacement: String,
+}
+
+impl FromStr for Redaction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "expected a redaction such as `s/\\d{{16}}/****/`, got `{}`",
+                s
+            )
+        };
+
+        let mut chars = s.chars();
+        if chars.next() != Some('s') {
+            return Err(invalid());
+        }
+        let delimiter = chars.next().ok_or_else(invalid)?;
+
+        let mut parts = vec![String::new()];
+        while let Some(c) = chars.next() {
+            let part = parts.last_mut().unwrap();
+            match c {
+                '\\' => match chars.next() {
+                    Some(next) if next == delimiter => part.push(next),
+                    Some(next) => {
+                        part.push('\\');
+                        part.push(next);
+                    }
+                    None => return Err(invalid()),
+                },
+                c if c == delimiter => parts.push(String::new()),
+                c => part.push(c),
+            }
+        }
+        // The closing delimiter leaves an empty part behind it.
+        if parts.len() != 3 || !parts[2].is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Redaction {
+            pattern: Regex::new(&parts[0]).map_err(|e| e.to_string())?,
+            replacement: parts.swap_remove(1),
+        })
+    }
+}
+
+impl Transform for Redaction {
+    fn apply<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        self.pattern.replace_all(line, self.replacement.as_str())
+    }
+}
+
+/// A redaction applying to a single channel, `<channel>=s/<pattern>/<replacement>/`.
+#[derive(Clone, Debug)]
+pub struct ChannelRedaction {
+    channel: String,
+    redaction: Redaction,
+}
+
+impl FromStr for ChannelRedaction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (channel, redaction) = s
+            .split_once('=')
+            .filter(|(channel, _)| !channel.is_empty())
+            .ok_or_else(|| {
+                format!(
+                    "expected `<channel>=s/<pattern>/<replacement>/`, got `{}`",
+                    s
+                )
+            })?;
+
+        Ok(ChannelRedaction {
+            channel: channel.to_string(),
+            redaction: redaction.parse()?,
+        })
+    }
+}
+
+/// The transforms every line goes through before it is written, the
+/// channel's own after those of every channel, each handed what the one
+/// before it made of the line.
+#[derive(Default)]
+pub struct Transforms {
+    global: Vec<Box<dyn Transform>>,
+    channels: BTreeMap<String, Vec<Box<dyn Transform>>>,
+}
+
+impl Transforms {
+    pub fn new(global: &[Redaction], channels: &[ChannelRedaction]) -> Self {
+        let mut transforms = Transforms::default();
+        for redaction in global {
+            transforms.global.push(Box::new(redaction.clone()));
+        }
+        for redaction in channels {
+            transforms.add(&redaction.channel, redaction.redaction.clone());
+        }
+
+        transforms
+    }
+
+    pub fn add<T: Transform + 'static>(&mut self, channel: &str, transform: T) {
+        self.channels
+            .entry(channel.to_string())
+            .or_default()
+            .push(Box::new(transform));
+    }
+
+    /// `line` of `channel`, whose settings are those of `settings_name`,
+    /// as it is written.
+    pub fn apply<'a>(&self, channel: &str, settings_name: &str, line: &'a str) -> Cow<'a, str> {
+        let own = self
+            .channels
+            .get(channel)
+            .or_else(|| self.channels.get(settings_name));
+
+        self.global.iter().chain(own.into_iter().flatten()).fold(
+            Cow::Borrowed(line),
+            |line, transform| match transform.apply(&line) {
+                Cow::Borrowed(_) => line,
+                Cow::Owned(changed) => Cow::Owned(changed),
+            },
+        )
+    }
+
+    /// `line`, sent to no channel, through the transforms of every channel.
+    pub fn apply_global<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        self.apply("", "", line)
+    }
+}
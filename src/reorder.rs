@@ -0,0 +1,223 @@
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+use chrono::{DateTime, Local};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+use crate::{report, FileWriter};
+
+/// Holds the lines of channels fed by several inputs for a short window and
+/// lets them go in the order of the timestamp each starts with, so a merged
+/// file comes out sorted. A line arriving later than the window after those
+/// it should precede is written where it arrives.
+pub struct Reorderer {
+    window: Duration,
+    /// Channels reordered, all of them when empty.
+    channels: BTreeSet<String>,
+    held: BTreeMap<String, ChannelLines>,
+    pub bytes: usize,
+}
+
+#[derive(Default)]
+struct ChannelLines {
+    /// Lines by timestamp in nanoseconds, then arrival.
+    lines: BTreeMap<(i64, u64), HeldLine>,
+    arrivals: u64,
+    /// Timestamp of the latest line, given to lines without one of their own
+    /// so they stay behind the line they followed.
+    latest: Option<i64>,
+}
+
+struct HeldLine {
+    arrived_at: Instant,
+    message: String,
+}
+
+impl Reorderer {
+    pub fn new(window: Duration, channels: BTreeSet<String>) -> Self {
+        Reorderer {
+            window,
+            channels,
+            held: BTreeMap::new(),
+            bytes: 0,
+        }
+    }
+
+    pub fn covers(&self, channel: &str) -> bool {
+        self.channels.is_empty() || self.channels.contains(channel)
+    }
+
+    pub fn hold(&mut self, channel: &str, message: &str) {
+        let held = self.held.entry(channel.to_string()).or_default();
+        let timestamp = match timestamp_of(message) {
+            Some(timestamp) => {
+                held.latest = Some(
+                    held.latest
+                        .map_or(timestamp, |latest| latest.max(timestamp)),
+                );
+                timestamp
+            }
+            None => held
+                .latest
+                .unwrap_or_else(|| Local::now().timestamp_nanos_opt().unwrap_or(i64::MAX)),
+        };
+
+        held.arrivals += 1;
+        held.lines.insert(
+            (timestamp, held.arrivals),
+            HeldLine {
+                arrived_at: Instant::now(),
+                message: message.to_string(),
+            },
+        );
+        self.bytes += message.len();
+    }
+
+    /// Lines whose turn has come, in order: the earliest line of a channel
+    /// goes once it has been held for the window, or right away when
+    /// `everything` is asked for.
+    pub fn release(&mut self, everything: bool) -> Vec<(String, String)> {
+        let now = Instant::now();
+        let mut released = Vec::new();
+
+        for (channel, held) in self.held.iter_mut() {
+            while let Some(entry) = held.lines.first_entry() {
+                if !everything && now.duration_since(entry.get().arrived_at) < self.window {
+                    break;
+                }
+                let line = entry.remove();
+                self.bytes -= line.message.len();
+                released.push((channel.clone(), line.message));
+            }
+        }
+        self.held.retain(|_, held| !held.lines.is_empty());
+
+        released
+    }
+}
+
+/// Nanoseconds since the epoch of the RFC 3339 timestamp a line starts with,
+/// bracketed or not.
+fn timestamp_of(message: &str) -> Option<i64> {
+    let word = message.split_whitespace().next()?;
+    let word = word.trim_start_matches('[').trim_end_matches(']');
+
+    DateTime::parse_from_rfc3339(word)
+        .ok()
+        .and_then(|timestamp| timestamp.timestamp_nanos_opt())
+}
+
+/// Writes out lines held for reordering as their window passes, while no
+/// new line comes in to do so.
+pub async fn release_every(writer: Arc<Mutex<FileWriter>>, interval: Duration) {
+    loop {
+        task::sleep(interval).await;
+
+        let mut writer = writer.lock().await;
+        if let Err(error) = writer.release_reordered(false).await {
+            tracing::warn!("unable to write reordered lines: {}", error);
+            report::record_error("reorder", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{self, LogDir};
+
+    fn messages(released: Vec<(String, String)>) -> Vec<String> {
+        released.into_iter().map(|(_, message)| message).collect()
+    }
+
+    #[test]
+    fn lines_start_with_their_timestamp_bracketed_or_not() {
+        assert_eq!(
+            timestamp_of("1970-01-01T00:00:01Z GET /a\n"),
+            Some(1_000_000_000)
+        );
+        assert_eq!(
+            timestamp_of("[1970-01-01T01:00:00.5+01:00] GET /a\n"),
+            Some(500_000_000)
+        );
+        assert_eq!(timestamp_of("GET /a 1970-01-01T00:00:01Z\n"), None);
+        assert_eq!(timestamp_of("\n"), None);
+    }
+
+    #[test]
+    fn held_lines_come_out_sorted_by_timestamp() {
+        let mut reorderer = Reorderer::new(Duration::from_secs(60), BTreeSet::new());
+        reorderer.hold("web", "2024-06-01T13:00:02Z second\n");
+        reorderer.hold("web", "2024-06-01T13:00:01Z first\n");
+        reorderer.hold("web", "  continued\n");
+        reorderer.hold("web", "2024-06-01T13:00:01Z first too\n");
+        assert_eq!(reorderer.bytes, 98);
+
+        // Still within the window.
+        assert!(reorderer.release(false).is_empty());
+        assert_eq!(
+            messages(reorderer.release(true)),
+            vec![
+                "2024-06-01T13:00:01Z first\n",
+                "2024-06-01T13:00:01Z first too\n",
+                "2024-06-01T13:00:02Z second\n",
+                "  continued\n",
+            ]
+        );
+        assert_eq!(reorderer.bytes, 0);
+    }
+
+    #[test]
+    fn lines_go_once_held_for_the_window() {
+        let mut reorderer = Reorderer::new(Duration::from_millis(20), BTreeSet::new());
+        reorderer.hold("web", "2024-06-01T13:00:01Z early\n");
+        std::thread::sleep(Duration::from_millis(30));
+        reorderer.hold("web", "2024-06-01T13:00:02Z late\n");
+
+        assert_eq!(
+            messages(reorderer.release(false)),
+            vec!["2024-06-01T13:00:01Z early\n"]
+        );
+        assert_eq!(reorderer.bytes, 26);
+    }
+
+    #[test]
+    fn only_the_channels_named_are_reordered() {
+        let everything = Reorderer::new(Duration::from_secs(1), BTreeSet::new());
+        assert!(everything.covers("web"));
+
+        let some = Reorderer::new(Duration::from_secs(1), ["web".to_string()].into());
+        assert!(some.covers("web"));
+        assert!(!some.covers("db"));
+    }
+
+    #[test]
+    fn merged_files_come_out_sorted() {
+        task::block_on(async {
+            let dir = LogDir::new("reorder");
+            let args = [
+                "--accepted-log-channels",
+                "web",
+                "--reorder-window",
+                "60000",
+                "--flush-bytes",
+                "0",
+            ];
+            let mut writer = testing::writer(&dir, &args).await;
+            for line in ["2024-06-01T13:00:02Z b\n", "2024-06-01T13:00:01Z a\n"] {
+                writer.write_to_channel("", "web", line).await.unwrap();
+            }
+            assert_eq!(dir.read("web_"), "");
+
+            writer.release_reordered(true).await.unwrap();
+            writer.sync_all().await.unwrap();
+            assert_eq!(
+                dir.read("web_"),
+                "2024-06-01T13:00:01Z a\n2024-06-01T13:00:02Z b\n"
+            );
+        });
+    }
+}
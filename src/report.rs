@@ -0,0 +1,169 @@
+use chrono::{DateTime, Local};
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::Mutex;
+
+#[cfg(feature = "report")]
+use async_std::io;
+#[cfg(feature = "report")]
+use serde_json::{json, Map, Value};
+
+#[cfg(feature = "report")]
+use crate::{FileHandle, FileWriter};
+
+/// Most recent error of every subsystem, for the shutdown report.
+static LAST_ERRORS: Mutex<BTreeMap<String, (String, DateTime<Local>)>> =
+    Mutex::new(BTreeMap::new());
+
+/// Bytes left in memory by file, or by paused channel, when shutdown gave up
+/// on writing them, for the shutdown report.
+static UNFLUSHED: Mutex<BTreeMap<String, usize>> = Mutex::new(BTreeMap::new());
+
+/// Errors of every subsystem since startup, for metrics.
+static ERROR_COUNTS: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+
+pub fn record_error<E: Display>(subsystem: &str, error: E) {
+    if let Ok(mut errors) = LAST_ERRORS.lock() {
+        errors.insert(subsystem.to_string(), (error.to_string(), Local::now()));
+    }
+    if let Ok(mut counts) = ERROR_COUNTS.lock() {
+        *counts.entry(subsystem.to_string()).or_default() += 1;
+    }
+}
+
+pub fn record_unflushed(name: &str, bytes: usize) {
+    if let Ok(mut unflushed) = UNFLUSHED.lock() {
+        unflushed.insert(name.to_string(), bytes);
+    }
+}
+
+#[cfg(feature = "metrics")]
+pub fn error_counts() -> BTreeMap<String, u64> {
+    match ERROR_COUNTS.lock() {
+        Ok(counts) => counts.clone(),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+/// Writes a summary of the run to `path` as it ends, whether cleanly or not,
+/// so a restarted collector leaves more behind than its last log lines. The
+/// channels are left out without the `writer`, still busy when shutdown gave
+/// up on it.
+#[cfg(feature = "report")]
+pub async fn write(
+    path: &str,
+    writer: Option<&FileWriter>,
+    started_at: DateTime<Local>,
+    outcome: &Result<(), io::Error>,
+) -> Result<(), io::Error> {
+    let last_errors: Map<String, Value> = match LAST_ERRORS.lock() {
+        Ok(errors) => errors
+            .iter()
+            .map(|(subsystem, (error, at))| {
+                let error = json!({ "error": error, "at": at.to_rfc3339() });
+                (subsystem.clone(), error)
+            })
+            .collect(),
+        Err(_) => Map::new(),
+    };
+
+    let unflushed: Map<String, Value> = match UNFLUSHED.lock() {
+        Ok(unflushed) => unflushed
+            .iter()
+            .map(|(name, bytes)| (name.clone(), Value::from(*bytes)))
+            .collect(),
+        Err(_) => Map::new(),
+    };
+
+    let mut report = json!({
+        "pid": std::process::id(),
+        "version": env!("CARGO_PKG_VERSION"),
+        "started_at": started_at.to_rfc3339(),
+        "stopped_at": Local::now().to_rfc3339(),
+        "outcome": match outcome {
+            Ok(()) => Value::from("ok"),
+            Err(error) => Value::from(error.to_string()),
+        },
+        "last_errors": last_errors,
+    });
+    if !unflushed.is_empty() {
+        report["unflushed_at_timeout"] = Value::from(unflushed);
+    }
+
+    if let Some(writer) = writer {
+        add_writer(&mut report, writer).await;
+    }
+
+    // Written aside and renamed, so a reader never sees half a report.
+    let partial = format!("{}.partial", path);
+    let mut contents = report.to_string();
+    contents.push('\n');
+    async_std::fs::write(&partial, contents).await?;
+    async_std::fs::rename(&partial, path).await
+}
+
+/// Adds what the writer knows of the channels and sinks to `report`.
+#[cfg(feature = "report")]
+async fn add_writer(report: &mut Value, writer: &FileWriter) {
+    let channels: Map<String, Value> = writer
+        .handles()
+        .map(|(name, handle)| (name.to_string(), channel_report(name, handle, writer)))
+        .collect();
+    report["channels"] = Value::from(channels);
+    report["inapt"] = channel_report(
+        &writer.inapt_file_handle.file_name,
+        &writer.inapt_file_handle,
+        writer,
+    );
+
+    if let Some(ref budget) = writer.memory_budget {
+        report["memory"] = json!({
+            "budget_bytes": budget.limit,
+            "dropped_lines": budget.dropped_lines,
+        });
+    }
+
+    #[cfg(feature = "gelf")]
+    if let Some(ref gelf_sink) = writer.gelf_sink {
+        report["gelf"] = json!({
+            "dropped_lines": gelf_sink.dropped_lines(),
+            "spill_dropped_bytes": gelf_sink.spill_dropped_bytes().await,
+        });
+    }
+
+    #[cfg(feature = "kafka")]
+    if let Some(ref kafka_sink) = writer.kafka_sink {
+        report["kafka"] = json!({
+            "produced_lines": kafka_sink.produced_lines(),
+            "fallback_lines": kafka_sink.fallback_lines(),
+            "dropped_lines": kafka_sink.dropped_lines(),
+        });
+    }
+}
+
+#[cfg(feature = "report")]
+fn channel_report(name: &str, handle: &FileHandle, writer: &FileWriter) -> Value {
+    let paused_bytes = writer
+        .paused_channels
+        .get(name)
+        .map_or(0, |pause| pause.buffered_bytes);
+
+    let mut report = json!({
+        "lines": handle.lines_written,
+        "bytes": handle.bytes_written,
+        "dropped_lines": handle.dropped_lines,
+        "unflushed_bytes": handle.memory_bytes() + paused_bytes,
+        "path": handle.current_path,
+    });
+
+    if let Some(pause) = writer.paused_channels.get(name) {
+        report["paused_rejected_lines"] = json!(pause.rejected);
+    }
+    if let Some(quota) = writer.quotas.get(name) {
+        report["quota_overflow_lines"] = json!(quota.overflow_lines);
+        report["quota_overflow_bytes"] = json!(quota.overflow_bytes);
+    }
+
+    report
+}
@@ -0,0 +1,274 @@
+use async_std::fs;
+use async_std::io;
+use async_std::path::{Path, PathBuf};
+use async_std::prelude::*;
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+use chrono::{Duration, NaiveDateTime};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex as SyncMutex;
+
+use log_revolve_rs::rotation::{DirLayout, FileNameParser};
+
+use crate::zone::Zone;
+use crate::{report, FileWriter};
+
+/// How often the log directory is pruned.
+const PRUNE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// How often it is pruned under a total budget, which a busy router can
+/// outgrow well within `PRUNE_INTERVAL`.
+const BUDGET_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// What was removed to keep the log directory within its total budget,
+/// `None` without one.
+static OVER_BUDGET: SyncMutex<Option<Removed>> = SyncMutex::new(None);
+
+/// How long rotated files of each channel are kept in the log directory: no
+/// longer than `max_age` after the time in their name, and no more than
+/// `max_files` of them counting the current one. On top of that, the oldest
+/// files of every channel go until all of them, current ones included, take
+/// no more than `max_total_bytes`. Files still written to, files being
+/// compressed and files whose names don't parse are left alone.
+#[derive(Clone)]
+pub struct Retention {
+    /// Zone the times in file names are read in.
+    pub zone: Zone,
+    /// How the times are read out of file names: `--file-timestamp`, or
+    /// `--file-name-template`.
+    pub parser: Arc<dyn FileNameParser>,
+    /// Subdirectories files are sharded into, looked through for files and
+    /// removed once emptied.
+    pub dirs: Option<DirLayout>,
+    pub max_age: Option<Duration>,
+    pub max_files: Option<usize>,
+    pub max_total_bytes: Option<u64>,
+}
+
+/// Files removed over the total budget, and the bytes they took.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Removed {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl Retention {
+    /// Removes the files of `log_dir` past retention, except the `current`
+    /// paths, returning how many went. Files are told apart by `file_names`,
+    /// the names channels' files are written under, or by the channel a
+    /// default name starts with.
+    pub async fn prune(
+        &self,
+        log_dir: &str,
+        file_names: &BTreeSet<String>,
+        current: &BTreeSet<String>,
+    ) -> Result<usize, io::Error> {
+        let names = self.file_names(log_dir).await?;
+
+        let mut channels: BTreeMap<&str, Vec<(NaiveDateTime, u32, &str)>> = BTreeMap::new();
+        for name in names.iter() {
+            let file_name = Path::new(name)
+                .file_name()
+                .and_then(|file_name| file_name.to_str())
+                .unwrap_or(name);
+            if let Some((channel, opened_at, seq)) =
+                stamped(self.parser.as_ref(), file_names, file_name)
+            {
+                channels
+                    .entry(channel)
+                    .or_default()
+                    .push((opened_at, seq, name));
+            }
+        }
+
+        let in_use = |name: &str| {
+            let path = Path::new(log_dir).join(name);
+            path.to_str().is_some_and(|path| current.contains(path))
+                || names.contains(&format!("{}.gz.partial", name))
+                || names.contains(&format!("{}.zst.partial", name))
+        };
+        let oldest = self
+            .max_age
+            .map(|max_age| self.zone.now().naive_local() - max_age);
+        let mut kept = Vec::new();
+        let mut removed = 0;
+        let mut emptied = BTreeSet::new();
+        for files in channels.values_mut() {
+            // Newest first.
+            files.sort_unstable_by(|a, b| b.cmp(a));
+            for (index, file) in files.iter().enumerate() {
+                let (opened_at, _, name) = *file;
+                let expired = oldest.is_some_and(|oldest| opened_at < oldest)
+                    || self.max_files.is_some_and(|max_files| index >= max_files);
+                if expired && !in_use(name) {
+                    remove_file(&Path::new(log_dir).join(name)).await?;
+                    emptied.extend(Path::new(name).parent().map(Path::to_path_buf));
+                    removed += 1;
+                } else {
+                    kept.push(*file);
+                }
+            }
+        }
+
+        let max_total_bytes = match self.max_total_bytes {
+            Some(max_total_bytes) => max_total_bytes,
+            None => {
+                remove_empty_dirs(log_dir, emptied).await;
+                return Ok(removed);
+            }
+        };
+        let mut sizes = Vec::with_capacity(kept.len());
+        for (_, _, name) in kept.iter() {
+            sizes.push(fs::metadata(Path::new(log_dir).join(name)).await?.len());
+        }
+        let mut total: u64 = sizes.iter().sum();
+        let mut files: Vec<_> = kept.into_iter().zip(sizes).collect();
+        // Oldest first, whatever their channel.
+        files.sort_unstable();
+        for ((_, _, name), size) in files {
+            if total <= max_total_bytes {
+                break;
+            }
+            if in_use(name) {
+                continue;
+            }
+
+            remove_file(&Path::new(log_dir).join(name)).await?;
+            emptied.extend(Path::new(name).parent().map(Path::to_path_buf));
+            total -= size;
+            removed += 1;
+            let mut over_budget = OVER_BUDGET.lock().unwrap();
+            let over_budget = over_budget.get_or_insert_with(Removed::default);
+            over_budget.files += 1;
+            over_budget.bytes += size;
+        }
+        if total > max_total_bytes {
+            tracing::warn!(
+                "{} holds {} bytes of files still in use, over its budget of {}",
+                log_dir,
+                total,
+                max_total_bytes
+            );
+        }
+        remove_empty_dirs(log_dir, emptied).await;
+
+        Ok(removed)
+    }
+
+    /// Paths, relative to `log_dir`, of the files in it and, under a
+    /// directory layout, in the subdirectories along it.
+    async fn file_names(&self, log_dir: &str) -> Result<BTreeSet<String>, io::Error> {
+        let mut names = BTreeSet::new();
+        let mut dirs = vec![(PathBuf::new(), 0)];
+        while let Some((dir, depth)) = dirs.pop() {
+            let mut entries = fs::read_dir(Path::new(log_dir).join(&dir)).await?;
+            while let Some(entry) = entries.next().await {
+                let entry = entry?;
+                let file_type = entry.file_type().await?;
+                let name = match entry.file_name().into_string() {
+                    Ok(name) => name,
+                    Err(_) => continue,
+                };
+                let sharded = self
+                    .dirs
+                    .as_ref()
+                    .is_some_and(|dirs| dirs.matches(depth, &name));
+                if file_type.is_file() {
+                    names.extend(dir.join(&name).into_os_string().into_string());
+                } else if file_type.is_dir() && sharded {
+                    dirs.push((dir.join(&name), depth + 1));
+                }
+            }
+        }
+
+        Ok(names)
+    }
+}
+
+/// Removes the `emptied` subdirectories of `log_dir` files were removed
+/// from, and those above them, for as long as they are left empty.
+async fn remove_empty_dirs(log_dir: &str, emptied: BTreeSet<PathBuf>) {
+    for dir in emptied {
+        let mut dir = Some(dir.as_path());
+        while let Some(subdir) = dir.filter(|dir| !dir.as_os_str().is_empty()) {
+            if fs::remove_dir(Path::new(log_dir).join(subdir))
+                .await
+                .is_err()
+            {
+                break;
+            }
+            dir = subdir.parent();
+        }
+    }
+}
+
+/// What was removed to keep the log directory within `--max-total-disk`,
+/// `None` without it.
+#[cfg(feature = "metrics")]
+pub fn removed_over_budget() -> Option<Removed> {
+    *OVER_BUDGET.lock().unwrap()
+}
+
+/// Removes the file at `path` along with its checksum sidecar, if it has one.
+pub async fn remove_file(path: &Path) -> Result<(), io::Error> {
+    fs::remove_file(path).await?;
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".sha256");
+    match fs::remove_file(Path::new(&sidecar)).await {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// The channel, opening time and sequence number of a file named by the
+/// router, compressed, encrypted or not: a channel of `channels` whose names
+/// `parser` reads it as, or the one a default name starts with.
+pub fn stamped<'a>(
+    parser: &dyn FileNameParser,
+    channels: &'a BTreeSet<String>,
+    file_name: &'a str,
+) -> Option<(&'a str, NaiveDateTime, u32)> {
+    let file_name = file_name.strip_suffix(".enc").unwrap_or(file_name);
+    let log_name = file_name
+        .strip_suffix(".gz")
+        .or_else(|| file_name.strip_suffix(".zst"))
+        .unwrap_or(file_name);
+    let named = log_name.rsplit_once('_').map(|(channel, _)| channel);
+
+    named
+        .into_iter()
+        .chain(channels.iter().map(String::as_str))
+        .find_map(|channel| {
+            let opened_at = parser.parse(channel, log_name)?;
+            Some((channel, opened_at, parser.sequence(channel, log_name)))
+        })
+}
+
+pub async fn prune_every(writer: Arc<Mutex<FileWriter>>, log_dir: String, retention: Retention) {
+    if retention.max_total_bytes.is_some() {
+        *OVER_BUDGET.lock().unwrap() = Some(Removed::default());
+    }
+    let interval = match retention.max_total_bytes {
+        Some(_) => BUDGET_INTERVAL,
+        None => PRUNE_INTERVAL,
+    };
+    loop {
+        let (file_names, current) = {
+            let mut writer = writer.lock().await;
+            (writer.file_names(), writer.current_paths())
+        };
+
+        match retention.prune(&log_dir, &file_names, &current).await {
+            Ok(0) => {}
+            Ok(removed) => tracing::info!("removed {} files past retention", removed),
+            Err(error) => {
+                tracing::warn!("unable to prune the log directory: {}", error);
+                report::record_error("retention", error);
+            }
+        }
+
+        task::sleep(interval).await;
+    }
+}
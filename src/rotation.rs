@@ -0,0 +1,639 @@
+use chrono::format::{self, Parsed, StrftimeItems};
+use chrono::{
+    DateTime, Datelike, Duration, LocalResult, NaiveDate, NaiveDateTime, Offset, TimeZone, Timelike,
+};
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::platform::Platform;
+
+/// How file names are stamped with the time their file was opened.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FileTimestamp {
+    /// `2024-06-01-13-00-00`
+    Seconds,
+    /// `2024-06-01-13`
+    Hours,
+    /// `2024-06-01T13:00:00`
+    Rfc3339Seconds,
+    /// `2024-06-01T13`
+    Rfc3339Hours,
+}
+
+impl FileTimestamp {
+    pub fn format(self) -> &'static str {
+        match self {
+            FileTimestamp::Seconds => "%Y-%m-%d-%H-%M-%S",
+            FileTimestamp::Hours => "%Y-%m-%d-%H",
+            FileTimestamp::Rfc3339Seconds => "%Y-%m-%dT%H:%M:%S",
+            FileTimestamp::Rfc3339Hours => "%Y-%m-%dT%H",
+        }
+    }
+
+    /// chrono won't parse a time without its minutes, so an hours stamp is
+    /// padded out to the matching seconds format first.
+    fn padding(self) -> (FileTimestamp, &'static str) {
+        match self {
+            FileTimestamp::Hours => (FileTimestamp::Seconds, "-00-00"),
+            FileTimestamp::Rfc3339Hours => (FileTimestamp::Rfc3339Seconds, ":00:00"),
+            other => (other, ""),
+        }
+    }
+}
+
+impl FromStr for FileTimestamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "seconds" => Ok(FileTimestamp::Seconds),
+            "hours" => Ok(FileTimestamp::Hours),
+            "rfc3339-seconds" => Ok(FileTimestamp::Rfc3339Seconds),
+            "rfc3339-hours" => Ok(FileTimestamp::Rfc3339Hours),
+            _ => Err(format!("unknown file timestamp format: {}", s)),
+        }
+    }
+}
+
+/// Who decides when a channel's file is replaced.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Rotation {
+    /// A file per hour, stamped with the time it was opened.
+    Hourly(FileTimestamp),
+    /// A file per period, e.g. 5 minutes or a day, aligned to the period's
+    /// boundaries counted from midnight. Periods divide a day evenly.
+    Periodic(FileTimestamp, Duration),
+    /// A single `<channel>.log`, renamed by a tool such as logrotate and
+    /// reopened on SIGHUP.
+    External,
+}
+
+/// Which file of a channel is current. The clock is never read here; every
+/// time is handed in by the caller. File names only ever move forward: a
+/// clock set back keeps lines in the current file rather than reopening an
+/// earlier one.
+#[derive(Clone, Debug)]
+pub struct Schedule<Tz: TimeZone> {
+    pub rotation: Rotation,
+    /// Time the current file is stamped with, the latest any of the
+    /// channel's files was.
+    pub opened_at: DateTime<Tz>,
+    /// Next period boundary; the file is replaced by the first line written
+    /// after it. `None` when rotation is left to an external tool.
+    pub due: Option<DateTime<Tz>>,
+    /// Files cut ahead of the schedule since the period began.
+    pub seq: u32,
+}
+
+impl<Tz: TimeZone> Schedule<Tz> {
+    pub fn new(rotation: Rotation, now: &DateTime<Tz>) -> Self {
+        let opened_at = period_start(rotation, now);
+
+        Schedule {
+            rotation,
+            due: rotation_due_after(rotation, &opened_at),
+            opened_at,
+            seq: 0,
+        }
+    }
+
+    /// Whether a line written at `now` belongs in a new file, the next
+    /// period having begun. A clock set back before the current period
+    /// doesn't make one due.
+    pub fn is_due(&self, now: &DateTime<Tz>) -> bool {
+        match self.due {
+            Some(ref due) => now >= due,
+            None => false,
+        }
+    }
+
+    /// Moves on to the period holding `now`.
+    pub fn advance(&mut self, now: &DateTime<Tz>) {
+        self.open(period_start(self.rotation, now));
+        self.seq = 0;
+    }
+
+    /// Moves on to a file stamped with `now`, ahead of the schedule, or with
+    /// the current file's time again if the clock was set back before it; the
+    /// caller tells the two files apart by their sequence numbers.
+    pub fn force(&mut self, now: &DateTime<Tz>) {
+        let opened_at = if *now < self.opened_at {
+            self.opened_at.clone()
+        } else {
+            now.clone()
+        };
+        self.open(opened_at);
+        self.seq += 1;
+    }
+
+    fn open(&mut self, opened_at: DateTime<Tz>) {
+        self.due = rotation_due_after(self.rotation, &opened_at);
+        self.opened_at = opened_at;
+    }
+}
+
+impl<Tz: TimeZone> Schedule<Tz>
+where
+    Tz::Offset: Display,
+{
+    /// `<channel>_<timestamp>.log`, or `<channel>.log` under external rotation.
+    pub fn file_name(&self, channel: &str) -> String {
+        let mut file_name = String::from(channel);
+        if let Rotation::Hourly(timestamp) | Rotation::Periodic(timestamp, _) = self.rotation {
+            file_name.push('_');
+            file_name.push_str(&self.opened_at.format(timestamp.format()).to_string());
+        }
+        file_name.push_str(".log");
+
+        file_name
+    }
+}
+
+/// File names laid out by a template in place of `<channel>_<timestamp>.log`,
+/// e.g. `{channel}-{year}{month}{day}.{seq}.{ext}`. The placeholders are
+/// `{channel}`; `{year}`, `{month}`, `{day}`, `{hour}`, `{minute}` and
+/// `{second}`, zero-padded, of the time the file was opened; `{seq}`, the
+/// files cut ahead of the schedule since the period began, e.g. by a size
+/// limit; and `{ext}`, `log`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FileNameLayout {
+    template: String,
+}
+
+impl FileNameLayout {
+    const PLACEHOLDERS: [&'static str; 9] = [
+        "channel", "year", "month", "day", "hour", "minute", "second", "seq", "ext",
+    ];
+
+    /// Name of the current file of `channel` in `schedule`.
+    pub fn file_name<Tz: TimeZone>(&self, schedule: &Schedule<Tz>, channel: &str) -> String {
+        let opened_at = &schedule.opened_at;
+        self.template
+            .replace("{channel}", channel)
+            .replace("{year}", &format!("{:04}", opened_at.year()))
+            .replace("{month}", &format!("{:02}", opened_at.month()))
+            .replace("{day}", &format!("{:02}", opened_at.day()))
+            .replace("{hour}", &format!("{:02}", opened_at.hour()))
+            .replace("{minute}", &format!("{:02}", opened_at.minute()))
+            .replace("{second}", &format!("{:02}", opened_at.second()))
+            .replace("{seq}", &schedule.seq.to_string())
+            .replace("{ext}", "log")
+    }
+}
+
+impl FromStr for FileNameLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| format!("unclosed placeholder in `{}`", s))?;
+            let name = &rest[start + 1..end];
+            if !FileNameLayout::PLACEHOLDERS.contains(&name) {
+                return Err(format!("unknown placeholder {{{}}} in `{}`", name, s));
+            }
+            rest = &rest[end + 1..];
+        }
+        if !s.contains("{channel}") {
+            return Err(format!("`{}` must hold {{channel}}", s));
+        }
+        if s.contains(|c| Platform::CURRENT.is_separator(c)) {
+            return Err(format!("`{}` must name a file, not a path", s));
+        }
+
+        Ok(FileNameLayout {
+            template: s.to_string(),
+        })
+    }
+}
+
+/// Subdirectories of the log directory files are sharded into by a template,
+/// e.g. `{channel}/{year}/{month}/{day}`, so no directory ends up holding
+/// every file of a long-running deployment. The placeholders are
+/// `{channel}` and `{year}`, `{month}`, `{day}` and `{hour}`, zero-padded, of
+/// the time the file was opened; a leading `{log_dir}/` may be spelled out.
+/// Files keep their names within them.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DirLayout {
+    components: Vec<String>,
+}
+
+impl DirLayout {
+    const PLACEHOLDERS: [&'static str; 5] = ["channel", "year", "month", "day", "hour"];
+
+    /// Directory, relative to the log directory and `/`-separated, of the
+    /// current file of `channel` in `schedule`.
+    pub fn dir<Tz: TimeZone>(&self, schedule: &Schedule<Tz>, channel: &str) -> String {
+        let opened_at = &schedule.opened_at;
+        self.components
+            .join("/")
+            .replace("{channel}", channel)
+            .replace("{year}", &format!("{:04}", opened_at.year()))
+            .replace("{month}", &format!("{:02}", opened_at.month()))
+            .replace("{day}", &format!("{:02}", opened_at.day()))
+            .replace("{hour}", &format!("{:02}", opened_at.hour()))
+    }
+
+    /// How many directories deep files are.
+    pub fn depth(&self) -> usize {
+        self.components.len()
+    }
+
+    /// Whether `name` can be the directory at `depth` of the layout, 0 for
+    /// the one right under the log directory, for what walks the shards.
+    pub fn matches(&self, depth: usize, name: &str) -> bool {
+        let component = match self.components.get(depth) {
+            Some(component) => component.as_str(),
+            None => return false,
+        };
+
+        matches_component(component, name)
+    }
+}
+
+/// Whether `name` is what `template` expands to for some channel and time.
+fn matches_component(template: &str, name: &str) -> bool {
+    let start = match template.find('{') {
+        Some(start) => start,
+        None => return template == name,
+    };
+    let name = match name.strip_prefix(&template[..start]) {
+        Some(name) => name,
+        None => return false,
+    };
+    let end = start + template[start..].find('}').unwrap_or(0);
+    let rest = &template[end + 1..];
+    let digits = match &template[start + 1..end] {
+        "year" => 4,
+        "channel" => {
+            // A channel takes at least one character, and as many as leave
+            // a match for the rest.
+            return (1..=name.len())
+                .filter(|&taken| name.is_char_boundary(taken))
+                .any(|taken| matches_component(rest, &name[taken..]));
+        }
+        _ => 2,
+    };
+
+    name.len() >= digits
+        && name.as_bytes()[..digits].iter().all(u8::is_ascii_digit)
+        && matches_component(rest, &name[digits..])
+}
+
+impl FromStr for DirLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let relative = s.strip_prefix("{log_dir}/").unwrap_or(s);
+        let mut rest = relative;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| format!("unclosed placeholder in `{}`", s))?;
+            let name = &rest[start + 1..end];
+            if !DirLayout::PLACEHOLDERS.contains(&name) {
+                return Err(format!("unknown placeholder {{{}}} in `{}`", name, s));
+            }
+            rest = &rest[end + 1..];
+        }
+
+        let components: Vec<String> = relative
+            .trim_end_matches('/')
+            .split(|c| Platform::CURRENT.is_separator(c))
+            .map(str::to_string)
+            .collect();
+        let invalid = components
+            .iter()
+            .any(|component| component.is_empty() || component == "." || component == "..");
+        if invalid || relative.contains(':') {
+            return Err(format!(
+                "`{}` must be a path relative to the log directory",
+                s
+            ));
+        }
+
+        Ok(DirLayout { components })
+    }
+}
+
+/// Reads back the time a file of `channel` was stamped with, to the
+/// precision of `timestamp`.
+pub fn parse_file_name(
+    channel: &str,
+    timestamp: FileTimestamp,
+    file_name: &str,
+) -> Option<NaiveDateTime> {
+    let stamp = file_name
+        .strip_prefix(channel)?
+        .strip_prefix('_')?
+        .strip_suffix(".log")?;
+    let (stamp, _) = split_sequence(stamp);
+    let (full, padding) = timestamp.padding();
+
+    NaiveDateTime::parse_from_str(&format!("{}{}", stamp, padding), full.format()).ok()
+}
+
+/// `file_name` with `seq` ahead of its extension, e.g.
+/// `app_2024-06-01-13-00-00.001.log`, for a file cut within the same second
+/// as a file of that name, which would otherwise be appended to.
+pub fn sequenced_file_name(file_name: &str, seq: u32) -> String {
+    match file_name.strip_suffix(".log") {
+        Some(stem) => format!("{}.{:03}.log", stem, seq),
+        None => format!("{}.{:03}", file_name, seq),
+    }
+}
+
+/// The sequence number `sequenced_file_name` put in a file name, 0 for a
+/// name without one, so files opened at the same time sort in the order
+/// they were cut.
+pub fn file_sequence(file_name: &str) -> u32 {
+    split_sequence(file_name.strip_suffix(".log").unwrap_or(file_name)).1
+}
+
+/// `stem` without its trailing `.<seq>`, and the sequence number.
+fn split_sequence(stem: &str) -> (&str, u32) {
+    match stem.rsplit_once('.') {
+        Some((rest, seq)) if seq.len() >= 3 && seq.bytes().all(|b| b.is_ascii_digit()) => {
+            (rest, seq.parse().unwrap_or(0))
+        }
+        _ => (stem, 0),
+    }
+}
+
+/// Reads the time a file of a channel was opened at out of its name, so
+/// directories laid out by other tools can be read and managed too.
+pub trait FileNameParser: Send + Sync {
+    fn parse(&self, channel: &str, file_name: &str) -> Option<NaiveDateTime>;
+
+    /// The sequence number of a file cut ahead of the schedule, 0 for the
+    /// first file of a period, so files opened at the same time sort in the
+    /// order they were cut.
+    fn sequence(&self, _channel: &str, file_name: &str) -> u32 {
+        file_sequence(file_name)
+    }
+
+    /// When a file opened at `opened_at` is replaced at the latest, the hour
+    /// boundary after it unless files cover longer periods.
+    fn period_end(&self, opened_at: NaiveDateTime) -> NaiveDateTime {
+        opened_at
+            .with_nanosecond(0)
+            .and_then(|time| time.with_second(0))
+            .and_then(|time| time.with_minute(0))
+            .map(|hour| hour + Duration::hours(1))
+            .unwrap_or(opened_at)
+    }
+}
+
+impl FileNameParser for FileTimestamp {
+    fn parse(&self, channel: &str, file_name: &str) -> Option<NaiveDateTime> {
+        parse_file_name(channel, *self, file_name)
+    }
+}
+
+impl FileNameLayout {
+    /// The time and sequence number a file of `channel` was named with,
+    /// `None` unless the name follows the template and holds a year.
+    fn fields(&self, channel: &str, file_name: &str) -> Option<(NaiveDateTime, u32)> {
+        let mut template = self.template.as_str();
+        let mut rest = file_name;
+        let mut year = None;
+        let [mut month, mut day, mut hour, mut minute, mut second, mut seq] = [1, 1, 0, 0, 0, 0];
+        while let Some(start) = template.find('{') {
+            let end = start + template[start..].find('}')?;
+            rest = rest.strip_prefix(&template[..start])?;
+            let placeholder = &template[start + 1..end];
+            template = &template[end + 1..];
+
+            let digits = match placeholder {
+                "channel" => {
+                    rest = rest.strip_prefix(channel)?;
+                    continue;
+                }
+                "ext" => {
+                    rest = rest.strip_prefix("log")?;
+                    continue;
+                }
+                "year" => 4,
+                "seq" => rest.bytes().take_while(u8::is_ascii_digit).count().max(1),
+                _ => 2,
+            };
+            let value: u32 = rest
+                .get(..digits)
+                .filter(|value| value.bytes().all(|b| b.is_ascii_digit()))?
+                .parse()
+                .ok()?;
+            rest = &rest[digits..];
+            match placeholder {
+                "year" => year = Some(value as i32),
+                "month" => month = value,
+                "day" => day = value,
+                "hour" => hour = value,
+                "minute" => minute = value,
+                "second" => second = value,
+                _ => seq = value,
+            }
+        }
+        if rest != template {
+            return None;
+        }
+
+        let opened_at =
+            NaiveDate::from_ymd_opt(year?, month, day)?.and_hms_opt(hour, minute, second)?;
+        Some((opened_at, seq))
+    }
+}
+
+impl FileNameParser for FileNameLayout {
+    fn parse(&self, channel: &str, file_name: &str) -> Option<NaiveDateTime> {
+        self.fields(channel, file_name)
+            .map(|(opened_at, _)| opened_at)
+    }
+
+    fn sequence(&self, channel: &str, file_name: &str) -> u32 {
+        self.fields(channel, file_name).map_or(0, |(_, seq)| seq)
+    }
+}
+
+/// File names following a strftime pattern with a `{channel}` placeholder,
+/// e.g. `{channel}.%Y%m%d.log`, each file covering `period` from the time in
+/// its name. Fields the pattern leaves out are taken as zero, so a daily file
+/// is read as opened at midnight.
+#[derive(Clone, Debug)]
+pub struct FileNameTemplate {
+    template: String,
+    period: Duration,
+}
+
+impl FileNameTemplate {
+    pub fn new(template: &str, period: Duration) -> Self {
+        FileNameTemplate {
+            template: template.to_string(),
+            period,
+        }
+    }
+}
+
+impl FileNameParser for FileNameTemplate {
+    fn parse(&self, channel: &str, file_name: &str) -> Option<NaiveDateTime> {
+        let pattern = self
+            .template
+            .replace("{channel}", &channel.replace('%', "%%"));
+
+        let mut parsed = Parsed::new();
+        format::parse(&mut parsed, file_name, StrftimeItems::new(&pattern)).ok()?;
+        // Each of these only fails when the pattern already set the field.
+        let _ = parsed.set_hour(0);
+        let _ = parsed.set_minute(0);
+        let _ = parsed.set_second(0);
+
+        parsed.to_naive_datetime_with_offset(0).ok()
+    }
+    fn period_end(&self, opened_at: NaiveDateTime) -> NaiveDateTime {
+        opened_at + self.period
+    }
+}
+
+/// Start of the hour holding `time` on the clocks of its zone.
+fn hour_start<Tz: TimeZone>(time: &DateTime<Tz>) -> DateTime<Tz> {
+    let local = time.naive_local();
+    let start = local
+        .with_nanosecond(0)
+        .and_then(|local| local.with_second(0))
+        .and_then(|local| local.with_minute(0))
+        .unwrap_or(local);
+
+    resolve_start(time, start)
+}
+
+/// Start of the period holding `time`: its hour, or the last multiple of the
+/// period since midnight. Periods are counted on the clocks of `time`'s zone,
+/// so a daily file of a zone keeping daylight saving time starts at its
+/// midnight all year round.
+fn period_start<Tz: TimeZone>(rotation: Rotation, time: &DateTime<Tz>) -> DateTime<Tz> {
+    match rotation {
+        Rotation::Periodic(_, period) => resolve_start(time, local_period_start(period, time)),
+        _ => hour_start(time),
+    }
+}
+
+fn local_period_start<Tz: TimeZone>(period: Duration, time: &DateTime<Tz>) -> NaiveDateTime {
+    let local = time.naive_local();
+    let midnight = local.date().and_hms_opt(0, 0, 0).unwrap();
+    let elapsed = (local - midnight).num_seconds();
+    let period_secs = period.num_seconds().max(1);
+
+    midnight + Duration::seconds(elapsed - elapsed % period_secs)
+}
+
+/// The period boundary following `time`, when a file opened at `time` is due
+/// to be replaced. An hour is an hour whatever the clocks do; longer periods
+/// end at the next boundary on the clocks, however long ago they began.
+fn rotation_due_after<Tz: TimeZone>(
+    rotation: Rotation,
+    time: &DateTime<Tz>,
+) -> Option<DateTime<Tz>> {
+    match rotation {
+        Rotation::Hourly(_) => Some(hour_start(time) + Duration::hours(1)),
+        Rotation::Periodic(_, period) => {
+            let boundary = local_period_start(period, time) + period;
+            Some(match time.timezone().from_local_datetime(&boundary) {
+                LocalResult::Single(due) | LocalResult::Ambiguous(due, _) => due,
+                LocalResult::None => skipped_to(time, boundary),
+            })
+        }
+        Rotation::External => None,
+    }
+}
+
+/// The instant the clocks of `time`'s zone read `start` at, the start of a
+/// period `time` falls in. When the clocks went back through `start`, the
+/// later of the two that isn't after `time`; when they skipped it, the
+/// instant they went forward.
+fn resolve_start<Tz: TimeZone>(time: &DateTime<Tz>, start: NaiveDateTime) -> DateTime<Tz> {
+    match time.timezone().from_local_datetime(&start) {
+        LocalResult::Single(start) => start,
+        LocalResult::Ambiguous(earlier, later) if later > *time => earlier,
+        LocalResult::Ambiguous(_, later) => later,
+        LocalResult::None => skipped_to(time, start).min(time.clone()),
+    }
+}
+
+/// The instant the clocks of `time`'s zone went forward over `local` at,
+/// read with the offset they had until then.
+fn skipped_to<Tz: TimeZone>(time: &DateTime<Tz>, local: NaiveDateTime) -> DateTime<Tz> {
+    let zone = time.timezone();
+    // Clocks don't change twice in a day.
+    let before = zone
+        .offset_from_utc_datetime(&(local - Duration::days(1)))
+        .fix();
+
+    zone.from_utc_datetime(&(local - Duration::seconds(i64::from(before.local_minus_utc()))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(hms: (u32, u32, u32)) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 6, 1)
+            .unwrap()
+            .and_hms_opt(hms.0, hms.1, hms.2)
+            .unwrap()
+    }
+
+    #[test]
+    fn timestamps_are_named_on_the_command_line() {
+        assert_eq!("seconds".parse(), Ok(FileTimestamp::Seconds));
+        assert_eq!("hours".parse(), Ok(FileTimestamp::Hours));
+        assert_eq!("rfc3339-seconds".parse(), Ok(FileTimestamp::Rfc3339Seconds));
+        assert_eq!("rfc3339-hours".parse(), Ok(FileTimestamp::Rfc3339Hours));
+        assert_eq!(
+            "minutes".parse::<FileTimestamp>(),
+            Err(String::from("unknown file timestamp format: minutes"))
+        );
+    }
+
+    #[test]
+    fn stamps_are_as_precise_as_their_format() {
+        let opened = time((13, 5, 9));
+        let stamp = |timestamp: FileTimestamp| opened.format(timestamp.format()).to_string();
+
+        assert_eq!(stamp(FileTimestamp::Seconds), "2024-06-01-13-05-09");
+        assert_eq!(stamp(FileTimestamp::Hours), "2024-06-01-13");
+        assert_eq!(stamp(FileTimestamp::Rfc3339Seconds), "2024-06-01T13:05:09");
+        assert_eq!(stamp(FileTimestamp::Rfc3339Hours), "2024-06-01T13");
+    }
+
+    #[test]
+    fn hour_stamps_parse_back_to_the_hour() {
+        assert_eq!(
+            parse_file_name("web", FileTimestamp::Hours, "web_2024-06-01-13.log"),
+            Some(time((13, 0, 0)))
+        );
+        assert_eq!(
+            parse_file_name(
+                "web",
+                FileTimestamp::Rfc3339Hours,
+                "web_2024-06-01T13.002.log"
+            ),
+            Some(time((13, 0, 0)))
+        );
+        assert_eq!(
+            parse_file_name("web", FileTimestamp::Hours, "web_2024-06-01-13-00-00.log"),
+            None
+        );
+        assert_eq!(
+            parse_file_name(
+                "web",
+                FileTimestamp::Seconds,
+                "webapp_2024-06-01-13-00-00.log"
+            ),
+            None
+        );
+    }
+}
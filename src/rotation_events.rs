@@ -0,0 +1,97 @@
+use chrono::{DateTime, Local};
+
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::{Mutex, OnceLock};
+
+use crate::envelope::Escaped;
+use crate::report;
+
+/// File every rotation is recorded in, set once at startup by `install`.
+static EVENTS: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Why a channel moved on to a new file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Reason {
+    /// Its period was up.
+    Time,
+    /// The file would have grown past `--max-file-size`.
+    Size,
+    /// Asked for, by a signal, the control socket or the admin endpoint.
+    Manual,
+}
+
+impl Reason {
+    fn name(self) -> &'static str {
+        match self {
+            Reason::Time => "time",
+            Reason::Size => "size",
+            Reason::Manual => "manual",
+        }
+    }
+}
+
+/// A channel moving on from one file to the next.
+pub struct Rotated<'a> {
+    pub channel: &'a str,
+    pub from: &'a str,
+    pub to: &'a str,
+    pub reason: Reason,
+    /// Size of the file rotated away from, as it was left.
+    pub bytes: u64,
+    /// Lines written to it by this run.
+    pub lines: u64,
+    pub opened_at: DateTime<Local>,
+    pub at: DateTime<Local>,
+}
+
+/// Appends a JSON line to the file at `path` for every rotation from now on,
+/// so archival jobs needn't tell what happened from file names, e.g.
+/// `{"at":"...","channel":"app","old_path":"...","new_path":"...",
+/// "reason":"size","bytes":1048576,"lines":9000,"open_seconds":630}`.
+pub fn install(path: &str) -> Result<(), io::Error> {
+    let file = OpenOptions::new().append(true).create(true).open(path)?;
+    let _ = EVENTS.set(Mutex::new(file));
+
+    Ok(())
+}
+
+/// Records `rotated`, if rotations are recorded. Failures are reported, the
+/// rotation stands.
+pub fn record(rotated: &Rotated) {
+    let events = match EVENTS.get() {
+        Some(events) => events,
+        None => return,
+    };
+
+    let mut event = String::new();
+    // Writing to a `String` can't fail.
+    let _ = write!(event, "{{\"at\":\"{}\"", rotated.at.to_rfc3339());
+    for (name, value) in [
+        ("channel", rotated.channel),
+        ("old_path", rotated.from),
+        ("new_path", rotated.to),
+        ("reason", rotated.reason.name()),
+    ] {
+        let _ = write!(event, ",\"{}\":\"", name);
+        let _ = Escaped(&mut event).write_str(value);
+        event.push('"');
+    }
+    let open_seconds = (rotated.at - rotated.opened_at).num_seconds().max(0);
+    let _ = writeln!(
+        event,
+        ",\"bytes\":{},\"lines\":{},\"open_seconds\":{}}}",
+        rotated.bytes, rotated.lines, open_seconds
+    );
+
+    let mut file = events.lock().unwrap_or_else(|e| e.into_inner());
+    if let Err(error) = file.write_all(event.as_bytes()) {
+        tracing::warn!(
+            "unable to record the rotation of {}: {}",
+            rotated.channel,
+            error
+        );
+        report::record_error("rotation-events", error);
+    }
+}
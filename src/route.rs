@@ -0,0 +1,38 @@
+use regex::Regex;
+
+use std::str::FromStr;
+
+/// A rule choosing the channel of a `routed` line by its content, written
+/// `<pattern>=><channel>`, e.g. `^\[nginx\]=>nginx`. The pattern is matched
+/// against the line without its line end.
+#[derive(Clone, Debug)]
+pub struct Route {
+    pattern: Regex,
+    channel: String,
+}
+
+impl FromStr for Route {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, channel) = s
+            .rsplit_once("=>")
+            .filter(|(_, channel)| !channel.is_empty())
+            .ok_or_else(|| format!("expected a route such as `ERROR.*=>errors`, got `{}`", s))?;
+        let pattern = Regex::new(pattern).map_err(|e| e.to_string())?;
+
+        Ok(Route {
+            pattern,
+            channel: channel.to_string(),
+        })
+    }
+}
+
+/// Channel of the first of `routes` matching `line`, if any does.
+pub fn channel_of<'a>(routes: &'a [Route], line: &str) -> Option<&'a str> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    routes
+        .iter()
+        .find(|route| route.pattern.is_match(line))
+        .map(|route| route.channel.as_str())
+}
@@ -0,0 +1,269 @@
+use chrono::{DateTime, Duration, FixedOffset, Local, Offset};
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::rotation::{FileNameLayout, Rotation};
+use crate::sink::{FileSink, Sink};
+
+/// When a channel's file is replaced: on the schedule of its rotation, and
+/// ahead of it once the file would grow past a size limit.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RotationPolicy {
+    pub rotation: Rotation,
+    /// Bytes a file may hold; a line that doesn't fit goes to a new one.
+    pub max_file_size: Option<u64>,
+    /// Names the files instead of `<channel>_<timestamp>.log`.
+    pub layout: Option<FileNameLayout>,
+}
+
+impl RotationPolicy {
+    pub fn new(rotation: Rotation) -> Self {
+        RotationPolicy {
+            rotation,
+            max_file_size: None,
+            layout: None,
+        }
+    }
+}
+
+/// Where a channel's files are written and how durably.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SinkConfig {
+    pub dir: PathBuf,
+    /// Syncs every line to disk before `write` returns, rather than leaving
+    /// lines buffered until `flush` or the next rotation.
+    pub sync: bool,
+}
+
+impl SinkConfig {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        SinkConfig {
+            dir: dir.into(),
+            sync: false,
+        }
+    }
+}
+
+/// A channel lines are routed to by name.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Channel {
+    pub name: String,
+    pub sink: SinkConfig,
+    pub rotation: RotationPolicy,
+}
+
+impl Channel {
+    pub fn new(name: &str, sink: SinkConfig, rotation: RotationPolicy) -> Self {
+        Channel {
+            name: name.to_string(),
+            sink,
+            rotation,
+        }
+    }
+}
+
+/// Where the router tells the time of lines written without one, so tests
+/// can take it over.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<FixedOffset>;
+}
+
+/// The system clock, in the local time zone.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<FixedOffset> {
+        let now = Local::now();
+        now.with_timezone(&now.offset().fix())
+    }
+}
+
+/// A clock standing still until it is set or advanced. Clones share the
+/// time, so a test can keep one and hand the other to the router.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    time: Arc<std::sync::Mutex<DateTime<FixedOffset>>>,
+}
+
+impl ManualClock {
+    pub fn new(time: DateTime<FixedOffset>) -> Self {
+        ManualClock {
+            time: Arc::new(std::sync::Mutex::new(time)),
+        }
+    }
+
+    /// Moves the clock to `time`, backwards as well as forwards.
+    pub fn set(&self, time: DateTime<FixedOffset>) {
+        *self.time.lock().unwrap_or_else(|e| e.into_inner()) = time;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let mut time = self.time.lock().unwrap_or_else(|e| e.into_inner());
+        *time += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<FixedOffset> {
+        *self.time.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Routes lines to the sinks of their channels, files rotated on schedule
+/// unless plugged in otherwise, for daemons embedding log-revolve instead of
+/// piping to it, and for the collector itself, whose channels are files of
+/// its own. Channels are written to one at a time: a daemon writing from
+/// several tasks shares the router behind a lock, as the collector does.
+pub struct Router<S: Sink + ?Sized = dyn Sink> {
+    sinks: BTreeMap<String, Box<S>>,
+    /// Tells the time of lines written without one.
+    clock: Box<dyn Clock>,
+}
+
+impl Router {
+    /// Router writing each of `channels` to its files.
+    pub fn new<I: IntoIterator<Item = Channel>>(channels: I) -> Self {
+        Router::with_sinks(channels.into_iter().map(|channel| {
+            let sink = FileSink::new(&channel.name, channel.sink, channel.rotation);
+            (channel.name, Box::new(sink) as Box<dyn Sink>)
+        }))
+    }
+}
+
+impl<S: Sink + ?Sized> Router<S> {
+    /// Router writing each channel to a sink of the caller's.
+    pub fn with_sinks<I: IntoIterator<Item = (String, Box<S>)>>(sinks: I) -> Self {
+        Router {
+            sinks: sinks.into_iter().collect(),
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    /// Reads the time of lines written without one from `clock` rather than
+    /// the system clock.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Box::new(clock);
+    }
+
+    /// The time lines written now are written at.
+    pub fn now(&self) -> DateTime<FixedOffset> {
+        self.clock.now()
+    }
+
+    /// Names of the channels lines can be written to.
+    pub fn channels(&self) -> impl Iterator<Item = &str> {
+        self.sinks.keys().map(String::as_str)
+    }
+
+    pub fn contains(&self, channel: &str) -> bool {
+        self.sinks.contains_key(channel)
+    }
+
+    /// Sink of `channel`, to be told what it can't be through the router.
+    pub fn get(&self, channel: &str) -> Option<&S> {
+        self.sinks.get(channel).map(Box::as_ref)
+    }
+
+    pub fn get_mut(&mut self, channel: &str) -> Option<&mut S> {
+        self.sinks.get_mut(channel).map(Box::as_mut)
+    }
+
+    /// Channels with their sinks, by name.
+    pub fn sinks(&self) -> impl Iterator<Item = (&str, &S)> {
+        self.sinks
+            .iter()
+            .map(|(channel, sink)| (channel.as_str(), sink.as_ref()))
+    }
+
+    pub fn sinks_mut(&mut self) -> impl Iterator<Item = (&str, &mut S)> {
+        self.sinks
+            .iter_mut()
+            .map(|(channel, sink)| (channel.as_str(), sink.as_mut()))
+    }
+
+    /// Adds `channel`, or hands it another sink, returning the one it had.
+    pub fn insert(&mut self, channel: String, sink: Box<S>) -> Option<Box<S>> {
+        self.sinks.insert(channel, sink)
+    }
+
+    /// Takes `channel` away, handing back its sink to be closed.
+    pub fn remove(&mut self, channel: &str) -> Option<Box<S>> {
+        self.sinks.remove(channel)
+    }
+
+    /// Writes `line` to the current file of `channel`, ended with a newline
+    /// unless it has one.
+    pub async fn write(&mut self, channel: &str, line: &str) -> Result<(), io::Error> {
+        let now = self.clock.now();
+        self.write_at(channel, line, &now).await
+    }
+
+    /// Writes `line` as if at `now`, which decides the file it lands in.
+    pub async fn write_at(
+        &mut self,
+        channel: &str,
+        line: &str,
+        now: &DateTime<FixedOffset>,
+    ) -> Result<(), io::Error> {
+        let sink = self.sink(channel)?;
+        match line.ends_with('\n') {
+            true => sink.write(line, now).await,
+            false => sink.write(&format!("{}\n", line), now).await,
+        }
+    }
+
+    /// Moves `channel` on to a new file ahead of its schedule.
+    pub async fn rotate(&mut self, channel: &str) -> Result<(), io::Error> {
+        let now = self.clock.now();
+        self.sink(channel)?.rotate(&now).await
+    }
+
+    /// Moves every channel on to a new file ahead of its schedule, e.g. so a
+    /// backup takes whole files.
+    pub async fn rotate_all(&mut self) -> Result<(), io::Error> {
+        let now = self.clock.now();
+        for sink in self.sinks.values_mut() {
+            sink.rotate(&now).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Path of the file `channel` is writing to, if it wrote anything yet.
+    pub fn current_path(&self, channel: &str) -> Option<&Path> {
+        self.sinks.get(channel)?.current_path()
+    }
+
+    /// Writes out what every channel has buffered.
+    pub async fn flush(&mut self) -> Result<(), io::Error> {
+        for sink in self.sinks.values_mut() {
+            sink.flush().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Closes every file, to be opened again by the next line under the same
+    /// name: how files renamed by an external rotation tool are let go of.
+    pub async fn reopen(&mut self) -> Result<(), io::Error> {
+        for sink in self.sinks.values_mut() {
+            sink.close().await?;
+        }
+
+        Ok(())
+    }
+
+    fn sink(&mut self, channel: &str) -> Result<&mut S, io::Error> {
+        match self.sinks.get_mut(channel) {
+            Some(sink) => Ok(sink.as_mut()),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown channel {}", channel),
+            )),
+        }
+    }
+}
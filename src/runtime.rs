@@ -0,0 +1,133 @@
+//! The async runtime the library's files are read and written through:
+//! async-std with the default `runtime-async-std` feature, or tokio with
+//! `runtime-tokio`, for daemons running on tokio that would rather not start
+//! a second runtime beside it. `runtime-tokio` wins when both are enabled,
+//! and async-std is kept when neither is. Only the library switches; the
+//! binary always runs on async-std.
+
+use std::ffi::OsString;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(not(feature = "runtime-tokio"))]
+pub(crate) use async_std::fs::{File, OpenOptions};
+#[cfg(not(feature = "runtime-tokio"))]
+pub(crate) use async_std::io::{BufWriter, WriteExt};
+
+#[cfg(feature = "runtime-tokio")]
+pub(crate) use tokio::fs::{File, OpenOptions};
+#[cfg(feature = "runtime-tokio")]
+pub(crate) use tokio::io::{AsyncWriteExt as WriteExt, BufWriter};
+
+use async_std::stream::Stream;
+
+/// Runs `future` to its end on the runtime the library was built for, for
+/// callers that aren't in one already, such as tests and one-off commands.
+#[cfg(not(feature = "runtime-tokio"))]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    async_std::task::block_on(future)
+}
+
+/// Runs `future` to its end on the runtime the library was built for, for
+/// callers that aren't in one already, such as tests and one-off commands.
+#[cfg(feature = "runtime-tokio")]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("a tokio runtime couldn't be started")
+        .block_on(future)
+}
+
+/// Whether anything is at `path`.
+pub(crate) async fn exists(path: &Path) -> bool {
+    #[cfg(not(feature = "runtime-tokio"))]
+    return async_std::path::Path::new(path).exists().await;
+    #[cfg(feature = "runtime-tokio")]
+    return tokio::fs::try_exists(path).await.unwrap_or(false);
+}
+
+/// Whether `path` is a directory, following symlinks.
+pub(crate) async fn is_dir(path: &Path) -> bool {
+    #[cfg(not(feature = "runtime-tokio"))]
+    return async_std::path::Path::new(path).is_dir().await;
+    #[cfg(feature = "runtime-tokio")]
+    return tokio::fs::metadata(path)
+        .await
+        .is_ok_and(|metadata| metadata.is_dir());
+}
+
+/// Names and paths of what is in `dir`.
+pub(crate) async fn read_dir(dir: &Path) -> Result<Vec<(OsString, PathBuf)>, io::Error> {
+    let mut entries = Vec::new();
+
+    #[cfg(not(feature = "runtime-tokio"))]
+    {
+        use async_std::prelude::*;
+
+        let mut read = async_std::fs::read_dir(dir).await?;
+        while let Some(entry) = read.next().await {
+            let entry = entry?;
+            entries.push((entry.file_name(), entry.path().into()));
+        }
+    }
+    #[cfg(feature = "runtime-tokio")]
+    {
+        let mut read = tokio::fs::read_dir(dir).await?;
+        while let Some(entry) = read.next_entry().await? {
+            entries.push((entry.file_name(), entry.path()));
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Lines of the file at `path`, read as they are asked for.
+pub(crate) async fn lines(
+    path: &Path,
+) -> Result<impl Stream<Item = Result<String, io::Error>> + Send, io::Error> {
+    let file = File::open(path).await?;
+
+    #[cfg(not(feature = "runtime-tokio"))]
+    return Ok(async_std::io::BufReadExt::lines(
+        async_std::io::BufReader::new(file),
+    ));
+    #[cfg(feature = "runtime-tokio")]
+    return Ok(Lines(tokio::io::AsyncBufReadExt::lines(
+        tokio::io::BufReader::new(file),
+    )));
+}
+
+/// Runs `work`, which blocks, away from the runtime's own threads.
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "encryption"))]
+pub(crate) async fn unblock<T, F>(work: F) -> Result<T, io::Error>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, io::Error> + Send + 'static,
+{
+    #[cfg(not(feature = "runtime-tokio"))]
+    return async_std::task::spawn_blocking(work).await;
+    #[cfg(feature = "runtime-tokio")]
+    return tokio::task::spawn_blocking(work)
+        .await
+        .map_err(io::Error::other)?;
+}
+
+/// tokio's lines of a file, read as a `Stream` like async-std's are.
+#[cfg(feature = "runtime-tokio")]
+struct Lines(tokio::io::Lines<tokio::io::BufReader<File>>);
+
+#[cfg(feature = "runtime-tokio")]
+impl Stream for Lines {
+    type Item = Result<String, io::Error>;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        std::pin::Pin::new(&mut self.0)
+            .poll_next_line(cx)
+            .map(Result::transpose)
+    }
+}
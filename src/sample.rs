@@ -0,0 +1,80 @@
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How much of a channel's lines is kept: `1/100` keeps the first line and
+/// every hundredth after it, `0.05` keeps each line with that probability.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SampleRate {
+    Every(u64),
+    Probability(f64),
+}
+
+impl FromStr for SampleRate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid sample rate: {}", s);
+        match s.strip_prefix("1/") {
+            Some(every) => match every.parse() {
+                Ok(0) | Err(_) => Err(invalid()),
+                Ok(every) => Ok(SampleRate::Every(every)),
+            },
+            None => match s.parse::<f64>() {
+                Ok(probability) if probability > 0.0 && probability <= 1.0 => {
+                    Ok(SampleRate::Probability(probability))
+                }
+                _ => Err(invalid()),
+            },
+        }
+    }
+}
+
+/// Keeps a statistically useful fraction of a chatty channel's lines,
+/// counting those it leaves out.
+pub struct Sampler {
+    rate: SampleRate,
+    /// Lines seen so far, for a rate of every so many.
+    seen: u64,
+    /// State of the xorshift generator, for a probability.
+    state: u64,
+    pub sampled_out_lines: u64,
+}
+
+impl Sampler {
+    pub fn new(rate: SampleRate) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as u64);
+
+        Sampler {
+            rate,
+            seen: 0,
+            // Xorshift never leaves zero.
+            state: seed | 1,
+            sampled_out_lines: 0,
+        }
+    }
+
+    /// Whether the next line is kept.
+    pub fn keeps(&mut self) -> bool {
+        let kept = match self.rate {
+            SampleRate::Every(every) => {
+                let kept = self.seen.is_multiple_of(every);
+                self.seen += 1;
+                kept
+            }
+            SampleRate::Probability(probability) => {
+                self.state ^= self.state << 13;
+                self.state ^= self.state >> 7;
+                self.state ^= self.state << 17;
+                // The top 53 bits, as a fraction in [0, 1).
+                ((self.state >> 11) as f64 / (1u64 << 53) as f64) < probability
+            }
+        };
+        if !kept {
+            self.sampled_out_lines += 1;
+        }
+
+        kept
+    }
+}
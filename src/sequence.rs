@@ -0,0 +1,133 @@
+use async_std::fs::{self, File};
+use async_std::io::{self, SeekFrom};
+use async_std::prelude::*;
+
+use log_revolve_rs::rotation;
+
+/// How far from the end of a file the last numbered line is looked for.
+const TAIL_BYTES: u64 = 64 * 1024;
+
+/// Finds the sequence number a channel continues from, by reading back the
+/// last numbered line of its newest file. Keeping no state of its own, it
+/// carries across rotation and restarts for as long as the files are around.
+pub async fn resume(log_dir: &str, channel: &str) -> Result<u64, io::Error> {
+    let prefix = format!("{}_", channel);
+    let unrotated = format!("{}.log", channel);
+    let mut paths = Vec::new();
+
+    let mut entries = fs::read_dir(log_dir).await?;
+    while let Some(entry) = entries.next().await {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => continue,
+        };
+        let stamp = match name
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(".log"))
+        {
+            Some(stamp) => stamp,
+            // Sorts ahead of every stamp.
+            None if name == unrotated => "",
+            None => continue,
+        };
+        if !stamp
+            .chars()
+            .all(|c| c.is_ascii_digit() || "-T:.".contains(c))
+        {
+            continue;
+        }
+        let seq = rotation::file_sequence(name);
+        let stamp = stamp.split('.').next().unwrap_or(stamp).to_string();
+        paths.push((stamp, seq, entry.path()));
+    }
+
+    // File names sort in the order they were opened, those cut within the
+    // same second by their sequence numbers, and a file left to external
+    // rotation has only itself to look at.
+    paths.sort();
+    for (_, _, path) in paths.iter().rev() {
+        if let Some(last) = last_number(&mut File::open(path).await?).await? {
+            return Ok(last + 1);
+        }
+    }
+
+    Ok(0)
+}
+
+async fn last_number(file: &mut File) -> Result<Option<u64>, io::Error> {
+    let length = file.metadata().await?.len();
+    file.seek(SeekFrom::Start(length.saturating_sub(TAIL_BYTES)))
+        .await?;
+
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).await?;
+
+    let last = String::from_utf8_lossy(&tail)
+        .lines()
+        .rev()
+        .find_map(|line| line.split_once(' ')?.0.parse().ok());
+    Ok(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::task;
+
+    use crate::testing::{self, LogDir};
+
+    fn resume_in(dir: &LogDir, channel: &str) -> u64 {
+        task::block_on(resume(dir.path().to_str().unwrap(), channel)).unwrap()
+    }
+
+    fn write(dir: &LogDir, name: &str, contents: &str) {
+        std::fs::write(dir.path().join(name), contents).unwrap();
+    }
+
+    #[test]
+    fn channels_without_files_start_at_zero() {
+        let dir = LogDir::new("sequence-empty");
+        write(&dir, "webapp_2024-06-01-13-00-00.log", "7 GET /\n");
+
+        assert_eq!(resume_in(&dir, "web"), 0);
+    }
+
+    #[test]
+    fn numbering_goes_on_from_the_newest_file() {
+        let dir = LogDir::new("sequence-newest");
+        write(&dir, "web_2024-06-01-12-00-00.log", "0 GET /\n1 GET /\n");
+        write(&dir, "web_2024-06-01-13-00-00.log", "2 GET /\n3 GET /\n");
+        write(&dir, "web_2024-06-01-13-00-00.001.log", "4 GET /\n");
+        write(&dir, "web_2024-06-01-13-00-00.log.gz", "");
+
+        assert_eq!(resume_in(&dir, "web"), 5);
+    }
+
+    #[test]
+    fn files_without_numbered_lines_are_passed_by() {
+        let dir = LogDir::new("sequence-unnumbered");
+        write(&dir, "web_2024-06-01-12-00-00.log", "0 GET /\n1 GET /\n");
+        write(&dir, "web_2024-06-01-13-00-00.log", "");
+        write(&dir, "web_2024-06-01-14-00-00.log", "GET /\n");
+
+        assert_eq!(resume_in(&dir, "web"), 2);
+    }
+
+    #[test]
+    fn written_lines_are_numbered_across_restarts() {
+        task::block_on(async {
+            let dir = LogDir::new("sequence-writer");
+            let args = ["--accepted-log-channels", "web", "--sequence-numbers"];
+            for _ in 0..2 {
+                let mut writer = testing::writer(&dir, &args).await;
+                writer.write_to_channel("", "web", "GET /\n").await.unwrap();
+                writer.sync_all().await.unwrap();
+            }
+
+            assert_eq!(dir.read("web_"), "0 GET /\n1 GET /\n");
+        });
+    }
+}
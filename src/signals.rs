@@ -0,0 +1,216 @@
+use async_std::channel::Sender;
+use async_std::io;
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1, SIGUSR2};
+use signal_hook::iterator::Signals;
+
+use std::thread;
+
+use crate::user_signal::UserSignal;
+use crate::{report, CliOptions, FileWriter, Stop};
+
+/// Acts on SIGHUP. Under external rotation every file is reopened at its
+/// current path, the contract expected by logrotate and similar tools: they
+/// rename the files, then signal the writer to start over under the original
+/// names. Given a config file, it is read again and its channels taken.
+pub fn on_hangup(
+    writer: Arc<Mutex<FileWriter>>,
+    options: Arc<CliOptions>,
+) -> Result<(), io::Error> {
+    let mut signals = Signals::new([SIGHUP])?;
+
+    thread::Builder::new()
+        .name(String::from("signals"))
+        .spawn(move || {
+            for _ in signals.forever() {
+                task::block_on(async {
+                    let mut writer = writer.lock().await;
+                    if options.external_rotation {
+                        match writer.reopen_all().await {
+                            Ok(()) => tracing::info!("reopened files on SIGHUP"),
+                            Err(error) => {
+                                tracing::error!("unable to reopen files: {}", error);
+                                report::record_error("signals", error);
+                            }
+                        }
+                    }
+                    #[cfg(feature = "config")]
+                    if let Some(ref path) = options.config {
+                        match writer.reload_config(&options, path).await {
+                            Ok((added, removed)) => tracing::info!(
+                                "reloaded {}, channels added: {:?}, removed: {:?}",
+                                path,
+                                added,
+                                removed
+                            ),
+                            Err(error) => {
+                                tracing::error!("unable to reload {}: {}", path, error);
+                                report::record_error("signals", error);
+                            }
+                        }
+                    }
+                });
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Reopens every file at its current path on `signal`, whatever rotates
+/// the files, so a tool renaming them from under the router has it start
+/// over under the original names rather than keep writing to the renamed
+/// ones.
+pub fn reopen_on(signal: UserSignal, writer: Arc<Mutex<FileWriter>>) -> Result<(), io::Error> {
+    let mut signals = Signals::new([number(signal)])?;
+
+    thread::Builder::new()
+        .name(String::from("reopen"))
+        .spawn(move || {
+            for _ in signals.forever() {
+                task::block_on(async {
+                    let mut writer = writer.lock().await;
+                    match writer.reopen_all().await {
+                        Ok(()) => tracing::info!("reopened files on {}", signal),
+                        Err(error) => {
+                            tracing::error!("unable to reopen files: {}", error);
+                            report::record_error("signals", error);
+                        }
+                    }
+                });
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Starts a fresh file for every channel on `signal`, whatever their
+/// rotation, e.g. for a backup window to take whole files.
+pub fn rotate_on(signal: UserSignal, writer: Arc<Mutex<FileWriter>>) -> Result<(), io::Error> {
+    let mut signals = Signals::new([number(signal)])?;
+
+    thread::Builder::new()
+        .name(String::from("rotate"))
+        .spawn(move || {
+            for _ in signals.forever() {
+                task::block_on(async {
+                    let mut writer = writer.lock().await;
+                    match writer.rotate_all().await {
+                        Ok(rotated) => tracing::info!("rotated {} files on {}", rotated, signal),
+                        Err(error) => {
+                            tracing::error!("unable to rotate files: {}", error);
+                            report::record_error("signals", error);
+                        }
+                    }
+                });
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Starts the shutdown on SIGTERM or SIGINT, flushing what is held in memory
+/// rather than dying with it. A second signal exits right away.
+pub fn stop_on_terminate(stop: Sender<Stop>) -> Result<(), io::Error> {
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+
+    thread::Builder::new()
+        .name(String::from("terminate"))
+        .spawn(move || {
+            let mut signals = signals.forever();
+            if let Some(signal) = signals.next() {
+                tracing::info!("shutting down on signal {}", signal);
+                let _ = task::block_on(stop.send(Stop::Terminated));
+            }
+            if signals.next().is_some() {
+                tracing::error!("exiting on second signal without flushing");
+                std::process::exit(1);
+            }
+        })?;
+
+    Ok(())
+}
+
+fn number(signal: UserSignal) -> i32 {
+    match signal {
+        UserSignal::Usr1 => SIGUSR1,
+        UserSignal::Usr2 => SIGUSR2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use signal_hook::low_level::raise;
+
+    use std::time::{Duration, Instant};
+
+    use crate::testing::{self, LogDir};
+
+    /// Waits for `done` to hold, as signals are handled on threads of
+    /// their own.
+    fn wait_until(mut done: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn hangups_reopen_files_at_their_paths_under_external_rotation() {
+        task::block_on(async {
+            let dir = LogDir::new("signals-hangup");
+            let args = [
+                "--accepted-log-channels",
+                "web",
+                "--external-rotation",
+                "--flush-bytes",
+                "0",
+            ];
+            let options = Arc::new(testing::options(&dir, &args));
+            let writer = Arc::new(Mutex::new(testing::writer(&dir, &args).await));
+            on_hangup(writer.clone(), options).unwrap();
+
+            let mut locked = writer.lock().await;
+            locked
+                .write_to_channel("", "web", "before\n")
+                .await
+                .unwrap();
+            drop(locked);
+            let path = dir.path().join("web.log");
+            std::fs::rename(&path, dir.path().join("web.log.1")).unwrap();
+
+            raise(SIGHUP).unwrap();
+            wait_until(|| path.exists());
+            let mut locked = writer.lock().await;
+            locked.write_to_channel("", "web", "after\n").await.unwrap();
+
+            let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+            assert_eq!(read("web.log.1"), "before\n");
+            assert_eq!(read("web.log"), "after\n");
+        });
+    }
+
+    #[test]
+    fn user_signals_start_fresh_files() {
+        task::block_on(async {
+            let dir = LogDir::new("signals-rotate");
+            let args = ["--accepted-log-channels", "web"];
+            let writer = Arc::new(Mutex::new(testing::writer(&dir, &args).await));
+            rotate_on(UserSignal::Usr2, writer.clone()).unwrap();
+            let files = || {
+                dir.file_names()
+                    .iter()
+                    .filter(|name| name.starts_with("web_"))
+                    .count()
+            };
+            assert_eq!(files(), 1);
+
+            raise(SIGUSR2).unwrap();
+            wait_until(|| files() == 2);
+        });
+    }
+}
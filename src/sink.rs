@@ -0,0 +1,217 @@
+use chrono::{DateTime, FixedOffset};
+
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+use crate::rotation::{self, Schedule};
+use crate::router::{RotationPolicy, SinkConfig};
+use crate::runtime::{self, BufWriter, File, OpenOptions, WriteExt};
+
+pub type SinkFuture<'a> = Pin<Box<dyn Future<Output = Result<(), io::Error>> + Send + 'a>>;
+
+/// Where a channel's lines end up. Every time is handed in by the caller,
+/// which decides what file, object or batch a line belongs to.
+pub trait Sink: Send {
+    /// Writes `line`, received at `now`, as it is: a line is ended by the
+    /// newline it comes with, if any.
+    fn write<'a>(&'a mut self, line: &'a str, now: &'a DateTime<FixedOffset>) -> SinkFuture<'a>;
+
+    /// Writes a line that isn't UTF-8. Sinks taking text write it with its
+    /// invalid sequences replaced.
+    fn write_bytes<'a>(
+        &'a mut self,
+        line: &'a [u8],
+        now: &'a DateTime<FixedOffset>,
+    ) -> SinkFuture<'a> {
+        Box::pin(async move { self.write(&String::from_utf8_lossy(line), now).await })
+    }
+
+    /// Moves on to a new file, or whatever the sink writes to, ahead of its
+    /// schedule.
+    fn rotate<'a>(&'a mut self, now: &'a DateTime<FixedOffset>) -> SinkFuture<'a>;
+
+    /// Writes out whatever is buffered.
+    fn flush(&mut self) -> SinkFuture<'_>;
+
+    /// Flushes and lets go of the file, or connection; a later write opens
+    /// it again.
+    fn close(&mut self) -> SinkFuture<'_>;
+
+    /// Path of the file being written to, for sinks writing to one.
+    fn current_path(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// Discards every line, for channels that are accepted but not kept.
+#[derive(Default)]
+pub struct NullSink;
+
+impl Sink for NullSink {
+    fn write<'a>(&'a mut self, _: &'a str, _: &'a DateTime<FixedOffset>) -> SinkFuture<'a> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn rotate<'a>(&'a mut self, _: &'a DateTime<FixedOffset>) -> SinkFuture<'a> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn flush(&mut self) -> SinkFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn close(&mut self) -> SinkFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Files of a channel in a directory, one per period of its rotation and
+/// cut ahead of it past the size limit.
+pub struct FileSink {
+    name: String,
+    config: SinkConfig,
+    rotation: RotationPolicy,
+    schedule: Option<Schedule<FixedOffset>>,
+    file: Option<OpenFile>,
+}
+
+struct OpenFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    bytes: u64,
+}
+
+impl FileSink {
+    pub fn new(name: &str, config: SinkConfig, rotation: RotationPolicy) -> Self {
+        FileSink {
+            name: name.to_string(),
+            config,
+            rotation,
+            schedule: None,
+            file: None,
+        }
+    }
+
+    async fn write_line(
+        &mut self,
+        line: &str,
+        now: &DateTime<FixedOffset>,
+    ) -> Result<(), io::Error> {
+        let rotation = self.rotation.rotation;
+        let schedule = self
+            .schedule
+            .get_or_insert_with(|| Schedule::new(rotation, now));
+        if schedule.is_due(now) {
+            schedule.advance(now);
+            self.close_file().await?;
+        }
+
+        let bytes = line.len() as u64;
+        let full = match (self.rotation.max_file_size, &self.file) {
+            (Some(max), Some(file)) => file.bytes > 0 && file.bytes + bytes > max,
+            _ => false,
+        };
+        if full {
+            self.rotate_file(now).await?;
+        }
+
+        if self.file.is_none() {
+            self.file = Some(self.open().await?);
+        }
+        let sync = self.config.sync;
+        if let Some(ref mut file) = self.file {
+            file.writer.write_all(line.as_bytes()).await?;
+            file.bytes += bytes;
+            if sync {
+                file.writer.flush().await?;
+                file.writer.get_ref().sync_data().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn rotate_file(&mut self, now: &DateTime<FixedOffset>) -> Result<(), io::Error> {
+        let rotation = self.rotation.rotation;
+        self.schedule
+            .get_or_insert_with(|| Schedule::new(rotation, now))
+            .force(now);
+        self.close_file().await
+    }
+
+    async fn open(&self) -> Result<OpenFile, io::Error> {
+        let file_name = match (&self.schedule, &self.rotation.layout) {
+            (Some(schedule), Some(layout)) => layout.file_name(schedule, &self.name),
+            (Some(schedule), None) => schedule.file_name(&self.name),
+            (None, _) => format!("{}.log", self.name),
+        };
+        let mut path = self.config.dir.join(&file_name);
+        // A file cut ahead of the schedule may be stamped with the same time
+        // as one already there.
+        if self
+            .schedule
+            .as_ref()
+            .is_some_and(|schedule| schedule.seq > 0)
+        {
+            let mut seq = 0;
+            while runtime::exists(&path).await {
+                seq += 1;
+                path = self
+                    .config
+                    .dir
+                    .join(rotation::sequenced_file_name(&file_name, seq));
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        let bytes = file.metadata().await?.len();
+
+        Ok(OpenFile {
+            path,
+            writer: BufWriter::new(file),
+            bytes,
+        })
+    }
+
+    async fn flush_file(&mut self) -> Result<(), io::Error> {
+        match self.file {
+            Some(ref mut file) => file.writer.flush().await,
+            None => Ok(()),
+        }
+    }
+
+    async fn close_file(&mut self) -> Result<(), io::Error> {
+        self.flush_file().await?;
+        self.file = None;
+
+        Ok(())
+    }
+}
+
+impl Sink for FileSink {
+    fn write<'a>(&'a mut self, line: &'a str, now: &'a DateTime<FixedOffset>) -> SinkFuture<'a> {
+        Box::pin(self.write_line(line, now))
+    }
+
+    fn rotate<'a>(&'a mut self, now: &'a DateTime<FixedOffset>) -> SinkFuture<'a> {
+        Box::pin(self.rotate_file(now))
+    }
+
+    fn flush(&mut self) -> SinkFuture<'_> {
+        Box::pin(self.flush_file())
+    }
+
+    fn close(&mut self) -> SinkFuture<'_> {
+        Box::pin(self.close_file())
+    }
+
+    fn current_path(&self) -> Option<&Path> {
+        self.file.as_ref().map(|file| file.path.as_path())
+    }
+}
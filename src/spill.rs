@@ -0,0 +1,186 @@
+use async_std::fs::{self, File, OpenOptions};
+use async_std::io::{self, SeekFrom};
+use async_std::path::PathBuf;
+use async_std::prelude::*;
+
+use std::collections::VecDeque;
+
+const SEGMENT_SUFFIX: &str = ".spill";
+const MAX_SEGMENT_BYTES: u64 = 16 * 1024 * 1024;
+
+struct Segment {
+    id: u64,
+    bytes: u64,
+}
+
+/// Bounded on-disk queue of records a remote sink couldn't take yet, kept as
+/// numbered segment files of length-prefixed records. Segments left by a
+/// previous run are picked up again, and once `max_bytes` is reached the
+/// oldest segment is discarded to make room.
+pub struct Spill {
+    dir: PathBuf,
+    max_bytes: u64,
+    segment_bytes: u64,
+    segments: VecDeque<Segment>,
+    tail: Option<File>,
+    head_offset: u64,
+    dropped_bytes: u64,
+}
+
+impl Spill {
+    pub async fn open(dir: &str, max_bytes: u64) -> Result<Self, io::Error> {
+        fs::create_dir_all(dir).await?;
+
+        let mut segments = Vec::new();
+        let mut entries = fs::read_dir(dir).await?;
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            let name = entry.file_name();
+            let id = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(SEGMENT_SUFFIX))
+                .and_then(|id| id.parse().ok());
+            if let Some(id) = id {
+                let bytes = entry.metadata().await?.len();
+                segments.push(Segment { id, bytes });
+            }
+        }
+        segments.sort_by_key(|segment| segment.id);
+
+        let spill = Spill {
+            dir: PathBuf::from(dir),
+            max_bytes,
+            segment_bytes: MAX_SEGMENT_BYTES.min(max_bytes / 4).max(1),
+            segments: segments.into(),
+            tail: None,
+            head_offset: 0,
+            dropped_bytes: 0,
+        };
+        if !spill.is_empty() {
+            tracing::info!("{} bytes left in spill {}", spill.pending_bytes(), dir);
+        }
+
+        Ok(spill)
+    }
+
+    #[cfg(feature = "report")]
+    pub fn dropped_bytes(&self) -> u64 {
+        self.dropped_bytes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending_bytes() == 0
+    }
+
+    fn pending_bytes(&self) -> u64 {
+        let total: u64 = self.segments.iter().map(|segment| segment.bytes).sum();
+        total - self.head_offset
+    }
+
+    fn segment_path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{:020}{}", id, SEGMENT_SUFFIX))
+    }
+
+    pub async fn push(&mut self, record: &[u8]) -> Result<(), io::Error> {
+        let size = 4 + record.len() as u64;
+        while self.pending_bytes() + size > self.max_bytes && self.segments.len() > 1 {
+            let discarded = self.pending_bytes();
+            self.retire_head().await?;
+            self.dropped_bytes += discarded - self.pending_bytes();
+            tracing::warn!(
+                "spill {} is full, {} bytes discarded so far",
+                self.dir.display(),
+                self.dropped_bytes
+            );
+        }
+        if self.pending_bytes() + size > self.max_bytes {
+            self.dropped_bytes += size;
+            return Ok(());
+        }
+
+        let segment_full = match self.segments.back() {
+            Some(segment) => segment.bytes > 0 && segment.bytes + size > self.segment_bytes,
+            None => true,
+        };
+        if segment_full {
+            let id = self.segments.back().map_or(0, |segment| segment.id + 1);
+            self.segments.push_back(Segment { id, bytes: 0 });
+            self.tail = None;
+        }
+
+        let tail_id = self.segments.back().map_or(0, |segment| segment.id);
+        if self.tail.is_none() {
+            let path = self.segment_path(tail_id);
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await?;
+            self.tail = Some(file);
+        }
+        if let Some(ref mut tail) = self.tail {
+            let mut frame = Vec::with_capacity(size as usize);
+            frame.extend_from_slice(&(record.len() as u32).to_be_bytes());
+            frame.extend_from_slice(record);
+            tail.write_all(&frame).await?;
+            tail.flush().await?;
+        }
+        if let Some(segment) = self.segments.back_mut() {
+            segment.bytes += size;
+        }
+
+        Ok(())
+    }
+
+    /// The oldest spilled record, left in place until `advance` is called.
+    pub async fn peek(&mut self) -> Result<Option<Vec<u8>>, io::Error> {
+        loop {
+            let (id, bytes) = match self.segments.front() {
+                Some(segment) => (segment.id, segment.bytes),
+                None => return Ok(None),
+            };
+
+            if self.head_offset < bytes {
+                match self.read_record(id).await {
+                    Ok(record) => return Ok(Some(record)),
+                    // Cut short by a crash while spilling; nothing after it is
+                    // readable.
+                    Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                        tracing::warn!("discarding truncated spill segment {}", id);
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+
+            self.retire_head().await?;
+        }
+    }
+
+    pub fn advance(&mut self, record: &[u8]) {
+        self.head_offset += 4 + record.len() as u64;
+    }
+
+    async fn read_record(&self, id: u64) -> Result<Vec<u8>, io::Error> {
+        let mut file = File::open(self.segment_path(id)).await?;
+        file.seek(SeekFrom::Start(self.head_offset)).await?;
+
+        let mut length = [0u8; 4];
+        file.read_exact(&mut length).await?;
+        let mut record = vec![0; u32::from_be_bytes(length) as usize];
+        file.read_exact(&mut record).await?;
+
+        Ok(record)
+    }
+
+    async fn retire_head(&mut self) -> Result<(), io::Error> {
+        if let Some(segment) = self.segments.pop_front() {
+            if self.segments.is_empty() {
+                self.tail = None;
+            }
+            self.head_offset = 0;
+            fs::remove_file(self.segment_path(segment.id)).await?;
+        }
+
+        Ok(())
+    }
+}
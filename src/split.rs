@@ -0,0 +1,106 @@
+use async_std::fs::File;
+use async_std::io::{self, BufReader};
+use async_std::prelude::*;
+use async_std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Local};
+use regex::Regex;
+use structopt::StructOpt;
+
+use std::sync::OnceLock;
+
+use crate::stamp::TimestampFormat;
+use crate::{clock, configure, failure, finish, input, CliOptions, FileWriter, INPUT_BUFFER_BYTES};
+
+/// How lines give their time, set once by `run`.
+static TIMESTAMPS: OnceLock<Timestamps> = OnceLock::new();
+
+/// Splits existing files, such as a combined log kept before the router was
+/// deployed, into channels as the router would have: `log-revolve-rs split
+/// --input big.log --log-dir /var/log/app --accepted-log-channels app,db`.
+/// Every `--input` is a file, read to its end one after the other, and lines
+/// are written at the time they carry, read as `--timestamp-format` says,
+/// rather than the clock's. A line carrying none takes the time of the one
+/// before it, and those before the first line carrying one take its time.
+#[derive(StructOpt)]
+#[structopt(name = "split")]
+pub struct SplitOptions {
+    /// Pattern finding a line's timestamp: its first group, or the whole
+    /// match if it has none
+    #[structopt(long, default_value = r"^\S+")]
+    timestamp_regex: Regex,
+
+    #[structopt(flatten)]
+    router: CliOptions,
+}
+
+struct Timestamps {
+    regex: Regex,
+    format: TimestampFormat,
+}
+
+impl Timestamps {
+    fn parse(&self, line: &str) -> Option<DateTime<Local>> {
+        let captures = self.regex.captures(line)?;
+        let text = captures.get(1).or_else(|| captures.get(0))?.as_str();
+
+        self.format.parse(text)
+    }
+}
+
+/// Moves the clock to the time `line` carries, if it carries one and lines
+/// are being split.
+pub fn see(line: &str) {
+    if let Some(time) = TIMESTAMPS
+        .get()
+        .and_then(|timestamps| timestamps.parse(line))
+    {
+        clock::set(time);
+    }
+}
+
+pub async fn run(options: SplitOptions) -> Result<(), io::Error> {
+    let SplitOptions {
+        timestamp_regex,
+        router: mut cli_options,
+    } = options;
+    if cli_options.inputs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "split needs at least one --input file",
+        ));
+    }
+    configure(&mut cli_options).await?;
+    let timestamps = TIMESTAMPS.get_or_init(|| Timestamps {
+        regex: timestamp_regex,
+        format: cli_options.timestamp_format.clone(),
+    });
+    clock::follow_lines();
+
+    // Files are opened as the writer starts, so it starts at the first time
+    // the lines carry.
+    let mut lines = BufReader::new(File::open(&cli_options.inputs[0]).await?).lines();
+    while let Some(line) = lines.next().await {
+        if let Some(time) = timestamps.parse(&line?) {
+            clock::set(time);
+            break;
+        }
+    }
+
+    let cli_options = Arc::new(cli_options);
+    let shared_writer = Arc::new(Mutex::new(FileWriter::with_options(&cli_options).await?));
+    failure::started();
+    for path in cli_options.inputs.iter() {
+        tracing::info!("splitting {}", path);
+        let file = File::open(path).await?;
+        input::read(
+            path,
+            BufReader::with_capacity(INPUT_BUFFER_BYTES, file),
+            shared_writer.clone(),
+            cli_options.clone(),
+        )
+        .await?;
+    }
+
+    finish(&cli_options, &shared_writer).await
+}
@@ -0,0 +1,75 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset};
+#[cfg(feature = "split")]
+use chrono::{Local, NaiveDateTime, TimeZone};
+
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+/// What `to_rfc3339_opts` writes given milliseconds, formatted in place.
+const RFC3339_MILLIS: &str = "%Y-%m-%dT%H:%M:%S%.3f%:z";
+
+/// How the receipt time prefixed to each line is written: `rfc3339`
+/// (2024-06-01T13:45:00.123+02:00), `epoch-millis` (1717242300123), or a
+/// strftime format such as `%d/%b/%Y:%H:%M:%S`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TimestampFormat {
+    Rfc3339,
+    EpochMillis,
+    Strftime(String),
+}
+
+impl TimestampFormat {
+    /// Appends `line` to `stamped`, prefixed with `time` and a space.
+    pub fn stamp(&self, time: &DateTime<FixedOffset>, line: &str, stamped: &mut String) {
+        // Writing to a `String` can't fail.
+        let _ = self.write(time, stamped);
+        stamped.push(' ');
+        stamped.push_str(line);
+    }
+
+    /// Writes `time` this way to `out`.
+    pub fn write<W: Write>(&self, time: &DateTime<FixedOffset>, out: &mut W) -> fmt::Result {
+        match self {
+            TimestampFormat::Rfc3339 => write!(out, "{}", time.format(RFC3339_MILLIS)),
+            TimestampFormat::EpochMillis => write!(out, "{}", time.timestamp_millis()),
+            TimestampFormat::Strftime(format) => write!(out, "{}", time.format(format)),
+        }
+    }
+
+    /// Reads a time written this way back, taking one written without an
+    /// offset in the local zone.
+    #[cfg(feature = "split")]
+    pub fn parse(&self, text: &str) -> Option<DateTime<Local>> {
+        match self {
+            TimestampFormat::Rfc3339 => DateTime::parse_from_rfc3339(text)
+                .ok()
+                .map(|time| time.with_timezone(&Local)),
+            TimestampFormat::EpochMillis => text
+                .parse()
+                .ok()
+                .and_then(|millis| Local.timestamp_millis_opt(millis).single()),
+            TimestampFormat::Strftime(format) => match DateTime::parse_from_str(text, format) {
+                Ok(time) => Some(time.with_timezone(&Local)),
+                Err(_) => NaiveDateTime::parse_from_str(text, format)
+                    .ok()
+                    .and_then(|time| Local.from_local_datetime(&time).earliest()),
+            },
+        }
+    }
+}
+
+impl FromStr for TimestampFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rfc3339" => Ok(TimestampFormat::Rfc3339),
+            "epoch-millis" => Ok(TimestampFormat::EpochMillis),
+            _ if s.is_empty() || StrftimeItems::new(s).any(|item| item == Item::Error) => {
+                Err(format!("invalid timestamp format: {}", s))
+            }
+            _ => Ok(TimestampFormat::Strftime(s.to_string())),
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex as SyncMutex, OnceLock};
+use std::time::Duration;
+
+use crate::{compress, file_finished, report, FileWriter};
+
+/// The state file of this run, set once at startup by `resume`.
+static STATE: OnceLock<StateFile> = OnceLock::new();
+
+/// What a run leaves behind for the next one, in `<state-dir>/state.json`:
+/// each channel's current file and how much of it was written, and the
+/// files rotated away from that are still to be compressed or handed to the
+/// rotate hook. Uploads keep a manifest of their own.
+#[derive(Default, Serialize, Deserialize)]
+struct State {
+    #[serde(default)]
+    channels: BTreeMap<String, Checkpoint>,
+    #[serde(default)]
+    pending: Vec<Task>,
+}
+
+/// A channel's current file and the bytes of it written out, batched lines
+/// left out.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Checkpoint {
+    path: String,
+    bytes: u64,
+}
+
+/// A file its channel is done with, and what is left to do with it.
+#[derive(Clone, Serialize, Deserialize)]
+struct Task {
+    channel: String,
+    path: String,
+    stage: Stage,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Stage {
+    /// Waiting to be compressed, if its channel compresses files.
+    Compress,
+    /// Final, waiting for the rotate hook.
+    Finish,
+}
+
+struct StateFile {
+    path: PathBuf,
+    state: SyncMutex<State>,
+}
+
+impl StateFile {
+    fn update<F: FnOnce(&mut State)>(&self, change: F) {
+        let mut state = self.state.lock().unwrap();
+        change(&mut state);
+        if let Err(error) = self.save(&state) {
+            tracing::warn!("unable to save {}: {}", self.path.display(), error);
+            report::record_error("state", error);
+        }
+    }
+
+    /// Written aside and renamed, so a crash never leaves half a file.
+    fn save(&self, state: &State) -> Result<(), io::Error> {
+        let contents = serde_json::to_vec_pretty(state)?;
+        let partial = self.path.with_extension("json.partial");
+        fs::write(&partial, contents)?;
+        fs::rename(&partial, &self.path)
+    }
+}
+
+/// Loads what the last run left in `dir` and picks up its work: files it
+/// rotated away from without finishing, and current files it never got to
+/// rotate away from because the period ended while it was down. A current
+/// file found shorter than it was written is reported.
+pub fn resume(dir: &str, writer: &FileWriter) -> Result<(), io::Error> {
+    fs::create_dir_all(dir)?;
+    let path = Path::new(dir).join("state.json");
+    let previous: State = match fs::read(&path) {
+        Ok(contents) => serde_json::from_slice(&contents).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })?,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => State::default(),
+        Err(error) => return Err(error),
+    };
+
+    let mut pending = previous.pending;
+    for (channel, checkpoint) in previous.channels {
+        let current = writer
+            .handle(&channel)
+            .map(|handle| handle.current_path.as_str());
+        if current != Some(checkpoint.path.as_str()) {
+            if Path::new(&checkpoint.path).is_file() {
+                pending.push(Task {
+                    channel,
+                    path: checkpoint.path,
+                    stage: Stage::Compress,
+                });
+            }
+            continue;
+        }
+
+        let bytes = fs::metadata(&checkpoint.path).map_or(0, |metadata| metadata.len());
+        if bytes < checkpoint.bytes {
+            let message = format!(
+                "{} holds {} bytes, {} were written to it before the restart",
+                checkpoint.path, bytes, checkpoint.bytes
+            );
+            tracing::warn!("{}", message);
+            report::record_error("state", message);
+        }
+    }
+
+    let pending: Vec<Task> = pending.into_iter().filter_map(located).collect();
+    let state = StateFile {
+        path,
+        state: SyncMutex::new(State {
+            channels: checkpoints(writer),
+            pending: pending.clone(),
+        }),
+    };
+    state.save(&state.state.lock().unwrap())?;
+    let _ = STATE.set(state);
+
+    for task in pending {
+        tracing::info!("resuming work on {}", task.path);
+        match task.stage {
+            Stage::Compress => {
+                let compression = writer.channel_settings.compression_of(&task.channel);
+                let channel = task.channel;
+                compress::spawn(task.path, compression, false, move |path| {
+                    file_finished(channel, path)
+                });
+            }
+            Stage::Finish => file_finished(task.channel, task.path),
+        }
+    }
+
+    Ok(())
+}
+
+/// `task` as it is found on disk: a file waiting for compression may have
+/// been compressed already, and one that is gone is given up on.
+fn located(mut task: Task) -> Option<Task> {
+    if Path::new(&task.path).is_file() {
+        return Some(task);
+    }
+
+    let archive = ["gz", "zst"]
+        .iter()
+        .map(|extension| format!("{}.{}", task.path, extension))
+        .find(|archive| Path::new(archive).is_file());
+    match archive {
+        Some(archive) if task.stage == Stage::Compress => {
+            task.path = archive;
+            task.stage = Stage::Finish;
+            Some(task)
+        }
+        _ => {
+            tracing::warn!("{} is gone, giving up on it", task.path);
+            None
+        }
+    }
+}
+
+fn checkpoints(writer: &FileWriter) -> BTreeMap<String, Checkpoint> {
+    writer
+        .handles()
+        .map(|(channel, handle)| {
+            let checkpoint = Checkpoint {
+                path: handle.current_path.clone(),
+                bytes: handle.file_bytes - handle.batch.len() as u64,
+            };
+            (channel.to_string(), checkpoint)
+        })
+        .collect()
+}
+
+/// Records `channel` moving on from `previous` to `current`, the previous
+/// file waiting to be compressed.
+pub fn rotated(channel: &str, previous: &str, current: &str) {
+    if let Some(state) = STATE.get() {
+        state.update(|state| {
+            let checkpoint = Checkpoint {
+                path: current.to_string(),
+                bytes: 0,
+            };
+            state.channels.insert(channel.to_string(), checkpoint);
+            state.pending.push(Task {
+                channel: channel.to_string(),
+                path: previous.to_string(),
+                stage: Stage::Compress,
+            });
+        });
+    }
+}
+
+/// Records a rotated file as final at `path`, the file itself or the
+/// archive it was compressed into.
+pub fn compressed(path: &str) {
+    if let Some(state) = STATE.get() {
+        state.update(|state| {
+            let task = state.pending.iter_mut().find(|task| {
+                task.stage == Stage::Compress
+                    && path
+                        .strip_prefix(task.path.as_str())
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+            });
+            if let Some(task) = task {
+                task.path = path.to_string();
+                task.stage = Stage::Finish;
+            }
+        });
+    }
+}
+
+/// Records that nothing is left to do with the file at `path`.
+pub fn finished(path: &str) {
+    if let Some(state) = STATE.get() {
+        state.update(|state| {
+            state
+                .pending
+                .retain(|task| task.stage != Stage::Finish || task.path != path)
+        });
+    }
+}
+
+/// Records how far each channel's current file was written.
+pub fn checkpoint(writer: &FileWriter) {
+    if let Some(state) = STATE.get() {
+        let channels = checkpoints(writer);
+        if state.state.lock().unwrap().channels != channels {
+            state.update(|state| state.channels = channels);
+        }
+    }
+}
+
+/// Records how far each channel got every `interval`.
+pub async fn checkpoint_every(writer: Arc<Mutex<FileWriter>>, interval: Duration) {
+    loop {
+        task::sleep(interval).await;
+
+        checkpoint(&*writer.lock().await);
+    }
+}
@@ -0,0 +1,211 @@
+use async_std::fs;
+use async_std::io;
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+use chrono::Utc;
+#[cfg(any(feature = "control-socket", feature = "http-admin"))]
+use chrono::{DateTime, Local, TimeZone};
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::{report, FileWriter};
+
+const SECONDS_PER_HOUR: i64 = 3600;
+
+/// Lines and bytes a channel wrote within one hour.
+#[derive(Default, Clone, Copy)]
+pub struct Counters {
+    pub lines: u64,
+    pub bytes: u64,
+}
+
+/// Per-channel write counters bucketed by hour. With a state file they are
+/// loaded on startup and saved as they go, so volumes from before a restart
+/// can still be reported. The file holds one `<channel> <hour> <lines>
+/// <bytes>` line per bucket, `hour` counting hours since the epoch.
+pub struct HourlyStats {
+    path: Option<String>,
+    retention_hours: i64,
+    pub channels: BTreeMap<String, BTreeMap<i64, Counters>>,
+}
+
+impl HourlyStats {
+    pub async fn load(path: Option<&str>, retention_hours: i64) -> Result<Self, io::Error> {
+        let mut stats = HourlyStats {
+            path: path.map(|path| path.to_string()),
+            retention_hours,
+            channels: BTreeMap::new(),
+        };
+
+        let contents = match path {
+            Some(path) => match fs::read_to_string(path).await {
+                Ok(contents) => contents,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(stats),
+                Err(error) => return Err(error),
+            },
+            None => return Ok(stats),
+        };
+
+        for line in contents.lines() {
+            match parse_bucket(line) {
+                Some((channel, hour, counters)) => {
+                    stats
+                        .channels
+                        .entry(channel.to_string())
+                        .or_default()
+                        .insert(hour, counters);
+                }
+                None => tracing::warn!("skipping malformed stats line: {}", line),
+            }
+        }
+        stats.prune();
+
+        Ok(stats)
+    }
+
+    pub fn record(&mut self, channel: &str, bytes: usize) {
+        let hour = Utc::now().timestamp().div_euclid(SECONDS_PER_HOUR);
+        let counters = match self.channels.get_mut(channel) {
+            Some(hours) => hours.entry(hour).or_default(),
+            None => self
+                .channels
+                .entry(channel.to_string())
+                .or_default()
+                .entry(hour)
+                .or_default(),
+        };
+
+        counters.lines += 1;
+        counters.bytes += bytes as u64;
+    }
+
+    /// Drops the hours that have fallen out of the retention window.
+    fn prune(&mut self) {
+        let oldest = Utc::now().timestamp().div_euclid(SECONDS_PER_HOUR) - self.retention_hours;
+        for hours in self.channels.values_mut() {
+            *hours = hours.split_off(&oldest);
+        }
+        self.channels.retain(|_, hours| !hours.is_empty());
+    }
+
+    pub async fn save(&mut self) -> Result<(), io::Error> {
+        self.prune();
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        let mut contents = String::new();
+        for (channel, hours) in self.channels.iter() {
+            for (hour, counters) in hours.iter() {
+                contents.push_str(&format!(
+                    "{} {} {} {}\n",
+                    channel, hour, counters.lines, counters.bytes
+                ));
+            }
+        }
+
+        // Written aside and renamed, so a crash never leaves half a file.
+        let partial = format!("{}.partial", path);
+        fs::write(&partial, contents).await?;
+        fs::rename(&partial, path).await
+    }
+}
+
+/// `<channel> <hour> <lines> <bytes>`, taking the numbers from the end so a
+/// channel name may hold spaces.
+fn parse_bucket(line: &str) -> Option<(&str, i64, Counters)> {
+    let mut fields = line.rsplitn(4, ' ');
+    let bytes = fields.next()?.parse().ok()?;
+    let lines = fields.next()?.parse().ok()?;
+    let hour = fields.next()?.parse().ok()?;
+    let channel = fields.next()?;
+
+    Some((channel, hour, Counters { lines, bytes }))
+}
+
+/// Start of an hour counted since the epoch, in local time.
+#[cfg(any(feature = "control-socket", feature = "http-admin"))]
+pub fn hour_start(hour: i64) -> DateTime<Local> {
+    Local.timestamp_opt(hour * SECONDS_PER_HOUR, 0).unwrap()
+}
+
+/// Saves the counters every `interval`.
+pub async fn save_every(writer: Arc<Mutex<FileWriter>>, interval: Duration) {
+    loop {
+        task::sleep(interval).await;
+
+        let mut writer = writer.lock().await;
+        if let Err(error) = writer.stats.save().await {
+            tracing::warn!("unable to save stats: {}", error);
+            report::record_error("stats", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::LogDir;
+
+    fn current_hour() -> i64 {
+        Utc::now().timestamp().div_euclid(SECONDS_PER_HOUR)
+    }
+
+    #[test]
+    fn buckets_take_channel_names_with_spaces() {
+        let (channel, hour, counters) = parse_bucket("my app 480000 12 3400").unwrap();
+        assert_eq!((channel, hour), ("my app", 480000));
+        assert_eq!((counters.lines, counters.bytes), (12, 3400));
+
+        assert!(parse_bucket("web 480000 12").is_none());
+        assert!(parse_bucket("web 480000 twelve 3400").is_none());
+    }
+
+    #[test]
+    fn counters_survive_a_restart() {
+        task::block_on(async {
+            let dir = LogDir::new("stats-restart");
+            let path = dir.path().join("stats");
+            let path = path.to_str().unwrap();
+            let hour = current_hour();
+            let contents = format!(
+                "web {} 5 500\nweb {} 1 10\nnot a bucket\n",
+                hour - 1,
+                hour - 100
+            );
+            std::fs::write(path, contents).unwrap();
+
+            let mut stats = HourlyStats::load(Some(path), 24).await.unwrap();
+            // The bucket past retention is dropped as it is loaded.
+            assert_eq!(stats.channels["web"].len(), 1);
+            stats.record("web", 40);
+            stats.record("web", 2);
+            stats.record("app", 7);
+            stats.save().await.unwrap();
+            assert!(!dir.path().join("stats.partial").exists());
+
+            let stats = HourlyStats::load(Some(path), 24).await.unwrap();
+            let web: Vec<(i64, u64, u64)> = stats.channels["web"]
+                .iter()
+                .map(|(hour, counters)| (*hour, counters.lines, counters.bytes))
+                .collect();
+            assert_eq!(web, [(hour - 1, 5, 500), (hour, 2, 42)]);
+            assert_eq!(stats.channels["app"][&hour].lines, 1);
+        });
+    }
+
+    #[test]
+    fn a_missing_state_file_starts_afresh() {
+        task::block_on(async {
+            let dir = LogDir::new("stats-missing");
+            let path = dir.path().join("stats");
+
+            let stats = HourlyStats::load(path.to_str(), 24).await.unwrap();
+            assert!(stats.channels.is_empty());
+        });
+    }
+}
@@ -0,0 +1,116 @@
+use async_std::io;
+use async_std::net::UdpSocket;
+use async_std::sync::{Arc, Mutex};
+
+use std::str::FromStr;
+
+use log_revolve_rs::framing::syslog::{self, SyslogMessage};
+
+use crate::{input, report, FileWriter};
+
+/// Largest datagram read whole; anything longer is cut by the socket.
+const MAX_DATAGRAM_BYTES: usize = 64 * 1024;
+
+/// Facility names in the order of their codes, `local0` to `local7` last.
+const FACILITIES: [&str; 24] = [
+    "kern",
+    "user",
+    "mail",
+    "daemon",
+    "auth",
+    "syslog",
+    "lpr",
+    "news",
+    "uucp",
+    "cron",
+    "authpriv",
+    "ftp",
+    "ntp",
+    "security",
+    "console",
+    "solaris-cron",
+    "local0",
+    "local1",
+    "local2",
+    "local3",
+    "local4",
+    "local5",
+    "local6",
+    "local7",
+];
+
+/// Which part of a syslog message names its channel: `app-name`, the
+/// facility's name for messages without one, or `facility`, e.g. `local3`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SyslogChannel {
+    AppName,
+    Facility,
+}
+
+impl SyslogChannel {
+    fn of<'a>(self, message: &SyslogMessage<'a>) -> &'a str {
+        let facility = FACILITIES
+            .get(usize::from(message.facility))
+            .copied()
+            .unwrap_or("syslog");
+        match self {
+            SyslogChannel::AppName => message.app_name.unwrap_or(facility),
+            SyslogChannel::Facility => facility,
+        }
+    }
+}
+
+impl FromStr for SyslogChannel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "app-name" => Ok(SyslogChannel::AppName),
+            "facility" => Ok(SyslogChannel::Facility),
+            _ => Err(format!("unknown syslog channel source: {}", s)),
+        }
+    }
+}
+
+/// Writes every datagram received on `socket`, RFC 5424 or BSD syslog, to
+/// the channel named by `channel`. Datagrams without a valid `<PRI>` go to
+/// the inapt file as malformed.
+pub async fn serve(socket: UdpSocket, writer: Arc<Mutex<FileWriter>>, channel: SyslogChannel) {
+    let mut buffer = vec![0; MAX_DATAGRAM_BYTES];
+    loop {
+        let (length, peer) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(error) => {
+                tracing::warn!("unable to receive syslog datagram: {}", error);
+                continue;
+            }
+        };
+        let datagram = String::from_utf8_lossy(&buffer[..length]);
+
+        let mut writer = writer.lock().await;
+        if let Err(error) = write(&mut writer, &datagram, channel).await {
+            tracing::warn!("unable to write syslog datagram from {}: {}", peer, error);
+            report::record_error("syslog", error);
+        }
+        input::unlock_throttled(writer).await;
+    }
+}
+
+async fn write(
+    writer: &mut FileWriter,
+    datagram: &str,
+    channel: SyslogChannel,
+) -> Result<(), io::Error> {
+    let message = match syslog::parse(datagram) {
+        Some(message) => message,
+        None => {
+            let line = format!("{}\n", datagram.trim_end());
+            return writer.write_inapt("malformed", None, &line).await;
+        }
+    };
+
+    let line = format!("{}\n", message.message);
+    writer
+        .write_to_channel("syslog", channel.of(&message), &line)
+        .await
+}
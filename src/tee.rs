@@ -0,0 +1,61 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::report;
+
+/// Stream of the router's own process a channel's lines are copied to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TeeStream {
+    Stdout,
+    Stderr,
+}
+
+impl FromStr for TeeStream {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stdout" => Ok(TeeStream::Stdout),
+            "stderr" => Ok(TeeStream::Stderr),
+            _ => Err(format!("unknown tee stream: {}", s)),
+        }
+    }
+}
+
+/// A channel whose lines also go to stdout or stderr, written
+/// `<channel>=stderr`, so an orchestrator capturing the router's output
+/// sees them while they still land in the channel's files.
+#[derive(Clone, Debug)]
+pub struct TeeChannel {
+    pub channel: String,
+    pub stream: TeeStream,
+}
+
+impl FromStr for TeeChannel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (channel, stream) = s
+            .split_once('=')
+            .filter(|(channel, _)| !channel.is_empty())
+            .ok_or_else(|| format!("expected `<channel>=stdout|stderr`, got `{}`", s))?;
+
+        Ok(TeeChannel {
+            channel: channel.to_string(),
+            stream: stream.parse()?,
+        })
+    }
+}
+
+/// Copies `line` to `stream`, whole, so it doesn't interleave with the
+/// router's own diagnostics. A failed copy is logged, never fatal.
+pub fn write(stream: TeeStream, line: &str) {
+    let (name, result) = match stream {
+        TeeStream::Stdout => ("stdout", io::stdout().lock().write_all(line.as_bytes())),
+        TeeStream::Stderr => ("stderr", io::stderr().lock().write_all(line.as_bytes())),
+    };
+    if let Err(error) = result {
+        tracing::warn!("unable to copy a line to {}: {}", name, error);
+        report::record_error("tee", error);
+    }
+}
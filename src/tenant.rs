@@ -0,0 +1,298 @@
+use async_std::fs;
+use async_std::io;
+use async_std::path::Path;
+use async_std::prelude::*;
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, SystemTime};
+
+use log_revolve_rs::platform::Platform;
+
+use crate::quota::Quota;
+use crate::{report, FileWriter};
+
+/// How often tenant directories are pruned of files past their retention.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Teams sharing one router, each with a directory of its own under the log
+/// directory. A tenant's lines name their channel `<tenant>/<channel>`,
+/// either in the frame or through a connection opening with
+/// `@tenant <name>`, and are written to `<log_dir>/<tenant>/`. Channel
+/// settings apply to every tenant's channel of that name; quotas, retention
+/// and the channels a tenant may write to can be given per tenant on top.
+pub struct Tenants {
+    pub names: Vec<String>,
+    pub quotas: BTreeMap<String, Quota>,
+    pub retention: BTreeMap<String, Duration>,
+    /// Channels of the tenants that don't take every accepted one.
+    pub channels: BTreeMap<String, Vec<String>>,
+}
+
+impl Tenants {
+    pub fn new(
+        names: Vec<String>,
+        quotas: BTreeMap<String, Quota>,
+        retention: BTreeMap<String, Duration>,
+        channels: BTreeMap<String, Vec<String>>,
+    ) -> Result<Self, io::Error> {
+        for name in names.iter() {
+            Platform::CURRENT.check_file_name(name).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid tenant name: {}", e),
+                )
+            })?;
+        }
+        for tenant in quotas.keys().chain(retention.keys()).chain(channels.keys()) {
+            if !names.contains(tenant) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown tenant `{}`", tenant),
+                ));
+            }
+        }
+
+        Ok(Tenants {
+            names,
+            quotas,
+            retention,
+            channels,
+        })
+    }
+
+    /// Every tenant's channel of each of the `accepted` ones, or of its own
+    /// channels if it was given some.
+    pub fn channels(&self, accepted: &[&str]) -> Vec<String> {
+        self.names
+            .iter()
+            .flat_map(|tenant| {
+                let channels: Vec<&str> = match self.channels.get(tenant) {
+                    Some(channels) => channels.iter().map(String::as_str).collect(),
+                    None => accepted.to_vec(),
+                };
+                channels
+                    .into_iter()
+                    .map(move |channel| format!("{}/{}", tenant, channel))
+            })
+            .collect()
+    }
+
+    pub async fn create_dirs(&self, log_dir: &str) -> Result<(), io::Error> {
+        for tenant in self.names.iter() {
+            fs::create_dir_all(Path::new(log_dir).join(tenant)).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The tenant and channel of a `<tenant>/<channel>` name.
+pub fn split(channel: &str) -> Option<(&str, &str)> {
+    channel.split_once('/')
+}
+
+/// Removes the files of `<log_dir>/<tenant>/` last written to longer than
+/// `max_age` ago, except the `current` paths and files being compressed,
+/// returning how many went.
+async fn prune(
+    log_dir: &str,
+    tenant: &str,
+    max_age: Duration,
+    current: &BTreeSet<String>,
+) -> Result<usize, io::Error> {
+    let now = SystemTime::now();
+    let dir = Path::new(log_dir).join(tenant);
+
+    let mut files = Vec::new();
+    let mut entries = fs::read_dir(&dir).await?;
+    while let Some(entry) = entries.next().await {
+        let entry = entry?;
+        let metadata = entry.metadata().await?;
+        if metadata.is_file() {
+            files.push((entry.file_name().to_string_lossy().into_owned(), metadata));
+        }
+    }
+
+    let names: BTreeSet<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+    let in_use = |name: &str| {
+        let path = dir.join(name);
+        path.to_str().is_some_and(|path| current.contains(path))
+            || name.ends_with(".partial")
+            || names.contains(format!("{}.gz.partial", name).as_str())
+            || names.contains(format!("{}.zst.partial", name).as_str())
+    };
+
+    let mut removed = 0;
+    for (name, metadata) in files.iter() {
+        let age = now.duration_since(metadata.modified()?).unwrap_or_default();
+        if age > max_age && !in_use(name) {
+            fs::remove_file(dir.join(name)).await?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+pub async fn prune_every(
+    writer: Arc<Mutex<FileWriter>>,
+    log_dir: String,
+    retention: BTreeMap<String, Duration>,
+) {
+    loop {
+        let current = writer.lock().await.current_paths();
+
+        for (tenant, max_age) in retention.iter() {
+            match prune(&log_dir, tenant, *max_age, &current).await {
+                Ok(0) => {}
+                Ok(removed) => {
+                    tracing::info!("removed {} expired files of tenant {}", removed, tenant)
+                }
+                Err(error) => {
+                    tracing::warn!("unable to prune files of tenant {}: {}", tenant, error);
+                    report::record_error("tenant", error);
+                }
+            }
+        }
+
+        task::sleep(PRUNE_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{self, LogDir};
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn settings_name_known_tenants_only() {
+        let retention = [("initech".to_string(), Duration::from_secs(60))].into();
+        let error = Tenants::new(
+            names(&["acme"]),
+            BTreeMap::new(),
+            retention,
+            BTreeMap::new(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(error.to_string(), "unknown tenant `initech`");
+
+        let error = Tenants::new(
+            names(&["a/b"]),
+            BTreeMap::new(),
+            BTreeMap::new(),
+            BTreeMap::new(),
+        )
+        .err()
+        .unwrap();
+        assert!(
+            error.to_string().starts_with("invalid tenant name: "),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn tenants_take_every_accepted_channel_unless_given_theirs() {
+        let channels = [("globex".to_string(), names(&["billing"]))].into();
+        let tenants = Tenants::new(
+            names(&["acme", "globex"]),
+            BTreeMap::new(),
+            BTreeMap::new(),
+            channels,
+        )
+        .unwrap();
+        assert_eq!(
+            tenants.channels(&["web", "db"]),
+            vec!["acme/web", "acme/db", "globex/billing"]
+        );
+
+        assert_eq!(split("acme/web"), Some(("acme", "web")));
+        assert_eq!(split("web"), None);
+    }
+
+    #[test]
+    fn expired_files_go_unless_still_in_use() {
+        task::block_on(async {
+            let dir = LogDir::new("tenant-prune");
+            let tenant_dir = dir.path().join("acme");
+            std::fs::create_dir(&tenant_dir).unwrap();
+            let long_ago = SystemTime::now() - Duration::from_secs(3600);
+            for file_name in [
+                "old.log",
+                "current.log",
+                "compressing.log",
+                "compressing.log.gz.partial",
+                "fresh.log",
+            ] {
+                let file = std::fs::File::create(tenant_dir.join(file_name)).unwrap();
+                if file_name != "fresh.log" {
+                    file.set_modified(long_ago).unwrap();
+                }
+            }
+
+            let log_dir = dir.path().to_str().unwrap();
+            let current = tenant_dir.join("current.log").to_str().unwrap().to_string();
+            let current = [current].into();
+            let removed = prune(log_dir, "acme", Duration::from_secs(60), &current)
+                .await
+                .unwrap();
+            assert_eq!(removed, 1);
+
+            let mut left: Vec<_> = std::fs::read_dir(&tenant_dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect();
+            left.sort();
+            assert_eq!(
+                left,
+                vec![
+                    "compressing.log",
+                    "compressing.log.gz.partial",
+                    "current.log",
+                    "fresh.log"
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn tenant_lines_go_to_their_own_directory() {
+        task::block_on(async {
+            let dir = LogDir::new("tenant-dirs");
+            let args = [
+                "--accepted-log-channels",
+                "web",
+                "--tenants",
+                "acme,globex",
+                "--flush-bytes",
+                "0",
+            ];
+            let mut writer = testing::writer(&dir, &args).await;
+            writer
+                .write_to_channel("", "acme/web", "GET /a\n")
+                .await
+                .unwrap();
+            writer
+                .write_to_channel("", "web", "GET /b\n")
+                .await
+                .unwrap();
+            writer.sync_all().await.unwrap();
+
+            let acme: Vec<_> = std::fs::read_dir(dir.path().join("acme"))
+                .unwrap()
+                .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+                .collect();
+            assert_eq!(acme, vec!["GET /a\n"]);
+            assert!(dir.path().join("globex").is_dir());
+            assert_eq!(dir.read("web_"), "GET /b\n");
+        });
+    }
+}
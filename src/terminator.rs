@@ -0,0 +1,82 @@
+use std::str::FromStr;
+
+use log_revolve_rs::platform;
+
+/// What ends each line written to a channel's files, for downstream parsers
+/// that want CRLF or records ended by a byte sequence of their own: `lf`,
+/// `crlf`, `nul` or `hex:<bytes>`, e.g. `hex:1e`. Lines arrive ending in
+/// `\n` or `\r\n`, which is replaced.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LineTerminator(String);
+
+impl LineTerminator {
+    /// Appends `line` to `terminated`, ended by the terminator instead.
+    pub fn apply(&self, line: &[u8], terminated: &mut Vec<u8>) {
+        terminated.extend_from_slice(platform::strip_line_ending_bytes(line));
+        terminated.extend_from_slice(self.0.as_bytes());
+    }
+}
+
+impl FromStr for LineTerminator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let terminator = match s {
+            "lf" => String::from("\n"),
+            "crlf" => String::from("\r\n"),
+            "nul" => String::from("\0"),
+            _ => {
+                let invalid =
+                    || format!("expected `lf`, `crlf`, `nul` or `hex:<bytes>`, got `{}`", s);
+                let hex = s.strip_prefix("hex:").ok_or_else(invalid)?;
+                if hex.is_empty() || hex.len() % 2 != 0 || !hex.is_ascii() {
+                    return Err(invalid());
+                }
+                let bytes = (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                    .collect::<Result<Vec<u8>, _>>()
+                    .map_err(|_| invalid())?;
+                String::from_utf8(bytes).map_err(|_| invalid())?
+            }
+        };
+
+        Ok(LineTerminator(terminator))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terminated(terminator: &str, line: &[u8]) -> Vec<u8> {
+        let mut terminated = Vec::new();
+        terminator
+            .parse::<LineTerminator>()
+            .unwrap()
+            .apply(line, &mut terminated);
+        terminated
+    }
+
+    #[test]
+    fn line_endings_are_replaced() {
+        assert_eq!(terminated("lf", b"GET /a\r\n"), b"GET /a\n");
+        assert_eq!(terminated("crlf", b"GET /a\n"), b"GET /a\r\n");
+        assert_eq!(terminated("nul", b"GET /a\n"), b"GET /a\0");
+        assert_eq!(terminated("hex:1e", b"record\n"), b"record\x1e");
+        assert_eq!(terminated("hex:0D0a", b"record"), b"record\r\n");
+    }
+
+    #[test]
+    fn hex_terminators_take_whole_bytes() {
+        for terminator in ["cr", "hex:", "hex:1", "hex:zz", "hex:ff", "hex:é1"] {
+            assert_eq!(
+                terminator.parse::<LineTerminator>(),
+                Err(format!(
+                    "expected `lf`, `crlf`, `nul` or `hex:<bytes>`, got `{}`",
+                    terminator
+                ))
+            );
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! What the unit tests of the router's modules share: a writer set up from a
+//! command line, as the router sets up its own, over a log directory of its
+//! own.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use structopt::StructOpt;
+
+use crate::{expand_shorthands, CliOptions, FileWriter};
+
+/// A log directory under the system's temporary one, emptied when created
+/// and removed when dropped.
+pub struct LogDir(PathBuf);
+
+impl LogDir {
+    pub fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("log-revolve-unit-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        LogDir(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Names of the files in the directory, sorted.
+    pub fn file_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(&self.0)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Contents of the files whose name starts with `prefix`, in the order
+    /// of their names.
+    pub fn read(&self, prefix: &str) -> String {
+        self.file_names()
+            .iter()
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| fs::read_to_string(self.0.join(name)).unwrap())
+            .collect()
+    }
+}
+
+impl Drop for LogDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The options of a router writing to `dir`, given `args` on top.
+pub fn options(dir: &LogDir, args: &[&str]) -> CliOptions {
+    let mut options = CliOptions::from_iter(
+        ["log-revolve-rs", "--log-dir", dir.path().to_str().unwrap()]
+            .iter()
+            .chain(args),
+    );
+    expand_shorthands(&mut options).unwrap();
+    options
+}
+
+/// A writer to `dir`, given `args` on top.
+pub async fn writer(dir: &LogDir, args: &[&str]) -> FileWriter {
+    FileWriter::with_options(&options(dir, args)).await.unwrap()
+}
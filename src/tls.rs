@@ -0,0 +1,82 @@
+use async_std::io::{self, Read, Write};
+use async_std::net::TcpStream;
+
+use futures_rustls::rustls::crypto::ring;
+use futures_rustls::rustls::pki_types::pem::PemObject;
+use futures_rustls::rustls::pki_types::{CertificateDer, ServerName};
+use futures_rustls::rustls::{ClientConfig, RootCertStore};
+use futures_rustls::TlsConnector;
+
+use std::convert::TryFrom;
+use std::sync::{Arc, OnceLock};
+
+/// Settings of the TLS connections the router makes, set once at startup by
+/// `trust`, the Mozilla roots trusted when it isn't called.
+static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+
+/// A connection made by `connect`, over TLS or not.
+pub trait Stream: Read + Write + Unpin + Send {}
+
+impl<T: Read + Write + Unpin + Send> Stream for T {}
+
+/// Trusts the certificate authorities of the PEM file at `ca_file` instead
+/// of the Mozilla roots built in, for servers with a private CA.
+pub fn trust(ca_file: &str) -> Result<(), io::Error> {
+    let mut roots = RootCertStore::empty();
+    for certificate in CertificateDer::pem_file_iter(ca_file).map_err(|e| invalid(ca_file, e))? {
+        roots
+            .add(certificate.map_err(|e| invalid(ca_file, e))?)
+            .map_err(|e| invalid(ca_file, e))?;
+    }
+    if roots.is_empty() {
+        return Err(invalid(ca_file, "no certificates"));
+    }
+
+    let _ = CONFIG.set(config(roots)?);
+    Ok(())
+}
+
+/// Connects to `addr`, `host:port`, over TLS when `tls` is set, the server's
+/// certificate checked against `host`.
+pub async fn connect(addr: &str, tls: bool) -> Result<Box<dyn Stream>, io::Error> {
+    let stream = TcpStream::connect(addr).await?;
+    if !tls {
+        return Ok(Box::new(stream));
+    }
+
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    let name = ServerName::try_from(host.trim_start_matches('[').trim_end_matches(']'))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", addr, e)))?
+        .to_owned();
+    let config = match CONFIG.get() {
+        Some(config) => config.clone(),
+        None => {
+            let roots = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            };
+            CONFIG
+                .get_or_init(|| config(roots).expect("default TLS settings"))
+                .clone()
+        }
+    };
+    let stream = TlsConnector::from(config).connect(name, stream).await?;
+
+    Ok(Box::new(stream))
+}
+
+fn config(roots: RootCertStore) -> Result<Arc<ClientConfig>, io::Error> {
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    Ok(Arc::new(config))
+}
+
+fn invalid(ca_file: &str, error: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("unable to read certificates from {}: {}", ca_file, error),
+    )
+}
@@ -0,0 +1,227 @@
+use async_std::io;
+
+use chrono::Local;
+use regex::Regex;
+use serde_json::json;
+
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::Instant;
+
+use crate::{fd, report};
+
+/// Which records are traced: `channel:<name>` or `regex:<pattern>`, the
+/// pattern matched against the line as received.
+#[derive(Clone, Debug)]
+pub enum TracePredicate {
+    Channel(String),
+    Regex(Regex),
+}
+
+impl FromStr for TracePredicate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(channel) = s.strip_prefix("channel:") {
+            return Ok(TracePredicate::Channel(channel.to_string()));
+        }
+        if let Some(pattern) = s.strip_prefix("regex:") {
+            return Regex::new(pattern)
+                .map(TracePredicate::Regex)
+                .map_err(|e| e.to_string());
+        }
+
+        Err(format!(
+            "expected `channel:<name>` or `regex:<pattern>`, got `{}`",
+            s
+        ))
+    }
+}
+
+impl TracePredicate {
+    fn matches(&self, channel: &str, line: &str) -> bool {
+        match self {
+            TracePredicate::Channel(name) => name == channel,
+            TracePredicate::Regex(regex) => regex.is_match(line),
+        }
+    }
+}
+
+/// Follows matching records through the router, emitting a JSON line for
+/// every step one takes, e.g.
+/// `{"event":"trace","record":3,"stage":"batched","detail":"...","elapsed_us":41,"at":"..."}`.
+/// A record is traced from the moment its channel is known until it has been
+/// handed to its file or turned away.
+pub struct Tracer {
+    predicate: TracePredicate,
+    output: Box<dyn Write + Send>,
+    traced: u64,
+    current: Option<(u64, Instant)>,
+}
+
+impl Tracer {
+    /// `output` is `stdout`, `stderr` or `fd:<n>` for a descriptor inherited
+    /// from the parent process.
+    pub fn new(predicate: TracePredicate, output: &str) -> Result<Self, io::Error> {
+        let output: Box<dyn Write + Send> = match output {
+            "stdout" => Box::new(std::io::stdout()),
+            "stderr" => Box::new(std::io::stderr()),
+            _ => Box::new(fd::open(output)?),
+        };
+
+        Ok(Tracer {
+            predicate,
+            output,
+            traced: 0,
+            current: None,
+        })
+    }
+
+    /// Starts tracing the record about to be routed, if it matches.
+    pub fn begin(&mut self, channel: &str, line: &str) {
+        self.current = None;
+        if self.predicate.matches(channel, line) {
+            self.traced += 1;
+            self.current = Some((self.traced, Instant::now()));
+            self.event("received", format_args!("{}: {}", channel, line.trim_end()));
+        }
+    }
+
+    pub fn event(&mut self, stage: &str, detail: fmt::Arguments) {
+        let (record, started_at) = match self.current {
+            Some(current) => current,
+            None => return,
+        };
+
+        let mut event = json!({
+            "event": "trace",
+            "record": record,
+            "stage": stage,
+            "detail": detail.to_string(),
+            "elapsed_us": started_at.elapsed().as_micros() as u64,
+            "at": Local::now().to_rfc3339(),
+        })
+        .to_string();
+        event.push('\n');
+
+        let result = self
+            .output
+            .write_all(event.as_bytes())
+            .and_then(|()| self.output.flush());
+        if let Err(error) = result {
+            tracing::warn!("unable to emit trace event: {}", error);
+            report::record_error("trace", error);
+        }
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn end(&mut self) {
+        self.current = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::Value;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Events(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Events {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Events {
+        fn take(&self) -> Vec<Value> {
+            let events = std::mem::take(&mut *self.0.lock().unwrap());
+            String::from_utf8(events)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    fn tracer(predicate: &str) -> (Tracer, Events) {
+        let events = Events::default();
+        let tracer = Tracer {
+            predicate: predicate.parse().unwrap(),
+            output: Box::new(events.clone()),
+            traced: 0,
+            current: None,
+        };
+        (tracer, events)
+    }
+
+    #[test]
+    fn predicates_name_a_channel_or_a_pattern() {
+        let channel: TracePredicate = "channel:web".parse().unwrap();
+        assert!(channel.matches("web", "anything"));
+        assert!(!channel.matches("webhooks", "anything"));
+
+        let regex: TracePredicate = "regex:status=5\\d\\d".parse().unwrap();
+        assert!(regex.matches("any", "GET / status=503\n"));
+        assert!(!regex.matches("any", "GET / status=200\n"));
+
+        assert!("regex:(".parse::<TracePredicate>().is_err());
+        let error = "web".parse::<TracePredicate>().unwrap_err();
+        assert!(error.contains("got `web`"), "{}", error);
+    }
+
+    #[test]
+    fn matching_records_are_followed_until_they_end() {
+        let (mut tracer, events) = tracer("channel:web");
+
+        tracer.begin("web", "GET /a\n");
+        assert!(tracer.is_tracing());
+        tracer.event("batched", format_args!("{} bytes", 7));
+        tracer.end();
+        assert!(!tracer.is_tracing());
+        tracer.event("written", format_args!("too late"));
+
+        let events = events.take();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"], "trace");
+        assert_eq!(events[0]["record"], 1);
+        assert_eq!(events[0]["stage"], "received");
+        assert_eq!(events[0]["detail"], "web: GET /a");
+        assert_eq!(events[1]["stage"], "batched");
+        assert_eq!(events[1]["detail"], "7 bytes");
+        assert!(events[1]["elapsed_us"].is_u64());
+        assert!(events[1]["at"].is_string());
+    }
+
+    #[test]
+    fn other_records_are_left_alone_and_numbering_continues() {
+        let (mut tracer, events) = tracer("channel:web");
+
+        tracer.begin("web", "first\n");
+        tracer.begin("db", "ignored\n");
+        assert!(!tracer.is_tracing());
+        tracer.event("batched", format_args!("not traced"));
+        tracer.begin("web", "second\n");
+
+        let records: Vec<_> = events
+            .take()
+            .iter()
+            .map(|event| (event["record"].as_u64().unwrap(), event["detail"].clone()))
+            .collect();
+        assert_eq!(
+            records,
+            vec![(1, "web: first".into()), (2, "web: second".into())]
+        );
+    }
+}
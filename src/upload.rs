@@ -0,0 +1,332 @@
+use async_std::channel::{self, Receiver, Sender};
+use async_std::fs::File;
+use async_std::io::{self, BufReader};
+use async_std::prelude::*;
+use async_std::task;
+
+use chrono::Utc;
+
+use log_revolve_rs::sigv4::{self, Credentials, Request};
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Write as _;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::report;
+use crate::tls;
+
+/// Wait before retrying a failed upload, doubled by every failure that
+/// follows up to `MAX_BACKOFF`.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Bytes of an error response kept for the log.
+const MAX_ERROR_BYTES: usize = 1024;
+
+/// Files waiting for their upload, set once at startup by `start`.
+static UPLOADS: OnceLock<(Sender<String>, Manifest)> = OnceLock::new();
+
+/// What becomes of a file once it is uploaded.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AfterUpload {
+    Keep,
+    Delete,
+}
+
+impl FromStr for AfterUpload {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(AfterUpload::Keep),
+            "delete" => Ok(AfterUpload::Delete),
+            _ => Err(format!("unknown after-upload action: {}", s)),
+        }
+    }
+}
+
+/// A bucket of S3-compatible object storage, addressed path-style
+/// (`<endpoint>/<bucket>/<key>`) as MinIO, Ceph and S3 itself all take it.
+pub struct Bucket {
+    /// `host[:port]` as sent in the `Host` header.
+    host: String,
+    /// Address connected to.
+    addr: String,
+    /// Whether the endpoint is `https://`.
+    tls: bool,
+    /// Path the endpoint is served under, without a trailing slash.
+    base_path: String,
+    bucket: String,
+    prefix: String,
+    region: String,
+    credentials: Credentials,
+}
+
+impl Bucket {
+    /// `bucket` behind `endpoint`, an `http[s]://host[:port][/path]` URL,
+    /// signed for with the `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and
+    /// `AWS_SESSION_TOKEN` environment variables.
+    pub fn new(
+        endpoint: &str,
+        bucket: &str,
+        prefix: &str,
+        region: &str,
+    ) -> Result<Self, io::Error> {
+        let (rest, tls) = match endpoint.strip_prefix("http://") {
+            Some(rest) => (rest, false),
+            None => match endpoint.strip_prefix("https://") {
+                Some(rest) => (rest, true),
+                None => return Err(invalid(format!("invalid S3 endpoint: {}", endpoint))),
+            },
+        };
+        let (host, base_path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], rest[slash..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        if host.is_empty() {
+            return Err(invalid(format!("invalid S3 endpoint: {}", endpoint)));
+        }
+        let addr = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:{}", host, if tls { 443 } else { 80 })
+        };
+
+        let variable = |name: &str| {
+            std::env::var(name)
+                .map_err(|_| invalid(format!("{} must be set to upload to S3", name)))
+        };
+
+        Ok(Bucket {
+            host: host.to_string(),
+            addr,
+            tls,
+            base_path: base_path.to_string(),
+            bucket: bucket.to_string(),
+            prefix: prefix.to_string(),
+            region: region.to_string(),
+            credentials: Credentials {
+                access_key_id: variable("AWS_ACCESS_KEY_ID")?,
+                secret_access_key: variable("AWS_SECRET_ACCESS_KEY")?,
+                session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+            },
+        })
+    }
+
+    /// Streams the file at `path` to `<prefix><file name>`, returning the key.
+    async fn put(&self, path: &str) -> Result<String, io::Error> {
+        let file = File::open(path).await?;
+        let length = file.metadata().await?.len();
+        let file_name = Path::new(path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| invalid(format!("no file name in {}", path)))?;
+        let key = format!("{}{}", self.prefix, file_name);
+        let target = format!(
+            "{}/{}/{}",
+            self.base_path,
+            sigv4::uri_encode(&self.bucket, true),
+            sigv4::uri_encode(&key, false)
+        );
+
+        let now = Utc::now();
+        let date = sigv4::amz_date(&now);
+        let mut headers = vec![
+            ("host", self.host.as_str()),
+            ("x-amz-content-sha256", sigv4::UNSIGNED_PAYLOAD),
+            ("x-amz-date", date.as_str()),
+        ];
+        if let Some(ref token) = self.credentials.session_token {
+            headers.push(("x-amz-security-token", token));
+        }
+        let authorization = sigv4::authorization(
+            &self.credentials,
+            &self.region,
+            "s3",
+            &now,
+            &Request {
+                method: "PUT",
+                path: &target,
+                query: "",
+                headers: &headers,
+                payload_hash: sigv4::UNSIGNED_PAYLOAD,
+            },
+        );
+
+        let mut head = format!("PUT {} HTTP/1.1\r\n", target);
+        for (name, value) in headers.iter() {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!(
+            "Authorization: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            authorization, length
+        ));
+
+        let mut stream = tls::connect(&self.addr, self.tls).await?;
+        stream.write_all(head.as_bytes()).await?;
+        io::copy(&mut file.take(length), &mut stream).await?;
+        stream.flush().await?;
+
+        let mut response = BufReader::new(stream);
+        let mut status_line = String::new();
+        response.read_line(&mut status_line).await?;
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok())
+            .ok_or_else(|| invalid(format!("malformed response: {}", status_line.trim())))?;
+        if (200..300).contains(&status) {
+            return Ok(key);
+        }
+
+        let mut body = Vec::new();
+        response
+            .take(MAX_ERROR_BYTES as u64)
+            .read_to_end(&mut body)
+            .await?;
+        let body = String::from_utf8_lossy(&body);
+        let message = body.split("\r\n\r\n").nth(1).unwrap_or("").trim();
+        Err(io::Error::other(format!(
+            "{} answered {}: {}",
+            self.host, status, message
+        )))
+    }
+}
+
+/// Files queued for upload and those uploaded since, one `queued <path>` or
+/// `done <path>` line each, so uploads cut short by a restart are picked up
+/// again. Rewritten with only what is still pending on startup.
+#[derive(Clone)]
+struct Manifest {
+    path: String,
+}
+
+impl Manifest {
+    /// Files queued but not uploaded yet, oldest first.
+    fn pending(&self) -> Result<Vec<String>, io::Error> {
+        let manifest = match fs::read_to_string(&self.path) {
+            Ok(manifest) => manifest,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error),
+        };
+
+        let mut queued = Vec::new();
+        let mut done = BTreeSet::new();
+        for line in manifest.lines() {
+            match line.split_once(' ') {
+                Some(("queued", path)) => queued.push(path.to_string()),
+                Some(("done", path)) => {
+                    done.insert(path.to_string());
+                }
+                _ => tracing::warn!("ignoring malformed upload manifest line: {}", line),
+            }
+        }
+        queued.retain(|path| !done.contains(path));
+
+        Ok(queued)
+    }
+
+    fn rewrite(&self, pending: &[String]) -> Result<(), io::Error> {
+        let partial = format!("{}.partial", self.path);
+        let mut manifest = String::new();
+        for path in pending {
+            manifest.push_str(&format!("queued {}\n", path));
+        }
+        fs::write(&partial, manifest)?;
+        fs::rename(&partial, &self.path)
+    }
+
+    fn record(&self, state: &str, path: &str) {
+        let recorded = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut manifest| writeln!(manifest, "{} {}", state, path));
+        if let Err(error) = recorded {
+            tracing::warn!("unable to record {} in {}: {}", path, self.path, error);
+        }
+    }
+}
+
+/// Starts uploading the files handed to `enqueue`, after those the manifest
+/// at `manifest_path` still lists from an earlier run.
+pub fn start(
+    bucket: Bucket,
+    after_upload: AfterUpload,
+    manifest_path: &str,
+) -> Result<(), io::Error> {
+    let manifest = Manifest {
+        path: manifest_path.to_string(),
+    };
+    let pending = manifest.pending()?;
+    manifest.rewrite(&pending)?;
+
+    let (sender, receiver) = channel::unbounded();
+    for path in pending {
+        tracing::info!("resuming upload of {}", path);
+        let _ = sender.try_send(path);
+    }
+    task::spawn(upload_all(bucket, after_upload, manifest.clone(), receiver));
+    let _ = UPLOADS.set((sender, manifest));
+
+    Ok(())
+}
+
+/// Queues a file that won't be written to anymore for upload, if uploads
+/// were started.
+pub fn enqueue(path: String) {
+    if let Some((sender, manifest)) = UPLOADS.get() {
+        manifest.record("queued", &path);
+        let _ = sender.try_send(path);
+    }
+}
+
+async fn upload_all(
+    bucket: Bucket,
+    after_upload: AfterUpload,
+    manifest: Manifest,
+    receiver: Receiver<String>,
+) {
+    while let Ok(path) = receiver.recv().await {
+        let mut backoff = FIRST_BACKOFF;
+        loop {
+            match bucket.put(&path).await {
+                Ok(key) => {
+                    tracing::info!("uploaded {} to {}", path, key);
+                    manifest.record("done", &path);
+                    if after_upload == AfterUpload::Delete {
+                        if let Err(error) = fs::remove_file(&path) {
+                            tracing::warn!("unable to delete uploaded {}: {}", path, error);
+                        }
+                        let _ = fs::remove_file(format!("{}.sha256", path));
+                    }
+                    break;
+                }
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    tracing::warn!("giving up on uploading {}: {}", path, error);
+                    manifest.record("done", &path);
+                    break;
+                }
+                Err(error) => {
+                    tracing::warn!(
+                        "unable to upload {}, retrying in {}s: {}",
+                        path,
+                        backoff.as_secs(),
+                        error
+                    );
+                    report::record_error("upload", error);
+                    task::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
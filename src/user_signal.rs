@@ -0,0 +1,32 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Signal left for users to act on, given by `--reopen-on-signal` or
+/// `--rotate-on-signal` as `SIGUSR1` or `SIGUSR2`, the `SIG` optional.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UserSignal {
+    Usr1,
+    Usr2,
+}
+
+impl FromStr for UserSignal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_uppercase();
+        match name.strip_prefix("SIG").unwrap_or(&name) {
+            "USR1" => Ok(UserSignal::Usr1),
+            "USR2" => Ok(UserSignal::Usr2),
+            _ => Err(format!("expected `SIGUSR1` or `SIGUSR2`, got `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for UserSignal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UserSignal::Usr1 => write!(f, "SIGUSR1"),
+            UserSignal::Usr2 => write!(f, "SIGUSR2"),
+        }
+    }
+}
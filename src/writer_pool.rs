@@ -0,0 +1,358 @@
+use async_std::channel::{self, Receiver, Sender};
+use async_std::fs::File;
+use async_std::io;
+use async_std::sync::Arc;
+use async_std::task;
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{IoSlice, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex as SyncMutex, OnceLock};
+
+/// Writer tasks batches are handed to, set once at startup by `start`.
+static WORKERS: OnceLock<Vec<Sender<Job>>> = OnceLock::new();
+
+/// Batches a writer task may have waiting before handing it one more holds
+/// back the router, so a slow disk slows the channels on it rather than
+/// filling memory.
+const QUEUED_BATCHES: usize = 16;
+
+/// Most bytes of a channel's queued batches gathered into a single vectored
+/// write. Only batches queued already are gathered, so none waits for
+/// another; how long a line may wait is up to `--flush-interval`.
+const VECTORED_BYTES: usize = 1024 * 1024;
+
+/// The error a channel's batch couldn't be written with, and the bytes of it
+/// and of every batch of the channel after it, none of which were written.
+pub type Failure = (io::Error, Vec<u8>);
+
+enum Job {
+    Write {
+        file: Arc<File>,
+        bytes: Vec<u8>,
+        failed: Arc<SyncMutex<Option<Failure>>>,
+        writes: Arc<AtomicU64>,
+    },
+    /// Answered once every job ahead of it is done.
+    Drain(Sender<()>),
+}
+
+/// Starts `threads` writer tasks, so channels hashed to different ones reach
+/// the disk concurrently. Without them, the router writes every batch out
+/// itself.
+pub fn start(threads: usize) {
+    if threads == 0 {
+        return;
+    }
+
+    let workers = (0..threads)
+        .map(|index| {
+            let (sender, receiver) = channel::bounded(QUEUED_BATCHES);
+            let _ = task::Builder::new()
+                .name(format!("writer-{}", index))
+                .spawn(work(receiver));
+            sender
+        })
+        .collect();
+    let _ = WORKERS.set(workers);
+}
+
+/// The writer task of `channel`, if there are writer tasks. A channel always
+/// gets the same one, so its batches reach its files in order.
+pub fn writer(channel: &str) -> Option<Writer> {
+    let workers = WORKERS.get()?;
+    let mut hasher = DefaultHasher::new();
+    channel.hash(&mut hasher);
+    let index = (hasher.finish() % workers.len() as u64) as usize;
+
+    Some(Writer {
+        jobs: workers[index].clone(),
+        failed: Arc::new(SyncMutex::new(None)),
+        writes: Arc::new(AtomicU64::new(0)),
+    })
+}
+
+/// A writer task of `channel`'s own, if there are writer tasks, for a
+/// priority channel whose lines mustn't wait behind other channels' batches.
+/// It ends once the channel lets go of it.
+pub fn dedicated(channel: &str) -> Option<Writer> {
+    WORKERS.get()?;
+    let (sender, receiver) = channel::bounded(QUEUED_BATCHES);
+    let _ = task::Builder::new()
+        .name(format!("writer-{}", channel))
+        .spawn(work(receiver));
+
+    Some(Writer {
+        jobs: sender,
+        failed: Arc::new(SyncMutex::new(None)),
+        writes: Arc::new(AtomicU64::new(0)),
+    })
+}
+
+/// A channel's way to its writer task.
+pub struct Writer {
+    jobs: Sender<Job>,
+    /// First error writing one of the channel's batches, reported by the
+    /// next call along with the bytes left unwritten. Batches handed over
+    /// after it are held back with them, so they stay in order.
+    failed: Arc<SyncMutex<Option<Failure>>>,
+    /// Writes the channel's batches went out in, fewer than the batches when
+    /// queued ones were gathered.
+    writes: Arc<AtomicU64>,
+}
+
+impl Writer {
+    /// Hands `bytes` over to be appended to `file` after the batches handed
+    /// over before it. Fails with the error of an earlier batch, if one
+    /// couldn't be written, handing back its bytes and those of every batch
+    /// after it, `bytes` last, to be written again.
+    pub async fn write(&self, file: &Arc<File>, bytes: Vec<u8>) -> Result<(), Failure> {
+        if self.has_failed() {
+            if let Err((error, mut unwritten)) = self.drain().await {
+                unwritten.extend_from_slice(&bytes);
+                return Err((error, unwritten));
+            }
+        }
+
+        let job = Job::Write {
+            file: file.clone(),
+            bytes,
+            failed: self.failed.clone(),
+            writes: self.writes.clone(),
+        };
+        match self.jobs.send(job).await {
+            Ok(()) => Ok(()),
+            Err(channel::SendError(Job::Write { bytes, .. })) => {
+                Err((io::Error::other("writer task ended"), bytes))
+            }
+            Err(channel::SendError(Job::Drain(_))) => unreachable!(),
+        }
+    }
+
+    /// Waits until every batch handed over so far is written out, before the
+    /// file is synced, closed or compressed. Fails as `write` does.
+    pub async fn drain(&self) -> Result<(), Failure> {
+        let (done, finished) = channel::bounded(1);
+        let ended = || (io::Error::other("writer task ended"), Vec::new());
+        self.jobs
+            .send(Job::Drain(done))
+            .await
+            .map_err(|_| ended())?;
+        finished.recv().await.map_err(|_| ended())?;
+
+        match self.failed.lock().ok().and_then(|mut failed| failed.take()) {
+            Some(failure) => Err(failure),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the channel's batches went out in so far.
+    #[cfg(feature = "metrics")]
+    pub fn writes(&self) -> u64 {
+        self.writes.load(Ordering::Relaxed)
+    }
+
+    fn has_failed(&self) -> bool {
+        self.failed.lock().is_ok_and(|failed| failed.is_some())
+    }
+}
+
+async fn work(jobs: Receiver<Job>) {
+    let mut next = None;
+    // The file last written to, kept from one batch to the next until the
+    // channel drains its writes to sync, close or compress it.
+    let mut current: Option<(Arc<File>, Arc<std::fs::File>)> = None;
+    loop {
+        let job = match next.take() {
+            Some(job) => job,
+            None => match jobs.recv().await {
+                Ok(job) => job,
+                Err(_) => return,
+            },
+        };
+        match job {
+            Job::Write {
+                file,
+                bytes,
+                failed,
+                writes,
+            } => {
+                // Batches of the same file queued behind this one go out
+                // with it, saving a write each.
+                let mut batches = vec![bytes];
+                let mut gathered = batches[0].len();
+                while gathered < VECTORED_BYTES {
+                    match jobs.try_recv() {
+                        Ok(Job::Write {
+                            file: ref queued,
+                            ref mut bytes,
+                            ..
+                        }) if Arc::ptr_eq(queued, &file) => {
+                            gathered += bytes.len();
+                            batches.push(std::mem::take(bytes));
+                        }
+                        Ok(job) => {
+                            next = Some(job);
+                            break;
+                        }
+                        Err(_) => break,
+                    }
+                }
+                // Held back behind a batch that failed, rather than written
+                // out ahead of it once the channel writes that one again.
+                if let Ok(mut failed) = failed.lock() {
+                    if let Some((_, ref mut unwritten)) = *failed {
+                        batches
+                            .iter()
+                            .for_each(|batch| unwritten.extend_from_slice(batch));
+                        continue;
+                    }
+                }
+                writes.fetch_add(1, Ordering::Relaxed);
+
+                let target = match current {
+                    Some((ref open, ref target)) if Arc::ptr_eq(open, &file) => Ok(target.clone()),
+                    _ => blocking_file(&file).map(Arc::new),
+                };
+                let (result, batches) = match target {
+                    Ok(target) => {
+                        current = Some((file, target.clone()));
+                        task::spawn_blocking(move || {
+                            (write_all_vectored(&target, &batches), batches)
+                        })
+                        .await
+                    }
+                    Err(error) => (Err((error, 0)), batches),
+                };
+                if let (Err((error, written)), Ok(mut failed)) = (result, failed.lock()) {
+                    let unwritten = batches.concat().split_off(written);
+                    failed.get_or_insert((error, unwritten));
+                }
+            }
+            Job::Drain(done) => {
+                current = None;
+                let _ = done.send(()).await;
+            }
+        }
+    }
+}
+
+/// `file` opened again, to be written to on a blocking thread past the
+/// async file's own cache, which is left empty.
+#[cfg(unix)]
+fn blocking_file(file: &File) -> Result<std::fs::File, io::Error> {
+    use std::os::unix::io::AsFd;
+
+    Ok(file.as_fd().try_clone_to_owned()?.into())
+}
+
+#[cfg(windows)]
+fn blocking_file(file: &File) -> Result<std::fs::File, io::Error> {
+    use std::os::windows::io::AsHandle;
+
+    Ok(file.as_handle().try_clone_to_owned()?.into())
+}
+
+/// Writes `batches` one after the other, in a single `writev` unless the
+/// file takes less at a time. Fails with how many bytes were written before
+/// the error.
+fn write_all_vectored(
+    mut file: &std::fs::File,
+    batches: &[Vec<u8>],
+) -> Result<(), (io::Error, usize)> {
+    let mut slices: Vec<IoSlice> = batches.iter().map(|batch| IoSlice::new(batch)).collect();
+    let mut slices = &mut slices[..];
+    let mut total = 0;
+    while !slices.is_empty() {
+        match file.write_vectored(slices) {
+            Ok(0) => return Err((io::ErrorKind::WriteZero.into(), total)),
+            Ok(written) => {
+                total += written;
+                IoSlice::advance_slices(&mut slices, written);
+            }
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err((error, total)),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::LogDir;
+
+    /// A writer of its own, its task started once `jobs` are queued so they
+    /// are all waiting for it.
+    async fn writer(jobs: Vec<(&Arc<File>, &str)>) -> Writer {
+        let (sender, receiver) = channel::bounded(QUEUED_BATCHES);
+        let writer = Writer {
+            jobs: sender,
+            failed: Arc::new(SyncMutex::new(None)),
+            writes: Arc::new(AtomicU64::new(0)),
+        };
+        for (file, bytes) in jobs {
+            writer.write(file, bytes.as_bytes().to_vec()).await.unwrap();
+        }
+        task::spawn(work(receiver));
+        writer
+    }
+
+    #[test]
+    fn batches_are_written_one_after_the_other() {
+        let dir = LogDir::new("writer-pool-vectored");
+        let path = dir.path().join("web.log");
+        let file = std::fs::File::create(&path).unwrap();
+
+        let batches = vec![b"GET /a\n".to_vec(), Vec::new(), b"GET /b\n".to_vec()];
+        write_all_vectored(&file, &batches).unwrap();
+        assert_eq!(dir.read("web.log"), "GET /a\nGET /b\n");
+    }
+
+    #[test]
+    fn queued_batches_of_a_file_are_gathered_into_one_write() {
+        task::block_on(async {
+            let dir = LogDir::new("writer-pool-gather");
+            let web = Arc::new(File::create(dir.path().join("web.log")).await.unwrap());
+            let db = Arc::new(File::create(dir.path().join("db.log")).await.unwrap());
+
+            let writer = writer(vec![
+                (&web, "GET /a\n"),
+                (&web, "GET /b\n"),
+                (&db, "SELECT 1\n"),
+                (&web, "GET /c\n"),
+            ])
+            .await;
+            writer.drain().await.unwrap();
+
+            assert_eq!(dir.read("web.log"), "GET /a\nGET /b\nGET /c\n");
+            assert_eq!(dir.read("db.log"), "SELECT 1\n");
+            assert_eq!(writer.writes.load(Ordering::Relaxed), 3);
+        });
+    }
+
+    #[test]
+    fn failed_batches_are_handed_back_with_those_after_them() {
+        task::block_on(async {
+            let dir = LogDir::new("writer-pool-failure");
+            let path = dir.path().join("web.log");
+            std::fs::write(&path, "").unwrap();
+            let read_only = Arc::new(File::open(&path).await.unwrap());
+
+            let writer = writer(vec![(&read_only, "GET /a\n"), (&read_only, "GET /b\n")]).await;
+            while !writer.has_failed() {
+                task::sleep(std::time::Duration::from_millis(5)).await;
+            }
+            let (_, unwritten) = writer
+                .write(&read_only, b"GET /c\n".to_vec())
+                .await
+                .unwrap_err();
+            assert_eq!(unwritten, b"GET /a\nGET /b\nGET /c\n");
+            assert!(!writer.has_failed());
+            assert_eq!(dir.read("web.log"), "");
+        });
+    }
+}
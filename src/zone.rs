@@ -0,0 +1,216 @@
+use chrono::{
+    DateTime, FixedOffset, Local, LocalResult, NaiveDate, NaiveDateTime, Offset, TimeZone,
+};
+
+use chrono_tz::Tz;
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::clock;
+
+/// Time zone a channel's files are cut and stamped in: `local`, `UTC`, a
+/// fixed offset such as `+09:00`, or a zone of the IANA database such as
+/// `Europe/Berlin`. A fixed offset follows daylight saving time only if it
+/// is changed with it; `local` and named zones follow it on their own.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Zone {
+    Local,
+    Fixed(FixedOffset),
+    /// A zone of the IANA database `chrono-tz` is built with.
+    Named(Tz),
+}
+
+/// Offset of a time in a `Zone`, kept with the zone so times stay in it as
+/// they are moved around.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ZoneOffset {
+    zone: Zone,
+    fixed: FixedOffset,
+}
+
+impl Offset for ZoneOffset {
+    fn fix(&self) -> FixedOffset {
+        self.fixed
+    }
+}
+
+impl fmt::Debug for ZoneOffset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.fixed, f)
+    }
+}
+
+impl fmt::Display for ZoneOffset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.fixed, f)
+    }
+}
+
+impl Zone {
+    pub fn now(&self) -> DateTime<Zone> {
+        self.at(clock::now())
+    }
+
+    /// `time` in the zone, for a caller that read the clock already.
+    pub fn at(&self, time: DateTime<Local>) -> DateTime<Zone> {
+        time.with_timezone(self)
+    }
+
+    fn offset(&self, fixed: FixedOffset) -> ZoneOffset {
+        ZoneOffset {
+            zone: self.clone(),
+            fixed,
+        }
+    }
+}
+
+/// `time` at its offset, for what stamps lines and names files with it.
+pub fn fixed(time: &DateTime<Zone>) -> DateTime<FixedOffset> {
+    time.with_timezone(&time.offset().fix())
+}
+
+impl TimeZone for Zone {
+    type Offset = ZoneOffset;
+
+    fn from_offset(offset: &ZoneOffset) -> Self {
+        offset.zone.clone()
+    }
+
+    fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<ZoneOffset> {
+        self.offset_from_local_datetime(&local.and_hms_opt(0, 0, 0).unwrap())
+    }
+
+    fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<ZoneOffset> {
+        let fixed = match self {
+            Zone::Local => Local
+                .offset_from_local_datetime(local)
+                .map(|offset| offset.fix()),
+            Zone::Fixed(offset) => LocalResult::Single(*offset),
+            Zone::Named(tz) => tz
+                .offset_from_local_datetime(local)
+                .map(|offset| offset.fix()),
+        };
+
+        fixed.map(|fixed| self.offset(fixed))
+    }
+
+    fn offset_from_utc_date(&self, utc: &NaiveDate) -> ZoneOffset {
+        self.offset_from_utc_datetime(&utc.and_hms_opt(0, 0, 0).unwrap())
+    }
+
+    fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> ZoneOffset {
+        let fixed = match self {
+            Zone::Local => Local.offset_from_utc_datetime(utc).fix(),
+            Zone::Fixed(offset) => *offset,
+            Zone::Named(tz) => tz.offset_from_utc_datetime(utc).fix(),
+        };
+
+        self.offset(fixed)
+    }
+}
+
+impl FromStr for Zone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "expected `local`, `UTC`, an offset like `+09:00` or a zone like \
+                 `Europe/Berlin`, got `{}`",
+                s
+            )
+        };
+        if s == "local" {
+            return Ok(Zone::Local);
+        }
+        if s.eq_ignore_ascii_case("utc") {
+            return FixedOffset::east_opt(0)
+                .map(Zone::Fixed)
+                .ok_or_else(invalid);
+        }
+
+        let (sign, offset) = match (s.strip_prefix('+'), s.strip_prefix('-')) {
+            (Some(offset), _) => (1, offset),
+            (_, Some(offset)) => (-1, offset),
+            _ => return s.parse().map(Zone::Named).map_err(|_| invalid()),
+        };
+        let (hours, minutes) = offset.split_once(':').ok_or_else(invalid)?;
+        let hours: i32 = hours.parse().map_err(|_| invalid())?;
+        let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+        if minutes >= 60 {
+            return Err(invalid());
+        }
+
+        FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+            .map(Zone::Fixed)
+            .ok_or_else(invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(text: &str) -> DateTime<Local> {
+        DateTime::parse_from_rfc3339(text)
+            .unwrap()
+            .with_timezone(&Local)
+    }
+
+    #[test]
+    fn zones_are_local_utc_offsets_or_named() {
+        assert_eq!("local".parse(), Ok(Zone::Local));
+        assert_eq!(
+            "UTC".parse(),
+            Ok(Zone::Fixed(FixedOffset::east_opt(0).unwrap()))
+        );
+        assert_eq!(
+            "+09:00".parse(),
+            Ok(Zone::Fixed(FixedOffset::east_opt(9 * 3600).unwrap()))
+        );
+        assert_eq!(
+            "-03:30".parse(),
+            Ok(Zone::Fixed(FixedOffset::west_opt(3 * 3600 + 1800).unwrap()))
+        );
+        assert_eq!("Europe/Berlin".parse(), Ok(Zone::Named(Tz::Europe__Berlin)));
+        for zone in ["+09", "+09:75", "+25:00", "Mars/Olympus"] {
+            let error = zone.parse::<Zone>().unwrap_err();
+            assert!(error.ends_with(&format!("got `{}`", zone)), "{}", error);
+        }
+    }
+
+    #[test]
+    fn named_zones_follow_daylight_saving_time() {
+        let berlin: Zone = "Europe/Berlin".parse().unwrap();
+        let winter = fixed(&berlin.at(utc("2024-01-15T12:00:00Z")));
+        assert_eq!(winter.to_rfc3339(), "2024-01-15T13:00:00+01:00");
+        let summer = fixed(&berlin.at(utc("2024-07-15T12:00:00Z")));
+        assert_eq!(summer.to_rfc3339(), "2024-07-15T14:00:00+02:00");
+
+        let tokyo: Zone = "+09:00".parse().unwrap();
+        let stamped = fixed(&tokyo.at(utc("2024-07-15T20:00:00Z")));
+        assert_eq!(stamped.to_rfc3339(), "2024-07-16T05:00:00+09:00");
+    }
+
+    #[test]
+    fn local_times_skipped_or_repeated_by_daylight_saving_time_are_told_apart() {
+        let berlin: Zone = "Europe/Berlin".parse().unwrap();
+        let at = |text| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap();
+
+        assert!(matches!(
+            berlin.from_local_datetime(&at("2024-03-31 02:30")),
+            LocalResult::None
+        ));
+        assert!(matches!(
+            berlin.from_local_datetime(&at("2024-10-27 02:30")),
+            LocalResult::Ambiguous(..)
+        ));
+        let midnight = berlin.from_local_datetime(&at("2024-10-27 00:00")).unwrap();
+        assert_eq!(
+            midnight.offset().fix(),
+            FixedOffset::east_opt(7200).unwrap()
+        );
+        assert_eq!(midnight.timezone(), berlin);
+    }
+}
@@ -0,0 +1,175 @@
+//! Routes lines through the library's `Router`, with the clock handed in.
+
+use chrono::{DateTime, Duration, FixedOffset, TimeZone};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use log_revolve_rs::rotation::{FileTimestamp, Rotation};
+use log_revolve_rs::router::{Channel, ManualClock, RotationPolicy, Router, SinkConfig};
+use log_revolve_rs::runtime;
+use log_revolve_rs::sink::{NullSink, Sink, SinkFuture};
+
+fn log_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "log-revolve-embedded-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
+
+fn at(hour: u32, minute: u32, second: u32) -> DateTime<FixedOffset> {
+    FixedOffset::east_opt(0)
+        .unwrap()
+        .with_ymd_and_hms(2024, 6, 1, hour, minute, second)
+        .unwrap()
+}
+
+fn hourly(dir: &Path, name: &str) -> Channel {
+    Channel::new(
+        name,
+        SinkConfig::new(dir),
+        RotationPolicy::new(Rotation::Hourly(FileTimestamp::Seconds)),
+    )
+}
+
+#[test]
+fn lines_go_to_the_files_of_their_channels() {
+    let dir = log_dir("channels");
+    let mut router = Router::new(vec![hourly(&dir, "app"), hourly(&dir, "web")]);
+
+    runtime::block_on(async {
+        router.write_at("app", "one", &at(9, 0, 0)).await.unwrap();
+        router
+            .write_at("web", "other", &at(9, 10, 0))
+            .await
+            .unwrap();
+        router.write_at("app", "two", &at(9, 20, 0)).await.unwrap();
+        assert!(router
+            .write_at("db", "unknown", &at(9, 30, 0))
+            .await
+            .is_err());
+        router.flush().await.unwrap();
+    });
+
+    let app = fs::read_to_string(dir.join("app_2024-06-01-09-00-00.log")).unwrap();
+    let web = fs::read_to_string(dir.join("web_2024-06-01-09-00-00.log")).unwrap();
+    assert_eq!(app, "one\ntwo\n");
+    assert_eq!(web, "other\n");
+}
+
+#[test]
+fn files_rotate_on_schedule_and_past_their_size_limit() {
+    let dir = log_dir("rotation");
+    let mut rotation = RotationPolicy::new(Rotation::Periodic(
+        FileTimestamp::Seconds,
+        Duration::minutes(30),
+    ));
+    rotation.max_file_size = Some(8);
+    let mut router = Router::new(vec![Channel::new(
+        "app",
+        SinkConfig::new(dir.clone()),
+        rotation,
+    )]);
+
+    runtime::block_on(async {
+        router.write_at("app", "one", &at(9, 0, 0)).await.unwrap();
+        router.write_at("app", "two", &at(9, 5, 0)).await.unwrap();
+        router
+            .write_at("app", "three", &at(9, 10, 0))
+            .await
+            .unwrap();
+        router.write_at("app", "four", &at(9, 30, 0)).await.unwrap();
+        router.flush().await.unwrap();
+    });
+
+    let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+    assert_eq!(read("app_2024-06-01-09-00-00.log"), "one\ntwo\n");
+    assert_eq!(read("app_2024-06-01-09-10-00.log"), "three\n");
+    assert_eq!(read("app_2024-06-01-09-30-00.log"), "four\n");
+}
+
+/// Keeps what it is handed, rotations included, where the test can see it.
+#[derive(Clone, Default)]
+struct MemorySink {
+    events: Arc<Mutex<Vec<String>>>,
+}
+
+impl MemorySink {
+    fn record(&self, event: String) -> SinkFuture<'_> {
+        self.events.lock().unwrap().push(event);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+impl Sink for MemorySink {
+    fn write<'a>(&'a mut self, line: &'a str, now: &'a DateTime<FixedOffset>) -> SinkFuture<'a> {
+        self.record(format!("{} {}", now.format("%H:%M"), line))
+    }
+
+    fn rotate<'a>(&'a mut self, _: &'a DateTime<FixedOffset>) -> SinkFuture<'a> {
+        self.record("rotate".to_string())
+    }
+
+    fn flush(&mut self) -> SinkFuture<'_> {
+        self.record("flush".to_string())
+    }
+
+    fn close(&mut self) -> SinkFuture<'_> {
+        self.record("close".to_string())
+    }
+}
+
+#[test]
+fn channels_can_be_written_to_sinks_of_their_own() {
+    let memory = MemorySink::default();
+    let mut router = Router::with_sinks(vec![
+        ("app".to_string(), Box::new(memory.clone()) as Box<dyn Sink>),
+        ("debug".to_string(), Box::new(NullSink)),
+    ]);
+
+    runtime::block_on(async {
+        router.write_at("app", "one", &at(9, 0, 0)).await.unwrap();
+        router
+            .write_at("debug", "discarded", &at(9, 5, 0))
+            .await
+            .unwrap();
+        router.rotate("app").await.unwrap();
+        router.write_at("app", "two", &at(9, 10, 0)).await.unwrap();
+        router.rotate_all().await.unwrap();
+        router.reopen().await.unwrap();
+    });
+
+    let events = memory.events.lock().unwrap().clone();
+    assert_eq!(
+        events,
+        ["09:00 one\n", "rotate", "09:10 two\n", "rotate", "close"]
+    );
+}
+
+#[test]
+fn lines_written_without_a_time_take_the_router_clock() {
+    let dir = log_dir("clock");
+    let clock = ManualClock::new(at(23, 59, 0));
+    let mut router = Router::new(vec![hourly(&dir, "app")]);
+    router.set_clock(clock.clone());
+
+    runtime::block_on(async {
+        router.write("app", "before").await.unwrap();
+        clock.advance(Duration::minutes(2));
+        router.write("app", "after").await.unwrap();
+        clock.set(at(23, 30, 0));
+        router.write("app", "set back").await.unwrap();
+        router.flush().await.unwrap();
+    });
+
+    let before = fs::read_to_string(dir.join("app_2024-06-01-23-00-00.log")).unwrap();
+    let after = fs::read_to_string(dir.join("app_2024-06-02-00-00-00.log")).unwrap();
+    assert_eq!(before, "before\n");
+    assert_eq!(after, "after\nset back\n");
+}
@@ -0,0 +1,240 @@
+//! Checks AES-256-GCM against the vectors of FIPS 197 and the GCM
+//! specification, and the records of encrypted files, which only open whole
+//! and in the order they were sealed into their file.
+
+#![cfg(feature = "encryption")]
+
+use std::convert::TryInto;
+
+use log_revolve_rs::encryption::{
+    record_aad, FileHeader, Key, Opener, Record, FILE_HEADER_BYTES, FILE_ID_BYTES, KEY_BYTES,
+    NONCE_BYTES,
+};
+
+fn hex(hex: &str) -> Vec<u8> {
+    let digits: Vec<u8> = hex
+        .bytes()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect();
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+        .collect()
+}
+
+fn key(text: &str) -> Key {
+    Key::new(hex(text).as_slice().try_into().unwrap())
+}
+
+fn nonce(text: &str) -> [u8; NONCE_BYTES] {
+    hex(text).as_slice().try_into().unwrap()
+}
+
+#[test]
+fn sealing_matches_the_gcm_test_vectors() {
+    let zero = Key::new(&[0; KEY_BYTES]);
+    let zero_nonce = [0; NONCE_BYTES];
+
+    // Test cases 13, 14 and 15.
+    assert_eq!(
+        zero.seal(&zero_nonce, b"", b""),
+        hex("530f8afbc74536b9a963b4f1c4cb738b")
+    );
+    assert_eq!(
+        zero.seal(&zero_nonce, b"", &[0; 16]),
+        hex("cea7403d4d606b6e074ec5d3baf39d18 d0d1c8a799996bf0265b98b5d48ab919")
+    );
+    let key = key("feffe9928665731c6d6a8f9467308308 feffe9928665731c6d6a8f9467308308");
+    let nonce = nonce("cafebabefacedbaddecaf888");
+    let plaintext = hex(
+        "d9313225f88406e5a55909c5aff5269a 86a7a9531534f7da2e4c303d8a318a72
+         1c3c0c95956809532fcf0e2449a6b525 b16aedf5aa0de657ba637b391aafd255",
+    );
+    let sealed = hex(
+        "522dc1f099567d07f47f37a32a84427d 643a8cdcbfe5c0c97598a2bd2555d1aa
+         8cb08e48590dbb3da7b08b1056828838 c5f61e6393ba7a0abcc9f662898015ad
+         b094dac5d93471bdec1a502270e3cc6c",
+    );
+    assert_eq!(key.seal(&nonce, b"", &plaintext), sealed);
+    assert_eq!(key.open(&nonce, b"", &sealed).unwrap(), plaintext);
+}
+
+#[test]
+fn tampered_messages_and_other_keys_fail_to_open() {
+    let key = Key::new(&[7; KEY_BYTES]);
+    let nonce = [1; NONCE_BYTES];
+    let mut sealed = key.seal(&nonce, b"aad", b"audit line\n");
+
+    assert!(Key::new(&[8; KEY_BYTES])
+        .open(&nonce, b"aad", &sealed)
+        .is_err());
+    assert!(key.open(&[2; NONCE_BYTES], b"aad", &sealed).is_err());
+    assert!(key.open(&nonce, b"other", &sealed).is_err());
+    assert!(key.open(&nonce, b"aad", &sealed[..10]).is_err());
+    sealed[0] ^= 1;
+    assert!(key.open(&nonce, b"aad", &sealed).is_err());
+}
+
+#[test]
+fn keys_are_read_from_hexadecimal() {
+    let text = "feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308\n";
+    let nonce = [0; NONCE_BYTES];
+
+    assert_eq!(
+        Key::from_hex(text).unwrap().seal(&nonce, b"", b"x"),
+        key("feffe9928665731c6d6a8f9467308308 feffe9928665731c6d6a8f9467308308")
+            .seal(&nonce, b"", b"x")
+    );
+    assert!(Key::from_hex("feffe992").is_err());
+    assert!(Key::from_hex(&"zz".repeat(KEY_BYTES)).is_err());
+}
+
+#[test]
+fn records_are_parsed_one_after_another_and_wait_for_their_end() {
+    let first = Record {
+        last: false,
+        nonce: [1; NONCE_BYTES],
+        sealed: b"first sealed message",
+    };
+    let second = Record {
+        last: true,
+        nonce: [2; NONCE_BYTES],
+        sealed: b"second",
+    };
+    let mut file = Vec::new();
+    first.encode(&mut file);
+    second.encode(&mut file);
+
+    let (parsed, used) = Record::parse(&file).unwrap().unwrap();
+    assert_eq!(parsed, first);
+    assert_eq!(Record::parse(&file[used..]).unwrap().unwrap().0, second);
+    // A record cut short by a crash.
+    assert_eq!(Record::parse(&file[used..file.len() - 1]).unwrap(), None);
+    assert!(Record::parse(b"plain text, not a record").is_err());
+}
+
+/// The records of a file `file_id`, one per batch, the last one marked so.
+fn seal_records(key: &Key, file_id: u8, batches: &[&[u8]]) -> Vec<Vec<u8>> {
+    let file_id = [file_id; FILE_ID_BYTES];
+    batches
+        .iter()
+        .enumerate()
+        .map(|(index, batch)| {
+            let last = index == batches.len() - 1;
+            let nonce = [index as u8 + 1; NONCE_BYTES];
+            let sealed = key.seal(&nonce, &record_aad(&file_id, index as u64, last), batch);
+            let mut record = Vec::new();
+            Record {
+                last,
+                nonce,
+                sealed: &sealed,
+            }
+            .encode(&mut record);
+            record
+        })
+        .collect()
+}
+
+/// What the file `file_id` made of `records` decrypts to, as far as it
+/// does.
+fn open_file(key: &Key, file_id: u8, records: &[&Vec<u8>]) -> Result<Vec<u8>, String> {
+    let mut file = Vec::new();
+    FileHeader {
+        file_id: [file_id; FILE_ID_BYTES],
+    }
+    .encode(&mut file);
+    for record in records {
+        file.extend_from_slice(record);
+    }
+
+    let header = FileHeader::parse(&file)?.unwrap();
+    let mut opener = Opener::new(&header);
+    let mut plaintext = Vec::new();
+    let mut rest = &file[FILE_HEADER_BYTES..];
+    while let Some((record, used)) = Record::parse(rest)? {
+        plaintext.extend(opener.open(key, &record)?);
+        rest = &rest[used..];
+    }
+    assert!(rest.is_empty());
+    opener.finish()?;
+    Ok(plaintext)
+}
+
+#[test]
+fn files_open_whole_and_in_order() {
+    let key = Key::new(&[7; KEY_BYTES]);
+    let records = seal_records(&key, 1, &[b"one\n", b"two\n", b""]);
+
+    assert_eq!(
+        open_file(&key, 1, &[&records[0], &records[1], &records[2]]).unwrap(),
+        b"one\ntwo\n"
+    );
+}
+
+#[test]
+fn deleted_records_fail_to_open() {
+    let key = Key::new(&[7; KEY_BYTES]);
+    let records = seal_records(&key, 1, &[b"one\n", b"two\n", b"three\n"]);
+
+    assert!(open_file(&key, 1, &[&records[1], &records[2]]).is_err());
+    assert!(open_file(&key, 1, &[&records[0], &records[2]]).is_err());
+}
+
+#[test]
+fn reordered_records_fail_to_open() {
+    let key = Key::new(&[7; KEY_BYTES]);
+    let records = seal_records(&key, 1, &[b"one\n", b"two\n", b"three\n"]);
+
+    assert!(open_file(&key, 1, &[&records[1], &records[0], &records[2]]).is_err());
+}
+
+#[test]
+fn duplicated_records_fail_to_open() {
+    let key = Key::new(&[7; KEY_BYTES]);
+    let records = seal_records(&key, 1, &[b"one\n", b"two\n", b"three\n"]);
+
+    assert!(open_file(
+        &key,
+        1,
+        &[&records[0], &records[0], &records[1], &records[2]]
+    )
+    .is_err());
+    let error = open_file(
+        &key,
+        1,
+        &[&records[0], &records[1], &records[2], &records[2]],
+    );
+    assert_eq!(error.unwrap_err(), "record after the file's last");
+}
+
+#[test]
+fn records_spliced_from_another_file_fail_to_open() {
+    let key = Key::new(&[7; KEY_BYTES]);
+    let first = seal_records(&key, 1, &[b"one\n", b"two\n", b"three\n"]);
+    let second = seal_records(&key, 2, &[b"uno\n", b"dos\n", b"tres\n"]);
+
+    assert!(open_file(&key, 1, &[&first[0], &second[1], &first[2]]).is_err());
+    // The same records under the other file's header.
+    assert!(open_file(&key, 2, &[&first[0], &first[1], &first[2]]).is_err());
+}
+
+#[test]
+fn truncated_files_fail_to_open() {
+    let key = Key::new(&[7; KEY_BYTES]);
+    let records = seal_records(&key, 1, &[b"one\n", b"two\n", b"three\n"]);
+
+    let error = open_file(&key, 1, &[&records[0], &records[1]]).unwrap_err();
+    assert!(error.contains("cut short"), "{}", error);
+    assert!(open_file(&key, 1, &[]).is_err());
+}
+
+#[test]
+fn the_last_record_mark_is_authenticated() {
+    let key = Key::new(&[7; KEY_BYTES]);
+    let records = seal_records(&key, 1, &[b"one\n", b"two\n"]);
+    // Marking the first record the file's last would hide the second gone.
+    let mut first = records[0].clone();
+    first[8] = 1;
+
+    assert!(open_file(&key, 1, &[&first]).is_err());
+}
@@ -0,0 +1,222 @@
+//! Replays the recorded streams of `tests/fixtures` through the router binary
+//! and compares the files it leaves behind with the ones expected.
+//!
+//! A fixture is a directory holding:
+//!
+//! - `args`, the router's arguments, one a line, `--log-dir` and
+//!   `--simulated-clock` left out;
+//! - `input`, the stream sent to stdin, in which `@clock <RFC 3339 time>`
+//!   lines move the clock instead of being sent, the first before anything;
+//! - `expected`, the files the log directory must hold once the router has
+//!   exited, named and filled exactly.
+//!
+//! Before moving the clock, the runner sends a line to its own `fixture`
+//! channel and waits until it is written, so every line before it was taken
+//! at the earlier time. Its `sync` file gives that line, `{}` standing for a
+//! counter, if the fixture's framing isn't `lines`. The channel's files are
+//! left out of the comparison. The router runs in UTC, so file names don't
+//! depend on the machine's zone.
+//!
+//! `UPDATE_GOLDEN=1 cargo test --test golden` writes what the router left
+//! behind as the expected files instead, to record a new fixture or accept a
+//! change in the output.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Channel the runner syncs on.
+const SYNC_CHANNEL: &str = "fixture";
+
+const CLOCK_DIRECTIVE: &str = "@clock ";
+
+/// The files of `dir` by name, leaving out hidden ones like the lock.
+fn files(dir: &Path) -> BTreeMap<String, String> {
+    if !dir.exists() {
+        return BTreeMap::new();
+    }
+
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_file())
+        .map(|path| {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, path)
+        })
+        .filter(|(name, _)| !name.starts_with('.'))
+        .map(|(name, path)| (name, fs::read_to_string(&path).unwrap()))
+        .collect()
+}
+
+fn is_sync_file(name: &str) -> bool {
+    name.starts_with(&format!("{}_", SYNC_CHANNEL))
+}
+
+/// `args` with `value` added to the comma-separated list of `option`, which
+/// is added if missing.
+fn add_to_list(args: &mut Vec<String>, option: &str, value: &str) {
+    match args.iter().position(|arg| arg == option) {
+        Some(index) => {
+            let list = &mut args[index + 1];
+            if !list.is_empty() {
+                list.push(',');
+            }
+            list.push_str(value);
+        }
+        None => args.extend([option.to_string(), value.to_string()]),
+    }
+}
+
+fn fixture_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// Runs the fixture `name` and checks, or with `UPDATE_GOLDEN` records, the
+/// files it leaves behind.
+fn replay(name: &str) {
+    let fixture = fixture_dir().join(name);
+    let dir = std::env::temp_dir().join(format!(
+        "log-revolve-golden-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    let log_dir = dir.join("logs");
+    fs::create_dir_all(&log_dir).unwrap();
+    let clock = dir.join("clock");
+
+    let mut args: Vec<String> = fs::read_to_string(fixture.join("args"))
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect();
+    add_to_list(&mut args, "--accepted-log-channels", SYNC_CHANNEL);
+    add_to_list(
+        &mut args,
+        "--buffering-profiles",
+        &format!("{}=latency", SYNC_CHANNEL),
+    );
+    let sync = fs::read_to_string(fixture.join("sync"))
+        .unwrap_or_else(|_| format!("{}\n{{}}\n", SYNC_CHANNEL));
+    let input = fs::read_to_string(fixture.join("input")).unwrap();
+
+    let mut lines = input.split_inclusive('\n');
+    let start = lines
+        .next()
+        .and_then(|line| line.strip_prefix(CLOCK_DIRECTIVE))
+        .expect("the input starts with the time");
+    fs::write(&clock, start.trim_end()).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+        .arg("--log-dir")
+        .arg(&log_dir)
+        .arg("--simulated-clock")
+        .arg(&clock)
+        .args(&args)
+        .env("TZ", "UTC")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+
+    let mut syncs = 0;
+    for line in lines {
+        let time = match line.strip_prefix(CLOCK_DIRECTIVE) {
+            Some(time) => time.trim_end(),
+            None => {
+                stdin.write_all(line.as_bytes()).unwrap();
+                continue;
+            }
+        };
+
+        syncs += 1;
+        let marker = syncs.to_string();
+        stdin
+            .write_all(sync.replace("{}", &marker).as_bytes())
+            .unwrap();
+        stdin.flush().unwrap();
+        let deadline = Instant::now() + WAIT_TIMEOUT;
+        while !files(&log_dir)
+            .iter()
+            .filter(|(name, _)| is_sync_file(name))
+            .any(|(_, contents)| contents.lines().any(|line| line == marker))
+        {
+            assert!(Instant::now() < deadline, "{} never synced", name);
+            thread::sleep(Duration::from_millis(20));
+        }
+        fs::write(&clock, time).unwrap();
+    }
+    drop(stdin);
+    assert!(child.wait().unwrap().success());
+
+    let written: BTreeMap<String, String> = files(&log_dir)
+        .into_iter()
+        .filter(|(name, _)| !is_sync_file(name))
+        .collect();
+    let expected_dir = fixture.join("expected");
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        let _ = fs::remove_dir_all(&expected_dir);
+        fs::create_dir_all(&expected_dir).unwrap();
+        for (file, contents) in written.iter() {
+            fs::write(expected_dir.join(file), contents).unwrap();
+        }
+    }
+    let expected = files(&expected_dir);
+    let _ = fs::remove_dir_all(&dir);
+
+    assert_eq!(written, expected, "{} left other files behind", name);
+}
+
+#[test]
+fn every_fixture_has_a_test() {
+    let mut fixtures: Vec<String> = fs::read_dir(fixture_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    fixtures.sort();
+
+    assert_eq!(
+        fixtures,
+        [
+            "hourly_rotation",
+            "max_file_size",
+            "paired_framing",
+            "prefixed_framing",
+            "producer_framing"
+        ]
+    );
+}
+
+#[test]
+fn hourly_rotation() {
+    replay("hourly_rotation");
+}
+
+#[test]
+fn max_file_size() {
+    replay("max_file_size");
+}
+
+#[test]
+fn paired_framing() {
+    replay("paired_framing");
+}
+
+#[test]
+fn prefixed_framing() {
+    replay("prefixed_framing");
+}
+
+#[test]
+fn producer_framing() {
+    replay("producer_framing");
+}
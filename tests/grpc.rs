@@ -0,0 +1,64 @@
+//! Checks the wire of the gRPC service against the protobuf encoding and the
+//! framing gRPC gives its messages.
+
+#![cfg(feature = "grpc")]
+
+use log_revolve_rs::grpc::{self, Ack, Line, Messages};
+use prost::Message;
+
+fn hex(hex: &str) -> Vec<u8> {
+    let digits: Vec<u8> = hex
+        .bytes()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect();
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn messages_are_gathered_across_frames() {
+    let line = Line {
+        channel: String::from("app"),
+        payload: b"hello".to_vec(),
+    };
+    let framed = grpc::message(&line.encode_to_vec());
+    let mut messages = Messages::default();
+
+    messages.push(&framed[..4]);
+    assert_eq!(messages.pop().unwrap(), None);
+    messages.push(&framed[4..]);
+    messages.push(&framed);
+    assert_eq!(
+        Line::decode(messages.pop().unwrap().unwrap().as_slice()).unwrap(),
+        line
+    );
+    assert_eq!(
+        Line::decode(messages.pop().unwrap().unwrap().as_slice()).unwrap(),
+        line
+    );
+    assert!(messages.is_empty());
+
+    messages.push(b"\x01\0\0\0\0");
+    assert!(messages.pop().is_err());
+}
+
+#[test]
+fn protobuf_messages_match_their_encoding_and_skip_unknown_fields() {
+    let line = Line {
+        channel: String::from("app"),
+        payload: b"hello".to_vec(),
+    };
+    assert_eq!(line.encode_to_vec(), hex("0a03 6170 7012 0568 656c 6c6f"));
+    // A varint field 3 and a fixed 32-bit field 4, unknown to the service.
+    assert_eq!(
+        Line::decode(&*hex("1801 0a03 6170 7025 0000 0000 1205 6865 6c6c 6f")).unwrap(),
+        line
+    );
+    assert!(Line::decode(&*hex("0a09 6170 70")).is_err());
+
+    let ack = Ack { written: 300 };
+    assert_eq!(ack.encode_to_vec(), hex("08ac 02"));
+    assert_eq!(Ack::decode(&*ack.encode_to_vec()).unwrap(), ack);
+}
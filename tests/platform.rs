@@ -0,0 +1,52 @@
+//! Checks the rules of every platform, whichever one the tests run on.
+
+use log_revolve_rs::platform::{self, CurrentLink, Platform};
+
+#[test]
+fn file_names_are_checked_against_each_platform() {
+    for platform in [Platform::Unix, Platform::Windows].iter() {
+        assert!(platform.check_file_name("app_v2.audit").is_ok());
+        for name in ["", ".", "..", "acme/app", "app\0"].iter() {
+            assert!(platform.check_file_name(name).is_err(), "{}", name);
+        }
+    }
+
+    for name in [
+        "acme\\app",
+        "app:1",
+        "app?",
+        "app.",
+        "app ",
+        "con",
+        "NUL.log",
+        "Com1",
+    ]
+    .iter()
+    {
+        assert!(Platform::Unix.check_file_name(name).is_ok(), "{}", name);
+        assert!(Platform::Windows.check_file_name(name).is_err(), "{}", name);
+    }
+    assert!(Platform::Windows.check_file_name("console").is_ok());
+}
+
+#[test]
+fn paths_take_the_platform_separators() {
+    assert_eq!(Platform::Unix.native_path("acme/app.log"), "acme/app.log");
+    assert_eq!(
+        Platform::Windows.native_path("acme/app.log"),
+        "acme\\app.log"
+    );
+    assert!(Platform::Windows.is_separator('\\'));
+    assert!(!Platform::Unix.is_separator('\\'));
+
+    assert_eq!(Platform::Unix.current_link(), CurrentLink::Symlink);
+    assert_eq!(Platform::Windows.current_link(), CurrentLink::HardLink);
+}
+
+#[test]
+fn line_endings_are_stripped_once() {
+    assert_eq!(platform::strip_line_ending("started\r\n"), "started");
+    assert_eq!(platform::strip_line_ending("started\n"), "started");
+    assert_eq!(platform::strip_line_ending("started\r\r\n"), "started\r");
+    assert_eq!(platform::strip_line_ending("started"), "started");
+}
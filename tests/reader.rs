@@ -0,0 +1,193 @@
+//! Reads channel history back from a log directory laid out the way the
+//! router leaves it.
+
+use async_std::prelude::*;
+
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, TimeZone};
+
+use std::fs;
+use std::path::PathBuf;
+
+use log_revolve_rs::reader::ChannelReader;
+use log_revolve_rs::rotation::{DirLayout, FileNameTemplate, FileTimestamp};
+use log_revolve_rs::runtime;
+
+fn log_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("log-revolve-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
+
+fn at(hour: u32, minute: u32) -> DateTime<Local> {
+    let time = NaiveDate::from_ymd_opt(2024, 6, 1)
+        .and_then(|date| date.and_hms_opt(hour, minute, 0))
+        .unwrap();
+    Local.from_local_datetime(&time).single().unwrap()
+}
+
+fn read<Tz>(reader: &ChannelReader, from: DateTime<Tz>, to: DateTime<Tz>) -> Vec<String>
+where
+    Tz: TimeZone,
+    Tz::Offset: Send,
+{
+    runtime::block_on(async {
+        let mut lines = reader.range("app", from, to).await.unwrap();
+        let mut read = Vec::new();
+        while let Some(line) = lines.next().await {
+            read.push(line.unwrap());
+        }
+        read
+    })
+}
+
+#[test]
+fn range_reads_overlapping_files_in_order() {
+    let dir = log_dir("range");
+    fs::write(dir.join("app_2024-06-01-12-00-00.log"), "noon\n").unwrap();
+    fs::write(dir.join("app_2024-06-01-13-00-00.log"), "one\n").unwrap();
+    fs::write(dir.join("app_2024-06-01-13-30-00.log"), "half past one\n").unwrap();
+    fs::write(dir.join("app_2024-06-01-14-00-00.log"), "two\n").unwrap();
+    fs::write(dir.join("web_2024-06-01-13-00-00.log"), "other channel\n").unwrap();
+    fs::write(dir.join("app_2024-06-01-13-00-00.log.gz.partial"), "").unwrap();
+    let reader = ChannelReader::new(dir.clone(), FileTimestamp::Seconds);
+
+    assert_eq!(
+        read(&reader, at(13, 10), at(13, 40)),
+        ["one", "half past one"]
+    );
+    assert_eq!(read(&reader, at(13, 30), at(14, 0)), ["half past one"]);
+    assert_eq!(
+        read(&reader, at(0, 0), at(23, 0)),
+        ["noon", "one", "half past one", "two"]
+    );
+    assert!(read(&reader, at(15, 0), at(16, 0)).is_empty());
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn foreign_layouts_are_read_through_a_template() {
+    let dir = log_dir("template");
+    fs::write(dir.join("app.20240531.log"), "yesterday\n").unwrap();
+    fs::write(dir.join("app.20240601.log"), "today\n").unwrap();
+    fs::write(dir.join("app_2024-06-01-13-00-00.log"), "not ours\n").unwrap();
+    let reader = ChannelReader::with_parser(
+        dir.clone(),
+        FileNameTemplate::new("{channel}.%Y%m%d.log", Duration::days(1)),
+    );
+
+    assert_eq!(read(&reader, at(13, 0), at(14, 0)), ["today"]);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn compressed_files_are_read_like_plain_ones() {
+    use std::io::Write;
+
+    let dir = log_dir("gzip");
+    let file = fs::File::create(dir.join("app_2024-06-01-13-00-00.log.gz")).unwrap();
+    let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+    encoder.write_all(b"one\nmore\n").unwrap();
+    encoder.finish().unwrap();
+    fs::write(dir.join("app_2024-06-01-14-00-00.log"), "two\n").unwrap();
+    let reader = ChannelReader::new(dir.clone(), FileTimestamp::Seconds);
+
+    assert_eq!(read(&reader, at(13, 0), at(15, 0)), ["one", "more", "two"]);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn files_sharded_into_subdirectories_are_read_in_their_zone() {
+    let dir = log_dir("sharded");
+    let day = dir.join("app/2024/06/01");
+    fs::create_dir_all(&day).unwrap();
+    fs::create_dir_all(dir.join("elsewhere")).unwrap();
+    fs::write(day.join("app_2024-06-01-13-00-00.log"), "one\n").unwrap();
+    fs::write(day.join("app_2024-06-01-14-00-00.log"), "two\n").unwrap();
+    fs::write(
+        dir.join("elsewhere/app_2024-06-01-13-00-00.log"),
+        "not ours\n",
+    )
+    .unwrap();
+    let reader = ChannelReader::new(dir.clone(), FileTimestamp::Seconds).with_dirs(
+        "{channel}/{year}/{month}/{day}"
+            .parse::<DirLayout>()
+            .unwrap(),
+    );
+    let tokyo = FixedOffset::east_opt(9 * 3600).unwrap();
+    let at = |hour| {
+        let time = NaiveDate::from_ymd_opt(2024, 6, 1)
+            .and_then(|date| date.and_hms_opt(hour, 30, 0))
+            .unwrap();
+        tokyo.from_local_datetime(&time).unwrap()
+    };
+
+    assert_eq!(read(&reader, at(13), at(14)), ["one", "two"]);
+    assert_eq!(read(&reader, at(14), at(15)), ["two"]);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn encrypted_files_are_read_with_their_key() {
+    use log_revolve_rs::encryption::{record_aad, FileHeader, Key, Record};
+
+    let dir = log_dir("encrypted");
+    let key = [7; 32];
+    let file_id = [3; 16];
+    let mut file = Vec::new();
+    FileHeader { file_id }.encode(&mut file);
+    let batches = [
+        ([1; 12], &b"one\nmo"[..]),
+        ([2; 12], &b"re\n"[..]),
+        ([3; 12], &b""[..]),
+    ];
+    let mut cut = 0;
+    for (index, (nonce, batch)) in batches.iter().enumerate() {
+        let last = index == batches.len() - 1;
+        let aad = record_aad(&file_id, index as u64, last);
+        let sealed = Key::new(&key).seal(nonce, &aad, batch);
+        cut = file.len();
+        Record {
+            last,
+            nonce: *nonce,
+            sealed: &sealed,
+        }
+        .encode(&mut file);
+    }
+    fs::write(dir.join("app_2024-06-01-13-00-00.log.enc"), &file).unwrap();
+    fs::write(dir.join("app_2024-06-01-14-00-00.log"), "two\n").unwrap();
+
+    let reader = ChannelReader::new(dir.clone(), FileTimestamp::Seconds).with_key(Key::new(&key));
+    assert_eq!(read(&reader, at(13, 0), at(15, 0)), ["one", "more", "two"]);
+
+    // Without its last record, a file the router is done with was cut
+    // short, while its newest one may still be written.
+    fs::write(dir.join("app_2024-06-01-13-00-00.log.enc"), &file[..cut]).unwrap();
+    let error = runtime::block_on(async {
+        let mut lines = reader.range("app", at(13, 0), at(15, 0)).await.unwrap();
+        loop {
+            if let Err(error) = lines.next().await.unwrap() {
+                break error;
+            }
+        }
+    });
+    assert!(error.to_string().contains("cut short"), "{}", error);
+    fs::remove_file(dir.join("app_2024-06-01-14-00-00.log")).unwrap();
+    assert_eq!(read(&reader, at(13, 0), at(15, 0)), ["one", "more"]);
+
+    let keyless = ChannelReader::new(dir.clone(), FileTimestamp::Seconds);
+    let error = runtime::block_on(async {
+        let mut lines = keyless.range("app", at(13, 0), at(15, 0)).await.unwrap();
+        lines.next().await.unwrap().unwrap_err()
+    });
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+    fs::remove_dir_all(dir).unwrap();
+}
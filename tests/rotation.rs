@@ -0,0 +1,343 @@
+//! Drives rotation schedules through random traffic with the clock handed in,
+//! keeping "files" in memory, and checks what ends up where.
+
+use chrono::{DateTime, Duration, FixedOffset, NaiveDateTime, TimeZone, Timelike};
+use proptest::prelude::*;
+
+use std::collections::BTreeMap;
+
+use log_revolve_rs::rotation::{self, DirLayout, FileTimestamp, Rotation, Schedule};
+
+const CHANNEL: &str = "app_v2";
+
+/// Something happening on a channel, some time after the previous event.
+#[derive(Clone, Debug)]
+enum Event {
+    Line { after_ms: i64 },
+    ForcedRotation { after_ms: i64 },
+}
+
+/// Files of one channel, each holding the times its lines were written at.
+#[derive(Default)]
+struct MemoryStorage {
+    files: BTreeMap<String, Vec<(usize, DateTime<FixedOffset>)>>,
+}
+
+impl MemoryStorage {
+    fn append(&mut self, file_name: String, line: usize, time: DateTime<FixedOffset>) {
+        self.files.entry(file_name).or_default().push((line, time));
+    }
+}
+
+/// Mirrors the router's write path: a line due for a new period rotates the
+/// file before being written.
+fn run(
+    rotation: Rotation,
+    start: DateTime<FixedOffset>,
+    events: &[Event],
+) -> (MemoryStorage, usize) {
+    let mut storage = MemoryStorage::default();
+    let mut schedule = Schedule::new(rotation, &start);
+    let mut now = start;
+    let mut lines = 0;
+
+    for event in events {
+        match *event {
+            Event::Line { after_ms } => {
+                now += Duration::milliseconds(after_ms);
+                if schedule.is_due(&now) {
+                    schedule.advance(&now);
+                }
+                storage.append(schedule.file_name(CHANNEL), lines, now);
+                lines += 1;
+            }
+            Event::ForcedRotation { after_ms } => {
+                now += Duration::milliseconds(after_ms);
+                schedule.force(&now);
+            }
+        }
+    }
+
+    (storage, lines)
+}
+
+/// A time cut down to what a file name stamped in `timestamp` holds.
+fn truncate(time: NaiveDateTime, timestamp: FileTimestamp) -> NaiveDateTime {
+    let time = time.with_nanosecond(0).unwrap();
+    match timestamp {
+        FileTimestamp::Seconds | FileTimestamp::Rfc3339Seconds => time,
+        FileTimestamp::Hours | FileTimestamp::Rfc3339Hours => {
+            time.with_minute(0).unwrap().with_second(0).unwrap()
+        }
+    }
+}
+
+fn file_timestamp() -> impl Strategy<Value = FileTimestamp> {
+    prop_oneof![
+        Just(FileTimestamp::Seconds),
+        Just(FileTimestamp::Hours),
+        Just(FileTimestamp::Rfc3339Seconds),
+        Just(FileTimestamp::Rfc3339Hours),
+    ]
+}
+
+fn start_time() -> impl Strategy<Value = DateTime<FixedOffset>> {
+    // Offsets in quarter hours, so hour boundaries of the zone don't line up
+    // with those of UTC.
+    (1_000_000_000i64..2_000_000_000, -48i32..=56).prop_map(|(seconds, quarters)| {
+        FixedOffset::east_opt(quarters * 15 * 60)
+            .unwrap()
+            .timestamp_opt(seconds, 0)
+            .unwrap()
+    })
+}
+
+fn events() -> impl Strategy<Value = Vec<Event>> {
+    let event = prop_oneof![
+        8 => (0i64..1_200_000).prop_map(|after_ms| Event::Line { after_ms }),
+        1 => (0i64..4 * 3_600_000).prop_map(|after_ms| Event::Line { after_ms }),
+        1 => (0i64..1_800_000).prop_map(|after_ms| Event::ForcedRotation { after_ms }),
+    ];
+    proptest::collection::vec(event, 0..200)
+}
+
+proptest! {
+    #[test]
+    fn every_line_lands_in_exactly_one_file(
+        timestamp in file_timestamp(),
+        start in start_time(),
+        events in events(),
+    ) {
+        let (storage, lines) = run(Rotation::Hourly(timestamp), start, &events);
+
+        let mut seen = vec![0; lines];
+        for written in storage.files.values() {
+            for &(line, _) in written {
+                seen[line] += 1;
+            }
+        }
+        prop_assert!(seen.iter().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn file_periods_never_overlap(
+        timestamp in file_timestamp(),
+        start in start_time(),
+        events in events(),
+    ) {
+        let (storage, _) = run(Rotation::Hourly(timestamp), start, &events);
+
+        let mut periods: Vec<_> = storage
+            .files
+            .values()
+            .map(|written| (written[0].1, written[written.len() - 1].1))
+            .collect();
+        periods.sort();
+        for pair in periods.windows(2) {
+            prop_assert!(pair[0].1 <= pair[1].0, "{:?} overlaps {:?}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn file_names_parse_back_to_their_period(
+        timestamp in file_timestamp(),
+        start in start_time(),
+        events in events(),
+    ) {
+        let (storage, _) = run(Rotation::Hourly(timestamp), start, &events);
+
+        for (file_name, written) in storage.files.iter() {
+            let stamp = rotation::parse_file_name(CHANNEL, timestamp, file_name);
+            prop_assert!(stamp.is_some(), "{} doesn't parse", file_name);
+            let stamp = stamp.unwrap();
+
+            let hour = truncate(stamp, FileTimestamp::Hours);
+            for &(_, time) in written {
+                let time = time.naive_local();
+                prop_assert!(truncate(time, timestamp) >= stamp, "{} holds {}", file_name, time);
+                prop_assert!(time < hour + Duration::hours(1), "{} holds {}", file_name, time);
+            }
+        }
+    }
+
+    #[test]
+    fn periodic_files_stay_within_their_period(
+        period_minutes in prop_oneof![Just(5i64), Just(15), Just(360), Just(1440)],
+        start in start_time(),
+        events in events(),
+    ) {
+        let period = Duration::minutes(period_minutes);
+        let rotation = Rotation::Periodic(FileTimestamp::Seconds, period);
+        let (storage, _) = run(rotation, start, &events);
+
+        for (file_name, written) in storage.files.iter() {
+            let stamp = rotation::parse_file_name(CHANNEL, FileTimestamp::Seconds, file_name);
+            prop_assert!(stamp.is_some(), "{} doesn't parse", file_name);
+            let stamp = stamp.unwrap();
+
+            let midnight = stamp.date().and_hms_opt(0, 0, 0).unwrap();
+            let elapsed = (stamp - midnight).num_seconds();
+            let end = midnight
+                + Duration::seconds(elapsed - elapsed % period.num_seconds())
+                + period;
+            for &(_, time) in written {
+                let time = time.naive_local();
+                prop_assert!(time >= stamp && time < end, "{} holds {}", file_name, time);
+            }
+        }
+    }
+
+    #[test]
+    fn external_rotation_keeps_a_single_file(
+        start in start_time(),
+        events in events(),
+    ) {
+        let (storage, lines) = run(Rotation::External, start, &events);
+
+        prop_assert!(storage.files.len() <= 1);
+        prop_assert_eq!(storage.files.values().map(Vec::len).sum::<usize>(), lines);
+        if let Some(file_name) = storage.files.keys().next() {
+            prop_assert_eq!(file_name.as_str(), "app_v2.log");
+        }
+    }
+}
+
+fn time(offset_hours: i32, date: (i32, u32, u32), hms: (u32, u32, u32)) -> DateTime<FixedOffset> {
+    FixedOffset::east_opt(offset_hours * 3600)
+        .unwrap()
+        .with_ymd_and_hms(date.0, date.1, date.2, hms.0, hms.1, hms.2)
+        .unwrap()
+}
+
+#[test]
+fn hourly_files_rotate_across_midnight() {
+    let mut schedule = Schedule::new(
+        Rotation::Hourly(FileTimestamp::Seconds),
+        &time(0, (2024, 12, 31), (23, 59, 59)),
+    );
+    assert!(schedule.is_due(&time(0, (2025, 1, 1), (0, 0, 0))));
+
+    schedule.advance(&time(0, (2025, 1, 1), (0, 0, 0)));
+    assert_eq!(
+        schedule.file_name(CHANNEL),
+        "app_v2_2025-01-01-00-00-00.log"
+    );
+    assert!(!schedule.is_due(&time(0, (2025, 1, 1), (0, 59, 59))));
+}
+
+#[test]
+fn daily_files_rotate_at_midnight_of_their_offset() {
+    let rotation = Rotation::Periodic(FileTimestamp::Seconds, Duration::days(1));
+    let mut schedule = Schedule::new(rotation, &time(5, (2024, 3, 9), (12, 0, 0)));
+    assert_eq!(
+        schedule.file_name(CHANNEL),
+        "app_v2_2024-03-09-00-00-00.log"
+    );
+    assert!(!schedule.is_due(&time(5, (2024, 3, 9), (23, 59, 59))));
+    assert!(schedule.is_due(&time(5, (2024, 3, 10), (0, 0, 0))));
+
+    schedule.advance(&time(5, (2024, 3, 10), (0, 0, 0)));
+    assert_eq!(
+        schedule.file_name(CHANNEL),
+        "app_v2_2024-03-10-00-00-00.log"
+    );
+}
+
+#[test]
+fn files_rotate_across_daylight_saving_changes() {
+    let rotation = Rotation::Hourly(FileTimestamp::Rfc3339Seconds);
+
+    // Clocks go forward from 02:00 +01:00 to 03:00 +02:00: one instant.
+    let mut schedule = Schedule::new(rotation, &time(1, (2024, 3, 31), (1, 30, 0)));
+    assert!(!schedule.is_due(&time(2, (2024, 3, 31), (2, 59, 59))));
+    assert!(schedule.is_due(&time(2, (2024, 3, 31), (3, 0, 0))));
+    schedule.advance(&time(2, (2024, 3, 31), (3, 0, 0)));
+    assert_eq!(schedule.opened_at, time(2, (2024, 3, 31), (3, 0, 0)));
+
+    // Clocks go back from 03:00 +02:00 to 02:00 +01:00: the repeated hour is
+    // appended to the file of the first, named without the offset.
+    let mut schedule = Schedule::new(rotation, &time(2, (2024, 10, 27), (2, 30, 0)));
+    let summer = schedule.file_name(CHANNEL);
+    assert!(!schedule.is_due(&time(2, (2024, 10, 27), (2, 59, 59))));
+    assert!(schedule.is_due(&time(1, (2024, 10, 27), (2, 0, 0))));
+    schedule.advance(&time(1, (2024, 10, 27), (2, 0, 0)));
+    assert_eq!(schedule.file_name(CHANNEL), summer);
+    assert!(!schedule.is_due(&time(1, (2024, 10, 27), (2, 59, 59))));
+}
+
+#[test]
+fn a_clock_set_back_before_the_period_keeps_to_the_current_file() {
+    let mut schedule = Schedule::new(
+        Rotation::Hourly(FileTimestamp::Seconds),
+        &time(0, (2024, 6, 1), (10, 30, 0)),
+    );
+    assert!(!schedule.is_due(&time(0, (2024, 6, 1), (10, 5, 0))));
+    assert!(!schedule.is_due(&time(0, (2024, 6, 1), (9, 50, 0))));
+    assert!(schedule.is_due(&time(0, (2024, 6, 1), (11, 0, 0))));
+
+    // A file cut ahead of the schedule while the clock is behind is stamped
+    // with the current file's time again, never an earlier one.
+    schedule.force(&time(0, (2024, 6, 1), (9, 55, 0)));
+    assert_eq!(
+        schedule.file_name(CHANNEL),
+        "app_v2_2024-06-01-10-00-00.log"
+    );
+    assert_eq!(schedule.seq, 1);
+    assert!(!schedule.is_due(&time(0, (2024, 6, 1), (9, 56, 0))));
+    assert!(schedule.is_due(&time(0, (2024, 6, 1), (11, 0, 0))));
+}
+
+#[test]
+fn sequenced_file_names_parse_back_to_their_second() {
+    let file_name = "app_v2_2024-06-01-10-00-00.log";
+    let sequenced = rotation::sequenced_file_name(file_name, 1);
+    assert_eq!(sequenced, "app_v2_2024-06-01-10-00-00.001.log");
+    assert_eq!(
+        rotation::parse_file_name(CHANNEL, FileTimestamp::Seconds, &sequenced),
+        rotation::parse_file_name(CHANNEL, FileTimestamp::Seconds, file_name)
+    );
+
+    assert_eq!(rotation::file_sequence(file_name), 0);
+    assert_eq!(rotation::file_sequence(&sequenced), 1);
+    assert_eq!(
+        rotation::file_sequence(&rotation::sequenced_file_name(file_name, 1234)),
+        1234
+    );
+}
+
+#[test]
+fn dir_layouts_shard_by_the_time_files_were_opened() {
+    let layout: DirLayout = "{log_dir}/{channel}/{year}/{month}/{day}".parse().unwrap();
+    let schedule = Schedule::new(
+        Rotation::Hourly(FileTimestamp::Seconds),
+        &time(2, (2024, 6, 1), (9, 30, 0)),
+    );
+    assert_eq!(layout.dir(&schedule, CHANNEL), "app_v2/2024/06/01");
+    assert_eq!(layout.depth(), 4);
+
+    assert!(layout.matches(0, CHANNEL));
+    assert!(layout.matches(1, "2024"));
+    assert!(layout.matches(3, "01"));
+    assert!(!layout.matches(1, "pending"));
+    assert!(!layout.matches(2, "6"));
+    assert!(!layout.matches(4, "01"));
+
+    let prefixed: DirLayout = "shards/{channel}-{year}{month}".parse().unwrap();
+    assert!(prefixed.matches(0, "shards"));
+    assert!(prefixed.matches(1, "app-v2-202406"));
+    assert!(!prefixed.matches(1, "app-v2-2024"));
+}
+
+#[test]
+fn dir_layouts_stay_within_the_log_directory() {
+    for template in [
+        "/var/log/{channel}",
+        "{channel}/../{year}",
+        "",
+        "{channel}/{week}",
+    ]
+    .iter()
+    {
+        assert!(template.parse::<DirLayout>().is_err(), "{}", template);
+    }
+}
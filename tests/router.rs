@@ -0,0 +1,4036 @@
+//! Runs the router binary against a temporary log directory, feeding it
+//! through stdin and sockets with its clock simulated, and checks the files
+//! it leaves behind.
+
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+
+use log_revolve_rs::framing::length_prefixed;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn at(hour: u32, minute: u32, second: u32) -> DateTime<Local> {
+    let time = NaiveDate::from_ymd_opt(2024, 6, 1)
+        .and_then(|date| date.and_hms_opt(hour, minute, second))
+        .unwrap();
+    Local.from_local_datetime(&time).single().unwrap()
+}
+
+/// Name of a channel's file opened at `time`.
+fn file_name(channel: &str, time: DateTime<Local>) -> String {
+    format!("{}_{}.log", channel, time.format("%Y-%m-%d-%H-%M-%S"))
+}
+
+/// A running router, its log directory and its clock.
+struct Router {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    dir: PathBuf,
+    log_dir: PathBuf,
+    clock: PathBuf,
+}
+
+impl Router {
+    /// Starts the router at `time` and waits until it reads its inputs.
+    fn start(name: &str, time: DateTime<Local>, args: &[&str]) -> Self {
+        Router::start_with_env(name, time, args, &[])
+    }
+
+    /// `start`, with `env` set for the router on top of the tests' own.
+    fn start_with_env(
+        name: &str,
+        time: DateTime<Local>,
+        args: &[&str],
+        env: &[(&str, &str)],
+    ) -> Self {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"));
+        command.args(args).envs(env.iter().copied());
+        Router::start_command(name, time, command)
+    }
+
+    /// `start`, the router run by `command`, e.g. through a shell setting up
+    /// the descriptors it inherits.
+    fn start_command(name: &str, time: DateTime<Local>, command: Command) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "log-revolve-router-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("logs")).unwrap();
+
+        Router::spawn_in(dir, time, command)
+    }
+
+    /// Starts the router at `time` on the log directory of `dir` as it is.
+    fn start_in(dir: PathBuf, time: DateTime<Local>, args: &[&str], env: &[(&str, &str)]) -> Self {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"));
+        command.args(args).envs(env.iter().copied());
+        Router::spawn_in(dir, time, command)
+    }
+
+    fn spawn_in(dir: PathBuf, time: DateTime<Local>, mut command: Command) -> Self {
+        let log_dir = dir.join("logs");
+        let clock = dir.join("clock");
+        fs::write(&clock, time.to_rfc3339()).unwrap();
+
+        let mut child = command
+            .arg("--log-dir")
+            .arg(&log_dir)
+            .arg("--simulated-clock")
+            .arg(&clock)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let mut banner = String::new();
+        BufReader::new(child.stdout.as_mut().unwrap())
+            .read_line(&mut banner)
+            .unwrap();
+        assert_eq!(banner, "log-revolve-rs started\n");
+
+        Router {
+            stdin: child.stdin.take(),
+            child,
+            dir,
+            log_dir,
+            clock,
+        }
+    }
+
+    fn set_clock(&self, time: DateTime<Local>) {
+        fs::write(&self.clock, time.to_rfc3339()).unwrap();
+    }
+
+    fn send(&mut self, channel: &str, message: &str) {
+        let stdin = self.stdin.as_mut().unwrap();
+        write!(stdin, "{}\n{}\n", channel, message).unwrap();
+        stdin.flush().unwrap();
+    }
+
+    /// Waits for a file of the log directory to hold exactly `contents`.
+    fn wait_for(&self, file_name: &str, contents: &str) {
+        let path = self.log_dir.join(file_name);
+        let deadline = Instant::now() + WAIT_TIMEOUT;
+        while fs::read_to_string(&path).ok().as_deref() != Some(contents) {
+            assert!(
+                Instant::now() < deadline,
+                "{} never held {:?}",
+                file_name,
+                contents
+            );
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// Closes stdin and waits for the router to finish, returning the files
+    /// left in the log directory.
+    fn stop(mut self) -> BTreeMap<String, String> {
+        self.stdin.take();
+        assert!(self.child.wait().unwrap().success());
+
+        files(&self.log_dir)
+    }
+
+    /// Stops the router and starts it again at `time` with `args`, on the
+    /// log directory as it left it.
+    fn restart(mut self, time: DateTime<Local>, args: &[&str]) -> Self {
+        self.stdin.take();
+        assert!(self.child.wait().unwrap().success());
+
+        let dir = std::mem::take(&mut self.dir);
+        Router::start_in(dir, time, args, &[])
+    }
+
+    /// Sends the router a signal, e.g. `HUP`.
+    #[cfg(unix)]
+    fn kill(&self, signal: &str) {
+        let status = Command::new("kill")
+            .arg(format!("-{}", signal))
+            .arg(self.child.id().to_string())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    /// Stops the router with a signal, e.g. `TERM`, its stdin still open.
+    #[cfg(unix)]
+    fn signal(mut self, signal: &str) -> BTreeMap<String, String> {
+        self.kill(signal);
+        assert!(self.child.wait().unwrap().success());
+
+        files(&self.log_dir)
+    }
+}
+
+impl Drop for Router {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// The files directly under `dir`, by name; tenant directories are left out.
+fn files(dir: &Path) -> BTreeMap<String, String> {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_file())
+        .map(|path| {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, fs::read_to_string(&path).unwrap())
+        })
+        .collect()
+}
+
+#[test]
+fn lines_move_to_a_new_file_as_the_hour_turns() {
+    let mut router = Router::start(
+        "hourly",
+        at(12, 10, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+        ],
+    );
+    router.send("app", "before");
+    router.wait_for(&file_name("app", at(12, 0, 0)), "before\n");
+
+    router.set_clock(at(13, 5, 0));
+    router.send("app", "after");
+    let files = router.stop();
+
+    assert_eq!(files[&file_name("app", at(12, 0, 0))], "before\n");
+    assert_eq!(files[&file_name("app", at(13, 0, 0))], "after\n");
+}
+
+#[test]
+fn writer_threads_keep_each_channels_lines_in_order() {
+    let mut router = Router::start(
+        "writer-threads",
+        at(12, 10, 0),
+        &[
+            "--accepted-log-channels",
+            "a,b,c",
+            "--buffering-profiles",
+            "a=latency,b=latency,c=latency",
+            "--writer-threads",
+            "2",
+        ],
+    );
+    let channels = ["a", "b", "c"];
+    let lines =
+        |range: std::ops::Range<usize>| range.map(|n| format!("{}\n", n)).collect::<String>();
+    for n in 0..50 {
+        for channel in channels {
+            router.send(channel, &n.to_string());
+        }
+    }
+    for channel in channels {
+        router.wait_for(&file_name(channel, at(12, 0, 0)), &lines(0..50));
+    }
+
+    router.set_clock(at(13, 5, 0));
+    for n in 50..100 {
+        for channel in channels {
+            router.send(channel, &n.to_string());
+        }
+    }
+    let files = router.stop();
+
+    for channel in channels {
+        assert_eq!(files[&file_name(channel, at(12, 0, 0))], lines(0..50));
+        assert_eq!(files[&file_name(channel, at(13, 0, 0))], lines(50..100));
+    }
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn files_compressed_as_written_decode_whole_once_let_go_of() {
+    for compression in [
+        &["--compress", "gzip"][..],
+        &["--compression", "gzip", "--compression-level", "1"],
+    ] {
+        let mut args = vec![
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--stream-compress",
+        ];
+        args.extend_from_slice(compression);
+        let mut router = Router::start("stream-compress", at(12, 10, 0), &args);
+        let before = router
+            .log_dir
+            .join(format!("{}.gz", file_name("app", at(12, 0, 0))));
+        router.send("app", "before");
+        let deadline = Instant::now() + WAIT_TIMEOUT;
+        while fs::metadata(&before).map_or(0, |metadata| metadata.len()) == 0 {
+            assert!(Instant::now() < deadline, "before was never written out");
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        router.set_clock(at(13, 5, 0));
+        router.send("app", "after");
+        router.stdin.take();
+        assert!(router.child.wait().unwrap().success());
+
+        let decode = |time| {
+            let path = router
+                .log_dir
+                .join(format!("{}.gz", file_name("app", time)));
+            let mut contents = String::new();
+            flate2::read::MultiGzDecoder::new(fs::File::open(path).unwrap())
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents
+        };
+        assert_eq!(decode(at(12, 0, 0)), "before\n");
+        assert_eq!(decode(at(13, 0, 0)), "after\n");
+        assert!(!router.log_dir.join(file_name("app", at(12, 0, 0))).exists());
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+        .args(["--log-dir", std::env::temp_dir().to_str().unwrap()])
+        .args(["--accepted-log-channels", "app"])
+        .args(["--compression", "gzip", "--compression-level", "10"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(78));
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn encrypted_channels_are_written_sealed_and_decrypt_with_their_key() {
+    let key_file = std::env::temp_dir().join(format!("log-revolve-key-{}", std::process::id()));
+    fs::write(&key_file, format!("{}\n", "2a".repeat(32))).unwrap();
+    let mut router = Router::start(
+        "encrypt",
+        at(12, 10, 0),
+        &[
+            "--accepted-log-channels",
+            "app,audit",
+            "--buffering-profiles",
+            "app=latency,audit=latency",
+            "--encrypt-channels",
+            "audit",
+            "--encryption-key-file",
+            key_file.to_str().unwrap(),
+        ],
+    );
+    let log_dir = router.log_dir.clone();
+    let sealed = |time| log_dir.join(format!("{}.enc", file_name("audit", time)));
+    router.send("audit", "granted root to alice");
+    router.send("app", "plain");
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    while fs::metadata(sealed(at(12, 0, 0))).map_or(0, |metadata| metadata.len()) == 0 {
+        assert!(Instant::now() < deadline, "audit was never written out");
+        thread::sleep(Duration::from_millis(20));
+    }
+    router.wait_for(&file_name("app", at(12, 0, 0)), "plain\n");
+
+    router.set_clock(at(13, 5, 0));
+    router.send("audit", "revoked root from alice");
+    router.stdin.take();
+    assert!(router.child.wait().unwrap().success());
+
+    let first = fs::read(sealed(at(12, 0, 0))).unwrap();
+    assert!(!String::from_utf8_lossy(&first).contains("alice"));
+    assert!(!router
+        .log_dir
+        .join(file_name("audit", at(12, 0, 0)))
+        .exists());
+
+    let decrypt = |key_file: &Path| {
+        Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+            .arg("decrypt")
+            .arg("--key-file")
+            .arg(key_file)
+            .arg(sealed(at(12, 0, 0)))
+            .arg(sealed(at(13, 0, 0)))
+            .output()
+            .unwrap()
+    };
+    let output = decrypt(&key_file);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "granted root to alice\nrevoked root from alice\n"
+    );
+
+    fs::write(&key_file, "2b".repeat(32)).unwrap();
+    let output = decrypt(&key_file);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("fails authentication"));
+    let _ = fs::remove_file(&key_file);
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn encrypted_files_carry_on_after_a_restart_and_tell_when_they_were_cut_short() {
+    let key_file =
+        std::env::temp_dir().join(format!("log-revolve-key-resume-{}", std::process::id()));
+    fs::write(&key_file, "2a".repeat(32)).unwrap();
+    let args = [
+        "--accepted-log-channels",
+        "audit",
+        "--encrypt-channels",
+        "audit",
+        "--encryption-key-file",
+        key_file.to_str().unwrap(),
+    ];
+    let mut router = Router::start("encrypt-resume", at(12, 10, 0), &args);
+    router.send("audit", "granted root to alice");
+    let mut router = router.restart(at(12, 20, 0), &args);
+    router.send("audit", "revoked root from alice");
+    router.stdin.take();
+    assert!(router.child.wait().unwrap().success());
+
+    let sealed = router
+        .log_dir
+        .join(format!("{}.enc", file_name("audit", at(12, 0, 0))));
+    let decrypt = || {
+        Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+            .arg("decrypt")
+            .arg("--key-file")
+            .arg(&key_file)
+            .arg(&sealed)
+            .output()
+            .unwrap()
+    };
+    let output = decrypt();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "granted root to alice\nrevoked root from alice\n"
+    );
+
+    // The file's last record, empty: its header, then the tag alone.
+    let file = fs::read(&sealed).unwrap();
+    fs::write(&sealed, &file[..file.len() - 21 - 16]).unwrap();
+    let output = decrypt();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cut short"));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "granted root to alice\nrevoked root from alice\n"
+    );
+    let _ = fs::remove_file(&key_file);
+}
+
+#[test]
+fn daily_files_start_at_midnight_of_their_zone_across_daylight_saving_time() {
+    let berlin = |time: &str| {
+        DateTime::parse_from_rfc3339(time)
+            .unwrap()
+            .with_timezone(&Local)
+    };
+    let mut router = Router::start(
+        "timezone",
+        berlin("2026-03-28T12:00:00+01:00"),
+        &[
+            "--accepted-log-channels",
+            "app,edge",
+            "--buffering-profiles",
+            "app=latency,edge=latency",
+            "--rotation-interval",
+            "1d",
+            "--timezone",
+            "Europe/Berlin",
+            "--channel-timezones",
+            "edge=UTC",
+        ],
+    );
+    router.send("app", "saturday");
+    router.wait_for("app_2026-03-28-00-00-00.log", "saturday\n");
+
+    // Clocks go forward overnight, making Sunday 23 hours long.
+    router.set_clock(berlin("2026-03-29T00:30:00+01:00"));
+    router.send("app", "sunday");
+    router.wait_for("app_2026-03-29-00-00-00.log", "sunday\n");
+    router.set_clock(berlin("2026-03-30T00:30:00+02:00"));
+    router.send("app", "monday");
+    router.send("edge", "still sunday in UTC");
+
+    let files = router.stop();
+    assert_eq!(files["app_2026-03-29-00-00-00.log"], "sunday\n");
+    assert_eq!(files["app_2026-03-30-00-00-00.log"], "monday\n");
+    assert_eq!(
+        files["edge_2026-03-29-00-00-00.log"],
+        "still sunday in UTC\n"
+    );
+}
+#[test]
+fn files_past_max_size_are_cut() {
+    let mut router = Router::start(
+        "max-size",
+        at(12, 10, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--max-file-size",
+            "10B",
+        ],
+    );
+    router.send("app", "first");
+    router.wait_for(&file_name("app", at(12, 0, 0)), "first\n");
+
+    router.set_clock(at(12, 20, 30));
+    router.send("app", "second");
+    let files = router.stop();
+
+    assert_eq!(files[&file_name("app", at(12, 0, 0))], "first\n");
+    assert_eq!(files[&file_name("app", at(12, 20, 30))], "second\n");
+}
+
+#[test]
+fn rotations_are_recorded_with_their_reason_size_and_lines() {
+    let events_file = std::env::temp_dir().join(format!(
+        "log-revolve-rotation-events-{}.jsonl",
+        std::process::id()
+    ));
+    let _ = fs::remove_file(&events_file);
+    let mut router = Router::start(
+        "rotation-events",
+        at(12, 10, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--max-file-size",
+            "10B",
+            "--rotation-events",
+            events_file.to_str().unwrap(),
+        ],
+    );
+    router.send("app", "first");
+    router.wait_for(&file_name("app", at(12, 0, 0)), "first\n");
+    router.set_clock(at(12, 20, 30));
+    router.send("app", "second");
+    router.wait_for(&file_name("app", at(12, 20, 30)), "second\n");
+    router.set_clock(at(13, 5, 0));
+    router.send("app", "third");
+    router.wait_for(&file_name("app", at(13, 0, 0)), "third\n");
+    let events = fs::read_to_string(&events_file).unwrap();
+    fs::remove_file(&events_file).unwrap();
+    let log_dir = router.log_dir.clone();
+    let path = |time| log_dir.join(file_name("app", time));
+    router.stop();
+
+    let events: Vec<&str> = events.lines().collect();
+    assert_eq!(events.len(), 2);
+    let expected = [
+        (at(12, 0, 0), at(12, 20, 30), "size", 6, 1, 630),
+        (at(12, 20, 30), at(13, 0, 0), "time", 7, 1, 2670),
+    ];
+    for (event, (from, to, reason, bytes, lines, open_seconds)) in events.iter().zip(expected) {
+        assert!(event.contains("\"channel\":\"app\""), "{}", event);
+        for field in [
+            format!("\"old_path\":\"{}\"", path(from).display()),
+            format!("\"new_path\":\"{}\"", path(to).display()),
+            format!("\"reason\":\"{}\"", reason),
+            format!("\"bytes\":{},\"lines\":{}", bytes, lines),
+            format!("\"open_seconds\":{}", open_seconds),
+        ] {
+            assert!(event.contains(&field), "{} lacks {}", event, field);
+        }
+    }
+}
+
+#[test]
+fn a_clock_stepped_back_never_reopens_an_earlier_file() {
+    let mut router = Router::start(
+        "stepped-back",
+        at(13, 10, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--max-file-size",
+            "10B",
+        ],
+    );
+    router.send("app", "first");
+    router.wait_for(&file_name("app", at(13, 0, 0)), "first\n");
+
+    // Stepped back an hour, the file is still cut past its size but under
+    // the time of the one before it.
+    router.set_clock(at(12, 10, 0));
+    router.send("app", "second");
+    router.wait_for("app_2024-06-01-13-00-00.001.log", "second\n");
+
+    router.set_clock(at(14, 0, 0));
+    router.send("app", "third");
+    let files = router.stop();
+
+    assert_eq!(files[&file_name("app", at(13, 0, 0))], "first\n");
+    assert_eq!(files[&file_name("app", at(14, 0, 0))], "third\n");
+    assert!(!files.contains_key(&file_name("app", at(12, 0, 0))));
+    assert!(!files.contains_key(&file_name("app", at(12, 10, 0))));
+}
+
+#[test]
+fn sampled_channels_keep_one_line_in_so_many() {
+    let mut router = Router::start(
+        "sample",
+        at(12, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "chatty,app",
+            "--sample-rates",
+            "chatty=1/10",
+        ],
+    );
+    for n in 0..25 {
+        router.send("chatty", &n.to_string());
+        router.send("app", &n.to_string());
+    }
+    let files = router.stop();
+
+    assert_eq!(files[&file_name("chatty", at(12, 0, 0))], "0\n10\n20\n");
+    assert_eq!(files[&file_name("app", at(12, 0, 0))].lines().count(), 25);
+}
+
+#[test]
+fn lines_past_max_line_bytes_are_truncated_split_or_rejected() {
+    for (action, app, inapt) in [
+        ("truncate", "01234567\nnext\n", ""),
+        ("split", "01234567\n89abcdef\nnext\n", ""),
+        ("drop-to-inapt", "next\n", "[oversized:app] 01234567\n"),
+    ]
+    .iter()
+    {
+        let mut router = Router::start(
+            &format!("max-line-{}", action),
+            at(9, 0, 0),
+            &[
+                "--accepted-log-channels",
+                "app",
+                "--max-line-bytes",
+                "8B",
+                "--max-line-action",
+                action,
+            ],
+        );
+        router.send("app", "0123456789abcdef");
+        router.send("app", "next");
+        let files = router.stop();
+
+        assert_eq!(files[&file_name("app", at(9, 0, 0))], *app, "{}", action);
+        assert_eq!(
+            files[&file_name("inapt", at(9, 0, 0))],
+            *inapt,
+            "{}",
+            action
+        );
+    }
+}
+
+#[test]
+fn lines_not_in_utf8_are_replaced_passed_through_or_rejected() {
+    for (handling, app, inapt) in [
+        ("lossy", &b"bad \xef\xbf\xbd byte\nnext\n"[..], &b""[..]),
+        ("passthrough", &b"bad \xff byte\nnext\n"[..], &b""[..]),
+        (
+            "inapt",
+            &b"next\n"[..],
+            &b"[invalid-utf8:app] bad \xef\xbf\xbd byte\n"[..],
+        ),
+    ]
+    .iter()
+    {
+        let mut router = Router::start(
+            &format!("invalid-utf8-{}", handling),
+            at(9, 0, 0),
+            &[
+                "--accepted-log-channels",
+                "app",
+                "--invalid-utf8",
+                handling,
+                "--normalize-line-endings",
+            ],
+        );
+        let mut stdin = router.stdin.take().unwrap();
+        stdin
+            .write_all(b"app\nbad \xff byte\r\napp\nnext\n")
+            .unwrap();
+        drop(stdin);
+        assert!(router.child.wait().unwrap().success());
+        let read =
+            |channel| fs::read(router.log_dir.join(file_name(channel, at(9, 0, 0)))).unwrap();
+
+        assert_eq!(read("app"), *app, "{}", handling);
+        assert_eq!(read("inapt"), *inapt, "{}", handling);
+    }
+}
+
+#[test]
+fn passed_through_lines_keep_the_characters_they_hold() {
+    let mut router = Router::start(
+        "invalid-utf8-private-use",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--invalid-utf8",
+            "passthrough",
+        ],
+    );
+    // U+10FF00 and U+10FFFF, private use characters, valid or not around.
+    let lines = b"app\nvalid \xf4\x8f\xbc\x80\napp\nbad \xff and \xf4\x8f\xbf\xbf\n";
+    let mut stdin = router.stdin.take().unwrap();
+    stdin.write_all(lines).unwrap();
+    drop(stdin);
+    assert!(router.child.wait().unwrap().success());
+
+    assert_eq!(
+        fs::read(router.log_dir.join(file_name("app", at(9, 0, 0)))).unwrap(),
+        b"valid \xf4\x8f\xbc\x80\nbad \xff and \xf4\x8f\xbf\xbf\n"
+    );
+}
+
+#[test]
+fn files_cut_within_one_second_are_numbered() {
+    let mut router = Router::start(
+        "same-second",
+        at(12, 20, 30),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--max-file-size",
+            "10B",
+        ],
+    );
+    for message in ["first", "second", "third"].iter() {
+        router.send("app", message);
+    }
+    let files = router.stop();
+
+    let name = file_name("app", at(12, 20, 30));
+    assert_eq!(files[&file_name("app", at(12, 0, 0))], "first\n");
+    assert_eq!(files[&name], "second\n");
+    assert_eq!(files[&name.replace(".log", ".001.log")], "third\n");
+}
+
+#[test]
+fn channels_can_write_to_directories_of_their_own() {
+    let debug_dir =
+        std::env::temp_dir().join(format!("log-revolve-channel-dirs-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&debug_dir);
+    let channel_dirs = format!("debug={}", debug_dir.join("debug").display());
+    let mut router = Router::start(
+        "channel-dirs",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,debug",
+            "--channel-dirs",
+            &channel_dirs,
+        ],
+    );
+    router.send("app", "kept");
+    router.send("debug", "noisy");
+    let files = router.stop();
+
+    assert_eq!(files[&file_name("app", at(9, 0, 0))], "kept\n");
+    assert!(!files.contains_key(&file_name("debug", at(9, 0, 0))));
+    let debug = fs::read_to_string(
+        debug_dir
+            .join("debug")
+            .join(file_name("debug", at(9, 0, 0))),
+    );
+    assert_eq!(debug.unwrap(), "noisy\n");
+    let _ = fs::remove_dir_all(&debug_dir);
+}
+
+#[test]
+fn a_log_directory_gone_by_rotation_is_created_again() {
+    let mut router = Router::start(
+        "create-dirs",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+        ],
+    );
+    router.send("app", "one");
+    router.wait_for(&file_name("app", at(9, 0, 0)), "one\n");
+    fs::remove_dir_all(&router.log_dir).unwrap();
+
+    router.set_clock(at(10, 0, 0));
+    router.send("app", "two");
+    let files = router.stop();
+
+    assert_eq!(files[&file_name("app", at(10, 0, 0))], "two\n");
+}
+
+#[test]
+fn files_deleted_or_renamed_while_open_are_recreated() {
+    let mut router = Router::start(
+        "recreate-files",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--log-dir-check-interval",
+            "1",
+        ],
+    );
+    let current = file_name("app", at(9, 0, 0));
+    router.send("app", "one");
+    router.wait_for(&current, "one\n");
+
+    fs::remove_file(router.log_dir.join(&current)).unwrap();
+    router.wait_for(&current, "");
+    router.send("app", "two");
+    router.wait_for(&current, "two\n");
+
+    fs::rename(
+        router.log_dir.join(&current),
+        router.log_dir.join("app.old"),
+    )
+    .unwrap();
+    router.wait_for(&current, "");
+    router.send("app", "three");
+    let files = router.stop();
+
+    assert_eq!(files["app.old"], "two\n");
+    assert_eq!(files[&current], "three\n");
+}
+
+#[test]
+fn dropping_queued_lines_is_refused_for_paired_input() {
+    let log_dir =
+        std::env::temp_dir().join(format!("log-revolve-queue-full-{}", std::process::id()));
+    let output = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+        .arg("--log-dir")
+        .arg(&log_dir)
+        .args(["--accepted-log-channels", "app", "--queue-depth", "8"])
+        .args(["--queue-full", "drop-oldest"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--input-format lines"));
+    let _ = std::fs::remove_dir_all(&log_dir);
+}
+
+#[test]
+fn a_missing_log_directory_fails_startup_with_no_create_dirs() {
+    let log_dir = std::env::temp_dir().join(format!(
+        "log-revolve-no-create-dirs-{}/logs",
+        std::process::id()
+    ));
+    let output = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+        .arg("--log-dir")
+        .arg(&log_dir)
+        .args(["--accepted-log-channels", "app", "--no-create-dirs"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--no-create-dirs is set"));
+    assert!(!log_dir.exists());
+}
+
+#[test]
+fn preflight_reports_every_unusable_directory_with_its_channels() {
+    let dir = std::env::temp_dir().join(format!("log-revolve-preflight-{}", std::process::id()));
+    let log_dir = dir.join("logs");
+    let audit_dir = dir.join("audit");
+    let output = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+        .arg("--log-dir")
+        .arg(&log_dir)
+        .args([
+            "--accepted-log-channels",
+            "app,web,audit",
+            "--no-create-dirs",
+        ])
+        .arg("--channel-dirs")
+        .arg(format!("audit={}", audit_dir.display()))
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("preflight found 2 problems"), "{}", stderr);
+    assert!(
+        stderr.contains(&format!(
+            "directory {} (channels app, web)",
+            log_dir.display()
+        )),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(&format!(
+            "directory {} (channels audit)",
+            audit_dir.display()
+        )),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn channel_names_unfit_for_file_names_fail_startup() {
+    let log_dir =
+        std::env::temp_dir().join(format!("log-revolve-channel-names-{}", std::process::id()));
+    let long = "a".repeat(65);
+    for channels in ["app,../etc", "app,web@1", "app,.hidden", long.as_str()].iter() {
+        let output = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+            .arg("--log-dir")
+            .arg(&log_dir)
+            .args(["--accepted-log-channels", channels])
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success(), "{}", channels);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("invalid channel name"), "{}", stderr);
+    }
+    let _ = fs::remove_dir_all(&log_dir);
+}
+
+#[cfg(feature = "split")]
+#[test]
+fn split_files_lines_at_the_time_they_carry() {
+    let dir = std::env::temp_dir().join(format!("log-revolve-split-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("combined.log");
+    fs::write(
+        &input,
+        "app\n\
+         starting up\n\
+         app\n\
+         [2024-06-01 09:10:00] ready\n\
+         db\n\
+         [2024-06-01 09:59:59] connected\n\
+         app\n\
+         [2024-06-01 10:00:01] request\n\
+         app\n\
+         \tat handler\n\
+         db\n\
+         [2024-06-01 12:30:00] slow query\n",
+    )
+    .unwrap();
+    let log_dir = dir.join("logs");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+        .arg("split")
+        .arg("--input")
+        .arg(&input)
+        .arg("--log-dir")
+        .arg(&log_dir)
+        .args(["--accepted-log-channels", "app,db"])
+        .args(["--timestamp-regex", r"^\[([^]]+)\]"])
+        .args(["--timestamp-format", "%Y-%m-%d %H:%M:%S"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let files = files(&log_dir);
+    let _ = fs::remove_dir_all(&dir);
+
+    assert_eq!(
+        files[&file_name("app", at(9, 0, 0))],
+        "starting up\n[2024-06-01 09:10:00] ready\n"
+    );
+    assert_eq!(
+        files[&file_name("app", at(10, 0, 0))],
+        "[2024-06-01 10:00:01] request\n\tat handler\n"
+    );
+    assert_eq!(
+        files[&file_name("db", at(9, 0, 0))],
+        "[2024-06-01 09:59:59] connected\n"
+    );
+    assert_eq!(
+        files[&file_name("db", at(12, 0, 0))],
+        "[2024-06-01 12:30:00] slow query\n"
+    );
+}
+
+#[test]
+fn check_config_validates_without_creating_anything() {
+    let log_dir =
+        std::env::temp_dir().join(format!("log-revolve-check-config-{}", std::process::id()));
+    let check = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+            .arg("--log-dir")
+            .arg(&log_dir)
+            .args(["--accepted-log-channels", "app,web", "--check-config"])
+            .args(args)
+            .stdin(Stdio::piped())
+            .output()
+            .unwrap()
+    };
+
+    let output = check(&["--max-file-size", "10MB"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("app: dir="), "{}", stdout);
+    assert!(stdout.contains("max_file_size=10485760"), "{}", stdout);
+    assert!(stdout.contains("configuration is valid"), "{}", stdout);
+    assert!(!log_dir.exists());
+
+    let output = check(&["--quotas", "app=plenty"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("plenty"), "{}", stderr);
+    assert!(!log_dir.exists());
+}
+
+#[test]
+fn check_config_is_a_subcommand_too() {
+    let output = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+        .arg("check-config")
+        .arg("--log-dir")
+        .arg(std::env::temp_dir())
+        .args(["--accepted-log-channels", "app"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("configuration is valid"), "{}", stdout);
+}
+
+#[test]
+fn compact_merges_the_files_of_a_period_but_the_newest() {
+    let log_dir = std::env::temp_dir().join(format!("log-revolve-compact-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&log_dir);
+    fs::create_dir_all(&log_dir).unwrap();
+    for (time, contents) in [
+        ((10, 0, 0), "ten\n"),
+        ((11, 0, 0), "eleven, cut short"),
+        ((12, 0, 0), "noon\n"),
+    ] {
+        fs::write(
+            log_dir.join(file_name("app", at(time.0, time.1, time.2))),
+            contents,
+        )
+        .unwrap();
+    }
+    fs::write(log_dir.join("app_2024-06-02-00-00-00.log"), "written to\n").unwrap();
+    fs::write(log_dir.join("web_2024-06-01-09-00-00.log"), "alone\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+        .arg("compact")
+        .arg("--log-dir")
+        .arg(&log_dir)
+        .args(["--merge", "1d"])
+        .output()
+        .unwrap();
+    let files = files(&log_dir);
+    let _ = fs::remove_dir_all(&log_dir);
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        files,
+        BTreeMap::from([
+            (
+                String::from("app_2024-06-01-00-00-00.log"),
+                String::from("ten\neleven, cut short\nnoon\n")
+            ),
+            (
+                String::from("app_2024-06-02-00-00-00.log"),
+                String::from("written to\n")
+            ),
+            (
+                String::from("web_2024-06-01-09-00-00.log"),
+                String::from("alone\n")
+            ),
+        ])
+    );
+}
+
+#[test]
+fn cat_and_grep_read_the_files_of_a_time_range() {
+    let log_dir = std::env::temp_dir().join(format!("log-revolve-query-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&log_dir);
+    fs::create_dir_all(&log_dir).unwrap();
+    for (hour, contents) in [
+        (9, "nine\nTimeout at nine\n"),
+        (10, "ten\n"),
+        (11, "eleven\ntimeout at eleven\n"),
+        (12, "noon\n"),
+    ] {
+        fs::write(log_dir.join(file_name("app", at(hour, 0, 0))), contents).unwrap();
+    }
+    fs::write(log_dir.join(file_name("web", at(10, 0, 0))), "timeout\n").unwrap();
+    let query = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+            .args(args)
+            .arg("--log-dir")
+            .arg(&log_dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let range = query(&[
+        "cat",
+        "app",
+        "--from",
+        "2024-06-01 10:30",
+        "--to",
+        "2024-06-01T11:15",
+    ]);
+    let matches = query(&["grep", "-i", "timeout", "app", "--to", "2024-06-01 12:00"]);
+    let _ = fs::remove_dir_all(&log_dir);
+
+    assert_eq!(range, "ten\neleven\ntimeout at eleven\n");
+    assert_eq!(matches, "Timeout at nine\ntimeout at eleven\n");
+}
+
+#[test]
+fn cat_reads_files_laid_out_and_stamped_as_the_router_wrote_them() {
+    let layout = [
+        "--dir-template",
+        "{channel}/{year}/{month}/{day}",
+        "--timezone",
+        "+09:00",
+    ];
+    let mut router = Router::start(
+        "cat-layout",
+        at(9, 0, 0),
+        &[
+            &["--accepted-log-channels", "app"][..],
+            &["--buffering-profiles", "app=latency"][..],
+            &layout[..],
+        ]
+        .concat(),
+    );
+    let tokyo = |time: DateTime<Local>| {
+        time.with_timezone(&chrono::FixedOffset::east_opt(9 * 3600).unwrap())
+    };
+    let nine = tokyo(at(9, 0, 0));
+    router.send("app", "nine");
+    router.wait_for(
+        &format!(
+            "app/{}/{}",
+            nine.format("%Y/%m/%d"),
+            nine.format("app_%Y-%m-%d-%H-%M-%S.log")
+        ),
+        "nine\n",
+    );
+    router.set_clock(at(10, 0, 0));
+    router.send("app", "ten");
+    router.stdin.take();
+    assert!(router.child.wait().unwrap().success());
+
+    let from = tokyo(at(10, 0, 0)).format("%Y-%m-%d %H:%M").to_string();
+    let output = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+        .args(["cat", "app", "--from", &from])
+        .args(layout)
+        .arg("--log-dir")
+        .arg(&router.log_dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "ten\n");
+}
+
+#[test]
+fn the_routers_own_records_go_to_the_internal_channel() {
+    let mut router = Router::start(
+        "internal-log",
+        at(9, 30, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--internal-log",
+        ],
+    );
+    router.send("app", "before");
+    router.wait_for(&file_name("app", at(9, 0, 0)), "before\n");
+    router.set_clock(at(10, 5, 0));
+    router.send("app", "after");
+    let files = router.stop();
+
+    let internal: String = files
+        .iter()
+        .filter(|(name, _)| name.starts_with("_internal_"))
+        .map(|(_, contents)| contents.as_str())
+        .collect();
+    assert!(internal.contains("\"event\":\"started\""), "{}", internal);
+    assert!(
+        internal.contains(&format!("{}\n", file_name("app", at(10, 0, 0)))),
+        "{}",
+        internal
+    );
+    assert!(
+        internal.contains("flushing files before exiting"),
+        "{}",
+        internal
+    );
+    assert_eq!(files[&file_name("app", at(10, 0, 0))], "after\n");
+}
+
+#[test]
+fn a_second_instance_refuses_a_locked_log_directory() {
+    let mut router = Router::start("locked", at(9, 0, 0), &["--accepted-log-channels", "app"]);
+    let second = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+            .arg("--log-dir")
+            .arg(&router.log_dir)
+            .args(["--accepted-log-channels", "app"])
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    let output = second(&[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("locked by another instance"), "{}", stderr);
+    assert!(
+        stderr.contains(&format!("pid {}", router.child.id())),
+        "{}",
+        stderr
+    );
+    assert!(second(&["--allow-shared-dir"]).status.success());
+
+    router.send("app", "started");
+    let files = router.stop();
+
+    assert_eq!(files[&file_name("app", at(9, 0, 0))], "started\n");
+}
+
+#[test]
+fn batched_lines_are_written_out_as_stdin_closes() {
+    let mut router = Router::start("shutdown", at(9, 0, 0), &["--accepted-log-channels", "app"]);
+    for message in ["one", "two", "three"].iter() {
+        router.send("app", message);
+    }
+    let files = router.stop();
+
+    assert_eq!(files[&file_name("app", at(9, 0, 0))], "one\ntwo\nthree\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn batched_lines_are_written_out_on_sigterm() {
+    let mut router = Router::start(
+        "sigterm",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,marker",
+            "--buffering-profiles",
+            "marker=latency",
+        ],
+    );
+    router.send("app", "batched");
+    // Lines are read in order, so once the marker is out the app line is
+    // batched.
+    router.send("marker", "read");
+    router.wait_for(&file_name("marker", at(9, 0, 0)), "read\n");
+    assert_eq!(
+        fs::read_to_string(router.log_dir.join(file_name("app", at(9, 0, 0)))).unwrap(),
+        ""
+    );
+    let files = router.signal("TERM");
+
+    assert_eq!(files[&file_name("app", at(9, 0, 0))], "batched\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn shutdown_marker_ends_every_channel_file() {
+    let mut router = Router::start(
+        "marker",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,web",
+            "--buffering-profiles",
+            "web=latency",
+            "--shutdown-marker",
+            "# stopped",
+        ],
+    );
+    router.send("web", "last");
+    router.wait_for(&file_name("web", at(9, 0, 0)), "last\n");
+    let files = router.signal("INT");
+
+    assert_eq!(files[&file_name("app", at(9, 0, 0))], "# stopped\n");
+    assert_eq!(files[&file_name("web", at(9, 0, 0))], "last\n# stopped\n");
+}
+
+#[test]
+fn batched_lines_are_written_out_past_the_flush_interval() {
+    let mut router = Router::start(
+        "flush-interval",
+        at(9, 0, 0),
+        &["--accepted-log-channels", "app", "--flush-interval", "100"],
+    );
+    router.send("app", "waited");
+    router.wait_for(&file_name("app", at(9, 0, 0)), "waited\n");
+    router.stop();
+}
+
+#[test]
+fn files_are_sharded_into_dated_directories_retention_looks_through() {
+    let args = [
+        "--accepted-log-channels",
+        "app",
+        "--buffering-profiles",
+        "app=latency",
+        "--rotation-interval",
+        "1d",
+        "--dir-template",
+        "{channel}/{year}/{month}/{day}",
+    ];
+    let mut router = Router::start("dir-template", at(23, 59, 0), &args);
+    router.send("app", "first");
+    router.wait_for(
+        &format!("app/2024/06/01/{}", file_name("app", at(0, 0, 0))),
+        "first\n",
+    );
+    let next_day = at(0, 0, 0) + chrono::Duration::days(1);
+    router.set_clock(next_day + chrono::Duration::seconds(30));
+    router.send("app", "second");
+
+    let mut max_files = args.to_vec();
+    max_files.extend(["--max-files", "2"].iter());
+    let router = router.restart(next_day + chrono::Duration::days(1), &max_files);
+    let shards = router.log_dir.join("app/2024/06");
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    while shards.join("01").exists() {
+        assert!(Instant::now() < deadline, "the first day was never pruned");
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    assert_eq!(
+        files(&shards.join("02"))[&file_name("app", next_day)],
+        "second\n"
+    );
+    assert!(shards.join("03").is_dir());
+    router.stop();
+}
+
+#[test]
+fn unknown_channels_go_to_the_inapt_file() {
+    let mut router = Router::start("inapt", at(9, 0, 0), &["--accepted-log-channels", "app"]);
+    router.send("web", "misrouted");
+    let files = router.stop();
+
+    assert_eq!(
+        files[&file_name("inapt", at(9, 0, 0))],
+        "[unknown:web] misrouted\n"
+    );
+}
+
+#[test]
+fn structured_inapt_lines_carry_time_and_reason_and_rotate_by_size() {
+    let mut router = Router::start(
+        "inapt-structured",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--inapt-format",
+            "structured",
+            "--timestamp-format",
+            "%H:%M:%S",
+            "--inapt-max-file-size",
+            "40B",
+        ],
+    );
+    router.send("web", "misrouted");
+    router.send("db", "also misrouted");
+    let files = router.stop();
+
+    let name = file_name("inapt", at(9, 0, 0));
+    assert_eq!(
+        files[&name],
+        "09:00:00 reason=unknown channel=web misrouted\n"
+    );
+    assert_eq!(
+        files[&name.replace(".log", ".001.log")],
+        "09:00:00 reason=unknown channel=db also misrouted\n"
+    );
+}
+
+#[test]
+fn length_prefixed_records_are_written_byte_for_byte() {
+    let mut router = Router::start(
+        "length-prefixed",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--input-format",
+            "length-prefixed",
+        ],
+    );
+    let stdin = router.stdin.as_mut().unwrap();
+    stdin
+        .write_all(&length_prefixed::encode("app", b"two\nlines\n"))
+        .unwrap();
+    stdin
+        .write_all(&length_prefixed::encode("app", b"unended"))
+        .unwrap();
+    stdin
+        .write_all(&length_prefixed::encode("app", b"\xff\xfe"))
+        .unwrap();
+    stdin.write_all(&[0, 0, 0, 2, b'n', b'o']).unwrap();
+    let app = router.log_dir.join(file_name("app", at(9, 0, 0)));
+    let inapt = router.log_dir.join(file_name("inapt", at(9, 0, 0)));
+    router.stdin.take();
+    assert!(router.child.wait().unwrap().success());
+
+    assert_eq!(fs::read(app).unwrap(), b"two\nlines\nunended\xff\xfe");
+    assert_eq!(fs::read_to_string(inapt).unwrap(), "[malformed] no\n");
+}
+
+#[test]
+fn the_exit_status_tells_a_failure_to_start_from_one_while_running() {
+    let missing = std::env::temp_dir().join(format!(
+        "log-revolve-exit-status-{}/logs",
+        std::process::id()
+    ));
+    let status = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+        .arg("--log-dir")
+        .arg(&missing)
+        .args(["--accepted-log-channels", "app", "--no-create-dirs"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(78));
+
+    for policy in ["abort", "continue"] {
+        let mut router = Router::start(
+            &format!("on-error-{}", policy),
+            at(9, 0, 0),
+            &[
+                "--accepted-log-channels",
+                "app",
+                "--input-format",
+                "length-prefixed",
+                "--on-error",
+                policy,
+            ],
+        );
+        let stdin = router.stdin.as_mut().unwrap();
+        stdin
+            .write_all(&length_prefixed::encode("app", b"before\n"))
+            .unwrap();
+        // A length past the limit leaves nothing to resume from.
+        stdin.write_all(&[0xff, 0xff, 0xff, 0xff]).unwrap();
+        router.stdin.take();
+
+        assert_eq!(router.child.wait().unwrap().code(), Some(74), "{}", policy);
+        if policy == "continue" {
+            assert_eq!(
+                files(&router.log_dir)[&file_name("app", at(9, 0, 0))],
+                "before\n"
+            );
+        }
+    }
+}
+
+#[test]
+fn bench_produce_reports_a_target_it_cannot_reach() {
+    let output = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+        .args(["bench-produce", "--to", &format!("tcp://{}", free_addr())])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(78));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with("log-revolve-rs bench-produce failed: "),
+        "{}",
+        stderr
+    );
+}
+
+#[cfg(feature = "routing")]
+#[test]
+fn routed_lines_go_to_the_channel_of_the_first_matching_rule() {
+    let mut router = Router::start(
+        "routed",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "errors,nginx",
+            "--input-format",
+            "routed",
+            "--route",
+            "^\\[nginx\\]=>nginx",
+            "--route",
+            "ERROR=>errors",
+        ],
+    );
+    let stdin = router.stdin.as_mut().unwrap();
+    stdin
+        .write_all(b"[nginx] GET /\nERROR disk full\n[nginx] ERROR 502\nall good\n")
+        .unwrap();
+    let files = router.stop();
+
+    assert_eq!(
+        files[&file_name("nginx", at(9, 0, 0))],
+        "[nginx] GET /\n[nginx] ERROR 502\n"
+    );
+    assert_eq!(
+        files[&file_name("errors", at(9, 0, 0))],
+        "ERROR disk full\n"
+    );
+    assert_eq!(
+        files[&file_name("inapt", at(9, 0, 0))],
+        "[unrouted] all good\n"
+    );
+}
+
+#[test]
+fn indented_lines_continue_the_message_before_them() {
+    let mut router = Router::start(
+        "multiline-indented",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,web",
+            "--multiline",
+            "indented",
+        ],
+    );
+    router.send("app", "panicked at boom\n  at main.rs:1\n\tat lib.rs:2");
+    router.send("web", "ok");
+    router.send("app", "next");
+    let files = router.stop();
+
+    assert_eq!(
+        files[&file_name("app", at(9, 0, 0))],
+        "panicked at boom\n  at main.rs:1\n\tat lib.rs:2\nnext\n"
+    );
+    assert_eq!(files[&file_name("web", at(9, 0, 0))], "ok\n");
+}
+
+#[test]
+fn delimited_messages_run_until_the_delimiter() {
+    let mut router = Router::start(
+        "multiline-delimited",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--input-format",
+            "prefixed",
+            "--multiline",
+            "delimited",
+            "--message-delimiter",
+            "%%",
+        ],
+    );
+    let stdin = router.stdin.as_mut().unwrap();
+    stdin
+        .write_all(b"app|first\nsecond line\n\n%%\napp|other\n%%\napp|unended\n")
+        .unwrap();
+    let files = router.stop();
+
+    assert_eq!(
+        files[&file_name("app", at(9, 0, 0))],
+        "first\nsecond line\n\nother\nunended\n"
+    );
+}
+
+#[test]
+fn prefixed_lines_name_their_own_channel() {
+    for option in ["--input-format", "--protocol"] {
+        let mut router = Router::start(
+            "prefixed",
+            at(9, 0, 0),
+            &[
+                "--accepted-log-channels",
+                "app",
+                option,
+                "prefixed",
+                "--prefix-delimiter",
+                "::",
+            ],
+        );
+        let stdin = router.stdin.as_mut().unwrap();
+        stdin
+            .write_all(b"app::one\nno channel\napp::two::three\n")
+            .unwrap();
+        let files = router.stop();
+
+        assert_eq!(files[&file_name("app", at(9, 0, 0))], "one\ntwo::three\n");
+        assert_eq!(
+            files[&file_name("inapt", at(9, 0, 0))],
+            "[unframed] no channel\n"
+        );
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+        .args(["--log-dir", std::env::temp_dir().to_str().unwrap()])
+        .args(["--accepted-log-channels", "app"])
+        .args(["--protocol", "producers"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("expected `prefixed` or `paired`"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn channel_names_are_normalized_before_they_are_looked_up() {
+    let mut router = Router::start(
+        "normalize-channels",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "errors",
+            "--input-format",
+            "prefixed",
+            "--normalize-channels",
+            "trim,fold-case",
+            "--normalize-channels",
+            "prefixed=strip-prefix:channel=",
+            "--normalize-channels",
+            "json=strip-prefix:ch=",
+        ],
+    );
+    let stdin = router.stdin.as_mut().unwrap();
+    stdin
+        .write_all(b"ERRORS|one\n Errors |two\nChannel=errors|three\nch=errors|four\n")
+        .unwrap();
+    let files = router.stop();
+
+    assert_eq!(
+        files[&file_name("errors", at(9, 0, 0))],
+        "one\ntwo\nthree\n"
+    );
+    assert_eq!(
+        files[&file_name("inapt", at(9, 0, 0))],
+        "[unknown:ch=errors] four\n"
+    );
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_records_are_routed_by_their_channel_field() {
+    let mut router = Router::start(
+        "json",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "api",
+            "--input-format",
+            "json",
+            "--channel-field",
+            "labels.service",
+            "--json-whole-records",
+        ],
+    );
+    let stdin = router.stdin.as_mut().unwrap();
+    stdin
+        .write_all(b"{\"labels\":{\"service\":\"api\"},\"status\":200}\n{\"labels\":\n")
+        .unwrap();
+    let files = router.stop();
+
+    assert_eq!(
+        files[&file_name("api", at(9, 0, 0))],
+        "{\"labels\":{\"service\":\"api\"},\"status\":200}\n"
+    );
+    assert_eq!(
+        files[&file_name("inapt", at(9, 0, 0))],
+        "[malformed] {\"labels\":\n"
+    );
+}
+
+#[cfg(feature = "journald")]
+#[test]
+fn journal_entries_are_routed_by_identifier_or_unit() {
+    for option in ["--input-format", "--input"] {
+        let mut router = Router::start(
+            "journald",
+            at(9, 0, 0),
+            &["--accepted-log-channels", "app,web", option, "journald"],
+        );
+        let mut export = Vec::new();
+        export.extend_from_slice(b"__CURSOR=s=1\nSYSLOG_IDENTIFIER=app\nMESSAGE=started\n\n");
+        export.extend_from_slice(b"_SYSTEMD_UNIT=web.service\nMESSAGE\n");
+        export.extend_from_slice(&17u64.to_le_bytes());
+        export.extend_from_slice(b"line one\nline two\n\n");
+        export.extend_from_slice(b"MESSAGE=orphan\n\n");
+        export.extend_from_slice(b"SYSLOG_IDENTIFIER=app\nMESSAGE=stopped\n");
+        let stdin = router.stdin.as_mut().unwrap();
+        stdin.write_all(&export).unwrap();
+        let files = router.stop();
+
+        assert_eq!(files[&file_name("app", at(9, 0, 0))], "started\nstopped\n");
+        assert_eq!(
+            files[&file_name("web", at(9, 0, 0))],
+            "line one\nline two\n"
+        );
+        assert_eq!(
+            files[&file_name("inapt", at(9, 0, 0))],
+            "[unrouted] orphan\n"
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn connections_opening_with_a_handshake_send_one_channel() {
+    use std::os::unix::net::UnixStream;
+
+    let socket_dir = std::env::temp_dir().join(format!("log-revolve-sock-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&socket_dir);
+    fs::create_dir_all(&socket_dir).unwrap();
+    let socket = socket_dir.join("in.sock");
+    let listen = format!("unix:{}", socket.display());
+    let router = Router::start(
+        "listen",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--listen",
+            &listen,
+        ],
+    );
+
+    let mut connection = UnixStream::connect(&socket).unwrap();
+    connection.write_all(b"@channel app\none\ntwo\n").unwrap();
+    drop(connection);
+    router.wait_for(&file_name("app", at(9, 0, 0)), "one\ntwo\n");
+    router.stop();
+
+    let _ = fs::remove_dir_all(socket_dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn listen_unix_takes_a_bare_socket_path() {
+    use std::os::unix::net::UnixStream;
+
+    let socket_dir =
+        std::env::temp_dir().join(format!("log-revolve-listen-unix-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&socket_dir);
+    fs::create_dir_all(&socket_dir).unwrap();
+    let socket = socket_dir.join("in.sock");
+    let router = Router::start(
+        "listen-unix",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--listen-unix",
+            socket.to_str().unwrap(),
+        ],
+    );
+
+    let mut connection = UnixStream::connect(&socket).unwrap();
+    connection.write_all(b"app\none\n").unwrap();
+    drop(connection);
+    router.wait_for(&file_name("app", at(9, 0, 0)), "one\n");
+    router.stop();
+
+    let _ = fs::remove_dir_all(socket_dir);
+}
+
+#[test]
+fn concurrent_tcp_producers_share_the_channel_files() {
+    use std::net::{TcpListener, TcpStream};
+
+    let port = || {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    };
+    let (port, tcp_port) = (port(), port());
+    let listen = format!("tcp://127.0.0.1:{}", port);
+    let listen_tcp = format!("127.0.0.1:{}", tcp_port);
+    let router = Router::start(
+        "tcp",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,web",
+            "--buffering-profiles",
+            "app=latency,web=latency",
+            "--listen",
+            &listen,
+            "--listen-tcp",
+            &listen_tcp,
+        ],
+    );
+
+    let mut app = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let mut web = TcpStream::connect(("127.0.0.1", tcp_port)).unwrap();
+    app.write_all(b"@channel app\nfrom app\n").unwrap();
+    web.write_all(b"web\nfrom web\n").unwrap();
+    // A producer hanging up mid-frame leaves the others be.
+    web.write_all(b"web\n").unwrap();
+    drop(web);
+    app.write_all(b"still app\n").unwrap();
+    drop(app);
+
+    router.wait_for(&file_name("app", at(9, 0, 0)), "from app\nstill app\n");
+    router.wait_for(&file_name("web", at(9, 0, 0)), "from web\n");
+    router.stop();
+}
+
+#[test]
+fn syslog_datagrams_go_to_the_channel_of_their_app_name() {
+    use std::net::UdpSocket;
+
+    let port = UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let addr = format!("127.0.0.1:{}", port);
+    let router = Router::start(
+        "syslog",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "api,nginx",
+            "--buffering-profiles",
+            "api=latency,nginx=latency",
+            "--listen-syslog-udp",
+            &addr,
+        ],
+    );
+
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for datagram in [
+        "<134>1 2024-06-01T09:00:00Z host api 42 - - started",
+        "<165>Jun  1 09:00:00 host nginx[12]: GET /",
+    ]
+    .iter()
+    {
+        sender.send_to(datagram.as_bytes(), &addr).unwrap();
+    }
+
+    router.wait_for(&file_name("api", at(9, 0, 0)), "started\n");
+    router.wait_for(&file_name("nginx", at(9, 0, 0)), "GET /\n");
+    router.stop();
+}
+
+#[test]
+fn unknown_channels_are_created_up_to_the_limit() {
+    for create in [
+        &["--unknown-channels", "create"][..],
+        &["--auto-create-channels"],
+    ] {
+        let mut args = vec![
+            "--accepted-log-channels",
+            "app",
+            "--max-dynamic-channels",
+            "1",
+        ];
+        args.extend_from_slice(create);
+        let mut router = Router::start("dynamic", at(9, 0, 0), &args);
+        router.send("../etc", "escaped");
+        router.send("billing", "created");
+        router.send("search", "one too many");
+        let files = router.stop();
+
+        assert_eq!(files[&file_name("billing", at(9, 0, 0))], "created\n");
+        assert_eq!(
+            files[&file_name("inapt", at(9, 0, 0))],
+            "[unknown:../etc] escaped\n[unknown:search] one too many\n"
+        );
+    }
+}
+
+#[cfg(all(unix, feature = "config"))]
+#[test]
+fn channels_follow_the_config_file_on_sighup() {
+    let config = std::env::temp_dir().join(format!(
+        "log-revolve-router-reload-{}.toml",
+        std::process::id()
+    ));
+    fs::write(
+        &config,
+        "[channels.app]\nbuffering_profile = \"latency\"\n\n\
+         [channels.audit]\nbuffering_profile = \"latency\"\n",
+    )
+    .unwrap();
+    let mut router = Router::start(
+        "reload",
+        at(9, 0, 0),
+        &["--config", config.to_str().unwrap()],
+    );
+    router.send("audit", "before");
+    router.wait_for(&file_name("audit", at(9, 0, 0)), "before\n");
+
+    fs::write(
+        &config,
+        "[channels.app]\nbuffering_profile = \"latency\"\n\n\
+         [channels.billing]\nbuffering_profile = \"latency\"\n",
+    )
+    .unwrap();
+    router.kill("HUP");
+    router.wait_for(&file_name("billing", at(9, 0, 0)), "");
+    router.send("billing", "added");
+    router.send("audit", "removed");
+    router.send("app", "kept");
+    let files = router.stop();
+    let _ = fs::remove_file(&config);
+
+    assert_eq!(files[&file_name("billing", at(9, 0, 0))], "added\n");
+    assert_eq!(files[&file_name("audit", at(9, 0, 0))], "before\n");
+    assert_eq!(files[&file_name("app", at(9, 0, 0))], "kept\n");
+    assert_eq!(
+        files[&file_name("inapt", at(9, 0, 0))],
+        "[unknown:audit] removed\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn files_renamed_away_are_reopened_on_the_reopen_signal() {
+    let mut router = Router::start(
+        "reopen-signal",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--reopen-on-signal",
+            "SIGUSR1",
+        ],
+    );
+    let name = file_name("app", at(9, 0, 0));
+    router.send("app", "before");
+    router.wait_for(&name, "before\n");
+
+    let renamed = format!("{}.1", name);
+    fs::rename(router.log_dir.join(&name), router.log_dir.join(&renamed)).unwrap();
+    router.kill("USR1");
+    router.wait_for(&name, "");
+    router.send("app", "after");
+    let files = router.stop();
+
+    assert_eq!(files[&renamed], "before\n");
+    assert_eq!(files[&name], "after\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn every_channel_starts_a_fresh_file_on_the_rotate_signal() {
+    let mut router = Router::start(
+        "rotate-signal",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,db",
+            "--buffering-profiles",
+            "app=latency,db=latency",
+            "--rotate-on-signal",
+            "SIGUSR2",
+        ],
+    );
+    router.send("app", "before");
+    router.send("db", "before");
+    router.wait_for(&file_name("app", at(9, 0, 0)), "before\n");
+    router.wait_for(&file_name("db", at(9, 0, 0)), "before\n");
+
+    router.set_clock(at(9, 20, 0));
+    router.kill("USR2");
+    router.wait_for(&file_name("db", at(9, 20, 0)), "");
+    router.send("app", "after");
+    let files = router.stop();
+
+    assert_eq!(files[&file_name("app", at(9, 0, 0))], "before\n");
+    assert_eq!(files[&file_name("app", at(9, 20, 0))], "after\n");
+    assert_eq!(files[&file_name("db", at(9, 0, 0))], "before\n");
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn channels_synced_every_line_write_each_line_out_at_once() {
+    let config = std::env::temp_dir().join(format!(
+        "log-revolve-router-sync-policy-{}.toml",
+        std::process::id()
+    ));
+    fs::write(
+        &config,
+        "[channels.audit]\nsync_policy = \"every-line\"\n\n[channels.bulk]\n",
+    )
+    .unwrap();
+    let mut router = Router::start(
+        "sync-policy",
+        at(9, 0, 0),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "--sync-policy",
+            "none",
+        ],
+    );
+    router.send("bulk", "batched");
+    router.send("audit", "synced");
+    router.wait_for(&file_name("audit", at(9, 0, 0)), "synced\n");
+    let bulk = fs::read_to_string(router.log_dir.join(file_name("bulk", at(9, 0, 0)))).unwrap();
+    let files = router.stop();
+    let _ = fs::remove_file(&config);
+
+    assert_eq!(bulk, "");
+    assert_eq!(files[&file_name("bulk", at(9, 0, 0))], "batched\n");
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn lines_sent_to_an_alias_go_to_its_channel() {
+    let config = std::env::temp_dir().join(format!(
+        "log-revolve-router-aliases-{}.toml",
+        std::process::id()
+    ));
+    fs::write(
+        &config,
+        "[channels.web]
+aliases = [\"web-1\", \"web-2\"]
+",
+    )
+    .unwrap();
+    let mut router = Router::start(
+        "aliases",
+        at(9, 0, 0),
+        &["--config", config.to_str().unwrap()],
+    );
+    router.send("web-1", "from the first");
+    router.send("web", "from the channel itself");
+    router.send("web-2", "from the second");
+    router.send("web-3", "from an unknown");
+    let files = router.stop();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+        .arg("--log-dir")
+        .arg(std::env::temp_dir())
+        .args(["--config", config.to_str().unwrap()])
+        .args(["--accepted-log-channels", "web-2", "--check-config"])
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(&config);
+
+    assert_eq!(
+        files[&file_name("web", at(9, 0, 0))],
+        "from the first\nfrom the channel itself\nfrom the second\n"
+    );
+    assert_eq!(
+        files[&file_name("inapt", at(9, 0, 0))],
+        "[unknown:web-3] from an unknown\n"
+    );
+    assert!(!files.keys().any(|name| name.starts_with("web-")));
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("alias `web-2` is also a channel"),
+        "{}",
+        stderr
+    );
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn files_start_with_the_channel_header_and_end_with_its_footer() {
+    let config = std::env::temp_dir().join(format!(
+        "log-revolve-router-boundaries-{}.toml",
+        std::process::id()
+    ));
+    fs::write(
+        &config,
+        "[channels.metrics]
+header = \"time,value # {channel}\"
+footer = \"# closed {ts}\\n\"
+buffering_profile = \"latency\"
+",
+    )
+    .unwrap();
+    let mut router = Router::start(
+        "boundaries",
+        at(12, 0, 0),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "--accepted-log-channels",
+            "app",
+            "--timestamp-format",
+            "%H:%M",
+        ],
+    );
+    router.send("metrics", "12:00,1");
+    router.send("app", "untouched");
+    router.wait_for(
+        &file_name("metrics", at(12, 0, 0)),
+        "time,value # metrics\n12:00,1\n",
+    );
+
+    router.set_clock(at(13, 5, 0));
+    router.send("metrics", "13:05,2");
+    router.wait_for(
+        &file_name("metrics", at(13, 0, 0)),
+        "time,value # metrics\n13:05,2\n",
+    );
+    router.set_clock(at(13, 30, 0));
+    let files = router.stop();
+    let _ = fs::remove_file(&config);
+
+    assert_eq!(
+        files[&file_name("metrics", at(12, 0, 0))],
+        "time,value # metrics\n12:00,1\n# closed 13:05\n"
+    );
+    assert_eq!(
+        files[&file_name("metrics", at(13, 0, 0))],
+        "time,value # metrics\n13:05,2\n# closed 13:30\n"
+    );
+    assert_eq!(files[&file_name("app", at(12, 0, 0))], "untouched\n");
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn high_priority_channels_of_the_config_file_are_written_through() {
+    let config = std::env::temp_dir().join(format!(
+        "log-revolve-router-priority-{}.toml",
+        std::process::id()
+    ));
+    fs::write(
+        &config,
+        "[channels.audit]
+priority = \"high\"
+",
+    )
+    .unwrap();
+    let mut router = Router::start(
+        "priority",
+        at(12, 0, 0),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "--accepted-log-channels",
+            "app",
+            "--writer-threads",
+            "1",
+        ],
+    );
+    for n in 0..100 {
+        router.send("app", &format!("debug {}", n));
+    }
+    router.send("audit", "login");
+    router.wait_for(&file_name("audit", at(12, 0, 0)), "login\n");
+    // The other channel's lines are still batched.
+    assert_eq!(
+        fs::read_to_string(router.log_dir.join(file_name("app", at(12, 0, 0)))).unwrap(),
+        ""
+    );
+    let files = router.stop();
+    let _ = fs::remove_file(&config);
+
+    assert_eq!(files[&file_name("app", at(12, 0, 0))].lines().count(), 100);
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn footers_sum_up_the_lines_bytes_and_times_of_their_file() {
+    let config = std::env::temp_dir().join(format!(
+        "log-revolve-router-footer-stats-{}.toml",
+        std::process::id()
+    ));
+    fs::write(
+        &config,
+        "[channels.app]
+footer = \"# {lines} lines, {bytes} bytes, {first_ts} to {last_ts}\"
+buffering_profile = \"latency\"
+",
+    )
+    .unwrap();
+    let mut router = Router::start(
+        "footer-stats",
+        at(12, 0, 0),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "--timestamp-format",
+            "%H:%M",
+        ],
+    );
+    router.set_clock(at(12, 10, 0));
+    router.send("app", "one");
+    router.wait_for(&file_name("app", at(12, 0, 0)), "one\n");
+    router.set_clock(at(12, 20, 0));
+    router.send("app", "three");
+    router.wait_for(&file_name("app", at(12, 0, 0)), "one\nthree\n");
+
+    router.set_clock(at(13, 5, 0));
+    router.send("app", "four");
+    router.wait_for(&file_name("app", at(13, 0, 0)), "four\n");
+    let files = router.stop();
+    let _ = fs::remove_file(&config);
+
+    assert_eq!(
+        files[&file_name("app", at(12, 0, 0))],
+        "one\nthree\n# 2 lines, 10 bytes, 12:10 to 12:20\n"
+    );
+    assert_eq!(
+        files[&file_name("app", at(13, 0, 0))],
+        "four\n# 1 lines, 5 bytes, 13:05 to 13:05\n"
+    );
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn channels_over_their_disk_quota_lose_old_files_or_new_lines() {
+    let config = std::env::temp_dir().join(format!(
+        "log-revolve-router-disk-quota-{}.toml",
+        std::process::id()
+    ));
+    fs::write(
+        &config,
+        "[channels.app]\nmax_file_size = \"10B\"\ndisk_quota = \"20B\"\n\n\
+         [channels.audit]\ndisk_quota = \"20B\"\ndisk_quota_action = \"stop\"\n",
+    )
+    .unwrap();
+    let mut router = Router::start(
+        "disk-quota",
+        at(9, 0, 0),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "--buffering-profiles",
+            "app=latency,audit=latency",
+        ],
+    );
+    for message in ["first---", "second--", "third---", "fourth--"].iter() {
+        router.send("app", message);
+        router.send("audit", message);
+    }
+    let files = router.stop();
+    let _ = fs::remove_file(&config);
+
+    let name = file_name("app", at(9, 0, 0));
+    assert!(!files.contains_key(&name));
+    assert_eq!(files[&name.replace(".log", ".001.log")], "second--\n");
+    assert_eq!(files[&name.replace(".log", ".002.log")], "third---\n");
+    assert_eq!(files[&name.replace(".log", ".003.log")], "fourth--\n");
+    assert_eq!(
+        files[&file_name("audit", at(9, 0, 0))],
+        "first---\nsecond--\n\
+         # over the disk quota of 20 bytes, lines are dropped until files are removed\n"
+    );
+}
+
+#[test]
+fn lines_over_a_rate_limit_go_to_the_overflow_file() {
+    let mut router = Router::start(
+        "rate-limit",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,web",
+            "--rate-limit",
+            "app=1/min",
+            "--rate-limit-action",
+            "overflow",
+        ],
+    );
+    router.send("app", "one");
+    router.send("app", "two");
+    router.send("web", "unlimited");
+    router.send("app", "three");
+    let files = router.stop();
+
+    assert_eq!(files[&file_name("app", at(9, 0, 0))], "one\n");
+    assert_eq!(
+        files[&file_name("app.overflow", at(9, 0, 0))],
+        "two\nthree\n"
+    );
+    assert_eq!(files[&file_name("web", at(9, 0, 0))], "unlimited\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn a_blocked_rate_limit_holds_back_only_its_own_input() {
+    use std::net::TcpStream;
+
+    let listen = free_addr();
+    let mut router = Router::start(
+        "rate-limit-block",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,web",
+            "--buffering-profiles",
+            "app=latency,web=latency",
+            "--rate-limit",
+            "app=1/min",
+            "--rate-limit-action",
+            "block",
+            "--listen-tcp",
+            &listen,
+        ],
+    );
+    // Both are written, and then stdin isn't read for a minute.
+    router.send("app", "one");
+    router.send("app", "two");
+    router.wait_for(&file_name("app", at(9, 0, 0)), "one\ntwo\n");
+    router.send("app", "three");
+
+    let mut web = TcpStream::connect(&listen).unwrap();
+    web.write_all(b"web\nunblocked\n").unwrap();
+    drop(web);
+    router.wait_for(&file_name("web", at(9, 0, 0)), "unblocked\n");
+
+    router.kill("TERM");
+    assert!(router.child.wait().unwrap().success());
+    assert_eq!(
+        fs::read_to_string(router.log_dir.join(file_name("app", at(9, 0, 0)))).unwrap(),
+        "one\ntwo\n"
+    );
+}
+
+#[test]
+fn repeated_lines_are_collapsed_into_one() {
+    let mut router = Router::start(
+        "dedup",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,web",
+            "--dedup-window",
+            "app=1m",
+        ],
+    );
+    router.send("app", "retrying");
+    router.send("app", "retrying");
+    router.send("app", "retrying");
+    router.send("app", "connected");
+    router.send("web", "GET /");
+    router.send("web", "GET /");
+    router.send("app", "connected");
+    let files = router.stop();
+
+    assert_eq!(
+        files[&file_name("app", at(9, 0, 0))],
+        "retrying ... repeated 3 times\nconnected ... repeated 2 times\n"
+    );
+    assert_eq!(files[&file_name("web", at(9, 0, 0))], "GET /\nGET /\n");
+}
+
+#[test]
+fn teed_channels_are_copied_to_stdout() {
+    let mut router = Router::start(
+        "tee",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,errors",
+            "--tee-channel",
+            "errors=stdout",
+        ],
+    );
+    let mut stdout = router.child.stdout.take().unwrap();
+    router.send("app", "started");
+    router.send("errors", "disk full");
+    let files = router.stop();
+
+    let mut output = String::new();
+    stdout.read_to_string(&mut output).unwrap();
+    assert_eq!(output, "disk full\nlog-revolve-rs finished\n");
+    assert_eq!(files[&file_name("errors", at(9, 0, 0))], "disk full\n");
+    assert_eq!(files[&file_name("app", at(9, 0, 0))], "started\n");
+}
+
+#[test]
+fn queued_lines_reach_their_files_in_order() {
+    let mut router = Router::start(
+        "queue",
+        at(9, 0, 0),
+        &["--accepted-log-channels", "app", "--queue-depth", "2"],
+    );
+    for number in 0..100 {
+        router.send("app", &number.to_string());
+    }
+    let files = router.stop();
+
+    let expected: String = (0..100).map(|number| format!("{}\n", number)).collect();
+    assert_eq!(files[&file_name("app", at(9, 0, 0))], expected);
+}
+
+#[test]
+fn lines_are_stamped_with_their_receipt_time() {
+    let mut router = Router::start(
+        "stamp",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--prepend-timestamp",
+            "--timestamp-format",
+            "epoch-millis",
+            "--buffering-profiles",
+            "app=latency",
+        ],
+    );
+    router.send("app", "first");
+    let first = format!("{} first\n", at(9, 0, 0).timestamp_millis());
+    router.wait_for(&file_name("app", at(9, 0, 0)), &first);
+    router.set_clock(at(9, 0, 5));
+    router.send("app", "second");
+    let files = router.stop();
+
+    assert_eq!(
+        files[&file_name("app", at(9, 0, 0))],
+        format!("{}{} second\n", first, at(9, 0, 5).timestamp_millis())
+    );
+}
+
+#[test]
+fn lines_are_wrapped_in_json_records_as_jsonl() {
+    let mut router = Router::start(
+        "jsonl",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--output-format",
+            "jsonl",
+            "--timestamp-format",
+            "epoch-millis",
+        ],
+    );
+    router.send("app", "say \"hi\"\tand \\ leave");
+    let files = router.stop();
+
+    assert_eq!(
+        files[&file_name("app", at(9, 0, 0))],
+        format!(
+            "{{\"ts\":{},\"channel\":\"app\",\"msg\":\"say \\\"hi\\\"\\tand \\\\ leave\"}}\n",
+            at(9, 0, 0).timestamp_millis()
+        )
+    );
+}
+
+#[test]
+fn file_names_follow_the_template() {
+    let mut router = Router::start(
+        "template",
+        at(12, 10, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--max-file-size",
+            "10B",
+            "--file-name-template",
+            "{channel}.{year}{month}{day}-{hour}.{seq}.{ext}",
+        ],
+    );
+    router.send("app", "first");
+    router.wait_for("app.20240601-12.0.log", "first\n");
+    router.send("app", "second");
+    router.wait_for("app.20240601-12.1.log", "second\n");
+
+    router.set_clock(at(13, 0, 0));
+    router.send("app", "third");
+    let files = router.stop();
+
+    assert_eq!(files["app.20240601-13.0.log"], "third\n");
+}
+
+#[test]
+fn files_named_by_the_template_are_pruned() {
+    let args = [
+        "--accepted-log-channels",
+        "app",
+        "--buffering-profiles",
+        "app=latency",
+        "--max-file-size",
+        "10B",
+        "--file-name-template",
+        "{channel}.{year}{month}{day}-{hour}.{seq}.{ext}",
+    ];
+    let mut router = Router::start("template-pruned", at(12, 10, 0), &args);
+    router.send("app", "first");
+    router.wait_for("app.20240601-12.0.log", "first\n");
+    router.send("app", "second");
+    router.wait_for("app.20240601-12.1.log", "second\n");
+    router.send("app", "third");
+    router.wait_for("app.20240601-12.2.log", "third\n");
+
+    let mut max_files = args.to_vec();
+    max_files.extend(["--max-files", "2"].iter());
+    let mut router = router.restart(at(13, 0, 0), &max_files);
+    let older = router.log_dir.join("app.20240601-12.1.log");
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    while older.exists() {
+        assert!(
+            Instant::now() < deadline,
+            "the older files were never pruned"
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+    router.send("app", "fourth");
+    let files = router.stop();
+
+    assert!(!files.contains_key("app.20240601-12.0.log"));
+    assert_eq!(files["app.20240601-12.2.log"], "third\n");
+    assert_eq!(files["app.20240601-13.0.log"], "fourth\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn the_current_symlink_follows_rotation() {
+    let mut router = Router::start(
+        "symlink",
+        at(12, 10, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--current-symlink",
+        ],
+    );
+    let link = router.log_dir.join("app.log");
+    router.send("app", "before");
+    router.wait_for(&file_name("app", at(12, 0, 0)), "before\n");
+    assert_eq!(
+        fs::read_link(&link).unwrap(),
+        Path::new(&file_name("app", at(12, 0, 0)))
+    );
+
+    router.set_clock(at(13, 0, 0));
+    router.send("app", "after");
+    router.wait_for(&file_name("app", at(13, 0, 0)), "after\n");
+    assert_eq!(fs::read_to_string(&link).unwrap(), "after\n");
+    router.stop();
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn tenants_of_the_config_file_keep_to_their_own_channels() {
+    use std::net::{TcpListener, TcpStream};
+
+    let config = std::env::temp_dir().join(format!(
+        "log-revolve-router-tenants-{}.toml",
+        std::process::id()
+    ));
+    fs::write(
+        &config,
+        "[tenants.acme]\nchannels = [\"app\"]\nquota = \"1MB/day\"\n\n[tenants.globex]\n",
+    )
+    .unwrap();
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let listen = format!("tcp://127.0.0.1:{}", port);
+    let mut router = Router::start(
+        "tenant-config",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,web",
+            "--buffering-profiles",
+            "app=latency,web=latency",
+            "--config",
+            config.to_str().unwrap(),
+            "--listen",
+            &listen,
+        ],
+    );
+
+    let mut acme = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    acme.write_all(b"@tenant acme\nglobex/app\nsneaky\napp\nown\n")
+        .unwrap();
+    drop(acme);
+    router.wait_for(&file_name("acme/app", at(9, 0, 0)), "own\n");
+    router.send("acme/web", "not acme's");
+    router.send("globex/web", "globex's");
+    router.wait_for(&file_name("globex/web", at(9, 0, 0)), "globex's\n");
+    router.wait_for(&file_name("globex/app", at(9, 0, 0)), "");
+    let files = router.stop();
+    let _ = fs::remove_file(&config);
+
+    assert_eq!(
+        files[&file_name("inapt", at(9, 0, 0))],
+        "[cross-tenant:acme/globex/app] sneaky\n[unknown:acme/web] not acme's\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn tenants_keep_current_symlinks_in_their_directories() {
+    let mut router = Router::start(
+        "tenant-symlink",
+        at(12, 10, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--tenants",
+            "acme",
+            "--buffering-profiles",
+            "app=latency",
+            "--current-symlink",
+        ],
+    );
+    router.send("acme/app", "started");
+    router.wait_for(&file_name("acme/app", at(12, 0, 0)), "started\n");
+    assert_eq!(
+        fs::read_link(router.log_dir.join("acme/app.log")).unwrap(),
+        Path::new(&file_name("app", at(12, 0, 0)))
+    );
+    router.stop();
+}
+
+/// The response to a GET of `path`, head included.
+#[cfg(feature = "metrics")]
+fn http_get(addr: &str, path: &str) -> String {
+    use std::io::Read;
+    use std::net::TcpStream;
+
+    let mut response = String::new();
+    let mut connection = TcpStream::connect(addr).unwrap();
+    write!(
+        connection,
+        "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        path
+    )
+    .unwrap();
+    connection.read_to_string(&mut response).unwrap();
+
+    response
+}
+
+/// An address of the loopback interface nothing listens on.
+fn free_addr() -> String {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    format!("127.0.0.1:{}", port)
+}
+
+#[cfg(feature = "grpc")]
+#[test]
+fn lines_streamed_over_grpc_are_written_and_acknowledged() {
+    use async_std::net::TcpStream;
+    use async_std::task;
+    use bytes::Bytes;
+    use log_revolve_rs::grpc::{self, Ack, Line, Messages};
+    use prost::Message;
+    use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+    let addr = free_addr();
+    let router = Router::start(
+        "grpc",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,web",
+            "--buffering-profiles",
+            "app=latency,web=latency",
+            "--listen-grpc",
+            &addr,
+        ],
+    );
+
+    let line = |channel: &str, payload: &str| {
+        let line = Line {
+            channel: channel.to_string(),
+            payload: payload.as_bytes().to_vec(),
+        };
+        grpc::message(&line.encode_to_vec())
+    };
+    let (acks, trailers) = task::block_on(async {
+        let stream = TcpStream::connect(&addr).await.unwrap();
+        let (client, connection) = h2::client::handshake(stream.compat()).await.unwrap();
+        task::spawn(connection);
+
+        let request = http::Request::post(format!("http://{}{}", addr, grpc::INGEST_PATH))
+            .header("content-type", "application/grpc")
+            .body(())
+            .unwrap();
+        let (response, mut call) = client
+            .ready()
+            .await
+            .unwrap()
+            .send_request(request, false)
+            .unwrap();
+        let mut batch = line("app", "one");
+        batch.extend(line("web", "two\n"));
+        call.send_data(Bytes::from(batch), false).unwrap();
+        call.send_data(Bytes::from(line("app", "three")), true)
+            .unwrap();
+
+        // Acks and trailers, until the call ends.
+        let mut body = response.await.unwrap().into_body();
+        let mut messages = Messages::default();
+        let mut acks = Vec::new();
+        while let Some(data) = body.data().await {
+            let data = data.unwrap();
+            body.flow_control().release_capacity(data.len()).unwrap();
+            messages.push(&data);
+            while let Some(message) = messages.pop().unwrap() {
+                acks.push(Ack::decode(message.as_slice()).unwrap().written);
+            }
+        }
+        (acks, body.trailers().await.unwrap().unwrap())
+    });
+
+    assert_eq!(acks, vec![2, 3]);
+    assert_eq!(trailers.len(), 1);
+    assert_eq!(trailers["grpc-status"], "0");
+    router.wait_for(&file_name("app", at(9, 0, 0)), "one\nthree\n");
+    router.wait_for(&file_name("web", at(9, 0, 0)), "two\n");
+    router.stop();
+}
+
+/// Frames written and read by hand, for what an HTTP/2 client library won't
+/// send: more calls than allowed, or a header block without end.
+#[cfg(feature = "grpc")]
+mod h2_frames {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+    pub const HEADERS: u8 = 0x1;
+    pub const RST_STREAM: u8 = 0x3;
+    pub const SETTINGS: u8 = 0x4;
+    pub const GOAWAY: u8 = 0x7;
+    pub const CONTINUATION: u8 = 0x9;
+    pub const ACK: u8 = 0x1;
+    pub const END_HEADERS: u8 = 0x4;
+
+    pub fn write(connection: &mut TcpStream, kind: u8, flags: u8, stream: u32, payload: &[u8]) {
+        let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        frame.extend_from_slice(&[kind, flags]);
+        frame.extend_from_slice(&stream.to_be_bytes());
+        frame.extend_from_slice(payload);
+        // The router may have hung up already.
+        let _ = connection.write_all(&frame);
+    }
+
+    /// The kind, stream and payload of the next frame.
+    pub fn read(connection: &mut TcpStream) -> (u8, u32, Vec<u8>) {
+        let mut head = [0; 9];
+        connection.read_exact(&mut head).unwrap();
+        let length = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+        let stream = u32::from_be_bytes([head[5], head[6], head[7], head[8]]);
+        let mut payload = vec![0; length];
+        connection.read_exact(&mut payload).unwrap();
+        (head[3], stream, payload)
+    }
+
+    /// The header block of an `Ingest` call: `:method POST` and `:scheme
+    /// http` from the static table, the path and content type literal.
+    pub fn ingest_headers() -> Vec<u8> {
+        let path = log_revolve_rs::grpc::INGEST_PATH;
+        let mut block = vec![0x83, 0x86, 0x04, path.len() as u8];
+        block.extend_from_slice(path.as_bytes());
+        block.extend_from_slice(&[0x0f, 0x10, 16]);
+        block.extend_from_slice(b"application/grpc");
+        block
+    }
+
+    /// A connection past its preface, its settings acknowledged both ways.
+    /// Asserts the router's settings include `setting`.
+    pub fn connect(addr: &str, setting: [u8; 6]) -> TcpStream {
+        let mut connection = TcpStream::connect(addr).unwrap();
+        connection.write_all(PREFACE).unwrap();
+        write(&mut connection, SETTINGS, 0, 0, &[]);
+        let (kind, _, settings) = read(&mut connection);
+        assert_eq!(kind, SETTINGS);
+        assert!(settings.chunks(6).any(|chunk| chunk == setting));
+        write(&mut connection, SETTINGS, ACK, 0, &[]);
+        connection
+    }
+}
+
+#[cfg(feature = "grpc")]
+#[test]
+fn grpc_connections_are_held_to_their_limits() {
+    use h2_frames::*;
+
+    let addr = free_addr();
+    let router = Router::start(
+        "grpc-limits",
+        at(9, 0, 0),
+        &["--accepted-log-channels", "app", "--listen-grpc", &addr],
+    );
+    let headers = ingest_headers();
+
+    // The 17th call going at once is refused.
+    let mut connection = connect(&addr, [0, 3, 0, 0, 0, 16]);
+    for stream in (1..=33).step_by(2) {
+        write(&mut connection, HEADERS, END_HEADERS, stream, &headers);
+    }
+    let reset = loop {
+        let (kind, stream, payload) = read(&mut connection);
+        if kind == RST_STREAM {
+            break (stream, payload);
+        }
+    };
+    assert_eq!(reset, (33, vec![0, 0, 0, 0x7]));
+    drop(connection);
+
+    // Header blocks strung out over frames without end are cut off.
+    let mut connection = connect(&addr, [0, 6, 0, 0, 0x40, 0]);
+    write(&mut connection, HEADERS, 0, 1, &headers);
+    for _ in 0..8 {
+        write(&mut connection, CONTINUATION, 0, 1, &[]);
+    }
+    let go_away = loop {
+        let (kind, _, payload) = read(&mut connection);
+        if kind == GOAWAY {
+            break payload;
+        }
+    };
+    assert_eq!(go_away[4..8], [0, 0, 0, 0xb]);
+    router.stop();
+}
+
+/// The response to a POST of `body` to `path`, head included.
+#[cfg(feature = "http-input")]
+fn http_post(addr: &str, path: &str, headers: &str, body: &[u8]) -> String {
+    use std::io::Read;
+    use std::net::TcpStream;
+
+    let mut response = String::new();
+    let mut connection = TcpStream::connect(addr).unwrap();
+    write!(
+        connection,
+        "POST {} HTTP/1.1\r\nHost: localhost\r\n{}Content-Length: {}\r\n\r\n",
+        path,
+        headers,
+        body.len()
+    )
+    .unwrap();
+    connection.write_all(body).unwrap();
+    connection.read_to_string(&mut response).unwrap();
+
+    response
+}
+
+#[cfg(feature = "http-input")]
+#[test]
+fn lines_posted_over_http_go_to_the_channel_of_the_path() {
+    let addr = free_addr();
+    let router = Router::start(
+        "ingest-http",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--listen-http",
+            &addr,
+        ],
+    );
+
+    let response = http_post(&addr, "/ingest/app", "", b"one\n{\"two\":2}\nthree");
+    assert!(response.starts_with("HTTP/1.1 202 Accepted\r\n"));
+    assert!(response.ends_with("{\"bytes\":20,\"channel\":\"app\",\"lines\":3,\"ok\":true}\n"));
+    router.wait_for(&file_name("app", at(9, 0, 0)), "one\n{\"two\":2}\nthree\n");
+
+    #[cfg(feature = "gzip")]
+    {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"four\n").unwrap();
+        let gzipped = encoder.finish().unwrap();
+        let response = http_post(&addr, "/ingest/app", "Content-Encoding: gzip\r\n", &gzipped);
+        assert!(response.starts_with("HTTP/1.1 202 Accepted\r\n"));
+        router.wait_for(
+            &file_name("app", at(9, 0, 0)),
+            "one\n{\"two\":2}\nthree\nfour\n",
+        );
+    }
+
+    let response = http_post(&addr, "/ingest/web", "", b"unknown\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    let response = http_post(&addr, "/ingest/app", "Content-Encoding: br\r\n", b"x\n");
+    assert!(response.starts_with("HTTP/1.1 415 Unsupported Media Type\r\n"));
+    router.stop();
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn metrics_count_the_lines_of_each_channel() {
+    let addr = free_addr();
+    let mut router = Router::start(
+        "metrics",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--metrics-addr",
+            &addr,
+        ],
+    );
+    router.send("app", "one");
+    router.send("app", "two");
+    router.send("web", "unknown");
+    router.wait_for(&file_name("app", at(9, 0, 0)), "one\ntwo\n");
+
+    let metrics = http_get(&addr, "/metrics");
+    router.stop();
+
+    assert!(metrics.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(metrics.contains("\nlog_revolve_lines_written_total{channel=\"app\"} 2\n"));
+    assert!(metrics.contains("\nlog_revolve_bytes_written_total{channel=\"app\"} 8\n"));
+}
+
+#[cfg(all(feature = "filter", feature = "metrics"))]
+#[test]
+fn filtered_lines_are_dropped_and_counted() {
+    let addr = free_addr();
+    let mut router = Router::start(
+        "filter",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,web",
+            "--buffering-profiles",
+            "app=latency,web=latency",
+            "--drop-pattern",
+            "heartbeat",
+            "--channel-keep-pattern",
+            "web=^GET",
+            "--channel-drop-pattern",
+            "web=/healthz$",
+            "--metrics-addr",
+            &addr,
+        ],
+    );
+    router.send("app", "heartbeat");
+    router.send("app", "started");
+    router.send("web", "GET /healthz");
+    router.send("web", "POST /login");
+    router.send("web", "GET /");
+    router.wait_for(&file_name("web", at(9, 0, 0)), "GET /\n");
+
+    let metrics = http_get(&addr, "/metrics");
+    let files = router.stop();
+
+    assert_eq!(files[&file_name("app", at(9, 0, 0))], "started\n");
+    assert!(metrics.contains("\nlog_revolve_filtered_lines_total{channel=\"app\"} 1\n"));
+    assert!(metrics.contains("\nlog_revolve_filtered_lines_total{channel=\"web\"} 2\n"));
+}
+
+#[cfg(feature = "redact")]
+#[test]
+fn redacted_lines_are_written_masked() {
+    let mut router = Router::start(
+        "redact",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,auth",
+            "--redact",
+            r"s/\d{16}/****/",
+            "--channel-redact",
+            r"auth=s|token=\w+|token=<hidden>|",
+        ],
+    );
+    router.send("app", "paid with 4111111111111111");
+    router.send("auth", "card 4111111111111111 token=abc123");
+    router.send("billing", "refund to 4111111111111111");
+    let files = router.stop();
+
+    assert_eq!(files[&file_name("app", at(9, 0, 0))], "paid with ****\n");
+    assert_eq!(
+        files[&file_name("auth", at(9, 0, 0))],
+        "card **** token=<hidden>\n"
+    );
+    assert_eq!(
+        files[&file_name("inapt", at(9, 0, 0))],
+        "[unknown:billing] refund to ****\n"
+    );
+}
+
+#[cfg(all(feature = "config", feature = "metrics"))]
+#[test]
+fn null_sink_channels_are_counted_but_never_written() {
+    let addr = free_addr();
+    let config = std::env::temp_dir().join(format!(
+        "log-revolve-router-null-sink-{}.toml",
+        std::process::id()
+    ));
+    fs::write(
+        &config,
+        "[channels.app]\nbuffering_profile = \"latency\"\n\n\
+         [channels.debug]\nsink = \"null\"\n",
+    )
+    .unwrap();
+    let mut router = Router::start(
+        "null-sink",
+        at(9, 0, 0),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "--metrics-addr",
+            &addr,
+        ],
+    );
+    router.send("debug", "noise");
+    router.send("debug", "more noise");
+    router.send("app", "kept");
+    router.wait_for(&file_name("app", at(9, 0, 0)), "kept\n");
+
+    let metrics = http_get(&addr, "/metrics");
+    let files = router.stop();
+    let _ = fs::remove_file(&config);
+
+    assert!(!files.keys().any(|name| name.starts_with("debug")));
+    assert_eq!(files[&file_name("inapt", at(9, 0, 0))], "");
+    assert!(metrics.contains("\nlog_revolve_discarded_lines_total{channel=\"debug\"} 2\n"));
+    assert!(metrics.contains("\nlog_revolve_discarded_bytes_total{channel=\"debug\"} 17\n"));
+}
+
+/// A host taking a single connection, handing over all it was sent.
+#[cfg(feature = "forward")]
+fn fake_collector() -> (String, thread::JoinHandle<String>) {
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let received = thread::spawn(move || {
+        let (mut connection, _) = listener.accept().unwrap();
+        let mut received = String::new();
+        connection.read_to_string(&mut received).unwrap();
+        received
+    });
+
+    (addr, received)
+}
+
+#[cfg(feature = "forward")]
+#[test]
+fn forwarded_channels_are_relayed_to_their_destination() {
+    let (lines_addr, lines) = fake_collector();
+    let (syslog_addr, syslog) = fake_collector();
+    let mut router = Router::start(
+        "forward",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,audit",
+            "--forward",
+            &format!("app=tcp://{}", lines_addr),
+            "--forward",
+            &format!("audit=syslog+tcp://{}", syslog_addr),
+            "--hostname",
+            "web-1",
+        ],
+    );
+    let pid = router.child.id();
+    router.send("app", "started");
+    router.send("audit", "ERROR access denied");
+    let files = router.stop();
+
+    assert_eq!(lines.join().unwrap(), "started\n");
+    assert_eq!(files[&file_name("app", at(9, 0, 0))], "started\n");
+
+    let syslog = syslog.join().unwrap();
+    let (length, message) = syslog.split_once(' ').unwrap();
+    assert_eq!(length.parse::<usize>().unwrap(), message.len());
+    assert!(message.starts_with("<11>1 "));
+    assert!(message.ends_with(&format!(" web-1 audit {} - - ERROR access denied", pid)));
+}
+
+/// Server side of TLS as `localhost`, its certificate issued by a CA of its
+/// own, and the PEM file of that CA to trust.
+#[cfg(any(feature = "forward", feature = "s3"))]
+fn tls_server(name: &str) -> (std::sync::Arc<rustls::ServerConfig>, PathBuf) {
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use rustls::crypto::ring;
+    use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+    use rustls::ServerConfig;
+    use std::sync::Arc;
+
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+    let key = KeyPair::generate().unwrap();
+    let certificate = CertificateParams::new(vec!["localhost".to_string()])
+        .unwrap()
+        .signed_by(&key, &ca, &ca_key)
+        .unwrap();
+
+    let ca_file = std::env::temp_dir().join(format!(
+        "log-revolve-router-{}-{}.pem",
+        name,
+        std::process::id()
+    ));
+    fs::write(&ca_file, ca.pem()).unwrap();
+    let key = PrivatePkcs8KeyDer::from(key.serialize_der());
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![certificate.der().clone()], PrivateKeyDer::Pkcs8(key))
+        .unwrap();
+    // Tickets the router never reads would have its close reset the
+    // connection under what it sent.
+    config.send_tls13_tickets = 0;
+
+    (Arc::new(config), ca_file)
+}
+
+/// A host taking a single TLS connection as `localhost`, with the PEM file
+/// of the CA to trust, handing over all it was sent.
+#[cfg(feature = "forward")]
+fn fake_tls_collector(name: &str) -> (String, PathBuf, thread::JoinHandle<String>) {
+    use rustls::{ServerConnection, StreamOwned};
+    use std::net::TcpListener;
+
+    let (config, ca_file) = tls_server(name);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = format!("localhost:{}", listener.local_addr().unwrap().port());
+    let received = thread::spawn(move || {
+        let (connection, _) = listener.accept().unwrap();
+        let session = ServerConnection::new(config).unwrap();
+        let mut received = String::new();
+        StreamOwned::new(session, connection)
+            .read_to_string(&mut received)
+            .unwrap();
+        received
+    });
+
+    (addr, ca_file, received)
+}
+
+#[cfg(feature = "forward")]
+#[test]
+fn forwarded_channels_are_relayed_over_tls() {
+    let (addr, ca_file, received) = fake_tls_collector("forward-tls");
+    let mut router = Router::start(
+        "forward-tls",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--forward",
+            &format!("app=tls://{}", addr),
+            "--tls-ca-file",
+            ca_file.to_str().unwrap(),
+        ],
+    );
+    router.send("app", "started");
+    router.send("app", "stopping");
+    router.stop();
+    let _ = fs::remove_file(&ca_file);
+
+    assert_eq!(received.join().unwrap(), "started\nstopping\n");
+}
+
+#[cfg(feature = "kafka")]
+#[test]
+fn lines_the_brokers_refuse_fall_back_to_a_file() {
+    let mut router = Router::start(
+        "kafka",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,audit",
+            "--kafka-brokers",
+            &free_addr(),
+            "--kafka-channels",
+            "app",
+            "--kafka-linger",
+            "10",
+        ],
+    );
+    router.send("app", "started");
+    router.send("audit", "access denied");
+    let files = router.stop();
+
+    assert_eq!(files[&file_name("app", at(9, 0, 0))], "started\n");
+    assert_eq!(files["kafka.fallback"], "app\nstarted\n");
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn status_reports_the_current_file_of_each_channel() {
+    let addr = free_addr();
+    let mut router = Router::start(
+        "status",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--metrics-addr",
+            &addr,
+        ],
+    );
+    router.send("app", "one");
+    router.wait_for(&file_name("app", at(9, 0, 0)), "one\n");
+
+    let status = http_get(&addr, "/status");
+    let path = router.log_dir.join(file_name("app", at(9, 0, 0)));
+    router.stop();
+
+    assert!(status.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(status.contains(&format!("\"path\":{:?}", path.to_str().unwrap())));
+    assert!(status.contains("\"size_bytes\":4"));
+    assert!(status.contains(&format!("\"last_write\":\"{}\"", at(9, 0, 0).to_rfc3339())));
+}
+
+#[cfg(all(unix, feature = "metrics"))]
+#[test]
+fn lines_a_pipe_out_falls_behind_on_are_counted() {
+    let addr = free_addr();
+    // Descriptor 3 is the router's stdout, which nothing reads past the
+    // banner and stalls once the pipe is full.
+    let mut command = Command::new("sh");
+    command
+        .args(["-c", "exec \"$0\" \"$@\" 3>&1"])
+        .arg(env!("CARGO_BIN_EXE_log-revolve-rs"))
+        .args([
+            "--accepted-log-channels",
+            "app",
+            "--pipe-out",
+            "app=fd:3",
+            "--metrics-addr",
+            &addr,
+        ]);
+    let mut router = Router::start_command("pipe-out-dropped", at(9, 0, 0), command);
+    let line = "x".repeat(100);
+    for _ in 0..8000 {
+        router.send("app", &line);
+    }
+
+    let counter = "\nlog_revolve_pipe_out_dropped_lines_total{channel=\"app\"} ";
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    loop {
+        let metrics = http_get(&addr, "/metrics");
+        let dropped = metrics
+            .split_once(counter)
+            .and_then(|(_, rest)| rest.lines().next())
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(0);
+        if dropped > 0 {
+            break;
+        }
+        assert!(Instant::now() < deadline, "no line was ever dropped");
+        thread::sleep(Duration::from_millis(20));
+    }
+    let status = http_get(&addr, "/status");
+    router.stop();
+
+    assert!(status.contains("\"pipe_outs\":{\"app\":{\"dropped_lines\":"));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn files_of_idle_channels_are_closed_until_their_next_line() {
+    let mut router = Router::start(
+        "idle-close",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,web",
+            "--buffering-profiles",
+            "app=latency,web=latency",
+            "--idle-close-after",
+            "4s",
+        ],
+    );
+    let app = router.log_dir.join(file_name("app", at(9, 0, 0)));
+    let web = router.log_dir.join(file_name("web", at(9, 0, 0)));
+    let fds = format!("/proc/{}/fd", router.child.id());
+    let is_open = |path: &PathBuf| {
+        fs::read_dir(&fds)
+            .unwrap()
+            .filter_map(|entry| fs::read_link(entry.ok()?.path()).ok())
+            .any(|target| &target == path)
+    };
+    router.send("app", "first");
+    router.wait_for(&file_name("app", at(9, 0, 0)), "first\n");
+    assert!(is_open(&app));
+
+    router.set_clock(at(9, 0, 10));
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    while is_open(&app) || is_open(&web) {
+        assert!(Instant::now() < deadline, "idle files were never closed");
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    router.send("app", "second");
+    router.wait_for(&file_name("app", at(9, 0, 0)), "first\nsecond\n");
+    assert!(is_open(&app));
+    assert!(!is_open(&web));
+    let files = router.stop();
+
+    assert_eq!(files[&file_name("web", at(9, 0, 0))], "");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn lines_of_a_failing_channel_are_held_until_it_recovers() {
+    let mut router = Router::start(
+        "degraded",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,web",
+            "--buffering-profiles",
+            "app=latency,web=latency",
+        ],
+    );
+    // Every write to the file the next hour opens fails.
+    let failing = router.log_dir.join(file_name("app", at(10, 0, 0)));
+    std::os::unix::fs::symlink("/dev/full", &failing).unwrap();
+
+    router.set_clock(at(10, 0, 0));
+    router.send("app", "held");
+    router.send("web", "unaffected");
+    router.wait_for(&file_name("web", at(10, 0, 0)), "unaffected\n");
+
+    fs::remove_file(&failing).unwrap();
+    router.send("app", "also held");
+    router.wait_for(&file_name("app", at(10, 0, 0)), "held\nalso held\n");
+    router.stop();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn lines_of_failing_rollup_and_inapt_files_are_held_until_they_recover() {
+    let mut router = Router::start(
+        "degraded-rollup",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "web,cdn",
+            "--flush-bytes",
+            "1",
+            "--log-dir-check-interval",
+            "0",
+            "--channel-groups",
+            "frontend=web+cdn",
+            "--rollup-groups",
+            "frontend",
+        ],
+    );
+    let rollup = router.log_dir.join(file_name("frontend", at(10, 0, 0)));
+    std::os::unix::fs::symlink("/dev/full", &rollup).unwrap();
+    let inapt = router.log_dir.join(file_name("inapt", at(10, 0, 0)));
+    std::os::unix::fs::symlink("/dev/full", &inapt).unwrap();
+
+    router.set_clock(at(10, 0, 0));
+    router.send("web", "one");
+    router.send("nope", "unknown");
+    router.send("cdn", "two");
+    router.wait_for(&file_name("web", at(10, 0, 0)), "one\n");
+    router.wait_for(&file_name("cdn", at(10, 0, 0)), "two\n");
+
+    fs::remove_file(&rollup).unwrap();
+    fs::remove_file(&inapt).unwrap();
+    router.send("web", "three");
+    router.wait_for(
+        &file_name("frontend", at(10, 0, 0)),
+        "[web] one\n[cdn] two\n[web] three\n",
+    );
+    router.wait_for(
+        &file_name("inapt", at(10, 0, 0)),
+        "[unknown:nope] unknown\n",
+    );
+    router.stop();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn batches_a_writer_thread_fails_to_write_are_held_until_it_recovers() {
+    let mut router = Router::start(
+        "degraded-pool",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,web",
+            "--buffering-profiles",
+            "app=latency,web=latency",
+            "--writer-threads",
+            "2",
+        ],
+    );
+    let failing = router.log_dir.join(file_name("app", at(10, 0, 0)));
+    std::os::unix::fs::symlink("/dev/full", &failing).unwrap();
+
+    router.set_clock(at(10, 0, 0));
+    // Writes fail on a writer thread, and are only reported by later ones.
+    router.send("app", "failed");
+    router.send("app", "reported");
+    router.send("app", "held");
+    router.send("web", "unaffected");
+    router.wait_for(&file_name("web", at(10, 0, 0)), "unaffected\n");
+
+    fs::remove_file(&failing).unwrap();
+    router.send("app", "recovered");
+    router.wait_for(
+        &file_name("app", at(10, 0, 0)),
+        "failed\nreported\nheld\nrecovered\n",
+    );
+    router.stop();
+}
+
+#[cfg(all(target_os = "linux", feature = "report"))]
+#[test]
+fn the_shutdown_report_is_written_when_the_writer_is_stuck() {
+    let report = std::env::temp_dir().join(format!(
+        "log-revolve-router-stuck-report-{}.json",
+        std::process::id()
+    ));
+    let mut router = Router::start(
+        "stuck",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--shutdown-timeout",
+            "1",
+            "--shutdown-report",
+            report.to_str().unwrap(),
+        ],
+    );
+    // Opening the file the next hour starts blocks until the FIFO is read,
+    // holding the writer.
+    let fifo = router.log_dir.join(file_name("app", at(10, 0, 0)));
+    let status = Command::new("mkfifo").arg(&fifo).status().unwrap();
+    assert!(status.success());
+    router.set_clock(at(10, 0, 0));
+    router.send("app", "stuck");
+    thread::sleep(Duration::from_millis(200));
+
+    router.kill("TERM");
+    assert!(!router.child.wait().unwrap().success());
+
+    let contents = fs::read_to_string(&report).unwrap();
+    let _ = fs::remove_file(&report);
+    assert!(contents.contains("\"outcome\":\"shutdown took longer than 1s\""));
+    assert!(contents.contains("writer is busy, unflushed lines can't be counted"));
+    assert!(!contents.contains("\"channels\""));
+}
+
+#[cfg(all(target_os = "linux", feature = "metrics"))]
+#[test]
+fn lines_spilled_past_the_buffer_size_are_dropped_and_counted() {
+    let addr = free_addr();
+    let mut router = Router::start(
+        "spill",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app,web",
+            "--buffering-profiles",
+            "app=latency,web=latency",
+            "--spill-buffer-size",
+            "8",
+            "--metrics-addr",
+            &addr,
+        ],
+    );
+    let failing = router.log_dir.join(file_name("app", at(10, 0, 0)));
+    std::os::unix::fs::symlink("/dev/full", &failing).unwrap();
+
+    router.set_clock(at(10, 0, 0));
+    router.send("app", "held");
+    router.send("app", "too much");
+    router.send("web", "unaffected");
+    router.wait_for(&file_name("web", at(10, 0, 0)), "unaffected\n");
+
+    let metrics = http_get(&addr, "/metrics");
+    assert!(metrics.contains("\nlog_revolve_degraded_channels 1\n"));
+    assert!(metrics.contains("\nlog_revolve_dropped_lines_total{channel=\"app\"} 1\n"));
+
+    fs::remove_file(&failing).unwrap();
+    router.wait_for(&file_name("app", at(10, 0, 0)), "held\n");
+    router.stop();
+}
+
+#[cfg(unix)]
+#[test]
+fn the_rotate_hook_runs_for_every_finished_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("log-revolve-hook-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let ran = dir.join("ran");
+    let script = dir.join("hook.sh");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\necho \"$2 $LOG_REVOLVE_CHANNEL $(cat \"$1\")\" >> {}\n",
+            ran.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let mut router = Router::start(
+        "hook",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--on-rotate-cmd",
+            script.to_str().unwrap(),
+        ],
+    );
+    router.send("app", "one");
+    router.wait_for(&file_name("app", at(9, 0, 0)), "one\n");
+    router.set_clock(at(10, 0, 0));
+    router.send("app", "two");
+
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    while fs::read_to_string(&ran).ok().as_deref() != Some("app app one\n") {
+        assert!(Instant::now() < deadline, "the hook never ran");
+        thread::sleep(Duration::from_millis(20));
+    }
+    router.stop();
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[cfg(feature = "manifest")]
+#[test]
+fn finished_files_are_recorded_in_the_manifest_with_their_checksum() {
+    use chrono::SecondsFormat;
+
+    let mut router = Router::start(
+        "manifest",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--manifest",
+            "--checksum-sidecars",
+        ],
+    );
+    router.send("app", "one");
+    router.wait_for(&file_name("app", at(9, 0, 0)), "one\n");
+    router.set_clock(at(10, 0, 0));
+    router.send("app", "two");
+    router.wait_for(&file_name("app", at(10, 0, 0)), "two\n");
+    let files = router.stop();
+
+    let finished = file_name("app", at(9, 0, 0));
+    // What `printf 'one\n' | sha256sum` prints.
+    let digest = "2c8b08da5ce60398e1f19af0e5dccc744df274b826abe585eaba68c525434806";
+    assert_eq!(
+        files["manifest.csv"],
+        format!(
+            "filename,size,sha256,closed_at\n{},4,{},{}\n",
+            finished,
+            digest,
+            at(10, 0, 0).to_rfc3339_opts(SecondsFormat::Millis, false)
+        )
+    );
+    assert_eq!(
+        files[&format!("{}.sha256", finished)],
+        format!("{}  {}\n", digest, finished)
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn rotate_hooks_still_running_are_waited_for_before_exiting() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("log-revolve-drain-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let ran = dir.join("ran");
+    let script = dir.join("hook.sh");
+    fs::write(
+        &script,
+        format!("#!/bin/sh\nsleep 1\necho \"$2\" >> {}\n", ran.display()),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let mut router = Router::start(
+        "drain",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--on-rotate-cmd",
+            script.to_str().unwrap(),
+        ],
+    );
+    router.send("app", "one");
+    router.wait_for(&file_name("app", at(9, 0, 0)), "one\n");
+    router.set_clock(at(10, 0, 0));
+    router.send("app", "two");
+    router.wait_for(&file_name("app", at(10, 0, 0)), "two\n");
+    router.stop();
+
+    assert_eq!(fs::read_to_string(&ran).unwrap(), "app\n");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn stdin_is_read_past_its_end_with_stay_alive() {
+    let mut router = Router::start(
+        "stay-alive",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--stay-alive",
+        ],
+    );
+    router.send("app", "before the producer left");
+    router.wait_for(&file_name("app", at(9, 0, 0)), "before the producer left\n");
+    router.stdin.take();
+    thread::sleep(Duration::from_millis(500));
+    assert!(router.child.try_wait().unwrap().is_none());
+
+    let files = router.signal("TERM");
+    assert_eq!(
+        files[&file_name("app", at(9, 0, 0))],
+        "before the producer left\n"
+    );
+}
+
+#[cfg(all(unix, feature = "control-socket"))]
+#[test]
+fn the_control_socket_rotates_and_flushes_on_request() {
+    use std::os::unix::net::UnixStream;
+
+    let socket =
+        std::env::temp_dir().join(format!("log-revolve-control-{}.sock", std::process::id()));
+    let _ = fs::remove_file(&socket);
+    let mut router = Router::start(
+        "control-socket",
+        at(9, 10, 0),
+        &[
+            "--accepted-log-channels",
+            "app,web",
+            "--control-socket",
+            socket.to_str().unwrap(),
+        ],
+    );
+    router.send("app", "before");
+    router.send("web", "kept");
+
+    // Words rather than JSON, as typed into `nc -U`, until the line was
+    // read and flushed.
+    let stream = UnixStream::connect(&socket).unwrap();
+    let mut responses = BufReader::new(&stream);
+    let app = router.log_dir.join(file_name("app", at(9, 0, 0)));
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    while fs::read_to_string(&app).unwrap_or_default() != "before\n" {
+        assert!(Instant::now() < deadline, "app was never flushed");
+        (&stream).write_all(b"flush app\n").unwrap();
+        let mut response = String::new();
+        responses.read_line(&mut response).unwrap();
+        assert_eq!(response, "{\"flushed\":[\"app\"],\"ok\":true}\n");
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    router.set_clock(at(9, 20, 0));
+    let output = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+        .arg("rotate")
+        .arg("--control-socket")
+        .arg(&socket)
+        .arg("app")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    router.send("app", "after");
+
+    let files = router.stop();
+    let _ = fs::remove_file(&socket);
+    assert_eq!(files[&file_name("app", at(9, 0, 0))], "before\n");
+    assert_eq!(files[&file_name("app", at(9, 20, 0))], "after\n");
+    assert_eq!(files[&file_name("web", at(9, 0, 0))], "kept\n");
+}
+
+#[cfg(all(unix, feature = "control-socket"))]
+#[test]
+fn priority_channels_are_written_through_while_others_are_paused() {
+    use std::os::unix::net::UnixStream;
+
+    let socket =
+        std::env::temp_dir().join(format!("log-revolve-pause-{}.sock", std::process::id()));
+    let _ = fs::remove_file(&socket);
+    let mut router = Router::start(
+        "pause",
+        at(9, 10, 0),
+        &[
+            "--accepted-log-channels",
+            "app,audit",
+            "--priority-channels",
+            "audit",
+            "--buffering-profiles",
+            "app=latency",
+            "--control-socket",
+            socket.to_str().unwrap(),
+        ],
+    );
+    let stream = UnixStream::connect(&socket).unwrap();
+    let mut responses = BufReader::new(&stream);
+    let mut request = |line: &str| {
+        (&stream)
+            .write_all(format!("{}\n", line).as_bytes())
+            .unwrap();
+        let mut response = String::new();
+        responses.read_line(&mut response).unwrap();
+        response
+    };
+    assert_eq!(
+        request("pause audit"),
+        "{\"error\":\"`audit` is a priority channel\",\"ok\":false}\n"
+    );
+    assert_eq!(
+        request("pause app"),
+        "{\"channel\":\"app\",\"ok\":true,\"paused\":true}\n"
+    );
+
+    router.send("app", "held");
+    router.send("audit", "through");
+    router.wait_for(&file_name("audit", at(9, 0, 0)), "through\n");
+    let app = router.log_dir.join(file_name("app", at(9, 0, 0)));
+    assert_eq!(fs::read_to_string(app).unwrap(), "");
+
+    assert!(request("resume app").contains("\"ok\":true"));
+    router.wait_for(&file_name("app", at(9, 0, 0)), "held\n");
+    router.stop();
+    let _ = fs::remove_file(&socket);
+}
+
+#[cfg(all(unix, feature = "control-socket"))]
+#[test]
+fn the_stats_subcommand_counts_lines_from_before_a_restart() {
+    let socket =
+        std::env::temp_dir().join(format!("log-revolve-stats-{}.sock", std::process::id()));
+    let _ = fs::remove_file(&socket);
+    let stats_file =
+        std::env::temp_dir().join(format!("log-revolve-stats-{}.txt", std::process::id()));
+    let _ = fs::remove_file(&stats_file);
+    let args = [
+        "--accepted-log-channels",
+        "app,web",
+        "--control-socket",
+        socket.to_str().unwrap(),
+        "--stats-file",
+        stats_file.to_str().unwrap(),
+    ];
+    let mut router = Router::start("stats", at(9, 10, 0), &args);
+    router.send("app", "one");
+    router.send("app", "two");
+
+    let mut router = router.restart(at(9, 20, 0), &args);
+    router.send("app", "three");
+    router.send("web", "four");
+
+    let stats = |channel: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_log-revolve-rs"))
+            .args(["stats", "--control-socket", socket.to_str().unwrap()])
+            .args(["--hours", "1", channel])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+    };
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    while stats("app")["channels"]["app"][0]["lines"] != 3 {
+        assert!(Instant::now() < deadline, "{}", stats("app"));
+        thread::sleep(Duration::from_millis(20));
+    }
+    let app = stats("app");
+    assert_eq!(app["channels"]["app"][0]["bytes"], 14);
+    assert!(app["channels"].get("web").is_none());
+
+    router.stop();
+    let _ = fs::remove_file(&socket);
+    let _ = fs::remove_file(&stats_file);
+}
+
+#[cfg(all(unix, feature = "state"))]
+#[test]
+fn work_left_by_a_crash_is_resumed_from_the_state_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("log-revolve-state-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let ran = dir.join("ran");
+    let script = dir.join("hook.sh");
+    fs::write(
+        &script,
+        format!("#!/bin/sh\necho \"$(cat \"$1\")\" >> {}\n", ran.display()),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+    // The last run was writing one file when it died, and had yet to
+    // compress one it rotated away from.
+    let unrotated = dir.join(file_name("app", at(8, 0, 0)));
+    fs::write(&unrotated, "unrotated\n").unwrap();
+    let unfinished = dir.join(file_name("app", at(7, 0, 0)));
+    fs::write(&unfinished, "unfinished\n").unwrap();
+    let state_dir = dir.join("state");
+    fs::create_dir_all(&state_dir).unwrap();
+    fs::write(
+        state_dir.join("state.json"),
+        format!(
+            r#"{{"channels": {{"app": {{"path": {:?}, "bytes": 10}}}},
+                "pending": [{{"channel": "app", "path": {:?}, "stage": "compress"}}]}}"#,
+            unrotated, unfinished
+        ),
+    )
+    .unwrap();
+
+    let mut router = Router::start(
+        "state",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--state-dir",
+            state_dir.to_str().unwrap(),
+            "--on-rotate-cmd",
+            script.to_str().unwrap(),
+        ],
+    );
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    loop {
+        let mut ran: Vec<String> = fs::read_to_string(&ran)
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        ran.sort();
+        if ran == ["unfinished", "unrotated"] {
+            break;
+        }
+        assert!(Instant::now() < deadline, "the hook never ran for both");
+        thread::sleep(Duration::from_millis(20));
+    }
+    router.send("app", "started");
+    let current = router.log_dir.join(file_name("app", at(9, 0, 0)));
+    router.stop();
+
+    let state = fs::read_to_string(state_dir.join("state.json")).unwrap();
+    assert!(state.contains(&format!("\"path\": {:?}", current)));
+    assert!(state.contains("\"bytes\": 8"));
+    assert!(state.contains("\"pending\": []"));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+/// A stand-in for S3 answering each upload with the next of `statuses`,
+/// over TLS as `localhost` if given its settings, handing over the head and
+/// body of every request it gets.
+#[cfg(feature = "s3")]
+fn fake_s3(
+    statuses: Vec<u16>,
+    tls: Option<std::sync::Arc<rustls::ServerConfig>>,
+) -> (String, std::sync::mpsc::Receiver<(String, String)>) {
+    use rustls::{ServerConnection, StreamOwned};
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    trait Connection: Read + Write {}
+    impl<T: Read + Write> Connection for T {}
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = format!("localhost:{}", listener.local_addr().unwrap().port());
+    let (sender, receiver) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        for (status, connection) in statuses.into_iter().zip(listener.incoming()) {
+            let connection: Box<dyn Connection> = match tls {
+                Some(ref config) => {
+                    let session = ServerConnection::new(config.clone()).unwrap();
+                    Box::new(StreamOwned::new(session, connection.unwrap()))
+                }
+                None => Box::new(connection.unwrap()),
+            };
+            let mut reader = BufReader::new(connection);
+            let mut head = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                head.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let length = head
+                .lines()
+                .find_map(|line| line.strip_prefix("Content-Length: "))
+                .map_or(0, |length| length.parse().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 {} Status\r\nContent-Length: 0\r\n\r\n",
+                status
+            )
+            .unwrap();
+            let _ = sender.send((head, String::from_utf8(body).unwrap()));
+        }
+    });
+
+    (addr, receiver)
+}
+
+#[cfg(feature = "s3")]
+#[test]
+fn rotated_files_are_uploaded_until_one_attempt_succeeds() {
+    std::env::set_var("AWS_ACCESS_KEY_ID", "test-key");
+    std::env::set_var("AWS_SECRET_ACCESS_KEY", "test-secret");
+    let (addr, requests) = fake_s3(vec![503, 200], None);
+    let endpoint = format!("http://{}", addr);
+    let mut router = Router::start(
+        "s3",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--s3-bucket",
+            "logs",
+            "--s3-prefix",
+            "web-1/",
+            "--s3-endpoint",
+            &endpoint,
+            "--after-upload",
+            "delete",
+        ],
+    );
+    router.send("app", "one");
+    router.wait_for(&file_name("app", at(9, 0, 0)), "one\n");
+    router.set_clock(at(10, 0, 0));
+    router.send("app", "two");
+
+    for _ in 0..2 {
+        let (head, body) = requests.recv_timeout(WAIT_TIMEOUT).unwrap();
+        assert!(head.starts_with("PUT /logs/web-1/app_2024-06-01-09-00-00.log HTTP/1.1\r\n"));
+        assert!(head.contains("Authorization: AWS4-HMAC-SHA256 Credential=test-key/"));
+        assert_eq!(body, "one\n");
+    }
+
+    let uploaded = router.log_dir.join(file_name("app", at(9, 0, 0)));
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    while uploaded.exists() {
+        assert!(Instant::now() < deadline, "the uploaded file was kept");
+        thread::sleep(Duration::from_millis(20));
+    }
+    let files = router.stop();
+    assert_eq!(
+        files[".s3-uploads"],
+        format!("queued {0}\ndone {0}\n", uploaded.display())
+    );
+}
+
+#[cfg(feature = "s3")]
+#[test]
+fn rotated_files_are_uploaded_to_an_https_endpoint() {
+    std::env::set_var("AWS_ACCESS_KEY_ID", "test-key");
+    std::env::set_var("AWS_SECRET_ACCESS_KEY", "test-secret");
+    let (config, ca_file) = tls_server("s3-https");
+    let (addr, requests) = fake_s3(vec![200], Some(config));
+    let endpoint = format!("https://{}", addr);
+    let mut router = Router::start(
+        "s3-https",
+        at(9, 0, 0),
+        &[
+            "--accepted-log-channels",
+            "app",
+            "--buffering-profiles",
+            "app=latency",
+            "--s3-bucket",
+            "logs",
+            "--s3-endpoint",
+            &endpoint,
+            "--tls-ca-file",
+            ca_file.to_str().unwrap(),
+        ],
+    );
+    router.send("app", "one");
+    router.wait_for(&file_name("app", at(9, 0, 0)), "one\n");
+    router.set_clock(at(10, 0, 0));
+    router.send("app", "two");
+
+    let (head, body) = requests.recv_timeout(WAIT_TIMEOUT).unwrap();
+    router.stop();
+    let _ = fs::remove_file(&ca_file);
+
+    assert!(head.starts_with("PUT /logs/app_2024-06-01-09-00-00.log HTTP/1.1\r\n"));
+    assert!(head.contains(&format!("host: {}\r\n", addr)));
+    assert_eq!(body, "one\n");
+}